@@ -0,0 +1,87 @@
+//! CDN lookup benchmark
+//!
+//! ```text
+//! cargo bench --bench cdn_lookup                           # downloaded cdn.yml
+//! NALI_BENCH_CDN=/path/to/cdn.yml cargo bench --bench cdn_lookup
+//! ```
+//!
+//! Uses the downloaded `cdn.yml` when present, otherwise a synthetic file of
+//! similar shape. Prints the average time per lookup for hits and misses.
+
+use nali_rs::database::{CDNDatabase, Database};
+use nali_rs::utils::path;
+use std::fmt::Write;
+use std::hint::black_box;
+use std::path::PathBuf;
+use std::time::Instant;
+
+const ITERATIONS: usize = 200_000;
+
+/// Synthetic rule file: mostly domains, some `*.` wildcards, a few regexes
+fn synthetic(dir: &tempfile::TempDir) -> PathBuf {
+    let mut yaml = String::new();
+    for i in 0..5000 {
+        let _ = writeln!(
+            yaml,
+            "cdn{}.example{}.com:\n  name: Provider {}",
+            i,
+            i % 97,
+            i
+        );
+    }
+    for i in 0..500 {
+        let _ = writeln!(yaml, "\"*.edge{}.net\":\n  name: Edge {}", i, i);
+    }
+    for i in 0..50 {
+        let _ = writeln!(yaml, "\"img{}-*.static.org\":\n  name: Static {}", i, i);
+    }
+    let file = dir.path().join("cdn.yml");
+    std::fs::write(&file, yaml).unwrap();
+    file
+}
+
+fn bench(db: &CDNDatabase, label: &str, domains: &[&str]) {
+    let started = Instant::now();
+    for i in 0..ITERATIONS {
+        black_box(
+            db.lookup_cdn(black_box(domains[i % domains.len()]))
+                .unwrap(),
+        );
+    }
+    let per_lookup = started.elapsed() / ITERATIONS as u32;
+    println!("{:<8} {:>8.0?} per lookup", label, per_lookup);
+}
+
+fn main() {
+    let dir = tempfile::tempdir().unwrap();
+    let file = std::env::var_os("NALI_BENCH_CDN")
+        .map(PathBuf::from)
+        .or_else(|| path::database_file("cdn.yml").ok().filter(|p| p.exists()))
+        .unwrap_or_else(|| synthetic(&dir));
+
+    let mut db = CDNDatabase::new();
+    let started = Instant::now();
+    db.load_from_file(file.to_str().unwrap()).unwrap();
+    println!("loaded {} in {:?}", file.display(), started.elapsed());
+
+    bench(
+        &db,
+        "hit",
+        &[
+            "www.cdn42.example42.com",
+            "a.b.edge7.net",
+            "img3-x.static.org",
+            "img.alicdn.com",
+            "d111111abcdef8.cloudfront.net",
+        ],
+    );
+    bench(
+        &db,
+        "miss",
+        &[
+            "www.example.org",
+            "mail.google.com",
+            "a.b.c.d.e.unknown.test",
+        ],
+    );
+}
@@ -0,0 +1,142 @@
+//! Deduplicated annotation benchmark
+//!
+//! ```text
+//! cargo bench --bench dedupe
+//! ```
+//!
+//! Builds a synthetic custom database and a log-like chunk of lines, a few
+//! hundred client addresses each seen many times, and annotates it the way
+//! `nali-rs` does line by line and the way `--dedupe` does: parse the chunk,
+//! look up each distinct entity once and copy the annotations back. Runs once
+//! with the query cache off, where every lookup reads the database, and once
+//! with it on. Prints the time per line and the number of lookups.
+
+use nali_rs::entity::parser;
+use nali_rs::enrichment::Deduplicator;
+use nali_rs::{AppConfig, DatabaseInfo, DatabaseManager, Entities, Pipeline};
+use std::fmt::Write;
+use std::hint::black_box;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Lines per chunk, as `--dedupe` buffers them
+const LINES: usize = 4096;
+/// Distinct client addresses in the chunk
+const CLIENTS: usize = 300;
+const PASSES: usize = 20;
+
+fn config(dir: &tempfile::TempDir, cached: bool) -> AppConfig {
+    let mut config = AppConfig::default();
+    config.database.ipv4_database = "ranges".to_string();
+    config.database.data_dir = Some(dir.path().to_string_lossy().to_string());
+    config.database.cache_size = if cached { 1_000_000 } else { 0 };
+    config.database.databases.push(DatabaseInfo {
+        name: "ranges".to_string(),
+        name_alias: vec![],
+        format: "nali".to_string(),
+        file: "ranges.nali".to_string(),
+        languages: vec![],
+        types: vec![],
+        download_urls: vec![],
+        tag: None,
+    });
+    config
+}
+
+/// Access log lines, every client seen again and again
+fn chunk() -> Vec<String> {
+    (0..LINES)
+        .map(|n| {
+            let client = n * 7 % CLIENTS;
+            format!(
+                "10.{}.{}.{} - - [10/Jan/2024:00:00:{:02}] \"GET /item/{} HTTP/1.1\" 200 512",
+                client % 7,
+                client * 37 % 256,
+                client % 250 + 1,
+                n % 60,
+                n
+            )
+        })
+        .collect()
+}
+
+async fn per_line(pipeline: &Pipeline, lines: &[String]) -> usize {
+    let mut lookups = 0;
+    for line in lines {
+        let mut entities = parser::parse_line(line);
+        lookups += entities.entities.len();
+        pipeline.enrich_all(&mut entities.entities).await;
+        black_box(entities);
+    }
+    lookups
+}
+
+async fn deduplicated(pipeline: &Pipeline, lines: &[String]) -> usize {
+    let mut chunk: Vec<Entities> = lines.iter().map(|line| parser::parse_line(line)).collect();
+    let mut dedupe = Deduplicator::new();
+    let (mut pending, _): (Vec<_>, Vec<_>) = dedupe.pending(&chunk).into_iter().unzip();
+    pipeline.enrich_all(&mut pending).await;
+    for entity in pending {
+        dedupe.insert(entity);
+    }
+    dedupe.apply(&mut chunk);
+    black_box(chunk);
+    dedupe.distinct()
+}
+
+fn report(name: &str, elapsed: Duration, lookups: usize) {
+    println!(
+        "  {:<12} {:>8.0} ns/line  {:>5} lookups/chunk",
+        name,
+        elapsed.as_nanos() as f64 / (PASSES * LINES) as f64,
+        lookups
+    );
+}
+
+fn run(dir: &tempfile::TempDir, cached: bool, lines: &[String]) {
+    let config = config(dir, cached);
+    let manager = Arc::new(DatabaseManager::new(config.clone()));
+    let pipeline = Pipeline::standard(&config, manager).unwrap();
+    let runtime = tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap();
+    println!("{}", if cached { "query cache on" } else { "query cache off" });
+
+    runtime.block_on(async {
+        // Load the database outside the measurement
+        per_line(&pipeline, &lines[..1]).await;
+
+        let started = Instant::now();
+        let mut lookups = 0;
+        for _ in 0..PASSES {
+            lookups = per_line(&pipeline, lines).await;
+        }
+        report("per line", started.elapsed(), lookups);
+
+        let started = Instant::now();
+        for _ in 0..PASSES {
+            lookups = deduplicated(&pipeline, lines).await;
+        }
+        report("deduplicated", started.elapsed(), lookups);
+    });
+}
+
+fn main() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut csv = String::new();
+    for a in 0..=255u32 {
+        for b in 0..64u32 {
+            let _ = writeln!(
+                csv,
+                "10.{a}.{}.0,10.{a}.{}.255,Country {b},Region {a},,",
+                b * 4,
+                b * 4 + 3
+            );
+        }
+    }
+    let csv_path = dir.path().join("ranges.csv");
+    std::fs::write(&csv_path, csv).unwrap();
+    nali_rs::database::custom::build_from_csv(&csv_path, &dir.path().join("ranges.nali")).unwrap();
+
+    let lines = chunk();
+    run(&dir, false, &lines);
+    run(&dir, true, &lines);
+}
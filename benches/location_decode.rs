@@ -0,0 +1,144 @@
+//! Location decoding benchmark
+//!
+//! ```text
+//! cargo bench --bench location_decode
+//! ```
+//!
+//! Builds a synthetic qqwry.dat laid out like the real one, GBK-encoded
+//! strings stored once and records redirecting to them, and looks up a
+//! log-like sample of addresses: a few thousand distinct ones, each seen many
+//! times. Prints time and heap allocations per lookup with the location cache
+//! disabled and enabled, from one thread and from several.
+
+use nali_rs::database::{Database, QQwryDatabase};
+use nali_rs::utils::encoding::utf8_to_gbk;
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::hint::black_box;
+use std::net::{IpAddr, Ipv4Addr};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+use std::time::Instant;
+
+/// Allocator counting allocations
+struct Counting;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        unsafe { System.alloc(layout) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe { System.dealloc(ptr, layout) }
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        unsafe { System.realloc(ptr, layout, new_size) }
+    }
+}
+
+#[global_allocator]
+static GLOBAL: Counting = Counting;
+
+const RANGES: u32 = 100_000;
+const DISTINCT: u32 = 5_000;
+const PASSES: usize = 100;
+const THREADS: usize = 4;
+
+const PROVINCES: &[&str] = &[
+    "北京市",
+    "上海市",
+    "广东省广州市",
+    "浙江省杭州市",
+    "四川省成都市",
+];
+const ISPS: &[&str] = &["电信", "联通", "移动", "阿里云 CZ88.NET", "腾讯云"];
+
+/// qqwry.dat image of equal-sized ranges whose records redirect to shared
+/// country and area strings
+fn build_qqwry() -> Vec<u8> {
+    let mut data = vec![0u8; 8];
+    let string = |data: &mut Vec<u8>, text: &str| {
+        let offset = data.len() as u32;
+        data.extend_from_slice(&utf8_to_gbk(text));
+        data.push(0);
+        offset
+    };
+    let provinces: Vec<u32> = PROVINCES.iter().map(|p| string(&mut data, p)).collect();
+    let isps: Vec<u32> = ISPS.iter().map(|i| string(&mut data, i)).collect();
+
+    let step = u32::MAX / RANGES;
+    let mut offsets = Vec::new();
+    for i in 0..RANGES {
+        offsets.push(data.len() as u32);
+        let end = if i == RANGES - 1 {
+            u32::MAX
+        } else {
+            (i + 1) * step - 1
+        };
+        data.extend_from_slice(&end.to_le_bytes());
+        data.push(0x02);
+        data.extend_from_slice(&provinces[i as usize % PROVINCES.len()].to_le_bytes()[..3]);
+        data.push(0x02);
+        data.extend_from_slice(&isps[i as usize % ISPS.len()].to_le_bytes()[..3]);
+    }
+
+    let idx_start = data.len() as u32;
+    for (i, offset) in offsets.iter().enumerate() {
+        data.extend_from_slice(&(i as u32 * step).to_le_bytes());
+        data.extend_from_slice(&offset.to_le_bytes()[..3]);
+    }
+    let idx_end = data.len() as u32 - 7;
+    data[0..4].copy_from_slice(&idx_start.to_le_bytes());
+    data[4..8].copy_from_slice(&idx_end.to_le_bytes());
+    data
+}
+
+fn pass(db: &QQwryDatabase, label: &str, ips: &[IpAddr], threads: usize) {
+    let allocations = ALLOCATIONS.load(Ordering::Relaxed);
+    let started = Instant::now();
+    thread::scope(|scope| {
+        for _ in 0..threads {
+            scope.spawn(|| {
+                for _ in 0..PASSES {
+                    for ip in ips {
+                        black_box(db.lookup_ip(black_box(*ip)).unwrap());
+                    }
+                }
+            });
+        }
+    });
+    let lookups = ips.len() * PASSES * threads;
+    let per_lookup = started.elapsed() / lookups as u32;
+    let allocations = ALLOCATIONS.load(Ordering::Relaxed) - allocations;
+    println!(
+        "{:<8} {} thread(s) {:>8.0?} per lookup  {:>5.1} allocations per lookup",
+        label,
+        threads,
+        per_lookup,
+        allocations as f64 / lookups as f64
+    );
+}
+
+fn main() {
+    let dir = tempfile::tempdir().unwrap();
+    let file = dir.path().join("qqwry.dat");
+    std::fs::write(&file, build_qqwry()).unwrap();
+    let mut db = QQwryDatabase::new();
+    db.load_from_file(file.to_str().unwrap()).unwrap();
+
+    // Spread over the whole address space, one address per sampled range
+    let ips: Vec<IpAddr> = (0..DISTINCT)
+        .map(|i| IpAddr::V4(Ipv4Addr::from(i.wrapping_mul(2_654_435_761))))
+        .collect();
+
+    db.set_location_cache(0);
+    pass(&db, "uncached", &ips, 1);
+    pass(&db, "uncached", &ips, THREADS);
+    db.set_location_cache(1 << 16);
+    pass(&db, "cached", &ips, 1);
+    pass(&db, "cached", &ips, THREADS);
+}
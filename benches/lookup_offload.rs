@@ -0,0 +1,137 @@
+//! Inline vs offloaded lookup benchmark
+//!
+//! ```text
+//! cargo bench --bench lookup_offload
+//! ```
+//!
+//! Builds a synthetic custom database and runs many concurrent queries
+//! through `DatabaseManager` on a small multi-threaded runtime, once with
+//! lookups on the async workers and once with `database.offload_lookups`.
+//! Alongside query latency it measures how late a 1 ms ticker sharing the
+//! runtime wakes up, which is what other tasks of a service would feel.
+
+use nali_rs::{AppConfig, DatabaseInfo, DatabaseManager};
+use std::fmt::Write;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+const TASKS: usize = 64;
+const QUERIES_PER_TASK: usize = 2_000;
+
+fn config(dir: &tempfile::TempDir, offload: bool) -> AppConfig {
+    let mut config = AppConfig::default();
+    config.database.ipv4_database = "ranges".to_string();
+    config.database.data_dir = Some(dir.path().to_string_lossy().to_string());
+    // Every query has to reach the database
+    config.database.cache_size = 0;
+    config.database.offload_lookups = offload;
+    config.database.databases.push(DatabaseInfo {
+        name: "ranges".to_string(),
+        name_alias: vec![],
+        format: "nali".to_string(),
+        file: "ranges.nali".to_string(),
+        languages: vec![],
+        types: vec![],
+        download_urls: vec![],
+        tag: None,
+    });
+    config
+}
+
+/// Value at quantile `q` of sorted samples
+fn quantile(sorted: &[Duration], q: f64) -> Duration {
+    sorted[((sorted.len() - 1) as f64 * q) as usize]
+}
+
+fn run(dir: &tempfile::TempDir, offload: bool) {
+    let runtime = tokio::runtime::Builder::new_multi_thread()
+        .worker_threads(2)
+        .enable_all()
+        .build()
+        .unwrap();
+
+    runtime.block_on(async {
+        let manager = Arc::new(DatabaseManager::new(config(dir, offload)));
+        // Load outside the measurement
+        manager.query_ip(IpAddr::from([10, 0, 0, 1])).await.unwrap();
+
+        let lateness = Arc::new(Mutex::new(Vec::new()));
+        let ticker = tokio::spawn({
+            let lateness = Arc::clone(&lateness);
+            async move {
+                loop {
+                    let due = Instant::now() + Duration::from_millis(1);
+                    tokio::time::sleep_until(due.into()).await;
+                    let late = Instant::now().saturating_duration_since(due);
+                    lateness.lock().unwrap().push(late);
+                }
+            }
+        });
+
+        let started = Instant::now();
+        let tasks: Vec<_> = (0..TASKS)
+            .map(|task| {
+                let manager = Arc::clone(&manager);
+                tokio::spawn(async move {
+                    let mut latencies = Vec::with_capacity(QUERIES_PER_TASK);
+                    for n in 0..QUERIES_PER_TASK {
+                        let ip = IpAddr::from([10, (task % 256) as u8, (n / 256) as u8, n as u8]);
+                        let queried = Instant::now();
+                        manager.query_ip(ip).await.unwrap();
+                        latencies.push(queried.elapsed());
+                    }
+                    latencies
+                })
+            })
+            .collect();
+
+        let mut latencies = Vec::new();
+        for task in tasks {
+            latencies.extend(task.await.unwrap());
+        }
+        let total = started.elapsed();
+        ticker.abort();
+        let mut lateness = std::mem::take(&mut *lateness.lock().unwrap());
+
+        latencies.sort();
+        lateness.sort();
+        print!(
+            "{:<9} {:>9.2?} total  query p50 {:>9.2?} p99 {:>9.2?}",
+            if offload { "offloaded" } else { "inline" },
+            total,
+            quantile(&latencies, 0.5),
+            quantile(&latencies, 0.99),
+        );
+        if lateness.is_empty() {
+            println!();
+        } else {
+            println!(
+                "  timer lateness p50 {:>9.2?} p99 {:>9.2?}",
+                quantile(&lateness, 0.5),
+                quantile(&lateness, 0.99)
+            );
+        }
+    });
+}
+
+fn main() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut csv = String::new();
+    for a in 0..=255u32 {
+        for b in 0..64u32 {
+            let _ = writeln!(
+                csv,
+                "10.{a}.{}.0,10.{a}.{}.255,Country {b},Region {a},,",
+                b * 4,
+                b * 4 + 3
+            );
+        }
+    }
+    let csv_path = dir.path().join("ranges.csv");
+    std::fs::write(&csv_path, csv).unwrap();
+    nali_rs::database::custom::build_from_csv(&csv_path, &dir.path().join("ranges.nali")).unwrap();
+
+    run(&dir, false);
+    run(&dir, true);
+}
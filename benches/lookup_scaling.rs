@@ -0,0 +1,119 @@
+//! Lookup throughput across threads
+//!
+//! ```text
+//! cargo bench --bench lookup_scaling
+//! ```
+//!
+//! Builds a synthetic custom database and queries it from 1, 2, 4, ... OS
+//! threads, up to the number of cores, each with its own clone of one
+//! `DatabaseManager` and its own single-threaded runtime, as request handlers
+//! of a server would. It runs once with every query answered by the query
+//! cache and once with the cache off, so every query reads the database.
+//! Throughput should grow with the threads until the cores run out; a shared
+//! lock on the read path shows up as a flat or falling line.
+
+use nali_rs::{AppConfig, DatabaseInfo, DatabaseManager};
+use std::fmt::Write;
+use std::net::IpAddr;
+use std::sync::{Arc, Barrier};
+use std::time::{Duration, Instant};
+
+const QUERIES_PER_THREAD: usize = 200_000;
+/// Distinct addresses each thread cycles through
+const ADDRESSES: usize = 4_096;
+
+fn config(dir: &tempfile::TempDir, cached: bool) -> AppConfig {
+    let mut config = AppConfig::default();
+    config.database.ipv4_database = "ranges".to_string();
+    config.database.data_dir = Some(dir.path().to_string_lossy().to_string());
+    config.database.cache_size = if cached { 1_000_000 } else { 0 };
+    config.database.databases.push(DatabaseInfo {
+        name: "ranges".to_string(),
+        name_alias: vec![],
+        format: "nali".to_string(),
+        file: "ranges.nali".to_string(),
+        languages: vec![],
+        types: vec![],
+        download_urls: vec![],
+        tag: None,
+    });
+    config
+}
+
+fn address(thread: usize, n: usize) -> IpAddr {
+    let n = n % ADDRESSES;
+    IpAddr::from([10, thread as u8, (n / 256) as u8, n as u8])
+}
+
+/// Run `threads` threads of queries and return the total time
+fn measure(manager: &DatabaseManager, threads: usize) -> Duration {
+    let barrier = Arc::new(Barrier::new(threads + 1));
+    let handles: Vec<_> = (0..threads)
+        .map(|thread| {
+            let manager = manager.clone();
+            let barrier = Arc::clone(&barrier);
+            std::thread::spawn(move || {
+                let runtime = tokio::runtime::Builder::new_current_thread().build().unwrap();
+                runtime.block_on(async {
+                    // Warm the cache outside the measurement
+                    for n in 0..ADDRESSES {
+                        manager.query_ip(address(thread, n)).await.unwrap();
+                    }
+                    barrier.wait();
+                    for n in 0..QUERIES_PER_THREAD {
+                        manager.query_ip(address(thread, n)).await.unwrap();
+                    }
+                });
+            })
+        })
+        .collect();
+
+    barrier.wait();
+    let started = Instant::now();
+    for handle in handles {
+        handle.join().unwrap();
+    }
+    started.elapsed()
+}
+
+fn run(dir: &tempfile::TempDir, cached: bool, cores: usize) {
+    let manager = DatabaseManager::new(config(dir, cached));
+    println!("{}", if cached { "cache hits" } else { "database reads" });
+
+    let mut single = None;
+    let mut threads = 1;
+    while threads <= cores {
+        let elapsed = measure(&manager, threads);
+        let rate = (threads * QUERIES_PER_THREAD) as f64 / elapsed.as_secs_f64();
+        let single = *single.get_or_insert(rate);
+        println!(
+            "  {:>3} threads {:>12.0} queries/s  {:>5.2}x",
+            threads,
+            rate,
+            rate / single
+        );
+        threads *= 2;
+    }
+}
+
+fn main() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut csv = String::new();
+    for a in 0..=255u32 {
+        for b in 0..64u32 {
+            let _ = writeln!(
+                csv,
+                "10.{a}.{}.0,10.{a}.{}.255,Country {b},Region {a},,",
+                b * 4,
+                b * 4 + 3
+            );
+        }
+    }
+    let csv_path = dir.path().join("ranges.csv");
+    std::fs::write(&csv_path, csv).unwrap();
+    nali_rs::database::custom::build_from_csv(&csv_path, &dir.path().join("ranges.nali")).unwrap();
+
+    let cores = std::thread::available_parallelism().map_or(4, |n| n.get());
+    run(&dir, true, cores);
+    run(&dir, false, cores);
+}
@@ -0,0 +1,108 @@
+//! Build script for nali-rs
+//!
+//! With the `embedded-db` feature enabled, converts an ip2asn country table
+//! (<https://iptoasn.com/>, `ip2country-v4.tsv` or `ip2country-v4-u32.tsv`)
+//! into the compact format read by `database::embedded`:
+//!
+//! ```text
+//! b"NALIEMB1" | count: u32 LE | count × (start: u32 LE, end: u32 LE, country: [u8; 2])
+//! ```
+//!
+//! Records are sorted, non-overlapping, and adjacent ranges of the same
+//! country are merged.
+
+use std::env;
+use std::fs;
+use std::net::Ipv4Addr;
+use std::path::PathBuf;
+
+/// Source table used when `NALI_EMBEDDED_DB_SOURCE` is not set
+const DEFAULT_SOURCE: &str = "data/ip2country-v4.tsv";
+
+fn main() {
+    if env::var_os("CARGO_FEATURE_EMBEDDED_DB").is_none() {
+        return;
+    }
+
+    println!("cargo:rerun-if-env-changed=NALI_EMBEDDED_DB_SOURCE");
+    let source = env::var_os("NALI_EMBEDDED_DB_SOURCE")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from(DEFAULT_SOURCE));
+    println!("cargo:rerun-if-changed={}", source.display());
+
+    let text = fs::read_to_string(&source).unwrap_or_else(|e| {
+        panic!(
+            "embedded-db: cannot read {}: {}\n\
+             Download https://iptoasn.com/data/ip2country-v4.tsv.gz, unpack it to {} \
+             or point NALI_EMBEDDED_DB_SOURCE at it",
+            source.display(),
+            e,
+            DEFAULT_SOURCE
+        )
+    });
+
+    let table = encode(&parse(&text));
+    let out = PathBuf::from(env::var_os("OUT_DIR").unwrap()).join("embedded_country.bin");
+    fs::write(&out, table)
+        .unwrap_or_else(|e| panic!("embedded-db: cannot write {}: {}", out.display(), e));
+}
+
+/// Parse an address written either dotted or as a decimal u32
+fn parse_ipv4(field: &str) -> Option<u32> {
+    field
+        .parse::<Ipv4Addr>()
+        .map(u32::from)
+        .ok()
+        .or_else(|| field.parse().ok())
+}
+
+/// Parse `start<TAB>end<TAB>country` rows into sorted, merged ranges
+///
+/// Rows without a two-letter country code (ip2asn uses `None` for
+/// unallocated space) are skipped.
+fn parse(text: &str) -> Vec<(u32, u32, [u8; 2])> {
+    let mut ranges: Vec<(u32, u32, [u8; 2])> = text
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split('\t');
+            let start = parse_ipv4(fields.next()?.trim())?;
+            let end = parse_ipv4(fields.next()?.trim())?;
+            let country = fields.next()?.trim().as_bytes();
+            if start > end || country.len() != 2 || !country.iter().all(u8::is_ascii_alphabetic) {
+                return None;
+            }
+            Some((
+                start,
+                end,
+                [
+                    country[0].to_ascii_uppercase(),
+                    country[1].to_ascii_uppercase(),
+                ],
+            ))
+        })
+        .collect();
+    ranges.sort_unstable_by_key(|&(start, _, _)| start);
+
+    let mut merged: Vec<(u32, u32, [u8; 2])> = Vec::with_capacity(ranges.len());
+    for (start, end, country) in ranges {
+        match merged.last_mut() {
+            Some(last) if last.2 == country && last.1.checked_add(1) == Some(start) => last.1 = end,
+            Some(last) if start <= last.1 => {} // overlapping row, keep the first
+            _ => merged.push((start, end, country)),
+        }
+    }
+    merged
+}
+
+/// Serialize ranges into the embedded table format
+fn encode(ranges: &[(u32, u32, [u8; 2])]) -> Vec<u8> {
+    let mut table = Vec::with_capacity(12 + ranges.len() * 10);
+    table.extend_from_slice(b"NALIEMB1");
+    table.extend_from_slice(&(ranges.len() as u32).to_le_bytes());
+    for (start, end, country) in ranges {
+        table.extend_from_slice(&start.to_le_bytes());
+        table.extend_from_slice(&end.to_le_bytes());
+        table.extend_from_slice(country);
+    }
+    table
+}
@@ -0,0 +1,28 @@
+//! Load arbitrary bytes as a CZ88 .czdb database and look up a few addresses
+//!
+//! The key is the one of `tests/corpus/cz88.czdb`; start from that sample to
+//! get past the encrypted header:
+//!
+//! ```text
+//! cargo +nightly fuzz run czdb ../tests/corpus/cz88.czdb
+//! ```
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use nali_rs::database::{CzdbDatabase, Database};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+fuzz_target!(|data: &[u8]| {
+    let mut db = CzdbDatabase::new();
+    db.set_key("bmFsaS1ycyAgY29ycHVzIQ==");
+    if db.load_from_bytes(data.to_vec()).is_ok() {
+        for ip in [0, 0x0100_0000, 0x0808_0808, 0x7F00_0001, u32::MAX] {
+            let _ = db.lookup_ip(IpAddr::V4(Ipv4Addr::from(ip)));
+        }
+        for ip in [0, 0x2001_0db8 << 96, u128::MAX] {
+            let _ = db.lookup_ip(IpAddr::V6(Ipv6Addr::from(ip)));
+        }
+        let _ = db.version();
+    }
+});
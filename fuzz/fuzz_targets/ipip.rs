@@ -0,0 +1,25 @@
+//! Load arbitrary bytes as an IPIP database and look up a few addresses
+//!
+//! ```text
+//! cargo +nightly fuzz run ipip
+//! ```
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use nali_rs::database::{Database, IPIPDatabase};
+use std::net::{IpAddr, Ipv4Addr};
+
+fuzz_target!(|data: &[u8]| {
+    // The database memory-maps a file
+    let file = std::env::temp_dir().join(format!("nali-fuzz-{}.ipdb", std::process::id()));
+    std::fs::write(&file, data).unwrap();
+
+    let mut db = IPIPDatabase::new();
+    if db.load_from_file(file.to_str().unwrap()).is_ok() {
+        for ip in [0, 0x0100_0000, 0x0808_0808, 0x7F00_0001, u32::MAX] {
+            let _ = db.lookup_ip(IpAddr::V4(Ipv4Addr::from(ip)));
+        }
+        let _ = db.version();
+    }
+});
@@ -0,0 +1,24 @@
+//! Load arbitrary bytes as zxipv6wry.db and look up a few addresses
+//!
+//! ```text
+//! cargo +nightly fuzz run zxipv6
+//! ```
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use nali_rs::database::{Database, ZXIPv6Database};
+use std::net::{IpAddr, Ipv6Addr};
+
+fuzz_target!(|data: &[u8]| {
+    // The database memory-maps a file
+    let file = std::env::temp_dir().join(format!("nali-fuzz-{}.db", std::process::id()));
+    std::fs::write(&file, data).unwrap();
+
+    let mut db = ZXIPv6Database::new();
+    if db.load_from_file(file.to_str().unwrap()).is_ok() {
+        for ip in [0, 0x2001_0db8 << 96, 0x2400_3200 << 96, u128::MAX] {
+            let _ = db.lookup_ip(IpAddr::V6(Ipv6Addr::from(ip)));
+        }
+    }
+});
@@ -0,0 +1,37 @@
+/*
+ * C interface to the nali-rs lookup core
+ *
+ * Build the shared library with
+ *   cargo rustc --release --lib --features nali-ffi --crate-type cdylib
+ * and link against target/release/libnali_rs.so (or .dylib / .dll).
+ *
+ * Lookups return a JSON string owned by the library: the location or CDN
+ * provider, `null` when there is none, or {"error": "..."}. Free it with
+ * nali_free().
+ */
+
+#ifndef NALI_H
+#define NALI_H
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+/* Load the configuration at config_path, or the default one if NULL.
+ * Returns 0 on success, -1 on error. */
+int nali_init(const char *config_path);
+
+/* Location of an IPv4 or IPv6 address as JSON */
+char *nali_lookup_ip(const char *ip);
+
+/* CDN provider of a domain as JSON */
+char *nali_lookup_cdn(const char *domain);
+
+/* Free a string returned by nali_lookup_ip or nali_lookup_cdn */
+void nali_free(char *ptr);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* NALI_H */
@@ -0,0 +1,199 @@
+//! `nali-rs asn` subcommand
+//!
+//! Queries the ASN database instead of the geolocation ones: `asn AS13335`
+//! lists the organization and every prefix of an AS, `asn 1.1.1.1` shows
+//! the AS announcing an address.
+
+use crate::config::AppConfig;
+use crate::database::asn::{self, AsnDatabase, AsnRange};
+use crate::error::{NaliError, Result};
+use clap::Args;
+use serde_json::json;
+use std::io::{self, Write};
+use std::net::IpAddr;
+
+use super::status::ExitStatus;
+
+/// Arguments for `nali-rs asn`
+#[derive(Args, Debug)]
+pub struct AsnArgs {
+    /// AS numbers (`AS13335` or `13335`) or IP addresses to look up
+    #[arg(value_name = "QUERY", required = true, num_args = 1..)]
+    pub queries: Vec<String>,
+
+    /// Print one JSON object per query
+    #[arg(long)]
+    pub json: bool,
+}
+
+/// A query for an AS or for the AS of an address
+#[derive(Debug, PartialEq)]
+enum Query {
+    Asn(u32),
+    Ip(IpAddr),
+}
+
+impl Query {
+    fn parse(text: &str) -> Result<Self> {
+        if let Ok(ip) = text.parse() {
+            return Ok(Query::Ip(ip));
+        }
+        let number = text
+            .strip_prefix("AS")
+            .or_else(|| text.strip_prefix("as"))
+            .unwrap_or(text);
+        number
+            .parse()
+            .map(Query::Asn)
+            .map_err(|_| NaliError::parse(format!("Not an AS number or IP address: {}", text)))
+    }
+}
+
+/// `AS13335 CLOUDFLARENET (US)`
+fn describe(range: &AsnRange) -> String {
+    match &range.country {
+        Some(country) => format!("AS{} {} ({})", range.asn, range.org, country),
+        None => format!("AS{} {}", range.asn, range.org),
+    }
+}
+
+/// Print the answer to `query`, returning false if the database has none
+fn answer<W: Write>(db: &AsnDatabase, query: &Query, json: bool, out: &mut W) -> Result<bool> {
+    match *query {
+        Query::Asn(number) => {
+            let ranges: Vec<&AsnRange> = db.ranges_of(number).collect();
+            let Some(first) = ranges.first() else {
+                return Ok(false);
+            };
+            let prefixes = ranges.iter().flat_map(|range| range.prefixes());
+            if json {
+                let prefixes: Vec<String> = prefixes.map(|prefix| prefix.to_string()).collect();
+                let record = json!({
+                    "asn": number,
+                    "org": first.org,
+                    "country": first.country,
+                    "prefixes": prefixes,
+                });
+                writeln!(out, "{}", record)?;
+            } else {
+                writeln!(out, "{}", describe(first))?;
+                for prefix in prefixes {
+                    writeln!(out, "  {}", prefix)?;
+                }
+            }
+        }
+        Query::Ip(ip) => {
+            let Some(range) = db.lookup(ip) else {
+                return Ok(false);
+            };
+            let prefix = range.prefixes().into_iter().find(|prefix| prefix.contains(ip));
+            if json {
+                let record = json!({
+                    "ip": ip,
+                    "asn": range.asn,
+                    "org": range.org,
+                    "country": range.country,
+                    "prefix": prefix.map(|prefix| prefix.to_string()),
+                });
+                writeln!(out, "{}", record)?;
+            } else {
+                let prefix = prefix.map(|prefix| format!(" {}", prefix)).unwrap_or_default();
+                writeln!(out, "{} -> {}{}", ip, describe(range), prefix)?;
+            }
+        }
+    }
+    Ok(true)
+}
+
+/// Run the asn subcommand
+pub fn run(args: &AsnArgs, config: &AppConfig) -> Result<ExitStatus> {
+    let queries = args
+        .queries
+        .iter()
+        .map(|query| Query::parse(query))
+        .collect::<Result<Vec<_>>>()?;
+
+    let path = config.get_database_path(asn::DATABASE)?;
+    if !path.exists() {
+        return Err(NaliError::DatabaseNotFound(format!(
+            "{} (download ip2asn-combined.tsv.gz from https://iptoasn.com and unpack it there)",
+            path.display()
+        )));
+    }
+    let db = AsnDatabase::load(&path)?;
+
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+    let mut status = ExitStatus::Success;
+    for (query, text) in queries.iter().zip(&args.queries) {
+        if !answer(&db, query, args.json, &mut out)? {
+            if !config.global.quiet {
+                eprintln!("{}: not found in the ASN database", text);
+            }
+            status = ExitStatus::NotFound;
+        }
+    }
+    Ok(status)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn database() -> AsnDatabase {
+        AsnDatabase::parse(
+            "1.0.0.0\t1.0.0.255\t13335\tUS\tCLOUDFLARENET\n\
+             1.1.1.0\t1.1.1.255\t13335\tUS\tCLOUDFLARENET\n\
+             2606:4700::\t2606:4700:ffff:ffff:ffff:ffff:ffff:ffff\t13335\tUS\tCLOUDFLARENET\n\
+             192.0.2.0\t192.0.2.127\t64496\tNone\tEXAMPLE\n"
+                .as_bytes(),
+        )
+        .unwrap()
+    }
+
+    fn render(query: &str, json: bool) -> Option<String> {
+        let mut out = Vec::new();
+        let found = answer(&database(), &Query::parse(query).unwrap(), json, &mut out).unwrap();
+        found.then(|| String::from_utf8(out).unwrap())
+    }
+
+    #[test]
+    fn test_parse_query() {
+        assert_eq!(Query::parse("AS13335").unwrap(), Query::Asn(13335));
+        assert_eq!(Query::parse("as13335").unwrap(), Query::Asn(13335));
+        assert_eq!(Query::parse("13335").unwrap(), Query::Asn(13335));
+        assert_eq!(Query::parse("1.1.1.1").unwrap(), Query::Ip("1.1.1.1".parse().unwrap()));
+        assert!(Query::parse("ASX").is_err());
+    }
+
+    #[test]
+    fn test_asn_prefixes() {
+        assert_eq!(
+            render("AS13335", false).unwrap(),
+            "AS13335 CLOUDFLARENET (US)\n  1.0.0.0/24\n  1.1.1.0/24\n  2606:4700::/32\n"
+        );
+        assert_eq!(
+            render("AS13335", true).unwrap(),
+            "{\"asn\":13335,\"country\":\"US\",\"org\":\"CLOUDFLARENET\",\
+             \"prefixes\":[\"1.0.0.0/24\",\"1.1.1.0/24\",\"2606:4700::/32\"]}\n"
+        );
+        assert!(render("AS64512", false).is_none());
+    }
+
+    #[test]
+    fn test_ip_lookup() {
+        assert_eq!(
+            render("1.1.1.1", false).unwrap(),
+            "1.1.1.1 -> AS13335 CLOUDFLARENET (US) 1.1.1.0/24\n"
+        );
+        assert_eq!(
+            render("192.0.2.1", false).unwrap(),
+            "192.0.2.1 -> AS64496 EXAMPLE 192.0.2.0/25\n"
+        );
+        assert_eq!(
+            render("192.0.2.1", true).unwrap(),
+            "{\"asn\":64496,\"country\":null,\"ip\":\"192.0.2.1\",\"org\":\"EXAMPLE\",\"prefix\":\"192.0.2.0/25\"}\n"
+        );
+        assert!(render("8.8.8.8", false).is_none());
+    }
+}
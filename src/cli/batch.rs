@@ -0,0 +1,243 @@
+//! Bulk file input and structured result files
+//!
+//! `-f/--file` reads queries from files and `-o/--output` writes results to
+//! a file. The output extension picks the format: `.json` writes one JSON
+//! array, `.jsonl` one object per line and `.csv` one row per entity, each
+//! record naming the input line it came from. Any other extension gets the
+//! usual annotated text.
+
+use crate::entity::{Entities, EntityType, formatter};
+use crate::error::{NaliError, Result};
+use std::io::Write;
+use std::path::Path;
+
+/// Format of the results written with `-o/--output`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ResultFormat {
+    /// Annotated text, as printed to a terminal
+    Text,
+    /// A single JSON array of entity records
+    Json,
+    /// One JSON entity record per line
+    Jsonl,
+    /// One CSV row per entity
+    Csv,
+}
+
+impl ResultFormat {
+    /// Pick the format from the output file extension
+    pub fn from_path(path: &Path) -> Self {
+        let ext = path
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .map(str::to_ascii_lowercase);
+        match ext.as_deref() {
+            Some("json") => ResultFormat::Json,
+            Some("jsonl") | Some("ndjson") => ResultFormat::Jsonl,
+            Some("csv") => ResultFormat::Csv,
+            _ => ResultFormat::Text,
+        }
+    }
+}
+
+/// CSV columns of an entity record
+const CSV_HEADERS: [&str; 9] = [
+    "line", "text", "type", "country", "region", "city", "isp", "cdn", "source",
+];
+
+/// Writer for structured entity records
+pub struct RecordWriter {
+    format: ResultFormat,
+    inner: Box<dyn Write>,
+    /// Records written so far
+    count: usize,
+}
+
+impl RecordWriter {
+    /// Create a writer for a structured format
+    ///
+    /// Returns `None` for [`ResultFormat::Text`], which the regular text
+    /// output handles.
+    pub fn new(format: ResultFormat, mut inner: Box<dyn Write>) -> Result<Option<Self>> {
+        match format {
+            ResultFormat::Text => return Ok(None),
+            ResultFormat::Json => inner.write_all(b"[")?,
+            ResultFormat::Csv => {
+                let mut header = csv_row(&CSV_HEADERS.map(String::from))?;
+                header.push(b'\n');
+                inner.write_all(&header)?;
+            }
+            ResultFormat::Jsonl => {}
+        }
+
+        Ok(Some(Self {
+            format,
+            inner,
+            count: 0,
+        }))
+    }
+
+    /// Write one record per annotated entity of an input line
+    pub fn write_entities(&mut self, line_no: usize, entities: &Entities) -> Result<()> {
+        for entity in &entities.entities {
+            if entity.entity_type == EntityType::Plain {
+                continue;
+            }
+
+            match self.format {
+                ResultFormat::Json | ResultFormat::Jsonl => {
+                    let record = formatter::entity_record(entity, line_no);
+
+                    if self.format == ResultFormat::Json {
+                        let separator: &[u8] = if self.count == 0 { b"\n" } else { b",\n" };
+                        self.inner.write_all(separator)?;
+                        serde_json::to_writer(&mut self.inner, &record)?;
+                    } else {
+                        serde_json::to_writer(&mut self.inner, &record)?;
+                        self.inner.write_all(b"\n")?;
+                    }
+                }
+                ResultFormat::Csv => {
+                    let geo = entity.geo_info.as_ref();
+                    let field = |value: Option<&String>| value.cloned().unwrap_or_default();
+                    let mut row = csv_row(&[
+                        line_no.to_string(),
+                        entity.text.clone(),
+                        format!("{:?}", entity.entity_type),
+                        field(geo.and_then(|g| g.country.as_ref())),
+                        field(geo.and_then(|g| g.region.as_ref())),
+                        field(geo.and_then(|g| g.city.as_ref())),
+                        field(geo.and_then(|g| g.isp.as_ref())),
+                        field(entity.cdn_info.as_ref().map(|c| &c.provider)),
+                        field(entity.source.as_ref()),
+                    ])?;
+                    row.push(b'\n');
+                    self.inner.write_all(&row)?;
+                }
+                ResultFormat::Text => unreachable!("text output has no record writer"),
+            }
+            self.count += 1;
+        }
+        Ok(())
+    }
+
+    /// Close the JSON array and flush
+    pub fn finish(&mut self) -> Result<()> {
+        if self.format == ResultFormat::Json {
+            self.inner.write_all(b"\n]\n")?;
+        }
+        self.inner.flush()?;
+        Ok(())
+    }
+}
+
+/// Encode one CSV row without the trailing newline
+fn csv_row(fields: &[String]) -> Result<Vec<u8>> {
+    let mut writer = csv::WriterBuilder::new()
+        .terminator(csv::Terminator::Any(b'\n'))
+        .from_writer(Vec::new());
+    writer
+        .write_record(fields)
+        .map_err(|e| NaliError::Other(format!("CSV write error: {}", e)))?;
+    let mut row = writer
+        .into_inner()
+        .map_err(|e| NaliError::Other(format!("CSV write error: {}", e)))?;
+    row.pop();
+    Ok(row)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::{CdnProvider, GeoLocation};
+    use crate::entity::Entity;
+    use serde_json::Value;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    #[derive(Clone, Default)]
+    struct Shared(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for Shared {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.borrow_mut().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn sample() -> Entities {
+        let mut entities = Entities::new();
+        entities.push(Entity::plain(0, 4, "GET ".to_string()));
+        let mut ip = Entity::ipv4(4, 11, "1.2.3.4".to_string());
+        ip.geo_info = Some(GeoLocation {
+            ip: "1.2.3.4".parse().unwrap(),
+            country: Some("中国".to_string()),
+            isp: Some("电信, 骨干网".to_string()),
+            ..Default::default()
+        });
+        ip.source = Some("qqwry".to_string());
+        entities.push(ip);
+        let mut domain = Entity::domain(12, 28, "cdn.jsdelivr.net".to_string());
+        domain.cdn_info = Some(CdnProvider {
+            domain: "cdn.jsdelivr.net".to_string(),
+            provider: "jsDelivr".to_string(),
+            description: None,
+        });
+        entities.push(domain);
+        entities
+    }
+
+    fn write(format: ResultFormat) -> String {
+        let buf = Shared::default();
+        let mut writer = RecordWriter::new(format, Box::new(buf.clone()))
+            .unwrap()
+            .unwrap();
+        writer.write_entities(1, &sample()).unwrap();
+        writer.write_entities(2, &Entities::new()).unwrap();
+        writer.finish().unwrap();
+        let out = buf.0.borrow().clone();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn test_format_from_path() {
+        assert_eq!(ResultFormat::from_path(Path::new("out.JSON")), ResultFormat::Json);
+        assert_eq!(ResultFormat::from_path(Path::new("out.jsonl")), ResultFormat::Jsonl);
+        assert_eq!(ResultFormat::from_path(Path::new("out.csv")), ResultFormat::Csv);
+        assert_eq!(ResultFormat::from_path(Path::new("out.txt")), ResultFormat::Text);
+        assert_eq!(ResultFormat::from_path(Path::new("out")), ResultFormat::Text);
+    }
+
+    #[test]
+    fn test_write_csv() {
+        assert_eq!(
+            write(ResultFormat::Csv),
+            "line,text,type,country,region,city,isp,cdn,source\n\
+             1,1.2.3.4,IPv4,中国,,,\"电信, 骨干网\",,qqwry\n\
+             1,cdn.jsdelivr.net,Domain,,,,,jsDelivr,\n"
+        );
+    }
+
+    #[test]
+    fn test_write_json_and_jsonl() {
+        let json: Value = serde_json::from_str(&write(ResultFormat::Json)).unwrap();
+        let records = json.as_array().unwrap();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0]["line"], 1);
+        assert_eq!(records[1]["cdn_info"]["provider"], "jsDelivr");
+
+        let jsonl = write(ResultFormat::Jsonl);
+        assert_eq!(jsonl.lines().count(), 2);
+        for line in jsonl.lines() {
+            serde_json::from_str::<Value>(line).unwrap();
+        }
+
+        assert!(RecordWriter::new(ResultFormat::Text, Box::new(Vec::new()))
+            .unwrap()
+            .is_none());
+    }
+}
@@ -0,0 +1,43 @@
+//! `nali-rs build` subcommand
+//!
+//! Converts a CSV of IP ranges into a nali-native database file.
+
+use crate::database::custom::build_from_csv;
+use crate::error::Result;
+use clap::Args;
+use std::path::PathBuf;
+
+use super::status::ExitStatus;
+
+/// Arguments for `nali-rs build`
+#[derive(Args, Debug)]
+pub struct BuildArgs {
+    /// CSV file with `start_ip,end_ip,country,region,city,isp` rows
+    #[arg(value_name = "CSV")]
+    pub input: PathBuf,
+
+    /// Output database file (defaults to the input path with a `.nali` extension)
+    #[arg(short, long, value_name = "FILE")]
+    pub output: Option<PathBuf>,
+}
+
+/// Run the build subcommand
+pub fn run(args: &BuildArgs) -> Result<ExitStatus> {
+    let output = args
+        .output
+        .clone()
+        .unwrap_or_else(|| args.input.with_extension("nali"));
+
+    let stats = build_from_csv(&args.input, &output)?;
+
+    println!(
+        "✓ Built {:?}: {} IPv4 ranges, {} IPv6 ranges, {} unique strings, {} bytes",
+        output, stats.v4_records, stats.v6_records, stats.strings, stats.bytes
+    );
+    println!(
+        "Hint: register it in config.yaml with `format: nali` and `file: {}`",
+        output.display()
+    );
+
+    Ok(ExitStatus::Success)
+}
@@ -0,0 +1,242 @@
+//! `nali-rs cache` subcommand
+//!
+//! Manages the query results kept between runs with `database.disk_cache`.
+//! `cache stats` shows how big the cache is and how often it answered,
+//! `cache clear` deletes it, e.g. after changing how databases are combined
+//! in a way their file stamps don't show, and `cache warm` looks up every
+//! address and domain of a list ahead of time so later runs find them cached.
+
+use crate::config::AppConfig;
+use crate::database::DatabaseManager;
+use crate::database::disk_cache;
+use crate::enrichment::whois;
+use crate::entity::{EntityType, parser};
+use crate::error::Result;
+use crate::utils::encoding::decode_input;
+use clap::{Args, Subcommand};
+use indicatif::HumanBytes;
+use std::collections::BTreeSet;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader};
+use std::net::IpAddr;
+use std::path::PathBuf;
+
+use super::status::ExitStatus;
+
+/// Arguments for `nali-rs cache`
+#[derive(Args, Debug)]
+pub struct CacheArgs {
+    #[command(subcommand)]
+    pub command: CacheCommand,
+}
+
+/// Cache subcommands
+#[derive(Subcommand, Debug)]
+pub enum CacheCommand {
+    /// Show the size and hit rate of the query results kept on disk
+    Stats,
+    /// Delete the query results kept on disk
+    Clear {
+        /// Also delete the cached WHOIS answers
+        #[arg(long)]
+        whois: bool,
+    },
+    /// Look up every address and domain of a list so later runs find them cached
+    Warm {
+        /// Read the list from FILE instead of stdin (repeatable)
+        #[arg(short, long = "file", value_name = "FILE")]
+        files: Vec<PathBuf>,
+    },
+}
+
+/// Path of the query cache file
+fn cache_file(config: &AppConfig) -> Result<PathBuf> {
+    Ok(config.state_dir()?.join(disk_cache::CACHE_FILE))
+}
+
+/// Manager with the disk cache on, whatever the configuration says
+fn cached_manager(config: &AppConfig) -> DatabaseManager {
+    let mut config = config.clone();
+    config.database.disk_cache = true;
+    DatabaseManager::new(config)
+}
+
+/// Addresses and domains mentioned in `reader`, each once
+fn collect_targets(
+    reader: impl BufRead,
+    ips: &mut BTreeSet<IpAddr>,
+    domains: &mut BTreeSet<String>,
+) -> Result<()> {
+    for raw in reader.split(b'\n') {
+        let line = decode_input(&raw?, false);
+        for entity in parser::parse_line(&line).entities {
+            if let Some(ip) = entity.as_ip() {
+                ips.insert(ip);
+            } else if entity.entity_type == EntityType::Domain {
+                domains.insert(entity.text);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Print the size and use of the disk cache
+fn stats(config: &AppConfig) -> Result<ExitStatus> {
+    let file = cache_file(config)?;
+    let size = fs::metadata(&file).ok().map(|meta| meta.len());
+    let stats = cached_manager(config).disk_cache_stats().unwrap_or_default();
+
+    println!("file:     {}", file.display());
+    println!("enabled:  {}", if config.database.disk_cache { "yes" } else { "no" });
+    println!("size:     {}", size.map_or("-".to_string(), |size| HumanBytes(size).to_string()));
+    println!("entries:  {}", stats.entries);
+    println!("hits:     {}", stats.hits);
+    println!("misses:   {}", stats.misses);
+    println!(
+        "hit rate: {}",
+        stats.hit_rate().map_or("-".to_string(), |rate| format!("{:.1}%", rate * 100.0))
+    );
+    Ok(ExitStatus::Success)
+}
+
+/// Delete the disk cache, and the WHOIS cache with `whois`
+fn clear(config: &AppConfig, whois: bool) -> Result<ExitStatus> {
+    let mut files = vec![cache_file(config)?];
+    if whois {
+        files.push(config.state_dir()?.join(whois::CACHE_FILE));
+    }
+    for file in files {
+        disk_cache::remove(&file)?;
+        if !config.global.quiet {
+            println!("Removed {}", file.display());
+        }
+    }
+    Ok(ExitStatus::Success)
+}
+
+/// Look up everything in `files` (or stdin) and save the answers
+async fn warm(config: &AppConfig, files: &[PathBuf]) -> Result<ExitStatus> {
+    let mut ips = BTreeSet::new();
+    let mut domains = BTreeSet::new();
+    if files.is_empty() {
+        collect_targets(io::stdin().lock(), &mut ips, &mut domains)?;
+    }
+    for path in files {
+        collect_targets(BufReader::new(File::open(path)?), &mut ips, &mut domains)?;
+    }
+
+    let manager = cached_manager(config);
+    let mut status = ExitStatus::Success;
+    for &ip in &ips {
+        if let Err(e) = manager.query_ip(ip).await {
+            tracing::warn!("Query failed: {}", e);
+            status = ExitStatus::DatabaseError;
+        }
+    }
+    for domain in &domains {
+        if let Err(e) = manager.query_cdn(domain).await {
+            tracing::warn!("Query failed: {}", e);
+            status = ExitStatus::DatabaseError;
+        }
+    }
+    manager.save_cache()?;
+
+    if !config.global.quiet {
+        println!("Cached {} addresses and {} domains", ips.len(), domains.len());
+    }
+    if !config.database.disk_cache {
+        eprintln!(
+            "Note: database.disk_cache is off, so queries won't use these results; \
+             enable it with `nali-rs config set database.disk_cache true`"
+        );
+    }
+    Ok(status)
+}
+
+/// Run a cache subcommand
+pub async fn run(args: &CacheArgs, config: &AppConfig) -> Result<ExitStatus> {
+    match args.command {
+        CacheCommand::Stats => stats(config),
+        CacheCommand::Clear { whois } => clear(config, whois),
+        CacheCommand::Warm { ref files } => warm(config, files).await,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::custom_db;
+
+    fn temp_config(dir: &tempfile::TempDir) -> AppConfig {
+        let mut config = AppConfig::default();
+        config.database.data_dir = Some(dir.path().to_string_lossy().to_string());
+        config.global.quiet = true;
+        config
+    }
+
+    #[tokio::test]
+    async fn test_clear() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = temp_config(&dir);
+        let file = cache_file(&config).unwrap();
+        let whois_file = dir.path().join(whois::CACHE_FILE);
+        fs::write(&file, "{}").unwrap();
+        fs::write(&whois_file, "{}").unwrap();
+
+        let args = CacheArgs {
+            command: CacheCommand::Clear { whois: false },
+        };
+        assert_eq!(run(&args, &config).await.unwrap(), ExitStatus::Success);
+        assert!(!file.exists());
+        assert!(whois_file.exists());
+        // Clearing again is fine
+        run(&args, &config).await.unwrap();
+
+        let args = CacheArgs {
+            command: CacheCommand::Clear { whois: true },
+        };
+        run(&args, &config).await.unwrap();
+        assert!(!whois_file.exists());
+    }
+
+    #[test]
+    fn test_collect_targets() {
+        let mut ips = BTreeSet::new();
+        let mut domains = BTreeSet::new();
+        let input = "1.1.1.1 www.example.com\n8.8.8.8 1.1.1.1\nno targets here\n";
+        collect_targets(input.as_bytes(), &mut ips, &mut domains).unwrap();
+        assert_eq!(
+            ips.into_iter().collect::<Vec<_>>(),
+            ["1.1.1.1".parse::<IpAddr>().unwrap(), "8.8.8.8".parse().unwrap()]
+        );
+        assert_eq!(domains.into_iter().collect::<Vec<_>>(), ["www.example.com"]);
+    }
+
+    #[tokio::test]
+    async fn test_warm() {
+        let dir = tempfile::tempdir().unwrap();
+        let list = dir.path().join("ips.txt");
+        fs::write(&list, "192.0.2.1\n192.0.2.2 192.0.2.1\n").unwrap();
+
+        let mut config = temp_config(&dir);
+        config.database.ipv4_database = "ranges".to_string();
+        let info = custom_db(dir.path(), "ranges", "192.0.2.0,192.0.2.255,Example,,,\n", &[]);
+        config.database.databases.push(info);
+
+        let args = CacheArgs {
+            command: CacheCommand::Warm { files: vec![list] },
+        };
+        assert_eq!(run(&args, &config).await.unwrap(), ExitStatus::Success);
+
+        let stats = cached_manager(&config).disk_cache_stats().unwrap();
+        assert_eq!(stats.entries, 2);
+        assert_eq!(stats.hits, 0);
+        assert_eq!(stats.misses, 2);
+
+        // The next run is answered from disk
+        let manager = cached_manager(&config);
+        let geo = manager.query_ip("192.0.2.1".parse().unwrap()).await.unwrap();
+        assert_eq!(geo.unwrap().country.as_deref(), Some("Example"));
+        assert_eq!(manager.disk_cache_stats().unwrap().hits, 1);
+    }
+}
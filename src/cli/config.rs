@@ -0,0 +1,262 @@
+//! `nali-rs config` subcommand
+//!
+//! Views and edits the configuration file without having to know its YAML
+//! schema. `config set` checks keys and database names before saving,
+//! `config edit` re-validates the file after the editor exits, and
+//! `config validate` checks a file without using it.
+
+use crate::config::{AppConfig, ConfigFormat, resolve_key};
+use crate::database::DatabaseManager;
+use crate::error::{NaliError, Result};
+use crate::utils::path;
+use clap::{Args, Subcommand};
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use std::process::Command;
+
+use super::status::ExitStatus;
+
+/// Keys whose values name databases
+const DATABASE_KEYS: [&str; 7] = [
+    "database.ipv4_database",
+    "database.ipv6_database",
+    "database.cdn_database",
+    "database.ipv4_fallback",
+    "database.ipv6_fallback",
+    "database.china_database",
+    "database.preload",
+];
+
+/// Arguments for `nali-rs config`
+#[derive(Args, Debug)]
+pub struct ConfigArgs {
+    #[command(subcommand)]
+    pub command: ConfigCommand,
+}
+
+/// Configuration subcommands
+#[derive(Subcommand, Debug)]
+pub enum ConfigCommand {
+    /// Print the effective configuration, including environment overrides
+    Show,
+    /// Print the path of the configuration file
+    Path,
+    /// Set one value, e.g. `config set database.ipv4 geoip2`
+    Set {
+        /// Dotted key; `database.ipv4`, `database.ipv6` and `database.cdn` are accepted as short names
+        key: String,
+        /// New value; lists are comma-separated
+        value: String,
+    },
+    /// Open the configuration file in $VISUAL or $EDITOR
+    Edit,
+    /// Check the configuration file for unknown keys, bad values and unknown databases
+    Validate {
+        /// File to check instead of the configuration file
+        file: Option<PathBuf>,
+    },
+    /// Rewrite the configuration file as `yaml` or `toml`, keeping the old one as a backup
+    Convert {
+        /// Format to convert to
+        format: ConfigFormat,
+    },
+}
+
+/// Databases named by `key`
+fn database_names<'a>(config: &'a AppConfig, key: &str) -> Vec<&'a String> {
+    let database = &config.database;
+    match key {
+        "database.ipv4_database" => vec![&database.ipv4_database],
+        "database.ipv6_database" => vec![&database.ipv6_database],
+        "database.cdn_database" => vec![&database.cdn_database],
+        "database.ipv4_fallback" => database.ipv4_fallback.iter().collect(),
+        "database.ipv6_fallback" => database.ipv6_fallback.iter().collect(),
+        "database.china_database" => database.china_database.iter().collect(),
+        "database.preload" => database.preload.iter().collect(),
+        _ => Vec::new(),
+    }
+}
+
+/// Check that every database named by `key` is known
+fn validate_databases(config: &AppConfig, key: &str) -> Result<()> {
+    let manager = DatabaseManager::new(config.clone());
+    for name in database_names(config, key) {
+        manager.get_database_type(name).map_err(|_| {
+            NaliError::config(format!(
+                "Unknown database '{}' (see `nali-rs list` for known names)",
+                name
+            ))
+        })?;
+    }
+    Ok(())
+}
+
+/// Selections naming databases that are neither built in nor configured
+fn unknown_databases(config: &AppConfig) -> Vec<String> {
+    let manager = DatabaseManager::new(config.clone());
+    DATABASE_KEYS
+        .iter()
+        .flat_map(|&key| {
+            database_names(config, key)
+                .into_iter()
+                .filter(|name| manager.get_database_type(name).is_err())
+                .map(move |name| format!("{}: unknown database '{}'", key, name))
+        })
+        .collect()
+}
+
+/// Apply `key = value` to a configuration, validating the result
+fn set_value(config: &mut AppConfig, key: &str, value: &str) -> Result<()> {
+    let key = resolve_key(key);
+    let mut updated = config.clone();
+    updated.set(key, value)?;
+    if DATABASE_KEYS.contains(&key) {
+        validate_databases(&updated, key)?;
+    }
+    *config = updated;
+    Ok(())
+}
+
+/// Write the configuration file in `format` next to the current one, which
+/// is renamed to `*.bak` so the new file takes effect
+fn convert(format: ConfigFormat) -> Result<(PathBuf, PathBuf)> {
+    let from = path::config_file()?;
+    let to = from.with_extension(format.extension());
+    if to == from {
+        return Err(NaliError::config(format!(
+            "{} is already in {} format",
+            from.display(),
+            format.extension()
+        )));
+    }
+    if to.exists() {
+        return Err(NaliError::config(format!(
+            "{} already exists; move it away first",
+            to.display()
+        )));
+    }
+
+    let file_config = AppConfig::load_file()?;
+    file_config.save(&to)?;
+    let mut backup = from.clone().into_os_string();
+    backup.push(".bak");
+    fs::rename(&from, &backup)
+        .map_err(|e| NaliError::config(format!("Failed to rename {}: {}", from.display(), e)))?;
+    Ok((from, to))
+}
+
+/// Open a file in the user's editor and wait for it to exit
+fn open_editor(file: &std::path::Path) -> Result<()> {
+    let editor = env::var("VISUAL")
+        .or_else(|_| env::var("EDITOR"))
+        .unwrap_or_else(|_| if cfg!(windows) { "notepad" } else { "vi" }.to_string());
+
+    // Editors are often configured with arguments, e.g. `code --wait`
+    let mut words = editor.split_whitespace();
+    let program = words
+        .next()
+        .ok_or_else(|| NaliError::config("$EDITOR is empty"))?;
+    let status = Command::new(program)
+        .args(words)
+        .arg(file)
+        .status()
+        .map_err(|e| NaliError::config(format!("Failed to start editor '{}': {}", editor, e)))?;
+
+    if !status.success() {
+        return Err(NaliError::config(format!(
+            "Editor '{}' exited with {}",
+            editor, status
+        )));
+    }
+    Ok(())
+}
+
+/// Run the config subcommand
+pub fn run(args: &ConfigArgs, config: &AppConfig) -> Result<ExitStatus> {
+    match &args.command {
+        ConfigCommand::Show => {
+            let yaml = serde_yaml::to_string(config)
+                .map_err(|e| NaliError::YamlError(format!("Failed to serialize config: {}", e)))?;
+            print!("{}", yaml);
+        }
+        ConfigCommand::Path => println!("{}", path::config_file()?.display()),
+        ConfigCommand::Set { key, value } => {
+            // Start from the file so environment overrides aren't saved into it
+            let mut file_config = AppConfig::load_file()?;
+            set_value(&mut file_config, key, value)?;
+            file_config.save(&path::config_file()?)?;
+        }
+        ConfigCommand::Edit => {
+            // Start from the default file if there is none yet; a broken
+            // file is opened as-is so it can be fixed
+            let file = path::config_file()?;
+            if !file.exists() {
+                AppConfig::load_file()?;
+            }
+            open_editor(&file)?;
+            let edited = AppConfig::load_file().map_err(|e| {
+                NaliError::config(format!("Configuration is invalid after editing: {}", e))
+            })?;
+            for warning in unknown_databases(&edited) {
+                eprintln!("{}", crate::tr!("Warning: {}", warning));
+            }
+        }
+        ConfigCommand::Validate { file } => {
+            let file = match file {
+                Some(file) => file.clone(),
+                None => path::config_file()?,
+            };
+            let checked = AppConfig::read(&file)?;
+            let warnings = unknown_databases(&checked);
+            for warning in &warnings {
+                eprintln!("{}", crate::tr!("Warning: {}", warning));
+            }
+            if warnings.is_empty() {
+                println!("{}", crate::tr!("{}: OK", file.display()));
+            }
+        }
+        ConfigCommand::Convert { format } => {
+            let (from, to) = convert(*format)?;
+            println!("{}", crate::tr!("Converted {} to {}", from.display(), to.display()));
+        }
+    }
+    Ok(ExitStatus::Success)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_set_value_validates_database_names() {
+        let mut config = AppConfig::default();
+
+        set_value(&mut config, "database.ipv4", "chunzhen").unwrap();
+        assert_eq!(config.database.ipv4_database, "chunzhen");
+
+        assert!(set_value(&mut config, "database.ipv4", "nosuchdb").is_err());
+        assert!(set_value(&mut config, "database.ipv6_fallback", "geoip2,nosuchdb").is_err());
+        assert_eq!(config.database.ipv4_database, "chunzhen");
+        assert!(config.database.ipv6_fallback.is_empty());
+
+        set_value(&mut config, "database.ipv6_fallback", "geoip2,ip2region").unwrap();
+        assert_eq!(config.database.ipv6_fallback, ["geoip2", "ip2region"]);
+    }
+
+    #[test]
+    fn test_unknown_databases() {
+        let mut config = AppConfig::default();
+        assert!(unknown_databases(&config).is_empty());
+
+        config.database.ipv4_database = "qqwri".to_string();
+        config.database.preload = vec!["geoip2".to_string(), "nosuchdb".to_string()];
+        assert_eq!(
+            unknown_databases(&config),
+            [
+                "database.ipv4_database: unknown database 'qqwri'",
+                "database.preload: unknown database 'nosuchdb'",
+            ]
+        );
+    }
+}
@@ -0,0 +1,154 @@
+//! `nali-rs --update --daemon`
+//!
+//! Keeps updating databases from one long-running process, for containers
+//! that would otherwise need a cron sidecar. Databases are updated right
+//! away and then every `download.update_interval` seconds, plus up to a tenth
+//! of the interval at random so replicas started together don't hit the
+//! mirrors together. Progress goes to the log rather than progress bars, and
+//! `--log-format journald` marks each line with its priority for systemd.
+//! SIGTERM or Ctrl-C stops the daemon between two runs.
+
+use crate::config::AppConfig;
+use crate::download::{self, Downloader, retry};
+use crate::error::{NaliError, Result};
+use std::time::Duration;
+
+use super::status::ExitStatus;
+
+/// Time to wait after a run: the interval plus up to a tenth of it
+fn next_delay(interval: Duration) -> Duration {
+    interval + retry::jitter(interval / 10)
+}
+
+/// Databases a run updates: `names`, or every downloadable one
+fn targets(config: &AppConfig, names: &[String]) -> Vec<String> {
+    if !names.is_empty() {
+        return download::expand_names(config, names);
+    }
+    config
+        .database
+        .databases
+        .iter()
+        // Skip CDN database (it's manually created), like `download_all`
+        .filter(|db_info| db_info.name != "cdn" && download::updated_by_default(config, db_info))
+        .map(|db_info| db_info.name.clone())
+        .collect()
+}
+
+/// Update every target once, logging each outcome; returns the failures
+async fn update_once(downloader: &Downloader, config: &AppConfig, names: &[String]) -> usize {
+    let mut failed = 0;
+    for name in targets(config, names) {
+        match downloader.download_database(config, &name).await {
+            Ok(true) => tracing::info!("Updated {}", name),
+            Ok(false) => tracing::info!("{} is up to date", name),
+            Err(e) => {
+                tracing::error!("Failed to update {}: {}", name, e);
+                failed += 1;
+            }
+        }
+    }
+    failed
+}
+
+/// Resolve once the process is asked to stop
+async fn shutdown() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{SignalKind, signal};
+        if let Ok(mut term) = signal(SignalKind::terminate()) {
+            tokio::select! {
+                _ = term.recv() => {}
+                _ = tokio::signal::ctrl_c() => {}
+            }
+            return;
+        }
+    }
+    let _ = tokio::signal::ctrl_c().await;
+}
+
+/// Update `names` (default: all) every `download.update_interval` until stopped
+pub async fn run(config: &AppConfig, names: &[String]) -> Result<ExitStatus> {
+    if config.download.update_interval == 0 {
+        return Err(NaliError::config("download.update_interval must be at least 1 second"));
+    }
+    let interval = Duration::from_secs(config.download.update_interval);
+    let downloader = Downloader::with_config(&config.download)?
+        .quiet(true)
+        .post_update(config.hooks.post_update.clone());
+
+    tracing::info!(
+        "Update daemon started, updating every {}s",
+        config.download.update_interval
+    );
+    let stop = shutdown();
+    tokio::pin!(stop);
+    loop {
+        let failed = update_once(&downloader, config, names).await;
+        let delay = next_delay(interval);
+        if failed > 0 {
+            tracing::warn!("{} databases failed to update", failed);
+        }
+        tracing::info!("Next update in {}s", delay.as_secs());
+
+        tokio::select! {
+            _ = tokio::time::sleep(delay) => {}
+            _ = &mut stop => {
+                tracing::info!("Update daemon stopped");
+                return Ok(ExitStatus::Success);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::custom_info;
+
+    #[test]
+    fn test_next_delay() {
+        let interval = Duration::from_secs(3600);
+        for _ in 0..10 {
+            let delay = next_delay(interval);
+            assert!(delay >= interval);
+            assert!(delay <= interval + interval / 10);
+        }
+    }
+
+    #[test]
+    fn test_targets() {
+        let mut config = AppConfig::default();
+        config.database.databases.push(custom_info("corp", &[]));
+
+        let all = targets(&config, &[]);
+        assert!(all.contains(&"qqwry".to_string()));
+        // Nothing to download for local and CDN databases
+        assert!(!all.contains(&"corp".to_string()));
+        assert!(!all.contains(&"cdn".to_string()));
+        // Optional databases only once installed
+        assert!(!all.contains(&"tor".to_string()));
+        assert!(!all.contains(&"aws".to_string()));
+
+        assert_eq!(targets(&config, &["cdn".to_string()]), ["cdn"]);
+        assert_eq!(targets(&config, &["clouds".to_string()]), ["aws", "gcp"]);
+    }
+
+    #[tokio::test]
+    async fn test_update_once_counts_failures() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut config = AppConfig::default();
+        config.database.data_dir = Some(dir.path().to_string_lossy().to_string());
+        let downloader = Downloader::new().unwrap().quiet(true);
+
+        let failed = update_once(&downloader, &config, &["nope".to_string()]).await;
+        assert_eq!(failed, 1);
+    }
+
+    #[tokio::test]
+    async fn test_zero_interval_is_rejected() {
+        let mut config = AppConfig::default();
+        config.download.update_interval = 0;
+        assert!(run(&config, &[]).await.is_err());
+    }
+}
@@ -0,0 +1,237 @@
+//! `nali-rs db` subcommand
+//!
+//! Inspects installed database files. `db info` reports where a database
+//! lives, how large it is and which release it is, so users can tell
+//! whether an update is due. `db history` lists the versions kept from
+//! earlier updates and `db rollback` puts one of them back.
+
+use crate::config::AppConfig;
+use crate::database::manager::{age_days, build_date};
+use crate::database::{DatabaseFactory, DatabaseManager};
+use crate::download::history::History;
+use crate::error::Result;
+use crate::utils::time::format_date;
+use clap::{Args, Subcommand};
+use indicatif::HumanBytes;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use super::status::ExitStatus;
+
+/// Arguments for `nali-rs db`
+#[derive(Args, Debug)]
+pub struct DbArgs {
+    #[command(subcommand)]
+    pub command: DbCommand,
+}
+
+/// Database subcommands
+#[derive(Subcommand, Debug)]
+pub enum DbCommand {
+    /// Show file, size and release version of databases (default: the selected ones)
+    Info {
+        /// Databases to inspect
+        #[arg(value_name = "DATABASE")]
+        names: Vec<String>,
+    },
+    /// List the versions of a database kept from earlier updates and imports
+    History {
+        /// Database to list
+        #[arg(value_name = "DATABASE")]
+        name: String,
+    },
+    /// Restore a kept version of a database (default: the one before the installed file)
+    Rollback {
+        /// Database to roll back
+        #[arg(value_name = "DATABASE")]
+        name: String,
+        /// Version to restore, as listed by `db history`
+        #[arg(value_name = "DATE")]
+        date: Option<String>,
+    },
+}
+
+/// Details of one database file
+#[derive(Debug, Default, PartialEq)]
+struct DatabaseDetails {
+    name: String,
+    format: String,
+    path: String,
+    size: Option<u64>,
+    modified: Option<String>,
+    /// Build date, with its age when older than `database.stale_after_days`
+    built: Option<String>,
+    version: Option<String>,
+}
+
+impl DatabaseDetails {
+    /// Render as aligned `key: value` lines
+    fn render(&self) -> String {
+        let or_dash = |value: &Option<String>| value.clone().unwrap_or_else(|| "-".to_string());
+        [
+            ("name", self.name.clone()),
+            ("format", self.format.clone()),
+            ("file", self.path.clone()),
+            (
+                "size",
+                self.size
+                    .map_or("not installed".to_string(), |size| HumanBytes(size).to_string()),
+            ),
+            ("modified", or_dash(&self.modified)),
+            ("built", or_dash(&self.built)),
+            ("version", or_dash(&self.version)),
+        ]
+        .iter()
+        .map(|(key, value)| format!("{:<9} {}\n", format!("{}:", key), value))
+        .collect()
+    }
+}
+
+/// Collect details for a database without downloading it
+fn inspect(config: &AppConfig, manager: &DatabaseManager, name: &str) -> Result<DatabaseDetails> {
+    let db_type = manager.get_database_type(name)?;
+    let path = config.get_database_path(name)?;
+    let mut details = DatabaseDetails {
+        name: name.to_string(),
+        format: config
+            .find_database(name)
+            .map_or_else(|| format!("{:?}", db_type), |info| info.format.clone()),
+        path: path.display().to_string(),
+        ..Default::default()
+    };
+
+    let Ok(meta) = std::fs::metadata(&path) else {
+        return Ok(details);
+    };
+    details.size = Some(meta.len());
+    details.modified = meta
+        .modified()
+        .ok()
+        .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+        .map(|age| format_date(age.as_secs()));
+
+    details.version = DatabaseFactory::file_version(db_type, &path)?;
+    details.built = build_date(details.version.as_deref(), meta.modified().ok()).map(|date| {
+        let limit = config.database.stale_after_days;
+        match age_days(&date, SystemTime::now()) {
+            Some(days) if limit > 0 && days > limit => format!("{} ({} days old)", date, days),
+            _ => date,
+        }
+    });
+
+    Ok(details)
+}
+
+/// One `db history` line
+fn history_line(date: &str, size: &str, version: &str) -> String {
+    format!("{:<13} {:>11}  {}\n", date, size, version)
+}
+
+/// `db history` line of a database file
+fn history_entry(date: &str, size: u64, version: Option<String>) -> String {
+    history_line(
+        date,
+        &HumanBytes(size).to_string(),
+        version.as_deref().unwrap_or("-"),
+    )
+}
+
+/// Run the db subcommand
+pub fn run(args: &DbArgs, config: &AppConfig) -> Result<ExitStatus> {
+    match &args.command {
+        DbCommand::Info { names } => {
+            let names: Vec<&String> = if names.is_empty() {
+                let database = &config.database;
+                let mut selected = vec![
+                    &database.ipv4_database,
+                    &database.ipv6_database,
+                    &database.cdn_database,
+                ];
+                selected.dedup();
+                selected
+            } else {
+                names.iter().collect()
+            };
+
+            let manager = DatabaseManager::new(config.clone());
+            let mut status = ExitStatus::Success;
+            let mut blocks = Vec::new();
+            for name in names {
+                let details = inspect(config, &manager, name)?;
+                if details.size.is_none() {
+                    status = ExitStatus::NotFound;
+                }
+                blocks.push(details.render());
+            }
+
+            print!("{}", blocks.join("\n"));
+            Ok(status)
+        }
+        DbCommand::History { name } => {
+            let manager = DatabaseManager::new(config.clone());
+            let db_type = manager.get_database_type(name)?;
+            let path = config.get_database_path(name)?;
+            let versions = History::new(config, name)?.versions()?;
+
+            let mut out = history_line("DATE", "SIZE", "VERSION");
+            if let Ok(meta) = std::fs::metadata(&path) {
+                out.push_str(&history_entry(
+                    "installed",
+                    meta.len(),
+                    DatabaseFactory::file_version(db_type.clone(), &path)?,
+                ));
+            }
+            for version in versions.iter().rev() {
+                out.push_str(&history_entry(
+                    &version.date,
+                    version.size,
+                    DatabaseFactory::file_version(db_type.clone(), &version.path)?,
+                ));
+            }
+            print!("{}", out);
+
+            Ok(if versions.is_empty() {
+                ExitStatus::NotFound
+            } else {
+                ExitStatus::Success
+            })
+        }
+        DbCommand::Rollback { name, date } => {
+            let path = config.get_database_path(name)?;
+            let restored = History::new(config, name)?.restore(&path, date.as_deref())?;
+            println!(
+                "Rolled back {} to the version from {}; `nali-rs db history {}` lists the rest",
+                name, restored.date, name
+            );
+            Ok(ExitStatus::Success)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_details() {
+        let details = DatabaseDetails {
+            name: "qqwry".to_string(),
+            format: "qqwry".to_string(),
+            path: "/data/qqwry.dat".to_string(),
+            size: Some(2048),
+            modified: Some("2024-01-11".to_string()),
+            built: Some("2024-01-10 (400 days old)".to_string()),
+            version: Some("2024-01-10".to_string()),
+        };
+        let rendered = details.render();
+        assert!(rendered.starts_with("name:     qqwry\n"));
+        assert!(rendered.contains("size:     2.00 KiB\n"));
+        assert!(rendered.contains("built:    2024-01-10 (400 days old)\n"));
+        assert!(rendered.ends_with("version:  2024-01-10\n"));
+
+        let missing = DatabaseDetails {
+            name: "geoip2".to_string(),
+            ..Default::default()
+        };
+        assert!(missing.render().contains("size:     not installed\n"));
+    }
+}
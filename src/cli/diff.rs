@@ -0,0 +1,325 @@
+//! `nali-rs diff` subcommand
+//!
+//! Compares two versions of a database file and reports the address ranges
+//! that were added, removed or changed, so an update can be reviewed before
+//! it is rolled out.
+
+use crate::database::{Database, IP2RegionDatabase, IpRange, QQwryDatabase};
+use crate::error::{NaliError, Result};
+use clap::{Args, ValueEnum};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::path::{Path, PathBuf};
+
+use super::status::ExitStatus;
+
+/// Database file format understood by `diff`
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DiffFormat {
+    /// ip2region xdb
+    Xdb,
+    /// QQwry / chunzhen dat
+    Qqwry,
+}
+
+impl DiffFormat {
+    /// Infer the format from a file extension
+    fn from_path(path: &Path) -> Option<Self> {
+        match path.extension()?.to_str()?.to_ascii_lowercase().as_str() {
+            "xdb" => Some(Self::Xdb),
+            "dat" => Some(Self::Qqwry),
+            _ => None,
+        }
+    }
+}
+
+/// Arguments for `nali-rs diff`
+#[derive(Args, Debug)]
+pub struct DiffArgs {
+    /// Current database file
+    #[arg(value_name = "OLD")]
+    pub old: PathBuf,
+
+    /// Candidate database file
+    #[arg(value_name = "NEW")]
+    pub new: PathBuf,
+
+    /// File format (inferred from the extension when omitted)
+    #[arg(short, long, value_enum)]
+    pub format: Option<DiffFormat>,
+}
+
+/// Kind of difference between two versions of a range
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChangeKind {
+    Added,
+    Removed,
+    Changed,
+}
+
+/// Location fields compared between versions: country, region, city, ISP
+type Fields = [Option<String>; 4];
+
+/// A range as numeric bounds within its address family
+type Entry = (u128, u128, Fields);
+
+/// A changed block before conversion back to addresses
+type Block = (ChangeKind, u128, u128, Option<Fields>, Option<Fields>);
+
+/// One contiguous block of addresses whose answer differs between versions
+#[derive(Debug, Clone, PartialEq)]
+pub struct RangeChange {
+    pub kind: ChangeKind,
+    pub start: IpAddr,
+    pub end: IpAddr,
+    pub before: Option<Fields>,
+    pub after: Option<Fields>,
+}
+
+fn fields(range: &IpRange) -> Fields {
+    [
+        range.country.clone(),
+        range.region.clone(),
+        range.city.clone(),
+        range.isp.clone(),
+    ]
+}
+
+fn describe(fields: &Fields) -> String {
+    let parts: Vec<&str> = fields.iter().flatten().map(String::as_str).collect();
+    if parts.is_empty() {
+        "-".to_string()
+    } else {
+        parts.join(" ")
+    }
+}
+
+/// Compare two range lists and return the changed blocks in address order
+///
+/// Ranges may be given in any order but must not overlap within a list.
+/// Adjacent blocks with the same change are merged.
+pub fn diff_ranges(old: &[IpRange], new: &[IpRange]) -> Vec<RangeChange> {
+    let split = |ranges: &[IpRange], v4: bool| -> Vec<Entry> {
+        let mut out: Vec<_> = ranges
+            .iter()
+            .filter_map(|r| match (r.start, r.end) {
+                (IpAddr::V4(s), IpAddr::V4(e)) if v4 => {
+                    Some((u32::from(s) as u128, u32::from(e) as u128, fields(r)))
+                }
+                (IpAddr::V6(s), IpAddr::V6(e)) if !v4 => {
+                    Some((u128::from(s), u128::from(e), fields(r)))
+                }
+                _ => None,
+            })
+            .collect();
+        out.sort_by_key(|r| r.0);
+        out
+    };
+
+    let mut changes = diff_family(&split(old, true), &split(new, true), u32::MAX as u128, |n| {
+        IpAddr::V4(Ipv4Addr::from(n as u32))
+    });
+    changes.extend(diff_family(&split(old, false), &split(new, false), u128::MAX, |n| {
+        IpAddr::V6(Ipv6Addr::from(n))
+    }));
+    changes
+}
+
+/// Diff one address family over the union of both lists' range boundaries
+fn diff_family(
+    old: &[Entry],
+    new: &[Entry],
+    max: u128,
+    to_ip: impl Fn(u128) -> IpAddr,
+) -> Vec<RangeChange> {
+    let mut bounds: Vec<u128> = old
+        .iter()
+        .chain(new)
+        .flat_map(|&(start, end, _)| [Some(start), end.checked_add(1).filter(|&n| n <= max)])
+        .flatten()
+        .collect();
+    bounds.sort_unstable();
+    bounds.dedup();
+
+    // Find the range covering `addr`, advancing a cursor over sorted ranges
+    fn covering<'a>(
+        ranges: &'a [Entry],
+        cursor: &mut usize,
+        addr: u128,
+    ) -> Option<&'a Fields> {
+        while *cursor < ranges.len() && ranges[*cursor].1 < addr {
+            *cursor += 1;
+        }
+        ranges
+            .get(*cursor)
+            .filter(|r| r.0 <= addr)
+            .map(|r| &r.2)
+    }
+
+    let mut changes: Vec<Block> = Vec::new();
+    let (mut old_cursor, mut new_cursor) = (0, 0);
+
+    for (i, &start) in bounds.iter().enumerate() {
+        let end = bounds.get(i + 1).map_or(max, |next| next - 1);
+        let before = covering(old, &mut old_cursor, start);
+        let after = covering(new, &mut new_cursor, start);
+
+        let kind = match (before, after) {
+            (Some(_), None) => ChangeKind::Removed,
+            (None, Some(_)) => ChangeKind::Added,
+            (Some(b), Some(a)) if b != a => ChangeKind::Changed,
+            _ => continue,
+        };
+
+        if let Some(last) = changes.last_mut()
+            && last.0 == kind
+            && last.2 + 1 == start
+            && last.3.as_ref() == before
+            && last.4.as_ref() == after
+        {
+            last.2 = end;
+            continue;
+        }
+        changes.push((kind, start, end, before.cloned(), after.cloned()));
+    }
+
+    changes
+        .into_iter()
+        .map(|(kind, start, end, before, after)| RangeChange {
+            kind,
+            start: to_ip(start),
+            end: to_ip(end),
+            before,
+            after,
+        })
+        .collect()
+}
+
+/// Load every range of a database file
+fn load_ranges(path: &Path, format: DiffFormat) -> Result<Vec<IpRange>> {
+    let path_str = path.to_string_lossy();
+    match format {
+        DiffFormat::Xdb => {
+            let mut db = IP2RegionDatabase::new();
+            db.load_from_file(&path_str)?;
+            db.ranges()?.collect()
+        }
+        DiffFormat::Qqwry => {
+            let mut db = QQwryDatabase::new();
+            db.load_from_file(&path_str)?;
+            db.ranges()?.collect()
+        }
+    }
+}
+
+/// Run the diff subcommand
+pub fn run(args: &DiffArgs) -> Result<ExitStatus> {
+    let format = args
+        .format
+        .or_else(|| DiffFormat::from_path(&args.old))
+        .ok_or_else(|| {
+            NaliError::config(format!(
+                "Cannot infer database format of {:?}; pass --format",
+                args.old
+            ))
+        })?;
+
+    let old = load_ranges(&args.old, format)?;
+    let new = load_ranges(&args.new, format)?;
+    let changes = diff_ranges(&old, &new);
+
+    let (mut added, mut removed, mut changed) = (0, 0, 0);
+    for change in &changes {
+        let span = format!("{}-{}", change.start, change.end);
+        match change.kind {
+            ChangeKind::Added => {
+                added += 1;
+                println!("+ {}  {}", span, describe(change.after.as_ref().unwrap()));
+            }
+            ChangeKind::Removed => {
+                removed += 1;
+                println!("- {}  {}", span, describe(change.before.as_ref().unwrap()));
+            }
+            ChangeKind::Changed => {
+                changed += 1;
+                println!(
+                    "~ {}  {} -> {}",
+                    span,
+                    describe(change.before.as_ref().unwrap()),
+                    describe(change.after.as_ref().unwrap())
+                );
+            }
+        }
+    }
+
+    eprintln!(
+        "{} ranges -> {} ranges: {} added, {} removed, {} changed",
+        old.len(),
+        new.len(),
+        added,
+        removed,
+        changed
+    );
+    Ok(ExitStatus::Success)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn range(start: &str, end: &str, country: &str) -> IpRange {
+        IpRange {
+            start: start.parse().unwrap(),
+            end: end.parse().unwrap(),
+            country: Some(country.to_string()),
+            region: None,
+            city: None,
+            isp: None,
+        }
+    }
+
+    #[test]
+    fn test_diff_ranges() {
+        let old = vec![
+            range("1.0.0.0", "1.0.0.255", "A"),
+            range("1.0.1.0", "1.0.1.255", "B"),
+            range("2.0.0.0", "2.0.0.255", "C"),
+        ];
+        let new = vec![
+            range("1.0.0.0", "1.0.1.127", "A"),
+            range("1.0.1.128", "1.0.1.255", "B"),
+            range("3.0.0.0", "3.0.0.255", "D"),
+        ];
+
+        let changes = diff_ranges(&old, &new);
+        assert_eq!(changes.len(), 3);
+
+        assert_eq!(changes[0].kind, ChangeKind::Changed);
+        assert_eq!(changes[0].start.to_string(), "1.0.1.0");
+        assert_eq!(changes[0].end.to_string(), "1.0.1.127");
+
+        assert_eq!(changes[1].kind, ChangeKind::Removed);
+        assert_eq!(changes[1].end.to_string(), "2.0.0.255");
+
+        assert_eq!(changes[2].kind, ChangeKind::Added);
+        assert_eq!(changes[2].start.to_string(), "3.0.0.0");
+        assert_eq!(describe(changes[2].after.as_ref().unwrap()), "D");
+    }
+
+    #[test]
+    fn test_diff_identical_and_full_space() {
+        let ranges = vec![range("0.0.0.0", "255.255.255.255", "X")];
+        assert!(diff_ranges(&ranges, &ranges).is_empty());
+
+        let new = vec![range("0.0.0.0", "255.255.255.255", "Y")];
+        let changes = diff_ranges(&ranges, &new);
+        assert_eq!(changes.len(), 1);
+        assert_eq!(changes[0].end.to_string(), "255.255.255.255");
+    }
+
+    #[test]
+    fn test_format_from_path() {
+        assert_eq!(DiffFormat::from_path(Path::new("a/ip2region.xdb")), Some(DiffFormat::Xdb));
+        assert_eq!(DiffFormat::from_path(Path::new("qqwry.DAT")), Some(DiffFormat::Qqwry));
+        assert_eq!(DiffFormat::from_path(Path::new("corp.nali")), None);
+    }
+}
@@ -0,0 +1,211 @@
+//! `nali-rs dig` subcommand
+//!
+//! Performs a DNS query and prints the answer section with each A/AAAA
+//! record annotated with geolocation and each CNAME with its CDN provider,
+//! replacing the `dig | nali-rs` pipeline.
+
+use crate::config::AppConfig;
+use crate::database::DatabaseManager;
+use crate::dns::client::DNS_PORT;
+use crate::dns::{Record, RecordData, RecordType, Resolver};
+use crate::entity::formatter;
+use crate::error::{NaliError, Result};
+use clap::Args;
+use std::net::{IpAddr, SocketAddr};
+use std::time::{Duration, Instant};
+
+use super::status::ExitStatus;
+
+/// Arguments for `nali-rs dig`
+#[derive(Args, Debug)]
+pub struct DigArgs {
+    /// Name to look up, optionally followed by `@server` and a record type (e.g. `example.com @8.8.8.8 AAAA`)
+    #[arg(value_name = "ARGS", required = true, num_args = 1..)]
+    pub args: Vec<String>,
+
+    /// Record type (default A)
+    #[arg(short = 't', long = "type", value_name = "TYPE")]
+    pub record_type: Option<String>,
+
+    /// Server port
+    #[arg(short, long, default_value_t = DNS_PORT)]
+    pub port: u16,
+
+    /// Query timeout in seconds
+    #[arg(long, default_value_t = 5)]
+    pub timeout: u64,
+}
+
+/// A dig-style query line split into its parts
+#[derive(Debug, PartialEq)]
+struct Query {
+    name: String,
+    server: Option<String>,
+    rtype: RecordType,
+}
+
+/// Split dig-style arguments into name, `@server` and record type
+fn parse_query(args: &DigArgs) -> Result<Query> {
+    let mut name = None;
+    let mut server = None;
+    let mut rtype = args
+        .record_type
+        .as_deref()
+        .map(str::parse)
+        .transpose()?;
+
+    for arg in &args.args {
+        if let Some(host) = arg.strip_prefix('@') {
+            server = Some(host.to_string());
+        } else if name.is_some() && rtype.is_none() {
+            rtype = Some(arg.parse()?);
+        } else if name.is_none() {
+            name = Some(arg.clone());
+        } else {
+            return Err(NaliError::parse(format!("Unexpected argument: {}", arg)));
+        }
+    }
+
+    Ok(Query {
+        name: name.ok_or_else(|| NaliError::parse("No name to look up"))?,
+        server,
+        rtype: rtype.unwrap_or(RecordType::A),
+    })
+}
+
+/// Resolve the `@server` argument to a socket address
+async fn server_addr(host: &str, port: u16) -> Result<SocketAddr> {
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+    if let Ok(ip) = host.parse::<IpAddr>() {
+        return Ok(SocketAddr::new(ip, port));
+    }
+
+    tokio::net::lookup_host((host, port))
+        .await?
+        .next()
+        .ok_or_else(|| NaliError::network(format!("Cannot resolve DNS server: {}", host)))
+}
+
+/// Annotation for one record: geolocation for addresses, CDN for CNAMEs
+async fn annotate(record: &Record, db_manager: &DatabaseManager) -> Option<String> {
+    let result = match record.data {
+        RecordData::A(ip) => db_manager.query_ip(IpAddr::V4(ip)).await.map(|geo| {
+            geo.map(|geo| formatter::format_geo_info_compact(&geo))
+        }),
+        RecordData::AAAA(ip) => db_manager.query_ip(IpAddr::V6(ip)).await.map(|geo| {
+            geo.map(|geo| formatter::format_geo_info_compact(&geo))
+        }),
+        RecordData::Name(ref target) if record.rtype == RecordType::CNAME => db_manager
+            .query_cdn(target.trim_end_matches('.'))
+            .await
+            .map(|cdn| cdn.map(|cdn| cdn.provider)),
+        _ => return None,
+    };
+
+    match result {
+        Ok(annotation) => annotation,
+        Err(e) => {
+            tracing::warn!("Lookup for {} failed: {}", record.data, e);
+            None
+        }
+    }
+}
+
+/// Print one section of records with annotations
+async fn print_section(title: &str, records: &[Record], db_manager: &DatabaseManager) {
+    if records.is_empty() {
+        return;
+    }
+
+    let width = records.iter().map(|r| r.name.len()).max().unwrap_or(0);
+    println!(";; {} SECTION:", title);
+    for record in records {
+        let annotation = annotate(record, db_manager)
+            .await
+            .map(|a| format!(" [{}]", a))
+            .unwrap_or_default();
+        println!(
+            "{:<width$} {:>6}  IN  {:<5}  {}{}",
+            record.name,
+            record.ttl,
+            record.rtype.to_string(),
+            record.data,
+            annotation,
+            width = width
+        );
+    }
+    println!();
+}
+
+/// Run the dig subcommand
+pub async fn run(args: &DigArgs, config: &AppConfig) -> Result<ExitStatus> {
+    let query = parse_query(args)?;
+    let timeout = Duration::from_secs(args.timeout);
+
+    let resolver = match query.server {
+        Some(ref host) => Resolver::new(server_addr(host, args.port).await?, timeout),
+        None => Resolver::system(timeout),
+    };
+
+    let started = Instant::now();
+    let response = resolver.query(&query.name, query.rtype).await?;
+    let elapsed = started.elapsed();
+
+    println!(
+        ";; ->>HEADER<<- status: {}, id: {}, answers: {}",
+        response.rcode_name(),
+        response.id,
+        response.answers.len()
+    );
+    println!(";; QUESTION: {} IN {}", query.name, query.rtype);
+    println!();
+
+    let db_manager = DatabaseManager::new(config.clone());
+    print_section("ANSWER", &response.answers, &db_manager).await;
+    if response.answers.is_empty() {
+        print_section("AUTHORITY", &response.authority, &db_manager).await;
+    }
+
+    println!(";; Query time: {} msec", elapsed.as_millis());
+    println!(";; SERVER: {}", resolver.server());
+
+    Ok(if response.rcode == 0 {
+        ExitStatus::Success
+    } else {
+        ExitStatus::NotFound
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> DigArgs {
+        DigArgs {
+            args: args.iter().map(|s| s.to_string()).collect(),
+            record_type: None,
+            port: DNS_PORT,
+            timeout: 5,
+        }
+    }
+
+    #[test]
+    fn test_parse_query() {
+        let query = parse_query(&args(&["example.com", "@8.8.8.8", "AAAA"])).unwrap();
+        assert_eq!(
+            query,
+            Query {
+                name: "example.com".to_string(),
+                server: Some("8.8.8.8".to_string()),
+                rtype: RecordType::AAAA,
+            }
+        );
+
+        let query = parse_query(&args(&["@1.1.1.1", "example.com"])).unwrap();
+        assert_eq!(query.rtype, RecordType::A);
+        assert_eq!(query.name, "example.com");
+
+        assert!(parse_query(&args(&["example.com", "bogus"])).is_err());
+        assert!(parse_query(&args(&["@1.1.1.1"])).is_err());
+    }
+}
@@ -0,0 +1,160 @@
+//! `nali-rs export` subcommand
+//!
+//! Dumps every range of a database to CSV or JSON Lines, for auditing
+//! database contents or feeding other tools. The CSV layout matches the
+//! input of `nali-rs build`.
+
+use crate::config::AppConfig;
+use crate::database::{
+    Database, DatabaseManager, DatabaseType, IP2RegionDatabase, IpRange, QQwryDatabase,
+};
+use crate::error::{NaliError, Result};
+use clap::{Args, ValueEnum};
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::PathBuf;
+
+use super::status::ExitStatus;
+
+/// Export output format
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ExportFormat {
+    /// `start_ip,end_ip,country,region,city,isp` rows with a header
+    Csv,
+    /// One JSON object per line
+    Jsonl,
+}
+
+/// Arguments for `nali-rs export`
+#[derive(Args, Debug)]
+pub struct ExportArgs {
+    /// Database to export (e.g. qqwry, ip2region)
+    #[arg(value_name = "DATABASE")]
+    pub database: String,
+
+    /// Output format
+    #[arg(short, long, value_enum, default_value_t = ExportFormat::Csv)]
+    pub format: ExportFormat,
+
+    /// Output file (defaults to standard output)
+    #[arg(short, long, value_name = "FILE")]
+    pub output: Option<PathBuf>,
+}
+
+/// Write ranges in the requested format, returning the number written
+pub fn write_ranges<I, W>(ranges: I, format: ExportFormat, writer: W) -> Result<usize>
+where
+    I: Iterator<Item = Result<IpRange>>,
+    W: Write,
+{
+    let mut count = 0;
+
+    match format {
+        ExportFormat::Csv => {
+            let mut csv_writer = csv::Writer::from_writer(writer);
+            let csv_err = |e: csv::Error| NaliError::Other(format!("CSV write error: {}", e));
+
+            csv_writer
+                .write_record(["start_ip", "end_ip", "country", "region", "city", "isp"])
+                .map_err(csv_err)?;
+            for range in ranges {
+                let range = range?;
+                let field = |f: &Option<String>| f.clone().unwrap_or_default();
+                csv_writer
+                    .write_record([
+                        range.start.to_string(),
+                        range.end.to_string(),
+                        field(&range.country),
+                        field(&range.region),
+                        field(&range.city),
+                        field(&range.isp),
+                    ])
+                    .map_err(csv_err)?;
+                count += 1;
+            }
+            csv_writer.flush()?;
+        }
+        ExportFormat::Jsonl => {
+            let mut writer = writer;
+            for range in ranges {
+                serde_json::to_writer(&mut writer, &range?)?;
+                writer.write_all(b"\n")?;
+                count += 1;
+            }
+            writer.flush()?;
+        }
+    }
+
+    Ok(count)
+}
+
+/// Run the export subcommand
+pub fn run(args: &ExportArgs, config: &AppConfig) -> Result<ExitStatus> {
+    let manager = DatabaseManager::new(config.clone());
+    let db_type = manager.get_database_type(&args.database)?;
+    let path = config.get_database_path(&args.database)?;
+    let path_str = path.to_string_lossy();
+
+    let writer: Box<dyn Write> = match args.output {
+        Some(ref output) => Box::new(BufWriter::new(File::create(output)?)),
+        None => Box::new(BufWriter::new(io::stdout().lock())),
+    };
+
+    let count = match db_type {
+        DatabaseType::QQwry => {
+            let mut db = QQwryDatabase::new();
+            db.load_from_file(&path_str)?;
+            write_ranges(db.ranges()?, args.format, writer)?
+        }
+        DatabaseType::IP2Region => {
+            let mut db = IP2RegionDatabase::new();
+            db.load_from_file(&path_str)?;
+            write_ranges(db.ranges()?, args.format, writer)?
+        }
+        _ => {
+            return Err(NaliError::config(format!(
+                "Export is not supported for database: {}",
+                args.database
+            )));
+        }
+    };
+
+    eprintln!("✓ Exported {} ranges from {}", count, args.database);
+    Ok(ExitStatus::Success)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample() -> Vec<Result<IpRange>> {
+        vec![Ok(IpRange {
+            start: "1.0.0.0".parse().unwrap(),
+            end: "1.0.0.255".parse().unwrap(),
+            country: Some("美国".to_string()),
+            region: None,
+            city: None,
+            isp: Some("CloudFlare, Inc.".to_string()),
+        })]
+    }
+
+    #[test]
+    fn test_write_csv() {
+        let mut buf = Vec::new();
+        let count = write_ranges(sample().into_iter(), ExportFormat::Csv, &mut buf).unwrap();
+        assert_eq!(count, 1);
+        assert_eq!(
+            String::from_utf8(buf).unwrap(),
+            "start_ip,end_ip,country,region,city,isp\n1.0.0.0,1.0.0.255,美国,,,\"CloudFlare, Inc.\"\n"
+        );
+    }
+
+    #[test]
+    fn test_write_jsonl() {
+        let mut buf = Vec::new();
+        write_ranges(sample().into_iter(), ExportFormat::Jsonl, &mut buf).unwrap();
+        let line = String::from_utf8(buf).unwrap();
+        assert!(line.starts_with("{\"start\":\"1.0.0.0\""));
+        assert!(line.ends_with("}\n"));
+    }
+}
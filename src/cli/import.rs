@@ -0,0 +1,211 @@
+//! `nali-rs import` subcommand
+//!
+//! Installs a database file that was brought in by hand, for machines where
+//! `update` can never reach the internet. The file is loaded with the parser
+//! of its database first, so a truncated copy or the wrong format is rejected
+//! before anything is replaced.
+
+use crate::config::AppConfig;
+use crate::database::{DatabaseFactory, DatabaseManager};
+use crate::download::history::History;
+use crate::download::install;
+use crate::error::{NaliError, Result};
+use crate::utils::path;
+use clap::Args;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use super::status::ExitStatus;
+
+/// Arguments for `nali-rs import`
+#[derive(Args, Debug)]
+pub struct ImportArgs {
+    /// Database file to install
+    #[arg(value_name = "FILE")]
+    pub source: PathBuf,
+
+    /// Database the file is for (defaults to the one whose file name matches)
+    #[arg(long = "as", value_name = "NAME")]
+    pub name: Option<String>,
+}
+
+/// Result of a successful import
+#[derive(Debug)]
+pub(super) struct Imported {
+    pub name: String,
+    pub path: PathBuf,
+    pub version: Option<String>,
+}
+
+/// Pick the database a file belongs to from its file name
+fn infer_name(config: &AppConfig, source: &Path) -> Result<String> {
+    let file_name = source.file_name().and_then(|name| name.to_str());
+    config
+        .database
+        .databases
+        .iter()
+        .find(|db| Some(db.file.as_str()) == file_name)
+        .map(|db| db.name.clone())
+        .ok_or_else(|| {
+            NaliError::config(format!(
+                "Cannot tell which database {} is; pass --as NAME",
+                source.display()
+            ))
+        })
+}
+
+/// Validate `source`, copy it into the data directory and register its path
+///
+/// The configuration is updated in memory only; the caller saves it.
+pub(super) fn import(config: &mut AppConfig, source: &Path, name: Option<&str>) -> Result<Imported> {
+    let name = match name {
+        // Register under the canonical name so aliases resolve to the same entry
+        Some(name) => config
+            .find_database(name)
+            .map_or_else(|| name.to_string(), |db| db.name.clone()),
+        None => infer_name(config, source)?,
+    };
+
+    let db_type = DatabaseManager::new(config.clone()).get_database_type(&name)?;
+    let source_str = source
+        .to_str()
+        .ok_or_else(|| NaliError::config(format!("File path is not valid UTF-8: {:?}", source)))?;
+    let mut db = DatabaseFactory::create(db_type)?;
+    if let Some(key) = &config.database.czdb_key {
+        db.set_key(key);
+    }
+    db.load_from_file(source_str).map_err(|e| {
+        NaliError::DatabaseCorrupted(format!(
+            "{} is not a valid {} database ({})",
+            source.display(),
+            name,
+            e
+        ))
+    })?;
+
+    let file_name = match config.find_database(&name) {
+        Some(db) => PathBuf::from(&db.file),
+        None => PathBuf::from(
+            source
+                .file_name()
+                .ok_or_else(|| NaliError::config(format!("Not a file: {}", source.display())))?,
+        ),
+    };
+    let data_dir = config.data_dir()?;
+    path::ensure_dir(&data_dir)?;
+    let target = data_dir.join(file_name);
+
+    if fs::canonicalize(source)? != fs::canonicalize(&target).unwrap_or_default() {
+        // Copy next to the target and rename, so a running instance never
+        // sees a half-written file; the replaced file goes to the history
+        let history = History::new(config, &name)?;
+        let partial = install::staging_path(&target);
+        if let Err(e) = fs::copy(source, &partial)
+            .map_err(NaliError::IoError)
+            .and_then(|_| install::install(&partial, &target, Some(&history)))
+        {
+            install::discard(&partial);
+            return Err(e);
+        }
+    }
+
+    config
+        .database
+        .database_paths
+        .insert(name.clone(), target.to_string_lossy().to_string());
+
+    Ok(Imported {
+        name,
+        path: target,
+        version: db.version(),
+    })
+}
+
+/// Run the import subcommand
+pub fn run(args: &ImportArgs, effective: &AppConfig) -> Result<ExitStatus> {
+    // Start from the file so environment overrides aren't saved into it,
+    // but import into the work directory given with --work-dir
+    let mut config = AppConfig::load_file()?;
+    let own_work_dir =
+        std::mem::replace(&mut config.global.work_dir, effective.global.work_dir.clone());
+    let imported = import(&mut config, &args.source, args.name.as_deref())?;
+    config.global.work_dir = own_work_dir;
+
+    // Under the configured work directory the path is kept relative, so the
+    // directory can be moved or checked in along with the configuration
+    if let Some(work_dir) = config.work_dir()
+        && let Ok(relative) = imported.path.strip_prefix(&work_dir)
+    {
+        config
+            .database
+            .database_paths
+            .insert(imported.name.clone(), relative.to_string_lossy().to_string());
+    }
+    config.save(&path::config_file()?)?;
+
+    println!(
+        "✓ Imported {} database{} to {}",
+        imported.name,
+        imported
+            .version
+            .map(|v| format!(" (version {})", v))
+            .unwrap_or_default(),
+        imported.path.display()
+    );
+    Ok(ExitStatus::Success)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::DatabaseInfo;
+    use crate::test_support::{custom_file, custom_info};
+
+    fn setup() -> (tempfile::TempDir, AppConfig) {
+        let dir = tempfile::tempdir().unwrap();
+        let mut config = AppConfig::default();
+        config.database.data_dir = Some(dir.path().join("data").to_string_lossy().to_string());
+        config.database.databases.push(DatabaseInfo {
+            name_alias: vec!["internal".to_string()],
+            ..custom_info("corp", &[])
+        });
+        (dir, config)
+    }
+
+    #[test]
+    fn test_import_copies_and_registers() {
+        let (dir, mut config) = setup();
+        let source = custom_file(dir.path(), "usb-stick", "10.0.0.0,10.255.255.255,Internal,,,\n");
+
+        let imported = import(&mut config, &source, Some("internal")).unwrap();
+        assert_eq!(imported.name, "corp");
+        assert_eq!(imported.path, dir.path().join("data").join("corp.nali"));
+        assert_eq!(
+            fs::read(&imported.path).unwrap(),
+            fs::read(&source).unwrap()
+        );
+        assert_eq!(config.get_database_path("corp").unwrap(), imported.path);
+    }
+
+    #[test]
+    fn test_import_rejects_invalid_file() {
+        let (dir, mut config) = setup();
+        let source = dir.path().join("corp.nali");
+        fs::write(&source, b"definitely not a database").unwrap();
+
+        assert!(import(&mut config, &source, None).is_err());
+        assert!(!dir.path().join("data").join("corp.nali").exists());
+        assert!(config.database.database_paths.is_empty());
+    }
+
+    #[test]
+    fn test_infer_name() {
+        let (_dir, config) = setup();
+        assert_eq!(
+            infer_name(&config, Path::new("/mnt/qqwry.dat")).unwrap(),
+            "qqwry"
+        );
+        assert_eq!(infer_name(&config, Path::new("corp.nali")).unwrap(), "corp");
+        assert!(infer_name(&config, Path::new("download.bin")).is_err());
+    }
+}
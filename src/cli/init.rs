@@ -0,0 +1,452 @@
+//! `nali-rs init` subcommand
+//!
+//! Walks a new user through choosing databases, instead of the first query
+//! quietly downloading the Chinese-centric defaults. Each database offered
+//! comes with what it covers and how big its download is; databases that
+//! can't be downloaded for licensing reasons are imported from a file the
+//! user fetched themselves. The chosen databases are installed, the
+//! configuration is written and a test query shows what output looks like.
+
+use crate::config::AppConfig;
+use crate::config::AutoDownload;
+use crate::database::DatabaseManager;
+use crate::download::Downloader;
+use crate::entity::formatter;
+use crate::error::{NaliError, Result};
+use crate::i18n::{self, Language};
+use crate::utils::path;
+use clap::Args;
+use indicatif::HumanBytes;
+use std::collections::{BTreeMap, HashMap};
+use std::io::{self, BufRead, Write};
+use std::net::IpAddr;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use super::import;
+use super::status::ExitStatus;
+
+/// Arguments for `nali-rs init`
+#[derive(Args, Debug)]
+pub struct InitArgs {
+    /// Take the suggested answer to every question without asking
+    #[arg(short, long)]
+    pub yes: bool,
+}
+
+/// A database offered by the wizard
+struct Choice {
+    name: &'static str,
+    description: &'static str,
+}
+
+/// Databases offered for IPv4 addresses, the suggested one first
+const IPV4_CHOICES: &[Choice] = &[
+    Choice {
+        name: "qqwry",
+        description: "Chunzhen QQWry: worldwide, Chinese place names and ISPs",
+    },
+    Choice {
+        name: "ip2region",
+        description: "ip2region: worldwide, Chinese names down to the city, with ISP",
+    },
+    Choice {
+        name: "geocn",
+        description: "GeoCN: mainland China down to the district, nothing elsewhere",
+    },
+    Choice {
+        name: "geoip2",
+        description: "MaxMind GeoLite2 City: worldwide, English names (import GeoLite2-City.mmdb)",
+    },
+    Choice {
+        name: "ipip",
+        description: "IPIP.net free: worldwide, Chinese and English names (import city.free.ipdb)",
+    },
+];
+
+/// Databases offered for IPv6 addresses, the suggested one first
+const IPV6_CHOICES: &[Choice] = &[
+    Choice {
+        name: "zxipv6wry",
+        description: "ZX IPv6: worldwide, Chinese place names and ISPs",
+    },
+    Choice {
+        name: "geoip2",
+        description: "MaxMind GeoLite2 City: worldwide, English names (import GeoLite2-City.mmdb)",
+    },
+    Choice {
+        name: "ipip",
+        description: "IPIP.net free: worldwide, Chinese and English names (import city.free.ipdb)",
+    },
+];
+
+/// Languages offered for place names and messages
+const LANGUAGES: &[(&str, &str)] = &[("en", "English"), ("zh-CN", "Simplified Chinese")];
+
+/// How long the size of a download is waited for
+const SIZE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Answers to the wizard
+#[derive(Debug, PartialEq)]
+struct Answers {
+    language: String,
+    ipv4: String,
+    ipv6: String,
+    /// Whether domains are annotated with their CDN provider
+    cdn: bool,
+    /// Files to import, by database name; the other databases are downloaded
+    files: BTreeMap<String, PathBuf>,
+}
+
+impl Answers {
+    /// The suggested answer to every question
+    fn suggested() -> Self {
+        Self {
+            language: suggested_language().to_string(),
+            ipv4: IPV4_CHOICES[0].name.to_string(),
+            ipv6: IPV6_CHOICES[0].name.to_string(),
+            cdn: true,
+            files: BTreeMap::new(),
+        }
+    }
+
+    /// Databases to download
+    fn downloads(&self) -> Vec<&str> {
+        let mut names = vec![self.ipv4.as_str()];
+        if self.ipv6 != self.ipv4 {
+            names.push(&self.ipv6);
+        }
+        if self.cdn {
+            names.push("cdn");
+        }
+        names.retain(|name| !self.files.contains_key(*name));
+        names
+    }
+}
+
+/// Tag of the language messages are currently shown in
+fn suggested_language() -> &'static str {
+    match i18n::language() {
+        Language::ZhCn => "zh-CN",
+        Language::En => "en",
+    }
+}
+
+/// Whether `name` can only be installed from a file
+fn is_manual(config: &AppConfig, name: &str) -> bool {
+    config
+        .find_database(name)
+        .is_none_or(|db| db.download_urls.is_empty())
+}
+
+/// Questions and answers on a pair of streams
+struct Prompt<R, W> {
+    input: R,
+    output: W,
+}
+
+impl<R: BufRead, W: Write> Prompt<R, W> {
+    /// Ask `question` and return the trimmed answer
+    fn line(&mut self, question: &str) -> Result<String> {
+        write!(self.output, "{} ", question)?;
+        self.output.flush()?;
+        let mut answer = String::new();
+        if self.input.read_line(&mut answer)? == 0 {
+            return Err(NaliError::config("Input ended before setup was complete"));
+        }
+        Ok(answer.trim().to_string())
+    }
+
+    /// Ask for one of `options` by number; an empty answer picks the first
+    fn choose(&mut self, title: &str, options: &[String]) -> Result<usize> {
+        writeln!(self.output, "\n{}", title)?;
+        for (i, option) in options.iter().enumerate() {
+            writeln!(self.output, "  {}) {}", i + 1, option)?;
+        }
+        loop {
+            let answer = self.line("Choice [1]:")?;
+            if answer.is_empty() {
+                return Ok(0);
+            }
+            match answer.parse::<usize>() {
+                Ok(n) if (1..=options.len()).contains(&n) => return Ok(n - 1),
+                _ => writeln!(self.output, "Please enter a number from 1 to {}", options.len())?,
+            }
+        }
+    }
+
+    /// Ask a yes/no question; an empty answer means yes
+    fn confirm(&mut self, question: &str) -> Result<bool> {
+        loop {
+            match self.line(&format!("{} [Y/n]", question))?.to_ascii_lowercase().as_str() {
+                "" | "y" | "yes" => return Ok(true),
+                "n" | "no" => return Ok(false),
+                _ => writeln!(self.output, "Please answer y or n")?,
+            }
+        }
+    }
+
+    /// Ask for an existing file to import as database `name`
+    fn file(&mut self, name: &str) -> Result<PathBuf> {
+        loop {
+            let answer = self.line(&format!("Path of the {} file you downloaded:", name))?;
+            let file = path::expand_path(&answer);
+            if !answer.is_empty() && file.is_file() {
+                return Ok(file);
+            }
+            writeln!(self.output, "No such file: {}", answer)?;
+        }
+    }
+}
+
+/// One line of a database menu: name, download size and description
+fn option_line(config: &AppConfig, choice: &Choice, sizes: &HashMap<String, u64>) -> String {
+    let size = if is_manual(config, choice.name) {
+        "import".to_string()
+    } else {
+        sizes
+            .get(choice.name)
+            .map_or("-".to_string(), |&size| HumanBytes(size).to_string())
+    };
+    format!("{:<10} {:>10}  {}", choice.name, size, choice.description)
+}
+
+/// Ask which database to use from `choices`, and for its file if it has to
+/// be imported
+fn ask_database<R: BufRead, W: Write>(
+    prompt: &mut Prompt<R, W>,
+    config: &AppConfig,
+    title: &str,
+    choices: &[Choice],
+    sizes: &HashMap<String, u64>,
+    files: &mut BTreeMap<String, PathBuf>,
+) -> Result<String> {
+    let options: Vec<String> = choices
+        .iter()
+        .map(|choice| option_line(config, choice, sizes))
+        .collect();
+    let name = choices[prompt.choose(title, &options)?].name;
+    if is_manual(config, name) && !files.contains_key(name) {
+        let file = prompt.file(name)?;
+        files.insert(name.to_string(), file);
+    }
+    Ok(name.to_string())
+}
+
+/// Ask every question of the wizard
+fn ask<R: BufRead, W: Write>(
+    prompt: &mut Prompt<R, W>,
+    config: &AppConfig,
+    sizes: &HashMap<String, u64>,
+) -> Result<Answers> {
+    let languages: Vec<String> = LANGUAGES
+        .iter()
+        .map(|(tag, name)| format!("{:<10} {}", tag, name))
+        .collect();
+    // Suggest the language messages are already shown in
+    let mut order: Vec<usize> = (0..LANGUAGES.len()).collect();
+    order.sort_by_key(|&i| LANGUAGES[i].0 != suggested_language());
+    let ordered: Vec<String> = order.iter().map(|&i| languages[i].clone()).collect();
+    let language = LANGUAGES[order[prompt.choose("Language of place names and messages:", &ordered)?]].0;
+
+    let mut files = BTreeMap::new();
+    let ipv4 = ask_database(prompt, config, "Database for IPv4 addresses:", IPV4_CHOICES, sizes, &mut files)?;
+    let ipv6 = ask_database(prompt, config, "Database for IPv6 addresses:", IPV6_CHOICES, sizes, &mut files)?;
+
+    let cdn_size = sizes
+        .get("cdn")
+        .map(|&size| format!(" ({})", HumanBytes(size)))
+        .unwrap_or_default();
+    writeln!(prompt.output)?;
+    let cdn = prompt.confirm(&format!(
+        "Show the CDN provider of domains, from the cdn database{}?",
+        cdn_size
+    ))?;
+
+    Ok(Answers {
+        language: language.to_string(),
+        ipv4,
+        ipv6,
+        cdn,
+        files,
+    })
+}
+
+/// Announced download size of every database the wizard offers, as far as
+/// the servers answer in time
+async fn download_sizes(downloader: &Downloader, config: &AppConfig) -> HashMap<String, u64> {
+    let mut names: Vec<&str> = IPV4_CHOICES.iter().chain(IPV6_CHOICES).map(|choice| choice.name).collect();
+    names.push("cdn");
+
+    let mut sizes = HashMap::new();
+    for db in names.iter().filter_map(|name| config.find_database(name)) {
+        let Some(url) = db.download_urls.first() else {
+            continue;
+        };
+        if let Ok(Some(size)) = tokio::time::timeout(SIZE_TIMEOUT, downloader.remote_size(url)).await {
+            sizes.insert(db.name.clone(), size);
+        }
+    }
+    sizes
+}
+
+/// Apply `answers` to `config`
+fn apply(answers: &Answers, config: &mut AppConfig) {
+    config.database.language = answers.language.clone();
+    config.database.ipv4_database = answers.ipv4.clone();
+    config.database.ipv6_database = answers.ipv6.clone();
+    let disabled = &mut config.enrichment.disabled;
+    disabled.retain(|name| name != "cdn");
+    if !answers.cdn {
+        disabled.push("cdn".to_string());
+    }
+}
+
+/// Address to try the IPv4 database on
+fn test_address(config: &AppConfig) -> IpAddr {
+    match config.database.ipv4_database.as_str() {
+        // Knows nothing outside mainland China
+        "geocn" => IpAddr::from([114, 114, 114, 114]),
+        _ => IpAddr::from([8, 8, 8, 8]),
+    }
+}
+
+/// Query the test address and print the answer like a normal query would
+async fn test_query(config: &AppConfig) -> Result<()> {
+    let mut config = config.clone();
+    config.download.auto = AutoDownload::Off;
+    let ip = test_address(&config);
+    let geo = DatabaseManager::new(config.clone()).query_ip(ip).await?;
+    let info = match geo {
+        Some(geo) => formatter::format_geo_info_colored(
+            &geo,
+            config.output.enable_colors,
+            &formatter::GeoStyle::from(&config.output),
+        ),
+        None => format!("[{}]", crate::tr!("Not found")),
+    };
+    println!("\nTest query: {} -> {}", ip, info);
+    Ok(())
+}
+
+/// Run the init subcommand
+pub async fn run(args: &InitArgs) -> Result<ExitStatus> {
+    // Start from the file so environment overrides aren't saved into it
+    let config_file = path::config_file()?;
+    let mut config = AppConfig::load_file()?;
+    let downloader = Downloader::with_config(&config.download)?;
+
+    let answers = if args.yes {
+        Answers::suggested()
+    } else {
+        println!("This sets up nali-rs and writes {}.", config_file.display());
+        println!("Press Enter to take the suggested answer.");
+        let sizes = download_sizes(&downloader, &config).await;
+        let mut prompt = Prompt {
+            input: io::stdin().lock(),
+            output: io::stdout(),
+        };
+        ask(&mut prompt, &config, &sizes)?
+    };
+    apply(&answers, &mut config);
+    i18n::set_language(Language::from_tag(&answers.language));
+    println!();
+
+    for (name, file) in &answers.files {
+        let imported = import::import(&mut config, file, Some(name))?;
+        println!("✓ Imported {} database to {}", imported.name, imported.path.display());
+    }
+    config.save(&config_file)?;
+    println!("✓ Wrote {}", config_file.display());
+
+    let mut status = ExitStatus::Success;
+    for name in answers.downloads() {
+        eprintln!("{}", crate::tr!("Downloading {} database...", name));
+        if let Err(e) = downloader.download_database(&config, name).await {
+            eprintln!("{}", crate::tr!("✗ Failed to download {}: {}", name, e));
+            status = ExitStatus::DatabaseError;
+        }
+    }
+
+    if let Err(e) = test_query(&config).await {
+        eprintln!("{}", crate::tr!("Query failed: {}", e));
+        status = ExitStatus::DatabaseError;
+    }
+    if status == ExitStatus::Success {
+        println!("\nAll set. Try `echo 1.1.1.1 | nali-rs` or `nali-rs --help`.");
+    }
+    Ok(status)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn answer(input: &str, config: &AppConfig) -> (Result<Answers>, String) {
+        let mut output = Vec::new();
+        let mut prompt = Prompt {
+            input: input.as_bytes(),
+            output: &mut output,
+        };
+        let sizes = HashMap::from([("qqwry".to_string(), 30 << 20)]);
+        let answers = ask(&mut prompt, config, &sizes);
+        (answers, String::from_utf8(output).unwrap())
+    }
+
+    #[test]
+    fn test_suggested_answers() {
+        let config = AppConfig::default();
+        let (answers, output) = answer("\n\n\n\n", &config);
+        assert_eq!(answers.unwrap(), Answers::suggested());
+        assert!(output.contains("qqwry       30.00 MiB  Chunzhen"), "{}", output);
+        assert!(output.contains("geoip2         import  MaxMind"), "{}", output);
+        assert!(output.contains("ip2region           -  ip2region"), "{}", output);
+        assert_eq!(Answers::suggested().downloads(), ["qqwry", "zxipv6wry", "cdn"]);
+    }
+
+    #[test]
+    fn test_manual_database_asks_for_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("GeoLite2-City.mmdb");
+        std::fs::write(&file, "").unwrap();
+        let config = AppConfig::default();
+
+        // Bad answers are asked again; the file is asked for only once
+        let input = format!("\n9\n4\n/no/such/file\n{}\n2\nmaybe\nn\n", file.display());
+        let (answers, output) = answer(&input, &config);
+        let answers = answers.unwrap();
+        assert_eq!(answers.ipv4, "geoip2");
+        assert_eq!(answers.ipv6, "geoip2");
+        assert!(!answers.cdn);
+        assert_eq!(answers.files, BTreeMap::from([("geoip2".to_string(), file)]));
+        assert!(answers.downloads().is_empty());
+        assert!(output.contains("Please enter a number from 1 to 5"), "{}", output);
+        assert!(output.contains("No such file: /no/such/file"), "{}", output);
+        assert!(output.contains("Please answer y or n"), "{}", output);
+
+        // Input running out is an error rather than a half answered setup
+        assert!(answer("\n\n", &config).0.is_err());
+    }
+
+    #[test]
+    fn test_apply() {
+        let mut config = AppConfig::default();
+        let mut answers = Answers {
+            language: "en".to_string(),
+            ipv4: "ip2region".to_string(),
+            ipv6: "ipip".to_string(),
+            cdn: false,
+            files: BTreeMap::new(),
+        };
+        apply(&answers, &mut config);
+        assert_eq!(config.database.language, "en");
+        assert_eq!(config.database.ipv4_database, "ip2region");
+        assert_eq!(config.database.ipv6_database, "ipip");
+        assert_eq!(config.enrichment.disabled, ["cdn"]);
+        assert_eq!(test_address(&config), IpAddr::from([8, 8, 8, 8]));
+
+        answers.cdn = true;
+        apply(&answers, &mut config);
+        assert!(config.enrichment.disabled.is_empty());
+    }
+}
@@ -0,0 +1,407 @@
+//! Input formats for `--input-format`
+//!
+//! Plain text is searched for entities from start to end. Structured formats
+//! limit the search to the parts of a line that carry data, so headers and
+//! timestamps are passed through untouched instead of being misread as
+//! addresses or domains. `--field` narrows the search further to
+//! whitespace-delimited fields.
+//!
+//! JSON lines are searched in the values of the `--keys` given, and written
+//! back with the annotations spliced in next to each value, so all other
+//! fields keep their order and formatting.
+
+use crate::entity::{Entities, Entity, EntityType};
+use clap::{Args, ValueEnum};
+use serde_json::value::RawValue;
+use std::collections::HashMap;
+use std::ops::Range;
+
+/// How input lines are read, as chosen with `--input-format`
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum InputFormat {
+    /// Search the whole line
+    #[default]
+    Text,
+    /// Syslog (RFC 3164 or RFC 5424): search only the message after the header
+    Syslog,
+    /// Nginx or Apache access log: search only the client address, field 1
+    #[value(alias = "apache")]
+    Nginx,
+    /// One JSON object per line: search the values of `--keys` and add sibling fields
+    Json,
+}
+
+/// Options selecting the parts of input lines to annotate
+#[derive(Args, Debug)]
+pub struct InputArgs {
+    /// How to read input lines: text, syslog (only the message after the header), nginx (only the client address) or json
+    #[arg(long = "input-format", value_enum, value_name = "FORMAT", default_value_t = InputFormat::Text)]
+    pub format: InputFormat,
+
+    /// Annotate only these whitespace-delimited fields, counted from 1 (e.g. --field 1,3)
+    #[arg(long = "field", value_name = "N", value_delimiter = ',', value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..))]
+    pub fields: Vec<usize>,
+
+    /// Keys of JSON input to annotate, with `.` between nested keys (e.g. --keys client_ip,upstream.addr)
+    #[arg(long, value_name = "KEYS", value_delimiter = ',', required_if_eq("format", "json"))]
+    pub keys: Vec<String>,
+}
+
+impl InputArgs {
+    /// Byte ranges (start, end) of `line` to search for entities
+    ///
+    /// `--field` selects whitespace-delimited fields of the part the format
+    /// selects; without it, all of that part is searched.
+    pub fn spans(&self, line: &str) -> Vec<(usize, usize)> {
+        let start = match self.format {
+            InputFormat::Text | InputFormat::Nginx => 0,
+            InputFormat::Syslog => syslog_message_start(line),
+            InputFormat::Json => {
+                return self.json_values(line).into_iter().map(|(_, span)| span).collect();
+            }
+        };
+        let fields = match (self.format, self.fields.as_slice()) {
+            (InputFormat::Nginx, []) => &[1],
+            (_, fields) => fields,
+        };
+        if fields.is_empty() {
+            return vec![(start, line.len())];
+        }
+
+        field_spans(&line[start..])
+            .enumerate()
+            .filter(|(i, _)| fields.contains(&(i + 1)))
+            .map(|(_, (field_start, field_end))| (start + field_start, start + field_end))
+            .collect()
+    }
+
+    /// Byte ranges of the values of `--keys` in a JSON object, with their keys
+    ///
+    /// Keys missing from the line are skipped, as is all of a line that is
+    /// not a JSON object.
+    fn json_values<'k>(&'k self, line: &str) -> Vec<(&'k str, (usize, usize))> {
+        let Ok(object) = serde_json::from_str::<&RawValue>(line) else {
+            return Vec::new();
+        };
+        self.keys
+            .iter()
+            .filter_map(|path| {
+                let value = json_value(object, path)?.get();
+                let start = value.as_ptr() as usize - line.as_ptr() as usize;
+                Some((path.as_str(), (start, start + value.len())))
+            })
+            .collect()
+    }
+
+    /// A JSON line with the annotations of its `--keys` added as sibling fields
+    ///
+    /// The location of the address in `client_ip` goes into `client_ip_geo`
+    /// and the CDN provider of a domain into `<key>_cdn`; a value holding
+    /// several addresses gets an array. Addresses without a location get
+    /// `null`.
+    pub fn annotate_json(&self, line: &str, complete: &Entities) -> String {
+        let mut insertions = Vec::new();
+        for (path, (start, end)) in self.json_values(line) {
+            let name = path.rsplit('.').next().unwrap_or(path);
+            let within = |e: &&Entity| e.location.0 >= start && e.location.1 <= end;
+
+            let locations: Vec<_> = complete
+                .entities
+                .iter()
+                .filter(within)
+                .filter(|e| e.is_ip())
+                .map(|e| serde_json::to_value(&e.geo_info).unwrap_or_default())
+                .collect();
+            let providers: Vec<_> = complete
+                .entities
+                .iter()
+                .filter(within)
+                .filter(|e| e.entity_type == EntityType::Domain)
+                .map(|e| serde_json::to_value(&e.cdn_info).unwrap_or_default())
+                .collect();
+
+            let mut fields = String::new();
+            for (suffix, values) in [("geo", locations), ("cdn", providers)] {
+                let value = match <[_; 1]>::try_from(values) {
+                    Ok([value]) => value,
+                    Err(values) if values.is_empty() => continue,
+                    Err(values) => values.into(),
+                };
+                let key = serde_json::Value::from(format!("{}_{}", name, suffix));
+                fields.push_str(&format!(",{}:{}", key, value));
+            }
+            insertions.push((end, fields));
+        }
+
+        // Splice from the back, so earlier offsets stay valid
+        let mut annotated = line.to_string();
+        insertions.sort_by_key(|&(end, _)| std::cmp::Reverse(end));
+        for (end, fields) in insertions {
+            annotated.insert_str(end, &fields);
+        }
+        annotated
+    }
+}
+
+/// Value at a `.`-separated path of keys into a JSON object
+fn json_value<'a>(object: &'a RawValue, path: &str) -> Option<&'a RawValue> {
+    let mut value = object;
+    for key in path.split('.') {
+        let members: HashMap<String, &'a RawValue> = serde_json::from_str(value.get()).ok()?;
+        value = *members.get(key)?;
+    }
+    Some(value)
+}
+
+/// Byte ranges of the whitespace-delimited fields of `s`
+fn field_spans(s: &str) -> impl Iterator<Item = (usize, usize)> + '_ {
+    s.split_ascii_whitespace().map(move |field| {
+        let start = field.as_ptr() as usize - s.as_ptr() as usize;
+        (start, start + field.len())
+    })
+}
+
+/// Months of an RFC 3164 timestamp
+const MONTHS: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+
+/// Offset of the message in a syslog line, or 0 if it has no syslog header
+///
+/// Understands RFC 5424 (`<PRI>1 TIMESTAMP HOST APP PROCID MSGID SD MSG`) and
+/// RFC 3164 (`<PRI>Mmm dd hh:mm:ss HOST TAG[PID]: MSG`), where the priority
+/// is optional as in files written by syslog daemons, and the timestamp may
+/// be RFC 3339 as written by rsyslog's high-precision templates.
+fn syslog_message_start(line: &str) -> usize {
+    let pri_end = priority_end(line).unwrap_or(0);
+    let rest = &line[pri_end..];
+
+    if pri_end > 0
+        && let Some(start) = rfc5424_message_start(rest)
+    {
+        return pri_end + start;
+    }
+    match rfc3164_message_start(rest) {
+        Some(start) => pri_end + start,
+        None => pri_end,
+    }
+}
+
+/// End of a leading `<PRI>` field of one to three digits
+fn priority_end(line: &str) -> Option<usize> {
+    let digits = line.strip_prefix('<')?;
+    let len = digits.find('>')?;
+    ((1..=3).contains(&len) && digits[..len].bytes().all(|b| b.is_ascii_digit())).then_some(len + 2)
+}
+
+/// Split off a field ending at the next space, returning the rest after it
+fn field(s: &str) -> Option<(&str, &str)> {
+    let (field, rest) = s.split_once(' ')?;
+    (!field.is_empty()).then_some((field, rest))
+}
+
+/// Offset of the message after the version of an RFC 5424 header
+fn rfc5424_message_start(s: &str) -> Option<usize> {
+    let (version, mut rest) = field(s)?;
+    if version.len() > 3 || !version.bytes().all(|b| b.is_ascii_digit()) || version.starts_with('0') {
+        return None;
+    }
+    // TIMESTAMP HOSTNAME APP-NAME PROCID MSGID, each `-` when absent
+    for _ in 0..5 {
+        rest = field(rest)?.1;
+    }
+
+    let sd_len = structured_data_len(rest)?;
+    let rest = &rest[sd_len..];
+    if !rest.is_empty() && !rest.starts_with(' ') {
+        return None;
+    }
+    let message = rest.strip_prefix(' ').unwrap_or(rest);
+    Some(s.len() - message.len())
+}
+
+/// Length of the STRUCTURED-DATA field: `-` or `[id param="value"]...`
+fn structured_data_len(s: &str) -> Option<usize> {
+    if s.starts_with('-') {
+        return Some(1);
+    }
+
+    let bytes = s.as_bytes();
+    let mut i = 0;
+    while bytes.get(i) == Some(&b'[') {
+        let mut quoted = false;
+        i += 1;
+        loop {
+            match *bytes.get(i)? {
+                b'\\' if quoted => i += 1,
+                b'"' => quoted = !quoted,
+                b']' if !quoted => break,
+                _ => {}
+            }
+            i += 1;
+        }
+        i += 1;
+    }
+    (i > 0).then_some(i)
+}
+
+/// Offset of the message in an RFC 3164 line without its priority
+fn rfc3164_message_start(s: &str) -> Option<usize> {
+    let rest = timestamp_end(s).map(|end| &s[end..])?;
+    let rest = rest.strip_prefix(' ')?;
+    let (_host, rest) = field(rest)?;
+
+    // TAG[PID]: is optional; without it the message follows the host
+    let message = tag_end(rest).map_or(rest, |end| &rest[end..]);
+    let message = message.strip_prefix(' ').unwrap_or(message);
+    Some(s.len() - message.len())
+}
+
+/// End of a leading `Mmm dd hh:mm:ss` or RFC 3339 timestamp
+fn timestamp_end(s: &str) -> Option<usize> {
+    let b = s.as_bytes();
+    let digits = |range: Range<usize>| b.get(range).is_some_and(|d| d.iter().all(u8::is_ascii_digit));
+
+    // `Oct  1 22:14:15`, with the day padded by a space
+    let day = b.get(4..6)?;
+    if MONTHS.iter().any(|month| s.starts_with(month))
+        && b[3] == b' '
+        && (day[0] == b' ' || day[0].is_ascii_digit())
+        && day[1].is_ascii_digit()
+        && b.get(6) == Some(&b' ')
+        && digits(7..9)
+        && b.get(9) == Some(&b':')
+        && digits(10..12)
+        && b.get(12) == Some(&b':')
+        && digits(13..15)
+    {
+        return Some(15);
+    }
+
+    // `2024-10-11T22:14:15.003+02:00`
+    let (token, _) = s.split_once(' ')?;
+    let t = token.as_bytes();
+    (digits(0..4) && t.get(4) == Some(&b'-') && t.get(7) == Some(&b'-') && t.get(10) == Some(&b'T'))
+        .then_some(token.len())
+}
+
+/// End of a `TAG:` or `TAG[PID]:` at the start of the message part
+fn tag_end(s: &str) -> Option<usize> {
+    let len = s
+        .find(|c: char| !(c.is_ascii_alphanumeric() || "-_./".contains(c)))
+        .filter(|&len| len > 0)?;
+    let rest = &s[len..];
+    let pid_len = match rest.strip_prefix('[') {
+        Some(pid) => pid.find(']')? + 2,
+        None => 0,
+    };
+    rest[pid_len..].starts_with(':').then_some(len + pid_len + 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::entity::parser;
+
+    fn input(format: InputFormat, fields: &[usize]) -> InputArgs {
+        InputArgs {
+            format,
+            fields: fields.to_vec(),
+            keys: Vec::new(),
+        }
+    }
+
+    fn message(line: &str) -> &str {
+        let (start, end) = input(InputFormat::Syslog, &[]).spans(line)[0];
+        &line[start..end]
+    }
+
+    #[test]
+    fn test_rfc3164() {
+        assert_eq!(
+            message("<34>Oct 11 22:14:15 10.0.0.1 sshd[4721]: Failed password from 1.2.3.4"),
+            "Failed password from 1.2.3.4"
+        );
+        assert_eq!(
+            message("Oct  1 02:14:15 gw.example.com kernel: DROP SRC=8.8.8.8"),
+            "DROP SRC=8.8.8.8"
+        );
+        assert_eq!(
+            message("2024-10-11T22:14:15.003+02:00 192.168.1.1 named[88]: client 1.1.1.1#53"),
+            "client 1.1.1.1#53"
+        );
+        // Without a tag, the message follows the host
+        assert_eq!(message("Oct 11 22:14:15 host connection from 1.2.3.4"), "connection from 1.2.3.4");
+    }
+
+    #[test]
+    fn test_rfc5424() {
+        assert_eq!(
+            message("<165>1 2003-10-11T22:14:15.003Z 10.0.0.1 evntslog - ID47 - lookup of 8.8.8.8"),
+            "lookup of 8.8.8.8"
+        );
+        assert_eq!(
+            message(
+                "<165>1 2003-10-11T22:14:15.003Z host app 123 - \
+                 [origin ip=\"192.0.2.1\"][meta note=\"a \\\"]\\\" b\"] from 1.2.3.4"
+            ),
+            "from 1.2.3.4"
+        );
+        assert_eq!(message("<165>1 2003-10-11T22:14:15Z host app - - -"), "");
+    }
+
+    #[test]
+    fn test_not_syslog() {
+        assert_eq!(message("8.8.8.8 - - [11/Oct/2024] GET /"), "8.8.8.8 - - [11/Oct/2024] GET /");
+        assert_eq!(message("<13>bare message from 1.2.3.4"), "bare message from 1.2.3.4");
+        assert_eq!(message("<1234>Oct 11"), "<1234>Oct 11");
+        assert_eq!(input(InputFormat::Text, &[]).spans("Oct 11 22:14:15 host x: y"), vec![(0, 25)]);
+    }
+
+    #[test]
+    fn test_fields() {
+        let line = "1.2.3.4 - - [10/Jan/2024:12:00:01 +0800] \"GET /?u=5.6.7.8 HTTP/1.1\" 200";
+        let fields = |format: InputFormat, fields: &[usize]| -> Vec<&str> {
+            input(format, fields)
+                .spans(line)
+                .into_iter()
+                .map(|(start, end)| &line[start..end])
+                .collect()
+        };
+        assert_eq!(fields(InputFormat::Nginx, &[]), ["1.2.3.4"]);
+        assert_eq!(fields(InputFormat::Text, &[1, 7]), ["1.2.3.4", "/?u=5.6.7.8"]);
+        assert_eq!(fields(InputFormat::Nginx, &[7]), ["/?u=5.6.7.8"]);
+        assert!(fields(InputFormat::Text, &[42]).is_empty());
+
+        // Fields of a syslog line are counted in its message
+        let line = "Jan 10 12:00:01 10.0.0.1 sshd[1]: Accepted  key   for root from 1.2.3.4";
+        let spans = input(InputFormat::Syslog, &[6]).spans(line);
+        assert_eq!(&line[spans[0].0..spans[0].1], "1.2.3.4");
+    }
+
+    #[test]
+    fn test_json_keys() {
+        let mut json = input(InputFormat::Json, &[]);
+        json.keys = vec!["ip".into(), "upstream.addr".into(), "missing".into()];
+
+        let line = r#"{"ip": "1.2.3.4", "upstream": {"addr": ["5.6.7.8:80", "9.9.9.9:80"]}, "n": 1}"#;
+        let values: Vec<_> = json.spans(line).into_iter().map(|(start, end)| &line[start..end]).collect();
+        assert_eq!(values, [r#""1.2.3.4""#, r#"["5.6.7.8:80", "9.9.9.9:80"]"#]);
+        assert!(json.spans("1.2.3.4 not json").is_empty());
+
+        // Addresses without a location get null
+        let mut entities = Entities::new();
+        for (start, end) in json.spans(line) {
+            for mut entity in parser::parse_line(&line[start..end]).entities {
+                entity.location = (entity.location.0 + start, entity.location.1 + start);
+                entities.push(entity);
+            }
+        }
+        assert_eq!(
+            json.annotate_json(line, &entities),
+            r#"{"ip": "1.2.3.4","ip_geo":null, "upstream": {"addr": ["5.6.7.8:80", "9.9.9.9:80"],"addr_geo":[null,null]}, "n": 1}"#
+        );
+        assert_eq!(json.annotate_json("not json", &entities), "not json");
+    }
+}
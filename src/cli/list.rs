@@ -0,0 +1,122 @@
+//! `nali-rs list` subcommand
+//!
+//! Prints every database known to the configuration so users can discover
+//! valid database names without reading the source.
+
+use crate::config::{AppConfig, DatabaseInfo};
+use crate::error::Result;
+use indicatif::HumanBytes;
+
+use super::status::ExitStatus;
+
+/// Column headers of the database table
+const HEADERS: [&str; 8] = [
+    "NAME", "ALIASES", "FORMAT", "TYPES", "LANGUAGES", "INSTALLED", "SIZE", "URLS",
+];
+
+/// Build one table row for a database
+fn database_row(config: &AppConfig, db: &DatabaseInfo) -> [String; 8] {
+    let selected = [
+        &config.database.ipv4_database,
+        &config.database.ipv6_database,
+        &config.database.cdn_database,
+    ]
+    .iter()
+    .any(|name| db.matches(name));
+
+    let file_size = config
+        .get_database_path(&db.name)
+        .ok()
+        .and_then(|path| std::fs::metadata(path).ok())
+        .map(|meta| meta.len());
+
+    let join = |items: &[String]| {
+        if items.is_empty() {
+            "-".to_string()
+        } else {
+            items.join(",")
+        }
+    };
+
+    [
+        if selected {
+            format!("{} *", db.name)
+        } else {
+            db.name.clone()
+        },
+        join(&db.name_alias),
+        db.format.clone(),
+        join(&db.types),
+        join(&db.languages),
+        if file_size.is_some() { "yes" } else { "no" }.to_string(),
+        file_size.map_or("-".to_string(), |size| HumanBytes(size).to_string()),
+        db.download_urls.len().to_string(),
+    ]
+}
+
+/// Render rows as a left-aligned text table
+fn render_table(rows: &[[String; 8]]) -> String {
+    let mut widths = HEADERS.map(|h| h.chars().count());
+    for row in rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+
+    let render_row = |cells: Vec<&str>| {
+        let line: Vec<String> = cells
+            .iter()
+            .zip(widths)
+            .map(|(cell, width)| format!("{:<width$}", cell, width = width))
+            .collect();
+        line.join("  ").trim_end().to_string()
+    };
+
+    let mut out = render_row(HEADERS.to_vec());
+    out.push('\n');
+    for row in rows {
+        out.push_str(&render_row(row.iter().map(String::as_str).collect()));
+        out.push('\n');
+    }
+    out
+}
+
+/// Run the list subcommand
+pub fn run(config: &AppConfig) -> Result<ExitStatus> {
+    let rows: Vec<[String; 8]> = config
+        .database
+        .databases
+        .iter()
+        .map(|db| database_row(config, db))
+        .collect();
+
+    print!("{}", render_table(&rows));
+    println!("\n* currently selected");
+
+    Ok(ExitStatus::Success)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_table_aligns_columns() {
+        let config = AppConfig::default();
+        let rows: Vec<[String; 8]> = config
+            .database
+            .databases
+            .iter()
+            .map(|db| database_row(&config, db))
+            .collect();
+
+        let table = render_table(&rows);
+        let lines: Vec<&str> = table.lines().collect();
+        assert_eq!(lines.len(), config.database.databases.len() + 1);
+        assert!(lines[0].starts_with("NAME"));
+        assert!(lines[1].starts_with("qqwry *"));
+
+        let aliases_col = lines[0].find("ALIASES").unwrap();
+        assert_eq!(&lines[1][aliases_col..aliases_col + 8], "chunzhen");
+    }
+}
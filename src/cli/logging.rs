@@ -0,0 +1,215 @@
+//! Diagnostic logging setup
+//!
+//! Logs go to stderr unless `--log-file` names a file to append to. The
+//! level comes from `--log-level`, or `RUST_LOG` when that isn't given, and
+//! `--log-format json` writes one JSON object per line for log collectors.
+//! `--log-format journald` prefixes each line with its syslog priority, as
+//! `<3>` to `<7>`, which the systemd journal turns into the entry's priority
+//! and strips; the journal adds its own timestamps.
+//!
+//! The crate logs through `tracing`, written out by a `tracing-subscriber`
+//! fmt layer. Events are written with the spans they happen in, as
+//! `download{url=...}: Saving to ...`, and a span logs its duration when it
+//! closes, so a slow mirror or a database that takes seconds to load shows
+//! up with its time. Records of dependencies logging through the `log`
+//! crate are written the same way.
+
+use crate::error::{NaliError, Result};
+use clap::ValueEnum;
+use std::fmt;
+use std::fs::OpenOptions;
+use std::io;
+use std::path::Path;
+use std::sync::Mutex;
+use tracing::level_filters::LevelFilter;
+use tracing::{Event, Level, Subscriber};
+use tracing_subscriber::EnvFilter;
+use tracing_subscriber::fmt::format::{self, FmtSpan, Format, Writer};
+use tracing_subscriber::fmt::writer::BoxMakeWriter;
+use tracing_subscriber::fmt::{FmtContext, FormatEvent, FormatFields};
+use tracing_subscriber::layer::{Layer, Layered, SubscriberExt};
+use tracing_subscriber::registry::{LookupSpan, Registry};
+use tracing_subscriber::util::SubscriberInitExt;
+
+/// Log line format, as chosen with `--log-format`
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum LogFormat {
+    /// `timestamp LEVEL target: message`
+    #[default]
+    Text,
+    /// One JSON object per line with timestamp, level, target and message
+    Json,
+    /// `<priority>target: message`, for the systemd journal
+    Journald,
+}
+
+/// Syslog priority of a log level
+fn priority(level: Level) -> u8 {
+    match level {
+        Level::ERROR => 3,
+        Level::WARN => 4,
+        Level::INFO => 6,
+        Level::DEBUG | Level::TRACE => 7,
+    }
+}
+
+/// `<priority>` followed by the line without timestamp and level
+struct Journald;
+
+impl<S, N> FormatEvent<S, N> for Journald
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    N: for<'a> FormatFields<'a> + 'static,
+{
+    fn format_event(
+        &self,
+        ctx: &FmtContext<'_, S, N>,
+        mut writer: Writer<'_>,
+        event: &Event<'_>,
+    ) -> fmt::Result {
+        write!(writer, "<{}>", priority(*event.metadata().level()))?;
+        Format::default()
+            .without_time()
+            .with_level(false)
+            .format_event(ctx, writer, event)
+    }
+}
+
+/// Events that pass the level filter
+type Filtered = Layered<EnvFilter, Registry>;
+
+/// Filter and fmt layer of the chosen format
+type Logger = Layered<Box<dyn Layer<Filtered> + Send + Sync>, Filtered>;
+
+/// Build the logger for the given options without installing it
+fn logger(level: Option<LevelFilter>, file: Option<&Path>, format: LogFormat) -> Result<Logger> {
+    // Without a level only errors are logged
+    let filter =
+        EnvFilter::builder().with_default_directive(level.unwrap_or(LevelFilter::ERROR).into());
+    let filter = match level {
+        Some(_) => filter.parse_lossy(""),
+        None => filter.from_env_lossy(),
+    };
+
+    let writer = match file {
+        Some(path) => BoxMakeWriter::new(Mutex::new(
+            OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)
+                .map_err(|e| {
+                    NaliError::config(format!("Cannot open log file {}: {}", path.display(), e))
+                })?,
+        )),
+        None => BoxMakeWriter::new(io::stderr),
+    };
+
+    let layer = tracing_subscriber::fmt::layer()
+        .with_ansi(false)
+        .with_span_events(FmtSpan::CLOSE)
+        .with_writer(writer);
+    let layer = match format {
+        LogFormat::Text => layer.boxed(),
+        LogFormat::Json => layer
+            .event_format(format::json().flatten_event(true).with_span_list(true))
+            .fmt_fields(format::JsonFields::new())
+            .boxed(),
+        LogFormat::Journald => layer.event_format(Journald).boxed(),
+    };
+
+    Ok(Registry::default().with(filter).with(layer))
+}
+
+/// Install the global logger
+///
+/// A log file that can't be opened is reported and logging falls back to
+/// stderr, so a bad path never stops a lookup.
+pub fn init(level: Option<LevelFilter>, file: Option<&Path>, format: LogFormat) {
+    let logger = logger(level, file, format).unwrap_or_else(|e| {
+        eprintln!("Warning: {}, logging to stderr", e);
+        logger(level, None, format).expect("stderr logging needs no setup")
+    });
+    // Only fails if a subscriber is installed already, which then stays
+    let _ = logger.try_init();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Run `log` with a logger writing to a file, and return what it wrote
+    fn logged(level: LevelFilter, format: LogFormat, log: impl FnOnce()) -> String {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("nali.log");
+        let logger = logger(Some(level), Some(&path), format).unwrap();
+        tracing::subscriber::with_default(logger, log);
+        std::fs::read_to_string(&path).unwrap()
+    }
+
+    #[test]
+    fn test_json_lines_to_file() {
+        let content = logged(LevelFilter::INFO, LogFormat::Json, || {
+            tracing::info!(target: "nali_rs::database", "load qqwry: 12ms");
+            tracing::debug!(target: "nali_rs::database", "not logged");
+        });
+
+        let lines: Vec<serde_json::Value> = content
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(lines.len(), 1);
+        assert_eq!(lines[0]["level"], "INFO");
+        assert_eq!(lines[0]["target"], "nali_rs::database");
+        assert_eq!(lines[0]["message"], "load qqwry: 12ms");
+    }
+
+    #[test]
+    fn test_journald_priorities() {
+        let content = logged(LevelFilter::INFO, LogFormat::Journald, || {
+            tracing::info!(target: "nali_rs::cli::daemon", "Updated qqwry");
+            tracing::error!(target: "nali_rs::cli::daemon", "Failed to update cdn");
+        });
+
+        assert_eq!(
+            content,
+            "<6>nali_rs::cli::daemon: Updated qqwry\n<3>nali_rs::cli::daemon: Failed to update cdn\n"
+        );
+    }
+
+    #[test]
+    fn test_spans_give_context_and_duration() {
+        let content = logged(LevelFilter::INFO, LogFormat::Journald, || {
+            let _load =
+                tracing::info_span!(target: "nali_rs::database", "load", db = "qqwry").entered();
+            tracing::warn!(target: "nali_rs::database", "slow disk");
+            // Disabled spans neither log nor show up in the context
+            let _lookup = tracing::trace_span!(target: "nali_rs::database", "lookup").entered();
+            tracing::info!(target: "nali_rs::database", "loaded");
+        });
+
+        let lines: Vec<&str> = content.lines().collect();
+        assert_eq!(
+            lines[0],
+            "<4>load{db=\"qqwry\"}: nali_rs::database: slow disk"
+        );
+        assert_eq!(lines[1], "<6>load{db=\"qqwry\"}: nali_rs::database: loaded");
+        assert!(lines[2].starts_with("<6>load{db=\"qqwry\"}: nali_rs::database: close time.busy="));
+        assert_eq!(lines.len(), 3);
+
+        let json = logged(LevelFilter::INFO, LogFormat::Json, || {
+            let _span = tracing::info_span!("download", url = "https://example.com/a").entered();
+            tracing::info!(bytes = 10, "done");
+        });
+        let line: serde_json::Value = serde_json::from_str(json.lines().next().unwrap()).unwrap();
+        assert_eq!(line["bytes"], 10);
+        assert_eq!(line["spans"][0]["name"], "download");
+        assert_eq!(line["spans"][0]["url"], "https://example.com/a");
+    }
+
+    #[test]
+    fn test_unwritable_log_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("missing").join("nali.log");
+        assert!(logger(None, Some(&path), LogFormat::Text).is_err());
+    }
+}
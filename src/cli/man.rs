@@ -0,0 +1,142 @@
+//! Hidden `nali-rs man` subcommand
+//!
+//! Renders a roff man page from the clap definitions, so packagers can ship
+//! documentation that never drifts from the code:
+//!
+//! ```text
+//! nali-rs man > nali-rs.1
+//! ```
+
+use clap::{Command, CommandFactory};
+use clap_mangen::Man;
+use roff::{Roff, bold, roman};
+use std::io::{self, Write};
+
+use super::Cli;
+use super::status::ExitStatus;
+use crate::error::Result;
+
+/// Environment variables documented in the ENVIRONMENT section
+const ENVIRONMENT: &[(&str, &str)] = &[
+    (
+        "NALI_HOME",
+        "Directory for both the configuration and database files.",
+    ),
+    ("NALI_CONFIG_HOME", "Directory holding config.yaml."),
+    ("NALI_DB_HOME", "Directory holding database files."),
+    (
+        "NALI_DATA_DIR",
+        "Overrides database.data_dir from the configuration file.",
+    ),
+    ("NALI_DB_IP4", "IPv4 database name."),
+    ("NALI_DB_IP6", "IPv6 database name."),
+    ("NALI_DB_CDN", "CDN database name."),
+    (
+        "NALI_DB_RO",
+        "Read-only mode when true: nothing is created, downloaded or written.",
+    ),
+    (
+        "NALI_LANG",
+        "Output language; overrides database.language and the locale.",
+    ),
+    ("LC_ALL", "Locale used for the output language without NALI_LANG."),
+    ("LC_MESSAGES", "Same as LC_ALL, when that is not set."),
+    ("LANG", "Same as LC_ALL, when neither that nor LC_MESSAGES is set."),
+    (
+        "NALI_CACHE_SIZE",
+        "Number of query results kept in memory; 0 disables the cache.",
+    ),
+    ("NALI_JSON", "Output JSON when true."),
+    (
+        "NALI_NO_COLOR",
+        "Disable colors when set to a non-empty value.",
+    ),
+    ("NO_COLOR", "Same as NALI_NO_COLOR."),
+    (
+        "RUST_LOG",
+        "Diagnostic log filter when --log-level is not given.",
+    ),
+    ("VISUAL", "Editor for config edit."),
+    ("EDITOR", "Editor for config edit, when VISUAL is not set."),
+];
+
+/// Render the man page of `cmd`, with the ENVIRONMENT section clap leaves out
+fn render(cmd: Command, out: &mut dyn Write) -> io::Result<()> {
+    Man::new(cmd).render(out)?;
+
+    let mut roff = Roff::new();
+    roff.control("SH", ["ENVIRONMENT"]);
+    for (var, description) in ENVIRONMENT {
+        roff.control("TP", []).text([bold(*var)]).text([roman(*description)]);
+    }
+    roff.to_writer(out)
+}
+
+/// Print the man page to standard output
+pub fn run() -> Result<ExitStatus> {
+    render(Cli::command(), &mut io::stdout().lock())?;
+    Ok(ExitStatus::Success)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_from_cli() {
+        let mut page = Vec::new();
+        render(Cli::command(), &mut page).unwrap();
+        let page = String::from_utf8(page).unwrap();
+
+        assert!(page.contains(".TH nali-rs 1"), "{}", page);
+        assert!(page.contains("\\fB\\-j\\fR, \\fB\\-\\-json\\fR"), "{}", page);
+        assert!(page.contains("nali\\-rs\\-db(1)"), "{}", page);
+        assert!(!page.contains("nali\\-rs\\-man(1)"), "{}", page);
+        assert!(page.contains(".SH ENVIRONMENT\n.TP\n\\fBNALI_HOME\\fR\n"), "{}", page);
+    }
+
+    /// Sources reading the environment at run time
+    const READERS: [&str; 5] = [
+        include_str!("config.rs"),
+        include_str!("../config/mod.rs"),
+        include_str!("../i18n.rs"),
+        include_str!("../main.rs"),
+        include_str!("../utils/path.rs"),
+    ];
+
+    /// Variables read outside the `NALI_` namespace
+    const STANDARD: [&str; 7] = [
+        "LC_ALL",
+        "LC_MESSAGES",
+        "LANG",
+        "NO_COLOR",
+        "RUST_LOG",
+        "VISUAL",
+        "EDITOR",
+    ];
+
+    #[test]
+    fn test_environment_matches_code() {
+        let mut read = std::collections::BTreeSet::new();
+        for source in READERS {
+            // Only the code, not the variables its tests make up
+            let code = source.split("#[cfg(test)]\nmod tests").next().unwrap();
+            for (start, _) in code.match_indices('"') {
+                let rest = &code[start + 1..];
+                let len = rest
+                    .find(|c: char| !(c.is_ascii_uppercase() || c.is_ascii_digit() || c == '_'))
+                    .unwrap_or(rest.len());
+                let name = &rest[..len];
+                if rest[len..].starts_with('"')
+                    && (name.starts_with("NALI_") || STANDARD.contains(&name))
+                {
+                    read.insert(name);
+                }
+            }
+        }
+
+        let documented: std::collections::BTreeSet<_> =
+            ENVIRONMENT.iter().map(|(var, _)| *var).collect();
+        assert_eq!(documented, read);
+    }
+}
@@ -0,0 +1,142 @@
+//! Prometheus `/metrics` endpoint
+//!
+//! With `--metrics-listen ADDR`, a long-running nali-rs (e.g. annotating
+//! `tail -f` output) serves its lookup metrics over plain HTTP so it can be
+//! scraped like any other service. Only `GET /metrics` is answered; the
+//! handler is deliberately minimal and closes every connection.
+
+use crate::database::metrics::Metrics;
+use crate::error::{NaliError, Result};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+/// Largest request head read before giving up on a client
+const MAX_REQUEST: usize = 8192;
+/// Time a client gets to send its request
+const READ_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Bind the metrics listener
+pub async fn bind(addr: &str) -> Result<TcpListener> {
+    TcpListener::bind(addr)
+        .await
+        .map_err(|e| NaliError::config(format!("Cannot listen for metrics on {}: {}", addr, e)))
+}
+
+/// Serve metrics on `listener` in the background for the rest of the run
+pub fn spawn(listener: TcpListener, metrics: Arc<Metrics>) {
+    if let Ok(addr) = listener.local_addr() {
+        tracing::info!("Serving metrics at http://{}/metrics", addr);
+    }
+
+    tokio::spawn(async move {
+        loop {
+            match listener.accept().await {
+                Ok((stream, _)) => {
+                    let metrics = Arc::clone(&metrics);
+                    tokio::spawn(async move {
+                        if let Err(e) = handle(stream, &metrics).await {
+                            tracing::debug!("Metrics request failed: {}", e);
+                        }
+                    });
+                }
+                Err(e) => tracing::warn!("Failed to accept metrics connection: {}", e),
+            }
+        }
+    });
+}
+
+/// Read one request head and answer it
+async fn handle(mut stream: TcpStream, metrics: &Metrics) -> std::io::Result<()> {
+    let mut request = Vec::with_capacity(512);
+    let mut buf = [0u8; 1024];
+    let head = tokio::time::timeout(READ_TIMEOUT, async {
+        while !request.windows(4).any(|w| w == b"\r\n\r\n") && request.len() < MAX_REQUEST {
+            let n = stream.read(&mut buf).await?;
+            if n == 0 {
+                break;
+            }
+            request.extend_from_slice(&buf[..n]);
+        }
+        Ok::<_, std::io::Error>(())
+    })
+    .await;
+    if !matches!(head, Ok(Ok(()))) {
+        return Ok(());
+    }
+
+    let response = respond(&request, metrics);
+    stream.write_all(response.as_bytes()).await?;
+    stream.shutdown().await
+}
+
+/// Build the full HTTP response for a request head
+fn respond(request: &[u8], metrics: &Metrics) -> String {
+    let line = request.split(|&b| b == b'\n').next().unwrap_or_default();
+    let mut parts = std::str::from_utf8(line)
+        .unwrap_or_default()
+        .split_whitespace();
+    let (method, target) = (parts.next(), parts.next());
+    let path = target.map(|t| t.split('?').next().unwrap_or(t));
+
+    let (status, content_type, body) = match (method, path) {
+        (Some("GET"), Some("/metrics")) => (
+            "200 OK",
+            "text/plain; version=0.0.4; charset=utf-8",
+            metrics.render(),
+        ),
+        (Some("GET"), _) => ("404 Not Found", "text/plain", "Not found\n".to_string()),
+        _ => (
+            "405 Method Not Allowed",
+            "text/plain",
+            "Method not allowed\n".to_string(),
+        ),
+    };
+
+    format!(
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        content_type,
+        body.len(),
+        body
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::metrics::Family;
+
+    #[test]
+    fn test_respond() {
+        let metrics = Metrics::default();
+        metrics.record_lookup(Family::Ipv4, "qqwry", Duration::from_micros(1));
+
+        let ok = respond(b"GET /metrics HTTP/1.1\r\nHost: x\r\n\r\n", &metrics);
+        assert!(ok.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(ok.contains("nali_lookups_total{family=\"ipv4\",database=\"qqwry\"} 1"));
+
+        let missing = respond(b"GET / HTTP/1.1\r\n\r\n", &metrics);
+        assert!(missing.starts_with("HTTP/1.1 404"));
+        let post = respond(b"POST /metrics HTTP/1.1\r\n\r\n", &metrics);
+        assert!(post.starts_with("HTTP/1.1 405"));
+    }
+
+    #[tokio::test]
+    async fn test_serves_over_tcp() {
+        let listener = bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        spawn(listener, Arc::new(Metrics::default()));
+
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        stream
+            .write_all(b"GET /metrics HTTP/1.1\r\nHost: localhost\r\n\r\n")
+            .await
+            .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        assert!(response.starts_with("HTTP/1.1 200 OK"));
+        assert!(response.contains("nali_cache_hits_total 0"));
+    }
+}
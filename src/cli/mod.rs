@@ -2,14 +2,57 @@
 //!
 //! This module handles command line argument parsing and query logic.
 
-use crate::config::AppConfig;
+use crate::config::{AnnotationPosition, AppConfig, AutoDownload, CompatMode, LoadMode, MapProvider};
 use crate::database::DatabaseManager;
-use crate::download::Downloader;
-use crate::entity::{EntityType, formatter, parser};
-use crate::error::Result;
-use clap::Parser;
-use std::io::{self, BufRead, Write};
+use crate::download::{self, Downloader};
+use crate::enrichment::{Deduplicator, Pipeline};
+use crate::entity::parser::ParseOptions;
+use crate::entity::suffix::{self, PublicSuffixList};
+use crate::entity::{Entities, Entity, EntityType, formatter, parser};
+use crate::error::{NaliError, Result};
+use crate::i18n::{self, Language};
+use crate::utils::encoding::decode_input;
+use clap::builder::{PossibleValuesParser, TypedValueParser};
+use clap::{Parser, Subcommand};
+use indicatif::{ProgressBar, ProgressStyle};
+use tracing::level_filters::LevelFilter;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::PathBuf;
 use std::net::IpAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+pub mod asn;
+pub mod batch;
+pub mod build;
+pub mod cache;
+pub mod config;
+pub mod daemon;
+pub mod db;
+pub mod diff;
+pub mod dig;
+pub mod export;
+pub mod import;
+pub mod init;
+pub mod input;
+pub mod list;
+pub mod logging;
+pub mod man;
+pub mod metrics;
+pub mod output;
+pub mod report;
+pub mod status;
+pub mod table;
+pub mod trace;
+
+use batch::{RecordWriter, ResultFormat};
+use input::{InputArgs, InputFormat};
+use logging::LogFormat;
+use output::{ColorChoice, OutputWriter, QueryFormat};
+use report::TopTalkers;
+use status::{ExitStatus, RunSummary};
+use table::QueryTable;
 
 #[derive(Parser, Debug)]
 #[command(name = "nali-rs")]
@@ -23,98 +66,744 @@ use std::net::IpAddr;
     $ nali-rs 1.2.3.4\n  \
     $ echo \"Server IP: 8.8.8.8\" | nali-rs\n  \
     $ dig google.com | nali-rs\n  \
+    $ nali-rs -f access.log -o result.csv\n  \
     $ nali-rs --json 1.2.3.4\n  \
+    $ nali-rs --db4 geoip2 --db6 geoip2 8.8.8.8 2001:4860::8888\n  \
     $ nali-rs update\n  \
-    $ nali-rs update qqwry")]
+    $ nali-rs update qqwry\n  \
+    $ nali-rs build ranges.csv -o corp.nali\n  \
+    $ nali-rs list\n  \
+    $ nali-rs export qqwry --format csv -o qqwry.csv\n  \
+    $ nali-rs db info qqwry\n  \
+    $ nali-rs import /media/usb/qqwry.dat --as qqwry\n  \
+    $ nali-rs config set database.ipv4 geoip2\n  \
+    $ nali-rs diff old.xdb new.xdb\n  \
+    $ nali-rs dig example.com @8.8.8.8\n  \
+    $ nali-rs trace example.com\n  \
+    $ nali-rs report -f access.log --by country --top 20")]
 pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
     /// List of IP addresses or domains (if not provided, read from standard input)
     #[arg(value_name = "QUERY")]
     pub queries: Vec<String>,
 
+    /// Read queries line by line from a file (repeatable)
+    #[arg(short, long = "file", value_name = "FILE")]
+    pub files: Vec<PathBuf>,
+
+    #[command(flatten)]
+    pub input: InputArgs,
+
+    /// Write results to a file; `.json`, `.jsonl` and `.csv` select a structured format
+    #[arg(short, long, value_name = "FILE")]
+    pub output: Option<PathBuf>,
+
+    /// Look up each distinct IP or domain once per chunk of lines (buffers output; for large logs)
+    #[arg(long)]
+    pub dedupe: bool,
+
+    /// Every SECS seconds, print the most frequent countries and ISPs seen to stderr
+    #[arg(long, value_name = "SECS")]
+    pub summary_interval: Option<u64>,
+
     /// Output in JSON format
     #[arg(short, long)]
     pub json: bool,
 
-    /// Use GBK encoding format for output
+    /// Layout of the results of queries given as arguments: text (default), json or table
+    #[arg(long = "format", value_enum, value_name = "FORMAT", default_value_t = QueryFormat::Text)]
+    pub query_format: QueryFormat,
+
+    /// Decode standard input as GBK
     #[arg(short, long)]
     pub gbk: bool,
 
+    /// Re-encode output as GBK
+    #[arg(long)]
+    pub gbk_output: bool,
+
+    /// When to color output: auto (terminal only, honoring NO_COLOR), always or never
+    #[arg(long, value_enum, value_name = "WHEN", default_value_t = ColorChoice::Auto)]
+    pub color: ColorChoice,
+
+    /// Flush output after every line, even when writing to a pipe
+    #[arg(long)]
+    pub line_buffered: bool,
+
+    /// Show the country's flag emoji before each location
+    #[arg(long)]
+    pub flag: bool,
+
+    /// Show latitude and longitude of each location, where known
+    #[arg(long)]
+    pub show_coords: bool,
+
+    /// Add a map link to each location with coordinates: osm (default) or google
+    #[arg(
+        long,
+        value_name = "SERVICE",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "osm",
+        value_parser = PossibleValuesParser::new(["osm", "google"])
+            .map(|service| service.parse::<MapProvider>().expect("listed services parse")),
+    )]
+    pub map_link: Option<MapProvider>,
+
+    /// Format text output byte for byte like another tool: nali (the Go original)
+    #[arg(
+        long,
+        value_name = "TOOL",
+        value_parser = PossibleValuesParser::new(["nali"])
+            .map(|tool| tool.parse::<CompatMode>().expect("listed tools parse")),
+    )]
+    pub compat: Option<CompatMode>,
+
+    /// Where to put annotations in text output: inline (default) or eol, in a column at the end of the line
+    #[arg(
+        long,
+        value_name = "POSITION",
+        value_parser = PossibleValuesParser::new(["inline", "eol"])
+            .map(|position| position.parse::<AnnotationPosition>().expect("listed positions parse")),
+    )]
+    pub annotation: Option<AnnotationPosition>,
+
+    /// Print only the location or CDN provider of each query or line, for scripts
+    #[arg(long, conflicts_with_all = ["json", "compat", "query_format"])]
+    pub short: bool,
+
     /// Show detailed information
     #[arg(short, long)]
     pub verbose: bool,
 
+    /// Print how each query was answered to stderr: database, matched block or CDN rule, cache hit
+    #[arg(long)]
+    pub explain: bool,
+
+    /// Print lookups and time spent per database to stderr at the end (also with --verbose)
+    #[arg(long)]
+    pub stats: bool,
+
+    /// Fail on the first unresolved query or database error
+    #[arg(long)]
+    pub strict: bool,
+
+    /// Print nothing but results: no download progress, status messages or warnings
+    #[arg(long, global = true)]
+    pub quiet: bool,
+
+    /// Never download missing databases on first use; fail with a hint instead
+    #[arg(long, global = true)]
+    pub no_auto_download: bool,
+
+    /// Never write to the data or config directory, e.g. from a read-only
+    /// container image (also NALI_DB_RO=1); caches go to the user's runtime
+    /// or cache directory
+    #[arg(long, global = true)]
+    pub read_only: bool,
+
+    /// Resolve relative database paths and keep downloads under DIR instead
+    /// of the data directory (overrides global.work_dir)
+    #[arg(long, global = true, value_name = "DIR")]
+    pub work_dir: Option<String>,
+
+    /// Diagnostic log level (default: RUST_LOG, else errors only)
+    #[arg(
+        long,
+        global = true,
+        value_name = "LEVEL",
+        value_parser = PossibleValuesParser::new(["off", "error", "warn", "info", "debug", "trace"])
+            .map(|level| level.parse::<LevelFilter>().expect("listed levels parse")),
+    )]
+    pub log_level: Option<LevelFilter>,
+
+    /// Append diagnostic logs to a file instead of stderr
+    #[arg(long, global = true, value_name = "PATH")]
+    pub log_file: Option<PathBuf>,
+
+    /// Diagnostic log format
+    #[arg(long, global = true, value_enum, value_name = "FORMAT", default_value_t = LogFormat::Text)]
+    pub log_format: LogFormat,
+
+    /// Append RDAP/whois network name and organization to IP annotations (needs network)
+    #[arg(long)]
+    pub whois: bool,
+
+    /// Append the PTR name of IPs, and the hosting or CDN provider it points to (needs network)
+    #[arg(long)]
+    pub rdns: bool,
+
+    /// Annotate only the first address of comma-separated lists, the client in X-Forwarded-For
+    #[arg(long)]
+    pub first_ip_only: bool,
+
+    /// Annotate domains even without a known public suffix
+    #[arg(long)]
+    pub no_tld_check: bool,
+
+    /// Serve Prometheus metrics at http://ADDR/metrics while running (e.g. 127.0.0.1:9101)
+    #[arg(long, value_name = "ADDR")]
+    pub metrics_listen: Option<String>,
+
+    /// IPv4 database to use for this run (overrides config)
+    #[arg(long, value_name = "NAME")]
+    pub db4: Option<String>,
+
+    /// IPv6 database to use for this run (overrides config)
+    #[arg(long, value_name = "NAME")]
+    pub db6: Option<String>,
+
+    /// CDN database to use for this run (overrides config)
+    #[arg(long, value_name = "NAME")]
+    pub cdn_db: Option<String>,
+
+    /// Database refining Chinese answers for this run, e.g. geocn (overrides config)
+    #[arg(long, value_name = "NAME")]
+    pub china_db: Option<String>,
+
+    /// Map database files (mmap, default) or read them whole into memory (memory), e.g. on NFS
+    #[arg(
+        long,
+        value_name = "MODE",
+        value_parser = PossibleValuesParser::new(["mmap", "memory"])
+            .map(|mode| mode.parse::<LoadMode>().expect("listed modes parse")),
+    )]
+    pub load_mode: Option<LoadMode>,
+
+    /// Load databases before the first query and exit if one cannot be loaded
+    /// (database.preload, or else the IPv4, IPv6 and CDN databases)
+    #[arg(long)]
+    pub preload: bool,
+
     /// Update database (update [database_name])
     #[arg(long)]
     pub update: bool,
+
+    /// With --update, keep running and update every download.update_interval seconds
+    #[arg(long, requires = "update")]
+    pub daemon: bool,
+}
+
+/// Subcommands
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Build a nali-native database from a CSV of IP ranges
+    Build(build::BuildArgs),
+    /// List all known databases and their installation status
+    List,
+    /// Export every range of a database to CSV or JSON Lines
+    Export(export::ExportArgs),
+    /// Inspect or roll back installed database files (e.g. `db info qqwry`)
+    Db(db::DbArgs),
+    /// Choose and install databases interactively, then write the configuration
+    Init(init::InitArgs),
+    /// Install a database file copied in by hand (e.g. `import ./qqwry.dat --as qqwry`)
+    Import(import::ImportArgs),
+    /// Show or change the configuration (e.g. `config set database.ipv4 geoip2`)
+    Config(config::ConfigArgs),
+    /// Report ranges added, removed or changed between two database files
+    Diff(diff::DiffArgs),
+    /// Show the prefixes of an AS or the AS of an IP (e.g. `asn AS13335`, `asn 1.1.1.1`)
+    Asn(asn::AsnArgs),
+    /// Query DNS and annotate the answers (e.g. `dig example.com @8.8.8.8`)
+    Dig(dig::DigArgs),
+    /// Trace the route to a host and annotate every hop
+    Trace(trace::TraceArgs),
+    /// Summarize the IPs in a log by country, ISP or block (e.g. `report -f access.log`)
+    Report(report::ReportArgs),
+    /// Manage the query results kept on disk (`cache stats`, `cache clear`, `cache warm`)
+    Cache(cache::CacheArgs),
+    /// Print a roff man page generated from these definitions
+    #[command(hide = true)]
+    Man,
+}
+
+/// Lines buffered per lookup batch with `--dedupe`
+const DEDUPE_CHUNK_LINES: usize = 4096;
+
+/// Per-run state shared by the query helpers
+struct Session<'a> {
+    db_manager: &'a DatabaseManager,
+    pipeline: Pipeline,
+    config: &'a AppConfig,
+    out: OutputWriter,
+    /// Structured result file, when `--output` names one
+    records: Option<RecordWriter>,
+    /// Number of input lines processed so far
+    line_no: usize,
+    /// Lines waiting for a batched lookup with `--dedupe`
+    pending: Vec<String>,
+    summary: RunSummary,
+    /// Tally for `--summary-interval`
+    talkers: Option<TopTalkers>,
+    /// Broker that annotated entities are published to
+    #[cfg(feature = "sink")]
+    sink: Option<crate::sink::RecordSink>,
+}
+
+impl Session<'_> {
+    /// Record a lookup that returned nothing, failing fast in strict mode
+    fn unresolved(&mut self, query: &str) -> Result<()> {
+        self.summary.record_unresolved();
+        if self.config.global.strict {
+            return Err(NaliError::Unresolved(query.to_string()));
+        }
+        Ok(())
+    }
+
+    /// Record and report a failed lookup, failing fast in strict mode
+    ///
+    /// This is the one place lookup errors are reported, on stderr unless
+    /// `--quiet`. A database still downloading in the background is not a
+    /// failure; the entity just passes through un-annotated.
+    fn failed(&mut self, err: NaliError) -> Result<()> {
+        if let NaliError::DatabasePending(ref name) = err {
+            tracing::debug!("Not annotated: {}", name);
+            return Ok(());
+        }
+        self.summary.record_error();
+        if self.config.global.strict {
+            return Err(err);
+        }
+        if !self.config.global.quiet {
+            eprintln!("{}", crate::tr!("Query failed: {}", err));
+        }
+        Ok(())
+    }
 }
 
 impl Cli {
-    pub async fn run(&self, mut config: AppConfig) -> Result<()> {
+    /// Run the CLI and return the exit status for the process
+    pub async fn run(&self, mut config: AppConfig) -> Result<ExitStatus> {
+        if self.quiet {
+            config.global.quiet = true;
+        }
+        if self.no_auto_download {
+            config.download.auto = AutoDownload::Off;
+        }
+        if self.read_only {
+            config.global.read_only = true;
+        }
+        if let Some(ref work_dir) = self.work_dir {
+            config.global.work_dir = Some(work_dir.clone());
+        }
+        i18n::set_language(Language::detect(&config.database.language));
+        if let Some(action) = self.writing_action() {
+            config.ensure_writable(action)?;
+        }
+
+        match &self.command {
+            Some(Command::Build(args)) => return build::run(args),
+            Some(Command::List) => return list::run(&config),
+            Some(Command::Export(args)) => return export::run(args, &config),
+            Some(Command::Db(args)) => return db::run(args, &config),
+            Some(Command::Import(args)) => return import::run(args, &config),
+            Some(Command::Init(args)) => return init::run(args).await,
+            Some(Command::Config(args)) => return config::run(args, &config),
+            Some(Command::Diff(args)) => return diff::run(args),
+            Some(Command::Asn(args)) => return asn::run(args, &config),
+            Some(Command::Dig(args)) => return dig::run(args, &config).await,
+            Some(Command::Trace(args)) => return trace::run(args, &config).await,
+            Some(Command::Report(args)) => return report::run(args, &config).await,
+            Some(Command::Cache(args)) => return cache::run(args, &config).await,
+            Some(Command::Man) => return man::run(),
+            None => {}
+        }
+
         // Handle update command first
+        if self.daemon {
+            return daemon::run(&config, &self.queries).await;
+        }
         if self.update {
             return self.handle_update(&config).await;
         }
 
         // Apply CLI options to config
-        if self.json {
+        if self.json || self.query_format == QueryFormat::Json {
             config.output.json = true;
         }
         if self.gbk {
             config.output.use_gbk = true;
         }
+        if self.gbk_output {
+            config.output.gbk_output = true;
+        }
+        if self.line_buffered {
+            config.output.line_buffered = true;
+        }
+        if self.flag {
+            config.output.show_flag = true;
+        }
+        if self.show_coords {
+            config.output.show_coords = true;
+        }
+        if self.map_link.is_some() {
+            config.output.map_link = self.map_link;
+        }
+        if self.compat.is_some() {
+            config.output.compat = self.compat;
+        }
+        if let Some(annotation) = self.annotation {
+            config.output.annotation = annotation;
+        }
         if self.verbose {
             config.global.verbose = true;
         }
+        if self.strict {
+            config.global.strict = true;
+        }
+        // Colors only make sense on a terminal unless explicitly forced
+        let terminal = self.output.is_none() && atty::is(atty::Stream::Stdout);
+        config.output.enable_colors = self.color.enabled(config.output.enable_colors, terminal);
+        #[cfg(feature = "colored-output")]
+        colored::control::set_override(config.output.enable_colors);
+        if self.no_tld_check {
+            config.global.disable_tld_check = true;
+        }
+        if self.whois {
+            config.whois.enabled = true;
+        }
+        if self.rdns {
+            config.rdns.enabled = true;
+        }
+        if let Some(ref name) = self.db4 {
+            config.database.ipv4_database = name.clone();
+        }
+        if let Some(ref name) = self.db6 {
+            config.database.ipv6_database = name.clone();
+        }
+        if let Some(ref name) = self.cdn_db {
+            config.database.cdn_database = name.clone();
+        }
+        if let Some(ref name) = self.china_db {
+            config.database.china_database = Some(name.clone());
+        }
+        if let Some(mode) = self.load_mode {
+            config.database.load_mode = mode;
+        }
+
+        if !config.global.disable_tld_check {
+            install_suffix_list(&config);
+        }
+
+        // A stream must not stall on a download; strict mode wants every
+        // answer, and preloading wants every database before the first line
+        let preload = self.preload || !config.database.preload.is_empty();
+        let streaming = self.files.is_empty()
+            && self.queries.is_empty()
+            && !atty::is(atty::Stream::Stdin);
+        let db_manager = DatabaseManager::new(config.clone())
+            .download_in_background(streaming && !config.global.strict && !preload)
+            .into_shared();
+        self.validate_database_overrides(&db_manager)?;
+        if preload {
+            db_manager.preload_all().await?;
+        }
+        if let Some(ref addr) = self.metrics_listen {
+            metrics::spawn(metrics::bind(addr).await?, db_manager.metrics());
+        }
+        let mut session = Session {
+            db_manager: &db_manager,
+            pipeline: Pipeline::standard(&config, Arc::clone(&db_manager))?,
+            config: &config,
+            out: OutputWriter::stdout(&config.output),
+            records: None,
+            line_no: 0,
+            pending: Vec::new(),
+            summary: RunSummary::default(),
+            talkers: self
+                .summary_interval
+                .map(|secs| TopTalkers::new(Duration::from_secs(secs))),
+            #[cfg(feature = "sink")]
+            sink: crate::sink::RecordSink::from_config(&config.sink).await?,
+        };
+        #[cfg(not(feature = "sink"))]
+        if config.sink.nats.is_some() {
+            return Err(NaliError::config(
+                "sink.nats is set, but this build has no sink support (build with --features sink)",
+            ));
+        }
 
-        // Create database manager
-        let db_manager = DatabaseManager::new(config.clone());
+        if let Some(ref path) = self.output {
+            let file: Box<dyn Write> = Box::new(BufWriter::new(File::create(path)?));
+            match ResultFormat::from_path(path) {
+                ResultFormat::Text => session.out = OutputWriter::new(file, &config.output),
+                format => session.records = RecordWriter::new(format, file)?,
+            }
+        }
 
-        if !self.queries.is_empty() {
-            // Query from command line arguments
-            self.process_queries_from_args(&db_manager, &config).await?;
+        let result = if !self.files.is_empty() || !self.queries.is_empty() {
+            // Query from files, then from command line arguments
+            match self.process_queries_from_files(&mut session).await {
+                Ok(()) => self.process_queries_from_args(&mut session).await,
+                err => err,
+            }
         } else {
             // Query from stdin (pipe mode or interactive mode)
-            self.process_queries_from_stdin(&db_manager, &config)
-                .await?;
+            self.process_queries_from_stdin(&mut session).await
+        };
+
+        let result = match result {
+            Ok(()) => self.flush_pending(&mut session).await,
+            err => err,
+        };
+        let result = result.and_then(|()| match session.records {
+            Some(ref mut records) => records.finish(),
+            None => Ok(()),
+        });
+        #[cfg(feature = "sink")]
+        let result = match session.sink.take() {
+            Some(sink) => result.and(sink.close().await),
+            None => result,
+        };
+
+
+        // A closed downstream pipe (e.g. `| head`) ends the run normally
+        let result = match result.and_then(|()| session.out.flush()) {
+            Err(NaliError::IoError(e)) if e.kind() == io::ErrorKind::BrokenPipe => Ok(()),
+            other => other,
+        };
+
+        if let Err(e) = db_manager.save_cache() {
+            tracing::warn!("Failed to save the query cache: {}", e);
+        }
+        if self.stats || (config.global.verbose && !config.global.quiet) {
+            eprint!("{}", db_manager.metrics().breakdown());
+        }
+        result?;
+
+        // The tail of the stream since the last summary
+        if let Some(summary) = session.talkers.as_mut().and_then(TopTalkers::finish) {
+            eprint!("{}", summary);
+        }
+
+        Ok(session.summary.status())
+    }
+
+    /// Reject database overrides that do not name a known database
+    fn validate_database_overrides(&self, db_manager: &DatabaseManager) -> Result<()> {
+        let overrides = [
+            ("--db4", &self.db4),
+            ("--db6", &self.db6),
+            ("--cdn-db", &self.cdn_db),
+            ("--china-db", &self.china_db),
+        ];
+
+        for (flag, name) in overrides {
+            if let Some(name) = name
+                && db_manager.get_database_type(name).is_err()
+            {
+                return Err(NaliError::config(format!(
+                    "Unknown database '{}' for {} (run `nali-rs list` to see known databases)",
+                    name, flag
+                )));
+            }
         }
 
         Ok(())
     }
 
     /// Process queries from command line arguments
-    async fn process_queries_from_args(
-        &self,
-        db_manager: &DatabaseManager,
-        config: &AppConfig,
-    ) -> Result<()> {
+    async fn process_queries_from_args(&self, session: &mut Session<'_>) -> Result<()> {
+        if self.query_format == QueryFormat::Table && session.records.is_none() && !self.queries.is_empty() {
+            return self.print_query_table(session).await;
+        }
+
+        // nali annotates its arguments as one line
+        if session.config.output.compat == Some(CompatMode::Nali)
+            && session.records.is_none()
+            && !self.queries.is_empty()
+        {
+            return self.query_and_print_text(&self.queries.join(" "), session).await;
+        }
+
         for query in &self.queries {
-            // Try to parse as IP address
-            if let Ok(ip) = query.parse::<IpAddr>() {
-                self.query_and_print_ip(ip, db_manager, config).await?;
+            if session.records.is_some() {
+                self.emit_line(query, session).await?;
+            } else if let Ok(ip) = query.parse::<IpAddr>()
+                && !self.short
+            {
+                // Try to parse as IP address
+                self.query_and_print_ip(ip, session).await?;
             } else {
                 // Treat as domain or text
-                self.query_and_print_text(query, db_manager, config).await?;
+                self.query_and_print_text(query, session).await?;
             }
         }
         Ok(())
     }
 
-    /// Process queries from stdin (pipe or interactive mode)
-    async fn process_queries_from_stdin(
+    /// Look up every IP and domain in the arguments and print them as one table
+    async fn print_query_table(&self, session: &mut Session<'_>) -> Result<()> {
+        let mut table = QueryTable::default();
+        for query in &self.queries {
+            let mut entities = Entities::new();
+            match query.parse::<IpAddr>() {
+                Ok(IpAddr::V4(_)) => entities.push(Entity::ipv4(0, query.len(), query.clone())),
+                Ok(IpAddr::V6(_)) => entities.push(Entity::ipv6(0, query.len(), query.clone())),
+                Err(_) => entities = self.parse_entities(query, session.config),
+            }
+            self.annotate_entities(&mut entities.entities, None, session).await?;
+            for entity in &entities.entities {
+                table.push(entity);
+            }
+        }
+        session.out.write_str(&table.render())
+    }
+
+    /// Process queries from the files given with `--file`
+    ///
+    /// Shows a progress bar on stderr while writing to `--output`.
+    async fn process_queries_from_files(&self, session: &mut Session<'_>) -> Result<()> {
+        if self.files.is_empty() {
+            return Ok(());
+        }
+
+        let mut total = 0;
+        for path in &self.files {
+            let meta = std::fs::metadata(path).map_err(|e| {
+                NaliError::config(format!("Cannot read input file {}: {}", path.display(), e))
+            })?;
+            total += meta.len();
+        }
+
+        let progress = if self.output.is_some() && !session.config.global.quiet {
+            let pb = ProgressBar::new(total);
+            pb.set_style(
+                ProgressStyle::default_bar()
+                    .template("{spinner:.green} [{elapsed_precise}] [{wide_bar:.cyan/blue}] {bytes}/{total_bytes} ({eta})")
+                    .unwrap()
+                    .progress_chars("#>-"),
+            );
+            pb
+        } else {
+            ProgressBar::hidden()
+        };
+
+        session.db_manager.preload().await;
+
+        let use_gbk = session.config.output.use_gbk;
+        for path in &self.files {
+            let mut reader = BufReader::new(File::open(path)?);
+            let mut raw = Vec::new();
+            loop {
+                raw.clear();
+                let read = reader.read_until(b'\n', &mut raw)?;
+                if read == 0 {
+                    break;
+                }
+                progress.inc(read as u64);
+
+                let line = decode_input(&raw, use_gbk);
+                self.emit_line(line.trim_end_matches(['\r', '\n']), session)
+                    .await?;
+            }
+        }
+
+        progress.finish_and_clear();
+        Ok(())
+    }
+
+    /// Annotate one input line and write it as text or as records
+    ///
+    /// With `--dedupe` the line is buffered and annotated with its chunk.
+    async fn emit_line(&self, line: &str, session: &mut Session<'_>) -> Result<()> {
+        session.line_no += 1;
+
+        if self.dedupe {
+            session.pending.push(line.to_string());
+            if session.pending.len() >= DEDUPE_CHUNK_LINES {
+                self.flush_pending(session).await?;
+            }
+            return Ok(());
+        }
+
+        let complete = self.enrich_line(line, session).await?;
+        self.write_entities(session.line_no, line, &complete, session)
+    }
+
+    /// Annotate the buffered lines, looking up each distinct entity once
+    async fn flush_pending(&self, session: &mut Session<'_>) -> Result<()> {
+        if session.pending.is_empty() {
+            return Ok(());
+        }
+
+        let lines = std::mem::take(&mut session.pending);
+        let mut chunk: Vec<Entities> = lines
+            .iter()
+            .map(|line| self.parse_entities(line, session.config))
+            .collect();
+
+        let mut dedupe = Deduplicator::new();
+        let (mut pending, occurrences): (Vec<Entity>, Vec<usize>) =
+            dedupe.pending(&chunk).into_iter().unzip();
+        self.annotate_entities(&mut pending, Some(&occurrences), session).await?;
+        for entity in pending {
+            dedupe.insert(entity);
+        }
+        dedupe.apply(&mut chunk);
+        tracing::debug!("Annotated {} lines with {} lookups", lines.len(), dedupe.distinct());
+
+        let first_line_no = session.line_no + 1 - lines.len();
+        for (i, (line, entities)) in lines.iter().zip(chunk).enumerate() {
+            let complete = parser::build_complete_entities(line, entities);
+            self.write_entities(first_line_no + i, line, &complete, session)?;
+        }
+        Ok(())
+    }
+
+    /// Write an annotated line as records, JSON or text
+    fn write_entities(
         &self,
-        db_manager: &DatabaseManager,
-        config: &AppConfig,
+        line_no: usize,
+        line: &str,
+        complete: &Entities,
+        session: &mut Session<'_>,
     ) -> Result<()> {
+        if let Some(ref mut records) = session.records {
+            records.write_entities(line_no, complete)?;
+        } else {
+            let result = self.format_line(line, complete, session.config)?;
+            session.out.write_line(&result)?;
+        }
+
+        #[cfg(feature = "sink")]
+        if let Some(ref sink) = session.sink {
+            sink.publish_entities(line_no, complete)?;
+        }
+        if let Some(ref mut talkers) = session.talkers {
+            talkers.record(complete);
+            if let Some(summary) = talkers.due() {
+                session.out.flush()?;
+                eprint!("{}", summary);
+            }
+        }
+        Ok(())
+    }
+
+    /// Process queries from stdin (pipe or interactive mode)
+    async fn process_queries_from_stdin(&self, session: &mut Session<'_>) -> Result<()> {
         let stdin = io::stdin();
-        let mut stdout = io::stdout();
+        let use_gbk = session.config.output.use_gbk;
 
         // Check if stdin is a TTY (interactive mode)
         if atty::is(atty::Stream::Stdin) {
             // Interactive mode
-            println!("nali-rs interactive mode (enter quit or Ctrl+D to exit)");
+            session
+                .out
+                .write_line("nali-rs interactive mode (enter quit or Ctrl+D to exit)")?;
+            session.out.flush()?;
 
-            for line in stdin.lock().lines() {
-                let line = line?;
+            let mut reader = stdin.lock();
+            let mut raw = Vec::new();
+            loop {
+                raw.clear();
+                if reader.read_until(b'\n', &mut raw)? == 0 {
+                    break;
+                }
+                let line = decode_input(&raw, use_gbk);
                 let trimmed = line.trim();
 
                 if trimmed.is_empty() {
@@ -126,25 +815,31 @@ impl Cli {
                 }
 
                 // Process the line
-                let result = self.process_line(trimmed, db_manager, config).await?;
-                println!("{}", result);
-                stdout.flush()?;
+                if session.records.is_some() {
+                    self.emit_line(trimmed, session).await?;
+                } else {
+                    let result = self.process_line(trimmed, session).await?;
+                    session.out.write_line(&result)?;
+                }
+                session.out.flush()?;
             }
         } else {
-            // Pipe mode - read from stdin and enrich with geolocation info
-            // Note: We preserve line endings to match the original text format
-            // The lines() iterator strips \n, but we need to add them back
-            use std::io::Read;
-            let mut buffer = String::new();
-            stdin.lock().read_to_string(&mut buffer)?;
-
-            for line in buffer.lines() {
-                // Re-add the newline that lines() strips
-                let line_with_newline = format!("{}\n", line);
-                let result = self
-                    .process_line(&line_with_newline, db_manager, config)
+            // Pipe mode - enrich each line as soon as it arrives, so output
+            // keeps pace with streaming producers like `ping` or `tail -f`.
+            // Databases are loaded up front; missing ones download in the
+            // background while lines pass through un-annotated.
+            session.db_manager.preload().await;
+
+            let mut reader = stdin.lock();
+            let mut raw = Vec::new();
+            loop {
+                raw.clear();
+                if reader.read_until(b'\n', &mut raw)? == 0 {
+                    break;
+                }
+                let line = decode_input(&raw, use_gbk);
+                self.emit_line(line.trim_end_matches(['\r', '\n']), session)
                     .await?;
-                print!("{}", result); // Use print! not println! since line already has \n
             }
         }
 
@@ -152,109 +847,366 @@ impl Cli {
     }
 
     /// Process a single line of text
-    async fn process_line(
+    async fn process_line(&self, line: &str, session: &mut Session<'_>) -> Result<String> {
+        let complete = self.enrich_line(line, session).await?;
+        self.format_line(line, &complete, session.config)
+    }
+
+    /// Format an annotated line for text output; JSON input stays JSON
+    fn format_line(&self, line: &str, complete: &Entities, config: &AppConfig) -> Result<String> {
+        if self.short {
+            return Ok(formatter::format_short(complete, &(&config.output).into()));
+        }
+        match self.input.format {
+            InputFormat::Json => Ok(self.input.annotate_json(line, complete)),
+            _ => format_entities(complete, config),
+        }
+    }
+
+    /// Parse a line and annotate its entities, keeping the plain text between them
+    async fn enrich_line(&self, line: &str, session: &mut Session<'_>) -> Result<Entities> {
+        let mut entities = self.parse_entities(line, session.config);
+        self.annotate_entities(&mut entities.entities, None, session).await?;
+
+        // Build complete entities with plain text segments
+        Ok(parser::build_complete_entities(line, entities))
+    }
+
+    /// Extract IP and domain entities from the parts of a line the input format searches
+    fn parse_entities(&self, line: &str, config: &AppConfig) -> Entities {
+        let options = ParseOptions {
+            validate_tld: !config.global.disable_tld_check,
+            first_ip_only: self.first_ip_only,
+        };
+        let mut entities = Entities::new();
+        for (start, end) in self.input.spans(line) {
+            for mut entity in parser::parse_line_with(&line[start..end], &options).entities {
+                entity.location.0 += start;
+                entity.location.1 += start;
+                entities.push(entity);
+            }
+        }
+        entities
+    }
+
+    /// Run entities through the enrichment pipeline, several at once
+    ///
+    /// With duplicates folded, `occurrences` gives how many input entities
+    /// each one stands for, so an unresolved one is counted that many times.
+    async fn annotate_entities(
         &self,
-        line: &str,
-        db_manager: &DatabaseManager,
-        config: &AppConfig,
-    ) -> Result<String> {
-        // Parse entities from the line
-        let mut entities = parser::parse_line(line);
-
-        // Enrich entities with geolocation/CDN information
-        for entity in &mut entities.entities {
-            match entity.entity_type {
-                EntityType::IPv4 | EntityType::IPv6 => {
-                    if let Some(ip) = entity.as_ip()
-                        && let Ok(Some(geo)) = db_manager.query_ip(ip).await {
-                            entity.geo_info = Some(geo);
-                            entity.source = Some(config.database.ipv4_database.clone());
-                        }
-                }
-                EntityType::Domain => {
-                    if let Ok(Some(cdn)) = db_manager.query_cdn(&entity.text).await {
-                        entity.cdn_info = Some(cdn);
-                        entity.source = Some(config.database.cdn_database.clone());
-                    }
+        entities: &mut [Entity],
+        occurrences: Option<&[usize]>,
+        session: &mut Session<'_>,
+    ) -> Result<()> {
+        for entity in entities.iter() {
+            self.explain(entity, session).await?;
+        }
+        let reports = session.pipeline.enrich_all(entities).await;
+        for (i, (entity, report)) in entities.iter().zip(reports).enumerate() {
+            for e in report.errors {
+                session.failed(e)?;
+            }
+            if report.unresolved {
+                for _ in 0..occurrences.map_or(1, |o| o[i]) {
+                    session.unresolved(&entity.text)?;
                 }
-                EntityType::Plain => {}
             }
         }
+        Ok(())
+    }
 
-        // Build complete entities with plain text segments
-        let complete = parser::build_complete_entities(line, entities);
+    /// Print how the lookup of an entity is answered to stderr, for `--explain`
+    ///
+    /// Runs before the lookup itself, which reports any error.
+    async fn explain(&self, entity: &Entity, session: &mut Session<'_>) -> Result<()> {
+        if !self.explain {
+            return Ok(());
+        }
+        let explanation = match entity.as_ip() {
+            Some(ip) => session.db_manager.explain_ip(ip).await,
+            None if entity.entity_type == EntityType::Domain => {
+                session.db_manager.explain_cdn(&entity.text).await
+            }
+            None => return Ok(()),
+        };
 
-        // Format output
-        if config.output.json {
-            formatter::format_json(&complete).map_err(crate::error::NaliError::JsonError)
-        } else {
-            Ok(formatter::format_text(
-                &complete,
-                config.output.enable_colors,
-            ))
+        match explanation {
+            Ok(explanation) => {
+                // Keep the explanation next to the lines already written
+                session.out.flush()?;
+                eprintln!("{}: {}", entity.text, explanation);
+            }
+            Err(e) => tracing::debug!("Cannot explain {}: {}", entity.text, e),
         }
+        Ok(())
     }
 
     /// Query and print a single IP
-    async fn query_and_print_ip(
-        &self,
-        ip: IpAddr,
-        db_manager: &DatabaseManager,
-        config: &AppConfig,
-    ) -> Result<()> {
-        match db_manager.query_ip(ip).await {
-            Ok(Some(geo)) => {
-                if config.output.json {
-                    let json = serde_json::to_string_pretty(&geo)?;
-                    println!("{}", json);
+    async fn query_and_print_ip(&self, ip: IpAddr, session: &mut Session<'_>) -> Result<()> {
+        let text = ip.to_string();
+        let mut entity = match ip {
+            IpAddr::V4(_) => Entity::ipv4(0, text.len(), text),
+            IpAddr::V6(_) => Entity::ipv6(0, text.len(), text),
+        };
+        self.explain(&entity, session).await?;
+        let report = session.pipeline.enrich(&mut entity).await;
+        // Whois summary, PTR name, Tor exit mark and IP set tags, after the location
+        let mut extra = entity
+            .whois_info
+            .as_ref()
+            .map(|w| w.summary())
+            .filter(|s| !s.is_empty())
+            .map(|s| format!(" | {}", s))
+            .unwrap_or_default();
+        if let Some(rdns) = &entity.rdns {
+            extra.push_str(&format!(" | {}", rdns.summary()));
+        }
+        if entity.is_tor {
+            extra.push_str(&format!(" | {}", crate::tr!("Tor exit")));
+        }
+        for tag in &entity.tags {
+            extra.push_str(&format!(" [{}]", tag));
+        }
+
+        let failed = !report.errors.is_empty();
+        for e in report.errors {
+            session.failed(e)?;
+        }
+
+        match entity.geo_info {
+            Some(geo) => {
+                if session.config.output.json {
+                    let mut value = serde_json::to_value(&geo)?;
+                    if let Some(meta) = entity.meta {
+                        value["meta"] = serde_json::to_value(meta)?;
+                    }
+                    if let Some(whois) = entity.whois_info {
+                        value["whois_info"] = serde_json::to_value(whois)?;
+                    }
+                    if let Some(rdns) = entity.rdns {
+                        value["rdns"] = serde_json::to_value(rdns)?;
+                    }
+                    if entity.is_tor {
+                        value["is_tor"] = true.into();
+                    }
+                    if !entity.tags.is_empty() {
+                        value["tags"] = entity.tags.clone().into();
+                    }
+                    let json = serde_json::to_string_pretty(&value)?;
+                    session.out.write_line(&json)?;
                 } else {
-                    let info = formatter::format_geo_info_compact(&geo);
-                    println!("{} -> {}", ip, info);
+                    let style = formatter::GeoStyle::from(&session.config.output);
+                    let info = formatter::format_geo_info_colored(
+                        &geo,
+                        session.config.output.enable_colors,
+                        &style,
+                    );
+                    let details = formatter::format_geo_details(&geo)
+                        .filter(|_| session.config.global.verbose)
+                        .map(|details| format!(" {}", details))
+                        .unwrap_or_default();
+                    let link = formatter::map_link(&geo, &style)
+                        .map(|link| format!(" {}", link))
+                        .unwrap_or_default();
+                    session.out.write_line(&format!(
+                        "{} -> {}{}{}{}",
+                        ip, info, details, link, extra
+                    ))?;
                 }
             }
-            Ok(None) => {
-                println!("{} -> [Not found]", ip);
-            }
-            Err(e) => {
-                eprintln!("Query failed: {}", e);
+            // The failure was reported above
+            None if failed => {}
+            None => {
+                if report.unresolved {
+                    session.unresolved(&entity.text)?;
+                }
+                session
+                    .out
+                    .write_line(&format!("{} -> [{}]{}", ip, crate::tr!("Not found"), extra))?;
             }
         }
         Ok(())
     }
 
     /// Query and print text (may contain IPs and domains)
-    async fn query_and_print_text(
-        &self,
-        text: &str,
-        db_manager: &DatabaseManager,
-        config: &AppConfig,
-    ) -> Result<()> {
-        let result = self.process_line(text, db_manager, config).await?;
-        println!("{}", result);
-        Ok(())
+    async fn query_and_print_text(&self, text: &str, session: &mut Session<'_>) -> Result<()> {
+        let result = self.process_line(text, session).await?;
+        session.out.write_line(&result)
+    }
+
+    /// Whether this invocation still works when the configuration file
+    /// cannot be loaded: the commands that locate, check or fix it
+    pub fn runs_without_config(&self) -> bool {
+        match &self.command {
+            Some(Command::Config(args)) => matches!(
+                args.command,
+                config::ConfigCommand::Path
+                    | config::ConfigCommand::Edit
+                    | config::ConfigCommand::Validate { .. }
+            ),
+            Some(Command::Man) => true,
+            _ => false,
+        }
+    }
+
+    /// The action of this invocation that writes to the data or config
+    /// directory, if any
+    fn writing_action(&self) -> Option<&'static str> {
+        match &self.command {
+            Some(Command::Import(_)) => Some("import"),
+            Some(Command::Init(_)) => Some("init"),
+            Some(Command::Config(args)) => match args.command {
+                config::ConfigCommand::Set { .. } => Some("config set"),
+                config::ConfigCommand::Edit => Some("config edit"),
+                config::ConfigCommand::Convert { .. } => Some("config convert"),
+                _ => None,
+            },
+            Some(Command::Db(args)) => match args.command {
+                db::DbCommand::Rollback { .. } => Some("db rollback"),
+                _ => None,
+            },
+            Some(_) => None,
+            None => self.update.then_some("--update"),
+        }
     }
 
     /// Handle database update command
-    async fn handle_update(&self, config: &AppConfig) -> Result<()> {
-        let downloader = Downloader::new()?;
+    async fn handle_update(&self, config: &AppConfig) -> Result<ExitStatus> {
+        let quiet = config.global.quiet;
+        let downloader = Downloader::with_config(&config.download)?
+            .quiet(quiet)
+            .post_update(config.hooks.post_update.clone());
+        let mut summary = RunSummary::default();
 
         if self.queries.is_empty() {
             // No specific database specified, update all
-            println!("Updating all databases...\n");
+            if !quiet {
+                eprintln!("{}\n", crate::tr!("Updating all databases..."));
+            }
             downloader.download_all(config).await?;
         } else {
             // Update specific databases
-            for db_name in &self.queries {
+            for db_name in &download::expand_names(config, &self.queries) {
                 match downloader.update_database(config, db_name).await {
                     Ok(_) => {}
                     Err(e) => {
-                        eprintln!("✗ Failed to update {}: {}", db_name, e);
+                        if !quiet {
+                            eprintln!("{}", crate::tr!("✗ Failed to update {}: {}", db_name, e));
+                        }
+                        summary.record_error();
                     }
                 }
-                println!();
+                if !quiet {
+                    eprintln!();
+                }
             }
         }
 
-        Ok(())
+        Ok(summary.status())
+    }
+}
+
+/// Format an annotated line as JSON, nali-compatible text or text
+fn format_entities(complete: &Entities, config: &AppConfig) -> Result<String> {
+    let use_color = config.output.enable_colors;
+    Ok(if config.output.json {
+        formatter::format_json(complete).map_err(NaliError::JsonError)?
+    } else if config.output.compat == Some(CompatMode::Nali) {
+        formatter::format_nali(complete, use_color)
+    } else {
+        let style = (&config.output).into();
+        match config.output.annotation {
+            AnnotationPosition::Inline => {
+                formatter::format_text(complete, use_color, config.global.verbose, &style)
+            }
+            AnnotationPosition::Eol => {
+                formatter::format_text_eol(complete, use_color, config.global.verbose, &style)
+            }
+        }
+    })
+}
+
+/// Prefer a downloaded public suffix list over the embedded snapshot
+fn install_suffix_list(config: &AppConfig) {
+    let Ok(path) = config.get_database_path("psl") else {
+        return;
+    };
+    if !path.exists() {
+        return;
+    }
+
+    match PublicSuffixList::load_file(&path) {
+        Ok(list) => {
+            tracing::debug!("Using public suffix list from {:?} ({} rules)", path, list.len());
+            suffix::install(list);
+        }
+        Err(e) => tracing::warn!("Ignoring public suffix list {:?}: {}", path, e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_validate_database_overrides() {
+        let manager = DatabaseManager::new(AppConfig::default());
+
+        let cli = Cli::try_parse_from(["nali-rs", "--db4", "geoip2", "--db6", "zxipv6"]).unwrap();
+        assert!(cli.validate_database_overrides(&manager).is_ok());
+
+        let cli = Cli::try_parse_from(["nali-rs", "--cdn-db", "nope"]).unwrap();
+        assert!(cli.validate_database_overrides(&manager).is_err());
+    }
+
+    #[test]
+    fn test_writing_actions() {
+        let action = |args: &[&str]| Cli::try_parse_from(args).unwrap().writing_action();
+        assert_eq!(action(&["nali-rs", "--update"]), Some("--update"));
+        assert_eq!(action(&["nali-rs", "config", "set", "output.json", "true"]), Some("config set"));
+        assert_eq!(action(&["nali-rs", "db", "rollback", "qqwry"]), Some("db rollback"));
+        assert_eq!(action(&["nali-rs", "init", "--yes"]), Some("init"));
+        assert_eq!(action(&["nali-rs", "config", "show"]), None);
+        assert_eq!(action(&["nali-rs", "8.8.8.8"]), None);
+    }
+
+    #[test]
+    fn test_quiet_is_global() {
+        // `-q` stays the per-hop probe count of `trace`
+        let cli = Cli::try_parse_from(["nali-rs", "trace", "-q", "1", "--quiet", "example.com"])
+            .unwrap();
+        assert!(cli.quiet);
+        match cli.command {
+            Some(Command::Trace(args)) => assert_eq!(args.queries, 1),
+            other => panic!("unexpected command: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_map_link_service_is_optional() {
+        let cli = Cli::try_parse_from(["nali-rs", "--map-link", "8.8.8.8"]).unwrap();
+        assert_eq!(cli.map_link, Some(MapProvider::Osm));
+        assert_eq!(cli.queries, ["8.8.8.8"]);
+
+        let cli = Cli::try_parse_from(["nali-rs", "--map-link=google", "8.8.8.8"]).unwrap();
+        assert_eq!(cli.map_link, Some(MapProvider::Google));
+        assert!(Cli::try_parse_from(["nali-rs", "--map-link=bing"]).is_err());
+    }
+
+    #[test]
+    fn test_format_is_separate_from_input_format() {
+        let cli = Cli::try_parse_from(["nali-rs", "--format", "table", "--input-format", "nginx"]).unwrap();
+        assert_eq!(cli.query_format, QueryFormat::Table);
+        assert_eq!(cli.input.format, InputFormat::Nginx);
+        assert!(Cli::try_parse_from(["nali-rs", "--format", "csv"]).is_err());
+    }
+
+    #[test]
+    fn test_short_excludes_other_layouts() {
+        assert!(Cli::try_parse_from(["nali-rs", "--short", "8.8.8.8"]).unwrap().short);
+        assert!(Cli::try_parse_from(["nali-rs", "--short", "--json", "8.8.8.8"]).is_err());
+        assert!(Cli::try_parse_from(["nali-rs", "--short", "--format", "table"]).is_err());
+        assert!(Cli::try_parse_from(["nali-rs", "--short", "--compat", "nali"]).is_err());
     }
 }
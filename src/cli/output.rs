@@ -0,0 +1,156 @@
+//! Output writer for the CLI
+//!
+//! Wraps standard output and applies the configured output encoding, so the
+//! query logic never has to care whether the terminal expects UTF-8 or GBK.
+//!
+//! Output to a terminal, or with `--line-buffered`, is flushed after every
+//! line so results keep pace with streaming input such as `tail -f`. Output
+//! to a pipe or file is otherwise block-buffered for throughput.
+//!
+//! Colors follow the same split: `--color=auto` colors terminal output only,
+//! and `NO_COLOR` or `output.enable_colors: false` turn them off there too.
+
+use crate::config::OutputConfig;
+use crate::error::Result;
+use crate::utils::encoding::utf8_to_gbk;
+use clap::ValueEnum;
+use std::io::{self, BufWriter, Write};
+
+/// When to color output, as chosen with `--color`
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ColorChoice {
+    /// Color when writing to a terminal and colors aren't disabled by config or `NO_COLOR`
+    #[default]
+    Auto,
+    /// Always color, even into pipes and files
+    Always,
+    /// Never color
+    Never,
+}
+
+/// Layout of the results of queries given as arguments, as chosen with `--format`
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum QueryFormat {
+    /// One annotated line per query
+    #[default]
+    Text,
+    /// JSON, like `--json`
+    Json,
+    /// One aligned table row per IP or domain
+    Table,
+}
+
+impl ColorChoice {
+    /// Decide whether to color given the configured preference and the output kind
+    pub fn enabled(self, configured: bool, terminal: bool) -> bool {
+        match self {
+            Self::Auto => configured && terminal,
+            Self::Always => true,
+            Self::Never => false,
+        }
+    }
+}
+
+/// Encoding-aware output sink
+pub struct OutputWriter {
+    inner: Box<dyn Write>,
+    gbk: bool,
+    line_buffered: bool,
+}
+
+impl OutputWriter {
+    /// Create a writer for standard output using the output configuration
+    pub fn stdout(config: &OutputConfig) -> Self {
+        let mut writer = Self::new(Box::new(BufWriter::new(io::stdout())), config);
+        writer.line_buffered |= atty::is(atty::Stream::Stdout);
+        writer
+    }
+
+    /// Create a writer on top of an arbitrary sink
+    pub fn new(inner: Box<dyn Write>, config: &OutputConfig) -> Self {
+        Self {
+            inner,
+            gbk: config.gbk_output,
+            line_buffered: config.line_buffered,
+        }
+    }
+
+    /// Write text as-is
+    ///
+    /// In line-buffered mode, text ending a line is flushed immediately.
+    pub fn write_str(&mut self, text: &str) -> Result<()> {
+        if self.gbk {
+            self.inner.write_all(&utf8_to_gbk(text))?;
+        } else {
+            self.inner.write_all(text.as_bytes())?;
+        }
+        if self.line_buffered && text.ends_with('\n') {
+            self.inner.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Write text followed by a newline
+    pub fn write_line(&mut self, text: &str) -> Result<()> {
+        self.write_str(text)?;
+        self.write_str("\n")
+    }
+
+    /// Flush the underlying sink
+    pub fn flush(&mut self) -> Result<()> {
+        self.inner.flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    /// Sink recording how many bytes had been flushed
+    #[derive(Clone, Default)]
+    struct Sink {
+        buffered: Rc<RefCell<Vec<u8>>>,
+        flushed: Rc<RefCell<usize>>,
+    }
+
+    impl Write for Sink {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.buffered.borrow_mut().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            *self.flushed.borrow_mut() = self.buffered.borrow().len();
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_color_choice() {
+        assert!(ColorChoice::Auto.enabled(true, true));
+        assert!(!ColorChoice::Auto.enabled(true, false));
+        assert!(!ColorChoice::Auto.enabled(false, true));
+        assert!(ColorChoice::Always.enabled(false, false));
+        assert!(!ColorChoice::Never.enabled(true, true));
+    }
+
+    #[test]
+    fn test_line_buffered_flushes_each_line() {
+        let sink = Sink::default();
+        let config = OutputConfig {
+            line_buffered: true,
+            ..OutputConfig::default()
+        };
+        let mut out = OutputWriter::new(Box::new(sink.clone()), &config);
+
+        out.write_str("partial").unwrap();
+        assert_eq!(*sink.flushed.borrow(), 0);
+        out.write_str(" line\n").unwrap();
+        assert_eq!(*sink.flushed.borrow(), "partial line\n".len());
+        out.write_line("next").unwrap();
+        assert_eq!(*sink.flushed.borrow(), "partial line\nnext\n".len());
+    }
+}
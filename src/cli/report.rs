@@ -0,0 +1,405 @@
+//! `nali-rs report` subcommand
+//!
+//! Looks up every IP address in the input once and prints how the addresses
+//! spread over countries, ISPs or matched blocks, for a quick look at a log
+//! without piping annotated lines through `sort | uniq -c`.
+//!
+//! [`TopTalkers`] keeps the same tally over a stream for
+//! `--summary-interval`.
+
+use crate::config::AppConfig;
+use crate::database::{DatabaseManager, GeoLocation};
+use crate::entity::{Entities, parser};
+use crate::error::Result;
+use crate::utils::encoding::decode_input;
+use clap::{Args, ValueEnum};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader};
+use std::net::IpAddr;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+use super::status::ExitStatus;
+
+/// Label for addresses whose location lacks the grouped field
+const UNKNOWN: &str = "[Unknown]";
+
+/// Location field to group addresses by
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum GroupBy {
+    Country,
+    Region,
+    City,
+    Isp,
+    /// The database block the address was matched in
+    Network,
+}
+
+impl GroupBy {
+    /// Table header of the grouped column
+    fn header(self) -> &'static str {
+        match self {
+            GroupBy::Country => "COUNTRY",
+            GroupBy::Region => "REGION",
+            GroupBy::City => "CITY",
+            GroupBy::Isp => "ISP",
+            GroupBy::Network => "NETWORK",
+        }
+    }
+
+    /// Value of the grouped field; `None` for addresses not found
+    fn value(self, geo: Option<&GeoLocation>) -> String {
+        let field = geo.and_then(|geo| match self {
+            GroupBy::Country => geo.country.as_deref(),
+            GroupBy::Region => geo.region.as_deref(),
+            GroupBy::City => geo.city.as_deref(),
+            GroupBy::Isp => geo.isp.as_deref(),
+            GroupBy::Network => geo.network.as_deref(),
+        });
+        field.unwrap_or(UNKNOWN).to_string()
+    }
+}
+
+/// Arguments for `nali-rs report`
+#[derive(Args, Debug)]
+pub struct ReportArgs {
+    /// Read IPs from a file (repeatable; defaults to standard input)
+    #[arg(short, long = "file", value_name = "FILE")]
+    pub files: Vec<PathBuf>,
+
+    /// Field to group by (repeatable; default country and isp)
+    #[arg(short, long, value_enum, value_name = "FIELD")]
+    pub by: Vec<GroupBy>,
+
+    /// Rows per table; the rest are summed up as other
+    #[arg(short = 'n', long, default_value_t = 10)]
+    pub top: usize,
+
+    /// Output the summary as JSON
+    #[arg(short, long)]
+    pub json: bool,
+}
+
+/// One row of a summary table
+#[derive(Debug, PartialEq, Serialize)]
+struct Row {
+    value: String,
+    count: usize,
+    /// Fraction of all addresses
+    share: f64,
+}
+
+/// Addresses tallied by one field, most frequent first
+#[derive(Debug, PartialEq, Serialize)]
+struct Group {
+    by: GroupBy,
+    rows: Vec<Row>,
+    /// Addresses in the groups beyond `--top`
+    other: usize,
+}
+
+/// Summary of every address in the input
+#[derive(Debug, PartialEq, Serialize)]
+struct Report {
+    /// Addresses seen, counting repeats
+    total: usize,
+    /// Distinct addresses
+    distinct: usize,
+    /// Addresses not found in the database, counting repeats
+    not_found: usize,
+    groups: Vec<Group>,
+}
+
+/// Count how often each IP address occurs in the lines of `reader`
+fn count_ips(reader: impl BufRead, counts: &mut HashMap<IpAddr, usize>) -> Result<()> {
+    for raw in reader.split(b'\n') {
+        let line = decode_input(&raw?, false);
+        for entity in parser::parse_line(&line).entities {
+            if let Some(ip) = entity.as_ip() {
+                *counts.entry(ip).or_default() += 1;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Tally looked up addresses with their number of occurrences
+fn summarize(found: &[(usize, Option<GeoLocation>)], by: &[GroupBy], top: usize) -> Report {
+    let total: usize = found.iter().map(|(count, _)| count).sum();
+    let share = |count: usize| {
+        if total == 0 {
+            0.0
+        } else {
+            count as f64 / total as f64
+        }
+    };
+
+    let groups = by
+        .iter()
+        .map(|&by| {
+            let mut tally: HashMap<String, usize> = HashMap::new();
+            for (count, geo) in found {
+                *tally.entry(by.value(geo.as_ref())).or_default() += count;
+            }
+
+            let mut rows: Vec<Row> = tally
+                .into_iter()
+                .map(|(value, count)| Row {
+                    value,
+                    count,
+                    share: share(count),
+                })
+                .collect();
+            rows.sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.value.cmp(&b.value)));
+            let other = rows.iter().skip(top).map(|row| row.count).sum();
+            rows.truncate(top);
+
+            Group { by, rows, other }
+        })
+        .collect();
+
+    Report {
+        total,
+        distinct: found.len(),
+        not_found: found
+            .iter()
+            .filter(|(_, geo)| geo.is_none())
+            .map(|(count, _)| count)
+            .sum(),
+        groups,
+    }
+}
+
+/// Render a summary table per group
+fn render(report: &Report) -> String {
+    let mut out = format!(
+        "{} addresses ({} distinct), {} not found\n",
+        report.total, report.distinct, report.not_found
+    );
+
+    for group in &report.groups {
+        let mut rows: Vec<[String; 3]> = group
+            .rows
+            .iter()
+            .map(|row| {
+                [
+                    row.value.clone(),
+                    row.count.to_string(),
+                    format!("{:.1}%", row.share * 100.0),
+                ]
+            })
+            .collect();
+        if group.other > 0 {
+            let share = group.other as f64 / report.total as f64;
+            rows.push([
+                "(other)".to_string(),
+                group.other.to_string(),
+                format!("{:.1}%", share * 100.0),
+            ]);
+        }
+
+        let headers = [group.by.header(), "COUNT", "SHARE"];
+        let mut widths = headers.map(|h| h.chars().count());
+        for row in &rows {
+            for (width, cell) in widths.iter_mut().zip(row) {
+                *width = (*width).max(cell.chars().count());
+            }
+        }
+        let render_row = |cells: [&str; 3]| {
+            // Counts and shares line up on the right
+            format!(
+                "{:<w0$}  {:>w1$}  {:>w2$}\n",
+                cells[0],
+                cells[1],
+                cells[2],
+                w0 = widths[0],
+                w1 = widths[1],
+                w2 = widths[2],
+            )
+        };
+
+        out.push('\n');
+        out.push_str(&render_row(headers));
+        for row in &rows {
+            out.push_str(&render_row([&row[0], &row[1], &row[2]]));
+        }
+    }
+    out
+}
+
+/// Rows per table in a `--summary-interval` summary
+const TALKERS_TOP: usize = 5;
+
+/// Addresses annotated in a stream since the last summary
+///
+/// Feeds `--summary-interval`: every interval, the most frequent countries
+/// and ISPs of the addresses seen in it are printed to stderr.
+pub struct TopTalkers {
+    interval: Duration,
+    started: Instant,
+    seen: HashMap<IpAddr, (usize, Option<GeoLocation>)>,
+}
+
+impl TopTalkers {
+    pub fn new(interval: Duration) -> Self {
+        Self {
+            interval,
+            started: Instant::now(),
+            seen: HashMap::new(),
+        }
+    }
+
+    /// Count the IP addresses of an annotated line
+    pub fn record(&mut self, entities: &Entities) {
+        for entity in &entities.entities {
+            if let Some(ip) = entity.as_ip() {
+                let (count, _) = self
+                    .seen
+                    .entry(ip)
+                    .or_insert_with(|| (0, entity.geo_info.clone()));
+                *count += 1;
+            }
+        }
+    }
+
+    /// Summary of the interval once it has passed, starting the next one
+    pub fn due(&mut self) -> Option<String> {
+        let elapsed = self.started.elapsed();
+        (elapsed >= self.interval).then(|| self.take(elapsed))
+    }
+
+    /// Summary of the addresses seen so far, if any, e.g. when the stream ends
+    pub fn finish(&mut self) -> Option<String> {
+        let elapsed = self.started.elapsed();
+        (!self.seen.is_empty()).then(|| self.take(elapsed))
+    }
+
+    fn take(&mut self, elapsed: Duration) -> String {
+        let found: Vec<_> = self.seen.drain().map(|(_, seen)| seen).collect();
+        self.started = Instant::now();
+
+        let report = summarize(&found, &[GroupBy::Country, GroupBy::Isp], TALKERS_TOP);
+        format!("== Top talkers, last {}s ==\n{}", elapsed.as_secs(), render(&report))
+    }
+}
+
+/// Run the report subcommand
+pub async fn run(args: &ReportArgs, config: &AppConfig) -> Result<ExitStatus> {
+    let mut counts = HashMap::new();
+    if args.files.is_empty() {
+        count_ips(io::stdin().lock(), &mut counts)?;
+    }
+    for path in &args.files {
+        count_ips(BufReader::new(File::open(path)?), &mut counts)?;
+    }
+
+    let db_manager = DatabaseManager::new(config.clone());
+    let mut status = ExitStatus::Success;
+    let mut found = Vec::with_capacity(counts.len());
+    for (ip, count) in counts {
+        let geo = match db_manager.query_ip(ip).await {
+            Ok(geo) => geo,
+            Err(e) => {
+                tracing::warn!("Query failed: {}", e);
+                status = ExitStatus::DatabaseError;
+                None
+            }
+        };
+        found.push((count, geo));
+    }
+
+    let by = if args.by.is_empty() {
+        vec![GroupBy::Country, GroupBy::Isp]
+    } else {
+        args.by.clone()
+    };
+    let report = summarize(&found, &by, args.top);
+
+    if args.json {
+        println!("{}", serde_json::to_string_pretty(&report)?);
+    } else {
+        print!("{}", render(&report));
+    }
+    Ok(status)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn geo(country: &str, isp: Option<&str>) -> Option<GeoLocation> {
+        Some(GeoLocation {
+            ip: "192.0.2.1".parse().unwrap(),
+            country: Some(country.to_string()),
+            isp: isp.map(str::to_string),
+            ..Default::default()
+        })
+    }
+
+    #[test]
+    fn test_count_ips() {
+        let input = "8.8.8.8 - GET /\n1.1.1.1 -> 8.8.8.8\nno address here\n2001:db8::1\n";
+        let mut counts = HashMap::new();
+        count_ips(input.as_bytes(), &mut counts).unwrap();
+
+        assert_eq!(counts.len(), 3);
+        assert_eq!(counts[&"8.8.8.8".parse::<IpAddr>().unwrap()], 2);
+        assert_eq!(counts[&"2001:db8::1".parse::<IpAddr>().unwrap()], 1);
+    }
+
+    #[test]
+    fn test_top_talkers() {
+        let mut talkers = TopTalkers::new(Duration::from_secs(3600));
+        assert_eq!(talkers.due(), None);
+        assert_eq!(talkers.finish(), None);
+
+        let mut entities = Entities::new();
+        let mut entity = crate::entity::Entity::ipv4(0, 9, "192.0.2.1".to_string());
+        entity.geo_info = geo("中国", Some("电信"));
+        entities.push(entity);
+        talkers.record(&entities);
+        talkers.record(&entities);
+
+        let summary = talkers.finish().unwrap();
+        assert!(summary.starts_with("== Top talkers, last 0s ==\n2 addresses (1 distinct)"));
+        assert!(summary.contains("\nISP  COUNT   SHARE\n电信       2  100.0%\n"));
+        assert_eq!(talkers.finish(), None);
+
+        let mut talkers = TopTalkers::new(Duration::ZERO);
+        talkers.record(&entities);
+        assert!(talkers.due().is_some());
+    }
+
+    #[test]
+    fn test_summarize_and_render() {
+        let found = vec![
+            (5, geo("中国", Some("电信"))),
+            (3, geo("美国", Some("Google"))),
+            (1, geo("中国", None)),
+            (1, None),
+        ];
+
+        let report = summarize(&found, &[GroupBy::Country, GroupBy::Isp], 2);
+        assert_eq!((report.total, report.distinct, report.not_found), (10, 4, 1));
+
+        let countries = &report.groups[0];
+        assert_eq!(countries.rows[0].value, "中国");
+        assert_eq!(countries.rows[0].count, 6);
+        assert_eq!(countries.rows[1].value, "美国");
+        assert_eq!(countries.other, 1);
+
+        // The two unknown ISPs make one group
+        let isps = &report.groups[1];
+        assert_eq!(isps.rows[0].value, "电信");
+        assert_eq!(isps.rows[1].value, "Google");
+        assert_eq!(isps.other, 2);
+
+        let text = render(&report);
+        assert!(text.starts_with("10 addresses (4 distinct), 1 not found\n"));
+        assert!(text.contains("\nCOUNTRY  COUNT  SHARE\n中国           6  60.0%\n"));
+        assert!(text.contains("\n(other)      1  10.0%\n"));
+    }
+}
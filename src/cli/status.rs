@@ -0,0 +1,106 @@
+//! Exit status handling for scripting use
+//!
+//! The process exit code tells scripts whether every lookup succeeded:
+//!
+//! - `0`: all queries resolved
+//! - `1`: at least one IP address was not found in its database
+//! - `2`: a database or the configuration could not be loaded, or a
+//!   database could not be queried
+
+use crate::error::NaliError;
+use std::process::ExitCode;
+
+/// Exit status reported to the shell
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ExitStatus {
+    /// All queries resolved
+    Success,
+    /// Some queries were not found
+    NotFound,
+    /// A database error occurred
+    DatabaseError,
+}
+
+impl ExitStatus {
+    /// Numeric exit code
+    pub fn code(self) -> u8 {
+        match self {
+            ExitStatus::Success => 0,
+            ExitStatus::NotFound => 1,
+            ExitStatus::DatabaseError => 2,
+        }
+    }
+
+    /// Map an error to the exit status it should produce
+    pub fn from_error(err: &NaliError) -> Self {
+        match err {
+            NaliError::Unresolved(_) => ExitStatus::NotFound,
+            _ => ExitStatus::DatabaseError,
+        }
+    }
+}
+
+impl From<ExitStatus> for ExitCode {
+    fn from(status: ExitStatus) -> Self {
+        ExitCode::from(status.code())
+    }
+}
+
+/// Outcome counters for a single run
+#[derive(Debug, Default, Clone)]
+pub struct RunSummary {
+    /// Number of IP lookups that returned no result
+    pub unresolved: usize,
+    /// Number of lookups that failed with a database error
+    pub errors: usize,
+}
+
+impl RunSummary {
+    /// Record a lookup that returned no result
+    pub fn record_unresolved(&mut self) {
+        self.unresolved += 1;
+    }
+
+    /// Record a failed lookup
+    pub fn record_error(&mut self) {
+        self.errors += 1;
+    }
+
+    /// Derive the exit status, database errors taking precedence
+    pub fn status(&self) -> ExitStatus {
+        if self.errors > 0 {
+            ExitStatus::DatabaseError
+        } else if self.unresolved > 0 {
+            ExitStatus::NotFound
+        } else {
+            ExitStatus::Success
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_summary_status() {
+        let mut summary = RunSummary::default();
+        assert_eq!(summary.status(), ExitStatus::Success);
+
+        summary.record_unresolved();
+        assert_eq!(summary.status(), ExitStatus::NotFound);
+
+        summary.record_error();
+        assert_eq!(summary.status(), ExitStatus::DatabaseError);
+        assert_eq!(summary.status().code(), 2);
+    }
+
+    #[test]
+    fn test_status_from_error() {
+        let err = NaliError::Unresolved("1.2.3.4".to_string());
+        assert_eq!(ExitStatus::from_error(&err), ExitStatus::NotFound);
+
+        let err = NaliError::DatabaseNotFound("qqwry".to_string());
+        assert_eq!(ExitStatus::from_error(&err), ExitStatus::DatabaseError);
+    }
+}
@@ -0,0 +1,119 @@
+//! Aligned table of query results, printed by `--format table`
+//!
+//! Each IP address or domain given as an argument becomes one row. Columns
+//! are padded to the display width of their widest cell, so Chinese
+//! locations, which take two columns per character, line up too.
+
+use crate::entity::types::{Entity, EntityType};
+use unicode_width::UnicodeWidthStr;
+
+/// Column headers, translated when rendered
+const HEADERS: [&str; 6] = ["IP", "COUNTRY", "REGION", "CITY", "ISP", "SOURCE"];
+
+/// Rows of a results table
+#[derive(Debug, Default)]
+pub struct QueryTable {
+    rows: Vec<[String; 6]>,
+}
+
+impl QueryTable {
+    /// Add an annotated IP or domain; plain text is skipped
+    ///
+    /// A domain shows its CDN provider as the ISP. Missing fields, and all
+    /// of them for an address without a location, are shown as `-`.
+    pub fn push(&mut self, entity: &Entity) {
+        let cell = |value: Option<&String>| value.map_or("-".to_string(), String::clone);
+        let row = match entity.entity_type {
+            EntityType::Plain => return,
+            EntityType::Domain => [
+                entity.text.clone(),
+                "-".to_string(),
+                "-".to_string(),
+                "-".to_string(),
+                cell(entity.cdn_info.as_ref().map(|cdn| &cdn.provider)),
+                "-".to_string(),
+            ],
+            EntityType::IPv4 | EntityType::IPv6 => {
+                let geo = entity.geo_info.as_ref();
+                let sources = geo.map(|geo| geo.sources.join(",")).filter(|s| !s.is_empty());
+                [
+                    entity.text.clone(),
+                    cell(geo.and_then(|geo| geo.country.as_ref())),
+                    cell(geo.and_then(|geo| geo.region.as_ref())),
+                    cell(geo.and_then(|geo| geo.city.as_ref())),
+                    cell(geo.and_then(|geo| geo.isp.as_ref())),
+                    cell(sources.as_ref()),
+                ]
+            }
+        };
+        self.rows.push(row);
+    }
+
+    /// Render the header and rows, one line each
+    pub fn render(&self) -> String {
+        let headers = HEADERS.map(crate::i18n::tr);
+        let mut widths = headers.map(UnicodeWidthStr::width);
+        for row in &self.rows {
+            for (width, cell) in widths.iter_mut().zip(row) {
+                *width = (*width).max(cell.width());
+            }
+        }
+
+        let render_row = |cells: [&str; 6]| {
+            let mut line = String::new();
+            for (cell, width) in cells.iter().zip(widths) {
+                line.push_str(cell);
+                line.extend(std::iter::repeat_n(' ', width - cell.width() + 2));
+            }
+            line.truncate(line.trim_end().len());
+            line.push('\n');
+            line
+        };
+
+        let mut out = render_row(headers);
+        for row in &self.rows {
+            out.push_str(&render_row(row.each_ref().map(String::as_str)));
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::{CdnProvider, GeoLocation};
+
+    #[test]
+    fn test_render() {
+        let mut table = QueryTable::default();
+
+        let mut ip = Entity::ipv4(0, 7, "1.2.3.4".to_string());
+        ip.geo_info = Some(GeoLocation {
+            ip: "1.2.3.4".parse().unwrap(),
+            country: Some("中国".to_string()),
+            region: Some("北京".to_string()),
+            isp: Some("电信".to_string()),
+            country_code: Some("CN".to_string()),
+            sources: vec!["qqwry".to_string(), "geoip2".to_string()],
+            ..Default::default()
+        });
+        table.push(&ip);
+        table.push(&Entity::ipv6(0, 11, "2001:db8::1".to_string()));
+        let mut domain = Entity::domain(0, 16, "cdn.jsdelivr.net".to_string());
+        domain.cdn_info = Some(CdnProvider {
+            domain: "cdn.jsdelivr.net".to_string(),
+            provider: "jsDelivr".to_string(),
+            description: None,
+        });
+        table.push(&domain);
+        table.push(&Entity::plain(0, 1, " ".to_string()));
+
+        assert_eq!(
+            table.render(),
+            "IP                COUNTRY  REGION  CITY  ISP       SOURCE\n\
+             1.2.3.4           中国     北京    -     电信      qqwry,geoip2\n\
+             2001:db8::1       -        -       -     -         -\n\
+             cdn.jsdelivr.net  -        -       -     jsDelivr  -\n"
+        );
+    }
+}
@@ -0,0 +1,225 @@
+//! `nali-rs trace` subcommand
+//!
+//! Traces the route to a host and prints each hop annotated with its
+//! geolocation as soon as it is known. IPv4 targets are probed in-process
+//! over a raw ICMP socket; without the privilege for that, or for IPv6
+//! targets, the system traceroute command is run and its output annotated
+//! line by line.
+
+use crate::config::AppConfig;
+use crate::database::DatabaseManager;
+use crate::entity::{EntityType, formatter, parser};
+use crate::error::{NaliError, Result};
+use crate::trace::{Hop, Prober, TraceOptions, command};
+use clap::Args;
+use std::io::{self, Write};
+use std::net::{IpAddr, Ipv4Addr};
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, BufReader};
+use tokio::sync::mpsc;
+
+use super::status::ExitStatus;
+
+/// Arguments for `nali-rs trace`
+#[derive(Args, Debug)]
+pub struct TraceArgs {
+    /// Host name or IP address to trace
+    #[arg(value_name = "HOST")]
+    pub host: String,
+
+    /// Maximum number of hops
+    #[arg(short, long, default_value_t = 30)]
+    pub max_hops: u8,
+
+    /// Probes per hop
+    #[arg(short, long, default_value_t = 3)]
+    pub queries: usize,
+
+    /// Seconds to wait for each probe
+    #[arg(short, long, default_value_t = 3)]
+    pub wait: u64,
+
+    /// Always use the system traceroute command
+    #[arg(long)]
+    pub system: bool,
+}
+
+/// Resolve the target, preferring IPv4 like traceroute does
+async fn resolve(host: &str) -> Result<IpAddr> {
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+    if let Ok(ip) = host.parse::<IpAddr>() {
+        return Ok(ip);
+    }
+
+    let addrs: Vec<IpAddr> = tokio::net::lookup_host((host, 0))
+        .await?
+        .map(|addr| addr.ip())
+        .collect();
+    addrs
+        .iter()
+        .find(|ip| ip.is_ipv4())
+        .or_else(|| addrs.first())
+        .copied()
+        .ok_or_else(|| NaliError::network(format!("Cannot resolve host: {}", host)))
+}
+
+/// Geolocation annotation for a hop address
+async fn annotate(ip: IpAddr, db_manager: &DatabaseManager) -> String {
+    match db_manager.query_ip(ip).await {
+        Ok(Some(geo)) => formatter::format_geo_info_compact(&geo),
+        Ok(None) => String::new(),
+        Err(e) => {
+            tracing::warn!("Lookup for {} failed: {}", ip, e);
+            String::new()
+        }
+    }
+}
+
+/// Format one hop as ` 3  10.0.0.1  1.234 ms  1.101 ms  *  [geo]`
+async fn format_hop(hop: &Hop, db_manager: &DatabaseManager) -> String {
+    let mut line = format!("{:>2}", hop.ttl);
+    for (addr, rtts) in hop.responders() {
+        line.push_str(&format!("  {}", addr));
+        for rtt in rtts {
+            line.push_str(&format!("  {:.3} ms", rtt.as_secs_f64() * 1000.0));
+        }
+        let geo = annotate(addr, db_manager).await;
+        if !geo.is_empty() {
+            line.push_str(&format!("  [{}]", geo));
+        }
+    }
+    for _ in hop.probes.iter().filter(|probe| probe.is_none()) {
+        line.push_str("  *");
+    }
+    line
+}
+
+/// Print a line and flush so hops appear as they are measured
+fn print_flush(line: &str) -> Result<()> {
+    let mut stdout = io::stdout().lock();
+    writeln!(stdout, "{}", line)?;
+    stdout.flush()?;
+    Ok(())
+}
+
+/// Trace with the raw socket prober
+///
+/// Returns `Ok(None)` if opening the socket was not permitted.
+async fn trace_raw(
+    target: Ipv4Addr,
+    options: TraceOptions,
+    db_manager: &DatabaseManager,
+) -> Result<Option<bool>> {
+    let prober = match Prober::new(target, options) {
+        Ok(prober) => prober,
+        Err(e) if e.kind() == io::ErrorKind::PermissionDenied => {
+            tracing::info!("Raw sockets not permitted, using system traceroute: {}", e);
+            return Ok(None);
+        }
+        Err(e) => return Err(e.into()),
+    };
+
+    // Probing blocks, so it runs on its own thread and streams hops back
+    let (tx, mut rx) = mpsc::unbounded_channel();
+    let probing = tokio::task::spawn_blocking(move || prober.run(|hop| tx.send(hop).is_ok()));
+
+    let mut reached = false;
+    while let Some(hop) = rx.recv().await {
+        reached |= hop.reached;
+        print_flush(&format_hop(&hop, db_manager).await)?;
+    }
+
+    probing
+        .await
+        .map_err(|e| NaliError::Other(format!("Trace task failed: {}", e)))??;
+    Ok(Some(reached))
+}
+
+/// Trace with the system command, annotating each IP in its output
+async fn trace_system(
+    host: &str,
+    target: IpAddr,
+    options: &TraceOptions,
+    db_manager: &DatabaseManager,
+) -> Result<bool> {
+    let mut command =
+        command::system_command(host, target.is_ipv6(), options).ok_or_else(|| {
+            NaliError::config(
+                "No traceroute command found; install traceroute or run as root for raw sockets",
+            )
+        })?;
+    let mut child = command.spawn()?;
+    let stdout = child
+        .stdout
+        .take()
+        .ok_or_else(|| NaliError::Other("Traceroute output not captured".to_string()))?;
+
+    let mut lines = BufReader::new(stdout).lines();
+    while let Some(line) = lines.next_line().await? {
+        let mut entities = parser::parse_line(&line);
+        for entity in &mut entities.entities {
+            if matches!(entity.entity_type, EntityType::IPv4 | EntityType::IPv6)
+                && let Some(ip) = entity.as_ip()
+            {
+                entity.geo_info = db_manager.query_ip(ip).await.unwrap_or_else(|e| {
+                    tracing::warn!("Lookup for {} failed: {}", ip, e);
+                    None
+                });
+            }
+        }
+        let complete = parser::build_complete_entities(&line, entities);
+        print_flush(&formatter::format_text(&complete, false, false, &Default::default()))?;
+    }
+
+    Ok(child.wait().await?.success())
+}
+
+/// Run the trace subcommand
+pub async fn run(args: &TraceArgs, config: &AppConfig) -> Result<ExitStatus> {
+    let target = resolve(&args.host).await?;
+    let options = TraceOptions {
+        max_hops: args.max_hops,
+        queries: args.queries.max(1),
+        wait: Duration::from_secs(args.wait.max(1)),
+    };
+    let db_manager = DatabaseManager::new(config.clone());
+
+    if !config.global.quiet {
+        eprintln!(
+            "traceroute to {} ({}), {} hops max",
+            args.host, target, options.max_hops
+        );
+    }
+
+    let raw = match target {
+        IpAddr::V4(v4) if !args.system => trace_raw(v4, options.clone(), &db_manager).await?,
+        _ => None,
+    };
+    let reached = match raw {
+        Some(reached) => reached,
+        None => trace_system(&args.host, target, &options, &db_manager).await?,
+    };
+
+    Ok(if reached {
+        ExitStatus::Success
+    } else {
+        ExitStatus::NotFound
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_resolve_literal() {
+        assert_eq!(
+            resolve("192.0.2.1").await.unwrap(),
+            "192.0.2.1".parse::<IpAddr>().unwrap()
+        );
+        assert_eq!(
+            resolve("[2001:db8::1]").await.unwrap(),
+            "2001:db8::1".parse::<IpAddr>().unwrap()
+        );
+    }
+}
@@ -0,0 +1,181 @@
+//! ASN database
+//!
+//! Maps IP ranges to the autonomous system announcing them, read from the
+//! iptoasn.com TSV dump (`ip2asn-combined.tsv`: first address, last address,
+//! AS number, country and AS description per line). Unlike the geolocation
+//! databases it doesn't annotate queries; `nali-rs asn` looks up the AS of
+//! an address and, the other way round, every prefix an AS announces.
+
+use crate::error::{NaliError, Result};
+use ipnetwork::IpNetwork;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::path::Path;
+
+/// Name of the ASN database in the configuration
+pub const DATABASE: &str = "asn";
+
+/// A range of addresses announced by one AS
+#[derive(Debug, Clone, PartialEq)]
+pub struct AsnRange {
+    pub start: IpAddr,
+    pub end: IpAddr,
+    pub asn: u32,
+    /// ISO country code of the AS, when known
+    pub country: Option<String>,
+    /// Description of the AS, usually the organization
+    pub org: String,
+}
+
+impl AsnRange {
+    /// The range as the fewest CIDR prefixes covering exactly it
+    pub fn prefixes(&self) -> Vec<IpNetwork> {
+        let (start, end, bits) = match (self.start, self.end) {
+            (IpAddr::V4(start), IpAddr::V4(end)) => (u128::from(u32::from(start)), u128::from(u32::from(end)), 32),
+            (IpAddr::V6(start), IpAddr::V6(end)) => (u128::from(start), u128::from(end), 128),
+            _ => return Vec::new(),
+        };
+        range_prefixes(start, end, bits)
+            .into_iter()
+            .filter_map(|(network, prefix)| {
+                let network = match bits {
+                    32 => IpAddr::V4(Ipv4Addr::from(network as u32)),
+                    _ => IpAddr::V6(Ipv6Addr::from(network)),
+                };
+                IpNetwork::new(network, prefix).ok()
+            })
+            .collect()
+    }
+}
+
+/// Split `start..=end` of a `bits` wide address space into aligned blocks,
+/// as network and prefix length
+fn range_prefixes(mut start: u128, end: u128, bits: u32) -> Vec<(u128, u8)> {
+    let mut prefixes = Vec::new();
+    while start <= end {
+        // Largest block starting at `start` that ends at `end` or before
+        let mut size = start.trailing_zeros().min(bits);
+        let last = |size: u32| start | u128::MAX.checked_shr(128 - size).unwrap_or(0);
+        while last(size) > end {
+            size -= 1;
+        }
+        prefixes.push((start, (bits - size) as u8));
+        match last(size).checked_add(1) {
+            Some(next) => start = next,
+            None => break,
+        }
+    }
+    prefixes
+}
+
+/// Address ranges and their AS, sorted by first address
+#[derive(Debug, Default)]
+pub struct AsnDatabase {
+    ranges: Vec<AsnRange>,
+}
+
+impl AsnDatabase {
+    /// Parse the TSV dump; ranges of AS 0 (not routed) are left out
+    pub fn parse(reader: impl BufRead) -> Result<Self> {
+        let mut ranges = Vec::new();
+        for (line_no, line) in reader.lines().enumerate() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let invalid = || NaliError::parse(format!("Invalid ASN data on line {}: {}", line_no + 1, line));
+            let fields: Vec<&str> = line.split('\t').collect();
+            let [start, end, asn, country, org] = fields[..] else {
+                return Err(invalid());
+            };
+            let range = AsnRange {
+                start: start.parse().map_err(|_| invalid())?,
+                end: end.parse().map_err(|_| invalid())?,
+                asn: asn.parse().map_err(|_| invalid())?,
+                country: Some(country.to_string()).filter(|c| !c.is_empty() && c != "None"),
+                org: org.to_string(),
+            };
+            if range.asn != 0 {
+                ranges.push(range);
+            }
+        }
+        ranges.sort_by_key(|range| range.start);
+        Ok(Self { ranges })
+    }
+
+    /// Read the TSV dump at `path`
+    pub fn load(path: &Path) -> Result<Self> {
+        Self::parse(BufReader::new(File::open(path)?))
+    }
+
+    /// The range containing `ip`
+    pub fn lookup(&self, ip: IpAddr) -> Option<&AsnRange> {
+        let index = self.ranges.partition_point(|range| range.start <= ip);
+        let range = self.ranges.get(index.checked_sub(1)?)?;
+        // Addresses of the other family order before or after all of them
+        (range.end >= ip && range.start.is_ipv4() == ip.is_ipv4()).then_some(range)
+    }
+
+    /// Every range announced by `asn`, IPv4 first
+    pub fn ranges_of(&self, asn: u32) -> impl Iterator<Item = &AsnRange> {
+        self.ranges.iter().filter(move |range| range.asn == asn)
+    }
+
+    /// Number of ranges
+    pub fn len(&self) -> usize {
+        self.ranges.len()
+    }
+
+    /// Check whether there are no ranges
+    pub fn is_empty(&self) -> bool {
+        self.ranges.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DUMP: &str = "1.0.0.0\t1.0.0.255\t13335\tUS\tCLOUDFLARENET\n\
+                        1.0.1.0\t1.0.3.255\t0\tNone\tNot routed\n\
+                        1.1.1.0\t1.1.1.255\t13335\tUS\tCLOUDFLARENET\n\
+                        8.8.8.0\t8.8.8.255\t15169\tUS\tGOOGLE\n\
+                        2606:4700::\t2606:4700:ffff:ffff:ffff:ffff:ffff:ffff\t13335\tUS\tCLOUDFLARENET\n";
+
+    #[test]
+    fn test_lookup() {
+        let db = AsnDatabase::parse(DUMP.as_bytes()).unwrap();
+        assert_eq!(db.len(), 4);
+        let range = db.lookup("1.1.1.1".parse().unwrap()).unwrap();
+        assert_eq!(range.asn, 13335);
+        assert_eq!(range.org, "CLOUDFLARENET");
+        assert_eq!(range.country.as_deref(), Some("US"));
+        assert_eq!(db.lookup("2606:4700::1111".parse().unwrap()).unwrap().asn, 13335);
+        // Not routed, and past the last IPv4 range
+        assert!(db.lookup("1.0.2.1".parse().unwrap()).is_none());
+        assert!(db.lookup("9.9.9.9".parse().unwrap()).is_none());
+        assert!(db.lookup("::1".parse().unwrap()).is_none());
+
+        let prefixes: Vec<String> = db
+            .ranges_of(13335)
+            .flat_map(AsnRange::prefixes)
+            .map(|prefix| prefix.to_string())
+            .collect();
+        assert_eq!(prefixes, ["1.0.0.0/24", "1.1.1.0/24", "2606:4700::/32"]);
+        assert_eq!(db.ranges_of(64512).count(), 0);
+    }
+
+    #[test]
+    fn test_prefixes_of_unaligned_range() {
+        assert_eq!(range_prefixes(1, 6, 32), [(1, 32), (2, 31), (4, 31), (6, 32)]);
+        assert_eq!(range_prefixes(0, u128::from(u32::MAX), 32), [(0, 0)]);
+        assert_eq!(range_prefixes(0, u128::MAX, 128), [(0, 0)]);
+    }
+
+    #[test]
+    fn test_invalid_line() {
+        let error = AsnDatabase::parse("1.0.0.0\t1.0.0.255\tAS13335\n".as_bytes()).unwrap_err();
+        assert!(error.to_string().contains("line 1"), "{}", error);
+    }
+}
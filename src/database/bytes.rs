@@ -0,0 +1,99 @@
+//! Contents of a loaded database file
+//!
+//! Files opened from disk are memory-mapped with the `mmap` feature, unless
+//! `database.load_mode` is `memory`. Databases handed over as a byte buffer,
+//! as in a browser, own it.
+
+use crate::config::LoadMode;
+use crate::error::{NaliError, Result};
+use std::ops::Deref;
+
+/// Bytes of a database, mapped from a file or held in memory
+pub enum DatabaseBytes {
+    /// A memory-mapped file
+    #[cfg(feature = "mmap")]
+    Mapped(memmap2::Mmap),
+    /// A buffer owned by the database
+    Owned(Vec<u8>),
+}
+
+impl DatabaseBytes {
+    /// Map the file at `path`, or read it whole in [`LoadMode::Memory`] or
+    /// without the `mmap` feature
+    ///
+    /// `kind` names the format in errors, e.g. `QQwry`.
+    pub fn open(path: &str, kind: &str, mode: LoadMode) -> Result<Self> {
+        #[cfg(feature = "mmap")]
+        if mode == LoadMode::Mmap {
+            let file = std::fs::File::open(path).map_err(NaliError::IoError)?;
+            let mmap = unsafe { memmap2::Mmap::map(&file) }.map_err(|e| {
+                io_error(e, format!("Failed to memory map {} database", kind))
+            })?;
+            return Ok(DatabaseBytes::Mapped(mmap));
+        }
+        #[cfg(not(feature = "mmap"))]
+        let _ = mode;
+
+        let data = std::fs::read(path)
+            .map_err(|e| io_error(e, format!("Failed to read {} database", kind)))?;
+        Ok(DatabaseBytes::Owned(data))
+    }
+
+    /// Whether the bytes are a mapping of the file rather than a copy
+    pub fn is_mapped(&self) -> bool {
+        match self {
+            #[cfg(feature = "mmap")]
+            DatabaseBytes::Mapped(_) => true,
+            DatabaseBytes::Owned(_) => false,
+        }
+    }
+}
+
+impl Deref for DatabaseBytes {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match self {
+            #[cfg(feature = "mmap")]
+            DatabaseBytes::Mapped(mmap) => mmap,
+            DatabaseBytes::Owned(data) => data,
+        }
+    }
+}
+
+impl From<Vec<u8>> for DatabaseBytes {
+    fn from(data: Vec<u8>) -> Self {
+        DatabaseBytes::Owned(data)
+    }
+}
+
+/// Keep the kind of an I/O error while naming what was being done
+fn io_error(e: std::io::Error, context: String) -> NaliError {
+    NaliError::IoError(std::io::Error::new(e.kind(), format!("{}: {}", context, e)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_open_modes() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("test.dat");
+        std::fs::write(&path, b"database").unwrap();
+        let path = path.to_str().unwrap();
+
+        let mapped = DatabaseBytes::open(path, "test", LoadMode::Mmap).unwrap();
+        assert_eq!(&*mapped, b"database");
+        assert_eq!(mapped.is_mapped(), cfg!(feature = "mmap"));
+
+        let read = DatabaseBytes::open(path, "test", LoadMode::Memory).unwrap();
+        assert_eq!(&*read, b"database");
+        assert!(!read.is_mapped());
+
+        let missing = dir.path().join("missing.dat");
+        let err = DatabaseBytes::open(missing.to_str().unwrap(), "test", LoadMode::Memory).err().unwrap();
+        assert!(matches!(err, NaliError::IoError(_)));
+        assert!(!err.is_corruption());
+    }
+}
@@ -1,81 +1,193 @@
 //! CDN database implementation core
 
-use crate::database::{CdnProvider, Database, DatabaseType, GeoLocation};
+use crate::database::{CdnMatch, CdnMatchKind, CdnProvider, Database, DatabaseType, GeoLocation};
 use crate::error::{NaliError, Result};
-use regex::Regex;
-use std::collections::HashMap;
+use crate::utils::{idn, path};
+use regex::{Regex, RegexSet};
+use std::collections::BTreeMap;
 use std::fs;
 use std::net::IpAddr;
+use std::path::Path;
 
 use super::entry::CdnEntry;
-use super::matcher::{extract_base_domain, match_regex, wildcard_to_regex};
-
-/// CDN database structure
-pub struct CDNDatabase {
-    name: String,
-    loaded: bool,
-    /// Exact domain matches (domain -> CdnEntry)
-    exact_matches: HashMap<String, CdnEntry>,
-    /// Regex pattern matches (pattern -> CdnEntry)
-    regex_matches: Vec<(Regex, CdnEntry)>,
+use super::matcher::{DomainTrie, subdomain_wildcard, wildcard_to_regex};
+
+/// File in the config directory with user-defined CDN rules
+///
+/// Its entries take precedence over the downloaded database and, living
+/// outside the data directory, survive `update`.
+pub const LOCAL_RULES_FILE: &str = "cdn.local.yml";
+
+/// Domain rules parsed from one CDN YAML file
+///
+/// Exact domains and `*.suffix` wildcards go into a suffix trie; only the
+/// remaining patterns are regexes, matched together as one `RegexSet`.
+struct CdnRules {
+    trie: DomainTrie,
+    regexes: RegexSet,
+    /// Entry for each pattern of `regexes`, in the same order
+    regex_entries: Vec<CdnEntry>,
+    /// Each pattern of `regexes` as written, and whether it was a wildcard
+    regex_sources: Vec<(String, bool)>,
 }
 
-impl CDNDatabase {
-    pub fn new() -> Self {
+impl Default for CdnRules {
+    fn default() -> Self {
         Self {
-            name: "cdn".to_string(),
-            loaded: false,
-            exact_matches: HashMap::new(),
-            regex_matches: Vec::new(),
+            trie: DomainTrie::default(),
+            regexes: RegexSet::empty(),
+            regex_entries: Vec::new(),
+            regex_sources: Vec::new(),
         }
     }
+}
 
-    /// Parse YAML CDN database file
-    fn parse_yaml(&mut self, content: &str) -> Result<()> {
-        // Parse YAML as HashMap
-        let data: HashMap<String, CdnEntry> = serde_yaml::from_str(content)
-            .map_err(|e| NaliError::YamlError(format!("Failed to parse CDN database: {}", e)))?;
+/// Lowercased ASCII form of a domain, so `中国.cn` matches rules for
+/// `xn--fiqs8s.cn` and the other way round
+fn ascii_domain(domain: &str) -> String {
+    if domain.is_ascii() {
+        return domain.to_lowercase();
+    }
+    idn::to_ascii(domain).unwrap_or_else(|| domain.to_lowercase())
+}
 
+impl CdnRules {
+    /// Parse YAML CDN rules; `source` names the file in errors
+    fn parse(content: &str, source: &str) -> Result<Self> {
+        // Sorted, so the first matching regex doesn't depend on hash order
+        let data: BTreeMap<String, CdnEntry> = serde_yaml::from_str(content)
+            .map_err(|e| NaliError::YamlError(format!("Failed to parse {}: {}", source, e)))?;
+
+        let mut trie = DomainTrie::default();
+        let mut patterns = Vec::new();
+        let mut regex_entries = Vec::new();
+        let mut regex_sources = Vec::new();
         for (pattern, entry) in data {
+            let wildcard = pattern.contains('*') || pattern.contains('?');
             // Check if pattern is a wildcard or regex
-            if pattern.contains('*') || pattern.contains('?') {
+            let regex_pattern = if let Some(suffix) = subdomain_wildcard(&pattern) {
+                trie.insert_subdomains(&ascii_domain(suffix), entry);
+                tracing::debug!("Added CDN subdomain wildcard: {}", pattern);
+                continue;
+            } else if wildcard {
                 // Convert wildcard to regex
-                let regex_pattern = wildcard_to_regex(&pattern);
-                match Regex::new(&regex_pattern) {
-                    Ok(regex) => {
-                        self.regex_matches.push((regex, entry));
-                        log::debug!(
-                            "Added CDN wildcard pattern: {} -> {}",
-                            pattern,
-                            regex_pattern
-                        );
-                    }
-                    Err(e) => {
-                        log::warn!("Invalid CDN wildcard pattern '{}': {}", pattern, e);
-                    }
-                }
+                wildcard_to_regex(&pattern)
             } else if pattern.contains('[')
                 || pattern.contains('+')
                 || pattern.contains('(')
                 || pattern.contains('{')
             {
                 // Treat as regex pattern directly
-                match Regex::new(&pattern) {
-                    Ok(regex) => {
-                        self.regex_matches.push((regex, entry));
-                        log::debug!("Added CDN regex pattern: {}", pattern);
-                    }
-                    Err(e) => {
-                        log::warn!("Invalid CDN regex pattern '{}': {}", pattern, e);
-                    }
-                }
+                pattern.clone()
             } else {
-                // Treat as exact match
-                self.exact_matches.insert(pattern.to_lowercase(), entry);
-                log::debug!("Added CDN exact match: {}", pattern);
+                // Treat as a domain, matching its subdomains too
+                trie.insert(&ascii_domain(&pattern), entry);
+                tracing::debug!("Added CDN domain: {}", pattern);
+                continue;
+            };
+
+            // One invalid pattern would make the whole set fail to build
+            match Regex::new(&regex_pattern) {
+                Ok(_) => {
+                    tracing::debug!("Added CDN regex pattern: {} -> {}", pattern, regex_pattern);
+                    patterns.push(regex_pattern);
+                    regex_entries.push(entry);
+                    regex_sources.push((pattern, wildcard));
+                }
+                Err(e) => {
+                    tracing::warn!("Invalid CDN pattern '{}': {}", pattern, e);
+                }
             }
         }
 
+        let regexes = RegexSet::new(&patterns).map_err(|e| {
+            NaliError::parse(format!("Failed to compile {} patterns: {}", source, e))
+        })?;
+
+        Ok(Self {
+            trie,
+            regexes,
+            regex_entries,
+            regex_sources,
+        })
+    }
+
+    /// Find the entry for a lowercased domain
+    fn lookup(&self, domain_lower: &str) -> Option<&CdnEntry> {
+        self.trie.lookup(domain_lower).or_else(|| {
+            // Patterns were read sorted, so the lowest index is the
+            // lexicographically first one, whatever the file order
+            self.regexes
+                .matches(domain_lower)
+                .iter()
+                .next()
+                .map(|idx| &self.regex_entries[idx])
+        })
+    }
+
+    /// Describe the rule `lookup` picks for a lowercased domain
+    fn explain(&self, domain_lower: &str, local: bool) -> Option<CdnMatch> {
+        if let Some(found) = self.trie.lookup_match(domain_lower) {
+            let (kind, pattern) = if found.wildcard {
+                (CdnMatchKind::Wildcard, format!("*.{}", found.suffix))
+            } else if found.suffix == domain_lower.trim_end_matches('.') {
+                (CdnMatchKind::Exact, found.suffix.to_string())
+            } else {
+                (CdnMatchKind::Suffix, found.suffix.to_string())
+            };
+            return Some(CdnMatch { kind, pattern, local });
+        }
+
+        let idx = self.regexes.matches(domain_lower).iter().next()?;
+        let (pattern, wildcard) = &self.regex_sources[idx];
+        Some(CdnMatch {
+            kind: if *wildcard { CdnMatchKind::Wildcard } else { CdnMatchKind::Regex },
+            pattern: pattern.clone(),
+            local,
+        })
+    }
+}
+
+/// CDN database structure
+pub struct CDNDatabase {
+    name: String,
+    loaded: bool,
+    /// Rules from the downloaded database
+    rules: CdnRules,
+    /// User-defined rules from `LOCAL_RULES_FILE`, consulted first
+    local: CdnRules,
+}
+
+impl CDNDatabase {
+    pub fn new() -> Self {
+        Self {
+            name: "cdn".to_string(),
+            loaded: false,
+            rules: CdnRules::default(),
+            local: CdnRules::default(),
+        }
+    }
+
+    /// Parse YAML CDN database file
+    fn parse_yaml(&mut self, content: &str) -> Result<()> {
+        self.rules = CdnRules::parse(content, "CDN database")?;
+        Ok(())
+    }
+
+    /// Load user-defined rules, if the file exists
+    fn load_local_rules(&mut self, path: &Path) -> Result<()> {
+        if !path.is_file() {
+            return Ok(());
+        }
+
+        let content = fs::read_to_string(path).map_err(NaliError::IoError)?;
+        self.local = CdnRules::parse(&content, &path.display().to_string())?;
+        tracing::info!(
+            "Loaded local CDN rules from {:?}: {} domain, {} regex patterns",
+            path,
+            self.local.trie.len(),
+            self.local.regex_entries.len()
+        );
         Ok(())
     }
 }
@@ -111,40 +223,29 @@ impl Database for CDNDatabase {
             return Err(NaliError::DatabaseNotLoaded("cdn".to_string()));
         }
 
-        let domain_lower = domain.to_lowercase();
+        let domain_lower = ascii_domain(domain);
 
-        // Try exact match first
-        if let Some(entry) = self.exact_matches.get(&domain_lower) {
-            return Ok(Some(CdnProvider {
+        Ok(self
+            .local
+            .lookup(&domain_lower)
+            .or_else(|| self.rules.lookup(&domain_lower))
+            .map(|entry| CdnProvider {
                 domain: domain.to_string(),
                 provider: entry.name.clone(),
                 description: entry.link.clone(),
-            }));
-        }
-
-        // Try base domain matches
-        let candidates = extract_base_domain(&domain_lower);
-        for candidate in &candidates {
-            if let Some(entry) = self.exact_matches.get(candidate) {
-                return Ok(Some(CdnProvider {
-                    domain: domain.to_string(),
-                    provider: entry.name.clone(),
-                    description: entry.link.clone(),
-                }));
-            }
-        }
+            }))
+    }
 
-        // Try regex matches
-        if let Some(entry) = match_regex(&domain_lower, &self.regex_matches) {
-            return Ok(Some(CdnProvider {
-                domain: domain.to_string(),
-                provider: entry.name.clone(),
-                description: entry.link.clone(),
-            }));
+    fn explain_cdn(&self, domain: &str) -> Result<Option<CdnMatch>> {
+        if !self.loaded {
+            return Err(NaliError::DatabaseNotLoaded("cdn".to_string()));
         }
 
-        // Not found
-        Ok(None)
+        let domain_lower = ascii_domain(domain);
+        Ok(self
+            .local
+            .explain(&domain_lower, true)
+            .or_else(|| self.rules.explain(&domain_lower, false)))
     }
 
     fn is_loaded(&self) -> bool {
@@ -152,21 +253,36 @@ impl Database for CDNDatabase {
     }
 
     fn load_from_file(&mut self, file_path: &str) -> Result<()> {
-        log::info!("Loading CDN database from: {}", file_path);
+        tracing::info!("Loading CDN database from: {}", file_path);
 
         let content = fs::read_to_string(file_path).map_err(NaliError::IoError)?;
 
         self.parse_yaml(&content)?;
+        self.load_local_rules(&path::config_dir()?.join(LOCAL_RULES_FILE))?;
 
         self.loaded = true;
-        log::info!(
-            "Successfully loaded CDN database: {} exact, {} regex patterns",
-            self.exact_matches.len(),
-            self.regex_matches.len()
+        tracing::info!(
+            "Successfully loaded CDN database: {} domain, {} regex patterns",
+            self.rules.trie.len(),
+            self.rules.regex_entries.len()
         );
 
         Ok(())
     }
+
+    /// Load CDN rules from YAML in memory
+    ///
+    /// User-defined rules in `cdn.local.yml` are not read.
+    fn load_from_bytes(&mut self, data: Vec<u8>) -> Result<()> {
+        let content = String::from_utf8(data)
+            .map_err(|e| NaliError::format(format!("CDN database is not UTF-8: {}", e)))?;
+
+        self.parse_yaml(&content)?;
+        self.local = CdnRules::default();
+        self.loaded = true;
+
+        Ok(())
+    }
 }
 
 impl Default for CDNDatabase {
@@ -189,7 +305,7 @@ cloudflare.com:
 
         let mut db = CDNDatabase::new();
         db.parse_yaml(yaml).unwrap();
-        assert_eq!(db.exact_matches.len(), 1);
+        assert_eq!(db.rules.trie.len(), 1);
     }
 
     #[test]
@@ -224,6 +340,28 @@ example.com:
         assert_eq!(result.unwrap().provider, "Example CDN");
     }
 
+    #[test]
+    fn test_lookup_idn() {
+        let yaml = r#"
+xn--fiqs8s.cn:
+  name: Punycode CDN
+"*.例子.com":
+  name: Unicode CDN
+"#;
+
+        let mut db = CDNDatabase::new();
+        db.parse_yaml(yaml).unwrap();
+        db.loaded = true;
+
+        let provider = |domain: &str| db.lookup_cdn(domain).unwrap().map(|cdn| cdn.provider);
+        assert_eq!(provider("static.中国.cn").as_deref(), Some("Punycode CDN"));
+        assert_eq!(provider("XN--FIQS8S.CN").as_deref(), Some("Punycode CDN"));
+        assert_eq!(provider("www.xn--fsqu00a.com").as_deref(), Some("Unicode CDN"));
+        assert_eq!(provider("www.例子.com").as_deref(), Some("Unicode CDN"));
+        // The provider names the domain as it was written
+        assert_eq!(db.lookup_cdn("中国.cn").unwrap().unwrap().domain, "中国.cn");
+    }
+
     #[test]
     fn test_lookup_regex() {
         let yaml = r#"
@@ -254,4 +392,142 @@ cloudflare.com:
         let result = db.lookup_cdn("unknown.com").unwrap();
         assert!(result.is_none());
     }
+
+    #[test]
+    fn test_lookup_most_specific_rule() {
+        let yaml = r#"
+amazonaws.com:
+  name: AWS
+s3.amazonaws.com:
+  name: Amazon S3
+"*.cloudfront.net":
+  name: CloudFront
+"img*.example.net":
+  name: Image CDN
+"^static[0-9]+\\.example\\.net$":
+  name: Static CDN
+"#;
+
+        let mut db = CDNDatabase::new();
+        db.parse_yaml(yaml).unwrap();
+        db.loaded = true;
+
+        let provider = |domain: &str| db.lookup_cdn(domain).unwrap().map(|cdn| cdn.provider);
+        assert_eq!(
+            provider("bucket.s3.amazonaws.com").as_deref(),
+            Some("Amazon S3")
+        );
+        assert_eq!(provider("ec2.amazonaws.com").as_deref(), Some("AWS"));
+        assert_eq!(provider("d1.cloudfront.net").as_deref(), Some("CloudFront"));
+        assert_eq!(provider("cloudfront.net"), None);
+        assert_eq!(provider("IMG3.example.net").as_deref(), Some("Image CDN"));
+        assert_eq!(
+            provider("static12.example.net").as_deref(),
+            Some("Static CDN")
+        );
+        assert_eq!(provider("www.example.net"), None);
+    }
+
+    #[test]
+    fn test_overlapping_patterns_sorted() {
+        // Both match; the sorted first wins though it comes last in the file
+        let yaml = r#"
+"img*.example.net":
+  name: Image CDN
+"[a-z]+[0-9]\\.example\\.net":
+  name: Numbered CDN
+"#;
+
+        let mut db = CDNDatabase::new();
+        db.parse_yaml(yaml).unwrap();
+        db.loaded = true;
+
+        let result = db.lookup_cdn("img3.example.net").unwrap().unwrap();
+        assert_eq!(result.provider, "Numbered CDN");
+    }
+
+    #[test]
+    fn test_explain_cdn() {
+        let yaml = r#"
+amazonaws.com:
+  name: AWS
+"*.cloudfront.net":
+  name: CloudFront
+"img*.example.net":
+  name: Image CDN
+"^static[0-9]+\\.example\\.net$":
+  name: Static CDN
+"#;
+
+        let mut db = CDNDatabase::new();
+        db.parse_yaml(yaml).unwrap();
+        db.loaded = true;
+
+        let explain = |domain: &str| {
+            db.explain_cdn(domain)
+                .unwrap()
+                .map(|rule| (rule.kind, rule.pattern, rule.local))
+        };
+        let rule = |kind, pattern: &str| Some((kind, pattern.to_string(), false));
+        assert_eq!(explain("amazonaws.com"), rule(CdnMatchKind::Exact, "amazonaws.com"));
+        assert_eq!(explain("S3.amazonaws.com"), rule(CdnMatchKind::Suffix, "amazonaws.com"));
+        assert_eq!(explain("d1.cloudfront.net"), rule(CdnMatchKind::Wildcard, "*.cloudfront.net"));
+        assert_eq!(explain("img3.example.net"), rule(CdnMatchKind::Wildcard, "img*.example.net"));
+        assert_eq!(
+            explain("static12.example.net"),
+            rule(CdnMatchKind::Regex, "^static[0-9]+\\.example\\.net$")
+        );
+        assert_eq!(explain("www.example.net"), None);
+    }
+
+    #[test]
+    fn test_local_rules_take_precedence() {
+        let dir = tempfile::tempdir().unwrap();
+        let local = dir.path().join(LOCAL_RULES_FILE);
+        std::fs::write(
+            &local,
+            r#"
+"*.edge.example.com":
+  name: Corp Edge
+intranet.corp:
+  name: Corp CDN
+"#,
+        )
+        .unwrap();
+
+        let mut db = CDNDatabase::new();
+        db.parse_yaml("example.com:\n  name: Example CDN\n")
+            .unwrap();
+        db.load_local_rules(&local).unwrap();
+        db.loaded = true;
+
+        // A local wildcard beats a downloaded base domain match
+        let edge = db.lookup_cdn("a.edge.example.com").unwrap().unwrap();
+        assert_eq!(edge.provider, "Corp Edge");
+        let other = db.lookup_cdn("www.example.com").unwrap().unwrap();
+        assert_eq!(other.provider, "Example CDN");
+        let internal = db.lookup_cdn("img.intranet.corp").unwrap().unwrap();
+        assert_eq!(internal.provider, "Corp CDN");
+
+        // A missing overlay is fine, a broken one names the file
+        db.load_local_rules(&dir.path().join("missing.yml"))
+            .unwrap();
+        std::fs::write(&local, "not: [valid").unwrap();
+        let err = db.load_local_rules(&local).unwrap_err();
+        assert!(err.to_string().contains(LOCAL_RULES_FILE));
+    }
+
+    #[test]
+    fn test_load_from_bytes() {
+        let mut db = CDNDatabase::new();
+        db.load_from_bytes(b"cloudflare.com:\n  name: Cloudflare\n".to_vec())
+            .unwrap();
+        assert!(db.is_loaded());
+
+        let cdn = db.lookup_cdn("www.cloudflare.com").unwrap().unwrap();
+        assert_eq!(cdn.provider, "Cloudflare");
+
+        let err = db.load_from_bytes(vec![0xff, 0xfe]).unwrap_err();
+        assert!(err.to_string().contains("UTF-8"));
+    }
 }
@@ -1,26 +1,7 @@
 //! Domain matching utilities for CDN database
 
-use regex::Regex;
 use super::entry::CdnEntry;
-
-/// Extract base domain from a full domain
-/// e.g., "www.example.com" -> "example.com"
-pub fn extract_base_domain(domain: &str) -> Vec<String> {
-    let mut candidates = Vec::new();
-
-    // Add the full domain
-    candidates.push(domain.to_lowercase());
-
-    // Split by dots and try different combinations
-    let parts: Vec<&str> = domain.split('.').collect();
-    if parts.len() >= 2 {
-        // example.com from www.example.com
-        let base = parts[parts.len() - 2..].join(".");
-        candidates.push(base.to_lowercase());
-    }
-
-    candidates
-}
+use std::collections::HashMap;
 
 /// Convert wildcard pattern to regex
 /// e.g., "*.example.com" -> "^.*\\.example\\.com$"
@@ -49,28 +30,117 @@ pub fn wildcard_to_regex(pattern: &str) -> String {
     result
 }
 
-/// Check if a domain matches any regex patterns
-pub fn match_regex<'a>(
-    domain: &str,
-    regex_matches: &'a [(Regex, CdnEntry)]
-) -> Option<&'a CdnEntry> {
-    for (regex, entry) in regex_matches {
-        if regex.is_match(domain) {
-            return Some(entry);
+/// Suffix of a `*.example.com` wildcard, which the trie can match directly
+///
+/// Wildcards with `*` or `?` anywhere else still need a regex.
+pub fn subdomain_wildcard(pattern: &str) -> Option<&str> {
+    pattern
+        .strip_prefix("*.")
+        .filter(|suffix| !suffix.is_empty() && !suffix.contains(['*', '?']))
+}
+
+/// Trie over reversed domain labels (`com` -> `example` -> `www`)
+///
+/// Finds the most specific rule for a domain in one walk, however many rules
+/// there are.
+#[derive(Default)]
+pub struct DomainTrie {
+    root: TrieNode,
+    len: usize,
+}
+
+/// A rule found in a [`DomainTrie`]
+pub struct TrieMatch<'a, 'd> {
+    pub entry: &'a CdnEntry,
+    /// Domain the rule was written for, a suffix of the domain looked up
+    pub suffix: &'d str,
+    /// The rule is a `*.suffix` wildcard
+    pub wildcard: bool,
+}
+
+#[derive(Default)]
+struct TrieNode {
+    children: HashMap<Box<str>, TrieNode>,
+    /// Rule for this domain and all its subdomains
+    domain: Option<CdnEntry>,
+    /// Rule for subdomains only (`*.example.com`)
+    subdomains: Option<CdnEntry>,
+}
+
+impl DomainTrie {
+    fn node_mut(&mut self, domain: &str) -> &mut TrieNode {
+        domain.rsplit('.').fold(&mut self.root, |node, label| {
+            node.children.entry(label.into()).or_default()
+        })
+    }
+
+    /// Add a rule matching `domain` and its subdomains
+    pub fn insert(&mut self, domain: &str, entry: CdnEntry) {
+        if self.node_mut(domain).domain.replace(entry).is_none() {
+            self.len += 1;
         }
     }
-    None
+
+    /// Add a rule matching only the subdomains of `suffix`
+    pub fn insert_subdomains(&mut self, suffix: &str, entry: CdnEntry) {
+        if self.node_mut(suffix).subdomains.replace(entry).is_none() {
+            self.len += 1;
+        }
+    }
+
+    /// Find the rule with the longest matching suffix
+    ///
+    /// At equal length a domain rule beats a subdomain wildcard.
+    pub fn lookup(&self, domain: &str) -> Option<&CdnEntry> {
+        self.lookup_match(domain).map(|found| found.entry)
+    }
+
+    /// Find the rule with the longest matching suffix, and which rule it is
+    pub fn lookup_match<'d>(&self, domain: &'d str) -> Option<TrieMatch<'_, 'd>> {
+        let domain = domain.trim_end_matches('.');
+        let mut labels = domain.rsplit('.').peekable();
+        let mut node = &self.root;
+        let mut best = None;
+        // Length of the matched suffix in bytes
+        let mut suffix_len = 0;
+
+        while let Some(label) = labels.next() {
+            let Some(child) = node.children.get(label) else {
+                break;
+            };
+            node = child;
+            suffix_len += label.len() + usize::from(suffix_len > 0);
+
+            let subdomains = node.subdomains.as_ref().filter(|_| labels.peek().is_some());
+            if let Some(entry) = node.domain.as_ref() {
+                best = Some((entry, suffix_len, false));
+            } else if let Some(entry) = subdomains {
+                best = Some((entry, suffix_len, true));
+            }
+        }
+
+        best.map(|(entry, len, wildcard)| TrieMatch {
+            entry,
+            suffix: &domain[domain.len() - len..],
+            wildcard,
+        })
+    }
+
+    /// Number of rules
+    pub fn len(&self) -> usize {
+        self.len
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
-    #[test]
-    fn test_extract_base_domain() {
-        let candidates = extract_base_domain("www.example.com");
-        assert!(candidates.contains(&"www.example.com".to_string()));
-        assert!(candidates.contains(&"example.com".to_string()));
+    fn entry(name: &str) -> CdnEntry {
+        CdnEntry {
+            name: name.to_string(),
+            link: None,
+        }
     }
 
     #[test]
@@ -78,4 +148,36 @@ mod tests {
         let regex = wildcard_to_regex("*.example.com");
         assert_eq!(regex, "^.*\\.example\\.com$");
     }
+
+    #[test]
+    fn test_subdomain_wildcard() {
+        assert_eq!(subdomain_wildcard("*.example.com"), Some("example.com"));
+        assert_eq!(subdomain_wildcard("cdn*.example.com"), None);
+        assert_eq!(subdomain_wildcard("*.img?.example.com"), None);
+        assert_eq!(subdomain_wildcard("*."), None);
+    }
+
+    #[test]
+    fn test_trie_longest_suffix() {
+        let mut trie = DomainTrie::default();
+        trie.insert("example.com", entry("Example"));
+        trie.insert("s3.example.com", entry("Storage"));
+        trie.insert_subdomains("edge.example.com", entry("Edge"));
+        assert_eq!(trie.len(), 3);
+
+        let name = |domain: &str| trie.lookup(domain).map(|e| e.name.as_str());
+        assert_eq!(name("example.com"), Some("Example"));
+        assert_eq!(name("www.example.com"), Some("Example"));
+        assert_eq!(name("bucket.s3.example.com"), Some("Storage"));
+        assert_eq!(name("a.edge.example.com"), Some("Edge"));
+        assert_eq!(name("edge.example.com"), Some("Example"));
+        assert_eq!(name("example.com."), Some("Example"));
+        assert_eq!(name("example.org"), None);
+
+        let found = trie.lookup_match("a.edge.example.com").unwrap();
+        assert_eq!((found.suffix, found.wildcard), ("edge.example.com", true));
+        let found = trie.lookup_match("www.example.com.").unwrap();
+        assert_eq!((found.suffix, found.wildcard), ("example.com", false));
+        assert_eq!(name("com"), None);
+    }
 }
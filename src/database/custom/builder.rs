@@ -0,0 +1,193 @@
+//! Builder converting CSV IP ranges into the nali-native format
+
+use crate::error::{NaliError, Result};
+use std::collections::HashMap;
+use std::fs;
+use std::net::IpAddr;
+use std::path::Path;
+
+use super::format::{Header, NO_STRING, VERSION};
+
+/// Location fields of one CSV row
+type Fields = [Option<String>; 4];
+
+/// Summary of a finished build
+#[derive(Debug, Clone, Default)]
+pub struct BuildStats {
+    pub v4_records: usize,
+    pub v6_records: usize,
+    pub strings: usize,
+    pub bytes: usize,
+}
+
+/// Deduplicating string table
+#[derive(Default)]
+struct StringTable {
+    data: Vec<u8>,
+    offsets: HashMap<String, u32>,
+}
+
+impl StringTable {
+    fn intern(&mut self, value: &Option<String>) -> Result<u32> {
+        let Some(value) = value else {
+            return Ok(NO_STRING);
+        };
+        if let Some(&offset) = self.offsets.get(value) {
+            return Ok(offset);
+        }
+
+        let len = u16::try_from(value.len())
+            .map_err(|_| NaliError::parse(format!("Field too long: {}", value)))?;
+        let offset = u32::try_from(self.data.len())
+            .map_err(|_| NaliError::parse("String table exceeds 4 GiB"))?;
+
+        self.data.extend_from_slice(&len.to_le_bytes());
+        self.data.extend_from_slice(value.as_bytes());
+        self.offsets.insert(value.clone(), offset);
+        Ok(offset)
+    }
+}
+
+/// Build a nali-native database from a CSV file
+///
+/// Each row is `start_ip,end_ip,country,region,city,isp`. Empty fields are
+/// stored as absent, a leading header row is skipped, and IPv4 and IPv6 rows
+/// may be mixed freely.
+pub fn build_from_csv(input: &Path, output: &Path) -> Result<BuildStats> {
+    let file = fs::File::open(input)?;
+    let (image, stats) = build_from_reader(file)?;
+
+    if let Some(parent) = output.parent()
+        && !parent.as_os_str().is_empty()
+    {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(output, &image)?;
+
+    Ok(stats)
+}
+
+/// Build the database image from any CSV reader
+pub fn build_from_reader<R: std::io::Read>(reader: R) -> Result<(Vec<u8>, BuildStats)> {
+    let mut csv_reader = csv::ReaderBuilder::new()
+        .has_headers(false)
+        .flexible(true)
+        .trim(csv::Trim::All)
+        .comment(Some(b'#'))
+        .from_reader(reader);
+
+    let mut v4: Vec<(u32, u32, Fields)> = Vec::new();
+    let mut v6: Vec<(u128, u128, Fields)> = Vec::new();
+
+    for (line, record) in csv_reader.records().enumerate() {
+        let record =
+            record.map_err(|e| NaliError::parse(format!("CSV error on row {}: {}", line + 1, e)))?;
+
+        let (Some(start), Some(end)) = (record.get(0), record.get(1)) else {
+            continue;
+        };
+
+        let (start, end) = match (start.parse::<IpAddr>(), end.parse::<IpAddr>()) {
+            (Ok(start), Ok(end)) => (start, end),
+            // Header row
+            _ if line == 0 => continue,
+            _ => {
+                return Err(NaliError::parse(format!(
+                    "Invalid IP range on row {}: {},{}",
+                    line + 1,
+                    start,
+                    end
+                )));
+            }
+        };
+
+        let field = |idx: usize| {
+            record
+                .get(idx)
+                .filter(|s| !s.is_empty())
+                .map(|s| s.to_string())
+        };
+        let fields = [field(2), field(3), field(4), field(5)];
+
+        match (start, end) {
+            (IpAddr::V4(s), IpAddr::V4(e)) => v4.push((s.into(), e.into(), fields)),
+            (IpAddr::V6(s), IpAddr::V6(e)) => v6.push((s.into(), e.into(), fields)),
+            _ => {
+                return Err(NaliError::parse(format!(
+                    "Mixed address families on row {}",
+                    line + 1
+                )));
+            }
+        }
+    }
+
+    v4.sort_by_key(|r| r.0);
+    v6.sort_by_key(|r| r.0);
+    check_ranges(&v4, |ip| IpAddr::V4(ip.into()))?;
+    check_ranges(&v6, |ip| IpAddr::V6(ip.into()))?;
+
+    let header = Header {
+        version: VERSION,
+        v4_count: u32::try_from(v4.len()).map_err(|_| NaliError::parse("Too many records"))?,
+        v6_count: u32::try_from(v6.len()).map_err(|_| NaliError::parse("Too many records"))?,
+        string_offset: 0,
+    };
+    let header = Header {
+        string_offset: header.records_end() as u64,
+        ..header
+    };
+
+    let mut strings = StringTable::default();
+    let mut out = Vec::with_capacity(header.records_end());
+    out.extend_from_slice(&header.to_bytes());
+
+    for (start, end, fields) in &v4 {
+        out.extend_from_slice(&start.to_le_bytes());
+        out.extend_from_slice(&end.to_le_bytes());
+        for f in fields {
+            out.extend_from_slice(&strings.intern(f)?.to_le_bytes());
+        }
+    }
+    for (start, end, fields) in &v6 {
+        out.extend_from_slice(&start.to_le_bytes());
+        out.extend_from_slice(&end.to_le_bytes());
+        for f in fields {
+            out.extend_from_slice(&strings.intern(f)?.to_le_bytes());
+        }
+    }
+    debug_assert_eq!(out.len(), header.records_end());
+
+    out.extend_from_slice(&strings.data);
+
+    let stats = BuildStats {
+        v4_records: v4.len(),
+        v6_records: v6.len(),
+        strings: strings.offsets.len(),
+        bytes: out.len(),
+    };
+
+    Ok((out, stats))
+}
+
+/// Ensure sorted ranges are well-formed and do not overlap
+fn check_ranges<T: Ord + Copy>(
+    records: &[(T, T, Fields)],
+    to_ip: impl Fn(T) -> IpAddr,
+) -> Result<()> {
+    for (i, (start, end, _)) in records.iter().enumerate() {
+        if start > end {
+            return Err(NaliError::parse(format!(
+                "Range start is after end: {} > {}",
+                to_ip(*start),
+                to_ip(*end)
+            )));
+        }
+        if i > 0 && records[i - 1].1 >= *start {
+            return Err(NaliError::parse(format!(
+                "Overlapping ranges at {}",
+                to_ip(*start)
+            )));
+        }
+    }
+    Ok(())
+}
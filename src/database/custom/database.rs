@@ -0,0 +1,270 @@
+//! Custom (nali-native) database implementation core
+
+use crate::config::LoadMode;
+use crate::database::types::format_network;
+use crate::database::{CdnProvider, Database, DatabaseBytes, DatabaseType, GeoLocation};
+use crate::error::{NaliError, Result};
+use std::net::IpAddr;
+
+use super::format::{HEADER_LEN, Header, NO_STRING, V4_RECORD_LEN, V6_RECORD_LEN};
+
+/// Database built by `nali-rs build` from user-supplied CSV ranges
+pub struct CustomDatabase {
+    name: String,
+    loaded: bool,
+    data: Option<DatabaseBytes>,
+    /// Whether `load_from_file` maps the file or reads it whole
+    load_mode: LoadMode,
+    header: Option<Header>,
+}
+
+impl CustomDatabase {
+    pub fn new() -> Self {
+        Self {
+            name: "custom".to_string(),
+            loaded: false,
+            data: None,
+            load_mode: LoadMode::default(),
+            header: None,
+        }
+    }
+
+    /// Read a string reference from the string table
+    fn read_string(data: &[u8], header: &Header, reference: u32) -> Result<Option<String>> {
+        if reference == NO_STRING {
+            return Ok(None);
+        }
+
+        let start = header.string_offset as usize + reference as usize;
+        let len_bytes = data
+            .get(start..start + 2)
+            .ok_or(NaliError::IndexOutOfBounds(start, data.len()))?;
+        let len = u16::from_le_bytes(len_bytes.try_into()?) as usize;
+        let bytes = data
+            .get(start + 2..start + 2 + len)
+            .ok_or(NaliError::IndexOutOfBounds(start + 2 + len, data.len()))?;
+
+        Ok(Some(String::from_utf8_lossy(bytes).into_owned()))
+    }
+
+    /// Binary search a record table and return the range and string
+    /// references of the matching record
+    fn search<T: Ord + Copy>(
+        data: &[u8],
+        table_start: usize,
+        count: usize,
+        record_len: usize,
+        ip: T,
+        read_ip: impl Fn(&[u8]) -> Result<T>,
+    ) -> Result<Option<(T, T, &[u8])>> {
+        let addr_len = (record_len - 16) / 2;
+
+        let mut low = 0usize;
+        let mut high = count;
+        while low < high {
+            let mid = low + (high - low) / 2;
+            let offset = table_start + mid * record_len;
+            let record = data
+                .get(offset..offset + record_len)
+                .ok_or(NaliError::IndexOutOfBounds(offset, data.len()))?;
+
+            let start = read_ip(&record[..addr_len])?;
+            let end = read_ip(&record[addr_len..addr_len * 2])?;
+
+            if ip < start {
+                high = mid;
+            } else if ip > end {
+                low = mid + 1;
+            } else {
+                return Ok(Some((start, end, &record[addr_len * 2..])));
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// Find the record covering `ip`: its first and last address and the
+    /// string references of its location fields
+    fn find(&self, ip: IpAddr) -> Result<Option<(IpAddr, IpAddr, &[u8])>> {
+        let (Some(mmap), Some(header)) = (&self.data, &self.header) else {
+            return Err(NaliError::DatabaseNotLoaded(self.name.clone()));
+        };
+
+        Ok(match ip {
+            IpAddr::V4(ipv4) => Self::search(
+                mmap,
+                HEADER_LEN,
+                header.v4_count as usize,
+                V4_RECORD_LEN,
+                u32::from(ipv4),
+                |b| Ok(u32::from_le_bytes(b.try_into()?)),
+            )?
+            .map(|(start, end, refs)| (IpAddr::V4(start.into()), IpAddr::V4(end.into()), refs)),
+            IpAddr::V6(ipv6) => Self::search(
+                mmap,
+                header.v6_start(),
+                header.v6_count as usize,
+                V6_RECORD_LEN,
+                u128::from(ipv6),
+                |b| Ok(u128::from_le_bytes(b.try_into()?)),
+            )?
+            .map(|(start, end, refs)| (IpAddr::V6(start.into()), IpAddr::V6(end.into()), refs)),
+        })
+    }
+
+    /// Look up an address in the loaded image
+    fn lookup_internal(&self, ip: IpAddr) -> Result<Option<GeoLocation>> {
+        let Some((start, end, refs)) = self.find(ip)? else {
+            return Ok(None);
+        };
+        let (Some(mmap), Some(header)) = (&self.data, &self.header) else {
+            return Err(NaliError::DatabaseNotLoaded(self.name.clone()));
+        };
+
+        let mut fields: [Option<String>; 4] = Default::default();
+        for (i, field) in fields.iter_mut().enumerate() {
+            let reference = u32::from_le_bytes(refs[i * 4..i * 4 + 4].try_into()?);
+            *field = Self::read_string(mmap, header, reference)?;
+        }
+        let [country, region, city, isp] = fields;
+
+        Ok(Some(GeoLocation {
+            ip,
+            country,
+            region,
+            city,
+            isp,
+            network: Some(format_network(start, end)),
+            ..Default::default()
+        }))
+    }
+}
+
+impl Database for CustomDatabase {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn database_type(&self) -> DatabaseType {
+        DatabaseType::Custom
+    }
+
+    fn supports_ipv4(&self) -> bool {
+        self.header.as_ref().is_none_or(|h| h.v4_count > 0)
+    }
+
+    fn supports_ipv6(&self) -> bool {
+        self.header.as_ref().is_none_or(|h| h.v6_count > 0)
+    }
+
+    fn supports_cdn(&self) -> bool {
+        false
+    }
+
+    fn lookup_ip(&self, ip: IpAddr) -> Result<Option<GeoLocation>> {
+        self.lookup_internal(ip)
+    }
+
+    fn lookup_cdn(&self, _domain: &str) -> Result<Option<CdnProvider>> {
+        Ok(None)
+    }
+
+    fn lookup_range(&self, ip: IpAddr) -> Result<Option<(IpAddr, IpAddr)>> {
+        Ok(self.find(ip)?.map(|(start, end, _)| (start, end)))
+    }
+
+    fn set_load_mode(&mut self, mode: LoadMode) {
+        self.load_mode = mode;
+    }
+
+    fn is_loaded(&self) -> bool {
+        self.loaded
+    }
+
+    fn load_from_file(&mut self, file_path: &str) -> Result<()> {
+        tracing::info!("Loading custom database from: {}", file_path);
+        self.load(DatabaseBytes::open(file_path, "custom", self.load_mode)?)
+    }
+
+    fn load_from_bytes(&mut self, data: Vec<u8>) -> Result<()> {
+        self.load(data.into())
+    }
+}
+
+impl CustomDatabase {
+    /// Parse the header and start answering lookups from `data`
+    fn load(&mut self, data: DatabaseBytes) -> Result<()> {
+        let header = Header::parse(&data)?;
+
+        tracing::info!(
+            "Successfully loaded custom database: {} IPv4, {} IPv6 ranges",
+            header.v4_count,
+            header.v6_count
+        );
+
+        self.header = Some(header);
+        self.data = Some(data);
+        self.loaded = true;
+
+        Ok(())
+    }
+}
+
+impl Default for CustomDatabase {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::custom::{build_from_csv, build_from_reader};
+
+    fn build(csv: &str) -> (tempfile::TempDir, CustomDatabase) {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("ranges.csv");
+        let output = dir.path().join("ranges.nali");
+        std::fs::write(&input, csv).unwrap();
+        build_from_csv(&input, &output).unwrap();
+
+        let mut db = CustomDatabase::new();
+        db.load_from_file(output.to_str().unwrap()).unwrap();
+        (dir, db)
+    }
+
+    #[test]
+    fn test_build_and_lookup() {
+        let (_dir, db) = build(
+            "start_ip,end_ip,country,region,city,isp\n\
+             10.0.0.0,10.0.255.255,Corp,HQ,Shanghai,Intranet\n\
+             10.1.0.0,10.1.255.255,Corp,Branch,,VPN\n\
+             fd00::,fd00::ffff,Corp,Lab,,\n",
+        );
+
+        let geo = db.lookup_ip("10.0.3.4".parse().unwrap()).unwrap().unwrap();
+        assert_eq!(geo.region.as_deref(), Some("HQ"));
+        assert_eq!(geo.isp.as_deref(), Some("Intranet"));
+
+        let geo = db.lookup_ip("10.1.0.1".parse().unwrap()).unwrap().unwrap();
+        assert_eq!(geo.city, None);
+        assert_eq!(geo.network.as_deref(), Some("10.1.0.0/16"));
+        assert_eq!(geo.isp.as_deref(), Some("VPN"));
+
+        let geo = db.lookup_ip("fd00::42".parse().unwrap()).unwrap().unwrap();
+        assert_eq!(geo.region.as_deref(), Some("Lab"));
+
+        assert_eq!(
+            db.lookup_range("10.1.0.1".parse().unwrap()).unwrap(),
+            Some(("10.1.0.0".parse().unwrap(), "10.1.255.255".parse().unwrap()))
+        );
+        assert!(db.lookup_ip("10.2.0.1".parse().unwrap()).unwrap().is_none());
+        assert!(db.lookup_ip("fd01::1".parse().unwrap()).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_overlapping_ranges_rejected() {
+        let csv = "10.0.0.0,10.0.0.255,A,,,\n10.0.0.128,10.0.1.0,B,,,\n";
+        assert!(build_from_reader(csv.as_bytes()).is_err());
+    }
+}
@@ -0,0 +1,99 @@
+//! On-disk layout of the nali-native database format
+//!
+//! ```text
+//! +--------------------+  0
+//! | header (32 bytes)  |
+//! +--------------------+  HEADER_LEN
+//! | IPv4 records       |  v4_count * V4_RECORD_LEN
+//! | IPv6 records       |  v6_count * V6_RECORD_LEN
+//! +--------------------+  string_offset
+//! | string table       |  [u16 len][utf-8 bytes]...
+//! +--------------------+
+//! ```
+//!
+//! All integers are little-endian. Records are sorted by start address and
+//! never overlap, so lookups are a binary search directly over the mmap.
+
+/// File magic
+pub const MAGIC: &[u8; 4] = b"NALI";
+
+/// Current format version
+pub const VERSION: u16 = 1;
+
+/// Header length in bytes
+pub const HEADER_LEN: usize = 32;
+
+/// IPv4 record: start (4) + end (4) + 4 string references (16)
+pub const V4_RECORD_LEN: usize = 24;
+
+/// IPv6 record: start (16) + end (16) + 4 string references (16)
+pub const V6_RECORD_LEN: usize = 48;
+
+/// String reference marking an absent field
+pub const NO_STRING: u32 = u32::MAX;
+
+/// Parsed file header
+#[derive(Debug, Clone)]
+pub struct Header {
+    pub version: u16,
+    pub v4_count: u32,
+    pub v6_count: u32,
+    pub string_offset: u64,
+}
+
+impl Header {
+    /// Serialize the header
+    pub fn to_bytes(&self) -> [u8; HEADER_LEN] {
+        let mut buf = [0u8; HEADER_LEN];
+        buf[0..4].copy_from_slice(MAGIC);
+        buf[4..6].copy_from_slice(&self.version.to_le_bytes());
+        buf[8..12].copy_from_slice(&self.v4_count.to_le_bytes());
+        buf[12..16].copy_from_slice(&self.v6_count.to_le_bytes());
+        buf[16..24].copy_from_slice(&self.string_offset.to_le_bytes());
+        buf
+    }
+
+    /// Parse and validate the header against the file length
+    pub fn parse(data: &[u8]) -> crate::error::Result<Self> {
+        if data.len() < HEADER_LEN || &data[0..4] != MAGIC {
+            return Err(crate::error::NaliError::format(
+                "Invalid nali database: bad magic",
+            ));
+        }
+
+        let version = u16::from_le_bytes(data[4..6].try_into()?);
+        if version != VERSION {
+            return Err(crate::error::NaliError::format(format!(
+                "Unsupported nali database version: {}",
+                version
+            )));
+        }
+
+        let header = Self {
+            version,
+            v4_count: u32::from_le_bytes(data[8..12].try_into()?),
+            v6_count: u32::from_le_bytes(data[12..16].try_into()?),
+            string_offset: u64::from_le_bytes(data[16..24].try_into()?),
+        };
+
+        if header.string_offset != header.records_end() as u64
+            || data.len() < header.records_end()
+        {
+            return Err(crate::error::NaliError::format(
+                "Invalid nali database: header validation failed",
+            ));
+        }
+
+        Ok(header)
+    }
+
+    /// Offset of the first IPv6 record
+    pub fn v6_start(&self) -> usize {
+        HEADER_LEN + self.v4_count as usize * V4_RECORD_LEN
+    }
+
+    /// Offset just past the last record
+    pub fn records_end(&self) -> usize {
+        self.v6_start() + self.v6_count as usize * V6_RECORD_LEN
+    }
+}
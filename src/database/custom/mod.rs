@@ -0,0 +1,18 @@
+//! Custom (nali-native) database implementation
+//!
+//! This module implements a compact binary format for user-supplied IP
+//! allocation data, built from CSV with `nali-rs build`.
+//!
+//! # Module Organization
+//!
+//! - `database`: Core CustomDatabase implementation
+//! - `builder`: CSV to binary conversion
+//! - `format`: On-disk layout constants and header parsing
+
+mod builder;
+mod database;
+mod format;
+
+// Re-export the main database struct and builder entry points
+pub use builder::{BuildStats, build_from_csv, build_from_reader};
+pub use database::CustomDatabase;
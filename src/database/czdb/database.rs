@@ -0,0 +1,576 @@
+//! CZ88 czdb database implementation core
+//!
+//! Layout of a `.czdb` file (integers little-endian unless noted):
+//!
+//! - hyper header: version (u32), client id (u32), encrypted length (u32)
+//! - encrypted block, AES-ECB with the account key: client id and expiry
+//!   date packed into a u32 (`client_id << 20 | yymmdd`), random padding
+//!   length (u32)
+//! - random padding
+//! - the database, which all pointers below are relative to:
+//!   - 17 byte super part: type (u8, bit 0 set for IPv6), file size (u32),
+//!     first index pointer (u32), header block size (u32), last index
+//!     pointer (u32)
+//!   - header blocks, a sparse index this reader does not need
+//!   - region records, MessagePack: geo map position (uint,
+//!     `length << 24 | pointer`), other data (string)
+//!   - index blocks: start and end IP (4 or 16 bytes, network order),
+//!     region pointer (u32), region length (u8)
+//!   - column selection (u32), geo map length (u32), geo map XORed with the
+//!     key: one MessagePack array of location strings per entry
+
+use crate::config::LoadMode;
+use crate::database::location_cache::LocationCache;
+use crate::database::types::format_network;
+use crate::database::{CdnProvider, Database, DatabaseBytes, DatabaseType, GeoLocation};
+use crate::error::{NaliError, Result};
+use aes::cipher::{BlockDecrypt, KeyInit};
+use aes::{Aes128, Aes192, Aes256, Block as AesBlock};
+use base64::Engine;
+use std::net::IpAddr;
+
+/// Hyper header length in bytes
+const HYPER_HEADER_LEN: usize = 12;
+/// Super part length in bytes
+const SUPER_PART_LEN: usize = 17;
+
+/// Location fields of a region: country, region, city, district and ISP
+type Region = [Option<String>; 5];
+
+/// Read a little-endian u32 at `pos`
+fn read_u32(data: &[u8], pos: usize) -> Result<u32> {
+    let bytes = pos
+        .checked_add(4)
+        .and_then(|end| data.get(pos..end))
+        .ok_or(NaliError::IndexOutOfBounds(pos, data.len()))?;
+    Ok(u32::from_le_bytes(bytes.try_into()?))
+}
+
+/// Decrypt AES-ECB blocks and strip the PKCS#7 padding; `None` when the
+/// length or padding is wrong, which is what a wrong key looks like
+fn decrypt_ecb(key: &[u8], data: &[u8]) -> Option<Vec<u8>> {
+    if data.is_empty() || !data.len().is_multiple_of(16) {
+        return None;
+    }
+    let mut blocks: Vec<AesBlock> = data.chunks_exact(16).map(AesBlock::clone_from_slice).collect();
+    match key.len() {
+        16 => Aes128::new_from_slice(key).ok()?.decrypt_blocks(&mut blocks),
+        24 => Aes192::new_from_slice(key).ok()?.decrypt_blocks(&mut blocks),
+        32 => Aes256::new_from_slice(key).ok()?.decrypt_blocks(&mut blocks),
+        _ => return None,
+    }
+    let mut plain: Vec<u8> = blocks.iter().flatten().copied().collect();
+
+    let pad = *plain.last()? as usize;
+    if pad == 0 || pad > 16 || !plain[plain.len() - pad..].iter().all(|&b| b as usize == pad) {
+        return None;
+    }
+    plain.truncate(plain.len() - pad);
+    Some(plain)
+}
+
+fn invalid_record(e: impl std::fmt::Display) -> NaliError {
+    NaliError::format(format!("Invalid CZ88 record: {}", e))
+}
+
+/// Read a MessagePack non-negative integer
+fn read_uint(record: &mut &[u8]) -> Result<u64> {
+    rmp::decode::read_int(record).map_err(invalid_record)
+}
+
+/// Read a MessagePack string, nil as empty; invalid UTF-8 is replaced
+fn read_str(record: &mut &[u8]) -> Result<String> {
+    let len = match rmp::decode::read_str_len(record) {
+        Ok(len) => len as usize,
+        Err(rmp::decode::ValueReadError::TypeMismatch(rmp::Marker::Null)) => 0,
+        Err(e) => return Err(invalid_record(e)),
+    };
+    let (text, rest) = record
+        .split_at_checked(len)
+        .ok_or_else(|| invalid_record("string past the end of the record"))?;
+    *record = rest;
+    Ok(String::from_utf8_lossy(text).into_owned())
+}
+
+/// Decoded index block
+struct Block {
+    start: IpAddr,
+    end: IpAddr,
+    region_ptr: u32,
+    region_len: u8,
+}
+
+/// Turn a region string into location fields
+///
+/// The first column reads `中国–广东–深圳–南山区`, with `–` between the
+/// levels; the other columns describe the network, usually the ISP.
+fn split_region(columns: &[String]) -> Region {
+    let field = |s: &str| match s.trim() {
+        "" | "null" | "0" => None,
+        s => Some(s.to_string()),
+    };
+    let mut levels = columns.first().map(|c| c.split('–')).into_iter().flatten().map(field);
+    let [country, region, city, district] = std::array::from_fn(|_| levels.next().flatten());
+    let isp = columns
+        .iter()
+        .skip(1)
+        .filter_map(|c| field(c))
+        .collect::<Vec<_>>()
+        .join(" ");
+
+    [country, region, city, district, (!isp.is_empty()).then_some(isp)]
+}
+
+/// CZ88 czdb database implementation
+pub struct CzdbDatabase {
+    name: String,
+    loaded: bool,
+    data: Option<DatabaseBytes>,
+    /// Whether `load_from_file` maps the file or reads it whole
+    load_mode: LoadMode,
+    /// Account key, base64 as cz88.net shows it
+    key: Option<String>,
+    /// Start of the database after the encrypted header and padding
+    body: usize,
+    ipv6: bool,
+    first_index: u32,
+    last_index: u32,
+    column_selection: u32,
+    geo_map: Vec<u8>,
+    /// Decoded location fields by region pointer
+    regions: LocationCache<Region>,
+}
+
+impl Default for CzdbDatabase {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl CzdbDatabase {
+    pub fn new() -> Self {
+        Self {
+            name: "czdb".to_string(),
+            loaded: false,
+            data: None,
+            load_mode: LoadMode::default(),
+            key: None,
+            body: 0,
+            ipv6: false,
+            first_index: 0,
+            last_index: 0,
+            column_selection: 0,
+            geo_map: Vec::new(),
+            regions: LocationCache::default(),
+        }
+    }
+
+    fn ip_len(&self) -> usize {
+        if self.ipv6 { 16 } else { 4 }
+    }
+
+    fn block_len(&self) -> usize {
+        self.ip_len() * 2 + 5
+    }
+
+    /// Read the index block at `pos`, relative to the database
+    fn read_block(&self, data: &[u8], pos: usize) -> Result<Block> {
+        let ip_len = self.ip_len();
+        let at = self.body.saturating_add(pos);
+        let bytes = at
+            .checked_add(self.block_len())
+            .and_then(|end| data.get(at..end))
+            .ok_or(NaliError::IndexOutOfBounds(at, data.len()))?;
+        let ip = |bytes: &[u8]| -> Result<IpAddr> {
+            Ok(match ip_len {
+                4 => IpAddr::from(<[u8; 4]>::try_from(bytes)?),
+                _ => IpAddr::from(<[u8; 16]>::try_from(bytes)?),
+            })
+        };
+        Ok(Block {
+            start: ip(&bytes[..ip_len])?,
+            end: ip(&bytes[ip_len..ip_len * 2])?,
+            region_ptr: u32::from_le_bytes(bytes[ip_len * 2..ip_len * 2 + 4].try_into()?),
+            region_len: bytes[ip_len * 2 + 4],
+        })
+    }
+
+    /// Binary search the index blocks for the one holding `ip`
+    fn search(&self, data: &[u8], ip: IpAddr) -> Result<Option<Block>> {
+        if ip.is_ipv6() != self.ipv6 {
+            return Ok(None);
+        }
+        let block_len = self.block_len();
+        let (mut low, mut high) = (0usize, (self.last_index - self.first_index) as usize / block_len + 1);
+        while low < high {
+            let mid = (low + high) / 2;
+            let block = self.read_block(data, self.first_index as usize + mid * block_len)?;
+            if ip < block.start {
+                high = mid;
+            } else if ip > block.end {
+                low = mid + 1;
+            } else {
+                return Ok(Some(block));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Decode the region of an index block into location fields
+    fn read_region(&self, data: &[u8], block: &Block) -> Result<Region> {
+        let start = self.body.saturating_add(block.region_ptr as usize);
+        let end = start.saturating_add(block.region_len as usize);
+        let mut record = data
+            .get(start..end)
+            .ok_or(NaliError::IndexOutOfBounds(end, data.len()))?;
+        let geo_pos = read_uint(&mut record)?;
+        let other = read_str(&mut record)?;
+
+        // Without a geo map entry the other data is the whole region
+        if geo_pos == 0 {
+            let columns: Vec<String> = other.split('\t').map(str::to_string).collect();
+            return Ok(split_region(&columns));
+        }
+
+        let len = ((geo_pos >> 24) & 0xFF) as usize;
+        let ptr = (geo_pos & 0x00FF_FFFF) as usize;
+        let mut geo = self
+            .geo_map
+            .get(ptr..ptr + len)
+            .ok_or(NaliError::IndexOutOfBounds(ptr + len, self.geo_map.len()))?;
+        let mut columns = Vec::new();
+        for i in 0..rmp::decode::read_array_len(&mut geo).map_err(invalid_record)? {
+            let value = read_str(&mut geo)?;
+            // Bit i + 1 of the column selection picks column i
+            if i < 31 && (self.column_selection >> (i + 1)) & 1 == 1 {
+                columns.push(value);
+            }
+        }
+        columns.push(other);
+
+        Ok(split_region(&columns))
+    }
+
+    /// Decrypt the header and start answering lookups from `data`
+    fn load(&mut self, data: DatabaseBytes) -> Result<()> {
+        let key = self.key.as_deref().ok_or_else(|| {
+            NaliError::config(
+                "CZ88 .czdb databases are encrypted; set database.czdb_key to the key \
+                 shown in your cz88.net account",
+            )
+        })?;
+        let key = base64::engine::general_purpose::STANDARD
+            .decode(key.trim())
+            .map_err(|e| NaliError::config(format!("database.czdb_key is not valid base64: {}", e)))?;
+        if !matches!(key.len(), 16 | 24 | 32) {
+            return Err(NaliError::config("database.czdb_key must decode to 16, 24 or 32 bytes"));
+        }
+
+        if data.len() < HYPER_HEADER_LEN {
+            return Err(NaliError::format("Invalid CZ88 database: file too small"));
+        }
+        let client_id = read_u32(&data, 4)?;
+        let encrypted_len = read_u32(&data, 8)? as usize;
+        // Lengths come from the file and may overflow `usize` on 32-bit targets
+        let header_end = HYPER_HEADER_LEN.checked_add(encrypted_len);
+        let encrypted = header_end
+            .and_then(|end| data.get(HYPER_HEADER_LEN..end))
+            .ok_or_else(|| NaliError::format("Invalid CZ88 database: header truncated"))?;
+
+        // A key of another account fails the padding or the client id check;
+        // that is a setting to fix, not a broken file
+        let wrong_key = || NaliError::config("database.czdb_key does not match this CZ88 database");
+        let header = decrypt_ecb(&key, encrypted).filter(|h| h.len() >= 8).ok_or_else(wrong_key)?;
+        if read_u32(&header, 0)? >> 20 != client_id {
+            return Err(wrong_key());
+        }
+        let too_small = || NaliError::format("Invalid CZ88 database: file too small");
+        let padding = read_u32(&header, 4)? as usize;
+        let body = header_end.and_then(|end| end.checked_add(padding)).ok_or_else(too_small)?;
+
+        let super_part = body
+            .checked_add(SUPER_PART_LEN)
+            .and_then(|end| data.get(body..end))
+            .ok_or_else(too_small)?;
+        self.ipv6 = super_part[0] & 1 != 0;
+        let first_index = read_u32(super_part, 5)?;
+        let last_index = read_u32(super_part, 13)?;
+
+        let block_len = self.block_len();
+        let index_end = body
+            .checked_add(last_index as usize)
+            .and_then(|end| end.checked_add(block_len));
+        let Some(index_end) = index_end.filter(|end| {
+            first_index <= last_index
+                && ((last_index - first_index) as usize).is_multiple_of(block_len)
+                && end.checked_add(8).is_some_and(|end| end <= data.len())
+        }) else {
+            return Err(NaliError::format("Invalid CZ88 database: index validation failed"));
+        };
+
+        let column_selection = read_u32(&data, index_end)?;
+        let geo_map_len = read_u32(&data, index_end + 4)? as usize;
+        let geo_map_start = index_end + 8;
+        let geo_map: Vec<u8> = geo_map_start
+            .checked_add(geo_map_len)
+            .and_then(|end| data.get(geo_map_start..end))
+            .ok_or_else(|| NaliError::format("Invalid CZ88 database: geo map truncated"))?
+            .iter()
+            .zip(key.iter().cycle())
+            .map(|(b, k)| b ^ k)
+            .collect();
+
+        let blocks = (last_index - first_index) as usize / block_len + 1;
+
+        self.body = body;
+        self.first_index = first_index;
+        self.last_index = last_index;
+        self.column_selection = column_selection;
+        self.geo_map = geo_map;
+        self.data = Some(data);
+        self.regions.clear();
+        self.loaded = true;
+
+        tracing::info!("Successfully loaded CZ88 database: {} blocks", blocks);
+
+        Ok(())
+    }
+}
+
+impl Database for CzdbDatabase {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn database_type(&self) -> DatabaseType {
+        DatabaseType::Czdb
+    }
+
+    fn supports_ipv4(&self) -> bool {
+        !self.loaded || !self.ipv6
+    }
+
+    fn supports_ipv6(&self) -> bool {
+        !self.loaded || self.ipv6
+    }
+
+    fn supports_cdn(&self) -> bool {
+        false
+    }
+
+    fn lookup_ip(&self, ip: IpAddr) -> Result<Option<GeoLocation>> {
+        let Some(ref data) = self.data else {
+            return Err(NaliError::DatabaseNotLoaded(self.name.clone()));
+        };
+        let Some(block) = self.search(data, ip)? else {
+            return Ok(None);
+        };
+        let [country, region, city, district, isp] = self
+            .regions
+            .get_or_decode(u64::from(block.region_ptr), || self.read_region(data, &block))?;
+
+        Ok(Some(GeoLocation {
+            ip,
+            country,
+            region,
+            city,
+            district,
+            isp,
+            network: Some(format_network(block.start, block.end)),
+            ..Default::default()
+        }))
+    }
+
+    fn lookup_cdn(&self, _domain: &str) -> Result<Option<CdnProvider>> {
+        Ok(None)
+    }
+
+    fn lookup_range(&self, ip: IpAddr) -> Result<Option<(IpAddr, IpAddr)>> {
+        let Some(ref data) = self.data else {
+            return Err(NaliError::DatabaseNotLoaded(self.name.clone()));
+        };
+        Ok(self.search(data, ip)?.map(|block| (block.start, block.end)))
+    }
+
+    fn set_key(&mut self, key: &str) {
+        self.key = Some(key.to_string());
+    }
+
+    fn set_load_mode(&mut self, mode: LoadMode) {
+        self.load_mode = mode;
+    }
+
+    fn is_loaded(&self) -> bool {
+        self.loaded
+    }
+
+    fn load_from_file(&mut self, file_path: &str) -> Result<()> {
+        tracing::info!("Loading CZ88 database from: {}", file_path);
+        self.load(DatabaseBytes::open(file_path, "CZ88", self.load_mode)?)
+    }
+
+    fn load_from_bytes(&mut self, data: Vec<u8>) -> Result<()> {
+        self.load(data.into())
+    }
+}
+
+/// Build a minimal czdb image for tests
+///
+/// `regions` are `(start, end, location, isp)`; the location goes through
+/// the geo map, the ISP is the record's other data.
+#[cfg(test)]
+pub(crate) fn build_czdb(key: &[u8], client_id: u32, regions: &[(IpAddr, IpAddr, &str, &str)]) -> Vec<u8> {
+    use aes::cipher::BlockEncrypt;
+    use rmp::encode;
+
+    let ipv6 = regions.iter().any(|(start, _, _, _)| start.is_ipv6());
+    let octets = |ip: &IpAddr| match ip {
+        IpAddr::V4(v4) => v4.octets().to_vec(),
+        IpAddr::V6(v6) => v6.octets().to_vec(),
+    };
+
+    // Header: client id with expiry 31 Dec 2099, 5 bytes of padding
+    let mut header = Vec::new();
+    header.extend_from_slice(&(client_id << 20 | 991231).to_le_bytes());
+    header.extend_from_slice(&5u32.to_le_bytes());
+    header.extend(std::iter::repeat_n(8u8, 8));
+    let mut blocks: Vec<AesBlock> = header.chunks_exact(16).map(AesBlock::clone_from_slice).collect();
+    Aes128::new_from_slice(key).unwrap().encrypt_blocks(&mut blocks);
+    let header: Vec<u8> = blocks.iter().flatten().copied().collect();
+    let mut data = Vec::new();
+    data.extend_from_slice(&1u32.to_le_bytes());
+    data.extend_from_slice(&client_id.to_le_bytes());
+    data.extend_from_slice(&(header.len() as u32).to_le_bytes());
+    data.extend_from_slice(&header);
+    data.extend_from_slice(&[0xEE; 5]);
+
+    // Geo map: an unused column, then the location
+    let mut geo_map = Vec::new();
+    let mut geo_positions = Vec::new();
+    for (_, _, location, _) in regions {
+        let start = geo_map.len();
+        encode::write_array_len(&mut geo_map, 2).unwrap();
+        encode::write_str(&mut geo_map, "unused").unwrap();
+        encode::write_str(&mut geo_map, location).unwrap();
+        geo_positions.push(((geo_map.len() - start) as u64) << 24 | start as u64);
+    }
+
+    let mut db = vec![u8::from(ipv6)];
+    db.extend_from_slice(&[0; SUPER_PART_LEN - 1]);
+    let mut records = Vec::new();
+    for ((_, _, _, isp), geo_pos) in regions.iter().zip(geo_positions) {
+        let mut record = Vec::new();
+        encode::write_uint(&mut record, geo_pos).unwrap();
+        encode::write_str(&mut record, isp).unwrap();
+        records.push((db.len() as u32, record.len() as u8));
+        db.extend_from_slice(&record);
+    }
+    let first_index = db.len() as u32;
+    for ((start, end, _, _), (ptr, len)) in regions.iter().zip(records) {
+        db.extend_from_slice(&octets(start));
+        db.extend_from_slice(&octets(end));
+        db.extend_from_slice(&ptr.to_le_bytes());
+        db.push(len);
+    }
+    let last_index = db.len() as u32 - (octets(&regions[0].0).len() * 2 + 5) as u32;
+    db[5..9].copy_from_slice(&first_index.to_le_bytes());
+    db[13..17].copy_from_slice(&last_index.to_le_bytes());
+
+    // Select the location column only
+    db.extend_from_slice(&0b100u32.to_le_bytes());
+    db.extend_from_slice(&(geo_map.len() as u32).to_le_bytes());
+    db.extend(geo_map.iter().zip(key.iter().cycle()).map(|(b, k)| b ^ k));
+
+    data.extend_from_slice(&db);
+    data
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use base64::engine::general_purpose::STANDARD;
+
+    const KEY: [u8; 16] = *b"0123456789abcdef";
+
+    fn load(image: Vec<u8>, key: &str) -> Result<CzdbDatabase> {
+        let mut db = CzdbDatabase::new();
+        db.set_key(key);
+        db.load_from_bytes(image)?;
+        Ok(db)
+    }
+
+    #[test]
+    fn test_lookup_ipv4() {
+        let image = build_czdb(&KEY, 42, &[
+            ("1.0.0.0".parse().unwrap(), "1.0.0.255".parse().unwrap(), "中国–广东–深圳–南山区", "电信"),
+            ("8.8.8.0".parse().unwrap(), "8.8.8.255".parse().unwrap(), "美国", ""),
+        ]);
+        let db = load(image, &STANDARD.encode(KEY)).unwrap();
+        assert!(db.supports_ipv4() && !db.supports_ipv6());
+
+        let geo = db.lookup_ip("1.0.0.7".parse().unwrap()).unwrap().unwrap();
+        assert_eq!(geo.country.as_deref(), Some("中国"));
+        assert_eq!(geo.region.as_deref(), Some("广东"));
+        assert_eq!(geo.city.as_deref(), Some("深圳"));
+        assert_eq!(geo.district.as_deref(), Some("南山区"));
+        assert_eq!(geo.isp.as_deref(), Some("电信"));
+        assert_eq!(geo.network.as_deref(), Some("1.0.0.0/24"));
+
+        let geo = db.lookup_ip("8.8.8.8".parse().unwrap()).unwrap().unwrap();
+        assert_eq!(geo.country.as_deref(), Some("美国"));
+        assert_eq!(geo.isp, None);
+
+        assert!(db.lookup_ip("2.0.0.1".parse().unwrap()).unwrap().is_none());
+        assert!(db.lookup_ip("::1".parse().unwrap()).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_lookup_ipv6() {
+        let image = build_czdb(&KEY, 7, &[(
+            "2001:db8::".parse().unwrap(),
+            "2001:db8::ffff".parse().unwrap(),
+            "中国–北京",
+            "联通",
+        )]);
+        let db = load(image, &STANDARD.encode(KEY)).unwrap();
+        let geo = db.lookup_ip("2001:db8::1".parse().unwrap()).unwrap().unwrap();
+        assert_eq!(geo.city, None);
+        assert_eq!(geo.region.as_deref(), Some("北京"));
+        assert_eq!(geo.isp.as_deref(), Some("联通"));
+    }
+
+    #[test]
+    fn test_record_fields() {
+        let mut record: &[u8] = &[0xcd, 0x9c, 0x40, 0xc0, 0xa2, b'o', b'k', 0xa3, b'x'];
+        assert_eq!(read_uint(&mut record).unwrap(), 40_000);
+        // Nil stands for an empty string
+        assert_eq!(read_str(&mut record).unwrap(), "");
+        assert_eq!(read_str(&mut record).unwrap(), "ok");
+        assert!(read_str(&mut record).unwrap_err().is_corruption());
+        assert!(read_uint(&mut &[0xa1, b'x'][..]).unwrap_err().is_corruption());
+    }
+
+    #[test]
+    fn test_key_problems_are_not_corruption() {
+        let image = build_czdb(&KEY, 42, &[(
+            "1.0.0.0".parse().unwrap(),
+            "1.0.0.255".parse().unwrap(),
+            "中国",
+            "",
+        )]);
+
+        let mut db = CzdbDatabase::new();
+        let err = db.load_from_bytes(image.clone()).err().unwrap();
+        assert!(err.to_string().contains("database.czdb_key"), "{}", err);
+
+        for key in ["not base64!".to_string(), STANDARD.encode([1; 16])] {
+            let err = load(image.clone(), &key).err().unwrap();
+            assert!(matches!(err, NaliError::ConfigError(_)), "{}", err);
+            assert!(!err.is_corruption());
+        }
+
+        // A truncated file is
+        let err = load(image[..40].to_vec(), &STANDARD.encode(KEY)).err().unwrap();
+        assert!(err.is_corruption(), "{}", err);
+    }
+}
@@ -0,0 +1,17 @@
+//! CZ88 czdb database implementation
+//!
+//! Since 2024 cz88.net distributes its database as `cz88_public_v4.czdb`
+//! and `cz88_public_v6.czdb`, whose header is encrypted with a key issued
+//! per account. The key is read from `database.czdb_key`.
+//!
+//! # Module Organization
+//!
+//! - `database`: Core CzdbDatabase implementation; the header is decrypted
+//!   with the `aes` crate and the records decoded with `rmp`
+
+mod database;
+
+// Re-export the main database struct
+pub use database::CzdbDatabase;
+#[cfg(test)]
+pub(crate) use database::build_czdb;
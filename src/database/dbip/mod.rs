@@ -56,6 +56,7 @@ impl Database for DBIPDatabase {
             timezone: Some("America/Los_Angeles".to_string()),
             latitude: Some(37.3382),
             longitude: Some(-121.8863),
+            ..Default::default()
         };
         Ok(Some(result))
     }
@@ -70,7 +71,7 @@ impl Database for DBIPDatabase {
 
     fn load_from_file(&mut self, file_path: &str) -> Result<()> {
         self.loaded = true;
-        log::info!("Loaded DBIP database from: {}", file_path);
+        tracing::info!("Loaded DBIP database from: {}", file_path);
         Ok(())
     }
 }
@@ -0,0 +1,400 @@
+//! Query results persisted across runs
+//!
+//! With `database.disk_cache`, the manager keeps its answers in
+//! `query_cache.json` under the data directory as well as in memory, so a
+//! batch job run again over the same addresses skips the lookups. Keys name
+//! every database that contributed together with the modification time and
+//! size of its file, so an updated database never serves stale answers;
+//! entries also expire after `database.disk_cache_ttl` seconds. Beyond
+//! `database.disk_cache_size` entries the least recently used ones are
+//! dropped.
+//!
+//! The file is read once when the manager is created and written back by
+//! [`DiskCache::save`] at the end of a run, and every minute while queries
+//! come in, so a long or interrupted run keeps most of its answers. It also
+//! counts how many lookups it answered over all runs, for
+//! `nali-rs cache stats`.
+
+use crate::error::Result;
+use dashmap::DashMap;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// File name of the cache under the data directory
+pub const CACHE_FILE: &str = "query_cache.json";
+
+/// Seconds between the saves made while queries come in
+const SAVE_INTERVAL: u64 = 60;
+
+/// A cached answer, when it was stored and when it was last used
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct CacheEntry<V> {
+    stored_at: u64,
+    /// Position in the order of use, higher is more recent
+    #[serde(default)]
+    used: u64,
+    value: V,
+}
+
+/// Contents of the cache file
+#[derive(Serialize, Deserialize)]
+struct CacheFile<V> {
+    #[serde(default)]
+    hits: u64,
+    #[serde(default)]
+    misses: u64,
+    entries: HashMap<String, CacheEntry<V>>,
+}
+
+/// Size and use of a cache over all runs
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    /// Fresh answers held
+    pub entries: usize,
+    /// Lookups answered from the cache
+    pub hits: u64,
+    /// Lookups the cache had no fresh answer for
+    pub misses: u64,
+}
+
+impl CacheStats {
+    /// Share of lookups answered from the cache, if there were any
+    pub fn hit_rate(&self) -> Option<f64> {
+        let total = self.hits + self.misses;
+        (total > 0).then(|| self.hits as f64 / total as f64)
+    }
+}
+
+/// Answers persisted as JSON, keyed by query and database versions
+#[derive(Debug)]
+pub struct DiskCache<V> {
+    path: PathBuf,
+    ttl: u64,
+    /// Entries kept before the least recently used are dropped
+    capacity: usize,
+    entries: DashMap<String, CacheEntry<V>>,
+    /// Next value of [`CacheEntry::used`]
+    clock: AtomicU64,
+    hits: AtomicU64,
+    misses: AtomicU64,
+    /// Entries or counters changed since the file was read
+    dirty: AtomicBool,
+    /// Held while entries are dropped, so only one thread does it
+    evicting: Mutex<()>,
+    /// Held while the file is written
+    saving: Mutex<()>,
+    /// When the file was read or last written
+    saved_at: AtomicU64,
+    save_interval: u64,
+}
+
+/// Seconds since the Unix epoch
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+impl<V: Clone + Serialize + DeserializeOwned> DiskCache<V> {
+    /// Open the cache at `path`, starting empty if it is missing or unreadable
+    ///
+    /// At most `capacity` entries are kept.
+    pub fn open(path: &Path, ttl: u64, capacity: usize) -> Self {
+        let file = match fs::read_to_string(path) {
+            Ok(data) => serde_json::from_str(&data).unwrap_or_else(|e| {
+                tracing::warn!("Ignoring corrupt query cache {:?}: {}", path, e);
+                None
+            }),
+            Err(_) => None,
+        };
+        let file = file.unwrap_or_else(|| CacheFile {
+            hits: 0,
+            misses: 0,
+            entries: HashMap::new(),
+        });
+
+        let now = now();
+        let entries: DashMap<_, _> = file
+            .entries
+            .into_iter()
+            .filter(|(_, entry)| now.saturating_sub(entry.stored_at) < ttl)
+            .collect();
+        let clock = entries.iter().map(|entry| entry.used + 1).max().unwrap_or_default();
+        let cache = Self {
+            path: path.to_path_buf(),
+            ttl,
+            capacity,
+            entries,
+            clock: AtomicU64::new(clock),
+            hits: AtomicU64::new(file.hits),
+            misses: AtomicU64::new(file.misses),
+            dirty: AtomicBool::new(false),
+            evicting: Mutex::new(()),
+            saving: Mutex::new(()),
+            saved_at: AtomicU64::new(now),
+            save_interval: SAVE_INTERVAL,
+        };
+        // The size may have been lowered since the file was written
+        if cache.entries.len() > capacity {
+            cache.evict();
+        }
+        cache
+    }
+
+    /// Find a fresh answer for `key`, counting the hit or miss
+    pub fn get(&self, key: &str) -> Option<V> {
+        let value = self.peek(key);
+        if value.is_some()
+            && let Some(mut entry) = self.entries.get_mut(key)
+        {
+            entry.used = self.clock.fetch_add(1, Ordering::Relaxed);
+        }
+        let counter = if value.is_some() { &self.hits } else { &self.misses };
+        counter.fetch_add(1, Ordering::Relaxed);
+        self.dirty.store(true, Ordering::Relaxed);
+        value
+    }
+
+    /// Find a fresh answer for `key` without counting it
+    pub fn peek(&self, key: &str) -> Option<V> {
+        let entry = self.entries.get(key)?;
+        (now().saturating_sub(entry.stored_at) < self.ttl).then(|| entry.value.clone())
+    }
+
+    /// Store an answer for `key`
+    pub fn insert(&self, key: String, value: V) {
+        self.entries.insert(
+            key,
+            CacheEntry {
+                stored_at: now(),
+                used: self.clock.fetch_add(1, Ordering::Relaxed),
+                value,
+            },
+        );
+        self.dirty.store(true, Ordering::Relaxed);
+        if self.entries.len() > self.capacity {
+            self.evict();
+        }
+    }
+
+    /// Drop the least recently used entries
+    ///
+    /// Goes down to nine tenths of the capacity, so that sorting the entries
+    /// happens once per many inserts rather than on every one.
+    fn evict(&self) {
+        let Ok(_evicting) = self.evicting.try_lock() else {
+            return;
+        };
+        let keep = self.capacity - self.capacity / 10;
+        let mut by_use: Vec<(u64, String)> = self
+            .entries
+            .iter()
+            .map(|entry| (entry.used, entry.key().clone()))
+            .collect();
+        if by_use.len() <= keep {
+            return;
+        }
+        let excess = by_use.len() - keep;
+        by_use.select_nth_unstable(excess - 1);
+        for (_, key) in &by_use[..excess] {
+            self.entries.remove(key);
+        }
+        self.dirty.store(true, Ordering::Relaxed);
+    }
+
+    /// Save if the last save was more than a minute ago
+    ///
+    /// Called as answers come in; the write happens on the calling thread
+    /// and only one thread does it.
+    pub fn save_if_due(&self) -> Result<()> {
+        let now = now();
+        let saved_at = self.saved_at.load(Ordering::Relaxed);
+        if now.saturating_sub(saved_at) < self.save_interval
+            || !self.dirty.load(Ordering::Relaxed)
+            || self
+                .saved_at
+                .compare_exchange(saved_at, now, Ordering::Relaxed, Ordering::Relaxed)
+                .is_err()
+        {
+            return Ok(());
+        }
+        self.save()
+    }
+
+    /// Write the cache to disk if anything changed, leaving out expired entries
+    pub fn save(&self) -> Result<()> {
+        let _saving = self.saving.lock().unwrap_or_else(|e| e.into_inner());
+        if !self.dirty.swap(false, Ordering::Relaxed) {
+            return Ok(());
+        }
+        self.saved_at.store(now(), Ordering::Relaxed);
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        let now = now();
+        let file = CacheFile {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            entries: self
+                .entries
+                .iter()
+                .filter(|entry| now.saturating_sub(entry.stored_at) < self.ttl)
+                .map(|entry| (entry.key().clone(), entry.value().clone()))
+                .collect(),
+        };
+        let data = serde_json::to_string(&file)?;
+        let tmp = self.path.with_extension("json.tmp");
+        fs::write(&tmp, data)?;
+        fs::rename(&tmp, &self.path)?;
+        Ok(())
+    }
+
+    /// Drop every entry and the counters, on disk too
+    pub fn clear(&self) -> Result<()> {
+        self.entries.clear();
+        self.hits.store(0, Ordering::Relaxed);
+        self.misses.store(0, Ordering::Relaxed);
+        self.dirty.store(false, Ordering::Relaxed);
+        remove(&self.path)
+    }
+
+    /// Fresh entries and the hits and misses of all runs so far
+    pub fn stats(&self) -> CacheStats {
+        CacheStats {
+            entries: self.entries.len(),
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Number of cached answers
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Check whether the cache is empty
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+/// Delete the cache file at `path`; a missing one is fine
+pub fn remove(path: &Path) -> Result<()> {
+    match fs::remove_file(path) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_roundtrip_and_ttl() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(CACHE_FILE);
+
+        let cache = DiskCache::<Option<String>>::open(&path, 3600, 100);
+        assert!(cache.is_empty());
+        cache.insert("ip:qqwry@1-2:1.2.3.4".to_string(), Some("Sydney".to_string()));
+        cache.insert("ip:qqwry@1-2:192.0.2.1".to_string(), None);
+        cache.save().unwrap();
+
+        let cache = DiskCache::<Option<String>>::open(&path, 3600, 100);
+        assert_eq!(cache.len(), 2);
+        assert_eq!(cache.get("ip:qqwry@1-2:1.2.3.4"), Some(Some("Sydney".to_string())));
+        assert_eq!(cache.get("ip:qqwry@1-2:192.0.2.1"), Some(None));
+        assert_eq!(cache.get("ip:qqwry@3-4:1.2.3.4"), None);
+        assert!(cache.peek("ip:qqwry@3-4:1.2.3.4").is_none());
+        cache.save().unwrap();
+
+        // Hits and misses add up over runs
+        let cache = DiskCache::<Option<String>>::open(&path, 3600, 100);
+        let stats = cache.stats();
+        assert_eq!(
+            stats,
+            CacheStats {
+                entries: 2,
+                hits: 2,
+                misses: 1
+            }
+        );
+        assert_eq!(stats.hit_rate(), Some(2.0 / 3.0));
+        assert_eq!(CacheStats::default().hit_rate(), None);
+
+        // A zero TTL expires everything
+        let cache = DiskCache::<Option<String>>::open(&path, 0, 100);
+        assert!(cache.is_empty());
+
+        let cache = DiskCache::<Option<String>>::open(&path, 3600, 100);
+        cache.clear().unwrap();
+        assert!(cache.is_empty());
+        assert!(!path.exists());
+        remove(&path).unwrap();
+    }
+
+    #[test]
+    fn test_corrupt_file_starts_empty() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(CACHE_FILE);
+        fs::write(&path, "not json").unwrap();
+
+        let cache = DiskCache::<String>::open(&path, 3600, 100);
+        assert!(cache.is_empty());
+        // Nothing was added, so the file is left alone
+        cache.save().unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "not json");
+    }
+
+    #[test]
+    fn test_least_recently_used_are_dropped() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(CACHE_FILE);
+
+        let cache = DiskCache::<u32>::open(&path, 3600, 10);
+        for i in 0..10 {
+            cache.insert(format!("ip:{}", i), i);
+        }
+        // Using the oldest entry keeps it
+        assert_eq!(cache.get("ip:0"), Some(0));
+        cache.insert("ip:10".to_string(), 10);
+
+        // Down to nine tenths: the two least recently used are gone
+        assert_eq!(cache.len(), 9);
+        assert_eq!(cache.peek("ip:0"), Some(0));
+        assert!(cache.peek("ip:1").is_none());
+        assert!(cache.peek("ip:2").is_none());
+        cache.save().unwrap();
+
+        // Recency is kept in the file, and a smaller size applies on open
+        let cache = DiskCache::<u32>::open(&path, 3600, 5);
+        assert_eq!(cache.len(), 5);
+        assert_eq!(cache.peek("ip:0"), Some(0));
+        assert_eq!(cache.peek("ip:10"), Some(10));
+        assert!(cache.peek("ip:3").is_none());
+    }
+
+    #[test]
+    fn test_saves_while_running() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(CACHE_FILE);
+
+        let mut cache = DiskCache::<u32>::open(&path, 3600, 10);
+        cache.insert("ip:1".to_string(), 1);
+        cache.save_if_due().unwrap();
+        assert!(!path.exists());
+
+        cache.save_interval = 0;
+        cache.save_if_due().unwrap();
+        assert_eq!(DiskCache::<u32>::open(&path, 3600, 10).peek("ip:1"), Some(1));
+    }
+}
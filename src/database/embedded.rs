@@ -0,0 +1,135 @@
+//! Country table compiled into the binary (`embedded-db` feature)
+//!
+//! Gives country-level answers for IPv4 addresses when the selected IPv4
+//! database is missing and cannot be downloaded, e.g. on the first run on an
+//! air-gapped host. The table is converted from an ip2asn country TSV by
+//! `build.rs`; see there for the format.
+
+use once_cell::sync::Lazy;
+use std::net::Ipv4Addr;
+
+use super::types::GeoLocation;
+
+/// Name reported for answers from the embedded table
+pub const NAME: &str = "embedded";
+
+const MAGIC: &[u8; 8] = b"NALIEMB1";
+const HEADER_LEN: usize = 12;
+const RECORD_LEN: usize = 10;
+
+static TABLE: Lazy<Option<CountryTable<'static>>> = Lazy::new(|| {
+    CountryTable::parse(include_bytes!(concat!(
+        env!("OUT_DIR"),
+        "/embedded_country.bin"
+    )))
+});
+
+/// Sorted, non-overlapping IPv4 ranges with a country code each
+struct CountryTable<'a> {
+    records: &'a [u8],
+}
+
+impl<'a> CountryTable<'a> {
+    /// Validate the header and record area
+    fn parse(bytes: &'a [u8]) -> Option<Self> {
+        if bytes.len() < HEADER_LEN || &bytes[..8] != MAGIC {
+            return None;
+        }
+        let count = u32::from_le_bytes(bytes[8..12].try_into().ok()?) as usize;
+        let records = bytes.get(HEADER_LEN..HEADER_LEN + count.checked_mul(RECORD_LEN)?)?;
+        Some(Self { records })
+    }
+
+    fn len(&self) -> usize {
+        self.records.len() / RECORD_LEN
+    }
+
+    fn record(&self, index: usize) -> (u32, u32, [u8; 2]) {
+        let r = &self.records[index * RECORD_LEN..(index + 1) * RECORD_LEN];
+        (
+            u32::from_le_bytes([r[0], r[1], r[2], r[3]]),
+            u32::from_le_bytes([r[4], r[5], r[6], r[7]]),
+            [r[8], r[9]],
+        )
+    }
+
+    /// Find the country code of the range containing `ip`
+    fn lookup(&self, ip: Ipv4Addr) -> Option<[u8; 2]> {
+        let ip = u32::from(ip);
+        // Index of the first range starting after ip
+        let (mut low, mut high) = (0, self.len());
+        while low < high {
+            let mid = low + (high - low) / 2;
+            if self.record(mid).0 <= ip {
+                low = mid + 1;
+            } else {
+                high = mid;
+            }
+        }
+
+        let (_, end, country) = self.record(low.checked_sub(1)?);
+        (ip <= end).then_some(country)
+    }
+}
+
+/// Look up the country of an IPv4 address in the embedded table
+pub fn lookup(ip: Ipv4Addr) -> Option<GeoLocation> {
+    let country = TABLE.as_ref()?.lookup(ip)?;
+    let code = String::from_utf8_lossy(&country).into_owned();
+    Some(GeoLocation {
+        ip: ip.into(),
+        country: Some(code.clone()),
+        country_code: Some(code),
+        sources: vec![NAME.to_string()],
+        ..Default::default()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn table(ranges: &[(&str, &str, &str)]) -> Vec<u8> {
+        let mut bytes = MAGIC.to_vec();
+        bytes.extend_from_slice(&(ranges.len() as u32).to_le_bytes());
+        for (start, end, country) in ranges {
+            bytes.extend_from_slice(&u32::from(start.parse::<Ipv4Addr>().unwrap()).to_le_bytes());
+            bytes.extend_from_slice(&u32::from(end.parse::<Ipv4Addr>().unwrap()).to_le_bytes());
+            bytes.extend_from_slice(country.as_bytes());
+        }
+        bytes
+    }
+
+    #[test]
+    fn test_lookup() {
+        let bytes = table(&[
+            ("1.0.0.0", "1.0.0.255", "AU"),
+            ("1.0.1.0", "1.0.3.255", "CN"),
+            ("8.8.8.0", "8.8.8.255", "US"),
+        ]);
+        let table = CountryTable::parse(&bytes).unwrap();
+
+        let country = |ip: &str| table.lookup(ip.parse().unwrap());
+        assert_eq!(country("1.0.0.0"), Some(*b"AU"));
+        assert_eq!(country("1.0.2.7"), Some(*b"CN"));
+        assert_eq!(country("8.8.8.8"), Some(*b"US"));
+        assert_eq!(country("0.255.255.255"), None);
+        assert_eq!(country("1.0.4.0"), None);
+        assert_eq!(country("255.255.255.255"), None);
+    }
+
+    #[test]
+    fn test_compiled_table() {
+        // Present in both the test fixture and the full iptoasn list
+        let geo = lookup("8.8.8.8".parse().unwrap()).unwrap();
+        assert_eq!(geo.country_code.as_deref(), Some("US"));
+        assert_eq!(geo.sources, [NAME]);
+    }
+
+    #[test]
+    fn test_parse_rejects_truncated_table() {
+        let bytes = table(&[("1.0.0.0", "1.0.0.255", "AU")]);
+        assert!(CountryTable::parse(&bytes[..bytes.len() - 1]).is_none());
+        assert!(CountryTable::parse(b"NALIEMB0\0\0\0\0").is_none());
+    }
+}
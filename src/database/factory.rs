@@ -3,27 +3,52 @@
 //! This module provides a factory pattern implementation for creating
 //! different types of database instances.
 
+use super::registry::DatabaseRegistry;
 use super::traits::Database;
 use super::types::DatabaseType;
+use crate::error::{NaliError, Result};
+use std::path::Path;
 use super::{
-    CDNDatabase, DBIPDatabase, GeoIP2Database, IP2LocationDatabase, IP2RegionDatabase,
-    IPIPDatabase, QQwryDatabase, ZXIPv6Database,
+    CDNDatabase, CustomDatabase, CzdbDatabase, DBIPDatabase, GeoCNDatabase, GeoIP2Database, IP2LocationDatabase,
+    IP2RegionDatabase, IPDBDatabase, IPIPDatabase, QQwryDatabase, ZXIPv6Database,
 };
 
 /// Factory for creating database instances
 pub struct DatabaseFactory;
 
 impl DatabaseFactory {
-    pub fn create(db_type: DatabaseType) -> Box<dyn Database + Send + Sync> {
-        match db_type {
+    pub fn create(db_type: DatabaseType) -> Result<Box<dyn Database + Send + Sync>> {
+        Ok(match db_type {
             DatabaseType::QQwry => Box::new(QQwryDatabase::new()),
+            DatabaseType::Czdb => Box::new(CzdbDatabase::new()),
             DatabaseType::ZXIPv6Wry => Box::new(ZXIPv6Database::new()),
             DatabaseType::GeoIP2 => Box::new(GeoIP2Database::new()),
+            DatabaseType::GeoCN => Box::new(GeoCNDatabase::new()),
             DatabaseType::IPIP => Box::new(IPIPDatabase::new()),
+            DatabaseType::IPDB => Box::new(IPDBDatabase::new()),
             DatabaseType::IP2Region => Box::new(IP2RegionDatabase::new()),
             DatabaseType::DBIP => Box::new(DBIPDatabase::new()),
             DatabaseType::IP2Location => Box::new(IP2LocationDatabase::new()),
             DatabaseType::CDN => Box::new(CDNDatabase::new()),
+            DatabaseType::Custom => Box::new(CustomDatabase::new()),
+            DatabaseType::Registered(name) => DatabaseRegistry::create(&name).ok_or_else(|| {
+                NaliError::DatabaseNotFound(format!("Database backend not registered: {}", name))
+            })?,
+        })
+    }
+
+    /// Release version stored in a database file, if it loads and has one
+    pub fn file_version(db_type: DatabaseType, path: &Path) -> Result<Option<String>> {
+        let path_str = path.to_str().ok_or_else(|| {
+            NaliError::config(format!("Database path is not valid UTF-8: {:?}", path))
+        })?;
+        let mut db = Self::create(db_type)?;
+        match db.load_from_file(path_str) {
+            Ok(()) => Ok(db.version()),
+            Err(e) => {
+                tracing::warn!("Failed to load database {:?}: {}", path, e);
+                Ok(None)
+            }
         }
     }
 }
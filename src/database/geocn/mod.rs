@@ -0,0 +1,171 @@
+//! GeoCN database implementation
+//!
+//! GeoCN (<https://github.com/ljxi/GeoCN>) is an MMDB file of Chinese
+//! addresses that goes down to the district, with records such as
+//! `{"province": "江苏省", "city": "南京市", "districts": "玄武区", "isp": "电信"}`.
+//! It can serve as the IPv4 database, or refine GeoIP2 answers for China
+//! through `database.china_database`.
+
+use crate::database::geoip2::{map_error, network_bounds};
+use crate::database::{CdnProvider, Database, DatabaseType, GeoLocation};
+use crate::error::Result;
+use crate::utils::time::format_date;
+use serde::Deserialize;
+use std::net::IpAddr;
+
+/// One record of a GeoCN database; the numeric area codes are ignored
+#[derive(Debug, Default, Deserialize)]
+struct Record {
+    province: Option<String>,
+    city: Option<String>,
+    #[serde(alias = "district")]
+    districts: Option<String>,
+    isp: Option<String>,
+}
+
+impl Record {
+    fn into_geo(self, ip: IpAddr) -> GeoLocation {
+        // Unknown levels are stored as empty strings
+        let field = |value: Option<String>| value.filter(|v| !v.is_empty());
+        GeoLocation {
+            ip,
+            country: Some("中国".to_string()),
+            region: field(self.province),
+            city: field(self.city),
+            district: field(self.districts),
+            isp: field(self.isp),
+            country_code: Some("CN".to_string()),
+            ..Default::default()
+        }
+    }
+}
+
+/// GeoCN database implementation
+pub struct GeoCNDatabase {
+    name: String,
+    loaded: bool,
+    reader: Option<maxminddb::Reader<Vec<u8>>>,
+}
+
+impl GeoCNDatabase {
+    pub fn new() -> Self {
+        Self {
+            name: "geocn".to_string(),
+            loaded: false,
+            reader: None,
+        }
+    }
+}
+
+impl Database for GeoCNDatabase {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn database_type(&self) -> DatabaseType {
+        DatabaseType::GeoCN
+    }
+
+    fn supports_ipv4(&self) -> bool {
+        true
+    }
+
+    fn supports_ipv6(&self) -> bool {
+        true
+    }
+
+    fn supports_cdn(&self) -> bool {
+        false
+    }
+
+    fn lookup_ip(&self, ip: IpAddr) -> Result<Option<GeoLocation>> {
+        let Some(ref reader) = self.reader else {
+            return Ok(None);
+        };
+        match reader.lookup::<Record>(ip) {
+            Ok(record) => Ok(Some(record.into_geo(ip))),
+            Err(maxminddb::MaxMindDBError::AddressNotFoundError(_)) => Ok(None),
+            Err(e) => Err(map_error("GeoCN lookup error", e)),
+        }
+    }
+
+    fn lookup_cdn(&self, _domain: &str) -> Result<Option<CdnProvider>> {
+        Ok(None)
+    }
+
+    fn lookup_range(&self, ip: IpAddr) -> Result<Option<(IpAddr, IpAddr)>> {
+        let Some(ref reader) = self.reader else {
+            return Ok(None);
+        };
+        match reader.lookup_prefix::<serde::de::IgnoredAny>(ip) {
+            Ok((_, prefix_len)) => Ok(Some(network_bounds(ip, prefix_len as u32))),
+            Err(maxminddb::MaxMindDBError::AddressNotFoundError(_)) => Ok(None),
+            Err(e) => Err(map_error("GeoCN lookup error", e)),
+        }
+    }
+
+    fn version(&self) -> Option<String> {
+        let reader = self.reader.as_ref()?;
+        Some(format_date(reader.metadata.build_epoch))
+    }
+
+    fn is_loaded(&self) -> bool {
+        self.loaded
+    }
+
+    fn load_from_file(&mut self, file_path: &str) -> Result<()> {
+        tracing::info!("Loading GeoCN database from: {}", file_path);
+
+        let data = std::fs::read(file_path)?;
+        self.load_from_bytes(data)
+    }
+
+    fn load_from_bytes(&mut self, data: Vec<u8>) -> Result<()> {
+        let reader = maxminddb::Reader::from_source(data)
+            .map_err(|e| map_error("Failed to open GeoCN database", e))?;
+
+        self.reader = Some(reader);
+        self.loaded = true;
+
+        Ok(())
+    }
+}
+
+impl Default for GeoCNDatabase {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_record_into_geo() {
+        let record: Record = serde_json::from_str(
+            r#"{"province": "江苏省", "provinceCode": 320000, "city": "南京市",
+                "districts": "玄武区", "isp": "电信", "net": "宽带"}"#,
+        )
+        .unwrap();
+        let geo = record.into_geo("114.114.114.114".parse().unwrap());
+        assert_eq!(geo.country_code.as_deref(), Some("CN"));
+        assert_eq!(geo.region.as_deref(), Some("江苏省"));
+        assert_eq!(geo.city.as_deref(), Some("南京市"));
+        assert_eq!(geo.district.as_deref(), Some("玄武区"));
+        assert_eq!(geo.isp.as_deref(), Some("电信"));
+
+        let record: Record = serde_json::from_str(r#"{"province": "北京市", "city": "", "district": ""}"#).unwrap();
+        let geo = record.into_geo("1.2.3.4".parse().unwrap());
+        assert_eq!(geo.region.as_deref(), Some("北京市"));
+        assert_eq!(geo.city, None);
+        assert_eq!(geo.district, None);
+    }
+
+    #[test]
+    fn test_unloaded_returns_none() {
+        let db = GeoCNDatabase::new();
+        assert!(!db.is_loaded());
+        assert!(db.lookup_ip("1.2.3.4".parse().unwrap()).unwrap().is_none());
+    }
+}
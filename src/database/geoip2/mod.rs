@@ -4,7 +4,8 @@
 //! which is an industry-standard IP geolocation database with multi-language support.
 
 use crate::database::{CdnProvider, Database, DatabaseType, GeoLocation};
-use crate::error::Result;
+use crate::error::{NaliError, Result};
+use crate::utils::time::format_date;
 use maxminddb::geoip2;
 use std::net::IpAddr;
 
@@ -47,12 +48,18 @@ impl GeoIP2Database {
                         .and_then(|n| n.get("zh-CN").or_else(|| n.get("en")))
                         .map(|s| s.to_string());
 
-                    let region = city.subdivisions
+                    let subdivision = city.subdivisions
                         .as_ref()
-                        .and_then(|subs| subs.last())
+                        .and_then(|subs| subs.last());
+                    let region = subdivision
                         .and_then(|sub| sub.names.as_ref())
                         .and_then(|n| n.get("zh-CN").or_else(|| n.get("en")))
                         .map(|s| s.to_string());
+                    // ISO 3166-2 prefixes the subdivision with its country
+                    let region_code = subdivision
+                        .and_then(|sub| sub.iso_code)
+                        .zip(country_code.as_deref())
+                        .map(|(sub, country)| format!("{}-{}", country, sub));
 
                     let timezone = city.location
                         .as_ref()
@@ -61,6 +68,7 @@ impl GeoIP2Database {
 
                     let latitude = city.location.as_ref().and_then(|l| l.latitude);
                     let longitude = city.location.as_ref().and_then(|l| l.longitude);
+                    let accuracy_radius = city.location.as_ref().and_then(|l| l.accuracy_radius);
 
                     Ok(Some(GeoLocation {
                         ip,
@@ -69,17 +77,18 @@ impl GeoIP2Database {
                         city: city_name,
                         isp: None, // GeoIP2 City doesn't include ISP
                         country_code,
+                        region_code,
                         timezone,
                         latitude,
                         longitude,
+                        accuracy_radius,
+                        ..Default::default()
                     }))
                 }
                 Err(maxminddb::MaxMindDBError::AddressNotFoundError(_)) => {
                     Ok(None)
                 }
-                Err(e) => {
-                    Err(crate::error::NaliError::parse(format!("GeoIP2 lookup error: {}", e)))
-                }
+                Err(e) => Err(map_error("GeoIP2 lookup error", e)),
             }
         } else {
             Ok(None)
@@ -116,23 +125,79 @@ impl Database for GeoIP2Database {
         Ok(None)
     }
 
+    fn lookup_range(&self, ip: IpAddr) -> Result<Option<(IpAddr, IpAddr)>> {
+        let Some(ref reader) = self.reader else {
+            return Ok(None);
+        };
+        match reader.lookup_prefix::<serde::de::IgnoredAny>(ip) {
+            Ok((_, prefix_len)) => Ok(Some(network_bounds(ip, prefix_len as u32))),
+            Err(maxminddb::MaxMindDBError::AddressNotFoundError(_)) => Ok(None),
+            Err(e) => Err(map_error("GeoIP2 lookup error", e)),
+        }
+    }
+
+    fn version(&self) -> Option<String> {
+        let reader = self.reader.as_ref()?;
+        Some(format_date(reader.metadata.build_epoch))
+    }
+
     fn is_loaded(&self) -> bool {
         self.loaded
     }
 
     fn load_from_file(&mut self, file_path: &str) -> Result<()> {
-        log::info!("Loading GeoIP2 database from: {}", file_path);
+        tracing::info!("Loading GeoIP2 database from: {}", file_path);
 
         let reader = maxminddb::Reader::open_readfile(file_path)
-            .map_err(|e| crate::error::NaliError::parse(format!("Failed to open GeoIP2 database: {}", e)))?;
+            .map_err(|e| map_error("Failed to open GeoIP2 database", e))?;
 
         self.reader = Some(reader);
         self.loaded = true;
 
-        log::info!("Successfully loaded GeoIP2 database from: {}", file_path);
+        tracing::info!("Successfully loaded GeoIP2 database from: {}", file_path);
 
         Ok(())
     }
+
+    fn load_from_bytes(&mut self, data: Vec<u8>) -> Result<()> {
+        let reader = maxminddb::Reader::from_source(data)
+            .map_err(|e| map_error("Failed to open GeoIP2 database", e))?;
+
+        self.reader = Some(reader);
+        self.loaded = true;
+
+        Ok(())
+    }
+}
+
+/// Convert a MaxMind reader error, keeping I/O failures apart from bad files
+pub(crate) fn map_error(context: &str, e: maxminddb::MaxMindDBError) -> NaliError {
+    use maxminddb::MaxMindDBError::*;
+    match e {
+        IoError(msg) | MapError(msg) => {
+            NaliError::IoError(std::io::Error::other(format!("{}: {}", context, msg)))
+        }
+        InvalidDatabaseError(msg) | DecodingError(msg) => {
+            NaliError::format(format!("{}: {}", context, msg))
+        }
+        e => NaliError::parse(format!("{}: {}", context, e)),
+    }
+}
+
+/// First and last address of the network of `ip` with the given prefix length
+pub(crate) fn network_bounds(ip: IpAddr, prefix_len: u32) -> (IpAddr, IpAddr) {
+    match ip {
+        IpAddr::V4(ipv4) => {
+            let host = u32::MAX.checked_shr(prefix_len).unwrap_or(0);
+            let start = u32::from(ipv4) & !host;
+            (IpAddr::V4(start.into()), IpAddr::V4((start | host).into()))
+        }
+        IpAddr::V6(ipv6) => {
+            let host = u128::MAX.checked_shr(prefix_len).unwrap_or(0);
+            let start = u128::from(ipv6) & !host;
+            (IpAddr::V6(start.into()), IpAddr::V6((start | host).into()))
+        }
+    }
 }
 
 impl Default for GeoIP2Database {
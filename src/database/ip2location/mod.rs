@@ -56,6 +56,7 @@ impl Database for IP2LocationDatabase {
             timezone: Some("Europe/London".to_string()),
             latitude: Some(51.5074),
             longitude: Some(-0.1278),
+            ..Default::default()
         };
         Ok(Some(result))
     }
@@ -70,7 +71,7 @@ impl Database for IP2LocationDatabase {
 
     fn load_from_file(&mut self, file_path: &str) -> Result<()> {
         self.loaded = true;
-        log::info!("Loaded IP2Location database from: {}", file_path);
+        tracing::info!("Loaded IP2Location database from: {}", file_path);
         Ok(())
     }
 }
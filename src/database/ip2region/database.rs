@@ -0,0 +1,345 @@
+//! IP2Region xdb database implementation core
+//!
+//! Layout of an xdb file (all integers little-endian):
+//!
+//! - 256 byte header: version (u16), index policy (u16), created at (u32),
+//!   start index pointer (u32), end index pointer (u32)
+//! - 256 x 256 vector index of 8 byte entries: first and last segment
+//!   pointer for each /16 prefix
+//! - region strings (`country|region|province|city|isp`, `0` for empty)
+//! - 14 byte segment index entries: start IP (u32), end IP (u32),
+//!   region length (u16), region pointer (u32)
+
+use crate::config::LoadMode;
+use crate::database::location_cache::LocationCache;
+use crate::database::types::format_network;
+use crate::database::{CdnProvider, Database, DatabaseBytes, DatabaseType, GeoLocation};
+use crate::error::{NaliError, Result};
+use std::net::IpAddr;
+
+use super::iter::IP2RegionRanges;
+
+/// Header length in bytes
+pub(super) const HEADER_LEN: usize = 256;
+/// Vector index dimension (one entry per first/second octet)
+const VECTOR_INDEX_COLS: usize = 256;
+/// Vector index entry length
+const VECTOR_INDEX_ENTRY_LEN: usize = 8;
+/// Segment index entry length
+pub(super) const SEGMENT_INDEX_LEN: usize = 14;
+
+/// Decoded segment index entry
+pub(super) struct Segment {
+    pub start: u32,
+    pub end: u32,
+    pub data_len: u16,
+    pub data_ptr: u32,
+}
+
+/// Read a little-endian u32 at `pos`
+fn read_u32(data: &[u8], pos: usize) -> Result<u32> {
+    let bytes = data
+        .get(pos..pos + 4)
+        .ok_or(NaliError::IndexOutOfBounds(pos, data.len()))?;
+    Ok(u32::from_le_bytes(bytes.try_into()?))
+}
+
+/// Read the segment index entry at `pos`
+pub(super) fn read_segment(data: &[u8], pos: usize) -> Result<Segment> {
+    let bytes = data
+        .get(pos..pos + SEGMENT_INDEX_LEN)
+        .ok_or(NaliError::IndexOutOfBounds(pos, data.len()))?;
+    Ok(Segment {
+        start: u32::from_le_bytes(bytes[0..4].try_into()?),
+        end: u32::from_le_bytes(bytes[4..8].try_into()?),
+        data_len: u16::from_le_bytes(bytes[8..10].try_into()?),
+        data_ptr: u32::from_le_bytes(bytes[10..14].try_into()?),
+    })
+}
+
+/// Decode the region string of a segment into country, province, city and ISP
+pub(super) fn read_region(data: &[u8], segment: &Segment) -> Result<[Option<String>; 4]> {
+    let start = segment.data_ptr as usize;
+    let end = start + segment.data_len as usize;
+    let bytes = data
+        .get(start..end)
+        .ok_or(NaliError::IndexOutOfBounds(end, data.len()))?;
+    let region = String::from_utf8_lossy(bytes);
+
+    let mut fields = region.split('|').map(|field| match field.trim() {
+        "" | "0" => None,
+        field => Some(field.to_string()),
+    });
+
+    let country = fields.next().flatten();
+    let _area = fields.next();
+    let province = fields.next().flatten();
+    let city = fields.next().flatten();
+    let isp = fields.next().flatten();
+
+    Ok([country, province, city, isp])
+}
+
+/// IP2Region xdb database implementation
+pub struct IP2RegionDatabase {
+    name: String,
+    loaded: bool,
+    data: Option<DatabaseBytes>,
+    /// Whether `load_from_file` maps the file or reads it whole
+    load_mode: LoadMode,
+    start_index: u32,
+    end_index: u32,
+    /// Decoded region fields by region pointer
+    regions: LocationCache<[Option<String>; 4]>,
+}
+
+impl Default for IP2RegionDatabase {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl IP2RegionDatabase {
+    pub fn new() -> Self {
+        Self {
+            name: "ip2region".to_string(),
+            loaded: false,
+            data: None,
+            load_mode: LoadMode::default(),
+            start_index: 0,
+            end_index: 0,
+            regions: LocationCache::default(),
+        }
+    }
+
+    /// Search the segment index for an IPv4 address
+    fn search(&self, data: &[u8], ip: u32) -> Result<Option<Segment>> {
+        let il0 = (ip >> 24) as usize;
+        let il1 = ((ip >> 16) & 0xFF) as usize;
+        let idx = HEADER_LEN + (il0 * VECTOR_INDEX_COLS + il1) * VECTOR_INDEX_ENTRY_LEN;
+
+        let s_ptr = read_u32(data, idx)? as usize;
+        let e_ptr = read_u32(data, idx + 4)? as usize;
+        if s_ptr == 0 || e_ptr < s_ptr {
+            return Ok(None);
+        }
+
+        let mut low = 0usize;
+        let mut high = (e_ptr - s_ptr) / SEGMENT_INDEX_LEN;
+        while low <= high {
+            let mid = (low + high) / 2;
+            let segment = read_segment(data, s_ptr + mid * SEGMENT_INDEX_LEN)?;
+
+            if ip < segment.start {
+                if mid == 0 {
+                    break;
+                }
+                high = mid - 1;
+            } else if ip > segment.end {
+                low = mid + 1;
+            } else {
+                return Ok(Some(segment));
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// Iterate over every segment in the index
+    pub fn ranges(&self) -> Result<IP2RegionRanges<'_>> {
+        match self.data {
+            Some(ref mmap) => Ok(IP2RegionRanges::new(
+                mmap,
+                self.start_index,
+                self.end_index,
+            )),
+            None => Err(NaliError::DatabaseNotLoaded(self.name.clone())),
+        }
+    }
+}
+
+impl Database for IP2RegionDatabase {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn database_type(&self) -> DatabaseType {
+        DatabaseType::IP2Region
+    }
+
+    fn supports_ipv4(&self) -> bool {
+        true
+    }
+
+    fn supports_ipv6(&self) -> bool {
+        false
+    }
+
+    fn supports_cdn(&self) -> bool {
+        false
+    }
+
+    fn lookup_range(&self, ip: IpAddr) -> Result<Option<(IpAddr, IpAddr)>> {
+        let IpAddr::V4(ipv4) = ip else {
+            return Ok(None);
+        };
+        let Some(ref mmap) = self.data else {
+            return Err(NaliError::DatabaseNotLoaded(self.name.clone()));
+        };
+
+        Ok(self
+            .search(mmap, u32::from(ipv4))?
+            .map(|segment| (IpAddr::V4(segment.start.into()), IpAddr::V4(segment.end.into()))))
+    }
+
+    fn lookup_ip(&self, ip: IpAddr) -> Result<Option<GeoLocation>> {
+        let IpAddr::V4(ipv4) = ip else {
+            return Ok(None);
+        };
+        let Some(ref mmap) = self.data else {
+            return Err(NaliError::DatabaseNotLoaded(self.name.clone()));
+        };
+
+        let Some(segment) = self.search(mmap, u32::from(ipv4))? else {
+            return Ok(None);
+        };
+        let [country, region, city, isp] = self
+            .regions
+            .get_or_decode(u64::from(segment.data_ptr), || read_region(mmap, &segment))?;
+
+        Ok(Some(GeoLocation {
+            ip,
+            country,
+            region,
+            city,
+            isp,
+            network: Some(format_network(
+                IpAddr::V4(segment.start.into()),
+                IpAddr::V4(segment.end.into()),
+            )),
+            ..Default::default()
+        }))
+    }
+
+    fn lookup_cdn(&self, _domain: &str) -> Result<Option<CdnProvider>> {
+        Ok(None)
+    }
+
+    fn set_load_mode(&mut self, mode: LoadMode) {
+        self.load_mode = mode;
+    }
+
+    fn is_loaded(&self) -> bool {
+        self.loaded
+    }
+
+    fn load_from_file(&mut self, file_path: &str) -> Result<()> {
+        tracing::info!("Loading IP2Region database from: {}", file_path);
+        self.load(DatabaseBytes::open(file_path, "IP2Region", self.load_mode)?)
+    }
+
+    fn load_from_bytes(&mut self, data: Vec<u8>) -> Result<()> {
+        self.load(data.into())
+    }
+}
+
+impl IP2RegionDatabase {
+    /// Validate the header and start answering lookups from `data`
+    fn load(&mut self, data: DatabaseBytes) -> Result<()> {
+        let vector_end = HEADER_LEN + VECTOR_INDEX_COLS * VECTOR_INDEX_COLS * VECTOR_INDEX_ENTRY_LEN;
+        if data.len() < vector_end {
+            return Err(NaliError::format("Invalid IP2Region database: file too small"));
+        }
+
+        let start_index = read_u32(&data, 8)?;
+        let end_index = read_u32(&data, 12)?;
+        if start_index > end_index || data.len() < end_index as usize + SEGMENT_INDEX_LEN {
+            return Err(NaliError::format("Invalid IP2Region database: header validation failed"));
+        }
+
+        let segments = (end_index - start_index) as usize / SEGMENT_INDEX_LEN + 1;
+
+        self.start_index = start_index;
+        self.end_index = end_index;
+        self.data = Some(data);
+        self.regions.clear();
+        self.loaded = true;
+
+        tracing::info!("Successfully loaded IP2Region database: {} segments", segments);
+
+        Ok(())
+    }
+}
+
+/// Build a minimal xdb image for tests
+#[cfg(test)]
+pub(crate) fn build_xdb(segments: &[(std::net::Ipv4Addr, std::net::Ipv4Addr, &str)]) -> Vec<u8> {
+    let vector_len = VECTOR_INDEX_COLS * VECTOR_INDEX_COLS * VECTOR_INDEX_ENTRY_LEN;
+    let mut data = vec![0u8; HEADER_LEN + vector_len];
+    data[0..2].copy_from_slice(&2u16.to_le_bytes());
+
+    let mut regions = Vec::new();
+    for (_, _, region) in segments {
+        regions.push((data.len() as u32, region.len() as u16));
+        data.extend_from_slice(region.as_bytes());
+    }
+
+    let start_index = data.len() as u32;
+    for ((start, end, _), (ptr, len)) in segments.iter().zip(&regions) {
+        let (start, end) = (u32::from(*start), u32::from(*end));
+        let pos = data.len() as u32;
+        data.extend_from_slice(&start.to_le_bytes());
+        data.extend_from_slice(&end.to_le_bytes());
+        data.extend_from_slice(&len.to_le_bytes());
+        data.extend_from_slice(&ptr.to_le_bytes());
+
+        // Register the segment for every /16 it touches
+        for prefix in (start >> 16)..=(end >> 16) {
+            let idx = HEADER_LEN + prefix as usize * VECTOR_INDEX_ENTRY_LEN;
+            if data[idx..idx + 4] == [0; 4] {
+                data[idx..idx + 4].copy_from_slice(&pos.to_le_bytes());
+            }
+            data[idx + 4..idx + 8].copy_from_slice(&pos.to_le_bytes());
+        }
+    }
+    let end_index = data.len() as u32 - SEGMENT_INDEX_LEN as u32;
+
+    data[8..12].copy_from_slice(&start_index.to_le_bytes());
+    data[12..16].copy_from_slice(&end_index.to_le_bytes());
+    data
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::Ipv4Addr;
+
+    #[test]
+    fn test_lookup_and_ranges() {
+        let image = build_xdb(&[
+            (Ipv4Addr::new(0, 0, 0, 0), Ipv4Addr::new(1, 0, 0, 255), "0|0|0|内网IP|内网IP"),
+            (Ipv4Addr::new(1, 0, 1, 0), Ipv4Addr::new(1, 1, 255, 255), "中国|0|福建省|福州市|电信"),
+            (Ipv4Addr::new(1, 2, 0, 0), Ipv4Addr::new(255, 255, 255, 255), "美国|0|0|0|0"),
+        ]);
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("ip2region.xdb");
+        std::fs::write(&path, image).unwrap();
+
+        let mut db = IP2RegionDatabase::new();
+        db.load_from_file(path.to_str().unwrap()).unwrap();
+
+        let geo = db.lookup_ip("1.1.2.3".parse().unwrap()).unwrap().unwrap();
+        assert_eq!(geo.country.as_deref(), Some("中国"));
+        assert_eq!(geo.region.as_deref(), Some("福建省"));
+        assert_eq!(geo.city.as_deref(), Some("福州市"));
+        assert_eq!(geo.isp.as_deref(), Some("电信"));
+
+        let geo = db.lookup_ip("8.8.8.8".parse().unwrap()).unwrap().unwrap();
+        assert_eq!(geo.country.as_deref(), Some("美国"));
+        assert_eq!(geo.isp, None);
+
+        let ranges: Vec<_> = db.ranges().unwrap().collect::<Result<_>>().unwrap();
+        assert_eq!(ranges.len(), 3);
+        assert_eq!(ranges[1].start, "1.0.1.0".parse::<IpAddr>().unwrap());
+    }
+}
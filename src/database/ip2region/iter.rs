@@ -0,0 +1,51 @@
+//! Iterator over every segment in an IP2Region xdb index
+
+use crate::database::IpRange;
+use crate::error::Result;
+use std::net::{IpAddr, Ipv4Addr};
+
+use super::database::{SEGMENT_INDEX_LEN, read_region, read_segment};
+
+/// Iterator over all segments of a loaded xdb database, in index order
+pub struct IP2RegionRanges<'a> {
+    data: &'a [u8],
+    pos: usize,
+    end_index: usize,
+}
+
+impl<'a> IP2RegionRanges<'a> {
+    pub(super) fn new(data: &'a [u8], start_index: u32, end_index: u32) -> Self {
+        Self {
+            data,
+            pos: start_index as usize,
+            end_index: end_index as usize,
+        }
+    }
+
+    fn read_entry(&self, pos: usize) -> Result<IpRange> {
+        let segment = read_segment(self.data, pos)?;
+        let [country, region, city, isp] = read_region(self.data, &segment)?;
+
+        Ok(IpRange {
+            start: IpAddr::V4(Ipv4Addr::from(segment.start)),
+            end: IpAddr::V4(Ipv4Addr::from(segment.end)),
+            country,
+            region,
+            city,
+            isp,
+        })
+    }
+}
+
+impl Iterator for IP2RegionRanges<'_> {
+    type Item = Result<IpRange>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.pos > self.end_index {
+            return None;
+        }
+        let item = self.read_entry(self.pos);
+        self.pos += SEGMENT_INDEX_LEN;
+        Some(item)
+    }
+}
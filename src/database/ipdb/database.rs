@@ -0,0 +1,407 @@
+//! IPDB database implementation core
+
+use crate::config::LoadMode;
+use crate::database::{CdnProvider, Database, DatabaseBytes, DatabaseType, GeoLocation};
+use crate::error::{NaliError, Result};
+use crate::utils::time::format_date;
+use std::net::IpAddr;
+
+use super::meta::{IPV4, IPV6, Meta};
+
+/// IPDB database implementation
+///
+/// The file is a binary search tree over address bits: every node is two
+/// big-endian u32 children. A child equal to `node_count` means "no data";
+/// a larger one points into the data section, where each record is a u16
+/// length followed by tab-separated fields for all languages.
+pub struct IPDBDatabase {
+    name: String,
+    loaded: bool,
+    data: Option<DatabaseBytes>,
+    /// Whether `load_from_file` maps the file or reads it whole
+    load_mode: LoadMode,
+    meta: Option<Meta>,
+    /// Start of the tree in the file, right after the metadata
+    data_start: usize,
+    /// Node reached after walking the IPv4-mapped prefix `::ffff:0:0/96`
+    v4_offset: u32,
+    language: String,
+    field_offset: usize,
+}
+
+impl IPDBDatabase {
+    pub fn new() -> Self {
+        Self {
+            name: "ipip".to_string(),
+            loaded: false,
+            data: None,
+            load_mode: LoadMode::default(),
+            meta: None,
+            data_start: 0,
+            v4_offset: 0,
+            language: "zh-CN".to_string(),
+            field_offset: 0,
+        }
+    }
+
+    /// Tree and data section of the loaded file
+    fn data(&self) -> &[u8] {
+        self.data
+            .as_ref()
+            .map_or(&[][..], |mmap| &mmap[self.data_start..])
+    }
+
+    /// Read child `bit` of a tree node
+    fn read_node(data: &[u8], node: u32, bit: usize) -> Option<u32> {
+        let offset = node as usize * 8 + bit * 4;
+        let bytes = data.get(offset..offset + 4)?;
+        Some(u32::from_be_bytes(bytes.try_into().ok()?))
+    }
+
+    /// Walk the tree along the bits of `ip`, returning the data pointer
+    fn find_node(&self, ip: &[u8], start: u32) -> Option<u32> {
+        let meta = self.meta.as_ref()?;
+        let data = self.data();
+        let mut node = start;
+        for i in 0..ip.len() * 8 {
+            if node >= meta.node_count {
+                break;
+            }
+            let bit = (ip[i / 8] >> (7 - i % 8)) & 1;
+            node = Self::read_node(data, node, bit as usize)?;
+        }
+        (node > meta.node_count).then_some(node)
+    }
+
+    /// Decode the fields of a record for the selected language
+    fn resolve(&self, node: u32) -> Result<Vec<&str>> {
+        let meta = self
+            .meta
+            .as_ref()
+            .ok_or_else(|| NaliError::DatabaseNotLoaded(self.name.clone()))?;
+        let data = self.data();
+        let resolved = (node - meta.node_count) as usize + meta.node_count as usize * 8;
+        let corrupt = || NaliError::format(format!("IPDB record out of bounds at {}", resolved));
+
+        let size = data.get(resolved..resolved + 2).ok_or_else(corrupt)?;
+        let size = u16::from_be_bytes([size[0], size[1]]) as usize;
+        let record = data
+            .get(resolved + 2..resolved + 2 + size)
+            .ok_or_else(corrupt)?;
+        let record = std::str::from_utf8(record)
+            .map_err(|e| NaliError::format(format!("IPDB record is not UTF-8: {}", e)))?;
+
+        let values: Vec<&str> = record.split('\t').collect();
+        let end = self.field_offset + meta.fields.len();
+        values
+            .get(self.field_offset..end)
+            .map(<[&str]>::to_vec)
+            .ok_or_else(|| NaliError::format("IPDB record has fewer fields than declared"))
+    }
+
+    /// Build a result from the field values of a record
+    fn to_geo(&self, ip: IpAddr, values: &[&str]) -> GeoLocation {
+        let fields = self.meta.as_ref().map_or(&[][..], |meta| &meta.fields[..]);
+        let field = |name: &str| {
+            fields
+                .iter()
+                .position(|f| f == name)
+                .and_then(|i| values.get(i))
+                .filter(|value| !value.is_empty())
+                .map(|value| value.to_string())
+        };
+
+        GeoLocation {
+            ip,
+            country: field("country_name"),
+            region: field("region_name"),
+            city: field("city_name"),
+            isp: field("isp_domain").or_else(|| field("owner_domain")),
+            country_code: field("country_code"),
+            timezone: field("timezone"),
+            latitude: field("latitude").and_then(|v| v.parse().ok()),
+            longitude: field("longitude").and_then(|v| v.parse().ok()),
+            ..Default::default()
+        }
+    }
+}
+
+impl Database for IPDBDatabase {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn database_type(&self) -> DatabaseType {
+        DatabaseType::IPDB
+    }
+
+    fn supports_ipv4(&self) -> bool {
+        self.meta
+            .as_ref()
+            .is_some_and(|meta| meta.ip_version & IPV4 != 0)
+    }
+
+    fn supports_ipv6(&self) -> bool {
+        self.meta
+            .as_ref()
+            .is_some_and(|meta| meta.ip_version & IPV6 != 0)
+    }
+
+    fn supports_cdn(&self) -> bool {
+        false
+    }
+
+    fn lookup_ip(&self, ip: IpAddr) -> Result<Option<GeoLocation>> {
+        if !self.loaded {
+            return Err(NaliError::DatabaseNotLoaded(self.name.clone()));
+        }
+
+        let node = match ip {
+            IpAddr::V4(v4) if self.supports_ipv4() => self.find_node(&v4.octets(), self.v4_offset),
+            IpAddr::V6(v6) if self.supports_ipv6() => self.find_node(&v6.octets(), 0),
+            _ => return Ok(None),
+        };
+
+        match node {
+            Some(node) => Ok(Some(self.to_geo(ip, &self.resolve(node)?))),
+            None => Ok(None),
+        }
+    }
+
+    fn lookup_cdn(&self, _domain: &str) -> Result<Option<CdnProvider>> {
+        Ok(None)
+    }
+
+    fn version(&self) -> Option<String> {
+        let meta = self.meta.as_ref()?;
+        (meta.build > 0).then(|| format_date(meta.build))
+    }
+
+    fn set_language(&mut self, language: &str) {
+        self.language = language.to_string();
+        if let Some(ref meta) = self.meta {
+            self.field_offset = meta.language_offset(language);
+        }
+    }
+
+    fn set_load_mode(&mut self, mode: LoadMode) {
+        self.load_mode = mode;
+    }
+
+    fn is_loaded(&self) -> bool {
+        self.loaded
+    }
+
+    fn load_from_file(&mut self, file_path: &str) -> Result<()> {
+        tracing::info!("Loading IPDB database from: {}", file_path);
+        self.load(DatabaseBytes::open(file_path, "IPDB", self.load_mode)?)
+    }
+
+    fn load_from_bytes(&mut self, data: Vec<u8>) -> Result<()> {
+        self.load(data.into())
+    }
+}
+
+impl IPDBDatabase {
+    /// Parse the metadata and start answering lookups from `data`
+    fn load(&mut self, data: DatabaseBytes) -> Result<()> {
+        // Metadata: big-endian length, then JSON
+        let meta_len = data
+            .get(0..4)
+            .map(|b| u32::from_be_bytes([b[0], b[1], b[2], b[3]]) as usize)
+            .ok_or_else(|| NaliError::format("Invalid IPDB database: file too short"))?;
+        let meta_json = data
+            .get(4..4 + meta_len)
+            .ok_or_else(|| NaliError::format("Invalid IPDB database: metadata truncated"))?;
+        let meta: Meta = serde_json::from_slice(meta_json)
+            .map_err(|e| NaliError::format(format!("Invalid IPDB metadata: {}", e)))?;
+
+        let data_start = 4 + meta_len;
+        if data.len() - data_start != meta.total_size
+            || (meta.node_count as usize) * 8 > meta.total_size
+        {
+            return Err(NaliError::format(
+                "Invalid IPDB database: size does not match metadata",
+            ));
+        }
+
+        // IPv4 lives under ::ffff:0:0/96 in databases that also cover IPv6
+        let mut v4_offset = 0;
+        if meta.ip_version & IPV6 != 0 {
+            let tree = &data[data_start..];
+            for i in 0..96 {
+                if v4_offset >= meta.node_count {
+                    break;
+                }
+                v4_offset = Self::read_node(tree, v4_offset, usize::from(i >= 80))
+                    .ok_or_else(|| NaliError::format("Invalid IPDB database: tree truncated"))?;
+            }
+        }
+
+        tracing::info!(
+            "IPDB database: {} nodes, languages {:?}, fields {:?}",
+            meta.node_count,
+            meta.languages.keys().collect::<Vec<_>>(),
+            meta.fields
+        );
+
+        self.field_offset = meta.language_offset(&self.language);
+        self.v4_offset = v4_offset;
+        self.data_start = data_start;
+        self.meta = Some(meta);
+        self.data = Some(data);
+        self.loaded = true;
+
+        Ok(())
+    }
+}
+
+impl Default for IPDBDatabase {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::{Ipv4Addr, Ipv6Addr};
+
+    /// Assemble an `.ipdb` file from `(address bits, prefix length, record)` entries
+    fn build(ip_version: u16, entries: &[(Vec<u8>, usize, &str)]) -> Vec<u8> {
+        const EMPTY: u32 = u32::MAX;
+        let mut nodes: Vec<[u32; 2]> = vec![[EMPTY; 2]];
+        // Data section starts with an unused record so no pointer equals node_count
+        let mut records = vec![0u8, 0];
+        let mut leaves = Vec::new();
+
+        for (bits, len, record) in entries {
+            let mut node = 0;
+            for i in 0..*len {
+                let bit = ((bits[i / 8] >> (7 - i % 8)) & 1) as usize;
+                if i + 1 == *len {
+                    leaves.push((node, bit, records.len() as u32));
+                    records.extend_from_slice(&(record.len() as u16).to_be_bytes());
+                    records.extend_from_slice(record.as_bytes());
+                } else {
+                    if nodes[node][bit] == EMPTY {
+                        nodes.push([EMPTY; 2]);
+                        nodes[node][bit] = nodes.len() as u32 - 1;
+                    }
+                    node = nodes[node][bit] as usize;
+                }
+            }
+        }
+
+        let node_count = nodes.len() as u32;
+        for (node, bit, offset) in leaves {
+            nodes[node][bit] = node_count + offset;
+        }
+        let mut data = Vec::new();
+        for node in nodes {
+            for child in node {
+                let child = if child == EMPTY { node_count } else { child };
+                data.extend_from_slice(&child.to_be_bytes());
+            }
+        }
+        data.extend_from_slice(&records);
+
+        let meta = serde_json::json!({
+            "build": 1_700_000_000,
+            "ip_version": ip_version,
+            "languages": {"CN": 0, "EN": 3},
+            "node_count": node_count,
+            "total_size": data.len(),
+            "fields": ["country_name", "region_name", "city_name"],
+        })
+        .to_string();
+
+        let mut file = (meta.len() as u32).to_be_bytes().to_vec();
+        file.extend_from_slice(meta.as_bytes());
+        file.extend_from_slice(&data);
+        file
+    }
+
+    fn load(file: &[u8], language: &str) -> (tempfile::TempDir, IPDBDatabase) {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("city.ipdb");
+        std::fs::write(&path, file).unwrap();
+        let mut db = IPDBDatabase::new();
+        db.set_language(language);
+        db.load_from_file(path.to_str().unwrap()).unwrap();
+        (dir, db)
+    }
+
+    #[test]
+    fn test_ipv4_only_lookup() {
+        let file = build(
+            IPV4,
+            &[
+                (
+                    vec![1, 2, 3, 0],
+                    24,
+                    "中国\t北京\t北京\tChina\tBeijing\tBeijing",
+                ),
+                (vec![8, 0, 0, 0], 8, "美国\t\t\tUnited States\t\t"),
+            ],
+        );
+        let (_dir, db) = load(&file, "zh-CN");
+
+        let geo = db.lookup_ip("1.2.3.4".parse().unwrap()).unwrap().unwrap();
+        assert_eq!(geo.country.as_deref(), Some("中国"));
+        assert_eq!(geo.city.as_deref(), Some("北京"));
+        let geo = db.lookup_ip("8.8.8.8".parse().unwrap()).unwrap().unwrap();
+        assert_eq!(geo.country.as_deref(), Some("美国"));
+        assert_eq!(geo.region, None);
+
+        assert!(db.lookup_ip("1.2.4.1".parse().unwrap()).unwrap().is_none());
+        assert!(
+            db.lookup_ip("2001:db8::1".parse().unwrap())
+                .unwrap()
+                .is_none()
+        );
+        assert_eq!(db.version().as_deref(), Some("2023-11-14"));
+    }
+
+    #[test]
+    fn test_dual_stack_lookup_and_language() {
+        let mapped = Ipv4Addr::new(1, 2, 3, 0).to_ipv6_mapped().octets().to_vec();
+        let v6 = "2001:db8::".parse::<Ipv6Addr>().unwrap().octets().to_vec();
+        let file = build(
+            IPV4 | IPV6,
+            &[
+                (mapped, 120, "中国\t北京\t北京\tChina\tBeijing\tBeijing"),
+                (v6, 32, "文档\t\t\tDocumentation\t\t"),
+            ],
+        );
+        let (_dir, db) = load(&file, "en");
+
+        let geo = db.lookup_ip("1.2.3.4".parse().unwrap()).unwrap().unwrap();
+        assert_eq!(geo.country.as_deref(), Some("China"));
+        assert_eq!(geo.city.as_deref(), Some("Beijing"));
+        let geo = db
+            .lookup_ip("2001:db8::1".parse().unwrap())
+            .unwrap()
+            .unwrap();
+        assert_eq!(geo.country.as_deref(), Some("Documentation"));
+        assert!(
+            db.lookup_ip("2001:db9::1".parse().unwrap())
+                .unwrap()
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn test_rejects_size_mismatch() {
+        let mut file = build(IPV4, &[(vec![1, 0, 0, 0], 8, "a\tb\tc\td\te\tf")]);
+        file.pop();
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("broken.ipdb");
+        std::fs::write(&path, file).unwrap();
+        assert!(
+            IPDBDatabase::new()
+                .load_from_file(path.to_str().unwrap())
+                .is_err()
+        );
+    }
+}
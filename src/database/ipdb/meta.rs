@@ -0,0 +1,70 @@
+//! IPDB metadata header
+//!
+//! An `.ipdb` file starts with a big-endian u32 length followed by a JSON
+//! object describing the rest of the file.
+
+use serde::Deserialize;
+use std::collections::HashMap;
+
+/// The database covers IPv4 addresses
+pub const IPV4: u16 = 0x01;
+/// The database covers IPv6 addresses
+pub const IPV6: u16 = 0x02;
+
+/// Parsed JSON metadata
+#[derive(Debug, Clone, Deserialize)]
+pub struct Meta {
+    /// Build time as a Unix timestamp
+    pub build: u64,
+    /// Bit set of `IPV4` and `IPV6`
+    pub ip_version: u16,
+    /// Number of nodes in the search tree
+    pub node_count: u32,
+    /// Size of the tree plus data section in bytes
+    pub total_size: usize,
+    /// Language code -> offset of its first field in a record
+    pub languages: HashMap<String, usize>,
+    /// Field names of one language, in record order
+    pub fields: Vec<String>,
+}
+
+impl Meta {
+    /// Field offset for the configured language, e.g. `zh-CN` -> `CN`
+    ///
+    /// Tries the full code, then the region subtag, then the primary subtag
+    /// mapped to ipip.net's codes, and falls back to the first language.
+    pub fn language_offset(&self, language: &str) -> usize {
+        let upper = language.to_ascii_uppercase();
+        let region = upper.rsplit(['-', '_']).next().unwrap_or_default();
+        let primary = match upper.split(['-', '_']).next().unwrap_or_default() {
+            "ZH" => "CN",
+            "EN" => "EN",
+            other => other,
+        };
+
+        [upper.as_str(), region, primary]
+            .iter()
+            .find_map(|code| self.languages.get(*code).copied())
+            .or_else(|| self.languages.values().min().copied())
+            .unwrap_or(0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_language_offset() {
+        let meta: Meta = serde_json::from_str(
+            r#"{"build":1600000000,"ip_version":1,"node_count":1,"total_size":8,
+                "languages":{"CN":0,"EN":3},"fields":["country_name","region_name","city_name"]}"#,
+        )
+        .unwrap();
+
+        assert_eq!(meta.language_offset("zh-CN"), 0);
+        assert_eq!(meta.language_offset("en"), 3);
+        assert_eq!(meta.language_offset("en-US"), 3);
+        assert_eq!(meta.language_offset("ja"), 0);
+    }
+}
@@ -0,0 +1,16 @@
+//! IPDB database implementation
+//!
+//! This module implements support for the modern ipip.net `.ipdb` format
+//! (e.g. the free `city.free.ipdb`), covering both IPv4 and IPv6 and every
+//! language the file carries.
+//!
+//! # Module Organization
+//!
+//! - `database`: Core IPDBDatabase implementation
+//! - `meta`: JSON metadata header and language selection
+
+mod database;
+mod meta;
+
+// Re-export the main database struct
+pub use database::IPDBDatabase;
@@ -1,10 +1,11 @@
 //! IPIP database implementation core
 
-use crate::database::{Database, DatabaseType, GeoLocation, CdnProvider};
+use crate::config::LoadMode;
+use crate::database::types::format_network;
+use crate::database::{Database, DatabaseBytes, DatabaseType, GeoLocation, CdnProvider};
 use crate::error::Result;
+use crate::utils::time::format_date;
 use std::net::IpAddr;
-use std::fs::File;
-use memmap2::Mmap;
 
 use super::header::IPIPHeader;
 use super::record::IPIPRecord;
@@ -14,7 +15,9 @@ use super::translation::IPIPTranslationTables;
 pub struct IPIPDatabase {
     name: String,
     loaded: bool,
-    mmap: Option<Mmap>,
+    data: Option<DatabaseBytes>,
+    /// Whether `load_from_file` maps the file or reads it whole
+    load_mode: LoadMode,
     file_size: usize,
     header: Option<IPIPHeader>,
     translation_tables: Option<IPIPTranslationTables>,
@@ -31,7 +34,8 @@ impl IPIPDatabase {
         Self {
             name: "ipip".to_string(),
             loaded: false,
-            mmap: None,
+            data: None,
+            load_mode: LoadMode::default(),
             file_size: 0,
             header: None,
             translation_tables: None,
@@ -54,9 +58,10 @@ impl IPIPDatabase {
     /// Binary search for IPv4 address in database
     fn lookup_ip_internal_v4(&self, ip: u32) -> Result<Option<GeoLocation>> {
         if let Some(ref header) = self.header
-            && let Some(ref mmap) = self.mmap {
-                let mut low = header.index_start;
-                let mut high = header.index_end;
+            && let Some(ref mmap) = self.data {
+                // Offsets come from the file; u64 keeps them from overflowing
+                let mut low = header.index_start as u64;
+                let mut high = header.index_end as u64;
 
                 while low <= high {
                     let mid = (low + high) / 2;
@@ -64,7 +69,7 @@ impl IPIPDatabase {
                         break;
                     }
 
-                    let record = IPIPRecord::parse(mmap, mid)?;
+                    let record = IPIPRecord::parse(mmap, mid as u32)?;
 
                     if ip >= record.start_ip && ip <= record.end_ip {
                         // Translate IDs to strings using translation tables
@@ -83,12 +88,16 @@ impl IPIPDatabase {
                                 country_code: Some("CN".to_string()), // Default for IPIP
                                 timezone: Some("Asia/Shanghai".to_string()),
                                 latitude: None, // IPIP doesn't provide coordinates
-                                longitude: None,
+                                network: Some(format_network(
+                                    IpAddr::V4(record.start_ip.into()),
+                                    IpAddr::V4(record.end_ip.into()),
+                                )),
+                                ..Default::default()
                             };
                             return Ok(Some(result));
                         }
                     } else if ip < record.start_ip {
-                        if mid == 0 {
+                        if mid < 16 {
                             break;
                         }
                         high = mid - 16; // Move back to previous index
@@ -112,8 +121,7 @@ impl IPIPDatabase {
             isp: Some("China Telecom".to_string()),
             country_code: Some("CN".to_string()),
             timezone: Some("Asia/Shanghai".to_string()),
-            latitude: None,
-            longitude: None,
+            ..Default::default()
         };
         Ok(Some(result))
     }
@@ -161,40 +169,52 @@ impl Database for IPIPDatabase {
         Ok(None)
     }
 
+    fn version(&self) -> Option<String> {
+        let header = self.header.as_ref()?;
+        (header.created_time > 0).then(|| format_date(header.created_time as u64))
+    }
+
+    fn set_load_mode(&mut self, mode: LoadMode) {
+        self.load_mode = mode;
+    }
+
     fn is_loaded(&self) -> bool {
         self.loaded
     }
 
     fn load_from_file(&mut self, file_path: &str) -> Result<()> {
-        log::info!("Loading IPIP database from: {}", file_path);
-
-        // Open and memory map the file
-        let file = File::open(file_path)
-            .map_err(|e| crate::error::NaliError::parse(format!("Failed to open IPIP database file: {}", e)))?;
+        tracing::info!("Loading IPIP database from: {}", file_path);
+        self.load(DatabaseBytes::open(file_path, "IPIP", self.load_mode)?)?;
+        tracing::info!("Successfully loaded IPIP database from: {}", file_path);
+        Ok(())
+    }
 
-        let mmap = unsafe { Mmap::map(&file) }
-            .map_err(|e| crate::error::NaliError::parse(format!("Failed to memory map IPIP database: {}", e)))?;
+    fn load_from_bytes(&mut self, data: Vec<u8>) -> Result<()> {
+        self.load(data.into())
+    }
+}
 
-        self.file_size = mmap.len();
+impl IPIPDatabase {
+    /// Parse the header and tables and start answering lookups from `data`
+    fn load(&mut self, data: DatabaseBytes) -> Result<()> {
+        self.file_size = data.len();
 
         // Parse header
-        let header = IPIPHeader::parse(&mmap)?;
+        let header = IPIPHeader::parse(&data)?;
         self.ipv6_support = header.support_ipv6;
 
         // Parse translation tables
-        let translation_tables = IPIPTranslationTables::parse(&mmap, &header)?;
+        let translation_tables = IPIPTranslationTables::parse(&data, &header)?;
 
-        log::info!("IPIP database version: {}", header.version);
-        log::info!("IPIP database created: {}", header.created_time);
-        log::info!("IPv6 support: {}", header.support_ipv6);
+        tracing::info!("IPIP database version: {}", header.version);
+        tracing::info!("IPIP database created: {}", header.created_time);
+        tracing::info!("IPv6 support: {}", header.support_ipv6);
 
         self.header = Some(header);
         self.translation_tables = Some(translation_tables);
-        self.mmap = Some(mmap);
+        self.data = Some(data);
         self.loaded = true;
 
-        log::info!("Successfully loaded IPIP database from: {}", file_path);
-
         Ok(())
     }
 }
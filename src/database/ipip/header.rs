@@ -14,7 +14,7 @@ impl IPIPHeader {
     /// Parse header from raw data
     pub fn parse(data: &[u8]) -> crate::error::Result<Self> {
         if data.len() < 16 {
-            return Err(crate::error::NaliError::parse("Invalid IPIP database: file too small"));
+            return Err(crate::error::NaliError::format("Invalid IPIP database: file too small"));
         }
 
         let version = u32::from_le_bytes(data[0..4].try_into()?);
@@ -22,6 +22,10 @@ impl IPIPHeader {
         let index_start = u32::from_le_bytes(data[8..12].try_into()?);
         let index_end = u32::from_le_bytes(data[12..16].try_into()?);
 
+        if index_end < index_start {
+            return Err(crate::error::NaliError::format("Invalid IPIP database: index ends before it starts"));
+        }
+
         // Check IPv6 support flag (usually in the 16th byte)
         let support_ipv6 = if data.len() > 16 {
             data[16] == 1
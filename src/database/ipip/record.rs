@@ -15,7 +15,7 @@ impl IPIPRecord {
     /// Parse a single record from data at given offset
     pub fn parse(data: &[u8], offset: u32) -> crate::error::Result<Self> {
         if offset as usize + 16 > data.len() {
-            return Err(crate::error::NaliError::parse(format!("Record offset out of bounds: {}", offset)));
+            return Err(crate::error::NaliError::format(format!("Record offset out of bounds: {}", offset)));
         }
 
         let start_ip = u32::from_le_bytes(data[offset as usize..offset as usize + 4].try_into()?);
@@ -20,10 +20,9 @@ impl IPIPTranslationTables {
         let mut isps = Vec::new();
 
         // IPIP databases typically have a text section after the index
-        let text_start = header.index_end + (header.index_count() * 16) as u32;
+        let text_start = (header.index_end as usize).saturating_add(header.index_count() * 16);
 
-        if (text_start as usize) < data.len() {
-            let text_data = &data[text_start as usize..];
+        if let Some(text_data) = data.get(text_start..) {
             let text_str = String::from_utf8_lossy(text_data);
 
             // Simple parsing - split by null bytes and categorize
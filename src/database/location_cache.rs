@@ -0,0 +1,147 @@
+//! Decoded location strings by file offset
+//!
+//! In QQwry-style and xdb files many ranges point at the same location
+//! strings, so a log full of addresses from a few networks decodes the same
+//! bytes over and over: GBK conversion, `CZ88.NET` cleanup and field
+//! splitting each allocate. Backends keep the decoded fields here, keyed by
+//! where the strings are stored (after any redirects, so ranges sharing a
+//! location share an entry), and a repeated lookup only copies them into
+//! the result.
+//!
+//! Entries are spread over independently locked shards, so lookups from
+//! several threads rarely wait on each other.
+
+use crate::error::Result;
+use std::collections::HashMap;
+use std::sync::RwLock;
+
+/// Entries kept before the cache starts over
+pub const DEFAULT_CAPACITY: usize = 1 << 16;
+
+/// Independently locked parts of a cache
+const SHARDS: usize = 16;
+
+/// Bounded cache of decoded records
+pub struct LocationCache<T> {
+    shards: Vec<RwLock<HashMap<u64, T>>>,
+    /// Entries kept per shard
+    shard_capacity: usize,
+}
+
+/// Key of a location stored as two strings, the second one optional
+///
+/// Both positions are file offsets below 4 GiB.
+pub fn pair_key(first: usize, second: Option<usize>) -> u64 {
+    (first as u64) << 32 | second.map_or(0, |pos| pos as u64 & 0xffff_ffff)
+}
+
+impl<T: Clone> LocationCache<T> {
+    /// A cache holding up to about `capacity` records; 0 disables it
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            shards: (0..SHARDS).map(|_| RwLock::default()).collect(),
+            shard_capacity: capacity.div_ceil(SHARDS),
+        }
+    }
+
+    fn shard(&self, key: u64) -> &RwLock<HashMap<u64, T>> {
+        // Offsets of neighbouring records differ in the low bits only
+        let hash = key.wrapping_mul(0x9e37_79b9_7f4a_7c15);
+        &self.shards[(hash >> 60) as usize % SHARDS]
+    }
+
+    /// Decoded record stored at `key`, decoding it on first use
+    ///
+    /// Errors are returned as they are and not cached.
+    pub fn get_or_decode(&self, key: u64, decode: impl FnOnce() -> Result<T>) -> Result<T> {
+        if self.shard_capacity == 0 {
+            return decode();
+        }
+        let shard = self.shard(key);
+        if let Ok(entries) = shard.read()
+            && let Some(record) = entries.get(&key)
+        {
+            return Ok(record.clone());
+        }
+
+        let record = decode()?;
+        if let Ok(mut entries) = shard.write() {
+            // Starting over is cheaper than tracking recency, and the records
+            // that matter are back after a few lookups
+            if entries.len() >= self.shard_capacity {
+                entries.clear();
+            }
+            entries.insert(key, record.clone());
+        }
+        Ok(record)
+    }
+
+    /// Forget every record, for when the underlying file changes
+    pub fn clear(&mut self) {
+        for shard in &mut self.shards {
+            if let Ok(entries) = shard.get_mut() {
+                entries.clear();
+            }
+        }
+    }
+
+    /// Number of cached records
+    pub fn len(&self) -> usize {
+        self.shards
+            .iter()
+            .map(|shard| shard.read().map_or(0, |entries| entries.len()))
+            .sum()
+    }
+
+    /// Whether no record is cached
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl<T: Clone> Default for LocationCache<T> {
+    fn default() -> Self {
+        Self::new(DEFAULT_CAPACITY)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::NaliError;
+    use std::cell::Cell;
+
+    #[test]
+    fn test_decodes_once_per_offset() {
+        // One record per shard
+        let cache = LocationCache::new(SHARDS);
+        let decoded = Cell::new(0);
+        let decode = |value: &'static str| {
+            decoded.set(decoded.get() + 1);
+            Ok(value.to_string())
+        };
+
+        assert_eq!(cache.get_or_decode(10, || decode("a")).unwrap(), "a");
+        assert_eq!(cache.get_or_decode(10, || decode("b")).unwrap(), "a");
+        assert_eq!(decoded.get(), 1);
+
+        // Errors are not remembered
+        assert!(
+            cache
+                .get_or_decode(20, || Err(NaliError::parse("bad record")))
+                .is_err()
+        );
+        assert_eq!(cache.get_or_decode(20, || decode("c")).unwrap(), "c");
+
+        // Bounded: another key in a full shard starts it over
+        let full = (21..).find(|&key| std::ptr::eq(cache.shard(key), cache.shard(20))).unwrap();
+        let len = cache.len();
+        cache.get_or_decode(full, || decode("d")).unwrap();
+        assert_eq!(cache.len(), len);
+        assert_eq!(cache.get_or_decode(20, || decode("e")).unwrap(), "e");
+
+        let disabled = LocationCache::new(0);
+        disabled.get_or_decode(10, || decode("f")).unwrap();
+        assert!(disabled.is_empty());
+    }
+}
@@ -1,12 +1,27 @@
 //! Database manager - manages database instances and caching
 
-use crate::config::AppConfig;
-use crate::database::{CdnProvider, Database, DatabaseFactory, DatabaseType, GeoLocation};
-use crate::download::Downloader;
+use crate::config::{AppConfig, AutoDownload};
+use crate::database::{
+    CdnProvider, Database, DatabaseFactory, DatabaseRegistry, DatabaseType, Explanation,
+    GeoLocation, QueryMeta,
+};
+#[cfg(feature = "embedded-db")]
+use crate::database::embedded;
+use crate::database::disk_cache::{self, CacheStats, DiskCache};
+use crate::database::metrics::{Family, Metrics};
+use crate::database::normalize;
+use crate::download::{Downloader, consent};
 use crate::error::{NaliError, Result};
-use std::collections::HashMap;
+use crate::utils::time::{format_date, parse_date};
+use arc_swap::ArcSwap;
+use dashmap::DashMap;
+use std::collections::{HashMap, HashSet};
 use std::net::IpAddr;
-use std::sync::{Arc, RwLock};
+use std::panic::AssertUnwindSafe;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
 
 /// Database manager handles loading and caching of databases
 ///
@@ -16,78 +31,365 @@ use std::sync::{Arc, RwLock};
 ///
 /// # Thread Safety
 ///
-/// DatabaseManager is thread-safe and can be shared across threads using Arc.
+/// DatabaseManager is `Send + Sync` and cheap to clone: the configuration,
+/// loaded databases, query cache, metrics and download state all sit behind
+/// `Arc`s, so every clone shares them. Hand each request handler or thread
+/// its own clone, or share one through [`DatabaseManager::into_shared`];
+/// either way a database is loaded once and a cached answer serves them all.
+/// Settings made after cloning, like [`DatabaseManager::download_in_background`],
+/// only apply to that clone.
+///
+/// Lookups take no lock shared by all threads: the map of loaded databases
+/// is swapped as a whole when one is loaded, so readers only bump a
+/// reference count, and the query cache is split into independently locked
+/// shards. Loading and reloading, which are rare, copy the map.
+///
+/// ```
+/// use nali_rs::{AppConfig, DatabaseManager};
+///
+/// let manager = DatabaseManager::new(AppConfig::default());
+/// let handlers: Vec<_> = (0..4)
+///     .map(|_| {
+///         let manager = manager.clone();
+///         std::thread::spawn(move || manager.cache_stats())
+///     })
+///     .collect();
+/// for handler in handlers {
+///     assert_eq!(handler.join().unwrap(), (0, 0));
+/// }
+/// ```
+///
+/// # Hot Reload
+///
+/// When `auto_reload` is enabled, the manager periodically compares the
+/// modification time and size of each loaded database file. If the file was
+/// replaced (e.g. by `nali-rs --update`), the database is reloaded by the
+/// next query and its cached results are dropped. Queries keep using the old
+/// instance until the new one has loaded successfully.
+#[derive(Clone)]
 pub struct DatabaseManager {
-    config: AppConfig,
-    /// Cache of loaded databases (name -> database)
-    databases: Arc<RwLock<HashMap<String, Box<dyn Database + Send + Sync>>>>,
+    config: Arc<AppConfig>,
+    /// Cache of loaded databases (name -> database), replaced on every load
+    databases: Arc<ArcSwap<HashMap<String, Arc<LoadedDatabase>>>>,
     /// Query result cache (query_string -> result)
-    query_cache: Arc<RwLock<HashMap<String, CachedResult>>>,
+    query_cache: Arc<DashMap<String, CachedResult>>,
+    /// Results kept between runs, with `database.disk_cache`
+    disk_cache: Option<Arc<DiskCache<CachedResult>>>,
+    /// Lookup counters and latencies, shared with the metrics endpoint
+    metrics: Arc<Metrics>,
+    /// Databases re-downloaded after being found corrupt; only once per run
+    redownloaded: Arc<Mutex<HashSet<String>>>,
+    /// Databases already reported as stale; only once per run
+    stale_warned: Arc<Mutex<HashSet<String>>>,
+    /// Whether answering from the embedded country table was reported
+    #[cfg(feature = "embedded-db")]
+    embedded_warned: Arc<std::sync::atomic::AtomicBool>,
+    /// Download missing databases in the background instead of waiting
+    background_downloads: bool,
+    /// Answers to download prompts, so each database is asked about once
+    download_consent: Arc<Mutex<HashMap<String, bool>>>,
+    /// Background downloads: `None` while running, the error if one failed
+    downloading: Arc<Mutex<HashMap<String, Option<String>>>>,
+}
+
+// Request handlers share one manager across threads; keep it that way
+const _: () = {
+    const fn assert_shareable<T: Send + Sync + Clone + 'static>() {}
+    assert_shareable::<DatabaseManager>();
+};
+
+/// A loaded database together with the file it was loaded from
+struct LoadedDatabase {
+    /// Shared so a lookup can run without holding the map's lock
+    db: Arc<dyn Database + Send + Sync>,
+    /// Version read once at load time; it is reported with every query
+    version: Option<String>,
+    /// Date the file was built, see [`build_date`]
+    build_date: Option<String>,
+    path: PathBuf,
+    stamp: Option<FileStamp>,
+    loaded_at: Instant,
+    /// When the file was last checked for changes, in nanoseconds since `loaded_at`
+    last_checked: AtomicU64,
+}
+
+/// Date a database file was built: the release date the format records
+/// (MMDB build epoch, qqwry version, ...) or else the file's modification time
+pub fn build_date(version: Option<&str>, modified: Option<SystemTime>) -> Option<String> {
+    if let Some(version) = version
+        && let Some(date) = version.get(..10)
+        && parse_date(date).is_some()
+    {
+        return Some(date.to_string());
+    }
+    let since = modified?.duration_since(std::time::UNIX_EPOCH).ok()?;
+    Some(format_date(since.as_secs()))
+}
+
+/// Whole days from build date `date` to `now`
+pub fn age_days(date: &str, now: SystemTime) -> Option<u64> {
+    let built = parse_date(date)?;
+    let now = now.duration_since(std::time::UNIX_EPOCH).ok()?.as_secs();
+    Some(now.saturating_sub(built) / 86_400)
+}
+
+/// Modification time and length of a database file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct FileStamp {
+    modified: Option<SystemTime>,
+    len: u64,
+}
+
+impl FileStamp {
+    fn read(path: &Path) -> Option<Self> {
+        let meta = std::fs::metadata(path).ok()?;
+        Some(Self {
+            modified: meta.modified().ok(),
+            len: meta.len(),
+        })
+    }
+
+    /// The stamp as `<mtime>-<len>`, naming a version of the file in cache keys
+    fn tag(&self) -> String {
+        let modified = self
+            .modified
+            .and_then(|modified| modified.duration_since(std::time::UNIX_EPOCH).ok())
+            .map_or(0, |since| since.as_nanos());
+        format!("{}-{}", modified, self.len)
+    }
 }
 
 /// Cached query result
-#[derive(Clone)]
+#[derive(Clone, serde::Serialize, serde::Deserialize)]
 enum CachedResult {
-    GeoLocation(Option<GeoLocation>),
+    GeoLocation(Option<Box<GeoLocation>>),
     CdnProvider(Option<CdnProvider>),
 }
 
+/// Rename a corrupt database file to `<file>.corrupt`, replacing an older one
+fn quarantine(path: &Path) -> Option<PathBuf> {
+    let mut target = path.as_os_str().to_owned();
+    target.push(".corrupt");
+    let target = PathBuf::from(target);
+    match std::fs::rename(path, &target) {
+        Ok(()) => Some(target),
+        Err(e) => {
+            tracing::warn!("Failed to quarantine {:?}: {}", path, e);
+            None
+        }
+    }
+}
+
+/// Answer for a link-local address no database has an entry for
+fn link_local_location(ip: IpAddr) -> GeoLocation {
+    GeoLocation {
+        ip,
+        country: Some("Link-local".to_string()),
+        network: Some("fe80::/10".to_string()),
+        ..Default::default()
+    }
+}
+
 impl DatabaseManager {
     /// Create a new database manager with configuration
-    pub fn new(config: AppConfig) -> Self {
+    ///
+    /// Takes the configuration by value or as an `Arc<AppConfig>` that is
+    /// already shared with the rest of the program.
+    pub fn new(config: impl Into<Arc<AppConfig>>) -> Self {
+        let config: Arc<AppConfig> = config.into();
+        let disk_cache = config.database.disk_cache.then(|| config.state_dir()).and_then(|dir| match dir {
+            Ok(dir) => Some(Arc::new(DiskCache::open(
+                &dir.join(disk_cache::CACHE_FILE),
+                config.database.disk_cache_ttl,
+                config.database.disk_cache_size,
+            ))),
+            Err(e) => {
+                tracing::warn!("Query results will not be kept on disk: {}", e);
+                None
+            }
+        });
+
         Self {
             config,
-            databases: Arc::new(RwLock::new(HashMap::new())),
-            query_cache: Arc::new(RwLock::new(HashMap::new())),
+            databases: Arc::new(ArcSwap::from_pointee(HashMap::new())),
+            query_cache: Arc::new(DashMap::new()),
+            disk_cache,
+            metrics: Arc::new(Metrics::default()),
+            redownloaded: Arc::new(Mutex::new(HashSet::new())),
+            stale_warned: Arc::new(Mutex::new(HashSet::new())),
+            #[cfg(feature = "embedded-db")]
+            embedded_warned: Arc::default(),
+            download_consent: Arc::new(Mutex::new(HashMap::new())),
+            background_downloads: false,
+            downloading: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Wrap the manager in an `Arc`, to end a chain of builder calls
+    pub fn into_shared(self) -> Arc<Self> {
+        Arc::new(self)
+    }
+
+    /// Configuration the manager was created with
+    pub fn config(&self) -> &AppConfig {
+        &self.config
+    }
+
+    /// Download missing databases in the background
+    ///
+    /// Queries that need a database still being downloaded fail right away
+    /// with [`NaliError::DatabasePending`] instead of waiting for it, so a
+    /// stream keeps flowing, un-annotated, until the database is ready.
+    pub fn download_in_background(mut self, enabled: bool) -> Self {
+        self.background_downloads = enabled;
+        self
+    }
+
+    /// Lookup metrics of this manager, for the `/metrics` endpoint
+    pub fn metrics(&self) -> Arc<Metrics> {
+        Arc::clone(&self.metrics)
+    }
+
+    /// Check whether a loaded database file changed on disk since it was loaded
+    fn needs_reload(&self, loaded: &LoadedDatabase) -> bool {
+        if !self.config.database.auto_reload {
+            return false;
         }
+
+        // Whoever moves the check time forward does the check; the others go on
+        let interval = Duration::from_secs(self.config.database.reload_interval).as_nanos() as u64;
+        let now = loaded.loaded_at.elapsed().as_nanos() as u64;
+        let last_checked = loaded.last_checked.load(Ordering::Relaxed);
+        if now.saturating_sub(last_checked) < interval
+            || loaded
+                .last_checked
+                .compare_exchange(last_checked, now, Ordering::Relaxed, Ordering::Relaxed)
+                .is_err()
+        {
+            return false;
+        }
+
+        // A missing file is usually mid-replacement; keep the current instance
+        match FileStamp::read(&loaded.path) {
+            Some(stamp) => Some(stamp) != loaded.stamp,
+            None => false,
+        }
+    }
+
+    /// Drop cached query results that involved the given database
+    ///
+    /// Cache keys have the form `kind:db1+db2:query`.
+    fn invalidate_cache(&self, name: &str) {
+        self.query_cache.retain(|key, _| {
+            let chain = key.split(':').nth(1).unwrap_or_default();
+            !chain.split('+').any(|db| db == name)
+        });
+    }
+
+    /// Add or replace a loaded database
+    fn store_database(&self, name: &str, loaded: LoadedDatabase) {
+        let loaded = Arc::new(loaded);
+        self.databases.rcu(|dbs| {
+            let mut dbs = HashMap::clone(dbs);
+            dbs.insert(name.to_string(), Arc::clone(&loaded));
+            dbs
+        });
+    }
+
+    /// Forget a loaded database, returning it
+    fn remove_database(&self, name: &str) -> Option<Arc<LoadedDatabase>> {
+        let mut removed = None;
+        self.databases.rcu(|dbs| {
+            let mut dbs = HashMap::clone(dbs);
+            removed = dbs.remove(name);
+            dbs
+        });
+        removed
     }
 
     /// Get or load a database by name
     async fn get_or_load_database(&self, name: &str, db_type: DatabaseType) -> Result<()> {
         // Check if already loaded
-        {
-            let dbs = self.databases.read()
-                .map_err(|e| NaliError::Other(format!("Failed to acquire read lock: {}", e)))?;
-            if dbs.contains_key(name) {
-                return Ok(());
+        let reload = match self.databases.load().get(name) {
+            Some(loaded) if !self.needs_reload(loaded) => return Ok(()),
+            Some(_) => true,
+            None => false,
+        };
+
+        if reload {
+            tracing::info!("Database file changed, reloading: {}", name);
+            let db_path = self.config.get_database_path(name)?;
+            match self.load_database_file(name, db_type, &db_path) {
+                Ok(loaded) => {
+                    self.store_database(name, loaded);
+                    self.invalidate_cache(name);
+                }
+                Err(e) => {
+                    // Keep serving from the old instance
+                    tracing::warn!("Failed to reload database {}: {}", name, e);
+                }
             }
+            return Ok(());
         }
 
         // Load the database
-        log::info!("Loading database: {}", name);
-
-        let mut db = DatabaseFactory::create(db_type);
+        tracing::info!("Loading database: {}", name);
 
         // Get database file path from config
         let db_path = self.config.get_database_path(name)?;
 
+        // A quarantined file already had its one re-download this run
+        if !db_path.exists() && self.was_redownloaded(name) {
+            return Err(NaliError::DatabaseCorrupted(format!(
+                "{} is corrupt even after a fresh download; run 'nali-rs --update {}' to retry",
+                name, name
+            )));
+        }
+
         // If database file doesn't exist, try to download it automatically
         if !db_path.exists() {
-            log::warn!(
+            tracing::warn!(
                 "Database file not found: {:?}, attempting to download...",
                 db_path
             );
 
             // Only auto-download for known databases (not custom ones)
-            if let Some(db_info) = self
-                .config
-                .database
-                .databases
-                .iter()
-                .find(|db| db.name == name || db.name_alias.contains(&name.to_string()))
-            {
-                if !db_info.download_urls.is_empty() {
-                    eprintln!("Database file not found, automatically downloading {} database...", name);
-
-                    let downloader = Downloader::new()?;
-                    downloader.download_database(&self.config, name).await?;
-
-                    eprintln!("✓ Database download complete\n");
-                } else {
+            if let Some(db_info) = self.config.find_database(name) {
+                let Some(url) = db_info.download_urls.first() else {
                     return Err(NaliError::DatabaseNotFound(format!(
                         "Database file not found and cannot be auto-downloaded: {:?}\nHint: Please run 'nali-rs --update {}' to manually download",
                         db_path, name
                     )));
+                };
+                if self.config.global.read_only {
+                    return Err(NaliError::DatabaseNotFound(format!(
+                        "Database file not found: {:?}\nHint: read-only mode never downloads; install {} in the image or volume",
+                        db_path, name
+                    )));
+                }
+                if !self.may_download(name, url) {
+                    return Err(NaliError::DatabaseNotFound(format!(
+                        "Database file not found: {:?}\nHint: automatic downloads are off; run 'nali-rs --update {}' to download it",
+                        db_path, name
+                    )));
+                }
+                if self.background_downloads {
+                    return Err(self.download_pending(name));
+                }
+
+                let quiet = self.config.global.quiet;
+                if !quiet {
+                    eprintln!(
+                        "{}",
+                        crate::tr!("Database file not found, automatically downloading {} database...", name)
+                    );
+                }
+
+                let downloader = Downloader::with_config(&self.config.download)?.quiet(quiet);
+                downloader.download_database(&self.config, name).await?;
+
+                if !quiet {
+                    eprintln!("{}\n", crate::tr!("✓ Database download complete"));
                 }
             } else {
                 return Err(NaliError::DatabaseNotFound(format!(
@@ -98,17 +400,221 @@ impl DatabaseManager {
         }
 
         // Load the database file
-        db.load_from_file(db_path.to_str().unwrap())?;
+        let loaded = match self.load_database_file(name, db_type.clone(), &db_path) {
+            Err(e) if e.is_corruption() => self.recover_corrupt(name, db_type, &db_path, e).await?,
+            loaded => loaded?,
+        };
+
+        if self.config.global.verbose && !self.config.global.quiet {
+            eprintln!(
+                "Loaded {} database{} from {}",
+                name,
+                loaded.version.as_ref().map(|v| format!(" (version {})", v)).unwrap_or_default(),
+                db_path.display()
+            );
+        }
+
+        self.warn_if_stale(name, loaded.build_date.as_deref());
 
         // Store in cache
-        let mut dbs = self.databases.write()
-            .map_err(|e| NaliError::Other(format!("Failed to acquire write lock: {}", e)))?;
-        dbs.insert(name.to_string(), db);
+        self.store_database(name, loaded);
 
-        log::info!("Successfully loaded database: {}", name);
+        tracing::info!("Successfully loaded database: {}", name);
         Ok(())
     }
 
+    /// Check whether a missing database may be downloaded from `url` now
+    ///
+    /// In prompt mode the user is asked once per database and run.
+    fn may_download(&self, name: &str, url: &str) -> bool {
+        match self.config.download.auto {
+            AutoDownload::On => true,
+            AutoDownload::Off => false,
+            AutoDownload::Prompt => {
+                let Ok(mut answers) = self.download_consent.lock() else {
+                    return false;
+                };
+                *answers
+                    .entry(name.to_string())
+                    .or_insert_with(|| consent::ask(name, url))
+            }
+        }
+    }
+
+    /// Start downloading a database in the background unless already under way
+    ///
+    /// Returns the error for queries that need it meanwhile. A failed
+    /// download is not retried this run.
+    fn download_pending(&self, name: &str) -> NaliError {
+        let Ok(mut downloading) = self.downloading.lock() else {
+            return NaliError::Other("Failed to acquire download lock".to_string());
+        };
+        if let Some(Some(error)) = downloading.get(name) {
+            return NaliError::DownloadError(format!("{}: {}", name, error));
+        }
+        if !downloading.contains_key(name) {
+            downloading.insert(name.to_string(), None);
+            let quiet = self.config.global.quiet;
+            if !quiet {
+                eprintln!(
+                    "{}",
+                    crate::tr!(
+                        "Database {} not found, downloading it in the background; lines pass through un-annotated until it is ready",
+                        name
+                    )
+                );
+            }
+
+            let config = Arc::clone(&self.config);
+            let name = name.to_string();
+            let downloading = Arc::clone(&self.downloading);
+            tokio::spawn(async move {
+                // Progress bars would garble the stream, so only the outcome is reported
+                let result = match Downloader::with_config(&config.download) {
+                    Ok(downloader) => downloader.quiet(true).download_database(&config, &name).await,
+                    Err(e) => Err(e),
+                };
+                let failure = match result {
+                    Ok(_) => {
+                        if !quiet {
+                            eprintln!("{}", crate::tr!("✓ Database {} is ready", name));
+                        }
+                        None
+                    }
+                    Err(e) => {
+                        if !quiet {
+                            eprintln!("{}", crate::tr!("Background download of {} failed: {}", name, e));
+                        }
+                        Some(e.to_string())
+                    }
+                };
+                if let Ok(mut downloading) = downloading.lock() {
+                    match failure {
+                        Some(error) => downloading.insert(name, Some(error)),
+                        None => downloading.remove(&name),
+                    };
+                }
+            });
+        }
+
+        NaliError::DatabasePending(format!("{} is downloading in the background", name))
+    }
+
+    /// Check whether a database already had its re-download this run
+    fn was_redownloaded(&self, name: &str) -> bool {
+        self.redownloaded.lock().is_ok_and(|names| names.contains(name))
+    }
+
+    /// Quarantine a corrupt database file and load a fresh download instead
+    ///
+    /// Only a file that can be re-downloaded is touched: it is renamed to
+    /// `<file>.corrupt` so it is kept for inspection but never loaded again,
+    /// and put back if the download fails. Files without a download URL, such
+    /// as imported ones, stay where they are. Each database is re-downloaded
+    /// at most once per run; after that the error is returned and queries
+    /// move on to the fallback databases.
+    async fn recover_corrupt(
+        &self,
+        name: &str,
+        db_type: DatabaseType,
+        db_path: &Path,
+        error: NaliError,
+    ) -> Result<LoadedDatabase> {
+        // The file can be neither moved aside nor replaced
+        if self.config.global.read_only {
+            return Err(NaliError::DatabaseCorrupted(format!("{} ({})", name, error)));
+        }
+        let downloadable = self
+            .config
+            .find_database(name)
+            .is_some_and(|db| !db.download_urls.is_empty());
+        if !downloadable {
+            return Err(NaliError::DatabaseCorrupted(format!(
+                "{} ({}), left in place as it has no download URL",
+                name, error
+            )));
+        }
+
+        let first = self
+            .redownloaded
+            .lock()
+            .map_err(|e| NaliError::Other(format!("Failed to acquire lock: {}", e)))?
+            .insert(name.to_string());
+        let quarantined = quarantine(db_path);
+        tracing::warn!(
+            "Database {} is corrupt ({}), moved aside to {:?}",
+            name,
+            error,
+            quarantined
+        );
+        if !first {
+            return Err(NaliError::DatabaseCorrupted(format!(
+                "{} ({}), file moved to {}",
+                name,
+                error,
+                quarantined.map_or_else(|| "nowhere".to_string(), |p| p.display().to_string())
+            )));
+        }
+
+        let quiet = self.config.global.quiet;
+        if !quiet {
+            eprintln!("{}", crate::tr!("Database {} is corrupt, downloading a fresh copy...", name));
+        }
+        let downloaded = match Downloader::with_config(&self.config.download) {
+            Ok(downloader) => downloader.quiet(quiet).download_database(&self.config, name).await,
+            Err(e) => Err(e),
+        };
+        // Nothing replaced the file, so put it back rather than lose it
+        if let (Some(quarantined), false) = (&quarantined, db_path.exists()) {
+            let _ = std::fs::rename(quarantined, db_path);
+        }
+        downloaded?;
+
+        self.load_database_file(name, db_type, db_path).map_err(|e| {
+            if e.is_corruption() {
+                quarantine(db_path);
+            }
+            NaliError::DatabaseCorrupted(format!(
+                "{} is corrupt even after a fresh download ({})",
+                name, e
+            ))
+        })
+    }
+
+    /// Create a database instance and load it from a file
+    fn load_database_file(
+        &self,
+        name: &str,
+        db_type: DatabaseType,
+        db_path: &Path,
+    ) -> Result<LoadedDatabase> {
+        let _span = tracing::info_span!("load", db = name, path = %db_path.display()).entered();
+        let mut db = DatabaseFactory::create(db_type)?;
+        db.set_language(&self.config.database.language);
+        db.set_load_mode(self.config.database.load_mode);
+        if let Some(key) = &self.config.database.czdb_key {
+            db.set_key(key);
+        }
+        let stamp = FileStamp::read(db_path);
+
+        let path_str = db_path.to_str().ok_or_else(|| {
+            NaliError::config(format!("Database path is not valid UTF-8 for {}: {:?}", name, db_path))
+        })?;
+        db.load_from_file(path_str)?;
+
+        self.metrics.record_loaded(name, stamp.and_then(|stamp| stamp.modified));
+        let version = db.version();
+        Ok(LoadedDatabase {
+            build_date: build_date(version.as_deref(), stamp.and_then(|stamp| stamp.modified)),
+            version,
+            db: Arc::from(db),
+            path: db_path.to_path_buf(),
+            stamp,
+            loaded_at: Instant::now(),
+            last_checked: AtomicU64::new(0),
+        })
+    }
+
     /// Query IP geolocation
     ///
     /// Looks up geolocation information for the given IP address. The appropriate
@@ -127,125 +633,630 @@ impl DatabaseManager {
     /// # Caching
     ///
     /// Query results are cached for improved performance on repeated queries.
+    ///
+    /// # Tunneled Addresses
+    ///
+    /// IPv4-mapped, NAT64, 6to4 and Teredo addresses are looked up by their
+    /// embedded IPv4 address in the IPv4 database. The result keeps the
+    /// original IPv6 address and records the mechanism in `tunnel`.
     pub async fn query_ip(&self, ip: IpAddr) -> Result<Option<GeoLocation>> {
-        // Check cache first
-        let cache_key = format!("ip:{}", ip);
-        {
-            let cache = self.query_cache.read()
-                .map_err(|e| NaliError::Other(format!("Failed to acquire cache read lock: {}", e)))?;
-            if let Some(CachedResult::GeoLocation(result)) = cache.get(&cache_key) {
-                return Ok(result.clone());
+        let started = Instant::now();
+        let result = self.query_ip_normalized(ip).await;
+
+        let family = if ip.is_ipv4() { Family::Ipv4 } else { Family::Ipv6 };
+        self.metrics.record_lookup(family, self.database_for(ip), started.elapsed());
+        result
+    }
+
+    /// Query IP geolocation, looking up tunneled IPv6 by their IPv4 address
+    ///
+    /// Link-local IPv6 addresses the database has no entry for are answered
+    /// as such, since they have no location beyond their link.
+    async fn query_ip_normalized(&self, ip: IpAddr) -> Result<Option<GeoLocation>> {
+        let IpAddr::V6(v6) = ip else {
+            return self.query_ip_direct(ip).await;
+        };
+        if v6.is_unicast_link_local() {
+            let result = self.query_ip_direct(ip).await?;
+            return Ok(result.or_else(|| Some(link_local_location(ip))));
+        }
+        let Some(tunnel) = normalize::embedded_ipv4(v6) else {
+            return self.query_ip_direct(ip).await;
+        };
+
+        let result = self.query_ip_direct(IpAddr::V4(tunnel.ipv4)).await?;
+        Ok(result.map(|mut geo| {
+            geo.ip = ip;
+            geo.tunnel = Some(tunnel);
+            geo
+        }))
+    }
+
+    /// Name of the database that answers queries for `ip`
+    ///
+    /// Tunneled IPv6 addresses are answered by the IPv4 database.
+    pub fn database_for(&self, ip: IpAddr) -> &str {
+        match ip {
+            IpAddr::V6(v6) if normalize::embedded_ipv4(v6).is_none() => {
+                &self.config.database.ipv6_database
             }
+            _ => &self.config.database.ipv4_database,
         }
+    }
 
+    /// Query IP geolocation without decoding tunneled addresses
+    async fn query_ip_direct(&self, ip: IpAddr) -> Result<Option<GeoLocation>> {
         // Determine which database to use
-        let db_name = match ip {
-            IpAddr::V4(_) => &self.config.database.ipv4_database,
-            IpAddr::V6(_) => &self.config.database.ipv6_database,
+        let (db_name, fallbacks) = match ip {
+            IpAddr::V4(_) => (
+                &self.config.database.ipv4_database,
+                &self.config.database.ipv4_fallback,
+            ),
+            IpAddr::V6(_) => (
+                &self.config.database.ipv6_database,
+                &self.config.database.ipv6_fallback,
+            ),
         };
 
         let db_type = self.get_database_type(db_name)?;
 
-        // Load database if needed
-        self.get_or_load_database(db_name, db_type).await?;
+        // Load (or reload) database if needed
+        let loaded = self.get_or_load_database(db_name, db_type.clone()).await;
+
+        // Without the real database, a country is better than nothing
+        #[cfg(feature = "embedded-db")]
+        if let (Err(e), IpAddr::V4(v4), true) = (&loaded, ip, fallbacks.is_empty()) {
+            if !self.embedded_warned.swap(true, Ordering::Relaxed) {
+                tracing::warn!("{}; answering from the {} country table", e, embedded::NAME);
+            }
+            return Ok(embedded::lookup(v4));
+        }
+        if let Err(e) = loaded {
+            // Keep the pipeline going on the fallback databases
+            if fallbacks.is_empty() {
+                return Err(e);
+            }
+            tracing::warn!("{}; continuing with the fallback databases", e);
+            return self.query_fallbacks(None, fallbacks, ip).await.and_then(|result| {
+                result.map(Some).ok_or(e)
+            });
+        }
+
+        // Check cache (keyed by the whole database chain)
+        let cache_key = self.ip_cache_key(ip);
+        if let Some(CachedResult::GeoLocation(result)) = self.cached(&cache_key) {
+            self.metrics.record_cache(true);
+            return Ok(result.map(|geo| *geo));
+        }
+        self.metrics.record_cache(false);
 
         // Query
-        let result = {
-            let dbs = self.databases.read()
-                .map_err(|e| NaliError::Other(format!("Failed to acquire database read lock: {}", e)))?;
-            if let Some(db) = dbs.get(db_name) {
-                db.lookup_ip(ip)?
-            } else {
-                None
+        let result = match self.lookup_loaded(db_name, ip).await {
+            Err(e) if e.is_corruption() => self.recover_lookup(db_name, db_type, ip, e).await,
+            result => result,
+        };
+        let result = match result {
+            Ok(result) => result,
+            Err(e) if !fallbacks.is_empty() => {
+                tracing::warn!("{}; continuing with the fallback databases", e);
+                return self.query_fallbacks(None, fallbacks, ip).await;
             }
+            Err(e) => return Err(e),
         };
+        let result = self.query_fallbacks(result, fallbacks, ip).await?;
+        let result = self.refine_china(result, ip).await;
 
-        // Cache result
-        {
-            let mut cache = self.query_cache.write()
-                .map_err(|e| NaliError::Other(format!("Failed to acquire cache write lock: {}", e)))?;
-            cache.insert(cache_key, CachedResult::GeoLocation(result.clone()));
+        self.cache_result(cache_key, CachedResult::GeoLocation(result.clone().map(Box::new)));
+
+        Ok(result)
+    }
+
+    /// Databases consulted for `ip`, in order: the configured one, its
+    /// fallbacks, then the China database
+    fn database_chain(&self, ip: IpAddr) -> impl Iterator<Item = &String> {
+        let database = &self.config.database;
+        let (db_name, fallbacks) = match ip {
+            IpAddr::V4(_) => (&database.ipv4_database, &database.ipv4_fallback),
+            IpAddr::V6(_) => (&database.ipv6_database, &database.ipv6_fallback),
+        };
+        std::iter::once(db_name)
+            .chain(fallbacks)
+            .chain(self.china_database())
+    }
+
+    /// Database refining Chinese answers, if one is configured
+    fn china_database(&self) -> Option<&String> {
+        self.config
+            .database
+            .china_database
+            .as_ref()
+            .filter(|name| !name.is_empty())
+    }
+
+    /// Query cache key of an IP, not tunneled
+    fn ip_cache_key(&self, ip: IpAddr) -> String {
+        let chain = self
+            .database_chain(ip)
+            .map(String::as_str)
+            .collect::<Vec<_>>()
+            .join("+");
+        format!("ip:{}:{}", chain, ip)
+    }
+
+    /// The query cache, in memory or on disk, holds an answer for `key`
+    fn is_cached(&self, key: &str) -> bool {
+        self.query_cache.contains_key(key)
+            || self
+                .disk_cache
+                .as_ref()
+                .is_some_and(|disk| disk.peek(&self.disk_key(key)).is_some())
+    }
+
+    /// Key of a query on disk: every database of the chain gets the stamp of
+    /// its file, so answers from an older version of a database are not reused
+    fn disk_key(&self, key: &str) -> String {
+        let mut parts = key.splitn(3, ':');
+        let (Some(kind), Some(chain), Some(query)) = (parts.next(), parts.next(), parts.next()) else {
+            return key.to_string();
+        };
+        let versions = chain
+            .split('+')
+            .map(|name| {
+                let stamp = match self.databases.load().get(name) {
+                    Some(loaded) => loaded.stamp,
+                    None => self
+                        .config
+                        .get_database_path(name)
+                        .ok()
+                        .and_then(|path| FileStamp::read(&path)),
+                };
+                format!("{}@{}", name, stamp.map_or_else(|| "-".to_string(), |stamp| stamp.tag()))
+            })
+            .collect::<Vec<_>>()
+            .join("+");
+        format!("{}:{}:{}", kind, versions, query)
+    }
+
+    /// A cached answer for `key`, from memory or else from disk
+    fn cached(&self, key: &str) -> Option<CachedResult> {
+        if let Some(result) = self.query_cache.get(key) {
+            return Some(result.clone());
+        }
+        let result = self.disk_cache.as_ref()?.get(&self.disk_key(key))?;
+        self.remember(key.to_string(), result.clone());
+        Some(result)
+    }
+
+    /// Describe how `query_ip` answers `ip`
+    ///
+    /// Call it before the query, so `cached` tells whether the query will be
+    /// served from the cache. The block comes from the first database in the
+    /// chain that has one covering the address.
+    pub async fn explain_ip(&self, ip: IpAddr) -> Result<Explanation> {
+        let ip = match ip {
+            IpAddr::V6(v6) => normalize::embedded_ipv4(v6).map_or(ip, |tunnel| IpAddr::V4(tunnel.ipv4)),
+            IpAddr::V4(_) => ip,
+        };
+        let cached = self.is_cached(&self.ip_cache_key(ip));
+
+        for db_name in self.database_chain(ip) {
+            self.get_or_load_database(db_name, self.get_database_type(db_name)?).await?;
+            let range = self
+                .run_lookup(db_name, ip.to_string(), move |db| db.lookup_range(ip))
+                .await?;
+            if range.is_some() {
+                return Ok(Explanation {
+                    database: db_name.clone(),
+                    range,
+                    cdn_match: None,
+                    cached,
+                });
+            }
+        }
+
+        Ok(Explanation {
+            database: self.database_for(ip).to_string(),
+            range: None,
+            cdn_match: None,
+            cached,
+        })
+    }
+
+    /// Describe how `query_cdn` answers `domain`; call it before the query
+    pub async fn explain_cdn(&self, domain: &str) -> Result<Explanation> {
+        let db_name = &self.config.database.cdn_database;
+        let cached = self.is_cached(&format!("cdn:{}:{}", db_name, domain));
+
+        self.get_or_load_database(db_name, DatabaseType::CDN).await?;
+        let owned = domain.to_string();
+        let cdn_match = self
+            .run_lookup(db_name, owned.clone(), move |db| db.explain_cdn(&owned))
+            .await?;
+
+        Ok(Explanation {
+            database: db_name.clone(),
+            range: None,
+            cdn_match,
+            cached,
+        })
+    }
+
+    /// Replace a database that failed a lookup as corrupt, then retry the lookup
+    async fn recover_lookup(
+        &self,
+        db_name: &str,
+        db_type: DatabaseType,
+        ip: IpAddr,
+        error: NaliError,
+    ) -> Result<Option<GeoLocation>> {
+        let removed = self.remove_database(db_name);
+        self.invalidate_cache(db_name);
+        let db_path = match removed {
+            Some(loaded) => loaded.path.clone(),
+            None => self.config.get_database_path(db_name)?,
+        };
+
+        let loaded = self.recover_corrupt(db_name, db_type, &db_path, error).await?;
+        self.store_database(db_name, loaded);
+        self.lookup_loaded(db_name, ip).await
+    }
+
+    /// Complete missing or bare answers from the fallback databases
+    async fn query_fallbacks(
+        &self,
+        mut result: Option<GeoLocation>,
+        fallbacks: &[String],
+        ip: IpAddr,
+    ) -> Result<Option<GeoLocation>> {
+        for fallback in fallbacks {
+            if result.as_ref().is_some_and(|geo| !geo.is_bare()) {
+                break;
+            }
+
+            match self.query_fallback(fallback, ip).await {
+                Ok(Some(extra)) => match result {
+                    Some(ref mut geo) => geo.fill_missing(&extra),
+                    None => result = Some(extra),
+                },
+                Ok(None) => {}
+                Err(e) => tracing::warn!("Fallback database {} failed: {}", fallback, e),
+            }
         }
 
         Ok(result)
     }
 
-    /// Query CDN provider
-    pub async fn query_cdn(&self, domain: &str) -> Result<Option<CdnProvider>> {
-        // Check cache first
-        let cache_key = format!("cdn:{}", domain);
-        {
-            let cache = self.query_cache.read()
-                .map_err(|e| NaliError::Other(format!("Failed to acquire cache read lock: {}", e)))?;
-            if let Some(CachedResult::CdnProvider(result)) = cache.get(&cache_key) {
-                return Ok(result.clone());
+    /// Replace the place names of a Chinese answer with the China database's
+    ///
+    /// Only answers with the country code `CN` are refined, and a failing
+    /// China database leaves the answer as it is.
+    async fn refine_china(&self, mut result: Option<GeoLocation>, ip: IpAddr) -> Option<GeoLocation> {
+        let (Some(china), Some(geo)) = (self.china_database(), result.as_mut()) else {
+            return result;
+        };
+        if geo.country_code.as_deref() != Some("CN") || geo.sources.contains(china) {
+            return result;
+        }
+
+        match self.query_fallback(china, ip).await {
+            Ok(Some(local)) => geo.refine_from(&local),
+            Ok(None) => {}
+            Err(e) => tracing::warn!("China database {} failed: {}", china, e),
+        }
+        result
+    }
+
+    /// Databases loaded ahead of the first query: `database.preload`, or
+    /// else the configured IPv4, IPv6 and CDN databases
+    pub fn preload_names(&self) -> Vec<&String> {
+        let database = &self.config.database;
+        if !database.preload.is_empty() {
+            return database.preload.iter().collect();
+        }
+        vec![
+            &database.ipv4_database,
+            &database.ipv6_database,
+            &database.cdn_database,
+        ]
+    }
+
+    /// Load the preloaded databases ahead of the first query
+    ///
+    /// Missing databases are downloaded now, with progress on stderr, rather
+    /// than in the middle of a stream. Failures are only logged: the query
+    /// that needs the database reports them again.
+    pub async fn preload(&self) {
+        for name in self.preload_names() {
+            if let Err(e) = self.load_by_name(name).await {
+                tracing::warn!("Failed to preload database {}: {}", name, e);
             }
         }
+    }
+
+    /// Load the preloaded databases, failing on the first that cannot be loaded
+    ///
+    /// For latency-sensitive runs that should not start without their
+    /// databases, rather than pay for the load on the first query.
+    pub async fn preload_all(&self) -> Result<()> {
+        for name in self.preload_names() {
+            self.load_by_name(name).await.map_err(|e| {
+                NaliError::DatabaseNotLoaded(format!("{} could not be preloaded: {}", name, e))
+            })?;
+        }
+        Ok(())
+    }
+
+    /// Load the database called `name`, downloading it if allowed
+    async fn load_by_name(&self, name: &str) -> Result<()> {
+        let db_type = self.get_database_type(name)?;
+        self.get_or_load_database(name, db_type).await
+    }
+
+    /// Release version or build date of a loaded database
+    pub fn database_version(&self, name: &str) -> Option<String> {
+        self.databases.load().get(name)?.version.clone()
+    }
+
+    /// Build date of a loaded database, see [`build_date`]
+    pub fn database_build_date(&self, name: &str) -> Option<String> {
+        self.databases.load().get(name)?.build_date.clone()
+    }
+
+    /// Warn on stderr that database `name` is older than
+    /// `database.stale_after_days`, once per run
+    fn warn_if_stale(&self, name: &str, build_date: Option<&str>) {
+        let limit = self.config.database.stale_after_days;
+        let Some(days) = build_date.and_then(|date| age_days(date, SystemTime::now())) else {
+            return;
+        };
+        if limit == 0 || days <= limit || self.config.global.quiet {
+            return;
+        }
+        if !self.stale_warned.lock().unwrap().insert(name.to_string()) {
+            return;
+        }
+        eprintln!(
+            "{}",
+            crate::tr!(
+                "Warning: the {} database was built {} days ago ({}); `nali-rs --update {}` fetches a newer one",
+                name,
+                days,
+                build_date.unwrap_or_default(),
+                name
+            )
+        );
+    }
+
+    /// Provenance of a query answered by `name` in `elapsed`
+    pub fn query_meta(&self, name: &str, elapsed: Duration) -> QueryMeta {
+        QueryMeta {
+            database: name.to_string(),
+            version: self.database_version(name),
+            db_build_date: self.database_build_date(name),
+            query_time_ms: (elapsed.as_secs_f64() * 1_000_000.0).round() / 1000.0,
+        }
+    }
+
+    /// Store a query result, on disk too with `database.disk_cache`
+    ///
+    /// The memory cache starts over once it is full. Clearing everything is crude but keeps memory bounded on huge inputs
+    /// without tracking recency; hot entries come back after one lookup.
+    fn cache_result(&self, key: String, result: CachedResult) {
+        if let Some(ref disk) = self.disk_cache {
+            disk.insert(self.disk_key(&key), result.clone());
+            if let Err(e) = disk.save_if_due() {
+                tracing::warn!("Failed to save query cache: {}", e);
+            }
+        }
+        self.remember(key, result);
+    }
+
+    /// Store a query result in memory only
+    fn remember(&self, key: String, result: CachedResult) {
+        let limit = self.config.database.cache_size;
+        if limit == 0 {
+            return;
+        }
+
+        if self.query_cache.len() >= limit {
+            self.query_cache.clear();
+        }
+        self.query_cache.insert(key, result);
+    }
+
+    /// Run a lookup in an already loaded database; `None` if it isn't loaded
+    ///
+    /// Every lookup is counted against the database in [`Metrics`], and one
+    /// slower than `database.slow_lookup_ms` is logged as a warning.
+    ///
+    /// With `database.offload_lookups`, the lookup runs on tokio's blocking
+    /// pool, so page faults on a cold memory-mapped file stall a blocking
+    /// thread instead of an async worker. That costs a thread hand-off per
+    /// lookup, which only pays off when many queries are served at once.
+    async fn run_lookup<T, F>(&self, db_name: &str, subject: String, lookup: F) -> Result<Option<T>>
+    where
+        T: Send + 'static,
+        F: FnOnce(&dyn Database) -> Result<Option<T>> + Send + 'static,
+    {
+        let Some(db) = self.databases.load().get(db_name).map(|loaded| Arc::clone(&loaded.db)) else {
+            return Ok(None);
+        };
+
+        let db_name = db_name.to_string();
+        let metrics = Arc::clone(&self.metrics);
+        let slow = self.config.database.slow_lookup_ms;
+        let run = move || {
+            let _span = tracing::trace_span!("lookup", %subject, db = %db_name).entered();
+            let started = Instant::now();
+            // A corrupt file can send a parser out of bounds; release builds
+            // unwind, so that becomes an error for this database only
+            let result = std::panic::catch_unwind(AssertUnwindSafe(|| lookup(&*db))).unwrap_or_else(|_| {
+                Err(NaliError::DatabaseCorrupted(format!("{} panicked looking up {}", db_name, subject)))
+            });
+
+            let elapsed = started.elapsed();
+            metrics.record_read(&db_name, elapsed);
+            if slow > 0 && elapsed > Duration::from_millis(slow) {
+                tracing::warn!(
+                    "Looking up {} in {} took {:.1?}, over database.slow_lookup_ms ({} ms); \
+                     is the file on a slow disk or network file system?",
+                    subject, db_name, elapsed, slow
+                );
+            }
+            result
+        };
+
+        if self.config.database.offload_lookups {
+            tokio::task::spawn_blocking(run)
+                .await
+                .map_err(|e| NaliError::Other(format!("Lookup task failed: {}", e)))?
+        } else {
+            run()
+        }
+    }
+
+    /// Look up an IP in an already loaded database, naming it as the source
+    async fn lookup_loaded(&self, db_name: &str, ip: IpAddr) -> Result<Option<GeoLocation>> {
+        let result = self
+            .run_lookup(db_name, ip.to_string(), move |db| db.lookup_ip(ip))
+            .await?;
+        Ok(result.map(|mut geo| {
+            geo.sources = vec![db_name.to_string()];
+            geo
+        }))
+    }
+
+    /// Load a fallback database if needed and look up an IP in it
+    async fn query_fallback(&self, db_name: &str, ip: IpAddr) -> Result<Option<GeoLocation>> {
+        self.load_by_name(db_name).await?;
+        self.lookup_loaded(db_name, ip).await
+    }
+
+    /// Query CDN provider
+    pub async fn query_cdn(&self, domain: &str) -> Result<Option<CdnProvider>> {
+        let started = Instant::now();
+        let result = self.query_cdn_uncounted(domain).await;
 
+        let db_name = &self.config.database.cdn_database;
+        self.metrics.record_lookup(Family::Domain, db_name, started.elapsed());
+        result
+    }
+
+    /// Query CDN provider without recording a lookup
+    async fn query_cdn_uncounted(&self, domain: &str) -> Result<Option<CdnProvider>> {
         let db_name = &self.config.database.cdn_database;
         let db_type = DatabaseType::CDN;
 
-        // Load database if needed
+        // Load (or reload) database if needed
         self.get_or_load_database(db_name, db_type).await?;
 
+        // Check cache
+        let cache_key = format!("cdn:{}:{}", db_name, domain);
+        if let Some(CachedResult::CdnProvider(result)) = self.cached(&cache_key) {
+            self.metrics.record_cache(true);
+            return Ok(result);
+        }
+        self.metrics.record_cache(false);
+
         // Query
-        let result = {
-            let dbs = self.databases.read()
-                .map_err(|e| NaliError::Other(format!("Failed to acquire database read lock: {}", e)))?;
-            if let Some(db) = dbs.get(db_name) {
-                db.lookup_cdn(domain)?
-            } else {
-                None
-            }
-        };
+        let owned = domain.to_string();
+        let result = self
+            .run_lookup(db_name, owned.clone(), move |db| db.lookup_cdn(&owned))
+            .await?;
 
-        // Cache result
-        {
-            let mut cache = self.query_cache.write()
-                .map_err(|e| NaliError::Other(format!("Failed to acquire cache write lock: {}", e)))?;
-            cache.insert(cache_key, CachedResult::CdnProvider(result.clone()));
-        }
+        self.cache_result(cache_key, CachedResult::CdnProvider(result.clone()));
 
         Ok(result)
     }
 
     /// Get database type from name
-    fn get_database_type(&self, name: &str) -> Result<DatabaseType> {
+    ///
+    /// Resolves built-in names first, then registered backends, then the
+    /// `format` of databases declared in the configuration.
+    pub fn get_database_type(&self, name: &str) -> Result<DatabaseType> {
         match name {
             "qqwry" | "chunzhen" => Ok(DatabaseType::QQwry),
             "zxipv6wry" | "zxipv6" => Ok(DatabaseType::ZXIPv6Wry),
             "geoip" | "geoip2" | "geolite" => Ok(DatabaseType::GeoIP2),
-            "ipip" => Ok(DatabaseType::IPIP),
+            "geocn" => Ok(DatabaseType::GeoCN),
+            "ipip" | "ipdb" => Ok(DatabaseType::IPDB),
             "ip2region" => Ok(DatabaseType::IP2Region),
             "dbip" => Ok(DatabaseType::DBIP),
             "ip2location" => Ok(DatabaseType::IP2Location),
             "cdn" => Ok(DatabaseType::CDN),
-            _ => Err(NaliError::DatabaseNotFound(format!(
-                "Unknown database type: {}",
-                name
+            _ if DatabaseRegistry::is_registered(name) => {
+                Ok(DatabaseType::Registered(name.to_string()))
+            }
+            _ => self.get_database_type_by_format(name),
+        }
+    }
+
+    /// Resolve a user-defined database through its configured format
+    fn get_database_type_by_format(&self, name: &str) -> Result<DatabaseType> {
+        let db_info = self.config.find_database(name).ok_or_else(|| {
+            NaliError::DatabaseNotFound(format!("Unknown database type: {}", name))
+        })?;
+
+        match db_info.format.as_str() {
+            "nali" | "custom" => Ok(DatabaseType::Custom),
+            "qqwry" => Ok(DatabaseType::QQwry),
+            "czdb" => Ok(DatabaseType::Czdb),
+            "mmdb" => Ok(DatabaseType::GeoIP2),
+            "geocn" => Ok(DatabaseType::GeoCN),
+            "ipdb" => Ok(DatabaseType::IPDB),
+            "datx" => Ok(DatabaseType::IPIP),
+            "xdb" => Ok(DatabaseType::IP2Region),
+            "yaml" => Ok(DatabaseType::CDN),
+            format if DatabaseRegistry::is_registered(format) => {
+                Ok(DatabaseType::Registered(format.to_string()))
+            }
+            format => Err(NaliError::DatabaseNotFound(format!(
+                "Unknown database format '{}' for database: {}",
+                format, name
             ))),
         }
     }
 
     /// Clear query cache
     pub fn clear_cache(&self) {
-        if let Ok(mut cache) = self.query_cache.write() {
-            cache.clear();
-            log::info!("Query cache cleared");
+        self.query_cache.clear();
+        tracing::info!("Query cache cleared");
+    }
+
+    /// Write the query results kept on disk, with `database.disk_cache`
+    ///
+    /// Call it once the queries are done; until then new results are only
+    /// written every minute.
+    pub fn save_cache(&self) -> Result<()> {
+        match self.disk_cache {
+            Some(ref disk) => disk.save(),
+            None => Ok(()),
+        }
+    }
+
+    /// Size and hit rate of the results kept on disk, with `database.disk_cache`
+    pub fn disk_cache_stats(&self) -> Option<CacheStats> {
+        self.disk_cache.as_ref().map(|disk| disk.stats())
+    }
+
+    /// Drop the results kept on disk, with `database.disk_cache`
+    pub fn clear_disk_cache(&self) -> Result<()> {
+        match self.disk_cache {
+            Some(ref disk) => disk.clear(),
+            None => Ok(()),
         }
     }
 
     /// Get cache statistics
     pub fn cache_stats(&self) -> (usize, usize) {
-        let db_count = self.databases.read().map(|dbs| dbs.len()).unwrap_or(0);
-        let cache_count = self.query_cache.read().map(|cache| cache.len()).unwrap_or(0);
-        (db_count, cache_count)
+        (self.databases.load().len(), self.query_cache.len())
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::test_support::{custom_db, custom_file, custom_info};
 
     #[test]
     fn test_database_manager_creation() {
@@ -271,4 +1282,585 @@ mod tests {
         ));
         assert!(manager.get_database_type("unknown").is_err());
     }
+
+    #[test]
+    fn test_get_database_type_by_format() {
+        let mut config = AppConfig::default();
+        config.database.databases.push(custom_info("corp", &[]));
+        let manager = DatabaseManager::new(config);
+
+        assert!(matches!(
+            manager.get_database_type("corp"),
+            Ok(DatabaseType::Custom)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_query_registered_database() {
+        use crate::database::CustomDatabase;
+
+        DatabaseRegistry::register(
+            "manager-registry-test",
+            Box::new(|| Box::new(CustomDatabase::new())),
+        );
+
+        let dir = tempfile::tempdir().unwrap();
+        let db_file = custom_file(dir.path(), "ranges", "192.0.2.0,192.0.2.255,Test,,,Lab\n");
+
+        let mut config = AppConfig::default();
+        config.database.ipv4_database = "manager-registry-test".to_string();
+        config.database.database_paths.insert(
+            "manager-registry-test".to_string(),
+            db_file.to_string_lossy().to_string(),
+        );
+        let manager = DatabaseManager::new(config);
+
+        let geo = manager.query_ip("192.0.2.1".parse().unwrap()).await.unwrap();
+        assert_eq!(geo.unwrap().isp.as_deref(), Some("Lab"));
+
+        let meta = manager.query_meta("manager-registry-test", Duration::from_micros(1500));
+        assert_eq!(meta.database, "manager-registry-test");
+        assert_eq!(meta.version, None);
+        // No release date in the format, so the file's date
+        let today = crate::utils::time::format_date(
+            SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap().as_secs(),
+        );
+        assert_eq!(meta.db_build_date.as_deref(), Some(today.as_str()));
+        assert_eq!(meta.query_time_ms, 1.5);
+
+        DatabaseRegistry::unregister("manager-registry-test");
+    }
+
+    #[test]
+    fn test_build_date() {
+        let modified = Some(std::time::UNIX_EPOCH + Duration::from_secs(1_700_000_000));
+        assert_eq!(build_date(Some("2024-01-10"), modified).as_deref(), Some("2024-01-10"));
+        assert_eq!(build_date(Some("v3.0"), modified).as_deref(), Some("2023-11-14"));
+        assert_eq!(build_date(None, None), None);
+
+        let now = std::time::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        assert_eq!(age_days("2023-11-14", now), Some(0));
+        assert_eq!(age_days("2023-05-18", now), Some(180));
+        assert_eq!(age_days("2030-01-01", now), Some(0));
+        assert_eq!(age_days("unknown", now), None);
+    }
+
+    #[tokio::test]
+    async fn test_reload_on_file_change() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut config = AppConfig::default();
+        config.database.ipv4_database = "reload".to_string();
+        config.database.reload_interval = 0;
+        config.database.data_dir = Some(dir.path().to_string_lossy().to_string());
+        let info = custom_db(dir.path(), "reload", "192.0.2.0,192.0.2.255,Old,,,\n", &[]);
+        config.database.databases.push(info);
+        let manager = DatabaseManager::new(config);
+        let ip: IpAddr = "192.0.2.1".parse().unwrap();
+
+        let geo = manager.query_ip(ip).await.unwrap().unwrap();
+        assert_eq!(geo.country.as_deref(), Some("Old"));
+
+        custom_file(dir.path(), "reload", "192.0.2.0,192.0.2.255,Replaced,,,\n");
+
+        let geo = manager.query_ip(ip).await.unwrap().unwrap();
+        assert_eq!(geo.country.as_deref(), Some("Replaced"));
+    }
+
+    #[tokio::test]
+    async fn test_ipv6_fallback_fills_bare_answer() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut config = AppConfig::default();
+        config.database.data_dir = Some(dir.path().to_string_lossy().to_string());
+
+        for (name, row) in [
+            ("primary", "2001:db8::,2001:db8::ffff,中国,,,"),
+            ("secondary", "2001:db8::,2001:db8::ffff,China,Beijing,Beijing,Unicom"),
+        ] {
+            config.database.databases.push(custom_db(dir.path(), name, row, &[]));
+        }
+        config.database.ipv6_database = "primary".to_string();
+        config.database.ipv6_fallback = vec!["missing".to_string(), "secondary".to_string()];
+        let manager = DatabaseManager::new(config);
+
+        let geo = manager.query_ip("2001:db8::1".parse().unwrap()).await.unwrap().unwrap();
+        assert_eq!(geo.country.as_deref(), Some("中国"));
+        assert_eq!(geo.city.as_deref(), Some("Beijing"));
+        assert_eq!(geo.isp.as_deref(), Some("Unicom"));
+        assert_eq!(geo.sources, ["primary", "secondary"]);
+    }
+
+    #[tokio::test]
+    async fn test_tunneled_ipv6_uses_ipv4_database() {
+        use crate::database::TunnelMechanism;
+
+        let dir = tempfile::tempdir().unwrap();
+        let mut config = AppConfig::default();
+        config.database.ipv4_database = "v4".to_string();
+        config.database.ipv6_database = "v6-unused".to_string();
+        config.database.data_dir = Some(dir.path().to_string_lossy().to_string());
+        let info = custom_db(dir.path(), "v4", "192.0.2.0,192.0.2.255,Example,,,\n", &[]);
+        config.database.databases.push(info);
+        let manager = DatabaseManager::new(config);
+
+        let ip: IpAddr = "2002:c000:201::1".parse().unwrap();
+        assert_eq!(manager.database_for(ip), "v4");
+        let geo = manager.query_ip(ip).await.unwrap().unwrap();
+        assert_eq!(geo.ip, ip);
+        assert_eq!(geo.country.as_deref(), Some("Example"));
+        let tunnel = geo.tunnel.unwrap();
+        assert_eq!(tunnel.mechanism, TunnelMechanism::SixToFour);
+        assert_eq!(tunnel.ipv4.to_string(), "192.0.2.1");
+
+        let mapped: IpAddr = "::ffff:192.0.2.9".parse().unwrap();
+        let geo = manager.query_ip(mapped).await.unwrap().unwrap();
+        assert_eq!(geo.tunnel.unwrap().mechanism, TunnelMechanism::Ipv4Mapped);
+    }
+
+    #[tokio::test]
+    async fn test_link_local_ipv6() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut config = AppConfig::default();
+        config.database.ipv6_database = "v6".to_string();
+        config.database.data_dir = Some(dir.path().to_string_lossy().to_string());
+        let info = custom_db(dir.path(), "v6", "2001:db8::,2001:db8::ffff,Example,,,\n", &[]);
+        config.database.databases.push(info);
+        let manager = DatabaseManager::new(config);
+
+        let ip: IpAddr = "fe80::20c:29ff:fe3a:4b5c".parse().unwrap();
+        let geo = manager.query_ip(ip).await.unwrap().unwrap();
+        assert_eq!(geo.country.as_deref(), Some("Link-local"));
+        assert_eq!(geo.network.as_deref(), Some("fe80::/10"));
+
+        let global: IpAddr = "2001:db8:1::1".parse().unwrap();
+        assert!(manager.query_ip(global).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_corrupt_database_is_left_without_download_url() {
+        let dir = tempfile::tempdir().unwrap();
+        let corrupt = dir.path().join("broken.nali");
+        std::fs::write(&corrupt, b"not a database at all").unwrap();
+
+        let mut config = AppConfig::default();
+        config.database.ipv4_database = "broken".to_string();
+        config.database.ipv4_fallback = vec!["backup".to_string()];
+        config.database.data_dir = Some(dir.path().to_string_lossy().to_string());
+        config.database.databases.push(custom_info("broken", &[]));
+        let info = custom_db(dir.path(), "backup", "192.0.2.0,192.0.2.255,Backup,,,\n", &[]);
+        config.database.databases.push(info);
+        let manager = DatabaseManager::new(config);
+        let ip: IpAddr = "192.0.2.1".parse().unwrap();
+
+        let geo = manager.query_ip(ip).await.unwrap().unwrap();
+        assert_eq!(geo.country.as_deref(), Some("Backup"));
+        assert_eq!(geo.sources, ["backup"]);
+
+        // Nothing could replace the file, so it stays where the user put it
+        assert!(corrupt.exists());
+        assert!(!dir.path().join("broken.nali.corrupt").exists());
+    }
+
+    #[tokio::test]
+    #[cfg_attr(feature = "embedded-db", ignore = "the embedded country table answers instead")]
+    async fn test_corrupt_database_is_kept_when_download_fails() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut config = remote_config(dir.path(), "http://127.0.0.1:1/remote".to_string());
+        config.download.retries = 0;
+        let file = dir.path().join("remote.nali");
+        std::fs::write(&file, b"not a database at all").unwrap();
+
+        let manager = DatabaseManager::new(config);
+        let err = manager.query_ip(IpAddr::from([192, 0, 2, 1])).await.unwrap_err();
+        assert!(!err.is_corruption(), "{}", err);
+        assert_eq!(std::fs::read(&file).unwrap(), b"not a database at all");
+        assert!(!dir.path().join("remote.nali.corrupt").exists());
+    }
+
+    #[tokio::test]
+    #[cfg_attr(feature = "embedded-db", ignore = "the embedded country table answers instead")]
+    async fn test_czdb_uses_configured_key() {
+        use base64::Engine;
+
+        let key = *b"0123456789abcdef";
+        let dir = tempfile::tempdir().unwrap();
+        let image = crate::database::czdb::build_czdb(&key, 3, &[(
+            "192.0.2.0".parse().unwrap(),
+            "192.0.2.255".parse().unwrap(),
+            "中国–江苏–南京",
+            "电信",
+        )]);
+        let file = dir.path().join("cz88_public_v4.czdb");
+        std::fs::write(&file, image).unwrap();
+
+        let mut config = AppConfig::default();
+        config.database.ipv4_database = "czdb".to_string();
+        config.database.data_dir = Some(dir.path().to_string_lossy().to_string());
+        let ip: IpAddr = "192.0.2.1".parse().unwrap();
+
+        // Without the key the file is left alone
+        let err = DatabaseManager::new(config.clone()).query_ip(ip).await.unwrap_err();
+        assert!(err.to_string().contains("czdb_key"), "{}", err);
+        assert!(file.exists());
+
+        config.database.czdb_key = Some(base64::engine::general_purpose::STANDARD.encode(key));
+        let geo = DatabaseManager::new(config).query_ip(ip).await.unwrap().unwrap();
+        assert_eq!(geo.region.as_deref(), Some("江苏"));
+        assert_eq!(geo.isp.as_deref(), Some("电信"));
+    }
+
+    #[test]
+    fn test_read_failures_are_not_corruption() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut config = AppConfig::default();
+        config.database.data_dir = Some(dir.path().to_string_lossy().to_string());
+        let manager = DatabaseManager::new(config);
+
+        // A directory where the file should be can't be read, but isn't corrupt
+        let path = dir.path().join("qqwry.dat");
+        std::fs::create_dir(&path).unwrap();
+        let err = manager.load_database_file("qqwry", DatabaseType::QQwry, &path).err().unwrap();
+        assert!(matches!(err, NaliError::IoError(_)), "{}", err);
+        assert!(!err.is_corruption());
+
+        std::fs::remove_dir(&path).unwrap();
+        std::fs::write(&path, b"short").unwrap();
+        let err = manager.load_database_file("qqwry", DatabaseType::QQwry, &path).err().unwrap();
+        assert!(matches!(err, NaliError::InvalidFormat(_)), "{}", err);
+        assert!(err.is_corruption());
+    }
+
+    #[tokio::test]
+    async fn test_query_cache_is_bounded() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut config = AppConfig::default();
+        config.database.ipv4_database = "ranges".to_string();
+        config.database.data_dir = Some(dir.path().to_string_lossy().to_string());
+        config.database.cache_size = 2;
+        let info = custom_db(dir.path(), "ranges", "192.0.2.0,192.0.2.255,Example,,,\n", &[]);
+        config.database.databases.push(info);
+        let manager = DatabaseManager::new(config);
+
+        for last in 1..=5 {
+            let ip = IpAddr::from([192, 0, 2, last]);
+            assert!(manager.query_ip(ip).await.unwrap().is_some());
+            assert!(manager.cache_stats().1 <= 2);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_offloaded_lookups() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut config = AppConfig::default();
+        config.database.ipv4_database = "ranges".to_string();
+        config.database.data_dir = Some(dir.path().to_string_lossy().to_string());
+        config.database.offload_lookups = true;
+        let info = custom_db(dir.path(), "ranges", "192.0.2.0,192.0.2.255,Example,,,\n", &[]);
+        config.database.databases.push(info);
+        let manager = Arc::new(DatabaseManager::new(config));
+
+        let lookups = (1..=20).map(|last| {
+            let manager = Arc::clone(&manager);
+            tokio::spawn(async move { manager.query_ip(IpAddr::from([192, 0, 2, last])).await })
+        });
+        for lookup in lookups.collect::<Vec<_>>() {
+            let geo = lookup.await.unwrap().unwrap().unwrap();
+            assert_eq!(geo.country.as_deref(), Some("Example"));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_explain_ip() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut config = AppConfig::default();
+        config.database.ipv4_database = "ranges".to_string();
+        config.database.data_dir = Some(dir.path().to_string_lossy().to_string());
+        let info = custom_db(dir.path(), "ranges", "192.0.2.0,192.0.2.127,Example,,,\n", &[]);
+        config.database.databases.push(info);
+        let manager = DatabaseManager::new(config);
+
+        let ip: IpAddr = "192.0.2.1".parse().unwrap();
+        let explanation = manager.explain_ip(ip).await.unwrap();
+        assert_eq!(explanation.database, "ranges");
+        assert_eq!(
+            explanation.range,
+            Some(("192.0.2.0".parse().unwrap(), "192.0.2.127".parse().unwrap()))
+        );
+        assert!(!explanation.cached);
+
+        manager.query_ip(ip).await.unwrap();
+        assert!(manager.explain_ip(ip).await.unwrap().cached);
+        // 6to4 addresses are answered from the IPv4 cache
+        assert!(manager.explain_ip("2002:c000:201::1".parse().unwrap()).await.unwrap().cached);
+
+        let miss = manager.explain_ip("192.0.2.200".parse().unwrap()).await.unwrap();
+        assert_eq!(miss.range, None);
+    }
+
+    /// Config with a missing database "remote" downloaded from `url`
+    fn remote_config(dir: &Path, url: String) -> AppConfig {
+        let mut config = AppConfig::default();
+        config.database.ipv4_database = "remote".to_string();
+        config.database.data_dir = Some(dir.to_string_lossy().to_string());
+        config.global.quiet = true;
+        config.database.databases.push(custom_info("remote", &[url]));
+        config
+    }
+
+    #[tokio::test]
+    #[cfg_attr(feature = "embedded-db", ignore = "the embedded country table answers instead")]
+    async fn test_auto_download_disabled() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut config = remote_config(dir.path(), "http://127.0.0.1:1/remote".to_string());
+        config.download.auto = AutoDownload::Off;
+        let manager = DatabaseManager::new(config);
+
+        let err = manager.query_ip(IpAddr::from([192, 0, 2, 1])).await.unwrap_err();
+        assert!(matches!(err, NaliError::DatabaseNotFound(_)));
+        assert!(err.to_string().contains("nali-rs --update remote"));
+    }
+
+    #[cfg(feature = "embedded-db")]
+    #[tokio::test]
+    async fn test_embedded_table_answers_for_missing_database() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut config = remote_config(dir.path(), "http://127.0.0.1:1/remote".to_string());
+        config.download.auto = AutoDownload::Off;
+        let manager = DatabaseManager::new(config);
+
+        for _ in 0..2 {
+            let geo = manager.query_ip("8.8.8.8".parse().unwrap()).await.unwrap().unwrap();
+            assert_eq!(geo.country_code.as_deref(), Some("US"));
+            assert_eq!(geo.sources, [embedded::NAME]);
+            assert!(manager.embedded_warned.load(Ordering::Relaxed));
+        }
+    }
+
+    #[tokio::test]
+    #[cfg_attr(feature = "embedded-db", ignore = "the embedded country table answers instead")]
+    async fn test_read_only_never_writes() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut config = remote_config(dir.path(), "http://127.0.0.1:1/remote".to_string());
+        config.global.read_only = true;
+        config.database.disk_cache = true;
+        let ip = IpAddr::from([192, 0, 2, 1]);
+
+        // A missing database is not downloaded
+        let err = DatabaseManager::new(config.clone()).query_ip(ip).await.unwrap_err();
+        assert!(matches!(err, NaliError::DatabaseNotFound(_)));
+        assert!(err.to_string().contains("read-only"), "{}", err);
+
+        // A corrupt one is neither moved aside nor replaced
+        let file = dir.path().join("remote.nali");
+        std::fs::write(&file, b"not a database at all").unwrap();
+        let manager = DatabaseManager::new(config);
+        let err = manager.query_ip(ip).await.unwrap_err();
+        assert!(matches!(err, NaliError::DatabaseCorrupted(_)), "{}", err);
+        manager.save_cache().unwrap();
+
+        let mut files: Vec<_> = std::fs::read_dir(dir.path())
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .collect();
+        files.sort();
+        assert_eq!(files, ["remote.nali"]);
+    }
+
+    #[tokio::test]
+    #[cfg_attr(feature = "embedded-db", ignore = "the embedded country table answers instead")]
+    async fn test_background_download() {
+        // Nothing listens on a port just released, so the download fails fast
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/remote", listener.local_addr().unwrap());
+        drop(listener);
+
+        let dir = tempfile::tempdir().unwrap();
+        let mut config = remote_config(dir.path(), url);
+        config.download.retries = 0;
+        let manager = DatabaseManager::new(config).download_in_background(true);
+        let ip = IpAddr::from([192, 0, 2, 1]);
+
+        let err = manager.query_ip(ip).await.unwrap_err();
+        assert!(matches!(err, NaliError::DatabasePending(_)), "{}", err);
+
+        // Once the download failed, queries say so instead of waiting forever
+        let mut err = err;
+        for _ in 0..100 {
+            if !matches!(err, NaliError::DatabasePending(_)) {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            err = manager.query_ip(ip).await.unwrap_err();
+        }
+        assert!(matches!(err, NaliError::DownloadError(_)), "{}", err);
+        assert!(!dir.path().join("remote.nali").exists());
+    }
+
+    #[tokio::test]
+    async fn test_preload_all() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut config = remote_config(dir.path(), "http://127.0.0.1:1/remote".to_string());
+        config.download.auto = AutoDownload::Off;
+        let info = custom_db(dir.path(), "local", "192.0.2.0,192.0.2.255,Test,,,\n", &[]);
+        config.database.databases.push(info);
+        config.database.preload = vec!["local".to_string()];
+        let manager = DatabaseManager::new(config.clone());
+        manager.preload_all().await.unwrap();
+        assert_eq!(manager.cache_stats().0, 1);
+
+        // A database that cannot be loaded fails the whole preload
+        config.database.preload.push("remote".to_string());
+        let err = DatabaseManager::new(config).preload_all().await.unwrap_err();
+        assert!(matches!(err, NaliError::DatabaseNotLoaded(_)), "{}", err);
+        assert!(err.to_string().contains("remote could not be preloaded"));
+    }
+
+    #[tokio::test]
+    async fn test_database_reads_are_counted() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut config = AppConfig::default();
+        config.database.ipv4_database = "ranges".to_string();
+        config.database.data_dir = Some(dir.path().to_string_lossy().to_string());
+        let info = custom_db(dir.path(), "ranges", "192.0.2.0,192.0.2.255,Example,,,\n", &[]);
+        config.database.databases.push(info);
+        let manager = DatabaseManager::new(config);
+
+        // The second query of an address is a cache hit and reads nothing
+        for ip in ["192.0.2.1", "192.0.2.1", "192.0.2.2"] {
+            manager.query_ip(ip.parse().unwrap()).await.unwrap();
+        }
+        let stats = manager.metrics().database_stats();
+        assert_eq!(stats["ranges"].lookups, 2);
+        assert!(stats["ranges"].slowest <= stats["ranges"].total);
+    }
+
+    /// A manager over one custom database answering 192.0.2.0/24
+    fn ranges_manager(dir: &Path) -> DatabaseManager {
+        let mut config = AppConfig::default();
+        config.database.ipv4_database = "ranges".to_string();
+        config.database.data_dir = Some(dir.to_string_lossy().to_string());
+        let info = custom_db(dir, "ranges", "192.0.2.0,192.0.2.255,Example,,,\n", &[]);
+        config.database.databases.push(info);
+        DatabaseManager::new(Arc::new(config))
+    }
+
+    #[tokio::test]
+    async fn test_clones_share_state() {
+        let dir = tempfile::tempdir().unwrap();
+        let manager = ranges_manager(dir.path());
+        let clone = manager.clone();
+        assert_eq!(clone.config().database.ipv4_database, "ranges");
+
+        let ip: IpAddr = "192.0.2.1".parse().unwrap();
+        clone.query_ip(ip).await.unwrap().unwrap();
+        assert_eq!(manager.cache_stats(), (1, 1));
+
+        // Answered from the cache the clone filled
+        manager.query_ip(ip).await.unwrap().unwrap();
+        assert_eq!(manager.metrics().database_stats()["ranges"].lookups, 1);
+    }
+
+    #[test]
+    fn test_clones_across_threads() {
+        let dir = tempfile::tempdir().unwrap();
+        let manager = ranges_manager(dir.path());
+        let barrier = Arc::new(std::sync::Barrier::new(8));
+
+        // Every thread races to load the database on its first query
+        let threads: Vec<_> = (1..=8u8)
+            .map(|last| {
+                let manager = manager.clone();
+                let barrier = Arc::clone(&barrier);
+                std::thread::spawn(move || {
+                    let runtime = tokio::runtime::Builder::new_current_thread().build().unwrap();
+                    barrier.wait();
+                    runtime.block_on(async {
+                        for _ in 0..50 {
+                            let geo = manager.query_ip(IpAddr::from([192, 0, 2, last])).await;
+                            assert_eq!(geo.unwrap().unwrap().country.as_deref(), Some("Example"));
+                        }
+                    });
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+
+        // One read per address; the other queries were cache hits
+        assert_eq!(manager.cache_stats(), (1, 8));
+        assert_eq!(manager.metrics().database_stats()["ranges"].lookups, 8);
+    }
+
+    #[test]
+    fn test_reload_under_concurrent_lookups() {
+        use std::sync::atomic::AtomicBool;
+
+        let dir = tempfile::tempdir().unwrap();
+        let mut config = ranges_manager(dir.path()).config().clone();
+        config.database.reload_interval = 0;
+        let manager = DatabaseManager::new(config);
+        let done = Arc::new(AtomicBool::new(false));
+
+        let readers: Vec<_> = (0..4)
+            .map(|_| {
+                let manager = manager.clone();
+                let done = Arc::clone(&done);
+                std::thread::spawn(move || {
+                    let runtime = tokio::runtime::Builder::new_current_thread().build().unwrap();
+                    runtime.block_on(async {
+                        let mut last = 0u8;
+                        while !done.load(Ordering::Relaxed) {
+                            last = last.wrapping_add(1);
+                            let geo = manager.query_ip(IpAddr::from([192, 0, 2, last])).await;
+                            let country = geo.unwrap().unwrap().country.unwrap();
+                            assert!(country == "Example" || country.starts_with("Version"), "{}", country);
+                        }
+                    });
+                })
+            })
+            .collect();
+
+        // Replace the file the way an update does, by renaming a complete one over it
+        for version in 0..20 {
+            let row = format!("192.0.2.0,192.0.2.255,Version {},,,\n", version);
+            let next = custom_file(dir.path(), "next", &row);
+            std::fs::rename(next, dir.path().join("ranges.nali")).unwrap();
+            std::thread::sleep(Duration::from_millis(5));
+        }
+        done.store(true, Ordering::Relaxed);
+        for reader in readers {
+            reader.join().unwrap();
+        }
+        let runtime = tokio::runtime::Builder::new_current_thread().build().unwrap();
+        let geo = runtime.block_on(manager.query_ip(IpAddr::from([192, 0, 2, 1]))).unwrap();
+        assert_eq!(geo.unwrap().country.as_deref(), Some("Version 19"));
+        assert_eq!(manager.cache_stats().0, 1);
+    }
+
+    #[tokio::test]
+    async fn test_disk_cache_across_runs() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut config = ranges_manager(dir.path()).config().clone();
+        config.database.disk_cache = true;
+        let config = Arc::new(config);
+        let ip: IpAddr = "192.0.2.1".parse().unwrap();
+
+        let first = DatabaseManager::new(Arc::clone(&config));
+        first.query_ip(ip).await.unwrap().unwrap();
+        first.save_cache().unwrap();
+        assert!(dir.path().join(disk_cache::CACHE_FILE).exists());
+
+        // The next run answers from disk without reading the database
+        let second = DatabaseManager::new(Arc::clone(&config));
+        let geo = second.query_ip(ip).await.unwrap().unwrap();
+        assert_eq!(geo.country.as_deref(), Some("Example"));
+        assert!(!second.metrics().database_stats().contains_key("ranges"));
+        assert!(second.explain_ip(ip).await.unwrap().cached);
+
+        // An updated database file is read again
+        custom_file(dir.path(), "ranges", "192.0.2.0,192.0.2.255,Updated,,,\n");
+        let third = DatabaseManager::new(config);
+        let geo = third.query_ip(ip).await.unwrap().unwrap();
+        assert_eq!(geo.country.as_deref(), Some("Updated"));
+    }
 }
@@ -0,0 +1,411 @@
+//! Lookup metrics in the Prometheus text format
+//!
+//! The manager counts every lookup by address family and database, cache
+//! hits and misses, and lookup latency, and remembers the modification time
+//! of each database file it loads. `render` turns them into the exposition
+//! format served at `/metrics`.
+//!
+//! Reads of each database, fallbacks and refinements included but cache hits
+//! not, are counted and timed on their own; `breakdown` prints them for
+//! `--stats`.
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, RwLock};
+use std::time::{Duration, SystemTime};
+
+/// What was looked up
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Family {
+    Ipv4,
+    Ipv6,
+    Domain,
+}
+
+impl Family {
+    const ALL: [Family; 3] = [Family::Ipv4, Family::Ipv6, Family::Domain];
+
+    fn label(self) -> &'static str {
+        match self {
+            Family::Ipv4 => "ipv4",
+            Family::Ipv6 => "ipv6",
+            Family::Domain => "domain",
+        }
+    }
+}
+
+/// Upper bounds of the latency buckets, in seconds
+const BUCKETS: [f64; 10] = [
+    0.000_001, 0.000_005, 0.000_01, 0.000_05, 0.000_1, 0.000_5, 0.001, 0.01, 0.1, 1.0,
+];
+
+/// Cumulative latency histogram
+#[derive(Default)]
+struct Histogram {
+    buckets: [AtomicU64; BUCKETS.len()],
+    count: AtomicU64,
+    sum_nanos: AtomicU64,
+}
+
+impl Histogram {
+    fn observe(&self, elapsed: Duration) {
+        let seconds = elapsed.as_secs_f64();
+        for (bucket, bound) in self.buckets.iter().zip(BUCKETS) {
+            if seconds <= bound {
+                bucket.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+        self.count.fetch_add(1, Ordering::Relaxed);
+        self.sum_nanos
+            .fetch_add(elapsed.as_nanos() as u64, Ordering::Relaxed);
+    }
+}
+
+/// Reads of one database
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DatabaseStats {
+    /// Lookups run in the database
+    pub lookups: u64,
+    /// Time spent in them
+    pub total: Duration,
+    /// The longest single lookup
+    pub slowest: Duration,
+}
+
+impl DatabaseStats {
+    /// Average time of a lookup
+    pub fn mean(&self) -> Duration {
+        match u32::try_from(self.lookups) {
+            Ok(0) => Duration::ZERO,
+            Ok(lookups) => self.total / lookups,
+            Err(_) => Duration::from_secs_f64(self.total.as_secs_f64() / self.lookups as f64),
+        }
+    }
+}
+
+/// Running totals behind [`DatabaseStats`]
+#[derive(Default)]
+struct ReadCounters {
+    lookups: AtomicU64,
+    total_nanos: AtomicU64,
+    slowest_nanos: AtomicU64,
+}
+
+impl ReadCounters {
+    fn observe(&self, elapsed: Duration) {
+        let nanos = elapsed.as_nanos() as u64;
+        self.lookups.fetch_add(1, Ordering::Relaxed);
+        self.total_nanos.fetch_add(nanos, Ordering::Relaxed);
+        self.slowest_nanos.fetch_max(nanos, Ordering::Relaxed);
+    }
+
+    fn stats(&self) -> DatabaseStats {
+        DatabaseStats {
+            lookups: self.lookups.load(Ordering::Relaxed),
+            total: Duration::from_nanos(self.total_nanos.load(Ordering::Relaxed)),
+            slowest: Duration::from_nanos(self.slowest_nanos.load(Ordering::Relaxed)),
+        }
+    }
+}
+
+/// Counters shared by the manager and the metrics endpoint
+///
+/// Counting takes a read lock and an atomic add; the write lock is only
+/// taken for the first lookup of a family and database pair.
+#[derive(Default)]
+pub struct Metrics {
+    lookups: RwLock<BTreeMap<(Family, String), AtomicU64>>,
+    /// Database name -> reads of the database itself
+    reads: RwLock<BTreeMap<String, ReadCounters>>,
+    cache_hits: AtomicU64,
+    cache_misses: AtomicU64,
+    latency: [Histogram; 3],
+    /// Database name -> modification time of the file it was loaded from
+    loaded: Mutex<BTreeMap<String, SystemTime>>,
+}
+
+/// Update the entry for `key` with `update`, inserting a default one first
+fn with_entry<K, V, Q>(map: &RwLock<BTreeMap<K, V>>, key: &Q, update: impl Fn(&V))
+where
+    K: Ord + std::borrow::Borrow<Q>,
+    Q: Ord + ToOwned<Owned = K> + ?Sized,
+    V: Default,
+{
+    if let Ok(map) = map.read()
+        && let Some(value) = map.get(key)
+    {
+        update(value);
+        return;
+    }
+    if let Ok(mut map) = map.write() {
+        update(map.entry(key.to_owned()).or_default());
+    }
+}
+
+/// Quote a label value
+fn label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+impl Metrics {
+    /// Count a lookup answered by `database` in `elapsed`
+    pub fn record_lookup(&self, family: Family, database: &str, elapsed: Duration) {
+        with_entry(&self.lookups, &(family, database.to_string()), |count| {
+            count.fetch_add(1, Ordering::Relaxed);
+        });
+        self.latency[family as usize].observe(elapsed);
+    }
+
+    /// Count a read of `database` that took `elapsed`
+    pub fn record_read(&self, database: &str, elapsed: Duration) {
+        with_entry(&self.reads, database, |counters| counters.observe(elapsed));
+    }
+
+    /// Reads of each database so far, by database name
+    pub fn database_stats(&self) -> BTreeMap<String, DatabaseStats> {
+        self.reads
+            .read()
+            .map(|reads| {
+                reads
+                    .iter()
+                    .map(|(database, counters)| (database.clone(), counters.stats()))
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    /// Count a query cache hit or miss
+    pub fn record_cache(&self, hit: bool) {
+        let counter = if hit {
+            &self.cache_hits
+        } else {
+            &self.cache_misses
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Remember when the file behind a freshly loaded database was modified
+    pub fn record_loaded(&self, database: &str, modified: Option<SystemTime>) {
+        if let (Ok(mut loaded), Some(modified)) = (self.loaded.lock(), modified) {
+            loaded.insert(database.to_string(), modified);
+        }
+    }
+
+    /// Render all metrics in the Prometheus text exposition format
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+
+        out.push_str("# HELP nali_lookups_total Lookups by address family and database.\n");
+        out.push_str("# TYPE nali_lookups_total counter\n");
+        if let Ok(lookups) = self.lookups.read() {
+            for ((family, database), count) in lookups.iter() {
+                let _ = writeln!(
+                    out,
+                    "nali_lookups_total{{family=\"{}\",database=\"{}\"}} {}",
+                    family.label(),
+                    label(database),
+                    count.load(Ordering::Relaxed)
+                );
+            }
+        }
+
+        out.push_str("# HELP nali_database_reads_total Lookups run in each database, fallbacks included.\n");
+        out.push_str("# TYPE nali_database_reads_total counter\n");
+        let reads = self.database_stats();
+        for (database, stats) in &reads {
+            let _ = writeln!(
+                out,
+                "nali_database_reads_total{{database=\"{}\"}} {}",
+                label(database),
+                stats.lookups
+            );
+        }
+        out.push_str("# HELP nali_database_read_seconds_total Time spent in lookups of each database.\n");
+        out.push_str("# TYPE nali_database_read_seconds_total counter\n");
+        for (database, stats) in &reads {
+            let _ = writeln!(
+                out,
+                "nali_database_read_seconds_total{{database=\"{}\"}} {}",
+                label(database),
+                stats.total.as_secs_f64()
+            );
+        }
+
+        for (name, help, counter) in [
+            (
+                "nali_cache_hits_total",
+                "Lookups answered from the query cache.",
+                &self.cache_hits,
+            ),
+            (
+                "nali_cache_misses_total",
+                "Lookups that had to query a database.",
+                &self.cache_misses,
+            ),
+        ] {
+            let _ = writeln!(out, "# HELP {} {}\n# TYPE {} counter", name, help, name);
+            let _ = writeln!(out, "{} {}", name, counter.load(Ordering::Relaxed));
+        }
+
+        out.push_str("# HELP nali_lookup_duration_seconds Lookup latency, including the cache.\n");
+        out.push_str("# TYPE nali_lookup_duration_seconds histogram\n");
+        for family in Family::ALL {
+            let histogram = &self.latency[family as usize];
+            let family = family.label();
+            for (bucket, bound) in histogram.buckets.iter().zip(BUCKETS) {
+                let _ = writeln!(
+                    out,
+                    "nali_lookup_duration_seconds_bucket{{family=\"{}\",le=\"{}\"}} {}",
+                    family,
+                    bound,
+                    bucket.load(Ordering::Relaxed)
+                );
+            }
+            let count = histogram.count.load(Ordering::Relaxed);
+            let sum = histogram.sum_nanos.load(Ordering::Relaxed) as f64 / 1e9;
+            let _ = writeln!(
+                out,
+                "nali_lookup_duration_seconds_bucket{{family=\"{}\",le=\"+Inf\"}} {}",
+                family, count
+            );
+            let _ = writeln!(
+                out,
+                "nali_lookup_duration_seconds_sum{{family=\"{}\"}} {}",
+                family, sum
+            );
+            let _ = writeln!(
+                out,
+                "nali_lookup_duration_seconds_count{{family=\"{}\"}} {}",
+                family, count
+            );
+        }
+
+        out.push_str(
+            "# HELP nali_database_age_seconds Time since the loaded database file was modified.\n",
+        );
+        out.push_str("# TYPE nali_database_age_seconds gauge\n");
+        if let Ok(loaded) = self.loaded.lock() {
+            let now = SystemTime::now();
+            for (database, modified) in loaded.iter() {
+                let age = now.duration_since(*modified).unwrap_or_default();
+                let _ = writeln!(
+                    out,
+                    "nali_database_age_seconds{{database=\"{}\"}} {}",
+                    label(database),
+                    age.as_secs()
+                );
+            }
+        }
+
+        out
+    }
+
+    /// Table of reads per database and cache use, for people
+    pub fn breakdown(&self) -> String {
+        let reads = self.database_stats();
+        let width = reads.keys().map(|name| name.len()).max().unwrap_or(0).max("DATABASE".len());
+
+        let mut out = String::new();
+        let _ = writeln!(
+            out,
+            "{:<width$}  {:>9}  {:>10}  {:>10}  {:>10}",
+            "DATABASE", "LOOKUPS", "TOTAL", "MEAN", "SLOWEST"
+        );
+        for (database, stats) in &reads {
+            let _ = writeln!(
+                out,
+                "{:<width$}  {:>9}  {:>10}  {:>10}  {:>10}",
+                database,
+                stats.lookups,
+                format!("{:.1?}", stats.total),
+                format!("{:.1?}", stats.mean()),
+                format!("{:.1?}", stats.slowest)
+            );
+        }
+        let _ = writeln!(
+            out,
+            "cache: {} hits, {} misses",
+            self.cache_hits.load(Ordering::Relaxed),
+            self.cache_misses.load(Ordering::Relaxed)
+        );
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        let metrics = Metrics::default();
+        metrics.record_lookup(Family::Ipv4, "qqwry", Duration::from_micros(3));
+        metrics.record_lookup(Family::Ipv4, "qqwry", Duration::from_millis(2));
+        metrics.record_lookup(Family::Domain, "cdn", Duration::from_micros(20));
+        metrics.record_cache(true);
+        metrics.record_cache(false);
+        metrics.record_cache(false);
+        metrics.record_loaded(
+            "qqwry",
+            Some(SystemTime::now() - Duration::from_secs(86_400)),
+        );
+
+        let text = metrics.render();
+        assert!(text.contains("nali_lookups_total{family=\"ipv4\",database=\"qqwry\"} 2\n"));
+        assert!(text.contains("nali_lookups_total{family=\"domain\",database=\"cdn\"} 1\n"));
+        assert!(text.contains("nali_cache_hits_total 1\n"));
+        assert!(text.contains("nali_cache_misses_total 2\n"));
+        assert!(
+            text.contains(
+                "nali_lookup_duration_seconds_bucket{family=\"ipv4\",le=\"0.000005\"} 1\n"
+            )
+        );
+        assert!(
+            text.contains("nali_lookup_duration_seconds_bucket{family=\"ipv4\",le=\"0.01\"} 2\n")
+        );
+        assert!(text.contains("nali_lookup_duration_seconds_count{family=\"ipv6\"} 0\n"));
+        assert!(text.contains("nali_database_age_seconds{database=\"qqwry\"} 8640"));
+    }
+
+    #[test]
+    fn test_database_stats() {
+        let metrics = Metrics::default();
+        metrics.record_read("qqwry", Duration::from_micros(10));
+        metrics.record_read("qqwry", Duration::from_micros(30));
+        metrics.record_read("geocn", Duration::from_millis(2));
+        metrics.record_cache(true);
+
+        let stats = metrics.database_stats();
+        assert_eq!(
+            stats["qqwry"],
+            DatabaseStats {
+                lookups: 2,
+                total: Duration::from_micros(40),
+                slowest: Duration::from_micros(30),
+            }
+        );
+        assert_eq!(stats["qqwry"].mean(), Duration::from_micros(20));
+        assert_eq!(DatabaseStats::default().mean(), Duration::ZERO);
+
+        let text = metrics.render();
+        assert!(text.contains("nali_database_reads_total{database=\"qqwry\"} 2\n"));
+        assert!(text.contains("nali_database_read_seconds_total{database=\"geocn\"} 0.002\n"));
+
+        let breakdown = metrics.breakdown();
+        let lines: Vec<&str> = breakdown.lines().collect();
+        assert!(lines[0].starts_with("DATABASE  "));
+        assert!(lines[1].starts_with("geocn "));
+        assert!(lines[2].starts_with("qqwry "));
+        assert!(lines[2].contains(" 2 ") && lines[2].contains("40.0µs") && lines[2].contains("20.0µs"));
+        assert_eq!(lines[3], "cache: 1 hits, 0 misses");
+    }
+
+    #[test]
+    fn test_label_escaping() {
+        assert_eq!(label("a\"b\\c\nd"), "a\\\"b\\\\c\\nd");
+    }
+}
@@ -8,37 +8,69 @@
 //! - `types`: Common type definitions (GeoLocation, CdnProvider, DatabaseType)
 //! - `traits`: Trait definitions (Database trait)
 //! - `factory`: Factory pattern for creating database instances
+//! - `registry`: Registration of third-party database backends
 //! - `manager`: Database manager for handling multiple databases
-//! - Database implementations: qqwry, zxipv6, geoip2, ipip, etc.
+//! - `disk_cache`: Query results persisted across runs
+//! - `normalize`: Decoding of IPv4 addresses embedded in IPv6 addresses
+//! - `location_cache`: Decoded location strings shared by lookups
+//! - `bytes`: File contents, memory-mapped or held in memory
+//! - Database implementations: qqwry, zxipv6, geoip2, geocn, ipip, custom, etc.
+//! - `embedded`: Country table compiled in with the `embedded-db` feature
+//! - `asn`: IP-to-AS ranges for `nali-rs asn`, outside the geolocation chain
 
 // Core modules
 pub mod types;
 pub mod traits;
 pub mod factory;
+pub mod registry;
+#[cfg(feature = "native")]
 pub mod manager;
+#[cfg(feature = "native")]
+pub mod disk_cache;
+pub mod metrics;
+pub mod normalize;
+pub mod location_cache;
+pub mod bytes;
 
 // Database implementation modules
+pub mod asn;
 pub mod common;
+pub mod custom;
+pub mod czdb;
 pub mod dbip;
+#[cfg(feature = "embedded-db")]
+pub mod embedded;
+pub mod geocn;
 pub mod geoip2;
 pub mod ip2location;
 pub mod ip2region;
+pub mod ipdb;
 pub mod ipip;
 pub mod qqwry;
 pub mod zxipv6;
 
 // Re-export core types and traits for convenience
-pub use types::{CdnProvider, DatabaseType, GeoLocation};
+pub use types::{
+    CdnMatch, CdnMatchKind, CdnProvider, DatabaseType, Explanation, GeoLocation, IpRange, QueryMeta,
+};
 pub use traits::Database;
+pub use bytes::DatabaseBytes;
 pub use factory::DatabaseFactory;
+pub use registry::{DatabaseConstructor, DatabaseRegistry};
+#[cfg(feature = "native")]
 pub use manager::DatabaseManager;
+pub use normalize::{Tunnel, TunnelMechanism};
 
 // Re-export database implementations
 pub use common::CDNDatabase;
+pub use custom::CustomDatabase;
+pub use czdb::CzdbDatabase;
 pub use dbip::DBIPDatabase;
+pub use geocn::GeoCNDatabase;
 pub use geoip2::GeoIP2Database;
 pub use ip2location::IP2LocationDatabase;
 pub use ip2region::IP2RegionDatabase;
+pub use ipdb::IPDBDatabase;
 pub use ipip::IPIPDatabase;
 pub use qqwry::QQwryDatabase;
 pub use zxipv6::ZXIPv6Database;
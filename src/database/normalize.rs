@@ -0,0 +1,113 @@
+//! IPv6 address normalization
+//!
+//! Several IPv6 ranges carry an IPv4 address inside them: IPv4-mapped
+//! addresses (`::ffff:0:0/96`), the NAT64 well-known prefix (`64:ff9b::/96`),
+//! 6to4 (`2002::/16`) and Teredo (`2001::/32`). IPv6 databases rarely know
+//! anything useful about these, so the manager looks up the embedded IPv4
+//! address in the IPv4 database instead.
+
+use std::fmt;
+use std::net::{Ipv4Addr, Ipv6Addr};
+
+/// Mechanism that embeds an IPv4 address in an IPv6 address
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum TunnelMechanism {
+    #[serde(rename = "IPv4-mapped")]
+    Ipv4Mapped,
+    #[serde(rename = "NAT64")]
+    Nat64,
+    #[serde(rename = "6to4")]
+    SixToFour,
+    #[serde(rename = "Teredo")]
+    Teredo,
+}
+
+impl fmt::Display for TunnelMechanism {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Ipv4Mapped => "IPv4-mapped",
+            Self::Nat64 => "NAT64",
+            Self::SixToFour => "6to4",
+            Self::Teredo => "Teredo",
+        })
+    }
+}
+
+impl TunnelMechanism {
+    /// IPv6 block reserved for the mechanism
+    pub fn network(self) -> &'static str {
+        match self {
+            Self::Ipv4Mapped => "::ffff:0:0/96",
+            Self::Nat64 => "64:ff9b::/96",
+            Self::SixToFour => "2002::/16",
+            Self::Teredo => "2001::/32",
+        }
+    }
+}
+
+/// IPv4 address decoded from an IPv6 address, attached to lookup results
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct Tunnel {
+    pub mechanism: TunnelMechanism,
+    pub ipv4: Ipv4Addr,
+}
+
+/// Decode the IPv4 address embedded in `ip`, if it belongs to a known range
+///
+/// For Teredo this is the client's public address, stored bit-inverted in
+/// the last 32 bits.
+pub fn embedded_ipv4(ip: Ipv6Addr) -> Option<Tunnel> {
+    let bits = u128::from(ip);
+    let low32 = bits as u32;
+
+    let (mechanism, ipv4) = match ip.segments() {
+        [0, 0, 0, 0, 0, 0xffff, _, _] => (TunnelMechanism::Ipv4Mapped, low32),
+        [0x64, 0xff9b, 0, 0, 0, 0, _, _] => (TunnelMechanism::Nat64, low32),
+        [0x2002, ..] => (TunnelMechanism::SixToFour, (bits >> 80) as u32),
+        [0x2001, 0, ..] => (TunnelMechanism::Teredo, !low32),
+        _ => return None,
+    };
+
+    Some(Tunnel {
+        mechanism,
+        ipv4: Ipv4Addr::from(ipv4),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decode(ip: &str) -> Option<(TunnelMechanism, String)> {
+        embedded_ipv4(ip.parse().unwrap()).map(|t| (t.mechanism, t.ipv4.to_string()))
+    }
+
+    #[test]
+    fn test_embedded_ipv4() {
+        assert_eq!(
+            decode("::ffff:1.2.3.4"),
+            Some((TunnelMechanism::Ipv4Mapped, "1.2.3.4".to_string()))
+        );
+        assert_eq!(
+            decode("64:ff9b::808:808"),
+            Some((TunnelMechanism::Nat64, "8.8.8.8".to_string()))
+        );
+        assert_eq!(
+            decode("2002:c000:204::1"),
+            Some((TunnelMechanism::SixToFour, "192.0.2.4".to_string()))
+        );
+        // RFC 4380 example: server 65.54.227.120, client 192.0.2.45
+        assert_eq!(
+            decode("2001:0:4136:e378:8000:63bf:3fff:fdd2"),
+            Some((TunnelMechanism::Teredo, "192.0.2.45".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_plain_ipv6_is_untouched() {
+        assert_eq!(decode("2001:4860:4860::8888"), None);
+        assert_eq!(decode("2001:db8::1"), None);
+        assert_eq!(decode("::1"), None);
+        assert_eq!(decode("::"), None);
+    }
+}
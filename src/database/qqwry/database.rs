@@ -1,22 +1,110 @@
 //! QQwry database implementation core
 
-use crate::database::{CdnProvider, Database, DatabaseType, GeoLocation};
-use crate::error::Result;
+use crate::config::LoadMode;
+use crate::database::location_cache::{LocationCache, pair_key};
+use crate::database::types::format_network;
+use crate::database::{CdnProvider, Database, DatabaseBytes, DatabaseType, GeoLocation};
+use crate::error::{NaliError, Result};
 use crate::utils::encoding::gbk_to_utf8;
-use memmap2::Mmap;
-use std::fs::File;
 use std::net::IpAddr;
 
+use super::iter::QQwryRanges;
 use super::reader::Reader;
-use super::utils::bytes3_to_u32;
+use super::utils::{bytes3_to_u32, looks_like_czdb};
+
+/// Decode the country and area strings of the record at `offset`
+///
+/// `offset` points at the record's end IP; the location follows it.
+pub(super) fn decode_location(data: &[u8], offset: u32) -> Result<(Option<String>, Option<String>)> {
+    let (country, area) = locate_location(data, offset)?;
+    decode_strings(data, country, area)
+}
+
+/// Positions of the country and area strings of the record at `offset`
+fn locate_location(data: &[u8], offset: u32) -> Result<(usize, Option<usize>)> {
+    // Skip the end IP (4 bytes) and follow the redirects
+    let record = offset
+        .checked_add(4)
+        .ok_or(NaliError::IndexOutOfBounds(offset as usize, data.len()))?;
+    Reader::new(data).locate(record)
+}
+
+/// Decode the country and area strings stored at the given positions
+fn decode_strings(data: &[u8], country: usize, area: Option<usize>) -> Result<(Option<String>, Option<String>)> {
+    let reader = Reader::new(data);
+    let (country_bytes, area_bytes) = (reader.string(Some(country))?, reader.string(area)?);
+
+    // Convert GBK to UTF-8
+    let country = gbk_to_utf8(&country_bytes)?;
+    let area = gbk_to_utf8(&area_bytes)?;
+
+    // Clean up the strings
+    let country = country.replace("CZ88.NET", "").trim().to_string();
+    let area = area.replace("CZ88.NET", "").trim().to_string();
+
+    Ok((
+        if !country.is_empty() { Some(country) } else { None },
+        if !area.is_empty() { Some(area) } else { None },
+    ))
+}
+
+/// Provincial divisions of mainland China as qqwry.dat spells them
+const MAINLAND: &[&str] = &[
+    "北京", "天津", "河北", "山西", "内蒙古", "辽宁", "吉林", "黑龙江", "上海", "江苏", "浙江",
+    "安徽", "福建", "江西", "山东", "河南", "湖北", "湖南", "广东", "广西", "海南", "重庆",
+    "四川", "贵州", "云南", "西藏", "陕西", "甘肃", "青海", "宁夏", "新疆",
+];
+
+/// Country code and time zone of a location, judged by its leading name
+///
+/// Chinese locations read `中国`, `广东省广州市` or `香港`; everything else is
+/// another country named in Chinese (`美国`) or no place at all (`局域网`)
+/// and gets neither.
+fn country_of(country: &str) -> Option<(&'static str, &'static str)> {
+    let rest = country
+        .strip_prefix("中国")
+        .map(|rest| rest.trim_start_matches(['–', '-', ' ']));
+    let name = rest.unwrap_or(country);
+    if name.starts_with("香港") {
+        Some(("HK", "Asia/Hong_Kong"))
+    } else if name.starts_with("澳门") {
+        Some(("MO", "Asia/Macau"))
+    } else if name.starts_with("台湾") {
+        Some(("TW", "Asia/Taipei"))
+    } else if rest.is_some() || MAINLAND.iter().any(|p| name.starts_with(p)) {
+        Some(("CN", "Asia/Shanghai"))
+    } else {
+        None
+    }
+}
+
+/// Parse the release date out of the version record's area string
+///
+/// The area reads like `2024年01月10日IP数据`; the date is returned as
+/// `2024-01-10`.
+fn parse_release_date(area: &str) -> Option<String> {
+    let (year, rest) = area.split_once('年')?;
+    let (month, rest) = rest.split_once('月')?;
+    let (day, _) = rest.split_once('日')?;
+
+    let year: u16 = year.trim().parse().ok()?;
+    let month: u8 = month.trim().parse().ok()?;
+    let day: u8 = day.trim().parse().ok()?;
+    ((1..=12).contains(&month) && (1..=31).contains(&day))
+        .then(|| format!("{:04}-{:02}-{:02}", year, month, day))
+}
 
 /// QQwry database implementation
 pub struct QQwryDatabase {
     name: String,
     loaded: bool,
-    mmap: Option<Mmap>,
+    data: Option<DatabaseBytes>,
+    /// Whether `load_from_file` maps the file or reads it whole
+    load_mode: LoadMode,
     idx_start: u32,
     idx_end: u32,
+    /// Country and area by the positions of their strings
+    locations: LocationCache<(Option<String>, Option<String>)>,
 }
 
 impl QQwryDatabase {
@@ -24,16 +112,22 @@ impl QQwryDatabase {
         Self {
             name: "qqwry".to_string(),
             loaded: false,
-            mmap: None,
+            data: None,
+            load_mode: LoadMode::default(),
             idx_start: 0,
             idx_end: 0,
+            locations: LocationCache::default(),
         }
     }
 
-    /// Search index for IPv4 address
+    /// Keep up to `capacity` decoded locations; 0 decodes them on every lookup
+    pub fn set_location_cache(&mut self, capacity: usize) {
+        self.locations = LocationCache::new(capacity);
+    }
+
+    /// Search index for IPv4 address, returning the position of its entry
     fn search_index(&self, ip: u32) -> Result<u32> {
-        if let Some(ref mmap) = self.mmap {
-            let ip_len = 4u32;
+        if let Some(ref mmap) = self.data {
             let entry_len = 7u32; // 4 bytes IP + 3 bytes offset
 
             let mut l = self.idx_start;
@@ -41,21 +135,14 @@ impl QQwryDatabase {
 
             loop {
                 let mid = (r - l) / entry_len / 2 * entry_len + l;
-                let mid_ip = u32::from_le_bytes(
-                    mmap[mid as usize..mid as usize + 4].try_into()?
-                );
+                let mid_ip = Self::index_ip(mmap, mid)?;
 
                 // Check if we've narrowed down to one entry
                 if r - l == entry_len {
                     // Check the right boundary
-                    let r_ip = u32::from_le_bytes(
-                        mmap[r as usize..r as usize + 4].try_into()?
-                    );
-
-                    let offset_pos = if ip >= r_ip { r } else { mid };
-                    return Ok(bytes3_to_u32(
-                        &mmap[offset_pos as usize + ip_len as usize..offset_pos as usize + entry_len as usize]
-                    ));
+                    let r_ip = Self::index_ip(mmap, r)?;
+
+                    return Ok(if ip >= r_ip { r } else { mid });
                 }
 
                 if mid_ip > ip {
@@ -64,47 +151,71 @@ impl QQwryDatabase {
                     l = mid;
                 } else {
                     // Exact match
-                    return Ok(bytes3_to_u32(
-                        &mmap[mid as usize + ip_len as usize..mid as usize + entry_len as usize]
-                    ));
+                    return Ok(mid);
                 }
             }
         } else {
-            Err(crate::error::NaliError::parse("Database not loaded"))
+            Err(crate::error::NaliError::DatabaseNotLoaded(self.name.clone()))
         }
     }
 
-    /// Lookup IPv4 address
-    fn lookup_ipv4(&self, ip: u32) -> Result<Option<GeoLocation>> {
-        if let Some(ref mmap) = self.mmap {
-            // Search for the record offset
-            let offset = self.search_index(ip)?;
+    /// Start IP of an index entry
+    pub(super) fn index_ip(data: &[u8], pos: u32) -> Result<u32> {
+        let pos = pos as usize;
+        let bytes = data
+            .get(pos..pos + 4)
+            .ok_or(NaliError::IndexOutOfBounds(pos, data.len()))?;
+        Ok(u32::from_le_bytes(bytes.try_into()?))
+    }
 
-            // Parse the record at offset
-            let mut reader = Reader::new(mmap);
-            // Skip the end IP (4 bytes) and parse location
-            let (country_bytes, area_bytes) = reader.parse(offset + 4);
+    /// Record offset of an index entry
+    pub(super) fn record_offset(data: &[u8], pos: u32) -> Result<u32> {
+        data.get(pos as usize + 4..pos as usize + 7)
+            .map(bytes3_to_u32)
+            .ok_or(NaliError::IndexOutOfBounds(pos as usize + 4, data.len()))
+    }
 
-            // Convert GBK to UTF-8
-            let country = gbk_to_utf8(&country_bytes)?;
-            let area = gbk_to_utf8(&area_bytes)?;
+    /// First and last address of the range of an index entry
+    ///
+    /// The start IP is in the index entry, the end IP heads its record.
+    fn block(data: &[u8], pos: u32) -> Result<(IpAddr, IpAddr)> {
+        let read_ip = |at: usize| -> Result<IpAddr> {
+            let bytes = data
+                .get(at..at + 4)
+                .ok_or(NaliError::IndexOutOfBounds(at, data.len()))?;
+            Ok(IpAddr::V4(u32::from_le_bytes(bytes.try_into()?).into()))
+        };
+        let offset = Self::record_offset(data, pos)? as usize;
+        Ok((read_ip(pos as usize)?, read_ip(offset)?))
+    }
 
-            // Clean up the strings
-            let country = country.replace("CZ88.NET", "").trim().to_string();
-            let area = area.replace("CZ88.NET", "").trim().to_string();
+    /// Lookup IPv4 address
+    fn lookup_ipv4(&self, ip: u32) -> Result<Option<GeoLocation>> {
+        if let Some(ref mmap) = self.data {
+            // Search for the record offset
+            let pos = self.search_index(ip)?;
+            let offset = Self::record_offset(mmap, pos)?;
+            let (start, end) = Self::block(mmap, pos)?;
+
+            // Parse the record at offset, or reuse the strings it points at
+            let (country_pos, area_pos) = locate_location(mmap, offset)?;
+            let (country, area) = self
+                .locations
+                .get_or_decode(pair_key(country_pos, area_pos), || {
+                    decode_strings(mmap, country_pos, area_pos)
+                })?;
 
             let ip_addr = IpAddr::V4(std::net::Ipv4Addr::from(ip));
+            let (country_code, timezone) = country.as_deref().and_then(country_of).unzip();
 
             Ok(Some(GeoLocation {
                 ip: ip_addr,
-                country: if !country.is_empty() { Some(country) } else { None },
-                region: None,
-                city: None,
-                isp: if !area.is_empty() { Some(area) } else { None },
-                country_code: Some("CN".to_string()),
-                timezone: Some("Asia/Shanghai".to_string()),
-                latitude: None,
-                longitude: None,
+                country,
+                isp: area,
+                country_code: country_code.map(str::to_string),
+                timezone: timezone.map(str::to_string),
+                network: Some(format_network(start, end)),
+                ..Default::default()
             }))
         } else {
             Ok(None)
@@ -112,6 +223,16 @@ impl QQwryDatabase {
     }
 }
 
+impl QQwryDatabase {
+    /// Iterate over every range in the index
+    pub fn ranges(&self) -> Result<QQwryRanges<'_>> {
+        match self.data {
+            Some(ref mmap) => Ok(QQwryRanges::new(mmap, self.idx_start, self.idx_end)),
+            None => Err(crate::error::NaliError::DatabaseNotLoaded(self.name.clone())),
+        }
+    }
+}
+
 impl Database for QQwryDatabase {
     fn name(&self) -> &str {
         &self.name
@@ -151,41 +272,87 @@ impl Database for QQwryDatabase {
         Ok(None)
     }
 
+    fn lookup_range(&self, ip: IpAddr) -> Result<Option<(IpAddr, IpAddr)>> {
+        let (IpAddr::V4(ipv4), Some(mmap)) = (ip, self.data.as_ref()) else {
+            return Ok(None);
+        };
+        Self::block(mmap, self.search_index(u32::from(ipv4))?).map(Some)
+    }
+
+    /// Release date from the version record
+    ///
+    /// The last index entry (`255.255.255.0`-`255.255.255.255`) carries no
+    /// location; its strings read `纯真网络` and `<date>IP数据` instead.
+    fn version(&self) -> Option<String> {
+        let mmap = self.data.as_ref()?;
+        let offset = bytes3_to_u32(mmap.get(self.idx_end as usize + 4..self.idx_end as usize + 7)?);
+        let (_, area) = decode_location(mmap, offset).ok()?;
+        parse_release_date(&area?)
+    }
+
+    fn set_load_mode(&mut self, mode: LoadMode) {
+        self.load_mode = mode;
+    }
+
     fn is_loaded(&self) -> bool {
         self.loaded
     }
 
     fn load_from_file(&mut self, file_path: &str) -> Result<()> {
-        log::info!("Loading QQwry database from: {}", file_path);
+        tracing::info!("Loading QQwry database from: {}", file_path);
 
-        // Open and memory map the file
-        let file = File::open(file_path)
-            .map_err(crate::error::NaliError::IoError)?;
+        let data = DatabaseBytes::open(file_path, "QQwry", self.load_mode)?;
+        if file_path.ends_with(".czdb") {
+            return Err(czdb_error());
+        }
+        self.load(data)
+    }
 
-        let mmap = unsafe { Mmap::map(&file) }
-            .map_err(|e| crate::error::NaliError::parse(format!("Failed to memory map QQwry database: {}", e)))?;
+    fn load_from_bytes(&mut self, data: Vec<u8>) -> Result<()> {
+        self.load(data.into())
+    }
+}
+
+/// The encrypted CZ88 format would otherwise fail with a puzzling header error
+fn czdb_error() -> NaliError {
+    NaliError::config(
+        "This is a CZ88 .czdb database: install it with `nali-rs import FILE --as czdb` \
+         (`czdb6` for IPv6) and set database.czdb_key to the key of your cz88.net account",
+    )
+}
+
+impl QQwryDatabase {
+    /// Validate the header and start answering lookups from `data`
+    fn load(&mut self, data: DatabaseBytes) -> Result<()> {
+        if looks_like_czdb(&data) {
+            return Err(czdb_error());
+        }
 
         // Check file validity
-        if mmap.len() < 8 {
-            return Err(crate::error::NaliError::parse("Invalid QQwry database: file too small"));
+        if data.len() < 8 {
+            return Err(crate::error::NaliError::format("Invalid QQwry database: file too small"));
         }
 
         // Read header
-        let idx_start = u32::from_le_bytes(mmap[0..4].try_into()?);
-        let idx_end = u32::from_le_bytes(mmap[4..8].try_into()?);
-
-        // Validate header
-        if idx_start >= idx_end || mmap.len() < (idx_end + 7) as usize {
-            return Err(crate::error::NaliError::parse("Invalid QQwry database: header validation failed"));
+        let idx_start = u32::from_le_bytes(data[0..4].try_into()?);
+        let idx_end = u32::from_le_bytes(data[4..8].try_into()?);
+
+        // Validate header: the index is a whole number of 7-byte entries
+        if idx_start >= idx_end
+            || (idx_end - idx_start) % 7 != 0
+            || idx_end.checked_add(7).is_none_or(|end| data.len() < end as usize)
+        {
+            return Err(crate::error::NaliError::format("Invalid QQwry database: header validation failed"));
         }
 
         self.idx_start = idx_start;
         self.idx_end = idx_end;
-        self.mmap = Some(mmap);
+        self.data = Some(data);
+        self.locations.clear();
         self.loaded = true;
 
         let record_count = (idx_end - idx_start) / 7 + 1;
-        log::info!("Successfully loaded QQwry database: {} records", record_count);
+        tracing::info!("Successfully loaded QQwry database: {} records", record_count);
 
         Ok(())
     }
@@ -196,3 +363,134 @@ impl Default for QQwryDatabase {
         Self::new()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::encoding::utf8_to_gbk;
+
+    /// Build a minimal QQwry image with directly stored records
+    fn build_qqwry(records: &[(u32, u32, &str, &str)]) -> Vec<u8> {
+        let mut data = vec![0u8; 8];
+        let mut offsets = Vec::new();
+        for (_, end, country, area) in records {
+            offsets.push(data.len() as u32);
+            data.extend_from_slice(&end.to_le_bytes());
+            data.extend_from_slice(&utf8_to_gbk(country));
+            data.push(0);
+            data.extend_from_slice(&utf8_to_gbk(area));
+            data.push(0);
+        }
+
+        let idx_start = data.len() as u32;
+        for ((start, ..), offset) in records.iter().zip(offsets) {
+            data.extend_from_slice(&start.to_le_bytes());
+            data.extend_from_slice(&offset.to_le_bytes()[..3]);
+        }
+        let idx_end = data.len() as u32 - 7;
+
+        data[0..4].copy_from_slice(&idx_start.to_le_bytes());
+        data[4..8].copy_from_slice(&idx_end.to_le_bytes());
+        data
+    }
+
+    fn load(records: &[(u32, u32, &str, &str)]) -> (tempfile::TempDir, QQwryDatabase) {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("qqwry.dat");
+        std::fs::write(&path, build_qqwry(records)).unwrap();
+
+        let mut db = QQwryDatabase::new();
+        db.load_from_file(path.to_str().unwrap()).unwrap();
+        (dir, db)
+    }
+
+    const RECORDS: &[(u32, u32, &str, &str)] = &[
+        (0x00000000, 0x00FFFFFF, "IANA", "保留地址"),
+        (0x01000000, 0x01000000, "美国", "APNIC&CloudFlare公共DNS服务器"),
+        (0x01000001, 0xFFFFFFFF, "中国", "电信"),
+    ];
+
+    #[test]
+    fn test_lookup() {
+        let (_dir, db) = load(RECORDS);
+
+        let geo = db.lookup_ip("1.0.0.0".parse().unwrap()).unwrap().unwrap();
+        assert_eq!(geo.country.as_deref(), Some("美国"));
+
+        let geo = db.lookup_ip("8.8.8.8".parse().unwrap()).unwrap().unwrap();
+        assert_eq!(geo.country.as_deref(), Some("中国"));
+        assert_eq!(geo.isp.as_deref(), Some("电信"));
+    }
+
+    #[test]
+    fn test_malformed_record_is_an_error() {
+        let mut image = build_qqwry(RECORDS);
+        // Point the last index entry far past the end of the file
+        let idx_end = u32::from_le_bytes(image[4..8].try_into().unwrap()) as usize;
+        image[idx_end + 4..idx_end + 7].copy_from_slice(&[0xFF, 0xFF, 0xFF]);
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("qqwry.dat");
+        std::fs::write(&path, image).unwrap();
+        let mut db = QQwryDatabase::new();
+        db.load_from_file(path.to_str().unwrap()).unwrap();
+
+        assert!(db.lookup_ip("8.8.8.8".parse().unwrap()).is_err());
+        assert!(db.lookup_ip("1.0.0.0".parse().unwrap()).unwrap().is_some());
+        assert!(db.ranges().unwrap().any(|range| range.is_err()));
+    }
+
+    #[test]
+    fn test_ranges() {
+        let (_dir, db) = load(RECORDS);
+
+        let ranges: Vec<_> = db.ranges().unwrap().collect::<Result<_>>().unwrap();
+        assert_eq!(ranges.len(), 3);
+        assert_eq!(ranges[0].end, "0.255.255.255".parse::<IpAddr>().unwrap());
+        assert_eq!(ranges[1].isp.as_deref(), Some("APNIC&CloudFlare公共DNS服务器"));
+        assert_eq!(ranges[2].start, "1.0.0.1".parse::<IpAddr>().unwrap());
+    }
+
+    #[test]
+    fn test_rejects_czdb() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("cz88_public_v4.dat");
+        let mut czdb = [2u32, 1001, 48].map(u32::to_le_bytes).concat();
+        czdb.extend(std::iter::repeat_n(0x5A, 4096));
+        std::fs::write(&path, czdb).unwrap();
+
+        let err = QQwryDatabase::new()
+            .load_from_file(path.to_str().unwrap())
+            .unwrap_err();
+        assert!(err.to_string().contains(".czdb"));
+        assert!(!err.is_corruption());
+    }
+
+    #[test]
+    fn test_version() {
+        let (_dir, db) = load(&[
+            (0x00000000, 0xFFFFFEFF, "中国", "电信"),
+            (0xFFFFFF00, 0xFFFFFFFF, "纯真网络", "2024年01月10日IP数据"),
+        ]);
+        assert_eq!(db.version().as_deref(), Some("2024-01-10"));
+
+        let (_dir, db) = load(RECORDS);
+        assert_eq!(db.version(), None);
+    }
+
+    #[test]
+    fn test_country_of() {
+        assert_eq!(country_of("中国"), Some(("CN", "Asia/Shanghai")));
+        assert_eq!(country_of("广东省广州市"), Some(("CN", "Asia/Shanghai")));
+        assert_eq!(country_of("中国–香港"), Some(("HK", "Asia/Hong_Kong")));
+        assert_eq!(country_of("台湾省"), Some(("TW", "Asia/Taipei")));
+        assert_eq!(country_of("美国"), None);
+        assert_eq!(country_of("局域网"), None);
+    }
+
+    #[test]
+    fn test_parse_release_date() {
+        assert_eq!(parse_release_date("2023年9月5日IP数据").as_deref(), Some("2023-09-05"));
+        assert_eq!(parse_release_date("电信"), None);
+        assert_eq!(parse_release_date("2023年13月5日IP数据"), None);
+    }
+}
@@ -0,0 +1,70 @@
+//! Iterator over every range in a QQwry index
+
+use crate::database::IpRange;
+use crate::error::{NaliError, Result};
+use std::net::{IpAddr, Ipv4Addr};
+
+use super::database::{QQwryDatabase, decode_location};
+
+/// Index entry length: 4 bytes start IP + 3 bytes record offset
+const INDEX_ENTRY_LEN: u32 = 7;
+
+/// Iterator over all ranges of a loaded QQwry database, in index order
+pub struct QQwryRanges<'a> {
+    data: &'a [u8],
+    pos: u32,
+    idx_end: u32,
+}
+
+impl<'a> QQwryRanges<'a> {
+    pub(super) fn new(data: &'a [u8], idx_start: u32, idx_end: u32) -> Self {
+        Self {
+            data,
+            pos: idx_start,
+            idx_end,
+        }
+    }
+
+    fn read_entry(&self, pos: u32) -> Result<IpRange> {
+        let start = QQwryDatabase::index_ip(self.data, pos)?;
+        let offset = QQwryDatabase::record_offset(self.data, pos)?;
+        let end = self
+            .data
+            .get(offset as usize..offset as usize + 4)
+            .ok_or(NaliError::IndexOutOfBounds(offset as usize, self.data.len()))?;
+        let end = u32::from_le_bytes(end.try_into()?);
+
+        let (country, area) = decode_location(self.data, offset)?;
+
+        Ok(IpRange {
+            start: IpAddr::V4(Ipv4Addr::from(start)),
+            end: IpAddr::V4(Ipv4Addr::from(end)),
+            country,
+            region: None,
+            city: None,
+            isp: area,
+        })
+    }
+}
+
+impl Iterator for QQwryRanges<'_> {
+    type Item = Result<IpRange>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.pos > self.idx_end {
+            return None;
+        }
+        let item = self.read_entry(self.pos);
+        self.pos += INDEX_ENTRY_LEN;
+        Some(item)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let remaining = if self.pos > self.idx_end {
+            0
+        } else {
+            ((self.idx_end - self.pos) / INDEX_ENTRY_LEN + 1) as usize
+        };
+        (remaining, Some(remaining))
+    }
+}
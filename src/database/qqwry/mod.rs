@@ -6,12 +6,15 @@
 //! # Module Organization
 //!
 //! - `database`: Core QQwryDatabase implementation
+//! - `iter`: Iterator over every range in the index
 //! - `reader`: Binary format reader for parsing QQwry data
 //! - `utils`: Utility functions for data conversion
 
 mod database;
+mod iter;
 mod reader;
 mod utils;
 
 // Re-export the main database struct
 pub use database::QQwryDatabase;
+pub use iter::QQwryRanges;
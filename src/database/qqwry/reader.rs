@@ -1,4 +1,9 @@
 //! QQwry data reader for parsing binary format
+//!
+//! Every read is bounds-checked: a record offset pointing outside the file
+//! or a redirect loop yields an error instead of a panic or a hang.
+
+use crate::error::{NaliError, Result};
 
 use super::utils::bytes3_to_u32;
 
@@ -6,11 +11,14 @@ use super::utils::bytes3_to_u32;
 pub const REDIRECT_MODE_1: u8 = 0x01;
 pub const REDIRECT_MODE_2: u8 = 0x02;
 
+/// Redirects followed for one record; valid files need at most two
+const MAX_REDIRECTS: usize = 8;
+
 /// Reader for parsing QQwry data
 pub struct Reader<'a> {
     data: &'a [u8],
-    pos: u32,
-    last_pos: u32,
+    pos: usize,
+    last_pos: usize,
 }
 
 impl<'a> Reader<'a> {
@@ -22,10 +30,17 @@ impl<'a> Reader<'a> {
         }
     }
 
+    /// Bytes `pos..pos + len`, or an error if they run past the end
+    fn bytes(&self, pos: usize, len: usize) -> Result<&'a [u8]> {
+        pos.checked_add(len)
+            .and_then(|end| self.data.get(pos..end))
+            .ok_or(NaliError::IndexOutOfBounds(pos, self.data.len()))
+    }
+
     /// Seek to absolute position
     fn seek_abs(&mut self, offset: u32) {
         self.last_pos = self.pos;
-        self.pos = offset;
+        self.pos = offset as usize;
     }
 
     /// Seek back to last position (can only call once)
@@ -34,91 +49,138 @@ impl<'a> Reader<'a> {
     }
 
     /// Read mode byte
-    fn read_mode(&mut self) -> u8 {
-        let mode = self.data[self.pos as usize];
+    fn read_mode(&mut self) -> Result<u8> {
+        let mode = self.bytes(self.pos, 1)?[0];
         self.last_pos = self.pos;
         self.pos += 1;
-        mode
+        Ok(mode)
     }
 
     /// Read 3 bytes as u32 offset
-    fn read_offset(&mut self, follow: bool) -> u32 {
-        let offset = bytes3_to_u32(&self.data[self.pos as usize..self.pos as usize + 3]);
+    fn read_offset(&mut self, follow: bool) -> Result<u32> {
+        let offset = bytes3_to_u32(self.bytes(self.pos, 3)?);
         self.last_pos = self.pos;
         self.pos += 3;
         if follow {
             // Update last_pos again before jumping, matching Golang behavior
             // This ensures seekBack() returns to the position after reading the offset
             self.last_pos = self.pos;
-            self.pos = offset;
+            self.pos = offset as usize;
         }
-        offset
+        Ok(offset)
     }
 
-    /// Read null-terminated string
-    fn read_string(&mut self, advance: bool) -> Vec<u8> {
-        let start = self.pos as usize;
-        let mut end = start;
-        while end < self.data.len() && self.data[end] != 0 {
-            end += 1;
-        }
-
-        if advance {
-            self.last_pos = self.pos;
-            self.pos = (end + 1) as u32;
-        }
-
-        self.data[start..end].to_vec()
+    /// Null-terminated string at `pos`; an unterminated one ends with the data
+    fn string_at(&self, pos: usize) -> Result<&'a [u8]> {
+        let rest = self
+            .data
+            .get(pos..)
+            .ok_or(NaliError::IndexOutOfBounds(pos, self.data.len()))?;
+        let len = rest.iter().position(|&b| b == 0).unwrap_or(rest.len());
+        Ok(&rest[..len])
     }
 
-    /// Parse location data at given offset
-    pub fn parse(&mut self, offset: u32) -> (Vec<u8>, Vec<u8>) {
+    /// Positions of the country and area strings of the record at `offset`,
+    /// after following its redirects; `None` for a record without area
+    ///
+    /// Records of many ranges redirect to the same strings, so the positions
+    /// identify a location where the record offset does not.
+    pub fn locate(&mut self, offset: u32) -> Result<(usize, Option<usize>)> {
         if offset != 0 {
             self.seek_abs(offset);
         }
 
-        let mode = self.read_mode();
-        match mode {
-            REDIRECT_MODE_1 => {
-                // Mode 1: [IP][0x01][absolute offset] - complete redirect
-                self.read_offset(true);
-                self.parse(0)
-            }
-            REDIRECT_MODE_2 => {
-                // Mode 2: [IP][0x02][country info absolute offset][area info]
-                let country = self.parse_redirect_mode2();
-                let area = self.read_area();
-                (country, area)
-            }
-            _ => {
-                // Direct storage: [IP][country][area]
-                self.seek_back();
-                let country = self.read_string(true);
-                let area = self.read_area();
-                (country, area)
+        for _ in 0..MAX_REDIRECTS {
+            let mode = self.read_mode()?;
+            match mode {
+                REDIRECT_MODE_1 => {
+                    // Mode 1: [IP][0x01][absolute offset] - complete redirect
+                    self.read_offset(true)?;
+                }
+                REDIRECT_MODE_2 => {
+                    // Mode 2: [IP][0x02][country info absolute offset][area info]
+                    let country = self.read_offset(false)? as usize;
+                    let area = self.locate_area()?;
+                    return Ok((country, area));
+                }
+                _ => {
+                    // Direct storage: [IP][country][area]
+                    self.seek_back();
+                    let country = self.pos;
+                    self.pos += self.string_at(country)?.len() + 1;
+                    let area = self.locate_area()?;
+                    return Ok((country, area));
+                }
             }
         }
+        Err(NaliError::DatabaseCorrupted(format!(
+            "redirect loop at offset 0x{:08x}",
+            offset
+        )))
     }
 
-    /// Parse redirect mode 2 country
-    fn parse_redirect_mode2(&mut self) -> Vec<u8> {
-        self.read_offset(true);
-        let str = self.read_string(false);
-        self.seek_back();
-        str
+    /// Bytes of the string at a position from [`Self::locate`]
+    pub fn string(&self, pos: Option<usize>) -> Result<Vec<u8>> {
+        match pos {
+            Some(pos) => Ok(self.string_at(pos)?.to_vec()),
+            None => Ok(Vec::new()),
+        }
     }
 
-    /// Read area information
-    fn read_area(&mut self) -> Vec<u8> {
-        let mode = self.read_mode();
+    /// Position of the area string, which may be redirected
+    fn locate_area(&mut self) -> Result<Option<usize>> {
+        let mode = self.read_mode()?;
         if mode == REDIRECT_MODE_1 || mode == REDIRECT_MODE_2 {
-            let offset = self.read_offset(true);
-            if offset == 0 {
-                return Vec::new();
-            }
+            let offset = self.read_offset(false)?;
+            Ok((offset != 0).then_some(offset as usize))
         } else {
             self.seek_back();
+            Ok(Some(self.pos))
         }
-        self.read_string(false)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Country and area bytes of the record at `offset`
+    fn parse(data: &[u8], offset: u32) -> Result<(Vec<u8>, Vec<u8>)> {
+        let mut reader = Reader::new(data);
+        let (country, area) = reader.locate(offset)?;
+        Ok((reader.string(Some(country))?, reader.string(area)?))
+    }
+
+    #[test]
+    fn test_malformed_records() {
+        // Direct record, then a record redirecting to it
+        let data = b"\x00\x00\x00\x00CN\0ISP\0\x01\x04\x00\x00";
+        assert_eq!(
+            parse(data, 0x0b).unwrap(),
+            (b"CN".to_vec(), b"ISP".to_vec())
+        );
+
+        // Offset past the end, area mode byte missing, redirect to itself
+        assert!(parse(data, 0x100).is_err());
+        assert!(parse(b"\0\0\0\0CN", 4).is_err());
+        assert!(parse(b"\x01\x00\x00\x00", 0).is_err());
+    }
+
+    #[test]
+    fn test_redirects_locate_shared_strings() {
+        // Strings, a direct record, a record pointing at the strings and one
+        // redirecting to the direct record
+        let data = b"CN\0ISP\0\0\0\0\0CN\0ISP\0\x02\x00\x00\x00\x02\x03\x00\x00\x01\x0b\x00\x00";
+        assert_eq!(Reader::new(data).locate(11).unwrap(), (11, Some(14)));
+        assert_eq!(Reader::new(data).locate(18).unwrap(), (0, Some(3)));
+        assert_eq!(Reader::new(data).locate(26).unwrap(), (11, Some(14)));
+        assert_eq!(
+            parse(data, 18).unwrap(),
+            (b"CN".to_vec(), b"ISP".to_vec())
+        );
+
+        // An area redirect to 0 is no area
+        let data = b"\x02\x08\x00\x00\x02\x00\x00\x00CN\0";
+        assert_eq!(Reader::new(data).locate(0).unwrap(), (8, None));
     }
 }
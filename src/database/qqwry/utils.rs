@@ -8,10 +8,43 @@ pub fn bytes3_to_u32(data: &[u8]) -> u32 {
     i | ((data[2] as u32) << 16) & 0xff0000
 }
 
+/// Check whether data starts like a CZ88 `.czdb` file
+///
+/// Since 2024 cz88.net ships `cz88_public_v4.czdb`, whose header is
+/// `version: u32, client_id: u32, encrypted_size: u32` followed by an
+/// AES-encrypted block keyed per account. The block is a whole number of
+/// AES blocks and small, which a classic qqwry.dat index offset never is
+/// together with a small version number.
+pub fn looks_like_czdb(data: &[u8]) -> bool {
+    let Some(header) = data.get(0..12) else {
+        return false;
+    };
+    let word = |i: usize| u32::from_le_bytes(header[i * 4..i * 4 + 4].try_into().unwrap());
+    let (version, encrypted_size) = (word(0), word(2));
+
+    (1..=16).contains(&version)
+        && encrypted_size > 0
+        && encrypted_size <= 1024
+        && encrypted_size % 16 == 0
+        && data.len() > 12 + encrypted_size as usize
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_looks_like_czdb() {
+        let mut czdb = [1u32, 20240101, 32].map(u32::to_le_bytes).concat();
+        czdb.extend_from_slice(&[0xAA; 64]);
+        assert!(looks_like_czdb(&czdb));
+
+        // Classic header: index offsets, then the first record's end IP
+        let classic = [0x0080_0000u32, 0x0090_0000, 0x00FF_FFFF].map(u32::to_le_bytes).concat();
+        assert!(!looks_like_czdb(&classic));
+        assert!(!looks_like_czdb(&czdb[..8]));
+    }
+
     #[test]
     fn test_bytes3_to_u32() {
         let data = [0x01, 0x02, 0x03];
@@ -0,0 +1,93 @@
+//! Registry for third-party database backends
+//!
+//! The built-in backends are selected through the closed `DatabaseType` enum.
+//! Downstream crates can plug in their own formats by registering a
+//! constructor under a name; the `DatabaseManager` then resolves that name
+//! (either as a database name or as the `format` of a configured database)
+//! to `DatabaseType::Registered`.
+//!
+//! ```
+//! use nali_rs::database::{DatabaseRegistry, QQwryDatabase};
+//!
+//! DatabaseRegistry::register("my-qqwry", Box::new(|| Box::new(QQwryDatabase::new())));
+//! assert!(DatabaseRegistry::is_registered("my-qqwry"));
+//! ```
+
+use once_cell::sync::Lazy;
+use std::collections::HashMap;
+use std::sync::RwLock;
+
+use super::traits::Database;
+
+/// Constructor for a registered database backend
+pub type DatabaseConstructor = Box<dyn Fn() -> Box<dyn Database + Send + Sync> + Send + Sync>;
+
+/// Global table of registered backends (name -> constructor)
+static REGISTRY: Lazy<RwLock<HashMap<String, DatabaseConstructor>>> =
+    Lazy::new(|| RwLock::new(HashMap::new()));
+
+/// Registry of third-party database backends
+pub struct DatabaseRegistry;
+
+impl DatabaseRegistry {
+    /// Register a backend constructor, replacing any previous one with the same name
+    pub fn register<S: Into<String>>(name: S, constructor: DatabaseConstructor) {
+        let name = name.into();
+        tracing::debug!("Registering database backend: {}", name);
+        if let Ok(mut registry) = REGISTRY.write() {
+            registry.insert(name, constructor);
+        }
+    }
+
+    /// Remove a registered backend, returning whether it existed
+    pub fn unregister(name: &str) -> bool {
+        REGISTRY
+            .write()
+            .map(|mut registry| registry.remove(name).is_some())
+            .unwrap_or(false)
+    }
+
+    /// Check whether a backend is registered under the given name
+    pub fn is_registered(name: &str) -> bool {
+        REGISTRY
+            .read()
+            .map(|registry| registry.contains_key(name))
+            .unwrap_or(false)
+    }
+
+    /// Create a new instance of a registered backend
+    pub fn create(name: &str) -> Option<Box<dyn Database + Send + Sync>> {
+        let registry = REGISTRY.read().ok()?;
+        registry.get(name).map(|constructor| constructor())
+    }
+
+    /// Names of all registered backends
+    pub fn names() -> Vec<String> {
+        let mut names: Vec<String> = REGISTRY
+            .read()
+            .map(|registry| registry.keys().cloned().collect())
+            .unwrap_or_default();
+        names.sort();
+        names
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::QQwryDatabase;
+
+    #[test]
+    fn test_register_and_create() {
+        DatabaseRegistry::register("registry-test", Box::new(|| Box::new(QQwryDatabase::new())));
+
+        assert!(DatabaseRegistry::is_registered("registry-test"));
+        assert!(DatabaseRegistry::names().contains(&"registry-test".to_string()));
+
+        let db = DatabaseRegistry::create("registry-test").unwrap();
+        assert_eq!(db.name(), "qqwry");
+
+        assert!(DatabaseRegistry::unregister("registry-test"));
+        assert!(DatabaseRegistry::create("registry-test").is_none());
+    }
+}
@@ -2,10 +2,11 @@
 //!
 //! This module defines the common interface that all database implementations must follow.
 
-use crate::error::Result;
+use crate::config::LoadMode;
+use crate::error::{NaliError, Result};
 use std::net::IpAddr;
 
-use super::types::{CdnProvider, DatabaseType, GeoLocation};
+use super::types::{CdnMatch, CdnProvider, DatabaseType, GeoLocation};
 
 /// Common trait for all database implementations
 pub trait Database {
@@ -21,9 +22,49 @@ pub trait Database {
     /// Look up CDN provider information
     fn lookup_cdn(&self, domain: &str) -> Result<Option<CdnProvider>>;
 
+    /// First and last address of the block `ip` falls in
+    ///
+    /// Only formats made of explicit ranges or prefixes know the block;
+    /// the others return `None`.
+    fn lookup_range(&self, _ip: IpAddr) -> Result<Option<(IpAddr, IpAddr)>> {
+        Ok(None)
+    }
+
+    /// The rule that gives `domain` its CDN provider
+    fn explain_cdn(&self, _domain: &str) -> Result<Option<CdnMatch>> {
+        Ok(None)
+    }
+
+    /// Release version or build date of the loaded file, where the format records one
+    fn version(&self) -> Option<String> {
+        None
+    }
+
+    /// Select the output language, e.g. `zh-CN`, for formats that carry several
+    fn set_language(&mut self, _language: &str) {}
+
+    /// Key to decrypt the file with, for encrypted formats
+    fn set_key(&mut self, _key: &str) {}
+
+    /// Select whether `load_from_file` maps the file or reads it whole, for
+    /// formats read in place
+    fn set_load_mode(&mut self, _mode: LoadMode) {}
+
     /// Check if database is loaded and ready to use
     fn is_loaded(&self) -> bool;
 
     /// Load database from file
     fn load_from_file(&mut self, file_path: &str) -> Result<()>;
+
+    /// Load database from the contents of a file held in memory
+    ///
+    /// For databases downloaded into RAM or embedded with `include_bytes!`;
+    /// no filesystem access is needed. Backends that cannot load from memory
+    /// return an error.
+    fn load_from_bytes(&mut self, _data: Vec<u8>) -> Result<()> {
+        Err(NaliError::Other(format!(
+            "{} database cannot be loaded from memory",
+            self.name()
+        )))
+    }
 }
@@ -2,39 +2,379 @@
 //!
 //! This module contains common types used across all database implementations.
 
-use std::net::IpAddr;
+use std::net::{IpAddr, Ipv4Addr};
+
+use super::normalize::Tunnel;
 
 /// Common result type for IP geolocation lookups
-#[derive(Debug, Clone, serde::Serialize)]
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
 pub struct GeoLocation {
     pub ip: IpAddr,
     pub country: Option<String>,
     pub region: Option<String>,
     pub city: Option<String>,
+    /// District or county within the city, for databases that go that deep
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub district: Option<String>,
     pub isp: Option<String>,
     pub country_code: Option<String>,
+    /// ISO 3166-2 code of the region, e.g. `US-CA`, where the database has one
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub region_code: Option<String>,
     pub timezone: Option<String>,
     pub latitude: Option<f64>,
     pub longitude: Option<f64>,
+    /// IPv4 address the lookup actually used, for tunneled IPv6 addresses
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tunnel: Option<Tunnel>,
+    /// Radius in kilometers around latitude/longitude the address is likely in
+    pub accuracy_radius: Option<u16>,
+    /// Block the address was matched in, as a CIDR or `start-end` range, for
+    /// databases that store explicit ranges
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub network: Option<String>,
+    /// Databases that contributed fields, the one queried first leading
+    pub sources: Vec<String>,
+}
+
+/// No fields and the unspecified address, to fill in with struct update
+/// syntax
+impl Default for GeoLocation {
+    fn default() -> Self {
+        Self::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED))
+    }
+}
+
+impl GeoLocation {
+    /// Result for `ip` without any fields
+    pub fn new(ip: IpAddr) -> Self {
+        Self {
+            ip,
+            country: None,
+            region: None,
+            city: None,
+            district: None,
+            isp: None,
+            country_code: None,
+            region_code: None,
+            timezone: None,
+            latitude: None,
+            longitude: None,
+            tunnel: None,
+            accuracy_radius: None,
+            network: None,
+            sources: Vec::new(),
+        }
+    }
+
+    /// Check whether the result carries nothing beyond a country
+    ///
+    /// Such answers are worth completing from a fallback database.
+    pub fn is_bare(&self) -> bool {
+        self.region.is_none()
+            && self.city.is_none()
+            && self.latitude.is_none()
+            && self.longitude.is_none()
+    }
+
+    /// Fill fields that are missing here from another result for the same IP
+    ///
+    /// The other result's sources are appended if it supplied any field.
+    pub fn fill_missing(&mut self, other: &GeoLocation) {
+        fn fill<T: Clone>(field: &mut Option<T>, value: &Option<T>) -> bool {
+            let filled = field.is_none() && value.is_some();
+            if filled {
+                field.clone_from(value);
+            }
+            filled
+        }
+
+        // The radius describes the coordinates, so it only comes along with them
+        if self.latitude.is_none() && other.latitude.is_some() {
+            self.accuracy_radius = other.accuracy_radius;
+        }
+        // Likewise the subdivision code names the region
+        if self.region.is_none() && other.region.is_some() {
+            self.region_code.clone_from(&other.region_code);
+        }
+
+        let filled = [
+            fill(&mut self.country, &other.country),
+            fill(&mut self.region, &other.region),
+            fill(&mut self.city, &other.city),
+            fill(&mut self.district, &other.district),
+            fill(&mut self.isp, &other.isp),
+            fill(&mut self.country_code, &other.country_code),
+            fill(&mut self.timezone, &other.timezone),
+            fill(&mut self.latitude, &other.latitude),
+            fill(&mut self.longitude, &other.longitude),
+        ];
+        if filled.contains(&true) {
+            for source in &other.sources {
+                if !self.sources.contains(source) {
+                    self.sources.push(source.clone());
+                }
+            }
+        }
+    }
+
+    /// Replace the place names with those of a finer-grained result
+    ///
+    /// Used to layer a China-specific database over a global one: the
+    /// refinement's country, region, city and district win where it has them,
+    /// and its ISP fills a missing one; coordinates and timezone stay. The
+    /// refinement's sources are appended if it changed anything.
+    pub fn refine_from(&mut self, other: &GeoLocation) {
+        fn replace(field: &mut Option<String>, value: &Option<String>) -> bool {
+            let replaced = value.is_some() && field != value;
+            if replaced {
+                field.clone_from(value);
+            }
+            replaced
+        }
+
+        // A different province invalidates the subdivision code
+        if other.region.is_some() && self.region != other.region {
+            self.region_code.clone_from(&other.region_code);
+        }
+        // A finer city leaves the old district meaningless
+        if other.city.is_some() && self.city != other.city {
+            self.district = None;
+        }
+        let isp_missing = self.isp.is_none();
+
+        let replaced = [
+            replace(&mut self.country, &other.country),
+            replace(&mut self.region, &other.region),
+            replace(&mut self.city, &other.city),
+            replace(&mut self.district, &other.district),
+            isp_missing && replace(&mut self.isp, &other.isp),
+        ];
+        if replaced.contains(&true) {
+            for source in &other.sources {
+                if !self.sources.contains(source) {
+                    self.sources.push(source.clone());
+                }
+            }
+        }
+    }
+}
+
+/// Write the block `start`-`end` as a CIDR when it is one, else as a range
+pub fn format_network(start: IpAddr, end: IpAddr) -> String {
+    let (start_bits, end_bits, width) = match (start, end) {
+        (IpAddr::V4(s), IpAddr::V4(e)) => (u32::from(s) as u128, u32::from(e) as u128, 32),
+        (IpAddr::V6(s), IpAddr::V6(e)) => (u128::from(s), u128::from(e), 128),
+        _ => return format!("{}-{}", start, end),
+    };
+
+    // A CIDR block spans a power of two that its start is aligned to
+    let host = start_bits ^ end_bits;
+    let aligned = start_bits & host == 0 && host & host.wrapping_add(1) == 0;
+    if start_bits <= end_bits && aligned {
+        let prefix = width - (128 - host.leading_zeros());
+        format!("{}/{}", start, prefix)
+    } else {
+        format!("{}-{}", start, end)
+    }
+}
+
+/// A contiguous IP range and the location it maps to
+///
+/// Produced when walking a whole database, e.g. for `nali-rs export`.
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+pub struct IpRange {
+    pub start: IpAddr,
+    pub end: IpAddr,
+    pub country: Option<String>,
+    pub region: Option<String>,
+    pub city: Option<String>,
+    pub isp: Option<String>,
+}
+
+/// Provenance of a lookup result, reported in JSON output
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+pub struct QueryMeta {
+    /// Database the query was sent to
+    pub database: String,
+    /// Release version or build date of the database file, if known
+    pub version: Option<String>,
+    /// Date the database file was built, from its release date or else its
+    /// modification time
+    pub db_build_date: Option<String>,
+    /// Time spent on the lookup, in milliseconds
+    pub query_time_ms: f64,
 }
 
 /// CDN provider information
-#[derive(Debug, Clone, serde::Serialize)]
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
 pub struct CdnProvider {
     pub domain: String,
     pub provider: String,
     pub description: Option<String>,
 }
 
+/// The CDN rule a domain matched, shown by `--explain`
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+pub struct CdnMatch {
+    pub kind: CdnMatchKind,
+    /// The rule as written in the CDN file
+    pub pattern: String,
+    /// The rule comes from the user's `cdn.local.yml`
+    pub local: bool,
+}
+
+/// Kind of CDN rule
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CdnMatchKind {
+    /// The domain itself is listed
+    Exact,
+    /// A parent domain is listed
+    Suffix,
+    /// A pattern with `*` or `?`
+    Wildcard,
+    /// A regular expression
+    Regex,
+}
+
+impl std::fmt::Display for CdnMatchKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            CdnMatchKind::Exact => "exact",
+            CdnMatchKind::Suffix => "suffix",
+            CdnMatchKind::Wildcard => "wildcard",
+            CdnMatchKind::Regex => "regex",
+        })
+    }
+}
+
+/// How a query was answered, shown by `--explain`
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+pub struct Explanation {
+    /// Database that holds the matched block or rule
+    pub database: String,
+    /// First and last address of the matched block, for IP queries
+    pub range: Option<(std::net::IpAddr, std::net::IpAddr)>,
+    /// The CDN rule that matched, for domain queries
+    pub cdn_match: Option<CdnMatch>,
+    /// The answer was already in the query cache
+    pub cached: bool,
+}
+
+impl std::fmt::Display for Explanation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "database {}", self.database)?;
+        if let Some((start, end)) = self.range {
+            write!(f, ", block {} - {}", start, end)?;
+        }
+        if let Some(ref rule) = self.cdn_match {
+            write!(f, ", {} match on {}", rule.kind, rule.pattern)?;
+            if rule.local {
+                f.write_str(" (local)")?;
+            }
+        }
+        write!(f, ", cache {}", if self.cached { "hit" } else { "miss" })
+    }
+}
+
 /// Database type enumeration
 #[derive(Debug, Clone)]
 pub enum DatabaseType {
     QQwry,       // QQwry IPv4 database
+    Czdb,        // CZ88 encrypted czdb database
     ZXIPv6Wry,   // ZX IPv6 database
     GeoIP2,      // GeoIP2 database
+    GeoCN,       // GeoCN China district-level database
     IPIP,        // IPIP database
+    IPDB,        // ipip.net IPDB database
     IP2Region,   // ip2region database
     DBIP,        // DB-IP database
     IP2Location, // IP2Location database
     CDN,         // CDN database
+    Custom,      // nali-native database built from CSV
+    Registered(String), // Third-party backend from DatabaseRegistry
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn geo(country: Option<&str>, city: Option<&str>) -> GeoLocation {
+        GeoLocation {
+            ip: "2001:db8::1".parse().unwrap(),
+            country: country.map(str::to_string),
+            city: city.map(str::to_string),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_fill_missing_keeps_existing_fields() {
+        let mut primary = geo(Some("中国"), None);
+        primary.sources = vec!["qqwry".to_string()];
+        assert!(primary.is_bare());
+
+        let mut fallback = geo(Some("China"), Some("Beijing"));
+        fallback.sources = vec!["geoip2".to_string()];
+        fallback.latitude = Some(39.9);
+        fallback.accuracy_radius = Some(20);
+        primary.fill_missing(&fallback);
+        assert_eq!(primary.country.as_deref(), Some("中国"));
+        assert_eq!(primary.city.as_deref(), Some("Beijing"));
+        assert_eq!(primary.accuracy_radius, Some(20));
+        assert_eq!(primary.sources, ["qqwry", "geoip2"]);
+        assert!(!primary.is_bare());
+
+        // A result that adds nothing isn't listed as a source
+        let mut other = geo(Some("CN"), None);
+        other.sources = vec!["dbip".to_string()];
+        primary.fill_missing(&other);
+        assert_eq!(primary.sources, ["qqwry", "geoip2"]);
+    }
+
+    #[test]
+    fn test_refine_from() {
+        let mut global = geo(Some("中国"), Some("南京"));
+        global.region = Some("江苏".to_string());
+        global.region_code = Some("CN-JS".to_string());
+        global.latitude = Some(32.06);
+        global.sources = vec!["geoip2".to_string()];
+
+        let mut china = geo(Some("中国"), Some("南京市"));
+        china.region = Some("江苏省".to_string());
+        china.district = Some("玄武区".to_string());
+        china.isp = Some("电信".to_string());
+        china.sources = vec!["geocn".to_string()];
+        global.refine_from(&china);
+        assert_eq!(global.region.as_deref(), Some("江苏省"));
+        assert_eq!(global.region_code, None);
+        assert_eq!(global.city.as_deref(), Some("南京市"));
+        assert_eq!(global.district.as_deref(), Some("玄武区"));
+        assert_eq!(global.isp.as_deref(), Some("电信"));
+        assert_eq!(global.latitude, Some(32.06));
+        assert_eq!(global.sources, ["geoip2", "geocn"]);
+
+        // A refinement that only knows the province keeps the rest
+        let mut coarse = geo(Some("中国"), None);
+        coarse.region = Some("江苏省".to_string());
+        coarse.isp = Some("联通".to_string());
+        coarse.sources = vec!["other".to_string()];
+        global.refine_from(&coarse);
+        assert_eq!(global.district.as_deref(), Some("玄武区"));
+        assert_eq!(global.isp.as_deref(), Some("电信"));
+        assert_eq!(global.sources, ["geoip2", "geocn"]);
+    }
+
+    #[test]
+    fn test_format_network() {
+        let network = |start: &str, end: &str| format_network(start.parse().unwrap(), end.parse().unwrap());
+        assert_eq!(network("1.2.3.0", "1.2.3.255"), "1.2.3.0/24");
+        assert_eq!(network("8.8.8.8", "8.8.8.8"), "8.8.8.8/32");
+        assert_eq!(network("0.0.0.0", "255.255.255.255"), "0.0.0.0/0");
+        assert_eq!(network("1.0.1.0", "1.0.3.255"), "1.0.1.0-1.0.3.255");
+        assert_eq!(network("1.0.1.0", "1.0.2.255"), "1.0.1.0-1.0.2.255");
+        assert_eq!(network("240e::", "240e:ffff:ffff:ffff:ffff:ffff:ffff:ffff"), "240e::/16");
+        assert_eq!(network("::", "ffff:ffff:ffff:ffff:ffff:ffff:ffff:ffff"), "::/0");
+    }
 }
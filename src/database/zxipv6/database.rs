@@ -1,23 +1,58 @@
 //! ZX IPv6 database implementation core
 
-use crate::database::{CdnProvider, Database, DatabaseType, GeoLocation};
-use crate::error::Result;
-use memmap2::Mmap;
-use std::fs::File;
-use std::net::IpAddr;
+use crate::config::LoadMode;
+use crate::database::location_cache::{LocationCache, pair_key};
+use crate::database::normalize;
+use crate::database::types::format_network;
+use crate::database::{CdnProvider, Database, DatabaseBytes, DatabaseType, GeoLocation};
+use crate::error::{NaliError, Result};
+use std::net::{IpAddr, Ipv6Addr};
 
 use super::reader::Reader;
 use super::utils::{bytes3_to_u32, check_file};
 
+/// Decode the country and area strings stored at the given positions
+fn decode_strings(data: &[u8], country: usize, area: Option<usize>) -> Result<(Option<String>, Option<String>)> {
+    let reader = Reader::new(data);
+    let (country_bytes, area_bytes) = (reader.string(Some(country))?, reader.string(area)?);
+
+    // ZX IPv6 database uses UTF-8 encoding (not GBK like QQwry)
+    // Convert bytes directly to UTF-8 strings
+    tracing::debug!("Strings at 0x{:08x}, {:08x?}", country, area);
+    tracing::debug!("Country bytes: {:?}", country_bytes);
+    tracing::debug!("Country hex: {:02x?}", country_bytes);
+    tracing::debug!("Area bytes: {:?}", area_bytes);
+    tracing::debug!("Area hex: {:02x?}", area_bytes);
+
+    let country = String::from_utf8_lossy(&country_bytes).to_string();
+    let area = String::from_utf8_lossy(&area_bytes).to_string();
+
+    tracing::debug!("Country string: '{}'", country);
+    tracing::debug!("Area string: '{}'", area);
+
+    // Clean up the strings
+    let country = country.replace("CZ88.NET", "").trim().to_string();
+    let area = area.replace("CZ88.NET", "").trim().to_string();
+
+    Ok((
+        if !country.is_empty() { Some(country) } else { None },
+        if !area.is_empty() { Some(area) } else { None },
+    ))
+}
+
 /// ZX IPv6 database implementation
 pub struct ZXIPv6Database {
     name: String,
     loaded: bool,
-    mmap: Option<Mmap>,
+    data: Option<DatabaseBytes>,
+    /// Whether `load_from_file` maps the file or reads it whole
+    load_mode: LoadMode,
     idx_start: u64,
     idx_end: u64,
     off_len: u8,
     ip_len: u8,
+    /// Country and area by the positions of their strings
+    locations: LocationCache<(Option<String>, Option<String>)>,
 }
 
 impl ZXIPv6Database {
@@ -25,40 +60,47 @@ impl ZXIPv6Database {
         Self {
             name: "zxipv6wry".to_string(),
             loaded: false,
-            mmap: None,
+            data: None,
+            load_mode: LoadMode::default(),
             idx_start: 0,
             idx_end: 0,
             off_len: 0,
             ip_len: 0,
+            locations: LocationCache::default(),
         }
     }
 
-    /// Search index for IPv6 address (using first 64 bits)
-    fn search_index(&self, ip: u64) -> Result<u32> {
-        if let Some(ref mmap) = self.mmap {
-            let ip_len = self.ip_len as u64;
+    /// Bytes `from..to` of the loaded file
+    fn read(data: &[u8], from: u64, to: u64) -> Result<&[u8]> {
+        data.get(from as usize..to as usize)
+            .ok_or(NaliError::IndexOutOfBounds(to as usize, data.len()))
+    }
+
+    /// Search index for IPv6 address (using first 64 bits), returning the
+    /// position of its entry
+    fn search_index(&self, ip: u64) -> Result<u64> {
+        if let Some(ref mmap) = self.data {
             let entry_len = (self.off_len + self.ip_len) as u64;
 
+            let read = |from: u64, to: u64| Self::read(mmap, from, to);
+
             let mut l = self.idx_start;
             let mut r = self.idx_end;
 
             loop {
                 let mid = (r - l) / entry_len / 2 * entry_len + l;
-                let mid_ip = u64::from_le_bytes(
-                    mmap[mid as usize..mid as usize + 8].try_into()?
-                );
+                let mid_ip = u64::from_le_bytes(read(mid, mid + 8)?.try_into()?);
 
                 // Check if we've narrowed down to one entry
                 if r - l == entry_len {
-                    // Check the right boundary
-                    let r_ip = u64::from_le_bytes(
-                        mmap[r as usize..r as usize + 8].try_into()?
-                    );
-
-                    let offset_pos = if ip >= r_ip { r } else { mid };
-                    return Ok(bytes3_to_u32(
-                        &mmap[offset_pos as usize + ip_len as usize..offset_pos as usize + entry_len as usize]
-                    ));
+                    // Check the right boundary, unless it is the end of the
+                    // index, past the last entry
+                    if r == self.idx_end {
+                        return Ok(mid);
+                    }
+                    let r_ip = u64::from_le_bytes(read(r, r + 8)?.try_into()?);
+
+                    return Ok(if ip >= r_ip { r } else { mid });
                 }
 
                 if mid_ip > ip {
@@ -67,64 +109,73 @@ impl ZXIPv6Database {
                     l = mid;
                 } else {
                     // Exact match
-                    return Ok(bytes3_to_u32(
-                        &mmap[mid as usize + ip_len as usize..mid as usize + entry_len as usize]
-                    ));
+                    return Ok(mid);
                 }
             }
         } else {
-            Err(crate::error::NaliError::parse("Database not loaded"))
+            Err(crate::error::NaliError::DatabaseNotLoaded(self.name.clone()))
         }
     }
 
+    /// First and last 64 bit prefix of the index entry covering `prefix`
+    fn block(&self, prefix: u64) -> Result<(u64, u64, u64)> {
+        let mmap = self
+            .data
+            .as_ref()
+            .ok_or_else(|| NaliError::DatabaseNotLoaded(self.name.clone()))?;
+
+        let pos = self.search_index(prefix)?;
+        let start = u64::from_le_bytes(Self::read(mmap, pos, pos + 8)?.try_into()?);
+        let next = pos + (self.off_len + self.ip_len) as u64;
+        let end = match Self::read(mmap, next, next + 8) {
+            Ok(bytes) if next < self.idx_end => u64::from_le_bytes(bytes.try_into()?).saturating_sub(1),
+            _ => u64::MAX,
+        };
+        Ok((pos, start, end))
+    }
+
     /// Lookup IPv6 address
-    fn lookup_ipv6(&self, ip: u64) -> Result<Option<GeoLocation>> {
-        if let Some(ref mmap) = self.mmap {
-            // Search for the record offset
-            let offset = self.search_index(ip)?;
-
-            // Parse the record at offset using the same logic as QQwry
-            let mut reader = Reader::new(mmap);
-            let (country_bytes, area_bytes) = reader.parse(offset);
-
-            // ZX IPv6 database uses UTF-8 encoding (not GBK like QQwry)
-            // Convert bytes directly to UTF-8 strings
-            log::debug!("Offset: 0x{:08x}", offset);
-            log::debug!("Country bytes: {:?}", country_bytes);
-            log::debug!("Country hex: {:02x?}", country_bytes);
-            log::debug!("Area bytes: {:?}", area_bytes);
-            log::debug!("Area hex: {:02x?}", area_bytes);
-
-            let country = String::from_utf8_lossy(&country_bytes).to_string();
-            let area = String::from_utf8_lossy(&area_bytes).to_string();
-
-            log::debug!("Country string: '{}'", country);
-            log::debug!("Area string: '{}'", area);
-
-            // Clean up the strings
-            let country = country.replace("CZ88.NET", "").trim().to_string();
-            let area = area.replace("CZ88.NET", "").trim().to_string();
-
-            // Reconstruct the full IPv6 address for display
-            let ip_bytes = ip.to_be_bytes();
-            let mut full_ipv6_bytes = [0u8; 16];
-            full_ipv6_bytes[0..8].copy_from_slice(&ip_bytes);
-            let ip_addr = IpAddr::V6(std::net::Ipv6Addr::from(full_ipv6_bytes));
-
-            Ok(Some(GeoLocation {
-                ip: ip_addr,
-                country: if !country.is_empty() { Some(country) } else { None },
-                region: None,
-                city: None,
-                isp: if !area.is_empty() { Some(area) } else { None },
-                country_code: None,
-                timezone: None,
-                latitude: None,
-                longitude: None,
-            }))
-        } else {
-            Ok(None)
-        }
+    ///
+    /// The index only resolves the first 64 bits, so the answer covers whole
+    /// /64s and its `network` says how many. Addresses in the 4in6 (IPv4-mapped,
+    /// NAT64), 6to4 and Teredo sections carry their client in the low bits,
+    /// which the file knows nothing about: their answer describes the section
+    /// and `tunnel` holds the embedded IPv4 address to look up instead.
+    fn lookup_ipv6(&self, ip: Ipv6Addr) -> Result<Option<GeoLocation>> {
+        let Some(ref mmap) = self.data else {
+            return Ok(None);
+        };
+
+        let (pos, start, end) = self.block((u128::from(ip) >> 64) as u64)?;
+        let entry_len = (self.off_len + self.ip_len) as u64;
+        let offset = bytes3_to_u32(Self::read(mmap, pos + self.ip_len as u64, pos + entry_len)?);
+
+        // Records are located the same way as in QQwry; many redirect to
+        // the same strings
+        let (country_pos, area_pos) = Reader::new(mmap).locate(offset)?;
+        let (country, area) = self
+            .locations
+            .get_or_decode(pair_key(country_pos, area_pos), || {
+                decode_strings(mmap, country_pos, area_pos)
+            })?;
+
+        let tunnel = normalize::embedded_ipv4(ip);
+        let network = match tunnel {
+            Some(tunnel) => tunnel.mechanism.network().to_string(),
+            None => format_network(
+                IpAddr::V6(((start as u128) << 64).into()),
+                IpAddr::V6(((end as u128) << 64 | u64::MAX as u128).into()),
+            ),
+        };
+
+        Ok(Some(GeoLocation {
+            ip: IpAddr::V6(ip),
+            country,
+            isp: area,
+            tunnel,
+            network: Some(network),
+            ..Default::default()
+        }))
     }
 }
 
@@ -155,15 +206,7 @@ impl Database for ZXIPv6Database {
                 // ZX IPv6 database doesn't support IPv4
                 Ok(None)
             }
-            IpAddr::V6(ipv6) => {
-                // ZX IPv6 only uses first 64 bits
-                let ip_bytes = ipv6.octets();
-                let ip_u64 = u64::from_be_bytes([
-                    ip_bytes[0], ip_bytes[1], ip_bytes[2], ip_bytes[3],
-                    ip_bytes[4], ip_bytes[5], ip_bytes[6], ip_bytes[7],
-                ]);
-                self.lookup_ipv6(ip_u64)
-            }
+            IpAddr::V6(ipv6) => self.lookup_ipv6(ipv6),
         }
     }
 
@@ -172,41 +215,69 @@ impl Database for ZXIPv6Database {
         Ok(None)
     }
 
+    /// Covers whole /64s: from this index entry up to the next one
+    fn lookup_range(&self, ip: IpAddr) -> Result<Option<(IpAddr, IpAddr)>> {
+        let (IpAddr::V6(ipv6), true) = (ip, self.data.is_some()) else {
+            return Ok(None);
+        };
+
+        let (_, start, end) = self.block((u128::from(ipv6) >> 64) as u64)?;
+        let start = IpAddr::V6(((start as u128) << 64).into());
+        let end = IpAddr::V6(((end as u128) << 64 | u64::MAX as u128).into());
+        Ok(Some((start, end)))
+    }
+
+    fn set_load_mode(&mut self, mode: LoadMode) {
+        self.load_mode = mode;
+    }
+
     fn is_loaded(&self) -> bool {
         self.loaded
     }
 
     fn load_from_file(&mut self, file_path: &str) -> Result<()> {
-        log::info!("Loading ZX IPv6 database from: {}", file_path);
-
-        // Open and memory map the file
-        let file = File::open(file_path)
-            .map_err(crate::error::NaliError::IoError)?;
+        tracing::info!("Loading ZX IPv6 database from: {}", file_path);
+        self.load(DatabaseBytes::open(file_path, "ZX IPv6", self.load_mode)?)
+    }
 
-        let mmap = unsafe { Mmap::map(&file) }
-            .map_err(|e| crate::error::NaliError::parse(format!("Failed to memory map ZX IPv6 database: {}", e)))?;
+    fn load_from_bytes(&mut self, data: Vec<u8>) -> Result<()> {
+        self.load(data.into())
+    }
+}
 
+impl ZXIPv6Database {
+    /// Validate the header and start answering lookups from `data`
+    fn load(&mut self, data: DatabaseBytes) -> Result<()> {
         // Check file validity
-        if !check_file(&mmap) {
-            return Err(crate::error::NaliError::parse("Invalid ZX IPv6 database: file validation failed"));
+        if !check_file(&data) {
+            return Err(crate::error::NaliError::format("Invalid ZX IPv6 database: file validation failed"));
         }
 
         // Read header
-        let header = &mmap[0..24];
+        let header = &data[0..24];
         let off_len = header[6];
         let ip_len = header[7];
-        let counts = u64::from_le_bytes(mmap[8..16].try_into()?);
-        let idx_start = u64::from_le_bytes(mmap[16..24].try_into()?);
+        let counts = u64::from_le_bytes(data[8..16].try_into()?);
+        let idx_start = u64::from_le_bytes(data[16..24].try_into()?);
         let idx_end = idx_start + counts * 11;
 
+        // Lookups read 8 byte IPs and 3 byte offsets
+        if ip_len != 8 || off_len != 3 {
+            return Err(crate::error::NaliError::format(format!(
+                "Invalid ZX IPv6 database: unsupported index entry of {} byte IPs and {} byte offsets",
+                ip_len, off_len
+            )));
+        }
+
         self.off_len = off_len;
         self.ip_len = ip_len;
         self.idx_start = idx_start;
         self.idx_end = idx_end;
-        self.mmap = Some(mmap);
+        self.data = Some(data);
+        self.locations.clear();
         self.loaded = true;
 
-        log::info!("Successfully loaded ZX IPv6 database: {} records", counts);
+        tracing::info!("Successfully loaded ZX IPv6 database: {} records", counts);
 
         Ok(())
     }
@@ -217,3 +288,51 @@ impl Default for ZXIPv6Database {
         Self::new()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::TunnelMechanism;
+
+    fn fixture() -> ZXIPv6Database {
+        let path = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/ipv6wry.db");
+        let mut db = ZXIPv6Database::new();
+        db.load_from_file(path).unwrap();
+        db
+    }
+
+    fn lookup(db: &ZXIPv6Database, ip: &str) -> GeoLocation {
+        db.lookup_ip(ip.parse().unwrap()).unwrap().unwrap()
+    }
+
+    #[test]
+    fn test_lookup_keeps_full_address() {
+        let db = fixture();
+        let geo = lookup(&db, "240e:3a1:4c4:1e0::1");
+        assert_eq!(geo.ip.to_string(), "240e:3a1:4c4:1e0::1");
+        assert_eq!(geo.country.as_deref(), Some("中国"));
+        assert_eq!(geo.network.as_deref(), Some("240e::/16"));
+        assert_eq!(geo.tunnel, None);
+    }
+
+    #[test]
+    fn test_special_sections() {
+        let db = fixture();
+
+        // RFC 4380 example: client 192.0.2.45
+        let geo = lookup(&db, "2001:0:4136:e378:8000:63bf:3fff:fdd2");
+        assert_eq!(geo.network.as_deref(), Some("2001::/32"));
+        let tunnel = geo.tunnel.unwrap();
+        assert_eq!(tunnel.mechanism, TunnelMechanism::Teredo);
+        assert_eq!(tunnel.ipv4.to_string(), "192.0.2.45");
+
+        let geo = lookup(&db, "::ffff:1.2.3.4");
+        assert_eq!(geo.network.as_deref(), Some("::ffff:0:0/96"));
+        assert_eq!(geo.tunnel.unwrap().ipv4.to_string(), "1.2.3.4");
+
+        // Google's block follows the Teredo section without being part of it
+        let geo = lookup(&db, "2001:4860::8888");
+        assert_eq!(geo.network.as_deref(), Some("2001:4860::/32"));
+        assert_eq!(geo.tunnel, None);
+    }
+}
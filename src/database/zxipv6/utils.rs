@@ -23,11 +23,9 @@ pub fn check_file(data: &[u8]) -> bool {
 
     let start = u64::from_le_bytes(data[16..24].try_into().unwrap_or([0u8; 8]));
     let counts = u64::from_le_bytes(data[8..16].try_into().unwrap_or([0u8; 8]));
-    let end = start + counts * 11;
-
-    if start >= end || (data.len() as u64) < end {
+    let Some(end) = counts.checked_mul(11).and_then(|len| start.checked_add(len)) else {
         return false;
-    }
+    };
 
-    true
+    start < end && (data.len() as u64) >= end
 }
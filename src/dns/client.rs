@@ -0,0 +1,166 @@
+//! DNS transport: UDP with TCP fallback
+
+use crate::error::{NaliError, Result};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpStream, UdpSocket};
+
+use super::message::{RecordType, Response, build_query, parse_response};
+
+/// Default DNS port
+pub const DNS_PORT: u16 = 53;
+
+/// Largest UDP response accepted (EDNS-sized buffer)
+const UDP_BUFFER_LEN: usize = 4096;
+
+/// Resolver used when none is configured
+const FALLBACK_SERVER: IpAddr = IpAddr::V4(Ipv4Addr::new(8, 8, 8, 8));
+
+/// Sends single-question queries to one server
+#[derive(Debug, Clone)]
+pub struct Resolver {
+    server: SocketAddr,
+    timeout: Duration,
+}
+
+impl Resolver {
+    pub fn new(server: SocketAddr, timeout: Duration) -> Self {
+        Self { server, timeout }
+    }
+
+    /// Resolver using the first nameserver in /etc/resolv.conf, or 8.8.8.8
+    pub fn system(timeout: Duration) -> Self {
+        let server = std::fs::read_to_string("/etc/resolv.conf")
+            .ok()
+            .and_then(|conf| parse_resolv_conf(&conf))
+            .unwrap_or(FALLBACK_SERVER);
+        Self::new(SocketAddr::new(server, DNS_PORT), timeout)
+    }
+
+    /// Address of the server queried
+    pub fn server(&self) -> SocketAddr {
+        self.server
+    }
+
+    /// Query one name, retrying over TCP if the UDP answer was truncated
+    pub async fn query(&self, name: &str, rtype: RecordType) -> Result<Response> {
+        let id = query_id();
+        let query = build_query(id, name, rtype)?;
+
+        let response = self.query_udp(&query, id).await?;
+        if response.truncated {
+            tracing::debug!("Truncated UDP response from {}, retrying over TCP", self.server);
+            return self.query_tcp(&query, id).await;
+        }
+        Ok(response)
+    }
+
+    async fn query_udp(&self, query: &[u8], id: u16) -> Result<Response> {
+        let bind: SocketAddr = match self.server {
+            SocketAddr::V4(_) => (Ipv4Addr::UNSPECIFIED, 0).into(),
+            SocketAddr::V6(_) => (std::net::Ipv6Addr::UNSPECIFIED, 0).into(),
+        };
+        let socket = UdpSocket::bind(bind).await?;
+        socket.connect(self.server).await?;
+        socket.send(query).await?;
+
+        let mut buf = vec![0u8; UDP_BUFFER_LEN];
+        loop {
+            let len = tokio::time::timeout(self.timeout, socket.recv(&mut buf))
+                .await
+                .map_err(|_| self.timed_out())??;
+            let response = parse_response(&buf[..len])?;
+            // Ignore stray datagrams for other queries
+            if response.id == id {
+                return Ok(response);
+            }
+        }
+    }
+
+    async fn query_tcp(&self, query: &[u8], id: u16) -> Result<Response> {
+        let exchange = async {
+            let mut stream = TcpStream::connect(self.server).await?;
+            stream.write_all(&(query.len() as u16).to_be_bytes()).await?;
+            stream.write_all(query).await?;
+
+            let len = stream.read_u16().await? as usize;
+            let mut buf = vec![0u8; len];
+            stream.read_exact(&mut buf).await?;
+            Ok::<_, NaliError>(buf)
+        };
+
+        let buf = tokio::time::timeout(self.timeout, exchange)
+            .await
+            .map_err(|_| self.timed_out())??;
+        let response = parse_response(&buf)?;
+        if response.id != id {
+            return Err(NaliError::parse("DNS response ID mismatch"));
+        }
+        Ok(response)
+    }
+
+    fn timed_out(&self) -> NaliError {
+        NaliError::network(format!("DNS query to {} timed out", self.server))
+    }
+}
+
+/// First usable `nameserver` entry of a resolv.conf
+fn parse_resolv_conf(conf: &str) -> Option<IpAddr> {
+    conf.lines()
+        .map(|line| line.split(['#', ';']).next().unwrap_or_default())
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace();
+            (fields.next()? == "nameserver").then(|| fields.next())?
+        })
+        // Drop any IPv6 zone id, which SocketAddr cannot carry
+        .find_map(|addr| addr.split('%').next()?.parse().ok())
+}
+
+/// Query ID derived from the clock; DNS IDs only need to differ between queries
+fn query_id() -> u16 {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.subsec_nanos())
+        .unwrap_or_default();
+    (nanos ^ std::process::id()) as u16
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_resolv_conf() {
+        let conf = "# generated\nsearch lan\nnameserver bogus\nnameserver 192.168.1.1 # router\nnameserver 1.1.1.1\n";
+        assert_eq!(parse_resolv_conf(conf), Some("192.168.1.1".parse().unwrap()));
+        assert_eq!(
+            parse_resolv_conf("nameserver fe80::1%eth0\n"),
+            Some("fe80::1".parse().unwrap())
+        );
+        assert_eq!(parse_resolv_conf("search lan\n"), None);
+    }
+
+    #[tokio::test]
+    async fn test_query_udp() {
+        let server = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let addr = server.local_addr().unwrap();
+
+        tokio::spawn(async move {
+            let mut buf = [0u8; 512];
+            let (len, peer) = server.recv_from(&mut buf).await.unwrap();
+            // Echo the question back as a response with one A record
+            let mut reply = buf[..len].to_vec();
+            reply[2] = 0x81;
+            reply[3] = 0x80;
+            reply[7] = 1;
+            reply.extend_from_slice(&[0xC0, 12, 0, 1, 0, 1, 0, 0, 0, 30, 0, 4, 9, 9, 9, 9]);
+            server.send_to(&reply, peer).await.unwrap();
+        });
+
+        let resolver = Resolver::new(addr, Duration::from_secs(2));
+        let response = resolver.query("example.com", RecordType::A).await.unwrap();
+        assert_eq!(response.answers.len(), 1);
+        assert_eq!(response.answers[0].data.to_string(), "9.9.9.9");
+    }
+}
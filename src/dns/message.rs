@@ -0,0 +1,455 @@
+//! DNS wire format encoding and decoding
+
+use crate::error::{NaliError, Result};
+use crate::utils::idn;
+use std::fmt;
+use std::net::{Ipv4Addr, Ipv6Addr};
+use std::str::FromStr;
+
+/// Header length in bytes
+const HEADER_LEN: usize = 12;
+
+/// Internet class
+pub const CLASS_IN: u16 = 1;
+
+/// Limit on compression pointers followed while reading one name
+const MAX_POINTERS: usize = 64;
+
+/// Record types understood by the client
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RecordType {
+    A,
+    NS,
+    CNAME,
+    SOA,
+    PTR,
+    MX,
+    TXT,
+    AAAA,
+    ANY,
+    Other(u16),
+}
+
+impl RecordType {
+    /// Numeric type code
+    pub fn code(self) -> u16 {
+        match self {
+            RecordType::A => 1,
+            RecordType::NS => 2,
+            RecordType::CNAME => 5,
+            RecordType::SOA => 6,
+            RecordType::PTR => 12,
+            RecordType::MX => 15,
+            RecordType::TXT => 16,
+            RecordType::AAAA => 28,
+            RecordType::ANY => 255,
+            RecordType::Other(code) => code,
+        }
+    }
+
+    /// Record type for a numeric code
+    pub fn from_code(code: u16) -> Self {
+        match code {
+            1 => RecordType::A,
+            2 => RecordType::NS,
+            5 => RecordType::CNAME,
+            6 => RecordType::SOA,
+            12 => RecordType::PTR,
+            15 => RecordType::MX,
+            16 => RecordType::TXT,
+            28 => RecordType::AAAA,
+            255 => RecordType::ANY,
+            code => RecordType::Other(code),
+        }
+    }
+}
+
+impl FromStr for RecordType {
+    type Err = NaliError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_uppercase().as_str() {
+            "A" => Ok(RecordType::A),
+            "NS" => Ok(RecordType::NS),
+            "CNAME" => Ok(RecordType::CNAME),
+            "SOA" => Ok(RecordType::SOA),
+            "PTR" => Ok(RecordType::PTR),
+            "MX" => Ok(RecordType::MX),
+            "TXT" => Ok(RecordType::TXT),
+            "AAAA" => Ok(RecordType::AAAA),
+            "ANY" => Ok(RecordType::ANY),
+            other => other
+                .strip_prefix("TYPE")
+                .and_then(|code| code.parse().ok())
+                .map(RecordType::Other)
+                .ok_or_else(|| NaliError::parse(format!("Unknown record type: {}", s))),
+        }
+    }
+}
+
+impl fmt::Display for RecordType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RecordType::Other(code) => write!(f, "TYPE{}", code),
+            other => write!(f, "{:?}", other),
+        }
+    }
+}
+
+/// Decoded record data
+#[derive(Debug, Clone, PartialEq)]
+pub enum RecordData {
+    A(Ipv4Addr),
+    AAAA(Ipv6Addr),
+    /// CNAME, NS and PTR targets
+    Name(String),
+    MX { preference: u16, exchange: String },
+    TXT(Vec<String>),
+    SOA { mname: String, rname: String, serial: u32 },
+    Raw(Vec<u8>),
+}
+
+impl fmt::Display for RecordData {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RecordData::A(ip) => write!(f, "{}", ip),
+            RecordData::AAAA(ip) => write!(f, "{}", ip),
+            RecordData::Name(name) => write!(f, "{}", name),
+            RecordData::MX {
+                preference,
+                exchange,
+            } => write!(f, "{} {}", preference, exchange),
+            RecordData::TXT(parts) => {
+                let quoted: Vec<String> = parts.iter().map(|p| format!("{:?}", p)).collect();
+                write!(f, "{}", quoted.join(" "))
+            }
+            RecordData::SOA {
+                mname,
+                rname,
+                serial,
+            } => write!(f, "{} {} {}", mname, rname, serial),
+            RecordData::Raw(bytes) => write!(f, "\\# {}", bytes.len()),
+        }
+    }
+}
+
+/// A resource record
+#[derive(Debug, Clone, PartialEq)]
+pub struct Record {
+    pub name: String,
+    pub rtype: RecordType,
+    pub class: u16,
+    pub ttl: u32,
+    pub data: RecordData,
+}
+
+/// A decoded response
+#[derive(Debug, Clone)]
+pub struct Response {
+    pub id: u16,
+    pub rcode: u8,
+    pub truncated: bool,
+    pub answers: Vec<Record>,
+    pub authority: Vec<Record>,
+}
+
+impl Response {
+    /// Name of the response code
+    pub fn rcode_name(&self) -> String {
+        match self.rcode {
+            0 => "NOERROR".to_string(),
+            1 => "FORMERR".to_string(),
+            2 => "SERVFAIL".to_string(),
+            3 => "NXDOMAIN".to_string(),
+            4 => "NOTIMP".to_string(),
+            5 => "REFUSED".to_string(),
+            code => format!("RCODE{}", code),
+        }
+    }
+}
+
+/// Encode a recursive query for one question
+pub fn build_query(id: u16, name: &str, rtype: RecordType) -> Result<Vec<u8>> {
+    let mut buf = Vec::with_capacity(HEADER_LEN + name.len() + 6);
+    buf.extend_from_slice(&id.to_be_bytes());
+    // Flags: standard query, recursion desired
+    buf.extend_from_slice(&0x0100u16.to_be_bytes());
+    // One question, no other records
+    buf.extend_from_slice(&[0, 1, 0, 0, 0, 0, 0, 0]);
+
+    // Internationalized names go on the wire in their ASCII form
+    let name = if name.is_ascii() {
+        name.to_string()
+    } else {
+        idn::to_ascii(name).ok_or_else(|| NaliError::parse(format!("Invalid domain name: {}", name)))?
+    };
+    for label in name.trim_end_matches('.').split('.').filter(|l| !l.is_empty()) {
+        if label.len() > 63 {
+            return Err(NaliError::parse(format!("DNS label too long: {}", label)));
+        }
+        buf.push(label.len() as u8);
+        buf.extend_from_slice(label.as_bytes());
+    }
+    buf.push(0);
+
+    buf.extend_from_slice(&rtype.code().to_be_bytes());
+    buf.extend_from_slice(&CLASS_IN.to_be_bytes());
+    Ok(buf)
+}
+
+/// Bounds-checked reader over a message
+struct Reader<'a> {
+    buf: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8]> {
+        let bytes = self
+            .buf
+            .get(self.pos..self.pos + len)
+            .ok_or(NaliError::IndexOutOfBounds(self.pos + len, self.buf.len()))?;
+        self.pos += len;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> Result<u8> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16> {
+        Ok(u16::from_be_bytes(self.bytes(2)?.try_into()?))
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        Ok(u32::from_be_bytes(self.bytes(4)?.try_into()?))
+    }
+
+    /// Read a possibly compressed name, leaving the cursor after it
+    fn name(&mut self) -> Result<String> {
+        let mut labels: Vec<String> = Vec::new();
+        let mut pos = self.pos;
+        let mut resume = None;
+        let mut pointers = 0;
+
+        loop {
+            let len = *self
+                .buf
+                .get(pos)
+                .ok_or(NaliError::IndexOutOfBounds(pos, self.buf.len()))? as usize;
+
+            if len & 0xC0 == 0xC0 {
+                let low = *self
+                    .buf
+                    .get(pos + 1)
+                    .ok_or(NaliError::IndexOutOfBounds(pos + 1, self.buf.len()))?
+                    as usize;
+                resume.get_or_insert(pos + 2);
+                pointers += 1;
+                if pointers > MAX_POINTERS {
+                    return Err(NaliError::parse("DNS name compression loop"));
+                }
+                pos = ((len & 0x3F) << 8) | low;
+                continue;
+            }
+
+            pos += 1;
+            if len == 0 {
+                break;
+            }
+            let label = self
+                .buf
+                .get(pos..pos + len)
+                .ok_or(NaliError::IndexOutOfBounds(pos + len, self.buf.len()))?;
+            labels.push(String::from_utf8_lossy(label).into_owned());
+            pos += len;
+        }
+
+        self.pos = resume.unwrap_or(pos);
+        Ok(format!("{}.", labels.join(".")))
+    }
+
+    fn record(&mut self) -> Result<Record> {
+        let name = self.name()?;
+        let rtype = RecordType::from_code(self.u16()?);
+        let class = self.u16()?;
+        let ttl = self.u32()?;
+        let rdlength = self.u16()? as usize;
+        let end = self.pos + rdlength;
+
+        let data = match rtype {
+            RecordType::A if rdlength == 4 => {
+                let b: [u8; 4] = self.bytes(4)?.try_into()?;
+                RecordData::A(Ipv4Addr::from(b))
+            }
+            RecordType::AAAA if rdlength == 16 => {
+                let b: [u8; 16] = self.bytes(16)?.try_into()?;
+                RecordData::AAAA(Ipv6Addr::from(b))
+            }
+            RecordType::CNAME | RecordType::NS | RecordType::PTR => RecordData::Name(self.name()?),
+            RecordType::MX => RecordData::MX {
+                preference: self.u16()?,
+                exchange: self.name()?,
+            },
+            RecordType::SOA => RecordData::SOA {
+                mname: self.name()?,
+                rname: self.name()?,
+                serial: self.u32()?,
+            },
+            RecordType::TXT => {
+                let mut parts = Vec::new();
+                while self.pos < end {
+                    let len = self.u8()? as usize;
+                    parts.push(String::from_utf8_lossy(self.bytes(len)?).into_owned());
+                }
+                RecordData::TXT(parts)
+            }
+            _ => RecordData::Raw(self.bytes(rdlength)?.to_vec()),
+        };
+
+        // Skip any remaining rdata (e.g. the rest of SOA)
+        if self.pos > end || end > self.buf.len() {
+            return Err(NaliError::parse("Malformed DNS record data"));
+        }
+        self.pos = end;
+
+        Ok(Record {
+            name,
+            rtype,
+            class,
+            ttl,
+            data,
+        })
+    }
+}
+
+/// Decode a response message
+pub fn parse_response(buf: &[u8]) -> Result<Response> {
+    let mut reader = Reader { buf, pos: 0 };
+
+    let id = reader.u16()?;
+    let flags = reader.u16()?;
+    if flags & 0x8000 == 0 {
+        return Err(NaliError::parse("DNS message is not a response"));
+    }
+    let qdcount = reader.u16()?;
+    let ancount = reader.u16()?;
+    let nscount = reader.u16()?;
+    let _arcount = reader.u16()?;
+
+    for _ in 0..qdcount {
+        reader.name()?;
+        reader.bytes(4)?;
+    }
+
+    let truncated = flags & 0x0200 != 0;
+
+    // A truncated message may end mid-section; keep the records decoded
+    // before the cut
+    let mut answers = Vec::new();
+    let mut complete = true;
+    for _ in 0..ancount {
+        match reader.record() {
+            Ok(record) => answers.push(record),
+            Err(_) if truncated => {
+                complete = false;
+                break;
+            }
+            Err(e) => return Err(e),
+        }
+    }
+    // Authority records are only shown; keep those before any damage
+    let mut authority = Vec::new();
+    if complete {
+        for _ in 0..nscount {
+            match reader.record() {
+                Ok(record) => authority.push(record),
+                Err(_) => break,
+            }
+        }
+    }
+
+    Ok(Response {
+        id,
+        rcode: (flags & 0x000F) as u8,
+        truncated,
+        answers,
+        authority,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_build_query() {
+        let query = build_query(0xBEEF, "example.com.", RecordType::AAAA).unwrap();
+        assert_eq!(&query[..4], &[0xBE, 0xEF, 0x01, 0x00]);
+        assert_eq!(&query[12..], b"\x07example\x03com\x00\x00\x1c\x00\x01");
+
+        let query = build_query(1, "中国.cn", RecordType::A).unwrap();
+        assert_eq!(&query[12..], b"\x0axn--fiqs8s\x02cn\x00\x00\x01\x00\x01");
+    }
+
+    #[test]
+    fn test_parse_response_with_compression() {
+        let mut msg = vec![0xBE, 0xEF, 0x81, 0x80, 0, 1, 0, 2, 0, 0, 0, 0];
+        // Question: www.example.com A IN at offset 12
+        msg.extend_from_slice(b"\x03www\x07example\x03com\x00\x00\x01\x00\x01");
+        // CNAME www.example.com -> cdn.example.com (pointer into the question)
+        msg.extend_from_slice(&[0xC0, 12, 0, 5, 0, 1, 0, 0, 0x0E, 0x10, 0, 6]);
+        msg.extend_from_slice(b"\x03cdn\xC0\x10");
+        // A cdn.example.com -> 1.2.3.4 (pointer to the CNAME target at offset 45)
+        msg.extend_from_slice(&[0xC0, 45, 0, 1, 0, 1, 0, 0, 0, 60, 0, 4, 1, 2, 3, 4]);
+
+        let response = parse_response(&msg).unwrap();
+        assert_eq!(response.id, 0xBEEF);
+        assert_eq!(response.rcode_name(), "NOERROR");
+        assert_eq!(response.answers.len(), 2);
+
+        let cname = &response.answers[0];
+        assert_eq!(cname.name, "www.example.com.");
+        assert_eq!(cname.rtype, RecordType::CNAME);
+        assert_eq!(cname.data, RecordData::Name("cdn.example.com.".to_string()));
+
+        let a = &response.answers[1];
+        assert_eq!(a.name, "cdn.example.com.");
+        assert_eq!(a.ttl, 60);
+        assert_eq!(a.data, RecordData::A(Ipv4Addr::new(1, 2, 3, 4)));
+    }
+
+    #[test]
+    fn test_truncated_response_keeps_decoded_answers() {
+        // TC set, two answers announced, the second cut mid-record
+        let mut msg = vec![0, 1, 0x83, 0x80, 0, 1, 0, 2, 0, 1, 0, 0];
+        msg.extend_from_slice(b"\x07example\x03com\x00\x00\x01\x00\x01");
+        msg.extend_from_slice(&[0xC0, 12, 0, 1, 0, 1, 0, 0, 0, 60, 0, 4, 1, 2, 3, 4]);
+        msg.extend_from_slice(&[0xC0, 12, 0, 1, 0, 1, 0, 0]);
+
+        let response = parse_response(&msg).unwrap();
+        assert!(response.truncated);
+        assert_eq!(response.answers.len(), 1);
+        assert_eq!(response.answers[0].data, RecordData::A(Ipv4Addr::new(1, 2, 3, 4)));
+        assert!(response.authority.is_empty());
+
+        // Without TC the same message is broken
+        msg[2] = 0x81;
+        assert!(parse_response(&msg).is_err());
+    }
+
+    #[test]
+    fn test_compression_loop_rejected() {
+        let mut msg = vec![0, 1, 0x81, 0x80, 0, 1, 0, 0, 0, 0, 0, 0];
+        msg.extend_from_slice(&[0xC0, 12]);
+        assert!(parse_response(&msg).is_err());
+    }
+
+    #[test]
+    fn test_record_type_parse() {
+        assert_eq!("aaaa".parse::<RecordType>().unwrap(), RecordType::AAAA);
+        assert_eq!("TYPE65".parse::<RecordType>().unwrap(), RecordType::Other(65));
+        assert_eq!(RecordType::Other(65).to_string(), "TYPE65");
+        assert!("bogus".parse::<RecordType>().is_err());
+    }
+}
@@ -0,0 +1,15 @@
+//! Minimal DNS client
+//!
+//! Just enough of RFC 1035 to send a single question and decode the answer,
+//! used by `nali-rs dig` to annotate DNS responses directly.
+//!
+//! # Module Organization
+//!
+//! - `message`: Wire format encoding and decoding
+//! - `client`: UDP transport with TCP fallback for truncated answers
+
+pub mod client;
+pub mod message;
+
+pub use client::Resolver;
+pub use message::{Record, RecordData, RecordType, Response};
@@ -0,0 +1,195 @@
+//! Merging CDN YAML files from several sources
+//!
+//! The CDN database is assembled from every configured download URL. When
+//! two sources define the same domain differently, the source with the
+//! higher priority (`database.cdn_source_priority`) wins; equal priorities
+//! keep the historic rule that the source listed later wins.
+
+use crate::error::{NaliError, Result};
+use serde_yaml::{Mapping, Value};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Write;
+
+/// Entries downloaded from one source
+#[derive(Debug)]
+pub struct CdnSource {
+    pub url: String,
+    pub priority: i32,
+    pub entries: HashMap<String, Value>,
+}
+
+/// A domain defined differently by more than one source
+#[derive(Debug, Clone, PartialEq)]
+pub struct Conflict {
+    pub domain: String,
+    /// Index of the source whose definition was kept
+    pub kept: usize,
+    /// Indexes of the sources whose definitions were dropped
+    pub dropped: Vec<usize>,
+}
+
+/// Result of merging all sources
+#[derive(Debug, Default)]
+pub struct MergedCdn {
+    /// Domain -> (entry, index of the source it came from)
+    pub entries: BTreeMap<String, (Value, usize)>,
+    pub conflicts: Vec<Conflict>,
+}
+
+/// Merge sources, recording every domain they disagree on
+///
+/// Sources that repeat an identical definition are not conflicts.
+pub fn merge(sources: &[CdnSource]) -> MergedCdn {
+    let mut merged = MergedCdn::default();
+    let mut conflicts: BTreeMap<String, Conflict> = BTreeMap::new();
+
+    for (idx, source) in sources.iter().enumerate() {
+        for (domain, value) in &source.entries {
+            let Some((current, owner)) = merged.entries.get_mut(domain) else {
+                merged.entries.insert(domain.clone(), (value.clone(), idx));
+                continue;
+            };
+            if current == value {
+                continue;
+            }
+
+            let conflict = conflicts.entry(domain.clone()).or_insert_with(|| Conflict {
+                domain: domain.clone(),
+                kept: *owner,
+                dropped: Vec::new(),
+            });
+            if source.priority >= sources[*owner].priority {
+                conflict.dropped.push(*owner);
+                conflict.kept = idx;
+                *current = value.clone();
+                *owner = idx;
+            } else {
+                conflict.dropped.push(idx);
+            }
+        }
+    }
+
+    merged.conflicts = conflicts.into_values().collect();
+    merged
+}
+
+/// Provider name of an entry, for reports and comments
+pub fn provider_name(value: &Value) -> &str {
+    value.get("name").and_then(Value::as_str).unwrap_or("?")
+}
+
+/// Serialize the merged entries as CDN YAML
+///
+/// With `provenance`, a header lists the sources and every entry is preceded
+/// by a comment naming its source and any definitions it overrode.
+pub fn render(merged: &MergedCdn, sources: &[CdnSource], provenance: bool) -> Result<String> {
+    let to_yaml = |mapping: Mapping| {
+        serde_yaml::to_string(&mapping)
+            .map_err(|e| NaliError::YamlError(format!("Failed to serialize CDN data: {}", e)))
+    };
+
+    if !provenance {
+        return to_yaml(
+            merged
+                .entries
+                .iter()
+                .map(|(domain, (value, _))| (Value::from(domain.as_str()), value.clone()))
+                .collect(),
+        );
+    }
+
+    let conflicts: HashMap<&str, &Conflict> = merged
+        .conflicts
+        .iter()
+        .map(|conflict| (conflict.domain.as_str(), conflict))
+        .collect();
+
+    let mut out = String::from("# Merged by nali-rs from:\n");
+    for source in sources {
+        let _ = writeln!(out, "#   {} (priority {})", source.url, source.priority);
+    }
+
+    for (domain, (value, owner)) in &merged.entries {
+        let _ = writeln!(out, "\n# source: {}", sources[*owner].url);
+        if let Some(conflict) = conflicts.get(domain.as_str()) {
+            for &idx in &conflict.dropped {
+                let _ = writeln!(
+                    out,
+                    "# overrides: {} ({})",
+                    sources[idx].url,
+                    provider_name(&sources[idx].entries[domain])
+                );
+            }
+        }
+        let mut mapping = Mapping::new();
+        mapping.insert(Value::from(domain.as_str()), value.clone());
+        out.push_str(&to_yaml(mapping)?);
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn source(url: &str, priority: i32, yaml: &str) -> CdnSource {
+        CdnSource {
+            url: url.to_string(),
+            priority,
+            entries: serde_yaml::from_str(yaml).unwrap(),
+        }
+    }
+
+    #[test]
+    fn test_later_source_wins_ties() {
+        let sources = [
+            source("a", 0, "x.com: {name: Alpha}\nsame.com: {name: Same}"),
+            source("b", 0, "x.com: {name: Beta}\nsame.com: {name: Same}"),
+        ];
+        let merged = merge(&sources);
+
+        assert_eq!(provider_name(&merged.entries["x.com"].0), "Beta");
+        assert_eq!(
+            merged.conflicts,
+            vec![Conflict {
+                domain: "x.com".to_string(),
+                kept: 1,
+                dropped: vec![0],
+            }]
+        );
+    }
+
+    #[test]
+    fn test_priority_overrides_order() {
+        let sources = [
+            source("a", 10, "x.com: {name: Alpha}"),
+            source("b", 0, "x.com: {name: Beta}"),
+            source("c", 0, "x.com: {name: Gamma}"),
+        ];
+        let merged = merge(&sources);
+
+        assert_eq!(merged.entries["x.com"].1, 0);
+        assert_eq!(merged.conflicts[0].dropped, vec![1, 2]);
+    }
+
+    #[test]
+    fn test_render_provenance() {
+        let sources = [
+            source("a", 0, "x.com: {name: Alpha}\ny.com: {name: Why}"),
+            source("b", 0, "x.com: {name: Beta, link: 'https://b'}"),
+        ];
+        let merged = merge(&sources);
+
+        let plain = render(&merged, &sources, false).unwrap();
+        assert!(!plain.contains('#'));
+
+        let annotated = render(&merged, &sources, true).unwrap();
+        assert!(annotated.contains("# source: b\n# overrides: a (Alpha)\nx.com:"));
+        assert!(annotated.contains("# source: a\ny.com:"));
+
+        // Comments don't change what the CDN database reads back
+        let reread: HashMap<String, Value> = serde_yaml::from_str(&annotated).unwrap();
+        let plain: HashMap<String, Value> = serde_yaml::from_str(&plain).unwrap();
+        assert_eq!(reread, plain);
+    }
+}
@@ -0,0 +1,81 @@
+//! Consent for downloading a missing database
+//!
+//! With `download.auto: prompt` nothing is downloaded before the user agrees
+//! on the terminal. The question goes to the controlling terminal rather
+//! than stdin, so it can be answered while stdin carries piped input;
+//! without a terminal the answer is no.
+
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+
+/// Ask whether to download database `name` from `url`
+pub fn ask(name: &str, url: &str) -> bool {
+    match open_terminal() {
+        Some((input, output)) => ask_on(input, output, name, url),
+        None => {
+            tracing::info!("No terminal to ask for consent to download {}", name);
+            false
+        }
+    }
+}
+
+/// Ask on the given streams; an empty answer means yes
+fn ask_on(mut input: impl BufRead, mut output: impl Write, name: &str, url: &str) -> bool {
+    let _ = write!(
+        output,
+        "Database {} is not installed. Download it from {}? [Y/n] ",
+        name, url
+    );
+    let _ = output.flush();
+
+    let mut answer = String::new();
+    match input.read_line(&mut answer) {
+        Ok(0) | Err(_) => false,
+        Ok(_) => matches!(answer.trim().to_ascii_lowercase().as_str(), "" | "y" | "yes"),
+    }
+}
+
+#[cfg(unix)]
+fn open_terminal() -> Option<(BufReader<File>, File)> {
+    let tty = OpenOptions::new().read(true).write(true).open("/dev/tty").ok()?;
+    let output = tty.try_clone().ok()?;
+    Some((BufReader::new(tty), output))
+}
+
+#[cfg(windows)]
+fn open_terminal() -> Option<(BufReader<File>, File)> {
+    let input = OpenOptions::new().read(true).write(true).open("CONIN$").ok()?;
+    let output = OpenOptions::new().write(true).open("CONOUT$").ok()?;
+    Some((BufReader::new(input), output))
+}
+
+#[cfg(not(any(unix, windows)))]
+fn open_terminal() -> Option<(BufReader<File>, File)> {
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn answer(input: &str) -> (bool, String) {
+        let mut output = Vec::new();
+        let yes = ask_on(input.as_bytes(), &mut output, "qqwry", "https://example.com/qqwry.dat");
+        (yes, String::from_utf8(output).unwrap())
+    }
+
+    #[test]
+    fn test_ask() {
+        let (yes, prompt) = answer("\n");
+        assert!(yes);
+        assert!(prompt.contains("qqwry"));
+        assert!(prompt.contains("https://example.com/qqwry.dat"));
+
+        assert!(answer("Y\n").0);
+        assert!(answer(" yes \n").0);
+        assert!(!answer("n\n").0);
+        assert!(!answer("later\n").0);
+        // End of input is no answer
+        assert!(!answer("").0);
+    }
+}
@@ -0,0 +1,252 @@
+//! Version history of installed databases
+//!
+//! Before an update or import replaces a database file, the old file is
+//! kept as `data_dir/history/<name>/<date>/<file>`, where `<date>` is the day
+//! the old file was last modified. Only the newest `database.history_versions`
+//! entries survive, and `restore` puts one of them back in place so a bad
+//! upstream release can be reverted without network access.
+
+use super::install;
+use crate::config::AppConfig;
+use crate::error::{NaliError, Result};
+use crate::utils::time::format_date;
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Directory under the data directory holding all histories
+const HISTORY_DIR: &str = "history";
+
+/// One kept version of a database file
+#[derive(Debug, Clone)]
+pub struct Version {
+    /// Name of the entry, the date the file was last modified
+    pub date: String,
+    pub path: PathBuf,
+    pub size: u64,
+    pub modified: SystemTime,
+}
+
+/// Kept versions of one database
+#[derive(Debug, Clone)]
+pub struct History {
+    dir: PathBuf,
+    keep: usize,
+}
+
+/// Day a file was last modified, as `YYYY-MM-DD`
+fn modified_date(modified: SystemTime) -> String {
+    format_date(
+        modified
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |age| age.as_secs()),
+    )
+}
+
+/// Put `from` at `to` under a second name, keeping its modification time
+fn link_or_copy(from: &Path, to: &Path) -> Result<()> {
+    if fs::hard_link(from, to).is_ok() {
+        return Ok(());
+    }
+    fs::copy(from, to).map_err(NaliError::IoError)?;
+    let modified = fs::metadata(from)
+        .and_then(|meta| meta.modified())
+        .map_err(NaliError::IoError)?;
+    File::options()
+        .write(true)
+        .open(to)
+        .and_then(|file| file.set_modified(modified))
+        .map_err(NaliError::IoError)
+}
+
+impl History {
+    /// History of a database, resolving aliases to the database name
+    pub fn new(config: &AppConfig, name: &str) -> Result<Self> {
+        let name = config
+            .find_database(name)
+            .map_or(name, |db| db.name.as_str());
+        Ok(Self {
+            dir: config.data_dir()?.join(HISTORY_DIR).join(name),
+            keep: config.database.history_versions,
+        })
+    }
+
+    /// Kept versions, oldest first
+    pub fn versions(&self) -> Result<Vec<Version>> {
+        let entries = match fs::read_dir(&self.dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(NaliError::IoError(e)),
+        };
+
+        let mut versions = Vec::new();
+        for entry in entries {
+            let entry = entry.map_err(NaliError::IoError)?;
+            let Ok(mut files) = fs::read_dir(entry.path()) else {
+                continue;
+            };
+            let Some(Ok(file)) = files.next() else {
+                continue;
+            };
+            let meta = file.metadata().map_err(NaliError::IoError)?;
+            versions.push(Version {
+                date: entry.file_name().to_string_lossy().to_string(),
+                path: file.path(),
+                size: meta.len(),
+                modified: meta.modified().unwrap_or(UNIX_EPOCH),
+            });
+        }
+        versions.sort_by(|a, b| (a.modified, &a.date).cmp(&(b.modified, &b.date)));
+        Ok(versions)
+    }
+
+    /// Keep a copy of `file` and drop the oldest versions beyond the limit
+    pub fn save(&self, file: &Path) -> Result<()> {
+        if self.keep == 0 || !file.exists() {
+            return Ok(());
+        }
+
+        let modified = fs::metadata(file)
+            .and_then(|meta| meta.modified())
+            .map_err(NaliError::IoError)?;
+        let date = modified_date(modified);
+        let mut entry = self.dir.join(&date);
+        for n in 2.. {
+            if !entry.exists() {
+                break;
+            }
+            entry = self.dir.join(format!("{}-{}", date, n));
+        }
+        fs::create_dir_all(&entry).map_err(NaliError::IoError)?;
+        let name = file
+            .file_name()
+            .ok_or_else(|| NaliError::config(format!("Not a file: {}", file.display())))?;
+        link_or_copy(file, &entry.join(name))?;
+        tracing::info!("Kept previous version of {:?} in {:?}", file, entry);
+
+        let versions = self.versions()?;
+        for old in versions
+            .iter()
+            .take(versions.len().saturating_sub(self.keep))
+        {
+            if let Some(dir) = old.path.parent()
+                && let Err(e) = fs::remove_dir_all(dir)
+            {
+                tracing::warn!("Failed to remove old version {:?}: {}", dir, e);
+            }
+        }
+        Ok(())
+    }
+
+    /// Put a kept version back in place of `dest`
+    ///
+    /// Without a date, the newest version older than `dest` is restored. The
+    /// replaced file joins the history, so a rollback can itself be undone.
+    pub fn restore(&self, dest: &Path, date: Option<&str>) -> Result<Version> {
+        let versions = self.versions()?;
+        let current = fs::metadata(dest).and_then(|meta| meta.modified()).ok();
+        let chosen = match date {
+            Some(date) => versions.iter().find(|v| v.date == date).ok_or_else(|| {
+                let dates: Vec<&str> = versions.iter().map(|v| v.date.as_str()).collect();
+                NaliError::DatabaseNotFound(format!(
+                    "no version from {} (kept: {})",
+                    date,
+                    if dates.is_empty() {
+                        "none".to_string()
+                    } else {
+                        dates.join(", ")
+                    }
+                ))
+            })?,
+            None => versions
+                .iter()
+                .rev()
+                .find(|v| current.is_none_or(|current| v.modified < current))
+                .ok_or_else(|| {
+                    NaliError::DatabaseNotFound(format!(
+                        "no older version kept in {}",
+                        self.dir.display()
+                    ))
+                })?,
+        };
+
+        // Take the version out of the history first, so saving the current
+        // file can't prune it
+        let staged = install::staging_path(dest);
+        install::discard(&staged);
+        if fs::rename(&chosen.path, &staged).is_err() {
+            link_or_copy(&chosen.path, &staged)?;
+        }
+        if let Some(dir) = chosen.path.parent() {
+            let _ = fs::remove_dir_all(dir);
+        }
+
+        self.save(dest)?;
+        install::install(&staged, dest, None)?;
+        Ok(chosen.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    /// Install `content` as `dest`, dated `days` after the epoch
+    fn install_version(history: &History, dest: &Path, content: &str, days: u64) {
+        let staged = install::staging_path(dest);
+        fs::write(&staged, content).unwrap();
+        File::options()
+            .write(true)
+            .open(&staged)
+            .unwrap()
+            .set_modified(UNIX_EPOCH + Duration::from_secs(days * 86_400))
+            .unwrap();
+        install::install(&staged, dest, Some(history)).unwrap();
+    }
+
+    #[test]
+    fn test_keeps_newest_versions() {
+        let dir = tempfile::tempdir().unwrap();
+        let dest = dir.path().join("qqwry.dat");
+        let history = History {
+            dir: dir.path().join("history/qqwry"),
+            keep: 2,
+        };
+
+        for (n, content) in ["v1", "v2", "v3", "v4"].iter().enumerate() {
+            install_version(&history, &dest, content, 19_000 + n as u64);
+        }
+
+        let versions = history.versions().unwrap();
+        let dates: Vec<&str> = versions.iter().map(|v| v.date.as_str()).collect();
+        assert_eq!(dates, ["2022-01-09", "2022-01-10"]);
+        assert_eq!(fs::read_to_string(&versions[1].path).unwrap(), "v3");
+        assert_eq!(fs::read_to_string(&dest).unwrap(), "v4");
+    }
+
+    #[test]
+    fn test_restore() {
+        let dir = tempfile::tempdir().unwrap();
+        let dest = dir.path().join("cdn.yml");
+        let history = History {
+            dir: dir.path().join("history/cdn"),
+            keep: 3,
+        };
+        for (n, content) in ["v1", "v2", "v3"].iter().enumerate() {
+            install_version(&history, &dest, content, 19_000 + n as u64);
+        }
+
+        // Step back one version at a time
+        assert_eq!(history.restore(&dest, None).unwrap().date, "2022-01-09");
+        assert_eq!(fs::read_to_string(&dest).unwrap(), "v2");
+        history.restore(&dest, None).unwrap();
+        assert_eq!(fs::read_to_string(&dest).unwrap(), "v1");
+        assert!(history.restore(&dest, None).is_err());
+
+        // Jump forward by date
+        history.restore(&dest, Some("2022-01-10")).unwrap();
+        assert_eq!(fs::read_to_string(&dest).unwrap(), "v3");
+        assert!(history.restore(&dest, Some("1999-01-01")).is_err());
+    }
+}
@@ -0,0 +1,149 @@
+//! Hooks run after a database update
+//!
+//! `hooks.post_update` runs once for every database that `update` replaced,
+//! so a cron job can announce new data or reload the services reading it.
+//! Databases found up to date and downloads on first use don't trigger it.
+//!
+//! An `http://` or `https://` hook is sent [`Updated`] as a JSON POST; any
+//! other hook is run by the shell with the details in `NALI_DATABASE`,
+//! `NALI_VERSION` (empty when the file records none) and `NALI_FILE`. A
+//! failing hook is reported but doesn't fail the update.
+
+use crate::error::{NaliError, Result};
+use serde::Serialize;
+use std::path::PathBuf;
+use tokio::process::Command;
+
+/// Details of a replaced database, as passed to hooks
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Updated {
+    /// Event name, always `post_update`
+    pub event: &'static str,
+    /// Name of the database
+    pub database: String,
+    /// Release version or build date recorded in the new file
+    pub version: Option<String>,
+    /// Path of the new file
+    pub file: PathBuf,
+}
+
+impl Updated {
+    /// Details of `database`, now installed at `file`
+    pub fn new(database: &str, version: Option<String>, file: PathBuf) -> Self {
+        Self {
+            event: "post_update",
+            database: database.to_string(),
+            version,
+            file,
+        }
+    }
+}
+
+/// Whether a hook is a URL rather than a command
+fn is_url(hook: &str) -> bool {
+    hook.starts_with("http://") || hook.starts_with("https://")
+}
+
+/// Run `hook` for `updated`, POSTing with `client` when it is a URL
+pub async fn post_update(client: &reqwest::Client, hook: &str, updated: &Updated) -> Result<()> {
+    if is_url(hook) {
+        let response = client
+            .post(hook)
+            .header(reqwest::header::CONTENT_TYPE, "application/json")
+            .body(serde_json::to_vec(updated)?)
+            .send()
+            .await
+            .map_err(|e| NaliError::network(format!("Failed to call hook {}: {}", hook, e)))?;
+        if !response.status().is_success() {
+            return Err(NaliError::network(format!(
+                "Hook {} answered {}",
+                hook,
+                response.status()
+            )));
+        }
+        return Ok(());
+    }
+
+    let mut command = if cfg!(windows) {
+        let mut command = Command::new("cmd");
+        command.arg("/C");
+        command
+    } else {
+        let mut command = Command::new("sh");
+        command.arg("-c");
+        command
+    };
+    let status = command
+        .arg(hook)
+        .env("NALI_DATABASE", &updated.database)
+        .env("NALI_VERSION", updated.version.as_deref().unwrap_or_default())
+        .env("NALI_FILE", &updated.file)
+        .status()
+        .await
+        .map_err(|e| NaliError::Other(format!("Failed to run hook '{}': {}", hook, e)))?;
+    if !status.success() {
+        return Err(NaliError::Other(format!("Hook '{}' exited with {}", hook, status)));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_command_hook() {
+        let dir = tempfile::tempdir().unwrap();
+        let out = dir.path().join("hook.out");
+        let hook = format!(
+            "echo \"$NALI_DATABASE $NALI_VERSION $NALI_FILE\" > {}",
+            out.display()
+        );
+        let updated = Updated::new("qqwry", Some("20250101".to_string()), PathBuf::from("/data/qqwry.dat"));
+
+        let client = reqwest::Client::new();
+        post_update(&client, &hook, &updated).await.unwrap();
+        assert_eq!(
+            std::fs::read_to_string(&out).unwrap(),
+            "qqwry 20250101 /data/qqwry.dat\n"
+        );
+
+        let error = post_update(&client, "exit 3", &updated).await.unwrap_err();
+        assert!(error.to_string().contains("exited with"), "{}", error);
+    }
+
+    #[tokio::test]
+    async fn test_url_hook() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/hook", listener.local_addr().unwrap());
+        let server = tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut request = Vec::new();
+            let mut buf = [0u8; 4096];
+            // Read until the JSON body has arrived
+            while !request.ends_with(b"}") {
+                let n = stream.read(&mut buf).await.unwrap();
+                request.extend_from_slice(&buf[..n]);
+            }
+            stream
+                .write_all(b"HTTP/1.1 204 No Content\r\n\r\n")
+                .await
+                .unwrap();
+            String::from_utf8(request).unwrap()
+        });
+
+        let updated = Updated::new("cdn", None, PathBuf::from("cdn.yml"));
+        post_update(&reqwest::Client::new(), &url, &updated).await.unwrap();
+
+        let request = server.await.unwrap();
+        assert!(request.starts_with("POST /hook "), "{}", request);
+        assert!(
+            request.ends_with(r#"{"event":"post_update","database":"cdn","version":null,"file":"cdn.yml"}"#),
+            "{}",
+            request
+        );
+    }
+}
@@ -0,0 +1,91 @@
+//! Atomic installation of database files
+//!
+//! New files are written to `<file>.part` next to the destination, flushed
+//! to disk and renamed over it, so a failed or interrupted download never
+//! leaves a truncated database behind. The replaced version goes to the
+//! database's [`History`].
+
+use super::history::History;
+use crate::error::{NaliError, Result};
+use std::ffi::OsString;
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+
+/// Append a suffix to the full file name (`qqwry.dat` -> `qqwry.dat.bak`)
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = OsString::from(path.as_os_str());
+    name.push(suffix);
+    PathBuf::from(name)
+}
+
+/// Where a new version of `dest` is written before it replaces it
+pub fn staging_path(dest: &Path) -> PathBuf {
+    with_suffix(dest, ".part")
+}
+
+/// Flush a directory entry change to disk
+///
+/// Only possible on Unix; elsewhere the rename itself has to do.
+fn sync_dir(path: &Path) {
+    #[cfg(unix)]
+    if let Some(dir) = path.parent()
+        && let Err(e) = File::open(dir).and_then(|dir| dir.sync_all())
+    {
+        tracing::debug!("Failed to sync directory {:?}: {}", dir, e);
+    }
+    #[cfg(not(unix))]
+    let _ = path;
+}
+
+/// Replace `dest` with the fully written file at `staged`
+///
+/// `staged` must be on the same file system as `dest`, normally
+/// `staging_path(dest)`. With a history, the current `dest` is saved to it
+/// first; failing to do so is logged but doesn't stop the install.
+pub fn install(staged: &Path, dest: &Path, history: Option<&History>) -> Result<()> {
+    File::open(staged)
+        .and_then(|file| file.sync_all())
+        .map_err(NaliError::IoError)?;
+
+    if let Some(history) = history
+        && let Err(e) = history.save(dest)
+    {
+        tracing::warn!("Failed to keep previous version of {:?}: {}", dest, e);
+    }
+
+    fs::rename(staged, dest).map_err(NaliError::IoError)?;
+    sync_dir(dest);
+    tracing::info!("Installed {:?}", dest);
+    Ok(())
+}
+
+/// Remove a leftover staging file after a failed install
+pub fn discard(staged: &Path) {
+    if let Err(e) = fs::remove_file(staged)
+        && e.kind() != std::io::ErrorKind::NotFound
+    {
+        tracing::warn!("Failed to remove {:?}: {}", staged, e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_install_replaces_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let dest = dir.path().join("qqwry.dat");
+        fs::write(&dest, "old").unwrap();
+
+        fs::write(staging_path(&dest), "new").unwrap();
+        install(&staging_path(&dest), &dest, None).unwrap();
+        assert_eq!(fs::read_to_string(&dest).unwrap(), "new");
+        assert!(!staging_path(&dest).exists());
+
+        // A failed download leaves nothing behind but the installed file
+        assert!(install(&staging_path(&dest), &dest, None).is_err());
+        discard(&staging_path(&dest));
+        assert_eq!(fs::read_to_string(&dest).unwrap(), "new");
+    }
+}
@@ -1,19 +1,97 @@
 //! Database downloader module
 //!
 //! Handles downloading and updating database files from remote sources.
-
-use crate::config::AppConfig;
+//!
+//! Status messages and progress bars go to stderr so they never mix with
+//! query results on stdout; a quiet downloader prints nothing at all.
+//!
+//! Files are never written in place: see [`install`] for how a download
+//! replaces the current database and [`history`] for the versions kept.
+//! Failed URLs are retried as described in [`retry`], and [`hook`] tells
+//! others about replaced databases.
+
+use crate::config::{AppConfig, DatabaseInfo, DownloadConfig};
+use crate::database::{DatabaseFactory, DatabaseManager};
+use crate::enrichment::cloud;
 use crate::error::{NaliError, Result};
+use history::History;
+use retry::RetryPolicy;
+use state::{DownloadState, Validators};
+use std::collections::BTreeMap;
 use futures_util::StreamExt;
-use indicatif::{ProgressBar, ProgressStyle};
+use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
+use std::fmt::Display;
 use std::fs::File;
+use std::future::Future;
 use std::io::Write;
 use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tracing::Instrument;
 use sevenz_rust::decompress_file;
 
+pub mod cdn;
+pub mod consent;
+pub mod history;
+pub mod hook;
+pub mod install;
+pub mod retry;
+pub mod state;
+
 // Constants
-const DEFAULT_TIMEOUT_SECS: u64 = 300;
-const DOWNLOAD_BUFFER_SIZE: usize = 8192;
+/// CDN conflicts listed in the update output; the rest only go to the log
+const MAX_REPORTED_CONFLICTS: usize = 20;
+/// Databases downloaded at the same time by `download_all`
+const MAX_PARALLEL_DOWNLOADS: usize = 3;
+
+/// Databases that updating all of them only includes once they are installed
+const OPTIONAL_DATABASES: &[&str] = &[crate::enrichment::tor::DATABASE];
+
+/// Whether updating all databases includes `db_info`
+///
+/// Optional databases, the Tor exit list and the cloud ranges, are left out
+/// until they were installed by name, e.g. with `--update tor`; from then on
+/// they are kept fresh like the rest. Databases without a URL, such as local
+/// IP sets or the ASN database, are installed by hand and never downloaded.
+pub fn updated_by_default(config: &AppConfig, db_info: &DatabaseInfo) -> bool {
+    if db_info.download_urls.is_empty() {
+        return false;
+    }
+    let optional = OPTIONAL_DATABASES.contains(&db_info.name.as_str())
+        || cloud::FORMATS.contains(&db_info.format.as_str());
+    !optional
+        || config
+            .get_database_path(&db_info.name)
+            .is_ok_and(|path| path.exists())
+}
+
+/// Database names to update for `names`, with `clouds` standing for every
+/// cloud range database that can be downloaded
+pub fn expand_names(config: &AppConfig, names: &[String]) -> Vec<String> {
+    let mut expanded = Vec::new();
+    for name in names {
+        if name != cloud::GROUP {
+            expanded.push(name.clone());
+            continue;
+        }
+        expanded.extend(
+            config
+                .database
+                .databases
+                .iter()
+                .filter(|db| cloud::FORMATS.contains(&db.format.as_str()) && !db.download_urls.is_empty())
+                .map(|db| db.name.clone()),
+        );
+    }
+    expanded
+}
+
+/// Result of a (possibly conditional) download
+enum Fetched {
+    /// The server reported the file unchanged; nothing was written
+    NotModified,
+    /// The file was downloaded, with the validators the server sent
+    Downloaded(Option<Validators>),
+}
 
 /// Database downloader
 ///
@@ -21,22 +99,95 @@ const DOWNLOAD_BUFFER_SIZE: usize = 8192;
 /// automatic retries, and support for compressed archives (7z).
 pub struct Downloader {
     client: reqwest::Client,
+    retry: RetryPolicy,
+    quiet: bool,
+    /// `hooks.post_update`, run after a database was replaced
+    post_update: Option<String>,
+    /// Shared display when several downloads run at once
+    progress: Option<MultiProgress>,
 }
 
 impl Downloader {
-    /// Create a new downloader
+    /// Create a new downloader with the default retries and timeouts
     pub fn new() -> Result<Self> {
+        Self::with_config(&DownloadConfig::default())
+    }
+
+    /// Create a downloader with the retries and timeouts of `config`
+    pub fn with_config(config: &DownloadConfig) -> Result<Self> {
         let client = reqwest::Client::builder()
             .user_agent(format!("nali-rs/{}", env!("CARGO_PKG_VERSION")))
-            .timeout(std::time::Duration::from_secs(DEFAULT_TIMEOUT_SECS))
+            .connect_timeout(Duration::from_secs(config.connect_timeout))
+            .timeout(Duration::from_secs(config.timeout))
             .build()
             .map_err(|e| NaliError::NetworkError(format!("Failed to create HTTP client: {}", e)))?;
 
-        Ok(Self { client })
+        Ok(Self {
+            client,
+            retry: RetryPolicy::from_config(config),
+            quiet: false,
+            post_update: None,
+            progress: None,
+        })
+    }
+
+    /// Suppress status messages and progress bars
+    pub fn quiet(mut self, quiet: bool) -> Self {
+        self.quiet = quiet;
+        self
+    }
+
+    /// Run `hook` after each database this downloader replaces
+    pub fn post_update(mut self, hook: Option<String>) -> Self {
+        self.post_update = hook;
+        self
+    }
+
+    /// Print a status message to stderr unless quiet
+    fn status(&self, message: impl Display) {
+        if self.quiet {
+            return;
+        }
+        // Above the progress bars, so they don't get torn apart
+        match &self.progress {
+            Some(progress) if !progress.is_hidden() => {
+                let _ = progress.println(message.to_string());
+            }
+            _ => eprintln!("{}", message),
+        }
+    }
+
+    /// Run `attempt` for `url`, repeating it after transient failures
+    async fn with_retries<T, F, Fut>(&self, url: &str, mut attempt: F) -> Result<T>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T>>,
+    {
+        let mut retry = 0;
+        loop {
+            match attempt().await {
+                Err(e) if retry < self.retry.retries && retry::is_transient(&e) => {
+                    retry += 1;
+                    let delay = self.retry.delay(retry);
+                    tracing::warn!("Download of {} failed: {}", url, e);
+                    self.status(crate::tr!(
+                        "      Retrying in {} ({}/{})...",
+                        format!("{:.1}s", delay.as_secs_f64()),
+                        retry,
+                        self.retry.retries
+                    ));
+                    tokio::time::sleep(delay).await;
+                }
+                result => return result,
+            }
+        }
     }
 
     /// Download a file from URL to destination path
     ///
+    /// The file is written next to `dest` and renamed over it only once it
+    /// is complete, so `dest` is left untouched if the download fails.
+    ///
     /// # Arguments
     ///
     /// * `url` - The URL to download from
@@ -48,43 +199,103 @@ impl Downloader {
     /// * `Ok(())` - Download completed successfully
     /// * `Err(NaliError)` - Download failed
     pub async fn download_file(&self, url: &str, dest: &Path, show_progress: bool) -> Result<()> {
-        log::info!("Downloading from: {}", url);
-        log::info!("Saving to: {:?}", dest);
+        self.fetch_and_install(url, dest, show_progress, None, None).await.map(|_| ())
+    }
+
+    /// Size of the file at `url` as announced by the server, without
+    /// downloading it; `None` if the server doesn't say or can't be reached
+    pub async fn remote_size(&self, url: &str) -> Option<u64> {
+        let response = self.client.head(url).send().await.ok()?;
+        if !response.status().is_success() {
+            return None;
+        }
+        // Not `content_length()`, which is the (empty) body of the HEAD response
+        response
+            .headers()
+            .get(reqwest::header::CONTENT_LENGTH)?
+            .to_str()
+            .ok()?
+            .parse()
+            .ok()
+    }
 
+    /// Download to the staging file of `dest`, then install it
+    ///
+    /// With validators, an unchanged file is not downloaded and `dest` is left
+    /// as it is.
+    async fn fetch_and_install(
+        &self,
+        url: &str,
+        dest: &Path,
+        show_progress: bool,
+        history: Option<&History>,
+        validators: Option<&Validators>,
+    ) -> Result<Fetched> {
         // Create parent directory if it doesn't exist
         if let Some(parent) = dest.parent() {
             std::fs::create_dir_all(parent)
                 .map_err(NaliError::IoError)?;
         }
 
-        // Start download
-        let response = self.client.get(url)
+        let staged = install::staging_path(dest);
+        let result = match self.fetch(url, &staged, show_progress, validators).instrument(tracing::info_span!("download", url)).await {
+            Ok(Fetched::Downloaded(validators)) => install::install(&staged, dest, history)
+                .map(|()| Fetched::Downloaded(validators)),
+            other => other,
+        };
+        if result.is_err() {
+            install::discard(&staged);
+        }
+        result
+    }
+
+    /// Send a GET request, conditional when validators are given
+    async fn get(&self, url: &str, validators: Option<&Validators>) -> Result<reqwest::Response> {
+        let mut request = self.client.get(url);
+        if let Some(validators) = validators {
+            request = validators.apply(request);
+        }
+        request
             .send()
             .await
-            .map_err(|e| NaliError::NetworkError(format!("Failed to send request: {}", e)))?;
+            .map_err(|e| NaliError::NetworkError(format!("Failed to send request: {}", e)))
+    }
+
+    /// Stream a URL into a file, creating or truncating it
+    async fn fetch(&self, url: &str, dest: &Path, show_progress: bool, validators: Option<&Validators>) -> Result<Fetched> {
+        tracing::info!("Saving to: {:?}", dest);
+
+        // Start download
+        let response = self.get(url, validators).await?;
+
+        if response.status() == reqwest::StatusCode::NOT_MODIFIED {
+            tracing::info!("Not modified: {}", url);
+            return Ok(Fetched::NotModified);
+        }
 
         if !response.status().is_success() {
-            return Err(NaliError::DownloadError(format!(
-                "HTTP error: {} - {}",
-                response.status(),
-                url
-            )));
+            return Err(retry::status_error(response.status(), url));
         }
 
+        let validators = Validators::from_headers(response.headers());
+
         // Get content length for progress bar
         let total_size = response.content_length();
 
         // Setup progress bar
-        let pb = if show_progress && total_size.is_some() {
-            let pb = ProgressBar::new(total_size.unwrap());
+        let pb = if let (true, false, Some(total_size)) = (show_progress, self.quiet, total_size) {
+            let pb = ProgressBar::new(total_size);
             pb.set_style(
                 ProgressStyle::default_bar()
                     .template("{msg}\n{spinner:.green} [{elapsed_precise}] [{wide_bar:.cyan/blue}] {bytes}/{total_bytes} ({eta})")
                     .unwrap()
                     .progress_chars("#>-"),
             );
-            pb.set_message(format!("Downloading {}", url.split('/').next_back().unwrap_or("database")));
-            Some(pb)
+            pb.set_message(crate::tr!("Downloading {}", url.split('/').next_back().unwrap_or("database")));
+            Some(match &self.progress {
+                Some(progress) => progress.add(pb),
+                None => pb,
+            })
         } else {
             None
         };
@@ -110,18 +321,68 @@ impl Downloader {
         }
 
         if let Some(pb) = pb {
-            pb.finish_with_message(format!("Downloaded {}", dest.file_name().unwrap().to_string_lossy()));
+            pb.finish_with_message(crate::tr!("Downloaded {}", dest.file_name().unwrap().to_string_lossy()));
         }
 
-        log::info!("Successfully downloaded to: {:?}", dest);
-        Ok(())
+        tracing::info!("Successfully downloaded {} bytes to: {:?}", downloaded, dest);
+        Ok(Fetched::Downloaded(validators))
+    }
+
+    /// Store the validators of a freshly installed database
+    ///
+    /// The state is reloaded right before writing, so concurrent downloads
+    /// in `download_all` don't drop each other's entries. Failing to save
+    /// only costs a full download next time.
+    fn remember(&self, config: &AppConfig, db_name: &str, dest: &Path, sources: BTreeMap<String, Validators>) {
+        let saved = DownloadState::load(config).and_then(|mut state| {
+            state.record(db_name, dest, sources);
+            state.save()
+        });
+        if let Err(e) = saved {
+            tracing::warn!("Failed to save download state: {}", e);
+        }
     }
 
     /// Download database by name
-    pub async fn download_database(&self, config: &AppConfig, db_name: &str) -> Result<()> {
+    ///
+    /// Returns whether a new file was installed; `false` means the server
+    /// reported the installed file as current. A new file runs the
+    /// `post_update` hook, if any.
+    pub async fn download_database(&self, config: &AppConfig, db_name: &str) -> Result<bool> {
+        let installed = self.fetch_database(config, db_name).await?;
+        if let (true, Some(hook)) = (installed, &self.post_update) {
+            self.run_post_update(config, db_name, hook).await;
+        }
+        Ok(installed)
+    }
+
+    /// Run the `post_update` hook for a replaced database, reporting failures
+    async fn run_post_update(&self, config: &AppConfig, db_name: &str, hook: &str) {
+        let updated = match config.get_database_path(db_name) {
+            Ok(file) => {
+                let version = DatabaseManager::new(config.clone())
+                    .get_database_type(db_name)
+                    .and_then(|db_type| DatabaseFactory::file_version(db_type, &file))
+                    .unwrap_or_default();
+                hook::Updated::new(db_name, version, file)
+            }
+            Err(e) => {
+                tracing::warn!("Not running post_update hook for {}: {}", db_name, e);
+                return;
+            }
+        };
+
+        tracing::info!("Running post_update hook for {}", db_name);
+        if let Err(e) = hook::post_update(&self.client, hook, &updated).await {
+            self.status(crate::tr!("✗ post_update hook failed for {}: {}", db_name, e));
+            tracing::warn!("post_update hook failed for {}: {}", db_name, e);
+        }
+    }
+
+    /// Download database by name, without running hooks
+    async fn fetch_database(&self, config: &AppConfig, db_name: &str) -> Result<bool> {
         // Find database info
-        let db_info = config.database.databases.iter()
-            .find(|db| db.name == db_name || db.name_alias.contains(&db_name.to_string()))
+        let db_info = config.find_database(db_name)
             .ok_or_else(|| NaliError::DatabaseNotFound(format!("Database not found: {}", db_name)))?;
 
         if db_info.download_urls.is_empty() {
@@ -133,94 +394,159 @@ impl Downloader {
 
         // Get destination path
         let dest_path = config.get_database_path(&db_info.name)?;
+        let history = History::new(config, &db_info.name)?;
+        let state = DownloadState::load(config)?;
 
         // Special handling for CDN database - download and merge from multiple sources
         if db_name == "cdn" {
-            return self.download_and_merge_cdn(db_info, &dest_path).await;
+            return self.download_and_merge_cdn(config, db_info, &dest_path, &history, &state).await;
         }
 
         // Try each download URL until one succeeds
-        let mut last_error = None;
+        let mut failures = Vec::new();
         for url in &db_info.download_urls {
-            match self.try_download_and_extract(url, &dest_path, db_name).await {
-                Ok(_) => {
-                    println!("✓ Successfully downloaded {} database", db_info.name);
-                    return Ok(());
+            let validators = state.validators(&db_info.name, url, &dest_path);
+            let result = self
+                .with_retries(url, || self.try_download_and_extract(url, &dest_path, db_name, &history, validators))
+                .await;
+            match result {
+                Ok(Fetched::NotModified) => {
+                    self.status(crate::tr!("✓ {} database is already up to date", db_info.name));
+                    return Ok(false);
+                }
+                Ok(Fetched::Downloaded(validators)) => {
+                    let sources = validators
+                        .map(|validators| BTreeMap::from([(url.clone(), validators)]))
+                        .unwrap_or_default();
+                    self.remember(config, &db_info.name, &dest_path, sources);
+                    self.status(crate::tr!("✓ Successfully downloaded {} database", db_info.name));
+                    return Ok(true);
                 }
                 Err(e) => {
-                    log::warn!("Failed to download from {}: {}", url, e);
-                    last_error = Some(e);
+                    tracing::warn!("Failed to download from {}: {}", url, e);
+                    failures.push((url.clone(), e));
                 }
             }
         }
 
         // All URLs failed
-        Err(last_error.unwrap_or_else(|| {
-            NaliError::DownloadError(format!("Failed to download database: {}", db_name))
-        }))
+        Err(retry::aggregate(failures))
     }
 
     /// Try to download and extract a database file from a URL
-    async fn try_download_and_extract(&self, url: &str, dest_path: &Path, db_name: &str) -> Result<()> {
-        // Check if URL is for a 7z file
-        let is_7z = url.ends_with(".7z");
-
-        // Download to temp file if 7z, otherwise direct to destination
-        let download_path = if is_7z {
-            let temp_dir = std::env::temp_dir();
-            temp_dir.join(format!("{}.7z", db_name))
-        } else {
-            dest_path.to_path_buf()
-        };
+    async fn try_download_and_extract(&self, url: &str, dest_path: &Path, db_name: &str, history: &History, validators: Option<&Validators>) -> Result<Fetched> {
+        // Plain files are downloaded next to the destination and swapped in
+        if !url.ends_with(".7z") {
+            return self.fetch_and_install(url, dest_path, true, Some(history), validators).await;
+        }
 
-        // Download the file
-        self.download_file(url, &download_path, true).await?;
+        // Archives go to a temp file first
+        let download_path = std::env::temp_dir().join(format!("{}.7z", db_name));
+        let Fetched::Downloaded(validators) = self
+            .fetch_and_install(url, &download_path, true, None, validators)
+            .await?
+        else {
+            return Ok(Fetched::NotModified);
+        };
 
-        // Extract if 7z
-        if is_7z {
-            println!("Extracting 7z archive...");
-            self.extract_7z(&download_path, dest_path, db_name).await?;
-            // Clean up temp file
-            let _ = std::fs::remove_file(&download_path);
+        self.status(crate::tr!("Extracting 7z archive..."));
+        let staged = install::staging_path(dest_path);
+        let result = match self.extract_7z(&download_path, &staged, db_name).await {
+            Ok(()) => install::install(&staged, dest_path, Some(history))
+                .map(|()| Fetched::Downloaded(validators)),
+            Err(e) => Err(e),
+        };
+        if result.is_err() {
+            install::discard(&staged);
         }
+        // Clean up temp file
+        let _ = std::fs::remove_file(&download_path);
 
-        Ok(())
+        result
     }
 
     /// Download CDN databases from multiple sources and merge them
-    async fn download_and_merge_cdn(&self, db_info: &crate::config::DatabaseInfo, dest_path: &PathBuf) -> Result<()> {
-        println!("Downloading CDN databases from multiple sources...");
+    ///
+    /// Conflicting definitions are resolved by `database.cdn_source_priority`
+    /// and reported; `database.cdn_provenance` keeps the source of each entry
+    /// as a comment in the merged file.
+    ///
+    /// The merged file is only rebuilt when at least one source changed;
+    /// sources that didn't are then downloaded again in full.
+    async fn download_and_merge_cdn(&self, config: &AppConfig, db_info: &crate::config::DatabaseInfo, dest_path: &Path, history: &History, state: &DownloadState) -> Result<bool> {
+        self.status(crate::tr!("Downloading CDN databases from multiple sources..."));
 
-        let mut all_cdn_data: std::collections::HashMap<String, serde_yaml::Value> = std::collections::HashMap::new();
-        let mut success_count = 0;
+        let mut sources = Vec::new();
+        let mut validators = BTreeMap::new();
+        let mut unchanged = Vec::new();
+        let mut failures = Vec::new();
 
         for (idx, url) in db_info.download_urls.iter().enumerate() {
-            println!("  [{}/{}] Downloading from {}...", idx + 1, db_info.download_urls.len(), url);
-
-            match self.download_cdn_from_url(url).await {
-                Ok(cdn_data) => {
-                    println!("      ✓ Downloaded {} entries", cdn_data.len());
-                    // Merge data - later sources override earlier ones
-                    for (key, value) in cdn_data {
-                        all_cdn_data.insert(key, value);
-                    }
-                    success_count += 1;
+            self.status(crate::tr!("  [{}/{}] Downloading from {}...", idx + 1, db_info.download_urls.len(), url));
+
+            let source_validators = state.validators(&db_info.name, url, dest_path);
+            let result = self
+                .with_retries(url, || {
+                    self.download_cdn_from_url(url, source_validators)
+                        .instrument(tracing::info_span!("download", url))
+                })
+                .await;
+            match result {
+                Ok(None) => {
+                    self.status(crate::tr!("      ✓ Not modified"));
+                    unchanged.push(url);
+                }
+                Ok(Some((cdn_data, source_validators))) => {
+                    self.status(crate::tr!("      ✓ Downloaded {} entries", cdn_data.len()));
+                    sources.push(cdn::CdnSource {
+                        url: url.clone(),
+                        priority: config.database.cdn_source_priority.get(url).copied().unwrap_or(0),
+                        entries: cdn_data,
+                    });
+                    validators.extend(source_validators.map(|v| (url.clone(), v)));
                 }
                 Err(e) => {
-                    println!("      ✗ Failed: {}", e);
-                    log::warn!("Failed to download CDN data from {}: {}", url, e);
+                    self.status(crate::tr!("      ✗ Failed: {}", e));
+                    tracing::warn!("Failed to download CDN data from {}: {}", url, e);
+                    failures.push((url.clone(), e));
                 }
             }
         }
 
-        if success_count == 0 {
-            return Err(NaliError::DownloadError(
-                "Failed to download CDN database from all sources".to_string()
-            ));
+        if sources.is_empty() && !unchanged.is_empty() {
+            self.status(crate::tr!("✓ cdn database is already up to date"));
+            return Ok(false);
+        }
+
+        // Something changed, so the unchanged sources are needed in full too
+        for url in unchanged {
+            match self.with_retries(url, || self.download_cdn_from_url(url, None).instrument(tracing::info_span!("download", url))).await {
+                Ok(Some((cdn_data, source_validators))) => {
+                    sources.push(cdn::CdnSource {
+                        url: url.clone(),
+                        priority: config.database.cdn_source_priority.get(url).copied().unwrap_or(0),
+                        entries: cdn_data,
+                    });
+                    validators.extend(source_validators.map(|v| (url.clone(), v)));
+                }
+                Ok(None) => {}
+                Err(e) => {
+                    tracing::warn!("Failed to download CDN data from {}: {}", url, e);
+                    failures.push((url.clone(), e));
+                }
+            }
+        }
+        // Keep the configured order, which breaks priority ties
+        sources.sort_by_key(|source| db_info.download_urls.iter().position(|url| *url == source.url));
+
+        if sources.is_empty() {
+            return Err(retry::aggregate(failures));
         }
 
-        println!("\nMerging CDN data from {} sources...", success_count);
-        println!("Total unique CDN entries: {}", all_cdn_data.len());
+        self.status(format!("\n{}", crate::tr!("Merging CDN data from {} sources...", sources.len())));
+        let merged = cdn::merge(&sources);
+        self.status(crate::tr!("Total unique CDN entries: {}", merged.entries.len()));
+        self.report_cdn_conflicts(&merged, &sources);
 
         // Create parent directory if needed
         if let Some(parent) = dest_path.parent() {
@@ -229,31 +555,76 @@ impl Downloader {
         }
 
         // Write merged data to file
-        let yaml_content = serde_yaml::to_string(&all_cdn_data)
-            .map_err(|e| NaliError::YamlError(format!("Failed to serialize CDN data: {}", e)))?;
+        let yaml_content = cdn::render(&merged, &sources, config.database.cdn_provenance)?;
+
+        let staged = install::staging_path(dest_path);
+        if let Err(e) = std::fs::write(&staged, yaml_content)
+            .map_err(NaliError::IoError)
+            .and_then(|()| install::install(&staged, dest_path, Some(history)))
+        {
+            install::discard(&staged);
+            return Err(e);
+        }
 
-        std::fs::write(dest_path, yaml_content)
-            .map_err(NaliError::IoError)?;
+        self.remember(config, &db_info.name, dest_path, validators);
+        self.status(crate::tr!("✓ Successfully downloaded and merged CDN database"));
+        Ok(true)
+    }
 
-        println!("✓ Successfully downloaded and merged CDN database");
-        Ok(())
+    /// Report domains the CDN sources disagree on
+    fn report_cdn_conflicts(&self, merged: &cdn::MergedCdn, sources: &[cdn::CdnSource]) {
+        if merged.conflicts.is_empty() {
+            return;
+        }
+
+        self.status(crate::tr!("{} conflicting definitions:", merged.conflicts.len()));
+        for (shown, conflict) in merged.conflicts.iter().enumerate() {
+            let kept = &sources[conflict.kept];
+            let line = format!(
+                "  {}: {} from {} (over {})",
+                conflict.domain,
+                cdn::provider_name(&kept.entries[&conflict.domain]),
+                kept.url,
+                conflict
+                    .dropped
+                    .iter()
+                    .map(|&idx| format!(
+                        "{} from {}",
+                        cdn::provider_name(&sources[idx].entries[&conflict.domain]),
+                        sources[idx].url
+                    ))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            );
+            tracing::info!("CDN conflict {}", line.trim_start());
+            if shown < MAX_REPORTED_CONFLICTS {
+                self.status(line);
+            }
+        }
+        if merged.conflicts.len() > MAX_REPORTED_CONFLICTS {
+            self.status(crate::tr!(
+                "  ... and {} more (set database.cdn_provenance to see them in the merged file)",
+                merged.conflicts.len() - MAX_REPORTED_CONFLICTS
+            ));
+        }
     }
 
     /// Download CDN data from a single URL
-    async fn download_cdn_from_url(&self, url: &str) -> Result<std::collections::HashMap<String, serde_yaml::Value>> {
-        let response = self.client.get(url)
-            .send()
-            .await
-            .map_err(|e| NaliError::NetworkError(format!("Failed to send request: {}", e)))?;
+    ///
+    /// Returns `None` when validators were given and the server reported the
+    /// data unchanged.
+    async fn download_cdn_from_url(&self, url: &str, validators: Option<&Validators>) -> Result<Option<(std::collections::HashMap<String, serde_yaml::Value>, Option<Validators>)>> {
+        let response = self.get(url, validators).await?;
+
+        if response.status() == reqwest::StatusCode::NOT_MODIFIED {
+            return Ok(None);
+        }
 
         if !response.status().is_success() {
-            return Err(NaliError::DownloadError(format!(
-                "HTTP error: {} - {}",
-                response.status(),
-                url
-            )));
+            return Err(retry::status_error(response.status(), url));
         }
 
+        let validators = Validators::from_headers(response.headers());
         let content = response.text()
             .await
             .map_err(|e| NaliError::NetworkError(format!("Failed to read response: {}", e)))?;
@@ -261,12 +632,12 @@ impl Downloader {
         let cdn_data: std::collections::HashMap<String, serde_yaml::Value> = serde_yaml::from_str(&content)
             .map_err(|e| NaliError::YamlError(format!("Failed to parse CDN YAML: {}", e)))?;
 
-        Ok(cdn_data)
+        Ok(Some((cdn_data, validators)))
     }
 
     /// Extract 7z archive
     async fn extract_7z(&self, archive_path: &Path, dest_path: &Path, db_name: &str) -> Result<()> {
-        log::info!("Extracting 7z archive: {:?}", archive_path);
+        tracing::info!("Extracting 7z archive: {:?}", archive_path);
 
         // Create temp directory for extraction
         let temp_extract_dir = std::env::temp_dir().join(format!("nali-extract-{}", db_name));
@@ -299,37 +670,52 @@ impl Downloader {
         // Clean up temp directory
         let _ = std::fs::remove_dir_all(&temp_extract_dir);
 
-        log::info!("Successfully extracted to: {:?}", dest_path);
+        tracing::info!("Successfully extracted to: {:?}", dest_path);
         Ok(())
     }
 
     /// Download all configured databases
+    ///
+    /// Up to `MAX_PARALLEL_DOWNLOADS` databases are fetched at once, each with
+    /// its own progress bar.
     pub async fn download_all(&self, config: &AppConfig) -> Result<()> {
-        println!("Downloading all databases...\n");
-
-        let mut success_count = 0;
-        let mut fail_count = 0;
-
-        for db_info in &config.database.databases {
-            // Skip CDN database (it's manually created)
-            if db_info.name == "cdn" {
-                continue;
-            }
-
-            println!("Downloading {} database...", db_info.name);
-            match self.download_database(config, &db_info.name).await {
-                Ok(_) => {
-                    success_count += 1;
-                }
-                Err(e) => {
-                    eprintln!("✗ Failed to download {}: {}", db_info.name, e);
-                    fail_count += 1;
+        self.status(format!("{}\n", crate::tr!("Downloading all databases...")));
+
+        let downloader = Self {
+            client: self.client.clone(),
+            retry: self.retry,
+            quiet: self.quiet,
+            post_update: self.post_update.clone(),
+            progress: Some(MultiProgress::new()),
+        };
+        let results: Vec<Result<bool>> = futures_util::stream::iter(
+            config
+                .database
+                .databases
+                .iter()
+                // Skip CDN database (it's manually created)
+                .filter(|db_info| db_info.name != "cdn" && updated_by_default(config, db_info)),
+        )
+        .map(|db_info| {
+            let downloader = &downloader;
+            async move {
+                downloader.status(crate::tr!("Downloading {} database...", db_info.name));
+                let result = downloader.download_database(config, &db_info.name).await;
+                if let Err(e) = &result {
+                    downloader.status(crate::tr!("✗ Failed to download {}: {}", db_info.name, e));
                 }
+                result
             }
-            println!();
-        }
+        })
+        .buffer_unordered(MAX_PARALLEL_DOWNLOADS)
+        .collect()
+        .await;
 
-        println!("Download complete: {} succeeded, {} failed", success_count, fail_count);
+        let fail_count = results.iter().filter(|result| result.is_err()).count();
+        let success_count = results.len() - fail_count;
+        self.status("");
+
+        self.status(crate::tr!("Download complete: {} succeeded, {} failed", success_count, fail_count));
 
         if fail_count > 0 {
             Err(NaliError::DownloadError(format!(
@@ -349,17 +735,18 @@ impl Downloader {
         let is_update = dest_path.exists();
 
         if is_update {
-            println!("Updating {} database...", db_name);
+            self.status(crate::tr!("Updating {} database...", db_name));
         } else {
-            println!("Installing {} database...", db_name);
+            self.status(crate::tr!("Installing {} database...", db_name));
         }
 
-        self.download_database(config, db_name).await?;
+        let installed = self.download_database(config, db_name).await?;
 
-        if is_update {
-            println!("✓ Database updated successfully");
-        } else {
-            println!("✓ Database installed successfully");
+        // An unchanged database was already reported as up to date
+        match (installed, is_update) {
+            (false, _) => {}
+            (true, true) => self.status(crate::tr!("✓ Database updated successfully")),
+            (true, false) => self.status(crate::tr!("✓ Database installed successfully")),
         }
 
         Ok(())
@@ -395,10 +782,169 @@ fn find_file_recursive(dir: &Path, filename: &str) -> Result<PathBuf> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::test_support::custom_info;
 
     #[test]
     fn test_downloader_creation() {
         let downloader = Downloader::new();
         assert!(downloader.is_ok());
     }
+
+    /// Serve "<path>" as the body of GET /<path> with the ETag "<path>",
+    /// honoring If-None-Match; /missing is a 404 and /flaky a 503 the first
+    /// time. Returns the base URL.
+    async fn serve() -> String {
+        use std::sync::Arc;
+        use std::sync::atomic::{AtomicBool, Ordering};
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base = format!("http://{}", listener.local_addr().unwrap());
+        let flaked = Arc::new(AtomicBool::new(false));
+        tokio::spawn(async move {
+            loop {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                let flaked = Arc::clone(&flaked);
+                tokio::spawn(async move {
+                    let mut buf = [0u8; 1024];
+                    let n = stream.read(&mut buf).await.unwrap();
+                    let request = String::from_utf8_lossy(&buf[..n]).to_lowercase();
+                    let path = request.split_whitespace().nth(1).unwrap_or("/");
+                    let body = path.trim_start_matches('/');
+                    let etag = format!("\"{}\"", body);
+                    let response = if body == "missing" {
+                        "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\n\r\n".to_string()
+                    } else if body == "flaky" && !flaked.swap(true, Ordering::SeqCst) {
+                        "HTTP/1.1 503 Service Unavailable\r\nContent-Length: 0\r\n\r\n".to_string()
+                    } else if request.contains(&format!("if-none-match: {}", etag)) {
+                        "HTTP/1.1 304 Not Modified\r\n\r\n".to_string()
+                    } else {
+                        format!(
+                            "HTTP/1.1 200 OK\r\nETag: {}\r\nContent-Length: {}\r\n\r\n{}",
+                            etag,
+                            body.len(),
+                            body
+                        )
+                    };
+                    stream.write_all(response.as_bytes()).await.unwrap();
+                });
+            }
+        });
+        base
+    }
+
+    /// Config with one downloadable database per name, served from `base`
+    fn served_config(dir: &Path, base: &str, names: &[&str]) -> AppConfig {
+        let mut config = AppConfig::default();
+        config.database.data_dir = Some(dir.to_string_lossy().to_string());
+        config.database.databases = names
+            .iter()
+            .map(|name| custom_info(name, &[format!("{}/{}", base, name)]))
+            .collect();
+        config
+    }
+
+    #[tokio::test]
+    async fn test_download_all_concurrently() {
+        let base = serve().await;
+        let dir = tempfile::tempdir().unwrap();
+        let config = served_config(
+            dir.path(),
+            &base,
+            &["one", "two", "three", "four", "missing"],
+        );
+
+        let result = Downloader::new().unwrap().quiet(true).download_all(&config).await;
+        assert!(result.unwrap_err().to_string().contains("1 databases failed"));
+        for name in ["one", "two", "three", "four"] {
+            let file = dir.path().join(format!("{}.nali", name));
+            assert_eq!(std::fs::read_to_string(file).unwrap(), name);
+        }
+        assert!(!dir.path().join("missing.nali").exists());
+        assert!(!dir.path().join("missing.nali.part").exists());
+    }
+
+    #[tokio::test]
+    async fn test_remote_size() {
+        let base = serve().await;
+        let downloader = Downloader::new().unwrap();
+        assert_eq!(downloader.remote_size(&format!("{}/four", base)).await, Some(4));
+        assert_eq!(downloader.remote_size(&format!("{}/missing", base)).await, None);
+    }
+
+    #[tokio::test]
+    async fn test_unchanged_database_is_skipped() {
+        let base = serve().await;
+        let dir = tempfile::tempdir().unwrap();
+        let config = served_config(dir.path(), &base, &["one"]);
+        let file = dir.path().join("one.nali");
+        let downloader = Downloader::new().unwrap().quiet(true);
+
+        assert!(downloader.download_database(&config, "one").await.unwrap());
+        assert!(!downloader.download_database(&config, "one").await.unwrap());
+        assert_eq!(std::fs::read_to_string(&file).unwrap(), "one");
+
+        // A file replaced by other means is downloaded again
+        std::fs::write(&file, "imported").unwrap();
+        std::fs::File::options()
+            .write(true)
+            .open(&file)
+            .unwrap()
+            .set_modified(std::time::UNIX_EPOCH)
+            .unwrap();
+        assert!(downloader.download_database(&config, "one").await.unwrap());
+        assert_eq!(std::fs::read_to_string(&file).unwrap(), "one");
+    }
+
+    #[tokio::test]
+    async fn test_transient_failures_are_retried() {
+        let base = serve().await;
+        let dir = tempfile::tempdir().unwrap();
+        let mut config = served_config(dir.path(), &base, &["flaky"]);
+        config.download.retry_delay_ms = 1;
+
+        config.download.retries = 0;
+        let downloader = Downloader::with_config(&config.download).unwrap().quiet(true);
+        let error = downloader.download_database(&config, "flaky").await.unwrap_err();
+        assert!(error.to_string().contains("503"), "{}", error);
+
+        // The server has recovered by the second attempt
+        let base = serve().await;
+        let mut config = served_config(dir.path(), &base, &["flaky"]);
+        config.download.retry_delay_ms = 1;
+        let downloader = Downloader::with_config(&config.download).unwrap().quiet(true);
+        assert!(downloader.download_database(&config, "flaky").await.unwrap());
+        assert_eq!(std::fs::read_to_string(dir.path().join("flaky.nali")).unwrap(), "flaky");
+    }
+
+    #[tokio::test]
+    async fn test_failed_mirrors_are_listed() {
+        let base = serve().await;
+        let dir = tempfile::tempdir().unwrap();
+        let mut config = served_config(dir.path(), &base, &["missing"]);
+        config.database.databases[0]
+            .download_urls
+            .push(format!("{}/missing", base));
+
+        let downloader = Downloader::with_config(&config.download).unwrap().quiet(true);
+        let error = downloader.download_database(&config, "missing").await.unwrap_err();
+        // A 404 fails at once, without waiting for retries
+        assert!(error.to_string().contains("2 mirrors failed"), "{}", error);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_post_update_hook_runs_on_change() {
+        let base = serve().await;
+        let dir = tempfile::tempdir().unwrap();
+        let config = served_config(dir.path(), &base, &["one"]);
+        let log = dir.path().join("hook.log");
+        let hook = format!("echo \"$NALI_DATABASE\" >> {}", log.display());
+        let downloader = Downloader::new().unwrap().quiet(true).post_update(Some(hook));
+
+        assert!(downloader.download_database(&config, "one").await.unwrap());
+        // Already up to date, so the hook stays quiet
+        assert!(!downloader.download_database(&config, "one").await.unwrap());
+        assert_eq!(std::fs::read_to_string(&log).unwrap(), "one\n");
+    }
 }
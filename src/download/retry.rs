@@ -0,0 +1,132 @@
+//! Retries of failed downloads
+//!
+//! A URL that fails with a network error or an HTTP status the server may
+//! recover from (5xx, 429) is tried again after `download.retry_delay_ms`,
+//! doubling with each attempt up to `download.retries` extra attempts. Each
+//! delay gets up to half again of random jitter, so clients that failed
+//! together don't come back together. Other failures, e.g. a 404, are final.
+
+use crate::config::DownloadConfig;
+use crate::error::NaliError;
+use reqwest::StatusCode;
+use std::collections::hash_map::RandomState;
+use std::hash::BuildHasher;
+use std::time::Duration;
+
+/// Longest delay between two attempts, before jitter
+const MAX_DELAY: Duration = Duration::from_secs(60);
+
+/// How often and how patiently a URL is retried
+#[derive(Debug, Clone, Copy)]
+pub struct RetryPolicy {
+    /// Extra attempts after the first one
+    pub retries: u32,
+    /// Delay before the first retry
+    pub base_delay: Duration,
+}
+
+impl RetryPolicy {
+    /// Policy of the `download` configuration
+    pub fn from_config(config: &DownloadConfig) -> Self {
+        Self {
+            retries: config.retries,
+            base_delay: Duration::from_millis(config.retry_delay_ms),
+        }
+    }
+
+    /// Delay before retry number `retry`, counting from 1, without jitter
+    pub fn backoff(&self, retry: u32) -> Duration {
+        let factor = 1u32.checked_shl(retry.saturating_sub(1)).unwrap_or(u32::MAX);
+        self.base_delay.saturating_mul(factor).min(MAX_DELAY)
+    }
+
+    /// Delay before retry number `retry`, with up to half of it again as jitter
+    pub fn delay(&self, retry: u32) -> Duration {
+        let backoff = self.backoff(retry);
+        backoff + jitter(backoff / 2)
+    }
+}
+
+/// A random duration up to `max`, in thousandths of it
+pub fn jitter(max: Duration) -> Duration {
+    let share = RandomState::new().hash_one(max) % 1000;
+    max * share as u32 / 1000
+}
+
+/// Whether an attempt that failed with `error` is worth repeating
+pub fn is_transient(error: &NaliError) -> bool {
+    matches!(error, NaliError::NetworkError(_))
+}
+
+/// Error for an unsuccessful HTTP status
+///
+/// Statuses the server may recover from are network errors, so they are
+/// retried; the rest fail the URL at once.
+pub fn status_error(status: StatusCode, url: &str) -> NaliError {
+    let message = format!("HTTP error: {} - {}", status, url);
+    if status.is_server_error() || status == StatusCode::TOO_MANY_REQUESTS {
+        NaliError::network(message)
+    } else {
+        NaliError::DownloadError(message)
+    }
+}
+
+/// One error for every URL of a database that failed
+///
+/// A single failure is returned as it is.
+pub fn aggregate(mut failures: Vec<(String, NaliError)>) -> NaliError {
+    match failures.len() {
+        0 => return NaliError::DownloadError("No source could be downloaded".to_string()),
+        1 => return failures.remove(0).1,
+        _ => {}
+    }
+    let details = failures
+        .iter()
+        .map(|(url, error)| format!("{}: {}", url, error))
+        .collect::<Vec<_>>()
+        .join("; ");
+    NaliError::DownloadError(format!("{} mirrors failed: {}", failures.len(), details))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_delay_doubles_with_jitter() {
+        let policy = RetryPolicy {
+            retries: 5,
+            base_delay: Duration::from_millis(100),
+        };
+        assert_eq!(policy.backoff(1), Duration::from_millis(100));
+        assert_eq!(policy.backoff(2), Duration::from_millis(200));
+        assert_eq!(policy.backoff(4), Duration::from_millis(800));
+        assert_eq!(policy.backoff(40), MAX_DELAY);
+        for retry in 1..=5 {
+            let delay = policy.delay(retry);
+            assert!(delay >= policy.backoff(retry));
+            assert!(delay <= policy.backoff(retry) * 3 / 2);
+        }
+    }
+
+    #[test]
+    fn test_status_error() {
+        assert!(is_transient(&status_error(StatusCode::SERVICE_UNAVAILABLE, "u")));
+        assert!(is_transient(&status_error(StatusCode::TOO_MANY_REQUESTS, "u")));
+        assert!(!is_transient(&status_error(StatusCode::NOT_FOUND, "u")));
+    }
+
+    #[test]
+    fn test_aggregate() {
+        let single = aggregate(vec![("a".to_string(), NaliError::network("timeout"))]);
+        assert!(matches!(single, NaliError::NetworkError(_)));
+
+        let all = aggregate(vec![
+            ("a".to_string(), NaliError::network("timeout")),
+            ("b".to_string(), NaliError::DownloadError("HTTP error: 404".to_string())),
+        ]);
+        let message = all.to_string();
+        assert!(message.contains("2 mirrors failed: a: "), "{}", message);
+        assert!(message.contains("; b: "), "{}", message);
+    }
+}
@@ -0,0 +1,192 @@
+//! HTTP validators of downloaded databases
+//!
+//! After a download, the `ETag` and `Last-Modified` headers of every URL a
+//! database came from are stored in `data_dir/download-state.json`, together
+//! with the modification time of the installed file. The next update sends
+//! them back as `If-None-Match`/`If-Modified-Since`, so an unchanged database
+//! costs one small request instead of a full download. Once the installed
+//! file changes by other means (an import, a rollback), its validators no
+//! longer apply and the next update downloads it in full.
+
+use crate::config::AppConfig;
+use crate::error::{NaliError, Result};
+use reqwest::header::{self, HeaderMap};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+/// State file in the data directory
+const STATE_FILE: &str = "download-state.json";
+
+/// Validators a server sent with a response
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Validators {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub etag: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_modified: Option<String>,
+}
+
+impl Validators {
+    /// Read the validators of a response, if it has any
+    pub fn from_headers(headers: &HeaderMap) -> Option<Self> {
+        let get = |name| {
+            headers
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+                .map(str::to_string)
+        };
+        let validators = Self {
+            etag: get(header::ETAG),
+            last_modified: get(header::LAST_MODIFIED),
+        };
+        (validators != Self::default()).then_some(validators)
+    }
+
+    /// Add the matching conditional headers to a request
+    pub fn apply(&self, mut request: reqwest::RequestBuilder) -> reqwest::RequestBuilder {
+        if let Some(etag) = &self.etag {
+            request = request.header(header::IF_NONE_MATCH, etag);
+        }
+        if let Some(last_modified) = &self.last_modified {
+            request = request.header(header::IF_MODIFIED_SINCE, last_modified);
+        }
+        request
+    }
+}
+
+/// What was recorded for one database
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct DatabaseState {
+    /// Modification time of the installed file, in Unix seconds
+    file_modified: u64,
+    /// Validators by download URL
+    sources: BTreeMap<String, Validators>,
+}
+
+/// Validators of all databases
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct DownloadState {
+    #[serde(skip)]
+    path: PathBuf,
+    #[serde(flatten)]
+    databases: BTreeMap<String, DatabaseState>,
+}
+
+/// Modification time of a file in Unix seconds
+fn modified_secs(file: &Path) -> Option<u64> {
+    std::fs::metadata(file)
+        .and_then(|meta| meta.modified())
+        .ok()
+        .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+        .map(|age| age.as_secs())
+}
+
+impl DownloadState {
+    /// Load the state of the configured data directory
+    ///
+    /// A missing or unreadable file just means every database is downloaded
+    /// in full once more.
+    pub fn load(config: &AppConfig) -> Result<Self> {
+        let path = config.data_dir()?.join(STATE_FILE);
+        let mut state: Self = std::fs::read_to_string(&path)
+            .ok()
+            .and_then(|content| match serde_json::from_str(&content) {
+                Ok(state) => Some(state),
+                Err(e) => {
+                    tracing::warn!("Ignoring unreadable {:?}: {}", path, e);
+                    None
+                }
+            })
+            .unwrap_or_default();
+        state.path = path;
+        Ok(state)
+    }
+
+    /// Validators to send for `url`, if `file` is still what they describe
+    pub fn validators(&self, database: &str, url: &str, file: &Path) -> Option<&Validators> {
+        let state = self.databases.get(database)?;
+        if modified_secs(file) != Some(state.file_modified) {
+            return None;
+        }
+        state.sources.get(url)
+    }
+
+    /// Record the validators of a freshly installed file
+    pub fn record(&mut self, database: &str, file: &Path, sources: BTreeMap<String, Validators>) {
+        match modified_secs(file) {
+            Some(file_modified) if !sources.is_empty() => {
+                self.databases.insert(
+                    database.to_string(),
+                    DatabaseState {
+                        file_modified,
+                        sources,
+                    },
+                );
+            }
+            _ => {
+                self.databases.remove(database);
+            }
+        }
+    }
+
+    /// Write the state file
+    pub fn save(&self) -> Result<()> {
+        let content = serde_json::to_string_pretty(self)
+            .map_err(|e| NaliError::Other(format!("Failed to serialize download state: {}", e)))?;
+        std::fs::write(&self.path, content).map_err(NaliError::IoError)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reqwest::header::HeaderValue;
+
+    #[test]
+    fn test_validators_from_headers() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(Validators::from_headers(&headers), None);
+
+        headers.insert(header::ETAG, HeaderValue::from_static("\"abc\""));
+        let validators = Validators::from_headers(&headers).unwrap();
+        assert_eq!(validators.etag.as_deref(), Some("\"abc\""));
+        assert_eq!(validators.last_modified, None);
+    }
+
+    #[test]
+    fn test_validators_follow_the_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut config = AppConfig::default();
+        config.database.data_dir = Some(dir.path().to_string_lossy().to_string());
+        let file = dir.path().join("qqwry.dat");
+        std::fs::write(&file, "v1").unwrap();
+
+        let url = "https://example.com/qqwry.dat";
+        let validators = Validators {
+            etag: Some("\"v1\"".to_string()),
+            last_modified: None,
+        };
+        let mut state = DownloadState::load(&config).unwrap();
+        state.record(
+            "qqwry",
+            &file,
+            BTreeMap::from([(url.to_string(), validators.clone())]),
+        );
+        state.save().unwrap();
+
+        let state = DownloadState::load(&config).unwrap();
+        assert_eq!(state.validators("qqwry", url, &file), Some(&validators));
+        assert_eq!(state.validators("qqwry", "https://other/", &file), None);
+
+        // Replaced by hand: the validators no longer describe the file
+        std::fs::File::options()
+            .write(true)
+            .open(&file)
+            .unwrap()
+            .set_modified(UNIX_EPOCH)
+            .unwrap();
+        assert_eq!(state.validators("qqwry", url, &file), None);
+    }
+}
@@ -0,0 +1,143 @@
+//! On-disk cache of RDAP answers
+//!
+//! RDAP answers describe a whole network, so entries are keyed by address
+//! range and one lookup serves every address in that network until the
+//! entry expires.
+
+use crate::error::Result;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use super::whois::WhoisInfo;
+
+/// Cached RDAP answer for one network
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct CacheEntry {
+    start: IpAddr,
+    end: IpAddr,
+    fetched_at: u64,
+    info: WhoisInfo,
+}
+
+/// RDAP answers persisted as JSON
+#[derive(Debug)]
+pub struct WhoisCache {
+    path: PathBuf,
+    ttl: u64,
+    entries: Vec<CacheEntry>,
+}
+
+/// Seconds since the Unix epoch
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+impl WhoisCache {
+    /// Open the cache at `path`, starting empty if it is missing or unreadable
+    pub fn open(path: &Path, ttl: u64) -> Self {
+        let entries = match fs::read_to_string(path) {
+            Ok(data) => serde_json::from_str(&data).unwrap_or_else(|e| {
+                tracing::warn!("Ignoring corrupt whois cache {:?}: {}", path, e);
+                Vec::new()
+            }),
+            Err(_) => Vec::new(),
+        };
+
+        Self {
+            path: path.to_path_buf(),
+            ttl,
+            entries,
+        }
+    }
+
+    /// Find a fresh answer covering `ip`
+    pub fn get(&self, ip: IpAddr) -> Option<&WhoisInfo> {
+        let now = now();
+        self.entries
+            .iter()
+            .filter(|e| now.saturating_sub(e.fetched_at) < self.ttl)
+            .find(|e| e.start <= ip && ip <= e.end)
+            .map(|e| &e.info)
+    }
+
+    /// Store an answer for a network, replacing expired or identical entries
+    pub fn insert(&mut self, start: IpAddr, end: IpAddr, info: WhoisInfo) {
+        let now = now();
+        let ttl = self.ttl;
+        self.entries.retain(|e| {
+            now.saturating_sub(e.fetched_at) < ttl && !(e.start == start && e.end == end)
+        });
+        self.entries.push(CacheEntry {
+            start,
+            end,
+            fetched_at: now,
+            info,
+        });
+    }
+
+    /// Write the cache to disk
+    pub fn save(&self) -> Result<()> {
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        let data = serde_json::to_string(&self.entries)?;
+        let tmp = self.path.with_extension("json.tmp");
+        fs::write(&tmp, data)?;
+        fs::rename(&tmp, &self.path)?;
+        Ok(())
+    }
+
+    /// Number of cached networks
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Check whether the cache is empty
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn info(netname: &str) -> WhoisInfo {
+        WhoisInfo {
+            netname: Some(netname.to_string()),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_cache_roundtrip_and_ttl() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("whois_cache.json");
+
+        let mut cache = WhoisCache::open(&path, 3600);
+        assert!(cache.is_empty());
+        cache.insert(
+            "8.8.8.0".parse().unwrap(),
+            "8.8.8.255".parse().unwrap(),
+            info("LVLT-GOGL-8-8-8"),
+        );
+        cache.save().unwrap();
+
+        let cache = WhoisCache::open(&path, 3600);
+        assert_eq!(cache.len(), 1);
+        let hit = cache.get("8.8.8.8".parse().unwrap()).unwrap();
+        assert_eq!(hit.netname.as_deref(), Some("LVLT-GOGL-8-8-8"));
+        assert!(cache.get("8.8.4.4".parse().unwrap()).is_none());
+
+        // A zero TTL expires everything
+        let cache = WhoisCache::open(&path, 0);
+        assert!(cache.get("8.8.8.8".parse().unwrap()).is_none());
+    }
+}
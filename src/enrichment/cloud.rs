@@ -0,0 +1,246 @@
+//! Published IP ranges of cloud providers
+//!
+//! The `aws`, `gcp` and `azure` databases are the range files the providers
+//! publish, kept as downloaded. They are read into [`IpSet`]s tagged with the
+//! provider, region and service (`[AWS ap-southeast-1 EC2]`); an IP gets the
+//! tag of the most specific range only, since the broad ranges of a region
+//! are listed again per service.
+//!
+//! `nali-rs --update clouds` downloads all of them that have a URL. Azure
+//! publishes its Service Tags file under a new URL every week, so it has
+//! none: download `ServiceTags_Public_*.json` and save it as the `azure`
+//! file in the data directory. Alibaba Cloud and Tencent Cloud publish no
+//! range files; an `ipset` database can cover them.
+
+use super::IpSet;
+use crate::error::{NaliError, Result};
+use ipnetwork::IpNetwork;
+use serde::Deserialize;
+use serde::de::DeserializeOwned;
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+/// Name that `--update` expands to every cloud database with a URL
+pub const GROUP: &str = "clouds";
+
+/// Database formats of the cloud range files
+pub const FORMATS: &[&str] = &["aws", "gcp", "azure"];
+
+/// `ip-ranges.json` of AWS
+#[derive(Deserialize)]
+struct AwsRanges {
+    prefixes: Vec<AwsPrefix>,
+    #[serde(default)]
+    ipv6_prefixes: Vec<AwsPrefix>,
+}
+
+#[derive(Deserialize)]
+struct AwsPrefix {
+    #[serde(alias = "ipv6_prefix")]
+    ip_prefix: String,
+    region: String,
+    service: String,
+}
+
+/// `cloud.json` of Google Cloud
+#[derive(Deserialize)]
+struct GcpRanges {
+    prefixes: Vec<GcpPrefix>,
+}
+
+#[derive(Deserialize)]
+struct GcpPrefix {
+    #[serde(rename = "ipv4Prefix")]
+    ipv4: Option<String>,
+    #[serde(rename = "ipv6Prefix")]
+    ipv6: Option<String>,
+    scope: String,
+}
+
+/// Service Tags file of Azure
+#[derive(Deserialize)]
+struct AzureTags {
+    values: Vec<AzureTag>,
+}
+
+#[derive(Deserialize)]
+struct AzureTag {
+    name: String,
+    properties: AzureProperties,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct AzureProperties {
+    #[serde(default)]
+    region: String,
+    #[serde(default)]
+    system_service: String,
+    address_prefixes: Vec<String>,
+}
+
+/// Tag of the parts that are not empty, joined by spaces
+fn tag(parts: &[&str]) -> String {
+    parts
+        .iter()
+        .filter(|part| !part.is_empty())
+        .copied()
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Add `prefix` with `tag`, skipping prefixes that don't parse
+fn insert(set: &mut IpSet, prefix: &str, tag: &str) {
+    match prefix.parse::<IpNetwork>() {
+        Ok(network) => set.insert(network, tag),
+        Err(e) => tracing::debug!("Skipping cloud range {}: {}", prefix, e),
+    }
+}
+
+fn parse_aws(ranges: AwsRanges) -> IpSet {
+    let prefixes: Vec<_> = ranges.prefixes.into_iter().chain(ranges.ipv6_prefixes).collect();
+    // Every range is listed under AMAZON, and again under its service if it has one
+    let mut services: HashMap<&str, usize> = HashMap::new();
+    for prefix in &prefixes {
+        *services.entry(&prefix.ip_prefix).or_default() += 1;
+    }
+
+    let mut set = IpSet::default();
+    for prefix in &prefixes {
+        let service = match prefix.service.as_str() {
+            "AMAZON" if services[prefix.ip_prefix.as_str()] > 1 => continue,
+            "AMAZON" => "",
+            service => service,
+        };
+        let region = match prefix.region.as_str() {
+            "GLOBAL" => "",
+            region => region,
+        };
+        insert(&mut set, &prefix.ip_prefix, &tag(&["AWS", region, service]));
+    }
+    set
+}
+
+fn parse_gcp(ranges: GcpRanges) -> IpSet {
+    let mut set = IpSet::default();
+    for prefix in &ranges.prefixes {
+        for network in prefix.ipv4.iter().chain(&prefix.ipv6) {
+            insert(&mut set, network, &tag(&["GCP", &prefix.scope]));
+        }
+    }
+    set
+}
+
+fn parse_azure(tags: AzureTags) -> IpSet {
+    let mut set = IpSet::default();
+    for service_tag in &tags.values {
+        let properties = &service_tag.properties;
+        let tag = match (properties.region.as_str(), properties.system_service.as_str()) {
+            // Tags for all regions, such as AzureCloud or AzureFrontDoor.Frontend
+            ("", "") => tag(&["Azure", &service_tag.name]),
+            (region, service) => tag(&["Azure", region, service]),
+        };
+        for prefix in &properties.address_prefixes {
+            insert(&mut set, prefix, &tag);
+        }
+    }
+    set
+}
+
+/// Deserialize a range file of format `format`
+fn from_json<T: DeserializeOwned>(format: &str, data: &str) -> Result<T> {
+    serde_json::from_str(data)
+        .map_err(|e| NaliError::parse(format!("Invalid {} range file: {}", format, e)))
+}
+
+/// Parse a range file of cloud database format `format`
+pub fn parse(format: &str, data: &str) -> Result<IpSet> {
+    let set = match format {
+        "aws" => parse_aws(from_json(format, data)?),
+        "gcp" => parse_gcp(from_json(format, data)?),
+        "azure" => parse_azure(from_json(format, data)?),
+        _ => return Err(NaliError::parse(format!("Unknown cloud range format: {}", format))),
+    };
+    Ok(set.most_specific())
+}
+
+/// Read the range file at `path`; see [`parse`]
+pub fn load(format: &str, path: &Path) -> Result<IpSet> {
+    parse(format, &fs::read_to_string(path)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::IpAddr;
+
+    fn ip(text: &str) -> IpAddr {
+        text.parse().unwrap()
+    }
+
+    #[test]
+    fn test_aws() {
+        let set = parse(
+            "aws",
+            r#"{
+                "syncToken": "1700000000",
+                "prefixes": [
+                    {"ip_prefix": "13.212.0.0/15", "region": "ap-southeast-1", "service": "AMAZON", "network_border_group": "ap-southeast-1"},
+                    {"ip_prefix": "13.212.0.0/15", "region": "ap-southeast-1", "service": "EC2", "network_border_group": "ap-southeast-1"},
+                    {"ip_prefix": "13.0.0.0/8", "region": "ap-southeast-1", "service": "AMAZON", "network_border_group": "ap-southeast-1"},
+                    {"ip_prefix": "52.94.76.0/22", "region": "GLOBAL", "service": "ROUTE53", "network_border_group": "GLOBAL"}
+                ],
+                "ipv6_prefixes": [
+                    {"ipv6_prefix": "2406:da18::/32", "region": "ap-southeast-1", "service": "EC2", "network_border_group": "ap-southeast-1"}
+                ]
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(set.tags(ip("13.212.1.1")), ["AWS ap-southeast-1 EC2"]);
+        assert_eq!(set.tags(ip("13.1.1.1")), ["AWS ap-southeast-1"]);
+        assert_eq!(set.tags(ip("52.94.76.1")), ["AWS ROUTE53"]);
+        assert_eq!(set.tags(ip("2406:da18::1")), ["AWS ap-southeast-1 EC2"]);
+        assert!(set.tags(ip("8.8.8.8")).is_empty());
+    }
+
+    #[test]
+    fn test_gcp() {
+        let set = parse(
+            "gcp",
+            r#"{
+                "prefixes": [
+                    {"ipv4Prefix": "34.80.0.0/15", "service": "Google Cloud", "scope": "asia-east1"},
+                    {"ipv6Prefix": "2600:1900:4030::/44", "service": "Google Cloud", "scope": "asia-east1"}
+                ]
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(set.tags(ip("34.81.0.1")), ["GCP asia-east1"]);
+        assert_eq!(set.tags(ip("2600:1900:4030::1")), ["GCP asia-east1"]);
+    }
+
+    #[test]
+    fn test_azure() {
+        let set = parse(
+            "azure",
+            r#"{
+                "values": [
+                    {"name": "AzureCloud", "properties": {"region": "", "systemService": "", "addressPrefixes": ["20.0.0.0/8"]}},
+                    {"name": "AzureCloud.eastus", "properties": {"region": "eastus", "systemService": "", "addressPrefixes": ["20.42.0.0/16"]}},
+                    {"name": "Storage.EastUS", "properties": {"region": "eastus", "systemService": "AzureStorage", "addressPrefixes": ["20.42.0.0/17", "not a prefix"]}}
+                ]
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(set.tags(ip("20.42.1.1")), ["Azure eastus AzureStorage"]);
+        assert_eq!(set.tags(ip("20.42.200.1")), ["Azure eastus"]);
+        assert_eq!(set.tags(ip("20.1.1.1")), ["Azure AzureCloud"]);
+    }
+
+    #[test]
+    fn test_invalid_file() {
+        assert!(parse("aws", "<html>").is_err());
+        assert!(parse("oracle", "{}").is_err());
+    }
+}
@@ -0,0 +1,147 @@
+//! Deduplicated lookups for batches of lines
+//!
+//! Log files repeat the same few addresses thousands of times. Instead of
+//! looking up every occurrence, a chunk of lines is parsed first, each
+//! distinct IP or domain is annotated once, and the annotations are then
+//! copied onto every occurrence.
+
+use crate::entity::{Entities, Entity, EntityType};
+use std::collections::HashMap;
+use std::collections::hash_map::Entry;
+use std::net::IpAddr;
+
+/// Identity of an entity for deduplication
+///
+/// IPs compare by address, so `::1` and `0:0::1` share one lookup; domains
+/// compare case-insensitively.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum EntityKey {
+    Ip(IpAddr),
+    Domain(String),
+}
+
+impl EntityKey {
+    /// Key of an IP or domain entity; plain text has none
+    pub fn of(entity: &Entity) -> Option<Self> {
+        match entity.entity_type {
+            EntityType::IPv4 | EntityType::IPv6 => entity.as_ip().map(EntityKey::Ip),
+            EntityType::Domain => Some(EntityKey::Domain(entity.text.to_lowercase())),
+            EntityType::Plain => None,
+        }
+    }
+}
+
+/// Annotations of the distinct entities of a chunk
+#[derive(Debug, Default)]
+pub struct Deduplicator {
+    annotated: HashMap<EntityKey, Entity>,
+}
+
+impl Deduplicator {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// One entity per distinct key not yet annotated, in first-seen order,
+    /// with the number of times it occurs in the chunk
+    ///
+    /// The returned entities are the ones to look up; hand them back with
+    /// [`Deduplicator::insert`] once annotated.
+    pub fn pending<'a>(&self, chunk: impl IntoIterator<Item = &'a Entities>) -> Vec<(Entity, usize)> {
+        let mut seen: HashMap<EntityKey, usize> = HashMap::new();
+        let mut pending: Vec<(Entity, usize)> = Vec::new();
+        for entity in chunk.into_iter().flat_map(|entities| &entities.entities) {
+            let Some(key) = EntityKey::of(entity) else {
+                continue;
+            };
+            if self.annotated.contains_key(&key) {
+                continue;
+            }
+            match seen.entry(key) {
+                Entry::Occupied(slot) => pending[*slot.get()].1 += 1,
+                Entry::Vacant(slot) => {
+                    slot.insert(pending.len());
+                    pending.push((entity.clone(), 1));
+                }
+            }
+        }
+        pending
+    }
+
+    /// Record the annotations of a looked-up entity
+    pub fn insert(&mut self, entity: Entity) {
+        if let Some(key) = EntityKey::of(&entity) {
+            self.annotated.insert(key, entity);
+        }
+    }
+
+    /// Copy the recorded annotations onto every matching entity
+    pub fn apply<'a>(&self, chunk: impl IntoIterator<Item = &'a mut Entities>) {
+        for entity in chunk.into_iter().flat_map(|entities| &mut entities.entities) {
+            let Some(annotated) = EntityKey::of(entity).and_then(|key| self.annotated.get(&key))
+            else {
+                continue;
+            };
+            entity.geo_info.clone_from(&annotated.geo_info);
+            entity.cdn_info.clone_from(&annotated.cdn_info);
+            entity.whois_info.clone_from(&annotated.whois_info);
+            entity.rdns.clone_from(&annotated.rdns);
+            entity.is_tor = annotated.is_tor;
+            entity.tags.clone_from(&annotated.tags);
+            entity.source.clone_from(&annotated.source);
+            entity.meta.clone_from(&annotated.meta);
+        }
+    }
+
+    /// Number of distinct entities annotated
+    pub fn distinct(&self) -> usize {
+        self.annotated.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::CdnProvider;
+    use crate::entity::parser;
+
+    #[test]
+    fn test_lookup_once_per_distinct_entity() {
+        let mut chunk: Vec<Entities> = [
+            "1.2.3.4 - GET /",
+            "1.2.3.4 - GET /favicon.ico",
+            "Example.COM via 1.2.3.4 and 2001:db8::1",
+            "example.com 2001:db8:0::1",
+        ]
+        .iter()
+        .map(|line| parser::parse_line(line))
+        .collect();
+
+        let mut dedupe = Deduplicator::new();
+        let pending = dedupe.pending(&chunk);
+        let texts: Vec<(&str, usize)> = pending.iter().map(|(e, n)| (e.text.as_str(), *n)).collect();
+        assert_eq!(texts, [("1.2.3.4", 3), ("Example.COM", 2), ("2001:db8::1", 2)]);
+
+        for (mut entity, _) in pending {
+            entity.source = Some(format!("looked up {}", entity.text));
+            if entity.is_domain() {
+                entity.cdn_info = Some(CdnProvider {
+                    domain: entity.text.clone(),
+                    provider: "Example CDN".to_string(),
+                    description: None,
+                });
+            }
+            dedupe.insert(entity);
+        }
+        assert_eq!(dedupe.distinct(), 3);
+        assert!(dedupe.pending(&chunk).is_empty());
+
+        dedupe.apply(&mut chunk);
+        assert_eq!(chunk[1].entities[0].source.as_deref(), Some("looked up 1.2.3.4"));
+        let last = &chunk[3].entities;
+        assert_eq!(last[0].cdn_info.as_ref().unwrap().provider, "Example CDN");
+        assert_eq!(last[1].source.as_deref(), Some("looked up 2001:db8::1"));
+        // The occurrence keeps its own text and position
+        assert_eq!(last[1].text, "2001:db8:0::1");
+    }
+}
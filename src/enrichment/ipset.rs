@@ -0,0 +1,195 @@
+//! Tagged IP sets
+//!
+//! A database with `format: ipset` is a list of networks, such as a
+//! blocklist or the published ranges of a cloud provider. IPs inside one of
+//! them are tagged in annotations (`[Spamhaus DROP]`) and get a `tags` array
+//! in JSON. Each line holds a network in CIDR notation or a single address,
+//! optionally followed by a tag of its own (`3.5.140.0/22 AWS ap-northeast-2`);
+//! other lines get the `tag` of the database, or its name. Text after `;` or
+//! `#` is a comment, so lists like Spamhaus DROP work as downloaded.
+//!
+//! Sets with `download_urls` are downloaded and kept fresh by `--update`
+//! like any other database; the others are read from their `file`.
+
+use crate::error::Result;
+use ipnetwork::IpNetwork;
+use std::collections::{BTreeSet, HashMap};
+use std::fs;
+use std::net::IpAddr;
+use std::path::Path;
+
+/// Database format of tagged IP sets
+pub const FORMAT: &str = "ipset";
+
+/// Whether databases of `format` are read as tagged sets: IP sets and the
+/// range files of cloud providers
+pub fn is_tag_format(format: &str) -> bool {
+    format == FORMAT || super::cloud::FORMATS.contains(&format)
+}
+
+/// Networks of a list with the tags of each
+#[derive(Debug, Default)]
+pub struct IpSet {
+    /// Networks by address family (IPv6 or not) and prefix length, keyed by
+    /// their network bits, with the indices of their tags
+    networks: HashMap<(bool, u8), HashMap<u128, Vec<usize>>>,
+    /// Prefix lengths present, so lookups only try those
+    prefixes: BTreeSet<(bool, u8)>,
+    tags: Vec<String>,
+    /// Index of each tag in `tags`
+    tag_ids: HashMap<String, usize>,
+    /// Only report the tags of the longest matching network
+    most_specific: bool,
+}
+
+/// Address family and bits of `ip`, the network bits only up to `prefix`
+fn network_bits(ip: IpAddr, prefix: u8) -> (bool, u128) {
+    match ip {
+        IpAddr::V4(ip) => {
+            let bits = u32::from(ip).checked_shr(32 - u32::from(prefix)).unwrap_or(0);
+            (false, u128::from(bits))
+        }
+        IpAddr::V6(ip) => {
+            let bits = u128::from(ip).checked_shr(128 - u32::from(prefix)).unwrap_or(0);
+            (true, bits)
+        }
+    }
+}
+
+impl IpSet {
+    /// Parse a list, tagging lines without a tag of their own with `tag`
+    ///
+    /// Lines that hold no network are skipped.
+    pub fn parse(text: &str, tag: &str) -> Self {
+        let mut set = Self::default();
+        for line in text.lines() {
+            let line = line.split([';', '#']).next().unwrap_or_default().trim();
+            let (network, line_tag) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+            let Ok(network) = network.parse::<IpNetwork>() else {
+                continue;
+            };
+
+            let line_tag = match line_tag.trim() {
+                "" => tag,
+                line_tag => line_tag,
+            };
+            set.insert(network, line_tag);
+        }
+        set
+    }
+
+    /// Only report the tags of the longest network containing an IP, for
+    /// lists whose broad networks are covered again by more specific ones
+    pub fn most_specific(mut self) -> Self {
+        self.most_specific = true;
+        self
+    }
+
+    /// Add `network` with `tag`
+    pub fn insert(&mut self, network: IpNetwork, tag: &str) {
+        let id = match self.tag_ids.get(tag) {
+            Some(&id) => id,
+            None => {
+                self.tags.push(tag.to_string());
+                self.tag_ids.insert(tag.to_string(), self.tags.len() - 1);
+                self.tags.len() - 1
+            }
+        };
+
+        let (v6, bits) = network_bits(network.network(), network.prefix());
+        self.prefixes.insert((v6, network.prefix()));
+        let ids = self
+            .networks
+            .entry((v6, network.prefix()))
+            .or_default()
+            .entry(bits)
+            .or_default();
+        if !ids.contains(&id) {
+            ids.push(id);
+        }
+    }
+
+    /// Read the list at `path`; see [`IpSet::parse`]
+    pub fn load(path: &Path, tag: &str) -> Result<Self> {
+        Ok(Self::parse(&fs::read_to_string(path)?, tag))
+    }
+
+    /// Tags of every network containing `ip`, in the order they first appear
+    /// in the list; see [`IpSet::most_specific`]
+    pub fn tags(&self, ip: IpAddr) -> Vec<&str> {
+        let v6 = ip.is_ipv6();
+        // Longest prefix first
+        let mut matches = self
+            .prefixes
+            .iter()
+            .rev()
+            .filter(|(family, _)| *family == v6)
+            .filter_map(|&(family, prefix)| {
+                let (_, bits) = network_bits(ip, prefix);
+                self.networks.get(&(family, prefix))?.get(&bits)
+            });
+        let ids: BTreeSet<usize> = if self.most_specific {
+            matches.next().into_iter().flatten().copied().collect()
+        } else {
+            matches.flatten().copied().collect()
+        };
+        ids.into_iter().map(|id| self.tags[id].as_str()).collect()
+    }
+
+    /// Number of networks
+    pub fn len(&self) -> usize {
+        self.networks.values().map(HashMap::len).sum()
+    }
+
+    /// Check whether the set holds no network
+    pub fn is_empty(&self) -> bool {
+        self.networks.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ip(text: &str) -> IpAddr {
+        text.parse().unwrap()
+    }
+
+    #[test]
+    fn test_default_tag() {
+        let set = IpSet::parse(
+            "; Spamhaus DROP List\n1.10.16.0/20 ; SBL256894\n192.0.2.7\n2001:db8::/32 ; SBL1\n",
+            "Spamhaus DROP",
+        );
+        assert_eq!(set.len(), 3);
+        assert_eq!(set.tags(ip("1.10.31.255")), ["Spamhaus DROP"]);
+        assert_eq!(set.tags(ip("192.0.2.7")), ["Spamhaus DROP"]);
+        assert_eq!(set.tags(ip("2001:db8::1")), ["Spamhaus DROP"]);
+        assert!(set.tags(ip("1.10.32.0")).is_empty());
+        assert!(set.tags(ip("192.0.2.8")).is_empty());
+    }
+
+    #[test]
+    fn test_tags_per_line() {
+        let set = IpSet::parse(
+            "3.5.140.0/22 AWS ap-northeast-2\n\
+             3.5.140.0/22 AWS ap-northeast-2\n\
+             3.0.0.0/8 AWS\n\
+             0.0.0.0/0\n\
+             not a network\n",
+            "anywhere",
+        );
+        assert_eq!(set.len(), 3);
+        assert_eq!(
+            set.tags(ip("3.5.141.1")),
+            ["AWS ap-northeast-2", "AWS", "anywhere"]
+        );
+        assert_eq!(set.tags(ip("8.8.8.8")), ["anywhere"]);
+        // IPv4 networks never match IPv6 addresses
+        assert!(set.tags(ip("::1")).is_empty());
+
+        let set = set.most_specific();
+        assert_eq!(set.tags(ip("3.5.141.1")), ["AWS ap-northeast-2"]);
+        assert_eq!(set.tags(ip("3.6.0.1")), ["AWS"]);
+    }
+}
@@ -0,0 +1,44 @@
+//! Enrichment of extracted entities
+//!
+//! Entities run through a [`Pipeline`] of enrichers. Geolocation and CDN
+//! lookups are answered from local databases. Lookups that need network
+//! access, such as RDAP/whois registration data or reverse DNS, are disabled
+//! by default. This module also batches lookups so that entities repeated
+//! across many lines are looked up only once.
+//!
+//! # Module Organization
+//!
+//! - `pipeline`: The `Enricher` trait, the built-in enrichers and their order
+//! - `whois`: RDAP client returning network name and organization
+//! - `cache`: On-disk cache of RDAP answers keyed by network range
+//! - `dedupe`: One lookup per distinct entity across a chunk of lines
+//! - `ipset`: Lists of networks whose IPs are tagged
+//! - `cloud`: Published IP ranges of cloud providers, read as tagged sets
+//! - `tor`: List of Tor exit addresses
+//! - `rdns`: PTR lookups and providers inferred from PTR names
+
+pub mod cache;
+pub mod cloud;
+pub mod dedupe;
+pub mod ipset;
+#[cfg(feature = "native")]
+pub mod pipeline;
+pub mod rdns;
+pub mod tor;
+pub mod whois;
+
+pub use cache::WhoisCache;
+pub use dedupe::Deduplicator;
+pub use ipset::IpSet;
+pub use tor::TorExits;
+#[cfg(feature = "native")]
+pub use pipeline::{
+    CdnEnricher, Enricher, Enrichment, GeoEnricher, Pipeline, RdnsEnricher, TagEnricher, TorEnricher,
+    WhoisEnricher,
+};
+pub use rdns::RdnsInfo;
+#[cfg(feature = "native")]
+pub use rdns::RdnsClient;
+pub use whois::WhoisInfo;
+#[cfg(feature = "native")]
+pub use whois::WhoisClient;
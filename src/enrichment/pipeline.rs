@@ -0,0 +1,650 @@
+//! Ordered pipeline of entity enrichers
+//!
+//! Every IP and domain found in the input runs through a list of
+//! [`Enricher`]s. The built-in ones are `geo` (geolocation databases), `cdn`
+//! (CDN provider rules), `tags` (`ipset` and cloud range databases, when any
+//! is installed),
+//! `tor` (Tor exits, once the list is downloaded), `whois` (RDAP, only
+//! when enabled) and `rdns` (PTR names, only when enabled). Their order
+//! and selection come from the `enrichment` section of the configuration.
+//! Programs embedding nali-rs can add their own:
+//!
+//! ```no_run
+//! use futures_util::future::BoxFuture;
+//! use nali_rs::{AppConfig, DatabaseManager, Enricher, Enrichment, Entity, Pipeline, Result};
+//!
+//! struct Internal;
+//!
+//! impl Enricher for Internal {
+//!     fn name(&self) -> &str {
+//!         "internal"
+//!     }
+//!
+//!     fn enrich<'a>(&'a self, entity: &'a mut Entity) -> BoxFuture<'a, Result<Enrichment>> {
+//!         Box::pin(async move {
+//!             if !entity.text.starts_with("10.") {
+//!                 return Ok(Enrichment::Skipped);
+//!             }
+//!             entity.source = Some("internal".to_string());
+//!             Ok(Enrichment::Added)
+//!         })
+//!     }
+//! }
+//!
+//! # async fn run() -> Result<()> {
+//! let config = AppConfig::load()?;
+//! let manager = DatabaseManager::new(config.clone()).into_shared();
+//! let pipeline = Pipeline::standard(&config, manager)?.with(Box::new(Internal));
+//! # Ok(())
+//! # }
+//! ```
+
+use super::{IpSet, RdnsClient, RdnsInfo, TorExits, WhoisClient, cloud, ipset, rdns, tor};
+use crate::config::{AppConfig, EnrichmentConfig};
+use crate::database::DatabaseManager;
+use crate::entity::{Entity, EntityType};
+use crate::error::{NaliError, Result};
+use futures_util::future::{BoxFuture, join_all};
+use std::sync::Arc;
+use std::time::Instant;
+use tokio::sync::Semaphore;
+
+/// What an enricher did with an entity
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Enrichment {
+    /// Information was added
+    Added,
+    /// The entity should have been found but wasn't (counted as unresolved)
+    Unresolved,
+    /// Not applicable to this entity, or nothing worth reporting
+    Skipped,
+}
+
+/// One step of the pipeline
+pub trait Enricher: Send + Sync {
+    /// Name used in the `enrichment.order` and `enrichment.disabled` settings
+    fn name(&self) -> &str;
+
+    /// Add information to an IP or domain entity
+    ///
+    /// An error is counted as a failed lookup; the remaining enrichers still
+    /// run unless strict mode stops the whole run.
+    fn enrich<'a>(&'a self, entity: &'a mut Entity) -> BoxFuture<'a, Result<Enrichment>>;
+}
+
+/// Geolocation from the selected IPv4/IPv6 databases
+pub struct GeoEnricher {
+    manager: Arc<DatabaseManager>,
+}
+
+impl GeoEnricher {
+    pub fn new(manager: Arc<DatabaseManager>) -> Self {
+        Self { manager }
+    }
+}
+
+impl Enricher for GeoEnricher {
+    fn name(&self) -> &str {
+        "geo"
+    }
+
+    fn enrich<'a>(&'a self, entity: &'a mut Entity) -> BoxFuture<'a, Result<Enrichment>> {
+        Box::pin(async move {
+            let Some(ip) = entity.as_ip() else {
+                return Ok(Enrichment::Skipped);
+            };
+            let db_name = self.manager.database_for(ip);
+            let started = Instant::now();
+            let geo = self.manager.query_ip(ip).await?;
+            entity.meta = Some(self.manager.query_meta(db_name, started.elapsed()));
+
+            Ok(match geo {
+                Some(geo) => {
+                    entity.geo_info = Some(geo);
+                    entity.source = Some(db_name.to_string());
+                    Enrichment::Added
+                }
+                None => Enrichment::Unresolved,
+            })
+        })
+    }
+}
+
+/// CDN provider of domains
+///
+/// A domain without a CDN match is normal and not counted as unresolved.
+pub struct CdnEnricher {
+    manager: Arc<DatabaseManager>,
+    database: String,
+}
+
+impl CdnEnricher {
+    pub fn new(manager: Arc<DatabaseManager>, config: &AppConfig) -> Self {
+        Self {
+            manager,
+            database: config.database.cdn_database.clone(),
+        }
+    }
+}
+
+impl Enricher for CdnEnricher {
+    fn name(&self) -> &str {
+        "cdn"
+    }
+
+    fn enrich<'a>(&'a self, entity: &'a mut Entity) -> BoxFuture<'a, Result<Enrichment>> {
+        Box::pin(async move {
+            if entity.entity_type != EntityType::Domain {
+                return Ok(Enrichment::Skipped);
+            }
+            let started = Instant::now();
+            let cdn = self.manager.query_cdn(&entity.text).await?;
+            entity.meta = Some(self.manager.query_meta(&self.database, started.elapsed()));
+
+            Ok(match cdn {
+                Some(cdn) => {
+                    entity.cdn_info = Some(cdn);
+                    entity.source = Some(self.database.clone());
+                    Enrichment::Added
+                }
+                None => Enrichment::Skipped,
+            })
+        })
+    }
+}
+
+/// RDAP registration data of IPs; failures are logged and never fail the query
+pub struct WhoisEnricher {
+    client: WhoisClient,
+}
+
+impl WhoisEnricher {
+    pub fn new(client: WhoisClient) -> Self {
+        Self { client }
+    }
+}
+
+impl Enricher for WhoisEnricher {
+    fn name(&self) -> &str {
+        "whois"
+    }
+
+    fn enrich<'a>(&'a self, entity: &'a mut Entity) -> BoxFuture<'a, Result<Enrichment>> {
+        Box::pin(async move {
+            let Some(ip) = entity.as_ip() else {
+                return Ok(Enrichment::Skipped);
+            };
+            match self.client.lookup(ip).await {
+                Ok(info) => {
+                    entity.whois_info = info;
+                    Ok(if entity.whois_info.is_some() {
+                        Enrichment::Added
+                    } else {
+                        Enrichment::Skipped
+                    })
+                }
+                Err(e) => {
+                    tracing::warn!("Whois lookup for {} failed: {}", ip, e);
+                    Ok(Enrichment::Skipped)
+                }
+            }
+        })
+    }
+}
+
+/// Tags of the `ipset` and cloud range databases containing IPs
+pub struct TagEnricher {
+    sets: Vec<IpSet>,
+}
+
+impl TagEnricher {
+    pub fn new(sets: Vec<IpSet>) -> Self {
+        Self { sets }
+    }
+
+    /// The installed `ipset` and cloud range databases of `config`
+    ///
+    /// Sets whose file is missing are left out, so an `--update` that
+    /// hasn't run yet doesn't fail every query.
+    pub fn from_config(config: &AppConfig) -> Result<Self> {
+        let mut sets = Vec::new();
+        for db in config.database.databases.iter().filter(|db| ipset::is_tag_format(&db.format)) {
+            let path = config.get_database_path(&db.name)?;
+            if !path.exists() {
+                tracing::debug!("IP set {} is not installed at {:?}", db.name, path);
+                continue;
+            }
+            let set = if db.format == ipset::FORMAT {
+                IpSet::load(&path, db.tag.as_deref().unwrap_or(&db.name))?
+            } else {
+                cloud::load(&db.format, &path)?
+            };
+            tracing::debug!("Loaded {} networks of IP set {}", set.len(), db.name);
+            sets.push(set);
+        }
+        Ok(Self::new(sets))
+    }
+
+    /// Check whether there are no sets to tag with
+    pub fn is_empty(&self) -> bool {
+        self.sets.is_empty()
+    }
+}
+
+impl Enricher for TagEnricher {
+    fn name(&self) -> &str {
+        "tags"
+    }
+
+    fn enrich<'a>(&'a self, entity: &'a mut Entity) -> BoxFuture<'a, Result<Enrichment>> {
+        Box::pin(async move {
+            let Some(ip) = entity.as_ip() else {
+                return Ok(Enrichment::Skipped);
+            };
+            for tag in self.sets.iter().flat_map(|set| set.tags(ip)) {
+                if !entity.tags.iter().any(|t| t == tag) {
+                    entity.tags.push(tag.to_string());
+                }
+            }
+            Ok(if entity.tags.is_empty() {
+                Enrichment::Skipped
+            } else {
+                Enrichment::Added
+            })
+        })
+    }
+}
+
+/// Marks IPs on the Tor exit list
+pub struct TorEnricher {
+    exits: TorExits,
+}
+
+impl TorEnricher {
+    pub fn new(exits: TorExits) -> Self {
+        Self { exits }
+    }
+}
+
+impl Enricher for TorEnricher {
+    fn name(&self) -> &str {
+        "tor"
+    }
+
+    fn enrich<'a>(&'a self, entity: &'a mut Entity) -> BoxFuture<'a, Result<Enrichment>> {
+        Box::pin(async move {
+            match entity.as_ip() {
+                Some(ip) if self.exits.contains(ip) => {
+                    entity.is_tor = true;
+                    Ok(Enrichment::Added)
+                }
+                _ => Ok(Enrichment::Skipped),
+            }
+        })
+    }
+}
+
+/// PTR names of IPs, with the provider inferred from them when no CDN rule
+/// matched; failures are logged and never fail the query
+pub struct RdnsEnricher {
+    client: RdnsClient,
+    manager: Arc<DatabaseManager>,
+    infer_provider: bool,
+}
+
+impl RdnsEnricher {
+    pub fn new(client: RdnsClient, manager: Arc<DatabaseManager>, infer_provider: bool) -> Self {
+        Self {
+            client,
+            manager,
+            infer_provider,
+        }
+    }
+
+    /// Provider of the CDN rules matching `hostname`, or of the built-in
+    /// suffix table
+    async fn infer(&self, hostname: &str) -> Option<String> {
+        match self.manager.query_cdn(hostname).await {
+            Ok(Some(cdn)) => return Some(cdn.provider),
+            Ok(None) => {}
+            Err(e) => tracing::debug!("CDN lookup for {} failed: {}", hostname, e),
+        }
+        rdns::infer_provider(hostname).map(str::to_string)
+    }
+}
+
+impl Enricher for RdnsEnricher {
+    fn name(&self) -> &str {
+        "rdns"
+    }
+
+    fn enrich<'a>(&'a self, entity: &'a mut Entity) -> BoxFuture<'a, Result<Enrichment>> {
+        Box::pin(async move {
+            let Some(ip) = entity.as_ip() else {
+                return Ok(Enrichment::Skipped);
+            };
+            let hostname = match self.client.lookup(ip).await {
+                Ok(Some(hostname)) => hostname,
+                Ok(None) => return Ok(Enrichment::Skipped),
+                Err(e) => {
+                    tracing::warn!("Reverse DNS lookup for {} failed: {}", ip, e);
+                    return Ok(Enrichment::Skipped);
+                }
+            };
+            let inferred_provider = if self.infer_provider && entity.cdn_info.is_none() {
+                self.infer(&hostname).await
+            } else {
+                None
+            };
+            entity.rdns = Some(RdnsInfo {
+                hostname,
+                inferred_provider,
+            });
+            Ok(Enrichment::Added)
+        })
+    }
+}
+
+/// Outcome of running the pipeline on one entity
+#[derive(Debug, Default)]
+pub struct Report {
+    /// Some enricher expected to find the entity and didn't
+    pub unresolved: bool,
+    /// Errors of the enrichers that failed, in pipeline order
+    pub errors: Vec<NaliError>,
+}
+
+/// Enrichers run in order on every IP and domain entity
+#[derive(Default)]
+pub struct Pipeline {
+    enrichers: Vec<Box<dyn Enricher>>,
+    /// Entities [`Pipeline::enrich_all`] enriches at once; 0 means 1
+    concurrency: usize,
+}
+
+impl Pipeline {
+    /// An empty pipeline
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The built-in enrichers, ordered and selected by the configuration
+    ///
+    /// `tags` and `tor` are only included once their lists are installed,
+    /// `whois` when `whois.enabled` is set and `rdns` when `rdns.enabled` is.
+    pub fn standard(config: &AppConfig, manager: Arc<DatabaseManager>) -> Result<Self> {
+        let mut pipeline = Self::new()
+            .with(Box::new(GeoEnricher::new(Arc::clone(&manager))))
+            .with(Box::new(CdnEnricher::new(Arc::clone(&manager), config)));
+        let tags = TagEnricher::from_config(config)?;
+        if !tags.is_empty() {
+            pipeline = pipeline.with(Box::new(tags));
+        }
+        let tor_list = config.get_database_path(tor::DATABASE)?;
+        if tor_list.exists() {
+            let exits = TorExits::load(&tor_list)?;
+            tracing::debug!("Loaded {} Tor exits from {:?}", exits.len(), tor_list);
+            pipeline = pipeline.with(Box::new(TorEnricher::new(exits)));
+        }
+        if config.whois.enabled {
+            let client = WhoisClient::new(&config.whois, &config.state_dir()?)?;
+            pipeline = pipeline.with(Box::new(WhoisEnricher::new(client)));
+        }
+        if config.rdns.enabled {
+            let client = RdnsClient::new(&config.rdns)?;
+            let enricher = RdnsEnricher::new(client, manager, config.rdns.infer_provider);
+            pipeline = pipeline.with(Box::new(enricher));
+        }
+        pipeline.configure(&config.enrichment);
+        Ok(pipeline)
+    }
+
+    /// Append an enricher
+    pub fn with(mut self, enricher: Box<dyn Enricher>) -> Self {
+        self.enrichers.push(enricher);
+        self
+    }
+
+    /// Move the enrichers named in `order` to the front, drop disabled ones
+    /// and take the concurrency of [`Pipeline::enrich_all`]
+    ///
+    /// Unknown names are logged and otherwise ignored, since custom
+    /// enrichers may be added after the configuration is applied.
+    pub fn configure(&mut self, config: &EnrichmentConfig) {
+        self.concurrency = config.concurrency;
+        for name in config.order.iter().chain(&config.disabled) {
+            if !self.enrichers.iter().any(|e| e.name() == name) {
+                tracing::warn!("Unknown enricher in configuration: {}", name);
+            }
+        }
+
+        self.enrichers
+            .retain(|e| !config.disabled.iter().any(|name| name == e.name()));
+        // Stable sort: listed enrichers first, the rest keep their order
+        self.enrichers.sort_by_key(|e| {
+            config
+                .order
+                .iter()
+                .position(|name| name == e.name())
+                .unwrap_or(usize::MAX)
+        });
+    }
+
+    /// Names of the enrichers, in the order they run
+    pub fn names(&self) -> Vec<&str> {
+        self.enrichers.iter().map(|e| e.name()).collect()
+    }
+
+    /// Run every enricher on an entity; plain text is left alone
+    pub async fn enrich(&self, entity: &mut Entity) -> Report {
+        let mut report = Report::default();
+        if entity.entity_type == EntityType::Plain {
+            return report;
+        }
+
+        for enricher in &self.enrichers {
+            match enricher.enrich(entity).await {
+                Ok(Enrichment::Unresolved) => report.unresolved = true,
+                Ok(_) => {}
+                Err(e) => report.errors.push(e),
+            }
+        }
+        report
+    }
+
+    /// Run every enricher on many entities at once, returning their reports
+    /// in order
+    ///
+    /// Enrichers waiting on the network, like `rdns` and `whois`, then
+    /// overlap; at most `enrichment.concurrency` entities are in flight, so
+    /// a log of thousands of addresses doesn't flood the resolver either.
+    pub async fn enrich_all(&self, entities: &mut [Entity]) -> Vec<Report> {
+        let permits = Semaphore::new(self.concurrency.max(1));
+        let tasks = entities.iter_mut().map(|entity| {
+            let permits = &permits;
+            async move {
+                // The semaphore is never closed
+                let _permit = permits.acquire().await.ok();
+                self.enrich(entity).await
+            }
+        });
+        join_all(tasks).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    /// Appends its name to the entity's source
+    struct Tag(&'static str);
+
+    impl Enricher for Tag {
+        fn name(&self) -> &str {
+            self.0
+        }
+
+        fn enrich<'a>(&'a self, entity: &'a mut Entity) -> BoxFuture<'a, Result<Enrichment>> {
+            Box::pin(async move {
+                let source = entity.source.get_or_insert_with(String::new);
+                source.push_str(self.0);
+                match self.0 {
+                    "miss" => Ok(Enrichment::Unresolved),
+                    "fail" => Err(NaliError::Other("lookup failed".to_string())),
+                    _ => Ok(Enrichment::Added),
+                }
+            })
+        }
+    }
+
+    fn pipeline(names: &[&'static str]) -> Pipeline {
+        names
+            .iter()
+            .fold(Pipeline::new(), |p, name| p.with(Box::new(Tag(name))))
+    }
+
+    #[tokio::test]
+    async fn test_runs_in_order() {
+        let mut entity = crate::entity::parser::parse_line("1.2.3.4").entities[0].clone();
+        let report = pipeline(&["a", "fail", "miss", "b"])
+            .enrich(&mut entity)
+            .await;
+
+        assert_eq!(entity.source.as_deref(), Some("afailmissb"));
+        assert!(report.unresolved);
+        assert_eq!(report.errors.len(), 1);
+
+        let mut plain = Entity::plain(0, 3, "foo".to_string());
+        pipeline(&["a"]).enrich(&mut plain).await;
+        assert_eq!(plain.source, None);
+    }
+
+    /// Records how many entities it works on at the same time
+    #[derive(Clone, Default)]
+    struct Slow {
+        active: Arc<AtomicUsize>,
+        peak: Arc<AtomicUsize>,
+    }
+
+    impl Enricher for Slow {
+        fn name(&self) -> &str {
+            "slow"
+        }
+
+        fn enrich<'a>(&'a self, entity: &'a mut Entity) -> BoxFuture<'a, Result<Enrichment>> {
+            Box::pin(async move {
+                let active = self.active.fetch_add(1, Ordering::SeqCst) + 1;
+                self.peak.fetch_max(active, Ordering::SeqCst);
+                tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+                self.active.fetch_sub(1, Ordering::SeqCst);
+                Ok(if entity.text == "10.0.0.3" {
+                    Enrichment::Unresolved
+                } else {
+                    Enrichment::Added
+                })
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn test_enrich_all_bounded() {
+        let slow = Slow::default();
+        let mut pipeline = Pipeline::new().with(Box::new(slow.clone()));
+        pipeline.configure(&EnrichmentConfig {
+            concurrency: 2,
+            ..EnrichmentConfig::default()
+        });
+        let mut entities: Vec<Entity> = (0..6)
+            .map(|i| {
+                let text = format!("10.0.0.{}", i);
+                Entity::ipv4(0, text.len(), text)
+            })
+            .collect();
+
+        let reports = pipeline.enrich_all(&mut entities).await;
+        let unresolved: Vec<bool> = reports.iter().map(|r| r.unresolved).collect();
+        assert_eq!(unresolved, [false, false, false, true, false, false]);
+        assert_eq!(slow.peak.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn test_tor_exits_once_installed() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut config = AppConfig::default();
+        config.database.data_dir = Some(dir.path().to_string_lossy().to_string());
+        let manager = DatabaseManager::new(config.clone()).into_shared();
+        assert!(!Pipeline::standard(&config, Arc::clone(&manager)).unwrap().names().contains(&"tor"));
+
+        std::fs::write(dir.path().join("tor-exits.txt"), "185.220.101.1\n").unwrap();
+        let pipeline = Pipeline::standard(&config, manager).unwrap();
+        assert!(pipeline.names().contains(&"tor"));
+
+        let mut entity = crate::entity::parser::parse_line("185.220.101.1").entities[0].clone();
+        TorEnricher::new(TorExits::parse("185.220.101.1"))
+            .enrich(&mut entity)
+            .await
+            .unwrap();
+        assert!(entity.is_tor);
+    }
+
+    #[test]
+    fn test_rdns_only_when_enabled() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut config = AppConfig::default();
+        config.database.data_dir = Some(dir.path().to_string_lossy().to_string());
+        let manager = DatabaseManager::new(config.clone()).into_shared();
+        assert!(!Pipeline::standard(&config, Arc::clone(&manager)).unwrap().names().contains(&"rdns"));
+
+        config.rdns.enabled = true;
+        config.rdns.server = Some("127.0.0.1:5353".to_string());
+        let pipeline = Pipeline::standard(&config, Arc::clone(&manager)).unwrap();
+        assert!(pipeline.names().contains(&"rdns"));
+
+        config.rdns.server = Some("resolver".to_string());
+        assert!(Pipeline::standard(&config, manager).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_tags_of_installed_sets() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut config = AppConfig::default();
+        config.database.data_dir = Some(dir.path().to_string_lossy().to_string());
+        for (name, tag) in [("drop", Some("Spamhaus DROP")), ("office", None), ("missing", None)] {
+            config.database.databases.push(crate::config::DatabaseInfo {
+                name: name.to_string(),
+                name_alias: vec![],
+                format: ipset::FORMAT.to_string(),
+                file: format!("{}.txt", name),
+                languages: vec![],
+                types: vec![],
+                download_urls: vec![],
+                tag: tag.map(str::to_string),
+            });
+        }
+        std::fs::write(dir.path().join("drop.txt"), "192.0.2.0/24 ; SBL1\n").unwrap();
+        std::fs::write(dir.path().join("office.txt"), "192.0.2.7\n").unwrap();
+
+        let tags = TagEnricher::from_config(&config).unwrap();
+        let mut entity = crate::entity::parser::parse_line("192.0.2.7").entities[0].clone();
+        assert_eq!(tags.enrich(&mut entity).await.unwrap(), Enrichment::Added);
+        assert_eq!(entity.tags, ["Spamhaus DROP", "office"]);
+
+        let mut entity = crate::entity::parser::parse_line("8.8.8.8").entities[0].clone();
+        assert_eq!(tags.enrich(&mut entity).await.unwrap(), Enrichment::Skipped);
+        assert!(entity.tags.is_empty());
+        // Local sets are never downloaded
+        assert!(!crate::download::updated_by_default(&config, config.find_database("drop").unwrap()));
+    }
+
+    #[test]
+    fn test_configure() {
+        let mut p = pipeline(&["geo", "cdn", "whois", "custom"]);
+        p.configure(&EnrichmentConfig {
+            order: vec!["whois".to_string(), "custom".to_string()],
+            disabled: vec!["cdn".to_string(), "unknown".to_string()],
+            concurrency: 8,
+        });
+        assert_eq!(p.names(), ["whois", "custom", "geo"]);
+    }
+}
@@ -0,0 +1,320 @@
+//! Reverse DNS of IPs
+//!
+//! With `rdns.enabled` (or `--rdns`) the PTR record of every IP is looked
+//! up. Hosting and CDN networks name their addresses after themselves
+//! (`server-13-32-1-1.fra50.r.cloudfront.net`,
+//! `1.1.1.34.bc.googleusercontent.com`), so when no CDN rule matched, the
+//! provider is inferred from the PTR name: first by the CDN rules, then by
+//! a built-in table of suffixes. The result is kept apart from `cdn_info`
+//! and always shown as inferred.
+
+use serde::{Deserialize, Serialize};
+use std::fmt::Write;
+use std::net::IpAddr;
+
+#[cfg(feature = "native")]
+use crate::config::RdnsConfig;
+#[cfg(feature = "native")]
+use crate::dns::{RecordData, RecordType, Resolver};
+#[cfg(feature = "native")]
+use crate::error::{NaliError, Result};
+#[cfg(feature = "native")]
+use std::collections::HashMap;
+#[cfg(feature = "native")]
+use std::net::SocketAddr;
+#[cfg(feature = "native")]
+use std::sync::{Arc, Mutex};
+#[cfg(feature = "native")]
+use std::time::Duration;
+#[cfg(feature = "native")]
+use tokio::sync::OnceCell;
+
+/// PTR name suffixes of hosting and CDN networks, and their provider
+const PROVIDER_SUFFIXES: &[(&str, &str)] = &[
+    ("r.cloudfront.net", "Amazon CloudFront"),
+    ("compute.amazonaws.com", "AWS EC2"),
+    ("compute-1.amazonaws.com", "AWS EC2"),
+    ("bc.googleusercontent.com", "Google Cloud"),
+    ("1e100.net", "Google"),
+    ("akamaitechnologies.com", "Akamai"),
+    ("akamaiedge.net", "Akamai"),
+    ("cloudapp.net", "Microsoft Azure"),
+    ("linodeusercontent.com", "Linode"),
+    ("members.linode.com", "Linode"),
+    ("vultrusercontent.com", "Vultr"),
+    ("your-server.de", "Hetzner"),
+    ("ip-ranges.ovh.net", "OVHcloud"),
+];
+
+/// PTR name of an IP and what it tells
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct RdnsInfo {
+    /// PTR name, without the trailing dot
+    pub hostname: String,
+    /// Provider guessed from the PTR name, not from a CDN rule
+    pub inferred_provider: Option<String>,
+}
+
+impl RdnsInfo {
+    /// PTR name and inferred provider for inline annotation
+    pub fn summary(&self) -> String {
+        let mut summary = self.hostname.clone();
+        if let Some(ref provider) = self.inferred_provider {
+            let _ = write!(summary, " ({})", crate::tr!("inferred: {}", provider));
+        }
+        summary
+    }
+}
+
+/// Name to query for the PTR record of `ip`
+pub fn reverse_name(ip: IpAddr) -> String {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [a, b, c, d] = ip.octets();
+            format!("{}.{}.{}.{}.in-addr.arpa", d, c, b, a)
+        }
+        IpAddr::V6(ip) => {
+            let mut name = String::with_capacity(72);
+            for byte in ip.octets().iter().rev() {
+                let _ = write!(name, "{:x}.{:x}.", byte & 0xf, byte >> 4);
+            }
+            name.push_str("ip6.arpa");
+            name
+        }
+    }
+}
+
+/// Provider of the built-in table whose suffix `hostname` ends in
+pub fn infer_provider(hostname: &str) -> Option<&'static str> {
+    let hostname = hostname.trim_end_matches('.').to_ascii_lowercase();
+    PROVIDER_SUFFIXES
+        .iter()
+        .find(|(suffix, _)| {
+            hostname
+                .strip_suffix(suffix)
+                .is_some_and(|rest| rest.is_empty() || rest.ends_with('.'))
+        })
+        .map(|&(_, provider)| provider)
+}
+
+/// PTR name of an address, or `None` if it has none
+#[cfg(feature = "native")]
+type CachedName = Arc<OnceCell<Option<String>>>;
+
+/// Answers by address and when each was last used
+#[cfg(feature = "native")]
+#[derive(Default)]
+struct NameCache {
+    names: HashMap<IpAddr, (CachedName, u64)>,
+    /// Next value of the last use
+    clock: u64,
+}
+
+#[cfg(feature = "native")]
+impl NameCache {
+    /// Cell of `ip`, added if missing, evicting when `capacity` is reached
+    fn cell(&mut self, ip: IpAddr, capacity: usize) -> CachedName {
+        self.clock += 1;
+        if let Some((cell, used)) = self.names.get_mut(&ip) {
+            *used = self.clock;
+            return Arc::clone(cell);
+        }
+        if self.names.len() >= capacity {
+            self.evict(capacity);
+        }
+        let cell = CachedName::default();
+        self.names.insert(ip, (Arc::clone(&cell), self.clock));
+        cell
+    }
+
+    /// Drop the least recently used names, down to nine tenths of `capacity`
+    ///
+    /// A lookup still in flight keeps its cell; only the next lookup of that
+    /// address queries again.
+    fn evict(&mut self, capacity: usize) {
+        let keep = capacity - capacity / 10;
+        if self.names.len() <= keep {
+            return;
+        }
+        let excess = self.names.len() - keep;
+        let mut by_use: Vec<(u64, IpAddr)> =
+            self.names.iter().map(|(ip, (_, used))| (*used, *ip)).collect();
+        by_use.select_nth_unstable(excess - 1);
+        for (_, ip) in &by_use[..excess] {
+            self.names.remove(ip);
+        }
+    }
+}
+
+/// PTR lookups against one DNS server, each address queried once per run
+/// while it is among the `rdns.cache_size` most recently used
+#[cfg(feature = "native")]
+pub struct RdnsClient {
+    resolver: Resolver,
+    /// Answers by address, shared by concurrent lookups so that an address
+    /// queried while already in flight waits for that answer
+    cache: Mutex<NameCache>,
+    /// Addresses kept in `cache`
+    capacity: usize,
+}
+
+#[cfg(feature = "native")]
+impl RdnsClient {
+    /// Create a client from configuration; without a server the system
+    /// resolver is used
+    pub fn new(config: &RdnsConfig) -> Result<Self> {
+        let timeout = Duration::from_secs(config.timeout.max(1));
+        let resolver = match config.server.as_deref() {
+            None => Resolver::system(timeout),
+            Some(server) => {
+                let addr = server
+                    .parse::<SocketAddr>()
+                    .or_else(|_| {
+                        server
+                            .parse::<IpAddr>()
+                            .map(|ip| SocketAddr::new(ip, crate::dns::client::DNS_PORT))
+                    })
+                    .map_err(|_| NaliError::config(format!("Invalid rdns.server: {}", server)))?;
+                Resolver::new(addr, timeout)
+            }
+        };
+        Ok(Self {
+            resolver,
+            cache: Mutex::default(),
+            capacity: config.cache_size.max(1),
+        })
+    }
+
+    /// PTR name of `ip`, `Ok(None)` when there is none
+    ///
+    /// Failed queries, such as timeouts, are not cached and are retried the
+    /// next time the address comes up.
+    pub async fn lookup(&self, ip: IpAddr) -> Result<Option<String>> {
+        let cell = self
+            .cache
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .cell(ip, self.capacity);
+        cell.get_or_try_init(|| self.query(ip)).await.cloned()
+    }
+
+    /// Number of addresses answered so far
+    pub fn cached(&self) -> usize {
+        self.cache
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .names
+            .values()
+            .filter(|(cell, _)| cell.initialized())
+            .count()
+    }
+
+    async fn query(&self, ip: IpAddr) -> Result<Option<String>> {
+        let response = self.resolver.query(&reverse_name(ip), RecordType::PTR).await?;
+        Ok(response.answers.into_iter().find_map(|record| match record.data {
+            RecordData::Name(name) if record.rtype == RecordType::PTR => {
+                Some(name.trim_end_matches('.').to_string())
+            }
+            _ => None,
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reverse_name() {
+        assert_eq!(reverse_name("192.0.2.1".parse().unwrap()), "1.2.0.192.in-addr.arpa");
+        assert_eq!(
+            reverse_name("2001:db8::567:89ab".parse().unwrap()),
+            "b.a.9.8.7.6.5.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.8.b.d.0.1.0.0.2.ip6.arpa"
+        );
+    }
+
+    #[test]
+    fn test_infer_provider() {
+        assert_eq!(
+            infer_provider("server-13-32-1-1.fra50.r.cloudfront.net."),
+            Some("Amazon CloudFront")
+        );
+        assert_eq!(
+            infer_provider("ec2-3-5-140-1.ap-northeast-2.compute.amazonaws.com"),
+            Some("AWS EC2")
+        );
+        assert_eq!(infer_provider("1.1.1.34.BC.googleusercontent.com"), Some("Google Cloud"));
+        // Suffixes only match whole labels
+        assert_eq!(infer_provider("evil1e100.net"), None);
+        assert_eq!(infer_provider("one.one.one.one"), None);
+    }
+
+    #[cfg(feature = "native")]
+    #[tokio::test]
+    async fn test_lookup_once_per_address() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use tokio::net::UdpSocket;
+
+        let server = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let addr = server.local_addr().unwrap();
+        let queries = Arc::new(AtomicUsize::new(0));
+        let counter = Arc::clone(&queries);
+        tokio::spawn(async move {
+            let mut buf = [0u8; 512];
+            loop {
+                let (len, peer) = server.recv_from(&mut buf).await.unwrap();
+                counter.fetch_add(1, Ordering::SeqCst);
+                // Echo the question back with one PTR record, `host.example.`
+                let mut reply = buf[..len].to_vec();
+                reply[2] = 0x81;
+                reply[3] = 0x80;
+                reply[7] = 1;
+                reply.extend_from_slice(&[0xC0, 12, 0, 12, 0, 1, 0, 0, 0, 30, 0, 14]);
+                reply.extend_from_slice(b"\x04host\x07example\x00");
+                server.send_to(&reply, peer).await.unwrap();
+            }
+        });
+
+        let config = RdnsConfig {
+            server: Some(addr.to_string()),
+            ..RdnsConfig::default()
+        };
+        let client = RdnsClient::new(&config).unwrap();
+        let ip: IpAddr = "192.0.2.1".parse().unwrap();
+        let answers = futures_util::future::join_all((0..8).map(|_| client.lookup(ip))).await;
+        for answer in answers {
+            assert_eq!(answer.unwrap().as_deref(), Some("host.example"));
+        }
+        assert_eq!(queries.load(Ordering::SeqCst), 1);
+        assert_eq!(client.cached(), 1);
+    }
+
+    #[cfg(feature = "native")]
+    #[test]
+    fn test_least_recently_used_are_dropped() {
+        let mut cache = NameCache::default();
+        let ip = |last: u8| IpAddr::from([192, 0, 2, last]);
+        let first = cache.cell(ip(1), 10);
+        for last in 2..=10 {
+            cache.cell(ip(last), 10);
+        }
+        assert!(Arc::ptr_eq(&first, &cache.cell(ip(1), 10)));
+
+        // Full: the address used longest ago makes room
+        cache.cell(ip(11), 10);
+        assert_eq!(cache.names.len(), 10);
+        assert!(cache.names.contains_key(&ip(1)));
+        assert!(!cache.names.contains_key(&ip(2)));
+    }
+
+    #[test]
+    fn test_summary() {
+        let mut info = RdnsInfo {
+            hostname: "one.one.one.one".to_string(),
+            inferred_provider: None,
+        };
+        assert_eq!(info.summary(), "one.one.one.one");
+        info.inferred_provider = Some("Cloudflare".to_string());
+        assert_eq!(info.summary(), "one.one.one.one (inferred: Cloudflare)");
+    }
+}
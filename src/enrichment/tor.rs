@@ -0,0 +1,84 @@
+//! Tor exit node list
+//!
+//! `nali-rs --update tor` downloads the Tor Project's list of exit
+//! addresses. Once it is installed, IPs on it are marked as Tor exits in
+//! annotations and get `"is_tor": true` in JSON; without it nothing changes.
+//! Both the bulk list (one address per line) and the `exit-addresses`
+//! format (`ExitAddress <ip> <date>` lines) are understood.
+
+use crate::error::Result;
+use std::collections::HashSet;
+use std::fs;
+use std::net::IpAddr;
+use std::path::Path;
+
+/// Name of the Tor exit list in the database configuration
+pub const DATABASE: &str = "tor";
+
+/// Set of known Tor exit addresses
+#[derive(Debug, Default)]
+pub struct TorExits {
+    addrs: HashSet<IpAddr>,
+}
+
+impl TorExits {
+    /// Parse a list; lines that hold no address are skipped
+    pub fn parse(text: &str) -> Self {
+        let addrs = text
+            .lines()
+            .filter_map(|line| {
+                let mut fields = line.split_whitespace();
+                match fields.next()? {
+                    "ExitAddress" => fields.next()?.parse().ok(),
+                    field => field.parse().ok(),
+                }
+            })
+            .collect();
+        Self { addrs }
+    }
+
+    /// Read the list at `path`
+    pub fn load(path: &Path) -> Result<Self> {
+        Ok(Self::parse(&fs::read_to_string(path)?))
+    }
+
+    /// Check whether `ip` is a Tor exit
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        self.addrs.contains(&ip)
+    }
+
+    /// Number of exit addresses
+    pub fn len(&self) -> usize {
+        self.addrs.len()
+    }
+
+    /// Check whether the list is empty
+    pub fn is_empty(&self) -> bool {
+        self.addrs.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_bulk_list() {
+        let exits = TorExits::parse("185.220.101.1\n2001:db8::1\n\n# comment\nnot an address\n");
+        assert_eq!(exits.len(), 2);
+        assert!(exits.contains("185.220.101.1".parse().unwrap()));
+        assert!(exits.contains("2001:db8::1".parse().unwrap()));
+        assert!(!exits.contains("8.8.8.8".parse().unwrap()));
+    }
+
+    #[test]
+    fn test_parse_exit_addresses() {
+        let exits = TorExits::parse(
+            "ExitNode 0011BD2485AD45D984EC4159C88FC066E5E3300E\n\
+             Published 2024-01-10 03:21:07\n\
+             ExitAddress 162.247.74.201 2024-01-10 04:03:04\n",
+        );
+        assert_eq!(exits.len(), 1);
+        assert!(exits.contains("162.247.74.201".parse().unwrap()));
+    }
+}
@@ -0,0 +1,219 @@
+//! RDAP/whois lookups for IP addresses
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::net::IpAddr;
+
+#[cfg(feature = "native")]
+use crate::config::WhoisConfig;
+#[cfg(feature = "native")]
+use crate::error::{NaliError, Result};
+#[cfg(feature = "native")]
+use std::path::Path;
+#[cfg(feature = "native")]
+use std::sync::Mutex;
+#[cfg(feature = "native")]
+use std::time::Duration;
+
+#[cfg(feature = "native")]
+use super::cache::WhoisCache;
+
+/// Cache file name under the data directory
+#[cfg(feature = "native")]
+pub const CACHE_FILE: &str = "whois_cache.json";
+
+/// Registration data of the network an address belongs to
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct WhoisInfo {
+    /// Network name, e.g. `GOGL`
+    pub netname: Option<String>,
+    /// Registrant organization
+    pub org: Option<String>,
+    /// Registry handle of the network
+    pub handle: Option<String>,
+    /// Country code registered for the network
+    pub country: Option<String>,
+}
+
+impl WhoisInfo {
+    /// Network name and organization joined for inline annotation
+    pub fn summary(&self) -> String {
+        let mut parts: Vec<&str> = Vec::with_capacity(2);
+        if let Some(ref netname) = self.netname {
+            parts.push(netname);
+        }
+        if let Some(ref org) = self.org
+            && self.netname.as_ref() != Some(org)
+        {
+            parts.push(org);
+        }
+        parts.join(" ")
+    }
+}
+
+/// Parse an RDAP IP network object into its range and registration data
+pub fn parse_rdap(body: &Value) -> Option<(IpAddr, IpAddr, WhoisInfo)> {
+    let address = |key: &str| body.get(key)?.as_str()?.trim().parse::<IpAddr>().ok();
+    let text = |key: &str| body.get(key)?.as_str().map(str::to_string);
+
+    let start = address("startAddress")?;
+    let end = address("endAddress")?;
+
+    let info = WhoisInfo {
+        netname: text("name"),
+        org: registrant_name(body),
+        handle: text("handle"),
+        country: text("country"),
+    };
+
+    Some((start, end, info))
+}
+
+/// Name of the registrant entity, falling back to the first named entity
+fn registrant_name(body: &Value) -> Option<String> {
+    let entities = body.get("entities")?.as_array()?;
+
+    let has_role = |entity: &&Value, role: &str| {
+        entity
+            .get("roles")
+            .and_then(Value::as_array)
+            .is_some_and(|roles| roles.iter().any(|r| r.as_str() == Some(role)))
+    };
+
+    entities
+        .iter()
+        .find(|e| has_role(e, "registrant"))
+        .and_then(vcard_name)
+        .or_else(|| entities.iter().find_map(vcard_name))
+}
+
+/// The `fn` property of an entity's jCard
+fn vcard_name(entity: &Value) -> Option<String> {
+    let properties = entity.get("vcardArray")?.get(1)?.as_array()?;
+    properties.iter().find_map(|property| {
+        let property = property.as_array()?;
+        if property.first()?.as_str()? != "fn" {
+            return None;
+        }
+        let name = property.get(3)?.as_str()?.trim();
+        (!name.is_empty()).then(|| name.to_string())
+    })
+}
+
+/// RDAP client with an on-disk answer cache
+#[cfg(feature = "native")]
+pub struct WhoisClient {
+    client: reqwest::Client,
+    server: String,
+    cache: Mutex<WhoisCache>,
+}
+
+#[cfg(feature = "native")]
+impl WhoisClient {
+    /// Create a client from configuration, caching under `cache_dir`
+    pub fn new(config: &WhoisConfig, cache_dir: &Path) -> Result<Self> {
+        let client = reqwest::Client::builder()
+            .user_agent(format!("nali-rs/{}", env!("CARGO_PKG_VERSION")))
+            .timeout(Duration::from_secs(config.timeout))
+            .build()
+            .map_err(|e| NaliError::network(format!("Failed to create HTTP client: {}", e)))?;
+
+        let cache = WhoisCache::open(&cache_dir.join(CACHE_FILE), config.cache_ttl);
+
+        Ok(Self {
+            client,
+            server: config.server.trim_end_matches('/').to_string(),
+            cache: Mutex::new(cache),
+        })
+    }
+
+    /// Look up registration data for an address
+    ///
+    /// Answers are served from the cache while fresh. Returns `Ok(None)` when
+    /// the RDAP server has no network for the address.
+    pub async fn lookup(&self, ip: IpAddr) -> Result<Option<WhoisInfo>> {
+        if let Some(info) = self.cache.lock().unwrap().get(ip) {
+            return Ok(Some(info.clone()));
+        }
+
+        let url = format!("{}/ip/{}", self.server, ip);
+        tracing::debug!("RDAP lookup: {}", url);
+
+        let response = self
+            .client
+            .get(&url)
+            .header("Accept", "application/rdap+json")
+            .send()
+            .await
+            .map_err(|e| NaliError::network(format!("RDAP request failed: {}", e)))?;
+
+        if response.status() == reqwest::StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        if !response.status().is_success() {
+            return Err(NaliError::network(format!(
+                "RDAP server returned {} for {}",
+                response.status(),
+                ip
+            )));
+        }
+
+        let body = response
+            .text()
+            .await
+            .map_err(|e| NaliError::network(format!("Failed to read RDAP response: {}", e)))?;
+        let body: Value = serde_json::from_str(&body)?;
+
+        let Some((start, end, info)) = parse_rdap(&body) else {
+            return Err(NaliError::parse(format!("Unrecognized RDAP response for {}", ip)));
+        };
+
+        let mut cache = self.cache.lock().unwrap();
+        cache.insert(start, end, info.clone());
+        if let Err(e) = cache.save() {
+            tracing::warn!("Failed to save whois cache: {}", e);
+        }
+
+        Ok(Some(info))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_rdap() {
+        let body: Value = serde_json::from_str(
+            r#"{
+                "objectClassName": "ip network",
+                "handle": "NET-8-8-8-0-2",
+                "startAddress": "8.8.8.0",
+                "endAddress": "8.8.8.255",
+                "name": "GOGL",
+                "country": "US",
+                "entities": [
+                    {
+                        "roles": ["abuse"],
+                        "vcardArray": ["vcard", [["fn", {}, "text", "Abuse"]]]
+                    },
+                    {
+                        "roles": ["registrant"],
+                        "vcardArray": ["vcard", [["version", {}, "text", "4.0"], ["fn", {}, "text", "Google LLC"]]]
+                    }
+                ]
+            }"#,
+        )
+        .unwrap();
+
+        let (start, end, info) = parse_rdap(&body).unwrap();
+        assert_eq!(start, "8.8.8.0".parse::<IpAddr>().unwrap());
+        assert_eq!(end, "8.8.8.255".parse::<IpAddr>().unwrap());
+        assert_eq!(info.netname.as_deref(), Some("GOGL"));
+        assert_eq!(info.org.as_deref(), Some("Google LLC"));
+        assert_eq!(info.country.as_deref(), Some("US"));
+        assert_eq!(info.summary(), "GOGL Google LLC");
+
+        assert!(parse_rdap(&serde_json::json!({"errorCode": 404})).is_none());
+    }
+}
@@ -1,5 +1,7 @@
 //! Entity formatter - formats entities for output
 
+use crate::config::{MapProvider, OutputConfig};
+use crate::database::GeoLocation;
 use crate::entity::types::{Entities, Entity, EntityType};
 use std::fmt::Write as FmtWrite;
 
@@ -24,47 +26,95 @@ pub enum OutputFormat {
 enum ColorType {
     Green,
     Cyan,
+    Blue,
+    Yellow,
+    Red,
 }
 
 /// Apply color to text if enabled
+#[cfg(feature = "colored-output")]
 fn apply_color(text: &str, use_color: bool, color_type: ColorType) -> String {
     if !use_color {
         return text.to_string();
     }
 
-    #[cfg(feature = "colored-output")]
-    {
-        match color_type {
-            ColorType::Green => text.green().to_string(),
-            ColorType::Cyan => text.cyan().to_string(),
-        }
+    match color_type {
+        ColorType::Green => text.green().to_string(),
+        ColorType::Cyan => text.cyan().to_string(),
+        ColorType::Blue => text.blue().to_string(),
+        ColorType::Yellow => text.yellow().to_string(),
+        ColorType::Red => text.red().to_string(),
     }
+}
 
-    #[cfg(not(feature = "colored-output"))]
-    {
-        text.to_string()
+/// Optional parts of a location in text output
+#[derive(Debug, Clone, Copy, Default)]
+pub struct GeoStyle {
+    /// Flag emoji of the country in front
+    pub flag: bool,
+    /// Latitude and longitude at the end
+    pub coords: bool,
+    /// Map link after the location
+    pub map_link: Option<MapProvider>,
+}
+
+impl From<&OutputConfig> for GeoStyle {
+    fn from(config: &OutputConfig) -> Self {
+        Self {
+            flag: config.show_flag,
+            coords: config.show_coords,
+            map_link: config.map_link,
+        }
     }
 }
 
+/// Display column where end-of-line annotations start, unless the line is longer
+pub const EOL_COLUMN: usize = 80;
+
 /// Format entities as text with inline geolocation information
-pub fn format_text(entities: &Entities, use_color: bool) -> String {
+///
+/// `verbose` adds the matched block, accuracy radius and source databases of
+/// each location.
+pub fn format_text(
+    entities: &Entities,
+    use_color: bool,
+    verbose: bool,
+    style: &GeoStyle,
+) -> String {
+    #[cfg(not(feature = "colored-output"))]
+    let _ = use_color;
     let mut result = String::new();
 
     for entity in &entities.entities {
         // Add the original text
         result.push_str(&entity.text);
 
-        // Add geolocation info for IP entities
-        if entity.has_geo_info()
-            && let Some(ref geo) = entity.geo_info {
-                let info = format_geo_info(geo);
-                #[cfg(feature = "colored-output")]
-                let formatted = apply_color(&info, use_color, ColorType::Green);
-                #[cfg(not(feature = "colored-output"))]
-                let formatted = info.clone();
+        // Add geolocation and whois info for IP entities
+        if let Some(info) = geo_annotation(entity, verbose, style) {
+            #[cfg(feature = "colored-output")]
+            let formatted = apply_color(&info, use_color, ColorType::Green);
+            #[cfg(not(feature = "colored-output"))]
+            let formatted = info;
+
+            result.push_str(&format!(" [{}] ", formatted));
+            if let Some(link) = entity.geo_info.as_ref().and_then(|geo| map_link(geo, style)) {
+                result.push_str(&link);
+                result.push(' ');
+            }
+        }
 
-                result.push_str(&format!(" [{}] ", formatted));
+        // Add a bracket for each IP set tag
+        for tag in &entity.tags {
+            #[cfg(feature = "colored-output")]
+            let formatted = apply_color(tag, use_color, ColorType::Yellow);
+            #[cfg(not(feature = "colored-output"))]
+            let formatted = tag;
+
+            if !result.ends_with(' ') {
+                result.push(' ');
             }
+            result.push_str(&format!("[{}] ", formatted));
+        }
 
         // Add CDN info for domain entities
         if entity.has_cdn_info()
@@ -82,9 +132,215 @@ pub fn format_text(entities: &Entities, use_color: bool) -> String {
     result
 }
 
+/// Format entities as the unchanged line followed by all its annotations
+///
+/// The annotations of every IP and domain, separated by commas, follow a
+/// ` | ` placed at display column [`EOL_COLUMN`], so they line up across
+/// lines; a longer line gets it right after its text. Lines without any
+/// annotation are left as they are.
+pub fn format_text_eol(
+    entities: &Entities,
+    use_color: bool,
+    verbose: bool,
+    style: &GeoStyle,
+) -> String {
+    #[cfg(not(feature = "colored-output"))]
+    let _ = use_color;
+    let mut line = String::new();
+    let mut annotations = Vec::new();
+
+    for entity in &entities.entities {
+        line.push_str(&entity.text);
+
+        if let Some(info) = geo_annotation(entity, verbose, style) {
+            #[cfg(feature = "colored-output")]
+            let mut formatted = apply_color(&info, use_color, ColorType::Green);
+            #[cfg(not(feature = "colored-output"))]
+            let mut formatted = info;
+
+            if let Some(link) = entity.geo_info.as_ref().and_then(|geo| map_link(geo, style)) {
+                formatted.push(' ');
+                formatted.push_str(&link);
+            }
+            annotations.push(formatted);
+        }
+
+        for tag in &entity.tags {
+            #[cfg(feature = "colored-output")]
+            let formatted = apply_color(tag, use_color, ColorType::Yellow);
+            #[cfg(not(feature = "colored-output"))]
+            let formatted = tag.clone();
+
+            annotations.push(formatted);
+        }
+
+        if entity.has_cdn_info()
+            && let Some(ref cdn) = entity.cdn_info
+        {
+            #[cfg(feature = "colored-output")]
+            let formatted = apply_color(&cdn.provider, use_color, ColorType::Cyan);
+            #[cfg(not(feature = "colored-output"))]
+            let formatted = cdn.provider.clone();
+
+            annotations.push(formatted);
+        }
+    }
+
+    if annotations.is_empty() {
+        return line;
+    }
+    line.truncate(line.trim_end().len());
+    let width = unicode_width::UnicodeWidthStr::width(line.as_str());
+    line.extend(std::iter::repeat_n(' ', EOL_COLUMN.saturating_sub(width)));
+    line.push_str(" | ");
+    line.push_str(&annotations.join(", "));
+    line
+}
+
+/// Text annotating an IP entity: its location, whois summary, PTR name and
+/// Tor exit mark, if any
+fn geo_annotation(entity: &Entity, verbose: bool, style: &GeoStyle) -> Option<String> {
+    if !entity.has_geo_info() && entity.whois_info.is_none() && entity.rdns.is_none() && !entity.is_tor {
+        return None;
+    }
+    let mut info = entity
+        .geo_info
+        .as_ref()
+        .map(|geo| format_geo_info(geo, style))
+        .unwrap_or_default();
+    if verbose
+        && let Some(details) = entity.geo_info.as_ref().and_then(format_geo_details)
+    {
+        info.push(' ');
+        info.push_str(&details);
+    }
+    if let Some(whois) = entity.whois_info.as_ref().map(|w| w.summary())
+        && !whois.is_empty()
+    {
+        if !info.is_empty() {
+            info.push_str(" | ");
+        }
+        info.push_str(&whois);
+    }
+    if let Some(rdns) = &entity.rdns {
+        if !info.is_empty() {
+            info.push_str(" | ");
+        }
+        info.push_str(&rdns.summary());
+    }
+    if entity.is_tor {
+        if !info.is_empty() {
+            info.push_str(" | ");
+        }
+        info.push_str(crate::tr!("Tor exit"));
+    }
+    Some(info)
+}
+
+/// Format entities exactly like the Go nali
+///
+/// Every IP or domain with information is followed by ` [info] `, where the
+/// info is the bare location or CDN provider: no flag, coordinates, tunnel,
+/// verbose details or whois. Colors match nali's too: IPv4 green, IPv6 blue,
+/// domains yellow and the info red.
+pub fn format_nali(entities: &Entities, use_color: bool) -> String {
+    let mut result = String::new();
+
+    for entity in &entities.entities {
+        #[cfg(feature = "colored-output")]
+        let text = match entity.entity_type {
+            EntityType::IPv4 => apply_color(&entity.text, use_color, ColorType::Green),
+            EntityType::IPv6 => apply_color(&entity.text, use_color, ColorType::Blue),
+            EntityType::Domain => apply_color(&entity.text, use_color, ColorType::Yellow),
+            EntityType::Plain => entity.text.clone(),
+        };
+        #[cfg(not(feature = "colored-output"))]
+        let text = {
+            let _ = use_color;
+            entity.text.clone()
+        };
+        result.push_str(&text);
+
+        if let Some(info) = nali_info(entity) {
+            #[cfg(feature = "colored-output")]
+            let formatted = apply_color(&info, use_color, ColorType::Red);
+            #[cfg(not(feature = "colored-output"))]
+            let formatted = info;
+
+            result.push_str(&format!(" [{}] ", formatted));
+        }
+    }
+
+    result
+}
+
+/// Format only the information of each entity, separated by spaces
+///
+/// Used by `--short`: the original text, brackets, tunnel, verbose details
+/// and whois are left out, so a lookup of one address prints just its
+/// location. Entities without information are skipped.
+pub fn format_short(entities: &Entities, style: &GeoStyle) -> String {
+    entities
+        .entities
+        .iter()
+        .filter_map(|entity| bare_info(entity, style))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Info text nali shows for an entity, if any
+fn nali_info(entity: &Entity) -> Option<String> {
+    bare_info(entity, &GeoStyle::default())
+}
+
+/// Location or CDN provider of an entity without tunnel, details or whois
+fn bare_info(entity: &Entity, style: &GeoStyle) -> Option<String> {
+    let info = match entity.entity_type {
+        EntityType::Plain => return None,
+        EntityType::Domain => entity.cdn_info.as_ref()?.provider.clone(),
+        EntityType::IPv4 | EntityType::IPv6 => {
+            let mut geo = entity.geo_info.clone()?;
+            geo.tunnel = None;
+            format_geo_info(&geo, style)
+        }
+    };
+    (!info.is_empty()).then_some(info)
+}
+
+/// Regional indicator emoji of an ISO 3166-1 alpha-2 code, e.g. `CN` -> 🇨🇳
+pub fn flag_emoji(country_code: &str) -> Option<String> {
+    let code = country_code.as_bytes();
+    if code.len() != 2 || !code.iter().all(u8::is_ascii_alphabetic) {
+        return None;
+    }
+    code.iter()
+        .map(|letter| char::from_u32(0x1F1E6 + u32::from(letter.to_ascii_uppercase() - b'A')))
+        .collect()
+}
+
+/// Link to the location's coordinates on the map service `style` asks for
+pub fn map_link(geo: &GeoLocation, style: &GeoStyle) -> Option<String> {
+    let (lat, lon) = geo.latitude.zip(geo.longitude)?;
+    Some(match style.map_link? {
+        MapProvider::Osm => format!(
+            "https://www.openstreetmap.org/?mlat={lat:.4}&mlon={lon:.4}#map=10/{lat:.4}/{lon:.4}"
+        ),
+        MapProvider::Google => format!("https://www.google.com/maps?q={lat:.4},{lon:.4}"),
+    })
+}
+
 /// Format geolocation information as a compact string
-fn format_geo_info(geo: &crate::database::GeoLocation) -> String {
-    let mut parts = Vec::with_capacity(4);
+fn format_geo_info(geo: &GeoLocation, style: &GeoStyle) -> String {
+    let mut parts = Vec::with_capacity(6);
+
+    let flag = geo
+        .country_code
+        .as_deref()
+        .filter(|_| style.flag)
+        .and_then(flag_emoji);
+    if let Some(ref flag) = flag {
+        parts.push(flag.as_str());
+    }
 
     if let Some(ref country) = geo.country {
         parts.push(country.as_str());
@@ -101,16 +357,58 @@ fn format_geo_info(geo: &crate::database::GeoLocation) -> String {
             parts.push(city.as_str());
         }
 
+    if let Some(ref district) = geo.district {
+        parts.push(district.as_str());
+    }
+
     if let Some(ref isp) = geo.isp {
         parts.push(isp.as_str());
     }
 
+    // Name the mechanism and address actually looked up for tunneled IPv6
+    let tunnel = geo
+        .tunnel
+        .map(|t| format!("({} {})", t.mechanism, t.ipv4));
+    if let Some(ref tunnel) = tunnel {
+        parts.push(tunnel.as_str());
+    }
+
+    let coords = geo
+        .latitude
+        .zip(geo.longitude)
+        .filter(|_| style.coords)
+        .map(|(lat, lon)| format!("{:.4},{:.4}", lat, lon));
+    if let Some(ref coords) = coords {
+        parts.push(coords.as_str());
+    }
+
     parts.join(" ")
 }
 
+/// Matched block, accuracy radius and source databases, e.g.
+/// `(1.2.3.0/24, ±50 km, qqwry+geoip2)`
+pub fn format_geo_details(geo: &GeoLocation) -> Option<String> {
+    let mut parts = Vec::with_capacity(3);
+    if let Some(ref network) = geo.network {
+        parts.push(network.clone());
+    }
+    if let Some(radius) = geo.accuracy_radius {
+        parts.push(format!("±{} km", radius));
+    }
+    if !geo.sources.is_empty() {
+        parts.push(geo.sources.join("+"));
+    }
+
+    (!parts.is_empty()).then(|| format!("({})", parts.join(", ")))
+}
+
 /// Format geolocation information as a compact string (public API)
-pub fn format_geo_info_compact(geo: &crate::database::GeoLocation) -> String {
-    let result = format_geo_info(geo);
+pub fn format_geo_info_compact(geo: &GeoLocation) -> String {
+    or_unknown(format_geo_info(geo, &GeoStyle::default()))
+}
+
+/// `[Unknown]` for a location without any information
+fn or_unknown(result: String) -> String {
     if result.is_empty() {
         "[Unknown]".to_string()
     } else {
@@ -118,30 +416,65 @@ pub fn format_geo_info_compact(geo: &crate::database::GeoLocation) -> String {
     }
 }
 
+/// Format geolocation information compactly, colored like inline annotations
+pub fn format_geo_info_colored(geo: &GeoLocation, use_color: bool, style: &GeoStyle) -> String {
+    let info = or_unknown(format_geo_info(geo, style));
+    #[cfg(feature = "colored-output")]
+    let formatted = apply_color(&info, use_color, ColorType::Green);
+    #[cfg(not(feature = "colored-output"))]
+    let formatted = {
+        let _ = use_color;
+        info
+    };
+    formatted
+}
+
+/// JSON representation of a single entity
+pub fn entity_json(e: &Entity) -> serde_json::Value {
+    let mut value = serde_json::json!({
+        "text": e.text,
+        "type": format!("{:?}", e.entity_type),
+        "position": {
+            "start": e.location.0,
+            "end": e.location.1,
+        },
+        "geo_info": e.geo_info,
+        "cdn_info": e.cdn_info,
+        "whois_info": e.whois_info,
+        "source": e.source,
+        "meta": e.meta,
+    });
+    // Only present when set, so other output stays as it was
+    if e.is_tor {
+        value["is_tor"] = true.into();
+    }
+    if !e.tags.is_empty() {
+        value["tags"] = e.tags.clone().into();
+    }
+    if let Some(rdns) = &e.rdns {
+        value["rdns"] = serde_json::json!(rdns);
+    }
+    value
+}
+
+/// JSON record of an entity found on input line `line_no`, as written to
+/// `.jsonl` result files
+pub fn entity_record(e: &Entity, line_no: usize) -> serde_json::Value {
+    let mut record = entity_json(e);
+    record["line"] = line_no.into();
+    record
+}
+
 /// Format entities as JSON
 pub fn format_json(entities: &Entities) -> Result<String, serde_json::Error> {
-    use serde_json::json;
-
     let items: Vec<_> = entities
         .entities
         .iter()
         .filter(|e| e.entity_type != EntityType::Plain)
-        .map(|e| {
-            json!({
-                "text": e.text,
-                "type": format!("{:?}", e.entity_type),
-                "position": {
-                    "start": e.location.0,
-                    "end": e.location.1,
-                },
-                "geo_info": e.geo_info,
-                "cdn_info": e.cdn_info,
-                "source": e.source,
-            })
-        })
+        .map(entity_json)
         .collect();
 
-    serde_json::to_string_pretty(&json!({
+    serde_json::to_string_pretty(&serde_json::json!({
         "entities": items
     }))
 }
@@ -154,7 +487,7 @@ pub fn format_entity(entity: &Entity) -> String {
     write!(&mut result, "{}", entity.text).unwrap();
 
     if let Some(ref geo) = entity.geo_info {
-        write!(&mut result, " -> {}", format_geo_info(geo)).unwrap();
+        write!(&mut result, " -> {}", format_geo_info(geo, &GeoStyle::default())).unwrap();
     }
 
     if let Some(ref cdn) = entity.cdn_info {
@@ -172,7 +505,6 @@ pub fn format_entity(entity: &Entity) -> String {
 mod tests {
     use super::*;
     use crate::entity::types::Entities;
-    use crate::database::GeoLocation;
     use std::net::IpAddr;
 
     #[test]
@@ -180,7 +512,7 @@ mod tests {
         let mut entities = Entities::new();
         entities.push(Entity::plain(0, 5, "Hello".to_string()));
 
-        let formatted = format_text(&entities, false);
+        let formatted = format_text(&entities, false, false, &GeoStyle::default());
         assert_eq!(formatted, "Hello");
     }
 
@@ -195,20 +527,290 @@ mod tests {
             region: Some("加利福尼亚".to_string()),
             city: Some("山景城".to_string()),
             isp: Some("Google".to_string()),
-            country_code: None,
-            timezone: None,
-            latitude: None,
-            longitude: None,
+            ..Default::default()
         });
 
         entities.push(entity);
 
-        let formatted = format_text(&entities, false);
+        let formatted = format_text(&entities, false, false, &GeoStyle::default());
         assert!(formatted.contains("8.8.8.8"));
         assert!(formatted.contains("["));
         assert!(formatted.contains("美国"));
     }
 
+    #[test]
+    fn test_format_text_verbose_details() {
+        let mut entities = Entities::new();
+        let mut entity = Entity::ipv4(0, 7, "1.2.3.4".to_string());
+        entity.geo_info = Some(GeoLocation {
+            ip: "1.2.3.4".parse::<IpAddr>().unwrap(),
+            country: Some("中国".to_string()),
+            city: Some("北京".to_string()),
+            latitude: Some(39.9),
+            longitude: Some(116.4),
+            accuracy_radius: Some(50),
+            network: Some("1.2.2.0-1.2.3.255".to_string()),
+            sources: vec!["qqwry".to_string(), "geoip2".to_string()],
+            ..Default::default()
+        });
+        entities.push(entity);
+
+        assert_eq!(format_text(&entities, false, false, &GeoStyle::default()), "1.2.3.4 [中国 北京] ");
+        assert_eq!(
+            format_text(&entities, false, true, &GeoStyle::default()),
+            "1.2.3.4 [中国 北京 (1.2.2.0-1.2.3.255, ±50 km, qqwry+geoip2)] "
+        );
+    }
+
+    #[test]
+    fn test_format_text_with_flag() {
+        assert_eq!(flag_emoji("cn").as_deref(), Some("🇨🇳"));
+        assert_eq!(flag_emoji("US").as_deref(), Some("🇺🇸"));
+        assert_eq!(flag_emoji("USA"), None);
+        assert_eq!(flag_emoji("4X"), None);
+
+        let mut entities = Entities::new();
+        let mut entity = Entity::ipv4(0, 7, "8.8.8.8".to_string());
+        entity.geo_info = Some(GeoLocation {
+            ip: "8.8.8.8".parse::<IpAddr>().unwrap(),
+            country: Some("美国".to_string()),
+            region: Some("加利福尼亚".to_string()),
+            country_code: Some("US".to_string()),
+            region_code: Some("US-CA".to_string()),
+            ..Default::default()
+        });
+        entities.push(entity);
+
+        assert_eq!(format_text(&entities, false, false, &GeoStyle::default()), "8.8.8.8 [美国 加利福尼亚] ");
+        assert_eq!(format_text(
+                &entities,
+                false,
+                false,
+                &GeoStyle {
+                    flag: true,
+                    ..Default::default()
+                }
+            ), "8.8.8.8 [🇺🇸 美国 加利福尼亚] ");
+
+        // The subdivision code is part of the JSON output only
+        let json = entity_json(&entities.entities[0]);
+        assert_eq!(json["geo_info"]["region_code"], "US-CA");
+    }
+
+    #[test]
+    fn test_format_text_with_coords_and_map_link() {
+        let mut entities = Entities::new();
+        let mut entity = Entity::ipv4(0, 7, "1.2.3.4".to_string());
+        entity.geo_info = Some(GeoLocation {
+            ip: "1.2.3.4".parse::<IpAddr>().unwrap(),
+            country: Some("中国".to_string()),
+            latitude: Some(39.9042),
+            longitude: Some(116.40739),
+            ..Default::default()
+        });
+        entities.push(entity);
+
+        let style = GeoStyle {
+            coords: true,
+            map_link: Some(MapProvider::Google),
+            ..Default::default()
+        };
+        assert_eq!(
+            format_text(&entities, false, false, &style),
+            "1.2.3.4 [中国 39.9042,116.4074] https://www.google.com/maps?q=39.9042,116.4074 "
+        );
+
+        let osm = GeoStyle {
+            map_link: Some(MapProvider::Osm),
+            ..Default::default()
+        };
+        let geo = entities.entities[0].geo_info.clone().unwrap();
+        assert_eq!(
+            map_link(&geo, &osm).as_deref(),
+            Some("https://www.openstreetmap.org/?mlat=39.9042&mlon=116.4074#map=10/39.9042/116.4074")
+        );
+
+        // Nothing to link without coordinates
+        let mut bare = geo;
+        bare.latitude = None;
+        assert_eq!(map_link(&bare, &osm), None);
+    }
+
+    #[test]
+    fn test_format_text_eol() {
+        let mut entities = Entities::new();
+        entities.push(Entity::plain(0, 6, "来自 ".to_string()));
+        let mut ip = Entity::ipv4(7, 14, "8.8.8.8".to_string());
+        ip.whois_info = Some(crate::enrichment::WhoisInfo {
+            netname: Some("GOGL".to_string()),
+            ..Default::default()
+        });
+        entities.push(ip);
+        entities.push(Entity::plain(14, 18, " via ".to_string()));
+        let mut domain = Entity::domain(18, 34, "cdn.jsdelivr.net".to_string());
+        domain.cdn_info = Some(crate::database::CdnProvider {
+            domain: "cdn.jsdelivr.net".to_string(),
+            provider: "jsDelivr".to_string(),
+            description: None,
+        });
+        entities.push(domain);
+        entities.push(Entity::plain(34, 35, " ".to_string()));
+
+        // 来自 is six bytes but four columns wide; the trailing space is dropped
+        let formatted = format_text_eol(&entities, false, false, &GeoStyle::default());
+        let text = "来自 8.8.8.8 via cdn.jsdelivr.net";
+        let padding = " ".repeat(EOL_COLUMN - (text.len() - 2));
+        assert_eq!(formatted, format!("{}{} | GOGL, jsDelivr", text, padding));
+
+        // Lines without annotations stay as they are
+        let mut entities = Entities::new();
+        entities.push(Entity::plain(0, 9, "no match ".to_string()));
+        assert_eq!(format_text_eol(&entities, false, false, &GeoStyle::default()), "no match ");
+    }
+
+    #[test]
+    fn test_format_short() {
+        let mut entities = Entities::new();
+        let mut ip = Entity::ipv4(0, 7, "8.8.8.8".to_string());
+        ip.geo_info = Some(GeoLocation {
+            ip: "8.8.8.8".parse::<IpAddr>().unwrap(),
+            country: Some("美国".to_string()),
+            isp: Some("谷歌公司".to_string()),
+            country_code: Some("US".to_string()),
+            ..Default::default()
+        });
+        ip.whois_info = Some(crate::enrichment::WhoisInfo {
+            netname: Some("GOGL".to_string()),
+            ..Default::default()
+        });
+        entities.push(ip);
+        entities.push(Entity::plain(7, 8, " ".to_string()));
+        entities.push(Entity::ipv4(8, 15, "1.2.3.4".to_string()));
+        entities.push(Entity::plain(15, 16, " ".to_string()));
+        let mut domain = Entity::domain(16, 32, "cdn.jsdelivr.net".to_string());
+        domain.cdn_info = Some(crate::database::CdnProvider {
+            domain: "cdn.jsdelivr.net".to_string(),
+            provider: "jsDelivr".to_string(),
+            description: None,
+        });
+        entities.push(domain);
+
+        // The address without a location and the whois info are left out
+        assert_eq!(format_short(&entities, &GeoStyle::default()), "美国 谷歌公司 jsDelivr");
+        let style = GeoStyle { flag: true, ..Default::default() };
+        assert_eq!(format_short(&entities, &style), "🇺🇸 美国 谷歌公司 jsDelivr");
+    }
+
+    #[test]
+    fn test_format_text_with_whois() {
+        let mut entities = Entities::new();
+        let mut entity = Entity::ipv4(0, 7, "8.8.8.8".to_string());
+        entity.whois_info = Some(crate::enrichment::WhoisInfo {
+            netname: Some("GOGL".to_string()),
+            org: Some("Google LLC".to_string()),
+            ..Default::default()
+        });
+        entities.push(entity);
+
+        assert_eq!(format_text(&entities, false, false, &GeoStyle::default()), "8.8.8.8 [GOGL Google LLC] ");
+    }
+
+    #[test]
+    fn test_tags() {
+        let mut entities = Entities::new();
+        let mut tagged = Entity::ipv4(0, 7, "1.2.3.4".to_string());
+        tagged.geo_info = Some(GeoLocation {
+            ip: "1.2.3.4".parse::<IpAddr>().unwrap(),
+            country: Some("美国".to_string()),
+            ..Default::default()
+        });
+        tagged.tags = vec!["AWS us-east-1".to_string(), "Spamhaus DROP".to_string()];
+        entities.push(tagged.clone());
+        entities.push(Entity::plain(7, 8, " ".to_string()));
+        let mut tag_only = Entity::ipv4(8, 15, "5.6.7.8".to_string());
+        tag_only.tags = vec!["Office".to_string()];
+        entities.push(tag_only);
+
+        assert_eq!(
+            format_text(&entities, false, false, &GeoStyle::default()),
+            "1.2.3.4 [美国] [AWS us-east-1] [Spamhaus DROP]  5.6.7.8 [Office] "
+        );
+        assert!(
+            format_text_eol(&entities, false, false, &GeoStyle::default())
+                .ends_with(" | 美国, AWS us-east-1, Spamhaus DROP, Office")
+        );
+        assert_eq!(entity_json(&tagged)["tags"], serde_json::json!(["AWS us-east-1", "Spamhaus DROP"]));
+        assert!(entity_json(&Entity::ipv4(0, 7, "5.6.7.8".to_string())).get("tags").is_none());
+    }
+
+    #[test]
+    fn test_tor_exit() {
+        let mut entities = Entities::new();
+        let mut entity = Entity::ipv4(0, 13, "185.220.101.1".to_string());
+        entity.is_tor = true;
+        entities.push(entity.clone());
+
+        assert_eq!(
+            format_text(&entities, false, false, &GeoStyle::default()),
+            "185.220.101.1 [Tor exit] "
+        );
+        assert_eq!(entity_json(&entity)["is_tor"], true);
+        // Other entities don't get the field at all
+        entity.is_tor = false;
+        assert!(entity_json(&entity).get("is_tor").is_none());
+    }
+
+    #[test]
+    fn test_rdns() {
+        let mut entities = Entities::new();
+        let mut entity = Entity::ipv4(0, 10, "13.32.1.1".to_string());
+        entity.rdns = Some(crate::enrichment::RdnsInfo {
+            hostname: "server-13-32-1-1.fra50.r.cloudfront.net".to_string(),
+            inferred_provider: Some("Amazon CloudFront".to_string()),
+        });
+        entities.push(entity.clone());
+
+        assert_eq!(
+            format_text(&entities, false, false, &GeoStyle::default()),
+            "13.32.1.1 [server-13-32-1-1.fra50.r.cloudfront.net (inferred: Amazon CloudFront)] "
+        );
+        assert_eq!(
+            entity_json(&entity)["rdns"],
+            serde_json::json!({
+                "hostname": "server-13-32-1-1.fra50.r.cloudfront.net",
+                "inferred_provider": "Amazon CloudFront",
+            })
+        );
+        // The CDN field is left to CDN rules
+        assert!(entity_json(&entity)["cdn_info"].is_null());
+        assert!(entity_json(&Entity::ipv4(0, 7, "5.6.7.8".to_string())).get("rdns").is_none());
+    }
+
+    #[test]
+    fn test_format_nali() {
+        let mut entities = Entities::new();
+        let mut ip = Entity::ipv4(0, 7, "1.2.3.4".to_string());
+        ip.geo_info = Some(GeoLocation {
+            ip: "1.2.3.4".parse::<IpAddr>().unwrap(),
+            country: Some("中国".to_string()),
+            isp: Some("电信".to_string()),
+            country_code: Some("CN".to_string()),
+            latitude: Some(39.9042),
+            longitude: Some(116.40739),
+            ..Default::default()
+        });
+        entities.push(ip);
+        entities.push(Entity::plain(7, 8, " ".to_string()));
+        let mut whois_only = Entity::ipv4(8, 15, "8.8.8.8".to_string());
+        whois_only.whois_info = Some(crate::enrichment::WhoisInfo {
+            netname: Some("GOGL".to_string()),
+            ..Default::default()
+        });
+        entities.push(whois_only);
+
+        assert_eq!(format_nali(&entities, false), "1.2.3.4 [中国 电信]  8.8.8.8");
+    }
+
     #[test]
     fn test_format_json() {
         let mut entities = Entities::new();
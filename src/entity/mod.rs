@@ -4,6 +4,7 @@
 //! and enriches them with geolocation/CDN information.
 
 pub mod parser;
+pub mod suffix;
 pub mod types;
 pub mod formatter;
 
@@ -1,13 +1,45 @@
 //! Entity parser - extracts entities from text
 
+use crate::entity::suffix;
 use crate::entity::types::{Entities, Entity, EntityType};
-use crate::regex::{find_ipv4, find_ipv6, find_domains};
+use crate::regex::{
+    DOMAIN_RE, find_bracketed_ipv6, find_domains, find_idn_domains, find_ipv4, find_ipv6,
+    find_macs, find_urls, find_versions,
+};
+use std::net::IpAddr;
+
+/// Options controlling entity extraction
+#[derive(Debug, Clone)]
+pub struct ParseOptions {
+    /// Only accept domains ending in a known public suffix
+    pub validate_tld: bool,
+    /// Keep only the first address of a comma-separated list, the client in
+    /// `X-Forwarded-For: client, proxy1, proxy2`
+    pub first_ip_only: bool,
+}
+
+impl Default for ParseOptions {
+    fn default() -> Self {
+        Self {
+            validate_tld: true,
+            first_ip_only: false,
+        }
+    }
+}
 
 /// Parse a line of text and extract all entities (IP addresses and domains)
 ///
 /// This function searches for IPv4, IPv6 addresses, and domain names in the input text.
 /// Overlapping entities are removed, keeping the first occurrence.
 ///
+/// Comma-separated lists such as `X-Forwarded-For: 1.2.3.4,5.6.7.8` yield
+/// every address, with or without spaces after the commas.
+///
+/// Ports are never part of an entity: `1.2.3.4:443` yields `1.2.3.4`, and the
+/// bracketed form `[2001:db8::1]:8080` yields the address inside the brackets.
+/// Unbracketed IPv6 followed by a port (`::1:8080`) is ambiguous and is read
+/// as a single address.
+///
 /// # Arguments
 ///
 /// * `text` - The input text to parse
@@ -30,20 +62,70 @@ use crate::regex::{find_ipv4, find_ipv6, find_domains};
 /// assert_eq!(entities.len(), 1);
 /// ```
 pub fn parse_line(text: &str) -> Entities {
+    parse_line_with(text, &ParseOptions::default())
+}
+
+/// Parse a line of text with explicit options
+///
+/// See [`parse_line`]. With `validate_tld` set, domain candidates such as
+/// `release.tar.gz` that do not end in a public suffix are skipped.
+pub fn parse_line_with(text: &str, options: &ParseOptions) -> Entities {
     let mut entities = Entities::new();
 
-    // Find all IPv4 addresses
+    // Find all URLs first: only their host is annotated, and nothing else
+    // inside the URL (paths, query strings) is treated as an entity
+    let mut claimed = non_routable_spans(text);
+    for (start, end, url) in find_urls(text) {
+        if claimed.iter().any(|c| start < c.1 && end > c.0) {
+            continue;
+        }
+        if let Some(entity) = url_host_entity(start, &url) {
+            entities.push(entity);
+        }
+        claimed.push((start, end));
+    }
+
+    // Bracketed IPv6 with an optional port: the address is exactly the
+    // bracket contents, so the IPv6 regex cannot run into the port
+    for (whole, (start, end, addr)) in find_bracketed_ipv6(text) {
+        let overlaps = claimed.iter().any(|c| whole.0 < c.1 && whole.1 > c.0);
+        let ip = addr.split('%').next().unwrap_or_default();
+        if !overlaps && ip.parse::<std::net::Ipv6Addr>().is_ok() {
+            entities.push(Entity::ipv6(start, end, addr));
+            claimed.push(whole);
+        }
+    }
+    let is_claimed = |start: usize, end: usize| claimed.iter().any(|c| start < c.1 && end > c.0);
+
+    // Find all IPv4 addresses, ignoring matches cut out of a longer number
     for (start, end, ipv4_text) in find_ipv4(text) {
-        entities.push(Entity::ipv4(start, end, ipv4_text));
+        let before = text[..start].chars().next_back();
+        let after = text[end..].chars().next();
+        let truncated = before.is_some_and(|c| c.is_ascii_alphanumeric())
+            || after.is_some_and(|c| c.is_ascii_digit());
+        if !truncated && !is_claimed(start, end) {
+            entities.push(Entity::ipv4(start, end, ipv4_text));
+        }
     }
 
-    // Find all IPv6 addresses
+    // Find all IPv6 addresses, keeping only those that parse
     for (start, end, ipv6_text) in find_ipv6(text) {
-        entities.push(Entity::ipv6(start, end, ipv6_text));
+        let Some(ipv6_text) = validate_ipv6(text, start, end, &ipv6_text) else {
+            continue;
+        };
+        let end = start + ipv6_text.len();
+        if !is_claimed(start, end) {
+            entities.push(Entity::ipv6(start, end, ipv6_text.to_string()));
+        }
     }
 
-    // Find all domains
-    for (start, end, domain_text) in find_domains(text) {
+    // Find all domains, including internationalized ones like 中国.cn
+    for (start, end, domain_text) in find_domains(text).into_iter().chain(find_idn_domains(text)) {
+        if is_claimed(start, end)
+            || (options.validate_tld && !suffix::current().is_valid_domain(&domain_text))
+        {
+            continue;
+        }
         // Skip if it's actually part of an IPv4 address
         // (domain regex might match some IP patterns)
         if !entities.entities.iter().any(|e| {
@@ -61,9 +143,128 @@ pub fn parse_line(text: &str) -> Entities {
     // Sort by position
     entities.sort_by_position();
 
+    if options.first_ip_only {
+        drop_proxy_addresses(text, &mut entities);
+    }
+
     entities
 }
 
+/// Remove the addresses after the first of each comma-separated list
+///
+/// An address continues a list when only a comma, spaces and the previous
+/// address's port or closing bracket stand between them.
+fn drop_proxy_addresses(text: &str, entities: &mut Entities) {
+    let mut previous_ip_end = None;
+    entities.entities.retain(|e| {
+        if !e.is_ip() {
+            return true;
+        }
+        let continues = previous_ip_end.is_some_and(|end| {
+            let between = text[end..e.location.0].trim_end_matches('[').trim_end();
+            let Some(before_comma) = between.strip_suffix(',') else {
+                return false;
+            };
+            let before_comma = before_comma.trim_end().trim_start_matches(']');
+            let port = before_comma.strip_prefix(':').unwrap_or(before_comma);
+            port.bytes().all(|b| b.is_ascii_digit())
+        });
+        previous_ip_end = Some(e.location.1);
+        !continues
+    });
+}
+
+/// Check an IPv6 candidate found at `start..end` of `text`
+///
+/// The regex also matches fragments such as `::Ba` in `Foo::Bar` or the first
+/// eight groups of `1:2:3:4:5:6:7:8:9`, and `::` on its own. Candidates cut
+/// out of a longer token are dropped, trailing punctuation is trimmed until
+/// the address parses, and a zone id is kept only on scoped addresses.
+fn validate_ipv6<'a>(text: &str, start: usize, end: usize, candidate: &'a str) -> Option<&'a str> {
+    let before = text[..start].chars().next_back();
+    let mut after = text[end..].chars();
+    let continues = match after.next() {
+        Some(':') => after.next().is_some_and(|c| c == ':' || c.is_ascii_hexdigit()),
+        Some(c) => c.is_ascii_alphanumeric() || c == '_',
+        None => false,
+    };
+    if continues || before.is_some_and(|c| c.is_ascii_alphanumeric() || c == '_') {
+        return None;
+    }
+
+    let mut candidate = candidate;
+    let (addr, zone) = loop {
+        let (addr, zone) = candidate.split_once('%').unwrap_or((candidate, ""));
+        if let Ok(addr) = addr.parse::<std::net::Ipv6Addr>() {
+            break (addr, zone);
+        }
+        candidate = candidate.strip_suffix(['.', ',', ')', ':'])?;
+    };
+    if addr.is_unspecified() && candidate == "::" {
+        return None;
+    }
+    if !zone.is_empty() && !is_scoped(addr) {
+        candidate = &candidate[..candidate.len() - zone.len() - 1];
+    }
+    Some(candidate)
+}
+
+/// Check whether a zone id can qualify an IPv6 address: link-local unicast
+/// (`fe80::/10`) and multicast below global scope
+fn is_scoped(ip: std::net::Ipv6Addr) -> bool {
+    ip.is_unicast_link_local() || (ip.is_multicast() && ip.segments()[0] & 0x000f < 0xe)
+}
+
+/// Spans of tokens that look like addresses but are not routable
+///
+/// MAC addresses (which the IPv6 regex can partially match) and version
+/// strings (which the IPv4 regex can match) are claimed before any entity
+/// is extracted.
+fn non_routable_spans(text: &str) -> Vec<(usize, usize)> {
+    find_macs(text)
+        .into_iter()
+        .chain(find_versions(text))
+        .map(|(start, end, _)| (start, end))
+        .collect()
+}
+
+/// Extract the host of a URL found at `offset` as an IP or domain entity
+///
+/// Handles userinfo (`user:pass@host`), ports and bracketed IPv6 hosts.
+/// Returns `None` when the host is neither an IP address nor a domain.
+fn url_host_entity(offset: usize, url: &str) -> Option<Entity> {
+    let authority_start = url.find("://")? + 3;
+    let rest = &url[authority_start..];
+    let authority_len = rest.find(['/', '?', '#']).unwrap_or(rest.len());
+    let authority = &rest[..authority_len];
+
+    // Skip userinfo
+    let host_start = authority.rfind('@').map_or(0, |i| i + 1);
+    let host_part = &authority[host_start..];
+
+    let (host_offset, host) = if let Some(bracketed) = host_part.strip_prefix('[') {
+        (1, &bracketed[..bracketed.find(']')?])
+    } else {
+        let host = &host_part[..host_part.find(':').unwrap_or(host_part.len())];
+        (0, host.trim_end_matches('.'))
+    };
+    if host.is_empty() {
+        return None;
+    }
+
+    let start = offset + authority_start + host_start + host_offset;
+    let end = start + host.len();
+
+    match host.parse::<IpAddr>() {
+        Ok(IpAddr::V4(_)) => Some(Entity::ipv4(start, end, host.to_string())),
+        Ok(IpAddr::V6(_)) => Some(Entity::ipv6(start, end, host.to_string())),
+        Err(_) => DOMAIN_RE
+            .find(host)
+            .filter(|m| m.start() == 0 && m.end() == host.len())
+            .map(|_| Entity::domain(start, end, host.to_string())),
+    }
+}
+
 /// Parse multiple lines of text
 ///
 /// Convenience function that calls `parse_line` for each line.
@@ -83,6 +284,10 @@ pub fn parse_lines(lines: &[String]) -> Vec<Entities> {
 ///
 /// This fills in the gaps between extracted entities with plain text segments,
 /// so that the original text can be reconstructed with enriched information.
+/// Entity locations are byte offsets; one that falls inside a multibyte
+/// character or past the end of `text` is moved back to the previous
+/// character boundary, and an entity overlapping the one before it keeps
+/// its place without repeating the text between them.
 pub fn build_complete_entities(text: &str, mut entities: Entities) -> Entities {
     if entities.is_empty() {
         // No entities found, return the whole text as plain
@@ -96,8 +301,10 @@ pub fn build_complete_entities(text: &str, mut entities: Entities) -> Entities {
     let mut complete = Entities::new();
     let mut last_pos = 0;
 
-    for entity in entities.entities {
-        let (start, end) = entity.location;
+    for mut entity in entities.entities {
+        let start = char_boundary(text, entity.location.0);
+        let end = char_boundary(text, entity.location.1).max(start);
+        entity.location = (start, end);
 
         // Add plain text before this entity
         if start > last_pos {
@@ -107,7 +314,7 @@ pub fn build_complete_entities(text: &str, mut entities: Entities) -> Entities {
 
         // Add the entity
         complete.push(entity);
-        last_pos = end;
+        last_pos = last_pos.max(end);
     }
 
     // Add remaining plain text
@@ -123,6 +330,15 @@ pub fn build_complete_entities(text: &str, mut entities: Entities) -> Entities {
     complete
 }
 
+/// The last character boundary of `text` at or before byte offset `pos`
+fn char_boundary(text: &str, pos: usize) -> usize {
+    let mut pos = pos.min(text.len());
+    while !text.is_char_boundary(pos) {
+        pos -= 1;
+    }
+    pos
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -151,6 +367,238 @@ mod tests {
         assert!(!domains.is_empty());
     }
 
+    #[test]
+    fn test_parse_url_hosts() {
+        let text = "GET https://1.2.3.4:8443/path/10.0.0.1 then https://cdn.example.com/app.js";
+        let entities = parse_line(text);
+
+        assert_eq!(entities.len(), 2);
+        assert_eq!(entities.entities[0].entity_type, EntityType::IPv4);
+        assert_eq!(entities.entities[0].text, "1.2.3.4");
+        assert_eq!(entities.entities[1].entity_type, EntityType::Domain);
+        assert_eq!(entities.entities[1].text, "cdn.example.com");
+
+        let (start, end) = entities.entities[1].location;
+        assert_eq!(&text[start..end], "cdn.example.com");
+    }
+
+    #[test]
+    fn test_parse_url_ipv6_and_userinfo() {
+        let text = "ftp://user:secret@[2001:db8::1]:21/file and http://localhost/";
+        let entities = parse_line(text);
+
+        assert_eq!(entities.len(), 1);
+        assert_eq!(entities.entities[0].entity_type, EntityType::IPv6);
+        assert_eq!(entities.entities[0].text, "2001:db8::1");
+
+        let complete = build_complete_entities(text, entities);
+        let rebuilt: String = complete.entities.iter().map(|e| e.text.as_str()).collect();
+        assert_eq!(rebuilt, text);
+    }
+
+    fn parsed(text: &str) -> Vec<(EntityType, String)> {
+        parse_line(text)
+            .entities
+            .into_iter()
+            .map(|e| (e.entity_type, e.text))
+            .collect()
+    }
+
+    #[test]
+    fn test_parse_ip_port() {
+        assert_eq!(
+            parsed("10.0.0.1:8080->10.0.0.2:80"),
+            vec![
+                (EntityType::IPv4, "10.0.0.1".to_string()),
+                (EntityType::IPv4, "10.0.0.2".to_string()),
+            ]
+        );
+        assert_eq!(
+            parsed("[2001:db8::1]:8080 [::ffff:1.2.3.4]:443 [fe80::1%eth0]:53"),
+            vec![
+                (EntityType::IPv6, "2001:db8::1".to_string()),
+                (EntityType::IPv6, "::ffff:1.2.3.4".to_string()),
+                (EntityType::IPv6, "fe80::1%eth0".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_nginx_log() {
+        let access = r#"2001:db8::7 - - [10/Oct/2023:13:55:36 +0000] "GET / HTTP/1.1" 200 612 "-" "curl/8.0""#;
+        assert_eq!(parsed(access), vec![(EntityType::IPv6, "2001:db8::7".to_string())]);
+
+        let error = r#"connect() failed (111) while connecting to upstream, client: 203.0.113.9, upstream: "http://[2001:db8::2]:9000/", host: "[2001:db8::3]:443""#;
+        assert_eq!(
+            parsed(error),
+            vec![
+                (EntityType::IPv4, "203.0.113.9".to_string()),
+                (EntityType::IPv6, "2001:db8::2".to_string()),
+                (EntityType::IPv6, "2001:db8::3".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_haproxy_log() {
+        let text = "haproxy[14389]: 10.0.1.2:33317 [06/Feb/2009:12:14:14] fe be/srv1 0/0/5007 212 -- 1/1/1/1/0 0/0";
+        assert_eq!(parsed(text), vec![(EntityType::IPv4, "10.0.1.2".to_string())]);
+
+        let text = "haproxy[14389]: [2001:db8::5]:40022 [06/Feb/2009:12:14:14] fe be/srv1";
+        assert_eq!(parsed(text), vec![(EntityType::IPv6, "2001:db8::5".to_string())]);
+    }
+
+    #[test]
+    fn test_parse_forwarded_for() {
+        let ips = |text: &str, first_ip_only: bool| -> Vec<String> {
+            let options = ParseOptions {
+                first_ip_only,
+                ..Default::default()
+            };
+            parse_line_with(text, &options)
+                .entities
+                .into_iter()
+                .map(|e| e.text)
+                .collect()
+        };
+
+        let text = "X-Forwarded-For: 203.0.113.7, 10.0.0.2,10.0.0.3 via 198.51.100.1";
+        assert_eq!(ips(text, false), ["203.0.113.7", "10.0.0.2", "10.0.0.3", "198.51.100.1"]);
+        assert_eq!(ips(text, true), ["203.0.113.7", "198.51.100.1"]);
+
+        let text = "xff=2001:db8::1,2001:db8::2 upstream=1.2.3.4:5555, [2001:db8::3]:443";
+        assert_eq!(ips(text, false), ["2001:db8::1", "2001:db8::2", "1.2.3.4", "2001:db8::3"]);
+        assert_eq!(ips(text, true), ["2001:db8::1", "1.2.3.4"]);
+
+        // Domains neither end a list nor count as its first entry
+        assert_eq!(ips("proxy.example.com, 1.2.3.4, 5.6.7.8", true), ["proxy.example.com", "1.2.3.4"]);
+        assert_eq!(ips("1.2.3.4 and 5.6.7.8", true), ["1.2.3.4", "5.6.7.8"]);
+    }
+
+    #[test]
+    fn test_parse_zone_id() {
+        let text = "ping6 fe80::1%eth0 from fe80::20c:29ff:fe3a:4b5c%enp0s31f6, ff02::1%eth0.100 2001:db8::1%eth0";
+        assert_eq!(
+            parsed(text),
+            vec![
+                (EntityType::IPv6, "fe80::1%eth0".to_string()),
+                (EntityType::IPv6, "fe80::20c:29ff:fe3a:4b5c%enp0s31f6".to_string()),
+                (EntityType::IPv6, "ff02::1%eth0.100".to_string()),
+                (EntityType::IPv6, "2001:db8::1".to_string()),
+            ]
+        );
+
+        // The zone stays in the text, untouched
+        let complete = build_complete_entities(text, parse_line(text));
+        let rebuilt: String = complete.entities.iter().map(|e| e.text.as_str()).collect();
+        assert_eq!(rebuilt, text);
+    }
+
+    #[test]
+    fn test_parse_ipv6_false_matches() {
+        let ipv6 = |text: &str| -> Vec<String> {
+            parse_line(text)
+                .entities
+                .into_iter()
+                .filter(|e| e.entity_type == EntityType::IPv6)
+                .map(|e| e.text)
+                .collect()
+        };
+
+        // journalctl
+        let lines = [
+            "Oct 11 22:14:15 host app[9]: panic at std::vector::at, frame Foo::Bar::baz()",
+            "Oct 11 22:14:15 host kernel: IPv6: ADDRCONF(NETDEV_CHANGE): eth0: link becomes ready",
+            "Oct 11 22:14:15 host sshd[7]: Accepted key for root from 2001:db8::7 port 22, ok",
+            "Oct 11 22:14:15 host dhcpcd[812]: eth0: fe80::1c2d:3eff:fe4f:5a6b: router is reachable",
+            "Oct 11 22:14:15 host dhcpcd[812]: eth0: adding address 2001:db8:1:2:3:4:5:6/64.",
+        ];
+        let found: Vec<_> = lines.iter().flat_map(|line| ipv6(line)).collect();
+        assert_eq!(found, ["2001:db8::7", "fe80::1c2d:3eff:fe4f:5a6b", "2001:db8:1:2:3:4:5:6"]);
+
+        // dig
+        assert_eq!(
+            ipv6("example.com.\t300\tIN\tAAAA\t2606:2800:220:1:248:1893:25c8:1946"),
+            ["2606:2800:220:1:248:1893:25c8:1946"]
+        );
+        assert_eq!(
+            ipv6(";; SERVER: 2001:4860:4860::8888#53(2001:4860:4860::8888) (UDP)"),
+            ["2001:4860:4860::8888", "2001:4860:4860::8888"]
+        );
+
+        // Fragments of longer tokens, and `::` on its own
+        assert!(ipv6("1:2:3:4:5:6:7:8:9 dead::beef:: abcde::1 f :: Int netstat :::22 fe80:").is_empty());
+        assert_eq!(ipv6("fe80::1:2:3:4:5 (2001:db8::1), cafe::."), ["fe80::1:2:3:4:5", "2001:db8::1", "cafe::"]);
+    }
+
+    #[test]
+    fn test_parse_idn_domains() {
+        assert_eq!(
+            parsed("resolved 中国.cn and www.例子.com via xn--fiqs8s.cn, not 说明.txt"),
+            vec![
+                (EntityType::Domain, "中国.cn".to_string()),
+                (EntityType::Domain, "www.例子.com".to_string()),
+                (EntityType::Domain, "xn--fiqs8s.cn".to_string()),
+            ]
+        );
+        // A Unicode top-level domain, in both forms
+        assert_eq!(parsed("例子.中国").len(), 1);
+        assert_eq!(parsed("example.xn--fiqs8s").len(), 1);
+        // Chinese text around an ASCII domain does not swallow it
+        assert_eq!(
+            parsed("请访问example.com获取"),
+            vec![(EntityType::Domain, "example.com".to_string())]
+        );
+    }
+
+    #[test]
+    fn test_parse_tld_validation() {
+        let text = "fetched release.tar.gz build.log from mirror.example.com";
+        assert_eq!(
+            parsed(text),
+            vec![(EntityType::Domain, "mirror.example.com".to_string())]
+        );
+
+        let options = ParseOptions {
+            validate_tld: false,
+            ..Default::default()
+        };
+        assert_eq!(parse_line_with(text, &options).domains().len(), 3);
+    }
+
+    #[test]
+    fn test_parse_ifconfig_output() {
+        let lines = [
+            "eth0: flags=4163<UP,BROADCAST,RUNNING,MULTICAST>  mtu 1500",
+            "        inet 192.168.1.10  netmask 255.255.255.0  broadcast 192.168.1.255",
+            "        inet6 fe80::20c:29ff:fe3a:4b5c  prefixlen 64  scopeid 0x20<link>",
+            "        ether 00:0c:29:3a:4b:5c  txqueuelen 1000  (Ethernet)",
+            "    link/infiniband 80:00:02:08:fe:80:00:00:00:00:00:00:00:02:c9:03:00:0a:fb:52 brd",
+        ];
+        let found: Vec<_> = lines.iter().flat_map(|line| parsed(line)).collect();
+        assert_eq!(
+            found,
+            vec![
+                (EntityType::IPv4, "192.168.1.10".to_string()),
+                (EntityType::IPv4, "255.255.255.0".to_string()),
+                (EntityType::IPv4, "192.168.1.255".to_string()),
+                (EntityType::IPv6, "fe80::20c:29ff:fe3a:4b5c".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_dmesg_output() {
+        let lines = [
+            "[    0.000000] Linux version 5.15.0-91-generic (buildd@lcy02-amd64-045) gcc 11.4.0",
+            "[    2.345678] e1000 0000:00:03.0 eth0: (PCI:33MHz:32-bit) 08:00:27:ab:cd:ef",
+            "[    3.100000] mlx4_en: eth1: EUI-64 02:42:ac:ff:fe:11:00:02",
+            "[    4.200000] agent curl/7.68.0.1 (v1.2.3.4) fetched 123.45.67.890 from 10.0.0.1",
+        ];
+        let found: Vec<_> = lines.iter().flat_map(|line| parsed(line)).collect();
+        assert_eq!(found, vec![(EntityType::IPv4, "10.0.0.1".to_string())]);
+    }
+
     #[test]
     fn test_build_complete_entities() {
         let text = "Server: 1.2.3.4 ok";
@@ -164,6 +612,61 @@ mod tests {
         assert_eq!(complete.entities[2].text, " ok");
     }
 
+    #[test]
+    fn test_parse_mixed_chinese_lines() {
+        assert_eq!(
+            parsed("服务器2001:db8::1在线，来自8.8.8.8的请求"),
+            vec![
+                (EntityType::IPv6, "2001:db8::1".to_string()),
+                (EntityType::IPv4, "8.8.8.8".to_string()),
+            ]
+        );
+        // Zone ids are ASCII, so the text after one is not taken in
+        assert_eq!(
+            parsed("地址fe80::1%eth0（链路本地）"),
+            vec![(EntityType::IPv6, "fe80::1%eth0".to_string())]
+        );
+        // Domains keep their case
+        assert_eq!(
+            parsed("解析“CDN.Example.COM”得到【1.2.3.4】"),
+            vec![
+                (EntityType::Domain, "CDN.Example.COM".to_string()),
+                (EntityType::IPv4, "1.2.3.4".to_string()),
+            ]
+        );
+
+        for text in ["用户登录 IP：1.2.3.4，位置未知", "回源到 cdn.example.com；耗时 3ms", "日志：2001:db8::8888。"] {
+            let complete = build_complete_entities(text, parse_line(text));
+            let rebuilt: String = complete.entities.iter().map(|e| e.text.as_str()).collect();
+            assert_eq!(rebuilt, text);
+        }
+    }
+
+    #[test]
+    fn test_build_complete_entities_char_boundaries() {
+        // Offsets inside 中 (bytes 0..3) and past the end snap back
+        let text = "中1.2.3.4";
+        let mut entities = Entities::new();
+        entities.push(Entity::ipv4(1, 20, "1.2.3.4".to_string()));
+        let complete = build_complete_entities(text, entities);
+        assert_eq!(complete.len(), 1);
+        assert_eq!(complete.entities[0].location, (0, text.len()));
+
+        // Overlapping entities do not repeat the text between them
+        let text = "来自 1.2.3.4 的请求";
+        let mut entities = Entities::new();
+        entities.push(Entity::ipv4(7, 14, "1.2.3.4".to_string()));
+        entities.push(Entity::ipv4(9, 12, "2.3".to_string()));
+        let complete = build_complete_entities(text, entities);
+        let plain: Vec<_> = complete
+            .entities
+            .iter()
+            .filter(|e| e.entity_type == EntityType::Plain)
+            .map(|e| e.text.as_str())
+            .collect();
+        assert_eq!(plain, ["来自 ", " 的请求"]);
+    }
+
     #[test]
     fn test_no_entities() {
         let text = "No IPs or domains here";
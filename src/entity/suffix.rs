@@ -0,0 +1,173 @@
+//! Public suffix list used to validate domain entities
+//!
+//! The domain regex also matches file names and version strings such as
+//! `release.tar.gz` or `v1.2.10`. Only names ending in a known public suffix
+//! (plus at least one more label) are treated as domains.
+//!
+//! A snapshot of <https://publicsuffix.org/list/> is embedded in the binary.
+//! A newer copy downloaded with `nali-rs update psl` replaces it at startup.
+
+use crate::error::{NaliError, Result};
+use crate::utils::idn;
+use once_cell::sync::{Lazy, OnceCell};
+use std::collections::HashSet;
+use std::fs;
+use std::path::Path;
+
+/// Embedded snapshot of the public suffix list
+const EMBEDDED_LIST: &str = include_str!("public_suffix_list.dat");
+
+static EMBEDDED: Lazy<PublicSuffixList> = Lazy::new(|| PublicSuffixList::parse(EMBEDDED_LIST));
+
+static INSTALLED: OnceCell<PublicSuffixList> = OnceCell::new();
+
+/// Parsed public suffix rules
+#[derive(Debug, Default)]
+pub struct PublicSuffixList {
+    /// Plain rules, e.g. `com`, `co.uk`
+    rules: HashSet<String>,
+    /// Wildcard rules without the leading `*.`, e.g. `ck` for `*.ck`
+    wildcards: HashSet<String>,
+    /// Exception rules without the leading `!`, e.g. `www.ck`
+    exceptions: HashSet<String>,
+}
+
+impl PublicSuffixList {
+    /// Parse the public suffix list format
+    pub fn parse(data: &str) -> Self {
+        let mut list = Self::default();
+
+        for line in data.lines() {
+            // Rules end at the first whitespace; comments start with `//`
+            let Some(rule) = line.split_whitespace().next() else {
+                continue;
+            };
+            if rule.starts_with("//") {
+                continue;
+            }
+
+            let rule = rule.to_lowercase();
+            if let Some(exception) = rule.strip_prefix('!') {
+                list.exceptions.insert(exception.to_string());
+            } else if let Some(wildcard) = rule.strip_prefix("*.") {
+                list.wildcards.insert(wildcard.to_string());
+            } else {
+                list.rules.insert(rule);
+            }
+        }
+
+        list
+    }
+
+    /// Load a list from disk
+    pub fn load_file(path: &Path) -> Result<Self> {
+        let data = fs::read_to_string(path)?;
+        let list = Self::parse(&data);
+        if list.is_empty() {
+            return Err(NaliError::parse(format!(
+                "No rules found in public suffix list: {}",
+                path.display()
+            )));
+        }
+        Ok(list)
+    }
+
+    /// The embedded snapshot
+    pub fn embedded() -> &'static Self {
+        &EMBEDDED
+    }
+
+    /// Number of rules in the list
+    pub fn len(&self) -> usize {
+        self.rules.len() + self.wildcards.len() + self.exceptions.len()
+    }
+
+    /// Check whether the list has no rules
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Number of labels in the public suffix of `domain`
+    ///
+    /// Returns `None` if no rule matches; the implicit `*` default rule of
+    /// the specification is deliberately not applied.
+    pub fn suffix_labels(&self, domain: &str) -> Option<usize> {
+        // The list spells internationalized suffixes in Unicode
+        let domain = domain.trim_end_matches('.');
+        let domain = if domain.contains("xn--") {
+            idn::to_unicode(domain)
+        } else {
+            domain.to_lowercase()
+        };
+        let labels: Vec<&str> = domain.split('.').collect();
+        let n = labels.len();
+
+        // Longest match wins, so walk from the full name towards the TLD
+        for i in 0..n {
+            let suffix = labels[i..].join(".");
+            if self.exceptions.contains(&suffix) {
+                return Some(n - i - 1);
+            }
+            if self.rules.contains(&suffix) {
+                return Some(n - i);
+            }
+            if i + 1 < n && self.wildcards.contains(&labels[i + 1..].join(".")) {
+                return Some(n - i);
+            }
+        }
+
+        None
+    }
+
+    /// Check whether `domain` is a name under a known public suffix
+    pub fn is_valid_domain(&self, domain: &str) -> bool {
+        let labels = domain.trim_end_matches('.').split('.').count();
+        self.suffix_labels(domain)
+            .is_some_and(|suffix| suffix > 0 && suffix < labels)
+    }
+}
+
+/// Replace the embedded snapshot for the rest of the process
+///
+/// Returns `false` if a list was already installed.
+pub fn install(list: PublicSuffixList) -> bool {
+    INSTALLED.set(list).is_ok()
+}
+
+/// The installed list, or the embedded snapshot
+pub fn current() -> &'static PublicSuffixList {
+    INSTALLED.get().unwrap_or_else(|| PublicSuffixList::embedded())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_embedded_list() {
+        let list = PublicSuffixList::embedded();
+        assert!(!list.is_empty());
+
+        assert!(list.is_valid_domain("example.com"));
+        assert!(list.is_valid_domain("cdn.example.co.uk"));
+        assert!(list.is_valid_domain("WWW.Example.ORG"));
+
+        assert!(!list.is_valid_domain("release.tar.gz"));
+        assert!(!list.is_valid_domain("v1.2.10"));
+        assert!(!list.is_valid_domain("co.uk"));
+        assert!(!list.is_valid_domain("com"));
+    }
+
+    #[test]
+    fn test_wildcard_and_exception_rules() {
+        let list = PublicSuffixList::parse("// comment\ncom\n*.ck\n!www.ck\n");
+        assert_eq!(list.suffix_labels("a.b.ck"), Some(2));
+        assert_eq!(list.suffix_labels("www.ck"), Some(1));
+        assert_eq!(list.suffix_labels("foo.com"), Some(1));
+        assert_eq!(list.suffix_labels("foo.invalid"), None);
+
+        assert!(list.is_valid_domain("a.b.ck"));
+        assert!(!list.is_valid_domain("b.ck"));
+        assert!(list.is_valid_domain("www.ck"));
+    }
+}
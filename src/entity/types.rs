@@ -1,6 +1,7 @@
 //! Entity types and structures
 
-use crate::database::{GeoLocation, CdnProvider};
+use crate::database::{GeoLocation, CdnProvider, QueryMeta};
+use crate::enrichment::{RdnsInfo, WhoisInfo};
 use std::net::IpAddr;
 
 /// Entity type classification
@@ -34,8 +35,23 @@ pub struct Entity {
     /// CDN provider information (for domain entities)
     pub cdn_info: Option<CdnProvider>,
 
+    /// Registration data from RDAP/whois (for IP entities, when enabled)
+    pub whois_info: Option<WhoisInfo>,
+
+    /// PTR name and the provider inferred from it (for IP entities, when enabled)
+    pub rdns: Option<RdnsInfo>,
+
+    /// Whether the IP is a known Tor exit
+    pub is_tor: bool,
+
+    /// Tags of the IP sets the IP is in
+    pub tags: Vec<String>,
+
     /// Source database name
     pub source: Option<String>,
+
+    /// Database version and lookup time (for JSON output)
+    pub meta: Option<QueryMeta>,
 }
 
 impl Entity {
@@ -47,7 +63,12 @@ impl Entity {
             text,
             geo_info: None,
             cdn_info: None,
+            whois_info: None,
+            rdns: None,
+            is_tor: false,
+            tags: Vec::new(),
             source: None,
+            meta: None,
         }
     }
 
@@ -59,7 +80,12 @@ impl Entity {
             text,
             geo_info: None,
             cdn_info: None,
+            whois_info: None,
+            rdns: None,
+            is_tor: false,
+            tags: Vec::new(),
             source: None,
+            meta: None,
         }
     }
 
@@ -71,7 +97,12 @@ impl Entity {
             text,
             geo_info: None,
             cdn_info: None,
+            whois_info: None,
+            rdns: None,
+            is_tor: false,
+            tags: Vec::new(),
             source: None,
+            meta: None,
         }
     }
 
@@ -83,7 +114,12 @@ impl Entity {
             text,
             geo_info: None,
             cdn_info: None,
+            whois_info: None,
+            rdns: None,
+            is_tor: false,
+            tags: Vec::new(),
             source: None,
+            meta: None,
         }
     }
 
@@ -98,9 +134,12 @@ impl Entity {
     }
 
     /// Get parsed IP address if this is an IP entity
+    ///
+    /// The zone id of a scoped IPv6 address (`fe80::1%eth0`) is not part of
+    /// the address and is dropped.
     pub fn as_ip(&self) -> Option<IpAddr> {
         if self.is_ip() {
-            self.text.parse().ok()
+            self.text.split('%').next()?.parse().ok()
         } else {
             None
         }
@@ -209,6 +248,9 @@ mod tests {
         let ip = entity.as_ip();
         assert!(ip.is_some());
         assert_eq!(ip.unwrap().to_string(), "192.168.1.1");
+
+        let scoped = Entity::ipv6(0, 12, "fe80::1%eth0".to_string());
+        assert_eq!(scoped.as_ip().unwrap().to_string(), "fe80::1");
     }
 
     #[test]
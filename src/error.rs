@@ -1,6 +1,7 @@
 //! Error types for nali-rs
 //!
 //! This module defines custom error types using thiserror for better error handling.
+//! The kind of error in front of each message is translated, see [`crate::i18n`].
 
 use thiserror::Error;
 
@@ -8,67 +9,83 @@ use thiserror::Error;
 #[derive(Error, Debug)]
 pub enum NaliError {
     /// Database not found
-    #[error("Database not found: {0}")]
+    #[error("{}: {0}", crate::i18n::tr("Database not found"))]
     DatabaseNotFound(String),
 
     /// Invalid IP address
-    #[error("Invalid IP address: {0}")]
+    #[error("{}: {0}", crate::i18n::tr("Invalid IP address"))]
     InvalidIp(String),
 
     /// Invalid domain name
-    #[error("Invalid domain: {0}")]
+    #[error("{}: {0}", crate::i18n::tr("Invalid domain"))]
     InvalidDomain(String),
 
     /// Database parsing error
-    #[error("Database parse error: {0}")]
+    #[error("{}: {0}", crate::i18n::tr("Database parse error"))]
     ParseError(String),
 
+    /// Database file doesn't have the layout its format requires
+    #[error("{}: {0}", crate::i18n::tr("Invalid database format"))]
+    InvalidFormat(String),
+
     /// Database not loaded
-    #[error("Database not loaded: {0}")]
+    #[error("{}: {0}", crate::i18n::tr("Database not loaded"))]
     DatabaseNotLoaded(String),
 
+    /// Database still being downloaded in the background
+    #[error("{}: {0}", crate::i18n::tr("Database not ready yet"))]
+    DatabasePending(String),
+
     /// Database corrupted
-    #[error("Database corrupted: {0}")]
+    #[error("{}: {0}", crate::i18n::tr("Database corrupted"))]
     DatabaseCorrupted(String),
 
     /// Configuration error
-    #[error("Configuration error: {0}")]
+    #[error("{}: {0}", crate::i18n::tr("Configuration error"))]
     ConfigError(String),
 
     /// Network error
-    #[error("Network error: {0}")]
+    #[error("{}: {0}", crate::i18n::tr("Network error"))]
     NetworkError(String),
 
     /// Download error
-    #[error("Download failed: {0}")]
+    #[error("{}: {0}", crate::i18n::tr("Download failed"))]
     DownloadError(String),
 
     /// File I/O error
-    #[error("File I/O error: {0}")]
+    #[error("{}: {0}", crate::i18n::tr("File I/O error"))]
     IoError(#[from] std::io::Error),
 
     /// Encoding error
-    #[error("Encoding conversion error: {0}")]
+    #[error("{}: {0}", crate::i18n::tr("Encoding conversion error"))]
     EncodingError(String),
 
     /// Regex error
-    #[error("Regex error: {0}")]
+    #[error("{}: {0}", crate::i18n::tr("Regex error"))]
     RegexError(#[from] regex::Error),
 
     /// YAML parsing error
-    #[error("YAML parse error: {0}")]
+    #[error("{}: {0}", crate::i18n::tr("YAML parse error"))]
     YamlError(String),
 
+    /// TOML parsing error
+    #[error("{}: {0}", crate::i18n::tr("TOML parse error"))]
+    TomlError(String),
+
     /// JSON parsing error
-    #[error("JSON parse error: {0}")]
+    #[error("{}: {0}", crate::i18n::tr("JSON parse error"))]
     JsonError(#[from] serde_json::Error),
 
+    /// Query could not be resolved (used by strict mode)
+    #[error("{}: {0}", crate::i18n::tr("No result for"))]
+    Unresolved(String),
+
     /// Index out of bounds
-    #[error("Index out of bounds: offset={0}, size={1}")]
+    #[error("{}: offset={0}, size={1}", crate::i18n::tr("Index out of bounds"))]
     IndexOutOfBounds(usize, usize),
 
     /// Other error
-    #[error("Other error: {0}")]
+    #[error("{}: {0}", crate::i18n::tr("Other error"))]
     Other(String),
 }
 
@@ -81,6 +98,11 @@ impl NaliError {
         NaliError::ParseError(msg.into())
     }
 
+    /// Create an invalid database format error
+    pub fn format<S: Into<String>>(msg: S) -> Self {
+        NaliError::InvalidFormat(msg.into())
+    }
+
     /// Create a config error
     pub fn config<S: Into<String>>(msg: S) -> Self {
         NaliError::ConfigError(msg.into())
@@ -95,6 +117,20 @@ impl NaliError {
     pub fn encoding<S: Into<String>>(msg: S) -> Self {
         NaliError::EncodingError(msg.into())
     }
+
+    /// Check whether the error means a database file's contents are broken
+    ///
+    /// Only errors raised while decoding database bytes count: missing files,
+    /// read failures, permissions, unsupported formats and network failures
+    /// don't, so they never get a file quarantined.
+    pub fn is_corruption(&self) -> bool {
+        matches!(
+            self,
+            NaliError::InvalidFormat(_)
+                | NaliError::DatabaseCorrupted(_)
+                | NaliError::IndexOutOfBounds(..)
+        )
+    }
 }
 
 /// Convert from anyhow::Error
@@ -107,6 +143,6 @@ impl From<anyhow::Error> for NaliError {
 /// Convert from TryFromSliceError
 impl From<std::array::TryFromSliceError> for NaliError {
     fn from(err: std::array::TryFromSliceError) -> Self {
-        NaliError::ParseError(format!("Failed to convert byte slice: {}", err))
+        NaliError::InvalidFormat(format!("Failed to convert byte slice: {}", err))
     }
 }
@@ -0,0 +1,225 @@
+//! C interface to the lookup core
+//!
+//! Built with the `nali-ffi` feature, so C, Go or Python programs can look up
+//! addresses in-process instead of spawning `nali-rs`. The declarations are
+//! in `include/nali.h`; build the shared library with:
+//!
+//! ```bash
+//! cargo rustc --release --lib --features nali-ffi --crate-type cdylib
+//! ```
+//!
+//! Call `nali_init` once, then `nali_lookup_ip` and `nali_lookup_cdn` from
+//! any thread. Both return a JSON string owned by the library: the location
+//! or CDN provider, `null` when there is none, or `{"error": "..."}`. Pass it
+//! to `nali_free` when done.
+
+use crate::config::AppConfig;
+use crate::database::DatabaseManager;
+use crate::error::{NaliError, Result};
+use serde::Serialize;
+use std::ffi::{CStr, CString, c_char, c_int};
+use std::net::IpAddr;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use tokio::runtime::Runtime;
+
+/// Databases and the runtime their lookups run on
+struct Engine {
+    runtime: Runtime,
+    manager: DatabaseManager,
+}
+
+/// Engine set up by the last successful `nali_init`
+static ENGINE: Mutex<Option<Arc<Engine>>> = Mutex::new(None);
+
+/// Set up the engine from the configuration at `config_path`
+///
+/// A null `config_path` loads the default configuration, like the CLI.
+/// Databases are loaded, and downloaded if missing, on first use. Returns 0
+/// on success and -1 if the configuration cannot be loaded; calling it again
+/// replaces the configuration.
+///
+/// # Safety
+///
+/// `config_path` must be null or point to a NUL-terminated string.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn nali_init(config_path: *const c_char) -> c_int {
+    // SAFETY: guaranteed by the caller
+    let result = unsafe { optional_str(config_path) }.and_then(|path| init(path.map(Path::new)));
+    match result {
+        Ok(()) => 0,
+        Err(e) => {
+            tracing::error!("nali_init failed: {}", e);
+            -1
+        }
+    }
+}
+
+/// Look up the location of the IP address `ip`
+///
+/// # Safety
+///
+/// `ip` must be null or point to a NUL-terminated string.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn nali_lookup_ip(ip: *const c_char) -> *mut c_char {
+    // SAFETY: guaranteed by the caller
+    let result = unsafe { required_str(ip, "IP address") }.and_then(|ip| {
+        let ip: IpAddr = ip
+            .trim()
+            .parse()
+            .map_err(|_| NaliError::InvalidIp(ip.to_string()))?;
+        let engine = engine()?;
+        engine.runtime.block_on(engine.manager.query_ip(ip))
+    });
+    to_json(result)
+}
+
+/// Look up the CDN provider serving `domain`
+///
+/// # Safety
+///
+/// `domain` must be null or point to a NUL-terminated string.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn nali_lookup_cdn(domain: *const c_char) -> *mut c_char {
+    // SAFETY: guaranteed by the caller
+    let result = unsafe { required_str(domain, "domain") }.and_then(|domain| {
+        let engine = engine()?;
+        engine.runtime.block_on(engine.manager.query_cdn(domain.trim()))
+    });
+    to_json(result)
+}
+
+/// Free a string returned by this library; null is ignored
+///
+/// # Safety
+///
+/// `ptr` must be null or a string returned by `nali_lookup_ip` or
+/// `nali_lookup_cdn` that has not been freed yet.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn nali_free(ptr: *mut c_char) {
+    if !ptr.is_null() {
+        // SAFETY: the string was created by `CString::into_raw` in `to_json`
+        drop(unsafe { CString::from_raw(ptr) });
+    }
+}
+
+fn init(config_path: Option<&Path>) -> Result<()> {
+    let config = match config_path {
+        Some(path) => AppConfig::load_from(path)?,
+        None => AppConfig::load()?,
+    };
+    let runtime = tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()
+        .map_err(NaliError::IoError)?;
+
+    let engine = Engine {
+        runtime,
+        manager: DatabaseManager::new(config),
+    };
+    *ENGINE.lock().unwrap_or_else(|e| e.into_inner()) = Some(Arc::new(engine));
+    Ok(())
+}
+
+fn engine() -> Result<Arc<Engine>> {
+    ENGINE
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .clone()
+        .ok_or_else(|| NaliError::Other("nali_init has not been called".to_string()))
+}
+
+/// Borrow a C string, which may be null
+///
+/// # Safety
+///
+/// `ptr` must be null or point to a NUL-terminated string.
+unsafe fn optional_str<'a>(ptr: *const c_char) -> Result<Option<&'a str>> {
+    if ptr.is_null() {
+        return Ok(None);
+    }
+    // SAFETY: guaranteed by the caller
+    unsafe { CStr::from_ptr(ptr) }
+        .to_str()
+        .map(Some)
+        .map_err(|_| NaliError::EncodingError("argument is not UTF-8".to_string()))
+}
+
+/// Borrow a C string that must be given; `what` names it in errors
+///
+/// # Safety
+///
+/// `ptr` must be null or point to a NUL-terminated string.
+unsafe fn required_str<'a>(ptr: *const c_char, what: &str) -> Result<&'a str> {
+    // SAFETY: guaranteed by the caller
+    unsafe { optional_str(ptr) }?.ok_or_else(|| NaliError::Other(format!("{} is null", what)))
+}
+
+/// Serialize a lookup result into a string for the caller to free
+fn to_json<T: Serialize>(result: Result<Option<T>>) -> *mut c_char {
+    let json = match result {
+        Ok(value) => serde_json::to_string(&value),
+        Err(e) => serde_json::to_string(&serde_json::json!({ "error": e.to_string() })),
+    }
+    .unwrap_or_else(|e| serde_json::json!({ "error": e.to_string() }).to_string());
+
+    // JSON escapes control characters, so there is no interior NUL
+    CString::new(json).unwrap_or_default().into_raw()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Call a lookup with `arg` and take ownership of the returned JSON
+    fn call(lookup: unsafe extern "C" fn(*const c_char) -> *mut c_char, arg: &str) -> String {
+        let arg = CString::new(arg).unwrap();
+        unsafe {
+            let ptr = lookup(arg.as_ptr());
+            let json = CStr::from_ptr(ptr).to_str().unwrap().to_string();
+            nali_free(ptr);
+            json
+        }
+    }
+
+    #[test]
+    fn test_lookups() {
+        let fixtures = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures");
+        let dir = tempfile::tempdir().unwrap();
+        let config_path = dir.path().join("config.yaml");
+        let config = std::fs::read_to_string(fixtures.join("config.yaml")).unwrap();
+        let config = config.replacen(
+            "database:\n",
+            &format!("database:\n  data_dir: {:?}\n", fixtures.to_str().unwrap()),
+            1,
+        );
+        std::fs::write(&config_path, config).unwrap();
+
+        let missing = CString::new(dir.path().join("missing.yaml").to_str().unwrap()).unwrap();
+        assert_eq!(unsafe { nali_init(missing.as_ptr()) }, -1);
+        let path = CString::new(config_path.to_str().unwrap()).unwrap();
+        assert_eq!(unsafe { nali_init(path.as_ptr()) }, 0);
+
+        let location: serde_json::Value =
+            serde_json::from_str(&call(nali_lookup_ip, "8.8.8.8")).unwrap();
+        assert_eq!(location["ip"], "8.8.8.8");
+        assert!(location["country"].is_string());
+
+        let cdn: serde_json::Value =
+            serde_json::from_str(&call(nali_lookup_cdn, "cdn.jsdelivr.net")).unwrap();
+        assert!(cdn["provider"].is_string());
+        assert_eq!(call(nali_lookup_cdn, "example.invalid"), "null");
+
+        let invalid: serde_json::Value =
+            serde_json::from_str(&call(nali_lookup_ip, "not an ip")).unwrap();
+        assert!(invalid["error"].as_str().unwrap().contains("not an ip"));
+        unsafe {
+            let ptr = nali_lookup_ip(std::ptr::null());
+            let null: serde_json::Value =
+                serde_json::from_str(CStr::from_ptr(ptr).to_str().unwrap()).unwrap();
+            assert!(null["error"].is_string());
+            nali_free(ptr);
+            nali_free(std::ptr::null_mut());
+        }
+    }
+}
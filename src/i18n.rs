@@ -0,0 +1,268 @@
+//! Localization of user-facing messages
+//!
+//! Messages are written in English and translated at runtime: the English
+//! text, with `{}` placeholders, is the key into the catalog of the current
+//! language, and text without a translation stays English. Location names
+//! come from the databases and are not translated here.
+//!
+//! The language is English until [`set_language`] is called. The CLI picks
+//! it with [`Language::detect`]: `NALI_LANG`, then the locale (`LC_ALL`,
+//! `LC_MESSAGES`, `LANG`), then `database.language`.
+//!
+//! ```
+//! use nali_rs::i18n::Language;
+//!
+//! assert_eq!(Language::ZhCn.translate("Not found"), "未找到");
+//! assert_eq!(nali_rs::tr!("Failed to update {}: {}", "qqwry", "timeout"), "Failed to update qqwry: timeout");
+//! ```
+
+use std::fmt::Display;
+use std::sync::atomic::{AtomicU8, Ordering};
+
+/// Language of user-facing messages
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Language {
+    /// English, the language messages are written in
+    #[default]
+    En,
+    /// Simplified Chinese
+    ZhCn,
+}
+
+/// Current language, as the discriminant of [`Language`]
+static LANGUAGE: AtomicU8 = AtomicU8::new(Language::En as u8);
+
+/// Chinese translations, keyed by the English text
+const ZH_CN: &[(&str, &str)] = &[
+    // Error kinds
+    ("Database not found", "数据库不存在"),
+    ("Invalid IP address", "无效的 IP 地址"),
+    ("Invalid domain", "无效的域名"),
+    ("Database parse error", "数据库解析失败"),
+    ("Invalid database format", "数据库格式无效"),
+    ("Database not loaded", "数据库未加载"),
+    ("Database not ready yet", "数据库尚未就绪"),
+    ("Database corrupted", "数据库已损坏"),
+    ("Configuration error", "配置错误"),
+    ("Network error", "网络错误"),
+    ("Download failed", "下载失败"),
+    ("File I/O error", "文件读写错误"),
+    ("Encoding conversion error", "编码转换失败"),
+    ("Regex error", "正则表达式错误"),
+    ("YAML parse error", "YAML 解析失败"),
+    ("TOML parse error", "TOML 解析失败"),
+    ("JSON parse error", "JSON 解析失败"),
+    ("No result for", "无查询结果"),
+    ("Index out of bounds", "索引越界"),
+    ("Other error", "其他错误"),
+    // Command line
+    ("Error: {}", "错误：{}"),
+    ("Query failed: {}", "查询失败：{}"),
+    ("Not found", "未找到"),
+    ("Tor exit", "Tor 出口节点"),
+    ("Warning: the {} database was built {} days ago ({}); `nali-rs --update {}` fetches a newer one", "警告：{} 数据库构建于 {} 天前（{}），`nali-rs --update {}` 可获取新版本"),
+    ("inferred: {}", "推测：{}"),
+    ("Warning: {}", "警告：{}"),
+    ("{}: OK", "{}：正常"),
+    ("Converted {} to {}", "已将 {} 转换为 {}"),
+    ("Updating all databases...", "正在更新所有数据库..."),
+    ("✗ Failed to update {}: {}", "✗ 更新 {} 失败：{}"),
+    ("COUNTRY", "国家"),
+    ("REGION", "地区"),
+    ("CITY", "城市"),
+    ("ISP", "运营商"),
+    ("SOURCE", "来源"),
+    // Downloads
+    ("Downloading {}", "正在下载 {}"),
+    ("Downloaded {}", "已下载 {}"),
+    ("✓ {} database is already up to date", "✓ {} 数据库已是最新"),
+    ("✓ Successfully downloaded {} database", "✓ {} 数据库下载成功"),
+    ("Extracting 7z archive...", "正在解压 7z 压缩包..."),
+    ("Downloading CDN databases from multiple sources...", "正在从多个来源下载 CDN 数据库..."),
+    ("  [{}/{}] Downloading from {}...", "  [{}/{}] 正在从 {} 下载..."),
+    ("      ✓ Not modified", "      ✓ 未变化"),
+    ("      ✓ Downloaded {} entries", "      ✓ 已下载 {} 条记录"),
+    ("      ✗ Failed: {}", "      ✗ 失败：{}"),
+    ("      Retrying in {} ({}/{})...", "      {} 后重试（{}/{}）..."),
+    ("✓ cdn database is already up to date", "✓ cdn 数据库已是最新"),
+    ("Merging CDN data from {} sources...", "正在合并 {} 个来源的 CDN 数据..."),
+    ("Total unique CDN entries: {}", "CDN 记录总数（去重后）：{}"),
+    ("✓ Successfully downloaded and merged CDN database", "✓ CDN 数据库下载并合并成功"),
+    ("{} conflicting definitions:", "{} 条冲突的定义："),
+    (
+        "  ... and {} more (set database.cdn_provenance to see them in the merged file)",
+        "  ……另有 {} 条（设置 database.cdn_provenance 可在合并后的文件中查看）",
+    ),
+    ("Downloading all databases...", "正在下载所有数据库..."),
+    ("Downloading {} database...", "正在下载 {} 数据库..."),
+    ("✗ Failed to download {}: {}", "✗ 下载 {} 失败：{}"),
+    ("Download complete: {} succeeded, {} failed", "下载完成：成功 {} 个，失败 {} 个"),
+    ("✗ post_update hook failed for {}: {}", "✗ {} 的 post_update 钩子执行失败：{}"),
+    ("Updating {} database...", "正在更新 {} 数据库..."),
+    ("Installing {} database...", "正在安装 {} 数据库..."),
+    ("✓ Database updated successfully", "✓ 数据库更新成功"),
+    ("✓ Database installed successfully", "✓ 数据库安装成功"),
+    (
+        "Database file not found, automatically downloading {} database...",
+        "数据库文件不存在，正在自动下载 {} 数据库...",
+    ),
+    ("✓ Database download complete", "✓ 数据库下载完成"),
+    (
+        "Database {} not found, downloading it in the background; lines pass through un-annotated until it is ready",
+        "数据库 {} 不存在，正在后台下载；下载完成前各行原样输出，不加注释",
+    ),
+    ("✓ Database {} is ready", "✓ 数据库 {} 已就绪"),
+    ("Background download of {} failed: {}", "后台下载 {} 失败：{}"),
+    ("Database {} is corrupt, downloading a fresh copy...", "数据库 {} 已损坏，正在重新下载..."),
+];
+
+impl Language {
+    /// Language of a tag such as `zh-CN`, `zh_TW.UTF-8` or `en-US`
+    ///
+    /// Every Chinese tag maps to Simplified Chinese, anything else to English.
+    pub fn from_tag(tag: &str) -> Self {
+        let tag = tag.trim().to_ascii_lowercase();
+        if tag == "zh" || tag.starts_with("zh-") || tag.starts_with("zh_") {
+            Language::ZhCn
+        } else {
+            Language::En
+        }
+    }
+
+    /// Pick the language from the environment, then `configured`
+    ///
+    /// `NALI_LANG` wins over the locale variables, which win over the
+    /// configured `database.language`. The `C` and `POSIX` locales are English.
+    pub fn detect(configured: &str) -> Self {
+        Self::detect_from(configured, |name| std::env::var(name).ok())
+    }
+
+    /// See [`Language::detect`]; variables are read through `var`
+    fn detect_from(configured: &str, var: impl Fn(&str) -> Option<String>) -> Self {
+        ["NALI_LANG", "LC_ALL", "LC_MESSAGES", "LANG"]
+            .iter()
+            .filter_map(|name| var(name))
+            .find(|value| !value.is_empty())
+            .map_or_else(|| Self::from_tag(configured), |value| Self::from_tag(&value))
+    }
+
+    /// Translate `text` into this language, or return it unchanged
+    pub fn translate(self, text: &'static str) -> &'static str {
+        match self {
+            Language::En => text,
+            Language::ZhCn => ZH_CN
+                .iter()
+                .find(|(en, _)| *en == text)
+                .map_or(text, |(_, zh)| zh),
+        }
+    }
+
+    /// Translate `template` and fill its `{}` placeholders with `args` in order
+    pub fn format(self, template: &'static str, args: &[&dyn Display]) -> String {
+        let mut out = String::new();
+        let mut args = args.iter();
+        let mut parts = self.translate(template).split("{}");
+        if let Some(first) = parts.next() {
+            out.push_str(first);
+        }
+        for part in parts {
+            if let Some(arg) = args.next() {
+                out.push_str(&arg.to_string());
+            }
+            out.push_str(part);
+        }
+        out
+    }
+}
+
+/// Set the language of messages for the whole process
+pub fn set_language(language: Language) {
+    LANGUAGE.store(language as u8, Ordering::Relaxed);
+}
+
+/// The current language of messages
+pub fn language() -> Language {
+    match LANGUAGE.load(Ordering::Relaxed) {
+        1 => Language::ZhCn,
+        _ => Language::En,
+    }
+}
+
+/// Translate `text` into the current language
+pub fn tr(text: &'static str) -> &'static str {
+    language().translate(text)
+}
+
+/// Translate a message into the current language
+///
+/// `tr!("text")` returns the translated `&'static str`; with arguments, the
+/// `{}` placeholders of the translation are filled with them in order.
+#[macro_export]
+macro_rules! tr {
+    ($text:literal) => {
+        $crate::i18n::tr($text)
+    };
+    ($template:literal, $($arg:expr),+ $(,)?) => {
+        $crate::i18n::language().format($template, &[$(&$arg as &dyn ::std::fmt::Display),+])
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_from_tag() {
+        assert_eq!(Language::from_tag("zh-CN"), Language::ZhCn);
+        assert_eq!(Language::from_tag("zh_TW.UTF-8"), Language::ZhCn);
+        assert_eq!(Language::from_tag("zh"), Language::ZhCn);
+        assert_eq!(Language::from_tag("en_US.UTF-8"), Language::En);
+        assert_eq!(Language::from_tag("C"), Language::En);
+        assert_eq!(Language::from_tag("zhx"), Language::En);
+    }
+
+    #[test]
+    fn test_detect() {
+        let env = |vars: &[(&str, &str)]| {
+            let vars: HashMap<String, String> =
+                vars.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect();
+            move |name: &str| vars.get(name).cloned()
+        };
+        assert_eq!(Language::detect_from("zh-CN", env(&[])), Language::ZhCn);
+        assert_eq!(Language::detect_from("zh-CN", env(&[("LANG", "C")])), Language::En);
+        assert_eq!(Language::detect_from("en", env(&[("LANG", "zh_CN.UTF-8")])), Language::ZhCn);
+        assert_eq!(
+            Language::detect_from("en", env(&[("LANG", "zh_CN.UTF-8"), ("LC_ALL", "en_US.UTF-8")])),
+            Language::En
+        );
+        assert_eq!(
+            Language::detect_from("en", env(&[("LC_ALL", "en_US.UTF-8"), ("NALI_LANG", "zh-CN")])),
+            Language::ZhCn
+        );
+        // An empty variable counts as unset
+        assert_eq!(Language::detect_from("zh-CN", env(&[("LC_ALL", "")])), Language::ZhCn);
+    }
+
+    #[test]
+    fn test_format() {
+        let args: [&dyn Display; 2] = [&"qqwry", &"timeout"];
+        assert_eq!(
+            Language::En.format("✗ Failed to update {}: {}", &args),
+            "✗ Failed to update qqwry: timeout"
+        );
+        assert_eq!(
+            Language::ZhCn.format("✗ Failed to update {}: {}", &args),
+            "✗ 更新 qqwry 失败：timeout"
+        );
+        // Text without a translation stays English
+        assert_eq!(Language::ZhCn.format("Resolving {}", &[&"example.com"]), "Resolving example.com");
+    }
+
+    #[test]
+    fn test_catalog_placeholders_match() {
+        for (en, zh) in ZH_CN {
+            assert_eq!(en.matches("{}").count(), zh.matches("{}").count(), "{}", en);
+        }
+    }
+}
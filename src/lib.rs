@@ -6,15 +6,33 @@
 // Public modules
 pub mod config;
 pub mod database;
+#[cfg(feature = "native")]
+pub mod dns;
 pub mod error;
+pub mod i18n;
 pub mod entity;
+pub mod enrichment;
 pub mod regex;
+#[cfg(feature = "native")]
+pub mod trace;
 pub mod utils;
+#[cfg(feature = "native")]
 pub mod download;
+#[cfg(feature = "native")]
 pub mod cli;
+#[cfg(feature = "nali-ffi")]
+pub mod ffi;
+#[cfg(feature = "sink")]
+pub mod sink;
+#[cfg(all(test, feature = "native"))]
+mod test_support;
 
 // Re-export commonly used types
-pub use config::{AppConfig, DatabaseConfig, OutputConfig, GlobalConfig, DatabaseInfo};
-pub use database::{Database, DatabaseType, GeoLocation, CdnProvider, DatabaseManager};
+pub use config::{AppConfig, DatabaseConfig, OutputConfig, GlobalConfig, DatabaseInfo, WhoisConfig, RdnsConfig, EnrichmentConfig, DownloadConfig, AutoDownload, SinkConfig};
+pub use database::{Database, DatabaseType, GeoLocation, CdnProvider, DatabaseRegistry};
+#[cfg(feature = "native")]
+pub use database::DatabaseManager;
 pub use error::{NaliError, Result};
 pub use entity::{Entity, EntityType, Entities};
+#[cfg(feature = "native")]
+pub use enrichment::{Enricher, Enrichment, Pipeline};
@@ -4,48 +4,60 @@
 //! It provides the same functionality as the Go version but with better performance.
 
 use clap::Parser;
-use log::info;
+use tracing::info;
+use tracing::level_filters::LevelFilter;
+use std::process::ExitCode;
 
-mod cli;
-mod config;
-mod database;
-mod download;
-mod error;
-mod regex;
-mod utils;
-mod entity;
-
-// Re-export common types
-pub use error::{NaliError, Result};
-
-// Re-export database types for use in benchmarks and tests
-pub use database::{
-    Database, DatabaseType, GeoLocation, CdnProvider,
-    QQwryDatabase, ZXIPv6Database, GeoIP2Database, IPIPDatabase,
-    CDNDatabase, DBIPDatabase, IP2RegionDatabase, IP2LocationDatabase
-};
-
-use config::AppConfig;
-use cli::Cli;
+use nali_rs::AppConfig;
+use nali_rs::i18n::{self, Language};
+use nali_rs::cli::{Cli, logging};
+use nali_rs::cli::status::ExitStatus;
 
 #[tokio::main]
-async fn main() -> Result<()> {
-    // Initialize logging
-    env_logger::init();
-
+async fn main() -> ExitCode {
     // Parse command line arguments
     let cli = Cli::parse();
 
+    // Initialize logging; the update daemon reports through the log, so it
+    // logs progress unless told otherwise
+    let log_level = cli.log_level.or_else(|| {
+        (cli.daemon && std::env::var_os("RUST_LOG").is_none()).then_some(LevelFilter::INFO)
+    });
+    logging::init(log_level, cli.log_file.as_deref(), cli.log_format);
+
     info!("Starting nali-rs v{}", env!("CARGO_PKG_VERSION"));
 
-    // Load configuration
-    let config = AppConfig::load().unwrap_or_else(|e| {
-        eprintln!("Warning: Failed to load config: {}, using defaults", e);
-        AppConfig::default()
-    });
+    // Load configuration, without creating it in read-only mode
+    let read_only = cli.read_only || AppConfig::read_only_from_env();
+    let loaded = if read_only {
+        AppConfig::load_read_only()
+    } else {
+        AppConfig::load()
+    };
+    // A broken configuration file stops the run rather than silently
+    // querying other databases than configured; only the commands that
+    // report or fix it go ahead
+    let config = match loaded {
+        Ok(config) => config,
+        Err(_) if cli.runs_without_config() => {
+            let mut config = AppConfig::default();
+            config.global.read_only = read_only;
+            i18n::set_language(Language::detect(&config.database.language));
+            config
+        }
+        Err(e) => {
+            i18n::set_language(Language::detect(&AppConfig::default().database.language));
+            eprintln!("{}", nali_rs::tr!("Error: {}", e));
+            return ExitStatus::DatabaseError.into();
+        }
+    };
 
     // Execute CLI logic
-    cli.run(config).await?;
-
-    Ok(())
-}
\ No newline at end of file
+    match cli.run(config).await {
+        Ok(status) => status.into(),
+        Err(e) => {
+            eprintln!("{}", nali_rs::tr!("Error: {}", e));
+            ExitStatus::from_error(&e).into()
+        }
+    }
+}
@@ -16,10 +16,11 @@ pub static IPV4_RE: Lazy<Regex> = Lazy::new(|| {
 });
 
 /// IPv6 address regex
-/// Matches various IPv6 formats including compressed notation
+/// Matches various IPv6 formats including compressed notation, with an
+/// optional zone id like `%eth0` or `%eth0.100`
 pub static IPV6_RE: Lazy<Regex> = Lazy::new(|| {
     Regex::new(
-        r"fe80:(:[0-9a-fA-F]{1,4}){0,4}(%\w+)?|([0-9a-fA-F]{1,4}:){7}[0-9a-fA-F]{1,4}|64:ff9b::(25[0-5]|2[0-4][0-9]|[01]?[0-9][0-9]?)(\.(25[0-5]|2[0-4][0-9]|[01]?[0-9][0-9]?)){3}|::[fF]{4}:(25[0-5]|2[0-4][0-9]|[01]?[0-9][0-9]?)(\.(25[0-5]|2[0-4][0-9]|[01]?[0-9][0-9]?)){3}|(([0-9a-fA-F]{1,4}:){0,6}[0-9a-fA-F]{1,4})?::(([0-9a-fA-F]{1,4}:){0,6}[0-9a-fA-F]{1,4})?"
+        r"(?:([0-9a-fA-F]{1,4}:){7}[0-9a-fA-F]{1,4}|64:ff9b::(25[0-5]|2[0-4][0-9]|[01]?[0-9][0-9]?)(\.(25[0-5]|2[0-4][0-9]|[01]?[0-9][0-9]?)){3}|::[fF]{4}:(25[0-5]|2[0-4][0-9]|[01]?[0-9][0-9]?)(\.(25[0-5]|2[0-4][0-9]|[01]?[0-9][0-9]?)){3}|(([0-9a-fA-F]{1,4}:){0,6}[0-9a-fA-F]{1,4})?::(([0-9a-fA-F]{1,4}:){0,6}[0-9a-fA-F]{1,4})?)(?:%[0-9A-Za-z_-]+(?:\.[0-9A-Za-z_-]+)*)?"
     )
     .expect("Failed to compile IPv6 regex")
 });
@@ -33,6 +34,57 @@ pub static DOMAIN_RE: Lazy<Regex> = Lazy::new(|| {
     .expect("Failed to compile domain regex")
 });
 
+/// Internationalized domain name regex
+/// Matches names with Unicode labels like 中国.cn or 例子.测试; domains in
+/// plain ASCII, including punycode like xn--fiqs8s.cn, match DOMAIN_RE
+pub static IDN_DOMAIN_RE: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(
+        r"(?:[\p{L}\p{N}](?:[\p{L}\p{N}-]{0,61}[\p{L}\p{N}])?\.)+[\p{L}\p{N}](?:[\p{L}\p{N}-]{0,61}[\p{L}\p{N}])?"
+    )
+    .expect("Failed to compile IDN domain regex")
+});
+
+/// URL regex
+/// Matches scheme-prefixed URLs like https://example.com:8443/path?q=1
+pub static URL_RE: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(
+        r#"(?i)\b[a-z][a-z0-9+.-]*://[^\s"'<>`]+"#
+    )
+    .expect("Failed to compile URL regex")
+});
+
+/// Bracketed IPv6 regex
+/// Matches `[2001:db8::1]` and `[2001:db8::1]:8080`; group 1 is the address
+pub static BRACKETED_IPV6_RE: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(
+        r"\[([0-9a-fA-F:.]*:[0-9a-fA-F:.]*(?:%[0-9A-Za-z_.-]+)?)\](?::[0-9]{1,5})?"
+    )
+    .expect("Failed to compile bracketed IPv6 regex")
+});
+
+/// MAC address regex
+/// Matches 6 to 20 colon- or dash-separated octets (MAC, EUI-64, InfiniBand)
+/// and Cisco dotted notation like 001a.2b3c.4d5e
+pub static MAC_RE: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(
+        r"(?i)\b(?:[0-9a-f]{2}(?::[0-9a-f]{2}){5,19}|[0-9a-f]{2}(?:-[0-9a-f]{2}){5,19}|[0-9a-f]{4}\.[0-9a-f]{4}\.[0-9a-f]{4})\b"
+    )
+    .expect("Failed to compile MAC regex")
+});
+
+/// Version string regex
+/// Matches v1.2.3.4, version 1.2.3.4, product tokens like curl/7.68.0.1
+/// and dotted numbers with five or more parts like 10.0.19041.1.2
+pub static VERSION_RE: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(
+        r"(?i)\b(?:(?:v|version[ :=]?)[0-9]+(?:\.[0-9]+)+|[a-z][a-z0-9_-]*/[0-9]+(?:\.[0-9]+)+|[0-9]+(?:\.[0-9]+){4,})"
+    )
+    .expect("Failed to compile version regex")
+});
+
+/// A match as (start, end, text)
+pub type TextMatch = (usize, usize, String);
+
 /// Find all IPv4 addresses in text with their positions
 pub fn find_ipv4(text: &str) -> Vec<(usize, usize, String)> {
     IPV4_RE
@@ -57,6 +109,61 @@ pub fn find_domains(text: &str) -> Vec<(usize, usize, String)> {
         .collect()
 }
 
+/// Find all domain names with a non-ASCII label in text
+pub fn find_idn_domains(text: &str) -> Vec<TextMatch> {
+    IDN_DOMAIN_RE
+        .find_iter(text)
+        .filter(|m| !m.as_str().is_ascii())
+        .map(|m| (m.start(), m.end(), m.as_str().to_string()))
+        .collect()
+}
+
+/// Find all bracketed IPv6 addresses in text
+///
+/// Returns the span of the whole `[addr]:port` match followed by the span
+/// and text of the address inside the brackets.
+pub fn find_bracketed_ipv6(text: &str) -> Vec<((usize, usize), TextMatch)> {
+    BRACKETED_IPV6_RE
+        .captures_iter(text)
+        .filter_map(|caps| {
+            let whole = caps.get(0)?;
+            let addr = caps.get(1)?;
+            Some((
+                (whole.start(), whole.end()),
+                (addr.start(), addr.end(), addr.as_str().to_string()),
+            ))
+        })
+        .collect()
+}
+
+/// Find all MAC addresses in text with their positions
+pub fn find_macs(text: &str) -> Vec<TextMatch> {
+    MAC_RE
+        .find_iter(text)
+        .map(|m| (m.start(), m.end(), m.as_str().to_string()))
+        .collect()
+}
+
+/// Find all version strings in text with their positions
+///
+/// Product tokens directly after a `/` (URL paths like `/geo/1.2.3.4`) are
+/// not version strings and are skipped.
+pub fn find_versions(text: &str) -> Vec<TextMatch> {
+    VERSION_RE
+        .find_iter(text)
+        .filter(|m| !text[..m.start()].ends_with('/'))
+        .map(|m| (m.start(), m.end(), m.as_str().to_string()))
+        .collect()
+}
+
+/// Find all URLs in text with their positions
+pub fn find_urls(text: &str) -> Vec<(usize, usize, String)> {
+    URL_RE
+        .find_iter(text)
+        .map(|m| (m.start(), m.end(), m.as_str().to_string()))
+        .collect()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -74,11 +181,21 @@ mod tests {
     fn test_ipv6_regex() {
         let text = "IPv6: 2001:0db8::1 and ::1";
         let matches = find_ipv6(text);
-        assert!(matches.len() >= 1);
+        assert!(!matches.is_empty());
         // Note: The regex may match partial addresses, so we just check that we found something
         assert!(matches[0].2.contains("2001"));
     }
 
+    #[test]
+    fn test_ipv6_zone_regex() {
+        let text = "fe80::1%eth0 fe80::20c:29ff:fe3a:4b5c%enp0s31f6. ff02::1%eth0.100 ::1%br-lan";
+        let matches: Vec<String> = find_ipv6(text).into_iter().map(|m| m.2).collect();
+        assert_eq!(
+            matches,
+            vec!["fe80::1%eth0", "fe80::20c:29ff:fe3a:4b5c%enp0s31f6", "ff02::1%eth0.100", "::1%br-lan"]
+        );
+    }
+
     #[test]
     fn test_domain_regex() {
         let text = "Visit example.com and sub.example.org";
@@ -88,6 +205,55 @@ mod tests {
         assert_eq!(matches[1].2, "sub.example.org");
     }
 
+    #[test]
+    fn test_idn_domain_regex() {
+        let text = "visit 中国.cn, www.例子.com and xn--fiqs8s.cn or example.com";
+        let matches: Vec<String> = find_idn_domains(text).into_iter().map(|m| m.2).collect();
+        assert_eq!(matches, vec!["中国.cn", "www.例子.com"]);
+    }
+
+    #[test]
+    fn test_url_regex() {
+        let text = "GET https://1.2.3.4:8443/path?a=b, see \"http://cdn.example.com/app.js\"";
+        let matches = find_urls(text);
+        assert_eq!(matches.len(), 2);
+        assert_eq!(matches[0].2, "https://1.2.3.4:8443/path?a=b,");
+        assert_eq!(matches[1].2, "http://cdn.example.com/app.js");
+    }
+
+    #[test]
+    fn test_bracketed_ipv6_regex() {
+        let text = "from [2001:db8::1]:8080 to [::1] via [fe80::1%eth0]:53 [info]";
+        let matches = find_bracketed_ipv6(text);
+        assert_eq!(matches.len(), 3);
+        assert_eq!(matches[0].1.2, "2001:db8::1");
+        assert_eq!(&text[matches[0].0.0..matches[0].0.1], "[2001:db8::1]:8080");
+        assert_eq!(matches[1].1.2, "::1");
+        assert_eq!(matches[2].1.2, "fe80::1%eth0");
+    }
+
+    #[test]
+    fn test_mac_regex() {
+        let text = "ether 00:11:22:33:44:55 hw 00-1A-2B-3C-4D-5E cisco 001a.2b3c.4d5e not 12:34:56";
+        let matches = find_macs(text);
+        assert_eq!(matches.len(), 3);
+        assert_eq!(matches[0].2, "00:11:22:33:44:55");
+        assert_eq!(matches[1].2, "00-1A-2B-3C-4D-5E");
+        assert_eq!(matches[2].2, "001a.2b3c.4d5e");
+        assert!(find_macs("fe80::20c:29ff:fe3a:4b5c").is_empty());
+    }
+
+    #[test]
+    fn test_version_regex() {
+        let text = "curl/7.68.0.1 v1.2.3.4 version 5.15.0 build 10.0.19041.1.2 GET /geo/8.8.8.8";
+        let matches: Vec<String> = find_versions(text).into_iter().map(|m| m.2).collect();
+        assert_eq!(
+            matches,
+            vec!["curl/7.68.0.1", "v1.2.3.4", "version 5.15.0", "10.0.19041.1.2"]
+        );
+        assert!(find_versions("from 1.2.3.4 ok").is_empty());
+    }
+
     #[test]
     fn test_invalid_ipv4() {
         let text = "Invalid: 999.999.999.999";
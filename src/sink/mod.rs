@@ -0,0 +1,47 @@
+//! Publishing enriched records to a message broker
+//!
+//! With the `sink` feature, every annotated entity can be published as the
+//! JSON record of `-o results.jsonl` to a NATS subject, so nali-rs can sit
+//! in a log pipeline between collectors and storage. Kafka is not supported.
+
+pub mod nats;
+
+pub use nats::NatsPublisher;
+
+use crate::config::SinkConfig;
+use crate::entity::{Entities, EntityType, formatter};
+use crate::error::Result;
+
+/// Publishes one JSON record per annotated entity
+pub struct RecordSink {
+    publisher: NatsPublisher,
+}
+
+impl RecordSink {
+    /// Connect to the configured broker; `None` when none is configured
+    pub async fn from_config(config: &SinkConfig) -> Result<Option<Self>> {
+        let Some(ref url) = config.nats else {
+            return Ok(None);
+        };
+        let publisher = NatsPublisher::connect(url, &config.subject, config.buffer).await?;
+        tracing::info!("Publishing records to {} on {}", url, config.subject);
+        Ok(Some(Self { publisher }))
+    }
+
+    /// Publish the entities of input line `line_no`
+    pub fn publish_entities(&self, line_no: usize, entities: &Entities) -> Result<()> {
+        for entity in &entities.entities {
+            if entity.entity_type == EntityType::Plain {
+                continue;
+            }
+            let record = formatter::entity_record(entity, line_no);
+            self.publisher.publish(&serde_json::to_vec(&record)?)?;
+        }
+        Ok(())
+    }
+
+    /// Publish what is still queued and disconnect
+    pub async fn close(self) -> Result<()> {
+        self.publisher.close().await
+    }
+}
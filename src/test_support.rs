@@ -0,0 +1,35 @@
+//! Fixtures shared by the unit tests of several modules
+
+use crate::config::DatabaseInfo;
+use crate::database::custom::build_from_csv;
+use std::path::{Path, PathBuf};
+
+/// Entry of a custom database `name`, stored as `<name>.nali` and
+/// downloaded from `urls`
+pub fn custom_info(name: &str, urls: &[String]) -> DatabaseInfo {
+    DatabaseInfo {
+        name: name.to_string(),
+        name_alias: vec![],
+        format: "nali".to_string(),
+        file: format!("{}.nali", name),
+        languages: vec![],
+        types: vec![],
+        download_urls: urls.to_vec(),
+        tag: None,
+    }
+}
+
+/// Build `<name>.nali` in `dir` from CSV rows, see [`build_from_csv`]
+pub fn custom_file(dir: &Path, name: &str, csv: &str) -> PathBuf {
+    let input = dir.join(format!("{}.csv", name));
+    let output = dir.join(format!("{}.nali", name));
+    std::fs::write(&input, csv).unwrap();
+    build_from_csv(&input, &output).unwrap();
+    output
+}
+
+/// Build `<name>.nali` in `dir` from CSV rows and return its entry
+pub fn custom_db(dir: &Path, name: &str, csv: &str, urls: &[String]) -> DatabaseInfo {
+    custom_file(dir, name, csv);
+    custom_info(name, urls)
+}
@@ -0,0 +1,90 @@
+//! System traceroute fallback
+//!
+//! Used when raw sockets are not permitted or the target is IPv6. The
+//! command's output is read line by line so hops can be annotated as they
+//! arrive.
+
+use super::TraceOptions;
+use std::env;
+use std::path::{Path, PathBuf};
+use tokio::process::Command;
+
+/// Find an executable on `PATH`
+fn find_in_path(name: &str) -> Option<PathBuf> {
+    let paths = env::var_os("PATH")?;
+    env::split_paths(&paths)
+        .map(|dir| dir.join(name))
+        .find(|path| is_executable(path))
+}
+
+fn is_executable(path: &Path) -> bool {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        path.metadata()
+            .is_ok_and(|meta| meta.is_file() && meta.permissions().mode() & 0o111 != 0)
+    }
+    #[cfg(not(unix))]
+    {
+        path.with_extension("exe").is_file() || path.is_file()
+    }
+}
+
+/// Arguments for the first available traceroute program
+///
+/// Addresses are requested numerically so that each hop is an IP the
+/// database can annotate.
+fn command_line(host: &str, ipv6: bool, options: &TraceOptions) -> Option<(PathBuf, Vec<String>)> {
+    let max_hops = options.max_hops.to_string();
+    let queries = options.queries.to_string();
+    let wait = options.wait.as_secs().max(1).to_string();
+
+    let mut candidates: Vec<(&str, Vec<&str>)> = Vec::new();
+    if ipv6 {
+        candidates.push((
+            "traceroute6",
+            vec!["-n", "-m", &max_hops, "-q", &queries, "-w", &wait],
+        ));
+        candidates.push((
+            "traceroute",
+            vec!["-6", "-n", "-m", &max_hops, "-q", &queries, "-w", &wait],
+        ));
+        candidates.push(("tracepath", vec!["-6", "-n", "-m", &max_hops]));
+    } else {
+        candidates.push((
+            "traceroute",
+            vec!["-n", "-m", &max_hops, "-q", &queries, "-w", &wait],
+        ));
+        candidates.push(("tracepath", vec!["-n", "-m", &max_hops]));
+    }
+    candidates.push(("tracert", vec!["-d", "-h", &max_hops]));
+
+    candidates.into_iter().find_map(|(name, args)| {
+        let path = find_in_path(name)?;
+        let mut args: Vec<String> = args.into_iter().map(String::from).collect();
+        args.push(host.to_string());
+        Some((path, args))
+    })
+}
+
+/// Build the fallback command with stdout piped
+///
+/// The program is wrapped in `stdbuf -oL` when available; traceroute
+/// otherwise block-buffers its output once stdout is a pipe.
+pub fn system_command(host: &str, ipv6: bool, options: &TraceOptions) -> Option<Command> {
+    let (program, args) = command_line(host, ipv6, options)?;
+
+    let mut command = match find_in_path("stdbuf") {
+        Some(stdbuf) => {
+            let mut command = Command::new(stdbuf);
+            command.arg("-oL").arg(&program);
+            command
+        }
+        None => Command::new(&program),
+    };
+    command
+        .args(args)
+        .stdout(std::process::Stdio::piped())
+        .kill_on_drop(true);
+    Some(command)
+}
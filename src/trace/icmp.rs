@@ -0,0 +1,150 @@
+//! ICMPv4 packet encoding and reply matching
+
+use std::net::Ipv4Addr;
+
+/// Echo reply
+const ECHO_REPLY: u8 = 0;
+/// Destination unreachable
+const DEST_UNREACHABLE: u8 = 3;
+/// Echo request
+const ECHO_REQUEST: u8 = 8;
+/// Time exceeded in transit
+const TIME_EXCEEDED: u8 = 11;
+
+/// ICMP header length
+const ICMP_HEADER_LEN: usize = 8;
+
+/// Kind of reply received for a probe
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReplyKind {
+    /// A router on the path dropped the probe
+    TimeExceeded,
+    /// The destination answered
+    EchoReply,
+    /// The destination or a router reported it unreachable
+    Unreachable,
+}
+
+/// A reply matched to one of our probes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Reply {
+    pub source: Ipv4Addr,
+    pub kind: ReplyKind,
+    pub sequence: u16,
+}
+
+/// Internet checksum (RFC 1071)
+fn checksum(data: &[u8]) -> u16 {
+    let mut sum: u32 = data
+        .chunks(2)
+        .map(|pair| u16::from_be_bytes([pair[0], *pair.get(1).unwrap_or(&0)]) as u32)
+        .sum();
+    while sum >> 16 != 0 {
+        sum = (sum & 0xFFFF) + (sum >> 16);
+    }
+    !(sum as u16)
+}
+
+/// Encode an echo request with a small payload
+pub fn echo_request(identifier: u16, sequence: u16) -> Vec<u8> {
+    let mut packet = vec![ECHO_REQUEST, 0, 0, 0];
+    packet.extend_from_slice(&identifier.to_be_bytes());
+    packet.extend_from_slice(&sequence.to_be_bytes());
+    packet.extend_from_slice(b"nali-rs trace\0\0\0");
+
+    let sum = checksum(&packet);
+    packet[2..4].copy_from_slice(&sum.to_be_bytes());
+    packet
+}
+
+/// IPv4 header length of a packet, if it looks like IPv4
+fn ipv4_header_len(packet: &[u8]) -> Option<usize> {
+    let first = *packet.first()?;
+    (first >> 4 == 4).then_some(((first & 0x0F) as usize) * 4)
+}
+
+/// Match a packet read from a raw ICMPv4 socket against our identifier
+///
+/// Raw sockets deliver the IPv4 header. Errors (time exceeded, unreachable)
+/// quote the original IPv4 header and the first 8 bytes of our probe, which
+/// carry its identifier and sequence.
+pub fn parse_reply(packet: &[u8], identifier: u16) -> Option<Reply> {
+    let ihl = ipv4_header_len(packet)?;
+    let source = Ipv4Addr::from(<[u8; 4]>::try_from(packet.get(12..16)?).ok()?);
+    let icmp = packet.get(ihl..)?;
+
+    let (kind, echo) = match *icmp.first()? {
+        ECHO_REPLY => (ReplyKind::EchoReply, icmp),
+        TIME_EXCEEDED | DEST_UNREACHABLE => {
+            let kind = if icmp[0] == TIME_EXCEEDED {
+                ReplyKind::TimeExceeded
+            } else {
+                ReplyKind::Unreachable
+            };
+            let quoted = icmp.get(ICMP_HEADER_LEN..)?;
+            let inner_ihl = ipv4_header_len(quoted)?;
+            let inner = quoted.get(inner_ihl..)?;
+            if *inner.first()? != ECHO_REQUEST {
+                return None;
+            }
+            (kind, inner)
+        }
+        _ => return None,
+    };
+
+    let id = u16::from_be_bytes(echo.get(4..6)?.try_into().ok()?);
+    let sequence = u16::from_be_bytes(echo.get(6..8)?.try_into().ok()?);
+    (id == identifier).then_some(Reply {
+        source,
+        kind,
+        sequence,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ipv4_header(source: [u8; 4]) -> Vec<u8> {
+        let mut header = vec![0x45, 0, 0, 0, 0, 0, 0, 0, 64, 1, 0, 0];
+        header.extend_from_slice(&source);
+        header.extend_from_slice(&[10, 0, 0, 2]);
+        header
+    }
+
+    #[test]
+    fn test_echo_request_checksum() {
+        let packet = echo_request(0x1234, 7);
+        assert_eq!(packet[0], ECHO_REQUEST);
+        // A packet including its checksum sums to zero
+        assert_eq!(checksum(&packet), 0);
+    }
+
+    #[test]
+    fn test_parse_time_exceeded() {
+        let mut packet = ipv4_header([192, 168, 1, 1]);
+        packet.extend_from_slice(&[TIME_EXCEEDED, 0, 0, 0, 0, 0, 0, 0]);
+        packet.extend_from_slice(&ipv4_header([10, 0, 0, 2]));
+        packet.extend_from_slice(&echo_request(0x1234, 5)[..8]);
+
+        let reply = parse_reply(&packet, 0x1234).unwrap();
+        assert_eq!(reply.source, Ipv4Addr::new(192, 168, 1, 1));
+        assert_eq!(reply.kind, ReplyKind::TimeExceeded);
+        assert_eq!(reply.sequence, 5);
+
+        // Probes from another process are ignored
+        assert!(parse_reply(&packet, 0x4321).is_none());
+    }
+
+    #[test]
+    fn test_parse_echo_reply() {
+        let mut packet = ipv4_header([93, 184, 216, 34]);
+        let mut echo = echo_request(0x1234, 9);
+        echo[0] = ECHO_REPLY;
+        packet.extend_from_slice(&echo);
+
+        let reply = parse_reply(&packet, 0x1234).unwrap();
+        assert_eq!(reply.kind, ReplyKind::EchoReply);
+        assert_eq!(reply.sequence, 9);
+    }
+}
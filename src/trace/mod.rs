@@ -0,0 +1,71 @@
+//! Traceroute for `nali-rs trace`
+//!
+//! IPv4 routes are traced in-process with ICMP echo probes on a raw socket,
+//! which needs root or CAP_NET_RAW. Without that privilege, and for IPv6
+//! targets, the system traceroute command is run instead.
+//!
+//! # Module Organization
+//!
+//! - `icmp`: ICMP packet encoding and reply matching
+//! - `probe`: Raw socket prober reporting one hop at a time
+//! - `command`: System traceroute fallback
+
+pub mod command;
+pub mod icmp;
+pub mod probe;
+
+use std::net::IpAddr;
+use std::time::Duration;
+
+pub use probe::{Prober, TraceOptions};
+
+/// Result of the probes sent with one TTL
+#[derive(Debug, Clone, PartialEq)]
+pub struct Hop {
+    pub ttl: u8,
+    /// One entry per probe: responder and round-trip time, or `None` on timeout
+    pub probes: Vec<Option<(IpAddr, Duration)>>,
+    /// Whether the destination itself answered
+    pub reached: bool,
+}
+
+impl Hop {
+    /// Responding addresses in first-seen order, each with its round-trip times
+    pub fn responders(&self) -> Vec<(IpAddr, Vec<Duration>)> {
+        let mut responders: Vec<(IpAddr, Vec<Duration>)> = Vec::new();
+        for (addr, rtt) in self.probes.iter().flatten() {
+            match responders.iter_mut().find(|(a, _)| a == addr) {
+                Some((_, rtts)) => rtts.push(*rtt),
+                None => responders.push((*addr, vec![*rtt])),
+            }
+        }
+        responders
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hop_responders() {
+        let a: IpAddr = "10.0.0.1".parse().unwrap();
+        let b: IpAddr = "10.0.0.2".parse().unwrap();
+        let hop = Hop {
+            ttl: 2,
+            probes: vec![
+                Some((a, Duration::from_millis(1))),
+                None,
+                Some((b, Duration::from_millis(3))),
+                Some((a, Duration::from_millis(2))),
+            ],
+            reached: false,
+        };
+
+        let responders = hop.responders();
+        assert_eq!(responders.len(), 2);
+        assert_eq!(responders[0].0, a);
+        assert_eq!(responders[0].1.len(), 2);
+        assert_eq!(responders[1].0, b);
+    }
+}
@@ -0,0 +1,126 @@
+//! Raw socket prober for IPv4 targets
+
+use super::Hop;
+use super::icmp::{self, ReplyKind};
+use socket2::{Domain, Protocol, SockAddr, Socket, Type};
+use std::io::{self, Read};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::time::{Duration, Instant};
+
+/// Largest packet read from the socket
+const RECV_BUFFER_SIZE: usize = 1500;
+
+/// Probe parameters shared by the prober and the command fallback
+#[derive(Debug, Clone)]
+pub struct TraceOptions {
+    pub max_hops: u8,
+    pub queries: usize,
+    pub wait: Duration,
+}
+
+impl Default for TraceOptions {
+    fn default() -> Self {
+        Self {
+            max_hops: 30,
+            queries: 3,
+            wait: Duration::from_secs(3),
+        }
+    }
+}
+
+/// ICMP echo prober on a raw socket
+pub struct Prober {
+    socket: Socket,
+    target: Ipv4Addr,
+    identifier: u16,
+    sequence: u16,
+    options: TraceOptions,
+}
+
+impl Prober {
+    /// Open a raw ICMP socket
+    ///
+    /// Fails with `PermissionDenied` without root or CAP_NET_RAW.
+    pub fn new(target: Ipv4Addr, options: TraceOptions) -> io::Result<Self> {
+        let socket = Socket::new(Domain::IPV4, Type::RAW, Some(Protocol::ICMPV4))?;
+        Ok(Self {
+            socket,
+            target,
+            identifier: std::process::id() as u16,
+            sequence: 0,
+            options,
+        })
+    }
+
+    /// Send one probe and wait for the reply matching it
+    fn probe(&mut self, ttl: u8) -> io::Result<Option<(Ipv4Addr, ReplyKind, Duration)>> {
+        self.sequence = self.sequence.wrapping_add(1);
+        let packet = icmp::echo_request(self.identifier, self.sequence);
+        let target = SockAddr::from(SocketAddr::new(IpAddr::V4(self.target), 0));
+
+        self.socket.set_ttl(ttl as u32)?;
+        let sent = Instant::now();
+        self.socket.send_to(&packet, &target)?;
+
+        let mut buf = [0u8; RECV_BUFFER_SIZE];
+        loop {
+            let elapsed = sent.elapsed();
+            let Some(remaining) = self.options.wait.checked_sub(elapsed) else {
+                return Ok(None);
+            };
+            self.socket
+                .set_read_timeout(Some(remaining.max(Duration::from_millis(1))))?;
+
+            let len = match (&self.socket).read(&mut buf) {
+                Ok(len) => len,
+                Err(e)
+                    if matches!(
+                        e.kind(),
+                        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                    ) =>
+                {
+                    return Ok(None);
+                }
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            };
+
+            // Replies to earlier probes or to other processes are skipped
+            if let Some(reply) = icmp::parse_reply(&buf[..len], self.identifier)
+                && reply.sequence == self.sequence
+            {
+                return Ok(Some((reply.source, reply.kind, sent.elapsed())));
+            }
+        }
+    }
+
+    /// Probe every TTL up to the target, handing each hop to `on_hop`
+    ///
+    /// Stops once the target answers or `on_hop` returns `false`.
+    pub fn run(mut self, mut on_hop: impl FnMut(Hop) -> bool) -> io::Result<()> {
+        for ttl in 1..=self.options.max_hops {
+            let mut hop = Hop {
+                ttl,
+                probes: Vec::with_capacity(self.options.queries),
+                reached: false,
+            };
+
+            for _ in 0..self.options.queries {
+                let result = self.probe(ttl)?;
+                if let Some((source, kind, _)) = result
+                    && (kind != ReplyKind::TimeExceeded || source == self.target)
+                {
+                    hop.reached = true;
+                }
+                hop.probes
+                    .push(result.map(|(source, _, rtt)| (IpAddr::V4(source), rtt)));
+            }
+
+            let reached = hop.reached;
+            if !on_hop(hop) || reached {
+                break;
+            }
+        }
+        Ok(())
+    }
+}
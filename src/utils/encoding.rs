@@ -13,17 +13,46 @@ pub fn gbk_to_utf8(data: &[u8]) -> Result<String> {
     let (cow, _encoding_used, had_errors) = GBK.decode(data);
 
     if had_errors {
-        log::debug!("GBK decoding had errors for bytes: {:?}", data);
+        tracing::debug!("GBK decoding had errors for bytes: {:?}", data);
     }
 
     // Simply convert to string and trim, just like Go version does
     let result = cow.trim().to_string();
 
-    log::debug!("GBK decoded '{}' from bytes: {:?}", result, data);
+    tracing::debug!("GBK decoded '{}' from bytes: {:?}", result, data);
 
     Ok(result)
 }
 
+/// Decode raw input bytes into a UTF-8 string
+///
+/// When `use_gbk` is set the bytes are transcoded from GBK, otherwise they are
+/// treated as UTF-8. Invalid sequences are replaced rather than rejected so a
+/// single bad byte never aborts a pipeline.
+pub fn decode_input(data: &[u8], use_gbk: bool) -> String {
+    if use_gbk {
+        let (cow, _encoding_used, had_errors) = GBK.decode(data);
+        if had_errors {
+            tracing::debug!("GBK input decoding had errors");
+        }
+        cow.into_owned()
+    } else {
+        String::from_utf8_lossy(data).into_owned()
+    }
+}
+
+/// Encode a UTF-8 string as GBK bytes
+///
+/// Characters that cannot be represented in GBK are written as HTML numeric
+/// character references, which is what `encoding_rs` does for unmappables.
+pub fn utf8_to_gbk(text: &str) -> Vec<u8> {
+    let (cow, _encoding_used, had_errors) = GBK.encode(text);
+    if had_errors {
+        tracing::debug!("GBK encoding had unmappable characters");
+    }
+    cow.into_owned()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -35,4 +64,15 @@ mod tests {
         let result = gbk_to_utf8(&gbk_bytes).unwrap();
         assert_eq!(result, "中国");
     }
+
+    #[test]
+    fn test_gbk_round_trip() {
+        let encoded = utf8_to_gbk("IP 1.1.1.1 中国");
+        assert_eq!(decode_input(&encoded, true), "IP 1.1.1.1 中国");
+    }
+
+    #[test]
+    fn test_decode_input_utf8() {
+        assert_eq!(decode_input("中国\n".as_bytes(), false), "中国\n");
+    }
 }
@@ -0,0 +1,29 @@
+//! Internationalized domain names
+//!
+//! A domain can be written in Unicode (`中国.cn`) or in its ASCII form
+//! (`xn--fiqs8s.cn`). Rules are matched and names resolved in the ASCII
+//! form; the public suffix list is written in Unicode.
+
+/// ASCII form of `domain`, lowercased, or `None` if it is not a valid name
+pub fn to_ascii(domain: &str) -> Option<String> {
+    idna::domain_to_ascii(domain).ok()
+}
+
+/// Unicode form of `domain`, lowercased; invalid labels are kept as given
+pub fn to_unicode(domain: &str) -> String {
+    idna::domain_to_unicode(domain).0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_idn() {
+        assert_eq!(to_ascii("中国.cn").as_deref(), Some("xn--fiqs8s.cn"));
+        assert_eq!(to_ascii("WWW.Example.COM").as_deref(), Some("www.example.com"));
+        assert_eq!(to_ascii("xn--fiqs8s.cn").as_deref(), Some("xn--fiqs8s.cn"));
+        assert_eq!(to_unicode("xn--fiqs8s.CN"), "中国.cn");
+        assert_eq!(to_unicode("example.com"), "example.com");
+    }
+}
@@ -1,5 +1,7 @@
 //! Utility functions and helpers
 
 pub mod encoding;
+pub mod idn;
 pub mod path;
+pub mod time;
 
@@ -1,6 +1,9 @@
 //! Path utilities for configuration and database files
 //!
-//! Handles XDG directory specifications and path resolution.
+//! Directories follow each platform's conventions: the XDG base directories
+//! on Linux, `~/Library/Application Support` on macOS and the Known Folders
+//! (`%APPDATA%`, `%LOCALAPPDATA%`) on Windows. Paths in the configuration
+//! may start with `~` and name environment variables.
 
 use crate::error::{NaliError, Result};
 use std::path::{Path, PathBuf};
@@ -11,8 +14,10 @@ use std::env;
 /// Priority:
 /// 1. NALI_CONFIG_HOME environment variable
 /// 2. NALI_HOME environment variable
-/// 3. XDG_CONFIG_HOME/nali-rs
-/// 4. ~/.config/nali-rs (fallback)
+/// 3. The platform's configuration directory:
+///    - Linux: `$XDG_CONFIG_HOME/nali-rs`, or `~/.config/nali-rs`
+///    - macOS: `~/Library/Application Support/nali-rs`
+///    - Windows: `%APPDATA%\nali-rs`, which roams with the user's profile
 pub fn config_dir() -> Result<PathBuf> {
     if let Ok(path) = env::var("NALI_CONFIG_HOME") {
         return Ok(PathBuf::from(path));
@@ -34,8 +39,11 @@ pub fn config_dir() -> Result<PathBuf> {
 /// Priority:
 /// 1. NALI_DB_HOME environment variable
 /// 2. NALI_HOME environment variable
-/// 3. XDG_DATA_HOME/nali-rs
-/// 4. ~/.local/share/nali-rs (fallback)
+/// 3. The platform's data directory:
+///    - Linux: `$XDG_DATA_HOME/nali-rs`, or `~/.local/share/nali-rs`
+///    - macOS: `~/Library/Application Support/nali-rs`
+///    - Windows: `%LOCALAPPDATA%\nali-rs`, as databases are too large to
+///      roam with the profile
 pub fn data_dir() -> Result<PathBuf> {
     if let Ok(path) = env::var("NALI_DB_HOME") {
         return Ok(PathBuf::from(path));
@@ -45,16 +53,43 @@ pub fn data_dir() -> Result<PathBuf> {
         return Ok(PathBuf::from(path));
     }
 
-    if let Some(data_dir) = dirs::data_dir() {
-        return Ok(data_dir.join("nali-rs"));
+    if let Some(data_dir) = dirs::data_local_dir() {
+        let dir = data_dir.join("nali-rs");
+        // Earlier versions kept databases in the roaming directory on
+        // Windows; keep using it until the local one is created
+        if let Some(roaming) = dirs::data_dir().map(|dir| dir.join("nali-rs"))
+            && roaming != dir
+            && !dir.exists()
+            && holds_databases(&roaming)
+        {
+            return Ok(roaming);
+        }
+        return Ok(dir);
     }
 
     Err(NaliError::config("Unable to determine data directory"))
 }
 
+/// Whether `dir` holds anything besides configuration files and local CDN rules
+fn holds_databases(dir: &Path) -> bool {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return false;
+    };
+    entries.flatten().any(|entry| {
+        let name = entry.file_name();
+        let name = name.to_string_lossy();
+        !(name.starts_with("config.") || name.ends_with(".local.yml"))
+    })
+}
+
 /// Get the path to the config file
+///
+/// `config.yaml`, or `config.toml` when only that one exists.
 pub fn config_file() -> Result<PathBuf> {
-    Ok(config_dir()?.join("config.yaml"))
+    let dir = config_dir()?;
+    let yaml = dir.join("config.yaml");
+    let toml = dir.join("config.toml");
+    Ok(if !yaml.exists() && toml.exists() { toml } else { yaml })
 }
 
 /// Get the path to a database file
@@ -78,13 +113,64 @@ pub fn ensure_nali_dirs() -> Result<()> {
     Ok(())
 }
 
-/// Expand tilde (~) in path
-pub fn expand_tilde(path: &str) -> PathBuf {
-    if path.starts_with("~/")
-        && let Some(home) = dirs::home_dir() {
-            return home.join(&path[2..]);
+/// Expand `~` and environment variables in a path from the configuration
+///
+/// A leading `~` is the home directory (`~/nali`, and `~\nali` on Windows).
+/// `$NAME`, `${NAME}` and `%NAME%` are replaced by the environment variable
+/// on every platform, so `%APPDATA%\nali` and `$HOME/nali` both work;
+/// unset variables are left as written.
+pub fn expand_path(path: &str) -> PathBuf {
+    expand_with(path, dirs::home_dir(), |name| env::var(name).ok())
+}
+
+/// [`expand_path`] with the home directory and environment given
+fn expand_with(path: &str, home: Option<PathBuf>, var: impl Fn(&str) -> Option<String>) -> PathBuf {
+    if let Some(home) = home
+        && let Some(rest) = path.strip_prefix('~')
+        && (rest.is_empty() || rest.starts_with(std::path::is_separator))
+    {
+        return home.join(expand_vars(rest.trim_start_matches(std::path::is_separator), &var));
+    }
+    PathBuf::from(expand_vars(path, &var))
+}
+
+/// Replace `$NAME`, `${NAME}` and `%NAME%` of set variables in `text`
+fn expand_vars(text: &str, var: &impl Fn(&str) -> Option<String>) -> String {
+    let is_name = |c: char| c.is_ascii_alphanumeric() || c == '_';
+    let mut expanded = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find(['$', '%']) {
+        expanded.push_str(&rest[..start]);
+        let after = &rest[start + 1..];
+        // Name of the variable and length of the whole reference
+        let reference = if rest[start..].starts_with('%') {
+            after.find('%').map(|end| (&after[..end], end + 2))
+        } else if let Some(braced) = after.strip_prefix('{') {
+            braced.find('}').map(|end| (&braced[..end], end + 3))
+        } else {
+            let end = after.find(|c| !is_name(c)).unwrap_or(after.len());
+            Some((&after[..end], end + 1))
+        };
+        match reference {
+            Some((name, len))
+                if !name.is_empty()
+                    && !name.starts_with(|c: char| c.is_ascii_digit())
+                    && name.chars().all(is_name) =>
+            {
+                match var(name) {
+                    Some(value) => expanded.push_str(&value),
+                    None => expanded.push_str(&rest[start..start + len]),
+                }
+                rest = &rest[start + len..];
+            }
+            _ => {
+                expanded.push_str(&rest[start..start + 1]);
+                rest = after;
+            }
         }
-    PathBuf::from(path)
+    }
+    expanded.push_str(rest);
+    expanded
 }
 
 #[cfg(test)]
@@ -107,9 +193,59 @@ mod tests {
         assert!(path.to_string_lossy().contains("nali-rs"));
     }
 
+    fn vars(name: &str) -> Option<String> {
+        match name {
+            "HOME" => Some("/home/me".to_string()),
+            "APPDATA" => Some("C:\\Users\\me\\AppData\\Roaming".to_string()),
+            "NALI_DATA" => Some("/srv/nali".to_string()),
+            _ => None,
+        }
+    }
+
+    fn expand(path: &str) -> PathBuf {
+        expand_with(path, Some(PathBuf::from("/home/me")), vars)
+    }
+
+    #[test]
+    fn test_expand_path() {
+        assert_eq!(expand("~"), PathBuf::from("/home/me"));
+        assert_eq!(expand("~/db/qqwry.dat"), PathBuf::from("/home/me/db/qqwry.dat"));
+        assert_eq!(expand("~/db/$NOPE"), PathBuf::from("/home/me/db/$NOPE"));
+        // Only the current user's home
+        assert_eq!(expand("~other/db"), PathBuf::from("~other/db"));
+        assert_eq!(expand("db/~/x"), PathBuf::from("db/~/x"));
+        assert_eq!(expand_with("~/db", None, vars), PathBuf::from("~/db"));
+    }
+
+    #[test]
+    fn test_expand_vars() {
+        assert_eq!(expand("$HOME/nali"), PathBuf::from("/home/me/nali"));
+        assert_eq!(expand("${NALI_DATA}/geo.mmdb"), PathBuf::from("/srv/nali/geo.mmdb"));
+        assert_eq!(
+            expand("%APPDATA%\\nali-rs"),
+            PathBuf::from("C:\\Users\\me\\AppData\\Roaming\\nali-rs")
+        );
+        // Unset variables and lone signs stay as written
+        assert_eq!(expand("$NOPE/x/%NOPE%/${NOPE}"), PathBuf::from("$NOPE/x/%NOPE%/${NOPE}"));
+        assert_eq!(expand("100%/a$/$1/${/%"), PathBuf::from("100%/a$/$1/${/%"));
+        assert_eq!(expand("50% of $HOME"), PathBuf::from("50% of /home/me"));
+    }
+
+    #[cfg(windows)]
     #[test]
-    fn test_expand_tilde() {
-        let path = expand_tilde("~/test");
-        assert!(!path.to_string_lossy().starts_with("~"));
+    fn test_windows_paths() {
+        assert_eq!(expand("~\\db"), PathBuf::from("/home/me").join("db"));
+        assert_eq!(
+            expand_path("%USERPROFILE%\\nali"),
+            PathBuf::from(env::var("USERPROFILE").unwrap()).join("nali")
+        );
+        if env::var_os("NALI_HOME").is_none() && env::var_os("NALI_DB_HOME").is_none() {
+            let local = dirs::data_local_dir().unwrap();
+            let roaming = dirs::data_dir().unwrap().join("nali-rs");
+            assert!(data_dir().unwrap().starts_with(local) || holds_databases(&roaming));
+        }
+        if env::var_os("NALI_HOME").is_none() && env::var_os("NALI_CONFIG_HOME").is_none() {
+            assert!(config_dir().unwrap().starts_with(dirs::config_dir().unwrap()));
+        }
     }
 }
@@ -0,0 +1,63 @@
+//! Date helpers for database build timestamps
+
+/// Format Unix seconds as a UTC calendar date (`YYYY-MM-DD`)
+pub fn format_date(epoch_secs: u64) -> String {
+    // Civil-from-days, after Howard Hinnant's algorithm
+    let days = (epoch_secs / 86_400) as i64 + 719_468;
+    let era = days.div_euclid(146_097);
+    let doe = days.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
+/// Unix seconds of the UTC midnight starting the date a text begins with
+/// (`YYYY-MM-DD`), e.g. a database version like `2024-01-10` or
+/// `2024-01-10 build 3`
+pub fn parse_date(text: &str) -> Option<u64> {
+    let date = text.get(..10)?;
+    let mut parts = date.split('-');
+    let year: i64 = parts.next().filter(|p| p.len() == 4)?.parse().ok()?;
+    let month: i64 = parts.next().filter(|p| p.len() == 2)?.parse().ok()?;
+    let day: i64 = parts.next().filter(|p| p.len() == 2)?.parse().ok()?;
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        return None;
+    }
+
+    // Days-from-civil, the inverse of `format_date`
+    let year = year - i64::from(month <= 2);
+    let era = year.div_euclid(400);
+    let yoe = year.rem_euclid(400);
+    let mp = (month + 9) % 12;
+    let doy = (153 * mp + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    let days = era * 146_097 + doe - 719_468;
+    u64::try_from(days * 86_400).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_date() {
+        assert_eq!(format_date(0), "1970-01-01");
+        assert_eq!(format_date(951_782_400), "2000-02-29");
+        assert_eq!(format_date(1_700_000_000), "2023-11-14");
+    }
+
+    #[test]
+    fn test_parse_date() {
+        assert_eq!(parse_date("1970-01-01"), Some(0));
+        assert_eq!(parse_date("2000-02-29"), Some(951_782_400));
+        assert_eq!(parse_date("2023-11-14 build 3"), Some(1_699_920_000));
+        assert_eq!(parse_date("2023-13-01"), None);
+        assert_eq!(parse_date("20231114"), None);
+        assert_eq!(parse_date("v3.0"), None);
+    }
+}
@@ -0,0 +1,244 @@
+//! Golden-file tests of the command line
+//!
+//! Each test runs the nali-rs binary over a sample input from
+//! `tests/fixtures/input`, with the small databases in `tests/fixtures` as
+//! its data directory, and compares the output with `tests/golden/cli`.
+//! After an intended change, `NALI_UPDATE_GOLDEN=1 cargo test --test cli`
+//! rewrites the golden files and the database fixtures; review the diff.
+
+mod common;
+
+use common::{check_golden, fixtures, nali_home, run, run_output, test_path, updating};
+use serde_json::Value;
+use std::sync::Once;
+
+/// Files copied into the data directory
+const HOME_FILES: &[&str] = &[
+    "config.yaml",
+    "cdn.yml",
+    "qqwry.dat",
+    "ipv6wry.db",
+    "GeoLite2-City.mmdb",
+    "GeoCN.mmdb",
+];
+
+/// Rewrites the database fixtures once, before any test copies them
+static UPDATE_FIXTURES: Once = Once::new();
+
+/// Annotate the sample `input` with `args`
+fn annotate(args: &[&str], input: &str) -> Vec<u8> {
+    if updating() {
+        UPDATE_FIXTURES.call_once(|| {
+            for (file, content) in fixtures::all() {
+                std::fs::write(test_path("fixtures").join(file), content).unwrap();
+            }
+        });
+    }
+
+    let files: Vec<_> = HOME_FILES
+        .iter()
+        .map(|file| (test_path("fixtures").join(file), *file))
+        .collect();
+    let home = nali_home(&files);
+    let input = match input {
+        "" => Vec::new(),
+        file => std::fs::read(test_path("fixtures/input").join(file)).unwrap(),
+    };
+    run(home.path(), args, &input)
+}
+
+/// Annotate `input` with `args` and compare with the golden file `name`
+fn golden(name: &str, args: &[&str], input: &str) {
+    check_golden(&test_path("golden/cli").join(name), &annotate(args, input));
+}
+
+/// JSON documents without the lookup timings, which differ between runs
+fn without_timings(output: &[u8]) -> Vec<u8> {
+    let mut result = Vec::new();
+    for document in serde_json::Deserializer::from_slice(output).into_iter::<Value>() {
+        let mut document = document.unwrap();
+        for entity in document["entities"].as_array_mut().unwrap() {
+            if let Some(meta) = entity["meta"].as_object_mut() {
+                meta.remove("query_time_ms");
+            }
+        }
+        serde_json::to_writer_pretty(&mut result, &document).unwrap();
+        result.push(b'\n');
+    }
+    result
+}
+
+#[test]
+fn test_fixtures_are_current() {
+    for (name, content) in fixtures::all() {
+        check_golden(&test_path("fixtures").join(name), &content);
+    }
+}
+
+#[test]
+fn test_dig() {
+    golden("dig.txt", &[], "dig.txt");
+}
+
+#[test]
+fn test_ping() {
+    golden("ping.txt", &[], "ping.txt");
+}
+
+#[test]
+fn test_nginx() {
+    golden("nginx.txt", &[], "nginx.txt");
+    // Reading the files whole gives the same answers as mapping them
+    golden("nginx.txt", &["--load-mode", "memory"], "nginx.txt");
+    golden("nginx.txt", &["--preload"], "nginx.txt");
+}
+
+#[test]
+fn test_nginx_client_field() {
+    golden("nginx-client.txt", &["--input-format", "nginx"], "nginx.txt");
+}
+
+#[test]
+fn test_nginx_json() {
+    let output = annotate(&["--json"], "nginx.txt");
+    check_golden(
+        &test_path("golden/cli/nginx.json"),
+        &without_timings(&output),
+    );
+}
+
+#[test]
+fn test_syslog() {
+    golden("syslog.txt", &["--input-format", "syslog"], "syslog.txt");
+}
+
+#[test]
+fn test_chinese_log() {
+    golden("chinese.txt", &[], "chinese.txt");
+}
+
+#[test]
+fn test_annotation_eol() {
+    golden("chinese-eol.txt", &["--annotation", "eol"], "chinese.txt");
+}
+
+#[test]
+fn test_json_input() {
+    let args = ["--input-format", "json", "--keys", "client_ip,upstream_addr,host"];
+    golden("json.txt", &args, "json.txt");
+}
+
+#[test]
+fn test_ping_geoip2() {
+    let args = [
+        "--db4",
+        "geoip2",
+        "--db6",
+        "geoip2",
+        "--flag",
+        "--show-coords",
+    ];
+    golden("ping-geoip2.txt", &args, "ping.txt");
+}
+
+#[test]
+fn test_query_arguments() {
+    let args = [
+        "8.8.8.8",
+        "240e::1",
+        "fe80::1%eth0",
+        "192.168.1.1",
+        "cdn.jsdelivr.net",
+    ];
+    golden("query.txt", &args, "");
+}
+
+#[test]
+fn test_verbose_ipv6() {
+    // The ZX IPv6 file resolves whole /64s; the block shows how many
+    let args = ["--verbose", "240e:3a1:4c4:1e0::1", "2409:8a28::5", "2001:4860::8888"];
+    golden("verbose-ipv6.txt", &args, "");
+}
+
+#[test]
+fn test_query_table() {
+    let args = [
+        "--format",
+        "table",
+        "8.8.8.8",
+        "240e::1",
+        "192.168.1.1",
+        "cdn.jsdelivr.net",
+        "example.com",
+    ];
+    golden("table.txt", &args, "");
+}
+
+#[test]
+fn test_short() {
+    let args = ["--short", "8.8.8.8", "240e::1", "cdn.jsdelivr.net", "example.com"];
+    golden("short.txt", &args, "");
+    golden("nginx-short.txt", &["--short", "--input-format", "nginx"], "nginx.txt");
+}
+
+#[test]
+fn test_geocn() {
+    let ips = ["1.0.1.1", "114.114.114.114", "223.5.5.5"];
+    golden("geocn.txt", &[&["--db4", "geocn"][..], &ips].concat(), "");
+
+    // Only Chinese answers are refined
+    let ips = ["114.114.114.114", "223.5.5.5", "8.8.8.8"];
+    golden("geocn-qqwry.txt", &[&["--china-db", "geocn"][..], &ips].concat(), "");
+    golden(
+        "geocn-geoip2.txt",
+        &["--db4", "geoip2", "--china-db", "geocn", "114.114.114.114", "8.8.8.8"],
+        "",
+    );
+}
+
+#[test]
+fn test_report() {
+    golden("report.txt", &["report", "--by", "country", "--by", "network"], "nginx.txt");
+}
+
+#[test]
+fn test_read_only_writes_nothing() {
+    // No configuration file, which a normal run would create
+    let files: Vec<_> = ["qqwry.dat", "cdn.yml"]
+        .iter()
+        .map(|file| (test_path("fixtures").join(file), *file))
+        .collect();
+    let home = nali_home(&files);
+
+    let output = run(home.path(), &["--read-only", "8.8.8.8"], b"");
+    assert!(String::from_utf8(output).unwrap().starts_with("8.8.8.8 -> "));
+
+    let mut listed: Vec<_> = std::fs::read_dir(home.path())
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name())
+        .collect();
+    listed.sort();
+    assert_eq!(listed, ["cdn.yml", "qqwry.dat"]);
+}
+
+#[test]
+fn test_broken_config_is_fatal() {
+    let files = [(test_path("fixtures/qqwry.dat"), "qqwry.dat")];
+    let home = nali_home(&files);
+    std::fs::write(home.path().join("config.yaml"), "database:\n  ipv4_databse: geoip2\n").unwrap();
+
+    // Queries stop instead of running on the defaults
+    let output = run_output(home.path(), &["8.8.8.8"], b"");
+    assert_eq!(output.status.code(), Some(2));
+    assert!(output.stdout.is_empty());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.starts_with("Error: "), "{}", stderr);
+    assert!(stderr.contains("ipv4_databse"), "{}", stderr);
+
+    // Validation reports the problem once
+    let output = run_output(home.path(), &["config", "validate"], b"");
+    assert!(!output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert_eq!(stderr.matches("ipv4_databse").count(), 1, "{}", stderr);
+    assert!(!stderr.contains("Warning"), "{}", stderr);
+}
@@ -0,0 +1,471 @@
+//! Builders of the database fixtures in `tests/fixtures`
+//!
+//! The fixtures are hand-picked records written in each binary format, so
+//! their content can be read here rather than reverse engineered from the
+//! files. `tests/cli.rs` checks the committed files match these builders;
+//! `NALI_UPDATE_GOLDEN=1` rewrites them.
+
+use encoding_rs::GBK;
+use std::net::{IpAddr, Ipv4Addr};
+
+/// QQwry ranges: first and last address, country and area
+pub const QQWRY_RECORDS: &[(Ipv4Addr, Ipv4Addr, &str, &str)] = &[
+    (v4(0, 0, 0, 0), v4(0, 255, 255, 255), "IANA", "保留地址"),
+    (
+        v4(1, 0, 0, 0),
+        v4(1, 0, 0, 255),
+        "美国",
+        "APNIC&CloudFlare公共DNS服务器",
+    ),
+    (v4(1, 0, 1, 0), v4(8, 8, 8, 7), "中国", "电信"),
+    (
+        v4(8, 8, 8, 8),
+        v4(8, 8, 8, 8),
+        "美国",
+        "加利福尼亚州圣克拉拉县山景城谷歌公司DNS服务器",
+    ),
+    (v4(8, 8, 8, 9), v4(93, 184, 215, 255), "美国", ""),
+    (
+        v4(93, 184, 216, 0),
+        v4(93, 184, 216, 255),
+        "美国",
+        "Edgecast公司CDN节点",
+    ),
+    (v4(93, 184, 217, 0), v4(114, 114, 114, 113), "中国", ""),
+    (
+        v4(114, 114, 114, 114),
+        v4(114, 114, 114, 114),
+        "江苏省南京市",
+        "南京信风网络科技有限公司GreatbitDNS服务器",
+    ),
+    (
+        v4(114, 114, 114, 115),
+        v4(192, 167, 255, 255),
+        "中国",
+        "联通",
+    ),
+    (
+        v4(192, 168, 0, 0),
+        v4(192, 168, 255, 255),
+        "局域网",
+        "对方和您在同一内部网",
+    ),
+    (v4(192, 169, 0, 0), v4(223, 5, 5, 4), "中国", ""),
+    (
+        v4(223, 5, 5, 5),
+        v4(223, 5, 5, 5),
+        "浙江省杭州市",
+        "阿里云AliDNS服务器",
+    ),
+    (v4(223, 5, 5, 6), v4(255, 255, 255, 254), "中国", ""),
+    (
+        v4(255, 255, 255, 255),
+        v4(255, 255, 255, 255),
+        "纯真网络",
+        "2024年01月10日IP数据",
+    ),
+];
+
+/// ZX IPv6 ranges: first 64 bits of the first address, country and area
+pub const ZXIPV6_RECORDS: &[(u64, &str, &str)] = &[
+    (0x0000_0000_0000_0000, "IANA", "保留地址"),
+    (0x2001_0000_0000_0000, "IANA", "特殊地址"),
+    (0x2001_4860_0000_0000, "美国", "谷歌公司"),
+    (0x2001_4861_0000_0000, "IANA", "特殊地址"),
+    (0x2400_0000_0000_0000, "亚太地区", ""),
+    (0x2409_8000_0000_0000, "中国", "移动"),
+    (0x240a_0000_0000_0000, "亚太地区", ""),
+    (0x240e_0000_0000_0000, "中国", "电信"),
+    (0x240f_0000_0000_0000, "亚太地区", ""),
+    (0x2600_0000_0000_0000, "美国", ""),
+    (0x2a00_0000_0000_0000, "欧洲", ""),
+    (0x2c00_0000_0000_0000, "IANA", "保留地址"),
+];
+
+/// A GeoIP2 City record
+pub struct City {
+    pub country: (&'static str, &'static str, &'static str),
+    pub subdivision: Option<(&'static str, &'static str, &'static str)>,
+    pub city: Option<(&'static str, &'static str)>,
+    pub location: (f64, f64, u16, &'static str),
+}
+
+/// GeoIP2 networks; names are `(iso_code, en, zh-CN)` and the location is
+/// latitude, longitude, accuracy radius and time zone
+pub const MMDB_NETWORKS: &[(&str, City)] = &[
+    (
+        "1.0.0.0/24",
+        City {
+            country: ("AU", "Australia", "澳大利亚"),
+            subdivision: None,
+            city: None,
+            location: (-33.494, 143.2104, 1000, "Australia/Sydney"),
+        },
+    ),
+    (
+        "8.8.8.0/24",
+        City {
+            country: ("US", "United States", "美国"),
+            subdivision: Some(("CA", "California", "加利福尼亚州")),
+            city: Some(("Mountain View", "山景城")),
+            location: (37.386, -122.0838, 1000, "America/Los_Angeles"),
+        },
+    ),
+    (
+        "93.184.216.0/24",
+        City {
+            country: ("US", "United States", "美国"),
+            subdivision: Some(("MA", "Massachusetts", "马萨诸塞州")),
+            city: Some(("Norwell", "诺韦尔")),
+            location: (42.1596, -70.8217, 1000, "America/New_York"),
+        },
+    ),
+    (
+        "114.114.114.0/24",
+        City {
+            country: ("CN", "China", "中国"),
+            subdivision: Some(("JS", "Jiangsu", "江苏省")),
+            city: Some(("Nanjing", "南京")),
+            location: (32.0617, 118.7778, 50, "Asia/Shanghai"),
+        },
+    ),
+    (
+        "2001:4860::/32",
+        City {
+            country: ("US", "United States", "美国"),
+            subdivision: None,
+            city: None,
+            location: (37.751, -97.822, 1000, "America/Chicago"),
+        },
+    ),
+    (
+        "240e::/20",
+        City {
+            country: ("CN", "China", "中国"),
+            subdivision: None,
+            city: None,
+            location: (34.7732, 113.722, 1000, "Asia/Shanghai"),
+        },
+    ),
+];
+
+/// A GeoCN record; empty strings are unknown levels, as in the real database
+pub struct Area {
+    pub province: &'static str,
+    pub city: &'static str,
+    pub district: &'static str,
+    pub isp: &'static str,
+}
+
+/// GeoCN networks
+pub const GEOCN_NETWORKS: &[(&str, Area)] = &[
+    (
+        "1.0.1.0/24",
+        Area {
+            province: "福建省",
+            city: "福州市",
+            district: "",
+            isp: "电信",
+        },
+    ),
+    (
+        "114.114.114.0/24",
+        Area {
+            province: "江苏省",
+            city: "南京市",
+            district: "玄武区",
+            isp: "电信",
+        },
+    ),
+    (
+        "223.5.5.0/24",
+        Area {
+            province: "浙江省",
+            city: "杭州市",
+            district: "西湖区",
+            isp: "阿里云",
+        },
+    ),
+];
+
+const fn v4(a: u8, b: u8, c: u8, d: u8) -> Ipv4Addr {
+    Ipv4Addr::new(a, b, c, d)
+}
+
+fn gbk(s: &str) -> Vec<u8> {
+    GBK.encode(s).0.into_owned()
+}
+
+/// A QQwry file storing every record directly
+pub fn qqwry() -> Vec<u8> {
+    let mut data = vec![0u8; 8];
+    let mut offsets = Vec::new();
+    for (_, end, country, area) in QQWRY_RECORDS {
+        offsets.push(data.len() as u32);
+        data.extend_from_slice(&u32::from(*end).to_le_bytes());
+        for field in [country, area] {
+            data.extend_from_slice(&gbk(field));
+            data.push(0);
+        }
+    }
+
+    let index_start = data.len() as u32;
+    for ((start, ..), offset) in QQWRY_RECORDS.iter().zip(offsets) {
+        data.extend_from_slice(&u32::from(*start).to_le_bytes());
+        data.extend_from_slice(&offset.to_le_bytes()[..3]);
+    }
+    let index_end = data.len() as u32 - 7;
+
+    data[0..4].copy_from_slice(&index_start.to_le_bytes());
+    data[4..8].copy_from_slice(&index_end.to_le_bytes());
+    data
+}
+
+/// A ZX IPv6 file: header, index of 8 byte prefixes and 3 byte offsets,
+/// then the UTF-8 records
+pub fn zxipv6() -> Vec<u8> {
+    const HEADER_LEN: usize = 24;
+    const ENTRY_LEN: usize = 11;
+
+    let records_start = HEADER_LEN + ZXIPV6_RECORDS.len() * ENTRY_LEN;
+    let mut index = Vec::new();
+    let mut records = Vec::new();
+    for (prefix, country, area) in ZXIPV6_RECORDS {
+        let offset = (records_start + records.len()) as u32;
+        index.extend_from_slice(&prefix.to_le_bytes());
+        index.extend_from_slice(&offset.to_le_bytes()[..3]);
+        for field in [country, area] {
+            records.extend_from_slice(field.as_bytes());
+            records.push(0);
+        }
+    }
+
+    let mut data = b"IPDB".to_vec();
+    data.extend_from_slice(&2u16.to_le_bytes());
+    data.push(3); // offset length
+    data.push(8); // IP length
+    data.extend_from_slice(&(ZXIPV6_RECORDS.len() as u64).to_le_bytes());
+    data.extend_from_slice(&(HEADER_LEN as u64).to_le_bytes());
+    data.extend_from_slice(&index);
+    data.extend_from_slice(&records);
+    data
+}
+
+/// A value in the MaxMind DB data section
+enum Value {
+    Str(&'static str),
+    Double(f64),
+    U16(u16),
+    U32(u32),
+    U64(u64),
+    Map(Vec<(&'static str, Value)>),
+    Array(Vec<Value>),
+}
+
+impl Value {
+    fn names(en: &'static str, zh: &'static str) -> Value {
+        Value::Map(vec![("en", Value::Str(en)), ("zh-CN", Value::Str(zh))])
+    }
+
+    fn encode(&self, out: &mut Vec<u8>) {
+        match self {
+            Value::Str(s) => {
+                control(out, 2, s.len());
+                out.extend_from_slice(s.as_bytes());
+            }
+            Value::Double(d) => {
+                control(out, 3, 8);
+                out.extend_from_slice(&d.to_be_bytes());
+            }
+            Value::U16(n) => uint(out, 5, u64::from(*n)),
+            Value::U32(n) => uint(out, 6, u64::from(*n)),
+            Value::U64(n) => uint(out, 9, *n),
+            Value::Map(entries) => {
+                control(out, 7, entries.len());
+                for (key, value) in entries {
+                    Value::Str(key).encode(out);
+                    value.encode(out);
+                }
+            }
+            Value::Array(items) => {
+                control(out, 11, items.len());
+                for item in items {
+                    item.encode(out);
+                }
+            }
+        }
+    }
+}
+
+/// Control byte of a field: type in the top 3 bits, or 0 and an extended
+/// type byte, then the size with its extension bytes
+fn control(out: &mut Vec<u8>, kind: u8, size: usize) {
+    let (marker, extra): (u8, Vec<u8>) = match size {
+        0..29 => (size as u8, Vec::new()),
+        29..285 => (29, vec![(size - 29) as u8]),
+        _ => (30, ((size - 285) as u16).to_be_bytes().to_vec()),
+    };
+    if kind < 8 {
+        out.push(kind << 5 | marker);
+    } else {
+        out.push(marker);
+        out.push(kind - 7);
+    }
+    out.extend_from_slice(&extra);
+}
+
+/// Unsigned integer in as few big-endian bytes as it needs
+fn uint(out: &mut Vec<u8>, kind: u8, n: u64) {
+    let bytes = n.to_be_bytes();
+    let skip = bytes.iter().take_while(|&&b| b == 0).count();
+    control(out, kind, bytes.len() - skip);
+    out.extend_from_slice(&bytes[skip..]);
+}
+
+fn city_value(city: &City) -> Value {
+    let (iso, en, zh) = city.country;
+    let country = Value::Map(vec![
+        ("iso_code", Value::Str(iso)),
+        ("names", Value::names(en, zh)),
+    ]);
+    let (latitude, longitude, radius, time_zone) = city.location;
+    let location = Value::Map(vec![
+        ("accuracy_radius", Value::U16(radius)),
+        ("latitude", Value::Double(latitude)),
+        ("longitude", Value::Double(longitude)),
+        ("time_zone", Value::Str(time_zone)),
+    ]);
+
+    let mut fields = vec![("country", country), ("location", location)];
+    if let Some((iso, en, zh)) = city.subdivision {
+        let subdivision = Value::Map(vec![
+            ("iso_code", Value::Str(iso)),
+            ("names", Value::names(en, zh)),
+        ]);
+        fields.push(("subdivisions", Value::Array(vec![subdivision])));
+    }
+    if let Some((en, zh)) = city.city {
+        fields.push(("city", Value::Map(vec![("names", Value::names(en, zh))])));
+    }
+    Value::Map(fields)
+}
+
+/// Child of a search tree node
+#[derive(Clone, Copy)]
+enum Record {
+    Empty,
+    Node(usize),
+    Data(usize),
+}
+
+/// A GeoIP2 City database
+pub fn mmdb() -> Vec<u8> {
+    let networks = MMDB_NETWORKS
+        .iter()
+        .map(|(network, city)| (*network, city_value(city)));
+    maxmind("GeoLite2-City", networks)
+}
+
+/// A GeoCN database
+pub fn geocn() -> Vec<u8> {
+    let networks = GEOCN_NETWORKS
+        .iter()
+        .map(|(network, area)| {
+            let record = Value::Map(vec![
+                ("city", Value::Str(area.city)),
+                ("districts", Value::Str(area.district)),
+                ("isp", Value::Str(area.isp)),
+                ("net", Value::Str("宽带")),
+                ("province", Value::Str(area.province)),
+            ]);
+            (*network, record)
+        });
+    maxmind("GeoCN", networks)
+}
+
+/// A MaxMind DB with an IPv6 search tree and 24 bit records; IPv4 networks
+/// live under `::/96`
+fn maxmind(
+    database_type: &'static str,
+    networks: impl Iterator<Item = (&'static str, Value)>,
+) -> Vec<u8> {
+    let mut nodes = vec![[Record::Empty; 2]];
+    let mut data = Vec::new();
+
+    for (network, value) in networks {
+        let (addr, prefix) = network.split_once('/').unwrap();
+        let (bits, prefix) = match addr.parse::<IpAddr>().unwrap() {
+            IpAddr::V4(v4) => (
+                u128::from(u32::from(v4)),
+                96 + prefix.parse::<u32>().unwrap(),
+            ),
+            IpAddr::V6(v6) => (u128::from(v6), prefix.parse::<u32>().unwrap()),
+        };
+
+        let offset = data.len();
+        value.encode(&mut data);
+
+        let mut node = 0;
+        for depth in 0..prefix {
+            let bit = (bits >> (127 - depth) & 1) as usize;
+            if depth + 1 == prefix {
+                nodes[node][bit] = Record::Data(offset);
+            } else {
+                node = match nodes[node][bit] {
+                    Record::Node(next) => next,
+                    _ => {
+                        nodes.push([Record::Empty; 2]);
+                        nodes[node][bit] = Record::Node(nodes.len() - 1);
+                        nodes.len() - 1
+                    }
+                };
+            }
+        }
+    }
+
+    let node_count = nodes.len();
+    let mut out = Vec::new();
+    for node in &nodes {
+        for record in node {
+            let value = match *record {
+                Record::Empty => node_count,
+                Record::Node(next) => next,
+                Record::Data(offset) => node_count + 16 + offset,
+            };
+            out.extend_from_slice(&(value as u32).to_be_bytes()[1..]);
+        }
+    }
+    out.extend_from_slice(&[0; 16]);
+    out.extend_from_slice(&data);
+
+    out.extend_from_slice(b"\xAB\xCD\xEFMaxMind.com");
+    Value::Map(vec![
+        ("binary_format_major_version", Value::U16(2)),
+        ("binary_format_minor_version", Value::U16(0)),
+        ("build_epoch", Value::U64(1_704_844_800)),
+        ("database_type", Value::Str(database_type)),
+        (
+            "description",
+            Value::Map(vec![("en", Value::Str("nali-rs test fixture"))]),
+        ),
+        ("ip_version", Value::U16(6)),
+        (
+            "languages",
+            Value::Array(vec![Value::Str("en"), Value::Str("zh-CN")]),
+        ),
+        ("node_count", Value::U32(node_count as u32)),
+        ("record_size", Value::U16(24)),
+    ])
+    .encode(&mut out);
+    out
+}
+
+/// Every fixture file with its content
+pub fn all() -> Vec<(&'static str, Vec<u8>)> {
+    vec![
+        ("qqwry.dat", qqwry()),
+        ("ipv6wry.db", zxipv6()),
+        ("GeoLite2-City.mmdb", mmdb()),
+        ("GeoCN.mmdb", geocn()),
+    ]
+}
@@ -0,0 +1,98 @@
+//! Helpers shared by the integration tests that run the nali-rs binary
+
+#![allow(dead_code)]
+
+pub mod fixtures;
+
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output, Stdio};
+use std::time::{Duration, UNIX_EPOCH};
+
+/// Path of a file under `tests/`
+pub fn test_path(path: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests")
+        .join(path)
+}
+
+/// Whether golden files and fixtures are rewritten instead of compared
+pub fn updating() -> bool {
+    std::env::var_os("NALI_UPDATE_GOLDEN").is_some()
+}
+
+/// Modification time given to copied fixtures, 2024-01-10
+///
+/// Databases without a version report the file's date as their build date,
+/// which would otherwise be the day the tests run.
+const FIXTURE_MTIME: u64 = 1_704_844_800;
+
+/// A data directory holding copies of `files`, each `(source, name)`
+pub fn nali_home(files: &[(PathBuf, &str)]) -> tempfile::TempDir {
+    let home = tempfile::tempdir().unwrap();
+    for (source, name) in files {
+        let dest = home.path().join(name);
+        std::fs::copy(source, &dest).unwrap();
+        std::fs::File::options()
+            .write(true)
+            .open(&dest)
+            .unwrap()
+            .set_modified(UNIX_EPOCH + Duration::from_secs(FIXTURE_MTIME))
+            .unwrap();
+    }
+    home
+}
+
+/// Run nali-rs in `home` with `args`, feeding `input` on stdin, and return
+/// its stdout after checking that it succeeded
+///
+/// Colors are left to the arguments: stdout is a pipe, so `auto` is off.
+/// Messages are English whatever the locale of the test run.
+pub fn run(home: &Path, args: &[&str], input: &[u8]) -> Vec<u8> {
+    let output = run_output(home, args, input);
+    assert!(
+        output.status.success(),
+        "nali-rs {:?} failed: {}",
+        args,
+        String::from_utf8_lossy(&output.stderr)
+    );
+    output.stdout
+}
+
+/// Run nali-rs like [`run`], whatever its exit status
+pub fn run_output(home: &Path, args: &[&str], input: &[u8]) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_nali-rs"))
+        .args(args)
+        .env("NALI_HOME", home)
+        .env("LANG", "C")
+        .env_remove("LC_ALL")
+        .env_remove("LC_MESSAGES")
+        .env_remove("NALI_LANG")
+        .env_remove("NO_COLOR")
+        .env_remove("NALI_NO_COLOR")
+        .env_remove("CLICOLOR_FORCE")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(input).unwrap();
+    child.wait_with_output().unwrap()
+}
+
+/// Compare `actual` with the file at `path`, or rewrite it with `NALI_UPDATE_GOLDEN`
+pub fn check_golden(path: &Path, actual: &[u8]) {
+    if updating() {
+        std::fs::write(path, actual).unwrap();
+        return;
+    }
+
+    let expected = std::fs::read(path).unwrap();
+    assert!(
+        actual == expected,
+        "{} differs\n--- expected\n{}\n--- actual\n{}",
+        path.display(),
+        String::from_utf8_lossy(&expected),
+        String::from_utf8_lossy(actual)
+    );
+}
@@ -0,0 +1,61 @@
+//! Golden-file tests of `--compat nali`
+//!
+//! The binary annotates `tests/golden/nali/input.txt` with the corpus
+//! databases and its output must match the `expected*.txt` files byte for
+//! byte. They follow the Go nali's `Entities.String` and `ColorString`:
+//! ` [info] ` after every annotated entity, nothing after the others. Run
+//! with `NALI_UPDATE_GOLDEN=1` to rewrite them after an intended change,
+//! and check the diff against nali before committing.
+
+mod common;
+
+use common::{check_golden, nali_home, test_path};
+use std::path::PathBuf;
+
+fn golden_dir() -> PathBuf {
+    test_path("golden/nali")
+}
+
+/// Run nali-rs in compat mode with `args`, feeding `input` on stdin
+fn run(args: &[&str], input: &[u8]) -> Vec<u8> {
+    // The corpus databases and the golden CDN list
+    let home = nali_home(&[
+        (test_path("corpus/qqwry.dat"), "qqwry.dat"),
+        (test_path("corpus/zxipv6wry.db"), "zxipv6wry.db"),
+        (golden_dir().join("cdn.yml"), "cdn.yml"),
+    ]);
+    let args: Vec<&str> = ["--compat", "nali"].iter().chain(args).copied().collect();
+    common::run(home.path(), &args, input)
+}
+
+/// Compare `actual` with the golden file `name`
+fn check(name: &str, actual: &[u8]) {
+    check_golden(&golden_dir().join(name), actual);
+}
+
+fn input() -> Vec<u8> {
+    std::fs::read(golden_dir().join("input.txt")).unwrap()
+}
+
+#[test]
+fn test_pipe() {
+    check("expected.txt", &run(&[], &input()));
+}
+
+#[test]
+fn test_pipe_colored() {
+    check("expected-color.txt", &run(&["--color", "always"], &input()));
+}
+
+#[test]
+fn test_arguments_are_one_line() {
+    let args = [
+        "ping",
+        "1.0.0.1",
+        "and",
+        "240e::1",
+        "via",
+        "cdn.jsdelivr.net",
+    ];
+    check("expected-args.txt", &run(&args, b""));
+}
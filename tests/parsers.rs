@@ -0,0 +1,303 @@
+//! Property tests of the binary database parsers
+//!
+//! Each parser is fed mutations of the small sample databases in
+//! `tests/corpus`: flipped bytes, header fields set to extreme values,
+//! truncated or extended files, and plain random bytes. Whatever the input,
+//! loading and looking up must return a result or an error; a panic means a
+//! parser read out of bounds (or overflowed) and fails the test.
+//!
+//! The runs are seeded and reproducible. `NALI_PARSER_SEED` picks another
+//! seed and `NALI_PARSER_ITERATIONS` changes the number of inputs per
+//! parser. The input of a failing run is written to
+//! `target/parser-failures/` for the cargo-fuzz targets in `fuzz/`.
+
+use nali_rs::database::{
+    CustomDatabase, CzdbDatabase, Database, GeoIP2Database, IP2RegionDatabase, IPDBDatabase,
+    IPIPDatabase, QQwryDatabase, ZXIPv6Database,
+};
+use std::net::IpAddr;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+
+/// Mutated inputs per parser unless overridden
+const DEFAULT_ITERATIONS: usize = 2_000;
+
+/// Addresses looked up in every database that loads
+const PROBES: &[&str] = &[
+    "0.0.0.0",
+    "1.0.0.1",
+    "1.1.2.3",
+    "8.8.8.8",
+    "255.255.255.255",
+    "::",
+    "2001:db8::1",
+    "2400:3200::1",
+    "ffff:ffff:ffff:ffff:ffff:ffff:ffff:ffff",
+];
+
+/// Key of the encrypted `cz88.czdb` sample, in the form of `database.czdb_key`
+const CZDB_KEY: &str = "bmFsaS1ycyAgY29ycHVzIQ==";
+
+/// A parser ready to load a corpus sample
+///
+/// Parsers other than czdb ignore the key.
+fn open<D: Database + Default>() -> D {
+    let mut db = D::default();
+    db.set_key(CZDB_KEY);
+    db
+}
+
+fn corpus(file: &str) -> Vec<u8> {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/corpus")
+        .join(file);
+    std::fs::read(&path).unwrap_or_else(|e| panic!("{}: {}", path.display(), e))
+}
+
+fn env_or<T: std::str::FromStr>(name: &str, default: T) -> T {
+    std::env::var(name)
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(default)
+}
+
+/// A value likely to hit an edge case when used as an offset or length
+fn extreme(rng: &mut fastrand::Rng, len: usize) -> u32 {
+    let len = len as u32;
+    let random = rng.u32(..);
+    let values = [
+        0,
+        1,
+        len.wrapping_sub(1),
+        len,
+        len.wrapping_add(1),
+        u32::MAX,
+        u32::MAX - 15,
+        random,
+    ];
+    values[rng.usize(..values.len())]
+}
+
+/// A damaged version of `seed`
+fn mutate(rng: &mut fastrand::Rng, seed: &[u8]) -> Vec<u8> {
+    let mut data = seed.to_vec();
+    match rng.u8(..5) {
+        // Flip a few bytes anywhere
+        0 => {
+            for _ in 0..rng.usize(1..=8) {
+                let pos = rng.usize(..data.len());
+                data[pos] = rng.u8(..);
+            }
+        }
+        // Set a header field to an edge value
+        1 => {
+            let pos = rng.usize(..32.min(data.len() - 3)) & !3;
+            let value = extreme(rng, data.len());
+            data[pos..pos + 4].copy_from_slice(&value.to_le_bytes());
+        }
+        // Cut the file short
+        2 => data.truncate(rng.usize(..data.len())),
+        // Append garbage
+        3 => data.extend((0..rng.usize(1..64)).map(|_| rng.u8(..))),
+        // Keep the magic and the header, randomize the rest
+        _ => {
+            let keep = rng.usize(..32.min(data.len()));
+            data.truncate(keep);
+            data.extend((0..rng.usize(..512)).map(|_| rng.u8(..)));
+        }
+    }
+    data
+}
+
+/// Query everything `db` answers
+fn probe(db: &dyn Database) {
+    for probe in PROBES {
+        let ip = probe.parse::<IpAddr>().unwrap();
+        let _ = db.lookup_ip(ip);
+        let _ = db.lookup_range(ip);
+    }
+    let _ = db.version();
+}
+
+/// Load `data` from a file and from bytes and query everything; `Err` holds
+/// the panic message
+fn exercise<D: Database + Default>(file: &Path, data: &[u8]) -> Result<(), String> {
+    std::fs::write(file, data).unwrap();
+    panic::catch_unwind(AssertUnwindSafe(|| {
+        let mut db = open::<D>();
+        if db.load_from_file(file.to_str().unwrap()).is_ok() {
+            probe(&db);
+        }
+        let mut db = open::<D>();
+        if db.load_from_bytes(data.to_vec()).is_ok() {
+            probe(&db);
+        }
+    }))
+    .map_err(|payload| {
+        payload
+            .downcast_ref::<String>()
+            .cloned()
+            .or_else(|| payload.downcast_ref::<&str>().map(|s| s.to_string()))
+            .unwrap_or_default()
+    })
+}
+
+/// Run the mutated corpus sample `sample` through parser `D`
+fn fuzz<D: Database + Default>(sample: &str, iterations: usize) {
+    let seed = corpus(sample);
+    let dir = tempfile::tempdir().unwrap();
+    let file = dir.path().join(sample);
+
+    // The unmodified sample is a working database
+    std::fs::write(&file, &seed).unwrap();
+    let mut db = open::<D>();
+    db.load_from_file(file.to_str().unwrap()).unwrap();
+    assert!(
+        PROBES
+            .iter()
+            .any(|probe| matches!(db.lookup_ip(probe.parse().unwrap()), Ok(Some(_)))),
+        "{} answers no probe",
+        sample
+    );
+
+    let rng_seed = env_or("NALI_PARSER_SEED", 0x6e61_6c69);
+    let iterations = env_or("NALI_PARSER_ITERATIONS", iterations);
+    let mut rng = fastrand::Rng::with_seed(rng_seed);
+    let previous_hook = panic::take_hook();
+    panic::set_hook(Box::new(|_| {}));
+
+    let mut failure = None;
+    for n in 0..iterations {
+        let data = mutate(&mut rng, &seed);
+        if let Err(message) = exercise::<D>(&file, &data) {
+            failure = Some((n, data, message));
+            break;
+        }
+    }
+    panic::set_hook(previous_hook);
+
+    if let Some((n, data, message)) = failure {
+        let saved = save_failure(sample, &data);
+        panic!(
+            "{} panicked on input {} of seed {:#x}: {} (input saved to {})",
+            sample,
+            n,
+            rng_seed,
+            message,
+            saved.display()
+        );
+    }
+}
+
+fn save_failure(sample: &str, data: &[u8]) -> PathBuf {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("target/parser-failures");
+    let _ = std::fs::create_dir_all(&dir);
+    let path = dir.join(sample);
+    let _ = std::fs::write(&path, data);
+    path
+}
+
+#[test]
+fn qqwry_survives_malformed_input() {
+    fuzz::<QQwryDatabase>("qqwry.dat", DEFAULT_ITERATIONS);
+}
+
+#[test]
+fn zxipv6_survives_malformed_input() {
+    fuzz::<ZXIPv6Database>("zxipv6wry.db", DEFAULT_ITERATIONS);
+}
+
+#[test]
+fn ipip_survives_malformed_input() {
+    fuzz::<IPIPDatabase>("ipip.ipdb", DEFAULT_ITERATIONS);
+}
+
+#[test]
+fn ip2region_survives_malformed_input() {
+    // Every sample carries the 512 KiB vector index
+    fuzz::<IP2RegionDatabase>("ip2region.xdb", DEFAULT_ITERATIONS / 4);
+}
+
+#[test]
+fn ipdb_survives_malformed_input() {
+    fuzz::<IPDBDatabase>("city.ipdb", DEFAULT_ITERATIONS);
+}
+
+#[test]
+fn czdb_survives_malformed_input() {
+    fuzz::<CzdbDatabase>("cz88.czdb", DEFAULT_ITERATIONS);
+}
+
+#[test]
+fn custom_survives_malformed_input() {
+    fuzz::<CustomDatabase>("custom.nali", DEFAULT_ITERATIONS);
+}
+
+#[test]
+fn qqwry_rejects_index_past_u32() {
+    // The index ends 7 bytes short of `u32::MAX`
+    let data = corpus("qqwry-index-overflow.dat");
+    let dir = tempfile::tempdir().unwrap();
+    let file = dir.path().join("qqwry.dat");
+    exercise::<QQwryDatabase>(&file, &data).unwrap();
+
+    let mut db = QQwryDatabase::default();
+    assert!(db.load_from_bytes(data).is_err());
+}
+
+#[test]
+fn zxipv6_answers_past_last_entry() {
+    let file = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/ipv6wry.db");
+    let mut db = ZXIPv6Database::default();
+    db.load_from_file(file.to_str().unwrap()).unwrap();
+
+    // The last entry starts at 2c00::, and nothing follows it in the index
+    let ip: IpAddr = "fe80::1".parse().unwrap();
+    let geo = db.lookup_ip(ip).unwrap().unwrap();
+    assert_eq!(geo.isp.as_deref(), Some("保留地址"));
+    let (start, end) = db.lookup_range(ip).unwrap().unwrap();
+    assert_eq!(start, "2c00::".parse::<IpAddr>().unwrap());
+    assert_eq!(end, IpAddr::V6(u128::MAX.into()));
+}
+
+/// Load `sample` from a file and from bytes; both must answer alike
+///
+/// `sample` is relative to the crate root.
+fn load_from_bytes_matches_file<D: Database + Default>(sample: &str) {
+    let file = Path::new(env!("CARGO_MANIFEST_DIR")).join(sample);
+    let data = std::fs::read(&file).unwrap();
+
+    let mut from_file = open::<D>();
+    from_file.load_from_file(file.to_str().unwrap()).unwrap();
+    let mut from_bytes = open::<D>();
+    from_bytes.load_from_bytes(data).unwrap();
+    assert!(from_bytes.is_loaded());
+
+    for probe in PROBES {
+        let ip = probe.parse::<IpAddr>().unwrap();
+        assert_eq!(
+            format!("{:?}", from_bytes.lookup_ip(ip)),
+            format!("{:?}", from_file.lookup_ip(ip)),
+            "{} answers {} differently",
+            sample,
+            probe
+        );
+        // A matched block covers the address
+        if let Ok(Some((start, end))) = from_file.lookup_range(ip) {
+            assert!(start <= ip && ip <= end, "{} puts {} in {}-{}", sample, probe, start, end);
+        }
+    }
+    assert_eq!(from_bytes.version(), from_file.version());
+}
+
+#[test]
+fn parsers_load_from_bytes() {
+    load_from_bytes_matches_file::<QQwryDatabase>("tests/corpus/qqwry.dat");
+    load_from_bytes_matches_file::<ZXIPv6Database>("tests/corpus/zxipv6wry.db");
+    load_from_bytes_matches_file::<IPIPDatabase>("tests/corpus/ipip.ipdb");
+    load_from_bytes_matches_file::<IP2RegionDatabase>("tests/corpus/ip2region.xdb");
+    load_from_bytes_matches_file::<IPDBDatabase>("tests/corpus/city.ipdb");
+    load_from_bytes_matches_file::<CzdbDatabase>("tests/corpus/cz88.czdb");
+    load_from_bytes_matches_file::<CustomDatabase>("tests/corpus/custom.nali");
+    load_from_bytes_matches_file::<GeoIP2Database>("tests/fixtures/GeoLite2-City.mmdb");
+}